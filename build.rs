@@ -0,0 +1,27 @@
+use std::{env, process::Command};
+
+fn main() {
+    // Not being built from a git checkout (like from a crate tarball) is fine,
+    // the hash is just unknown then.
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|h| h.trim().to_owned())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=GCMOD_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    // Cargo sets CARGO_FEATURE_<NAME> for each enabled feature.
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase().replace('_', "-")))
+        // What's in it is listed on its own
+        .filter(|f| f != "default")
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=GCMOD_FEATURES={}", features.join(","));
+}
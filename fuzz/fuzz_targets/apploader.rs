@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use gcmod::sections::apploader::Apploader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Apploader::new(Cursor::new(data), 0);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use gcmod::sections::dol::DOLHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DOLHeader::new(Cursor::new(data), 0);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use gcmod::sections::fst::{ParseOptions, FST};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = FST::new(Cursor::new(data), 0);
+    let _ = FST::open(Cursor::new(data), 0, data.len());
+    let _ = FST::open_with(Cursor::new(data), 0, data.len(), &ParseOptions::default().max_buffered_fst_size(0));
+    let _ = FST::summary(Cursor::new(data), 0, data.len());
+    let _ = FST::probe_path(Cursor::new(data), 0, data.len(), "/a/b");
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use gcmod::sections::thp::ThpHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ThpHeader::new(Cursor::new(data), 0);
+});
@@ -0,0 +1,452 @@
+// Analyses `info --analyzers` can run on a game, each behind the `Analyzer`
+// trait so a team that cares about something particular to its games, like
+// a debug file or a version string in the DOL, can add its own without
+// touching the CLI. `AnalyzerRegistry::with_builtins` has the ones gcmod
+// comes with, and `register` adds more.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{BufReader, Read, Seek, SeekFrom},
+};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{
+    align,
+    junk::fill_junk,
+    known_files::{self, Category},
+    quick_id::SamplePlan,
+    sections::{
+        banner::BannerVersion,
+        fst::entry::FileEntry,
+        header::{country_code_for_region, region_name, COUNTRY_CODE_PAL},
+    },
+    Game,
+};
+
+// What analyzers read the image through, since trait objects can only
+// have one non-auto trait.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+pub trait Analyzer {
+    // What `--analyzers` selects it by, which should be short and have no
+    // commas, like "region"
+    fn name(&self) -> &str;
+
+    // A line for `--analyzers list`
+    fn description(&self) -> &str;
+
+    // Anything that can't be read goes in the report's warnings, so the
+    // other analyzers still run.
+    fn analyze(&self, game: &Game, iso: &mut dyn ReadSeek) -> AnalyzerReport;
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Field {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ReportSection {
+    // None for the first section under the analyzer's own name
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub title: Option<String>,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct AnalyzerReport {
+    pub sections: Vec<ReportSection>,
+    pub warnings: Vec<String>,
+}
+
+impl AnalyzerReport {
+    // Adds a field to the last section, starting an untitled one if there
+    // isn't one yet.
+    pub fn field(&mut self, key: impl Into<String>, value: impl fmt::Display) {
+        if self.sections.is_empty() {
+            self.sections.push(ReportSection::default());
+        }
+        self.sections.last_mut().unwrap().fields.push(Field { key: key.into(), value: value.to_string() });
+    }
+
+    pub fn section(&mut self, title: impl Into<String>) {
+        self.sections.push(ReportSection { title: Some(title.into()), fields: Vec::new() });
+    }
+
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.warnings.push(message.into());
+    }
+
+    // The fields with their values lined up, indented under the sections'
+    // titles, then the warnings.
+    pub fn to_text(&self) -> String {
+        let width = self.sections.iter()
+            .flat_map(|s| &s.fields)
+            .map(|f| f.key.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut s = String::new();
+        for section in &self.sections {
+            let indent = match &section.title {
+                Some(title) => {
+                    s += &format!("{}:\n", title);
+                    "  "
+                },
+                None => "",
+            };
+            for f in &section.fields {
+                s += &format!("{}{:<w$}  {}\n", indent, format!("{}:", f.key), f.value, w = width + 1);
+            }
+        }
+        for w in &self.warnings {
+            s += &format!("Warning: {}\n", w);
+        }
+        s
+    }
+}
+
+// A report with the name of the analyzer that made it, for printing a few
+// together.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct NamedReport {
+    pub analyzer: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub report: AnalyzerReport,
+}
+
+#[derive(Default)]
+pub struct AnalyzerRegistry {
+    analyzers: Vec<Box<dyn Analyzer>>,
+}
+
+impl AnalyzerRegistry {
+    pub fn new() -> AnalyzerRegistry {
+        AnalyzerRegistry::default()
+    }
+
+    pub fn with_builtins() -> AnalyzerRegistry {
+        let mut registry = AnalyzerRegistry::new();
+        registry.register(Box::new(RegionAnalyzer));
+        registry.register(Box::new(FingerprintAnalyzer));
+        registry.register(Box::new(ScrubAnalyzer));
+        registry.register(Box::new(AssetAnalyzer));
+        registry
+    }
+
+    // Replaces any analyzer with the same name, so a built-in can be swapped
+    // out for one that does more.
+    pub fn register(&mut self, analyzer: Box<dyn Analyzer>) {
+        match self.analyzers.iter_mut().find(|a| a.name() == analyzer.name()) {
+            Some(a) => *a = analyzer,
+            None => self.analyzers.push(analyzer),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Analyzer> {
+        self.analyzers.iter().find(|a| a.name() == name).map(|a| &**a)
+    }
+
+    // In the order they were registered
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Analyzer> {
+        self.analyzers.iter().map(|a| &**a)
+    }
+
+    // Runs the analyzers named in `names`, in that order, or says which name
+    // isn't registered.
+    pub fn run(&self, names: &[&str], game: &Game, iso: &mut dyn ReadSeek) -> Result<Vec<NamedReport>, String> {
+        let analyzers = names.iter()
+            .map(|&name| self.get(name).ok_or_else(|| name.to_owned()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(analyzers.into_iter()
+            .map(|a| NamedReport { analyzer: a.name().to_owned(), report: a.analyze(game, &mut *iso) })
+            .collect())
+    }
+}
+
+// Whether the game code's region, the header's, and the banner agree.
+pub struct RegionAnalyzer;
+
+impl Analyzer for RegionAnalyzer {
+    fn name(&self) -> &str {
+        "region"
+    }
+
+    fn description(&self) -> &str {
+        "Checks that the game code, the header's region, and the banner agree"
+    }
+
+    fn analyze(&self, game: &Game, iso: &mut dyn ReadSeek) -> AnalyzerReport {
+        let mut report = AnalyzerReport::default();
+        let header_code = game.header.information.country_code;
+        report.field("Header region", region_name(header_code));
+        match game.header.game_code.chars().nth(3) {
+            Some(c) => {
+                let code_region = country_code_for_region(c);
+                report.field("Game code region", format!("{} ({})", region_name(code_region), c));
+                if code_region != header_code {
+                    report.warning(format!(
+                        "The game code is for {}, but the header says {}",
+                        region_name(code_region), region_name(header_code),
+                    ));
+                }
+            },
+            None => report.warning(format!("The game code {:?} has no region", game.header.game_code)),
+        }
+        match game.read_banner(iso) {
+            Some((_, Ok(banner))) => {
+                report.field("Banner", format!("{:?}", banner.version));
+                report.field("Banner languages", banner.infos.len());
+                // Only PAL games have a block for each European language
+                if banner.version == BannerVersion::BNR2 && header_code != COUNTRY_CODE_PAL {
+                    report.warning(format!("The banner is BNR2, which is for PAL games, but the header says {}", region_name(header_code)));
+                }
+            },
+            Some((_, Err(e))) => report.warning(format!("Couldn't read the banner: {}", e)),
+            None => report.field("Banner", "none"),
+        }
+        report
+    }
+}
+
+// The image's quick-id, see `quick_id`.
+pub struct FingerprintAnalyzer;
+
+impl Analyzer for FingerprintAnalyzer {
+    fn name(&self) -> &str {
+        "fingerprint"
+    }
+
+    fn description(&self) -> &str {
+        "The quick-id, for telling whether two images are the same dump"
+    }
+
+    fn analyze(&self, _game: &Game, iso: &mut dyn ReadSeek) -> AnalyzerReport {
+        let mut report = AnalyzerReport::default();
+        let mut iso = BufReader::new(iso);
+        let result = SamplePlan::for_image(&mut iso)
+            .and_then(|plan| Ok((plan.quick_id(&mut iso)?, plan)));
+        match result {
+            Ok((id, plan)) => {
+                report.field("Quick-id", id);
+                report.field("Bytes sampled", plan.sampled_bytes());
+            },
+            Err(e) => report.warning(format!("Couldn't work out the quick-id: {}", e)),
+        }
+        report
+    }
+}
+
+// What's in the unused space: zeros for a scrubbed image, or the junk
+// Nintendo's tools filled it with, which `unscrub` puts back.
+pub struct ScrubAnalyzer;
+
+// How much of each gap is looked at, from its first sector boundary, since
+// the end of the sector a section ends in isn't always junk.
+const SCRUB_SAMPLE_SIZE: u64 = 0x8000;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum GapContents {
+    Zeros,
+    Junk,
+    Other,
+}
+
+impl Analyzer for ScrubAnalyzer {
+    fn name(&self) -> &str {
+        "scrub"
+    }
+
+    fn description(&self) -> &str {
+        "Whether the unused space has been zeroed or still has the original junk"
+    }
+
+    fn analyze(&self, game: &Game, iso: &mut dyn ReadSeek) -> AnalyzerReport {
+        let mut report = AnalyzerReport::default();
+        let Ok(game_code) = <[u8; 4]>::try_from(game.header.game_code.as_bytes()) else {
+            report.warning(format!("Invalid game code {:?}", game.header.game_code));
+            return report;
+        };
+        // The cut off tail of a trimmed image has nothing to look at
+        let image_len = game.trimmed_to.unwrap_or(game.capacity);
+        let mut found: BTreeMap<GapContents, (usize, u64)> = BTreeMap::new();
+        let mut buf = vec![0; SCRUB_SAMPLE_SIZE as usize];
+        let mut junk = vec![0; SCRUB_SAMPLE_SIZE as usize];
+        for (start, end) in game.rom_layout().gaps(image_len) {
+            let sample_start = align(start, SCRUB_SAMPLE_SIZE);
+            if sample_start >= end {
+                continue;
+            }
+            let n = (end - sample_start).min(SCRUB_SAMPLE_SIZE) as usize;
+            let read = iso.seek(SeekFrom::Start(sample_start)).and_then(|_| iso.read_exact(&mut buf[..n]));
+            if let Err(e) = read {
+                report.warning(format!("Couldn't read the gap at {:#x}: {}", start, e));
+                return report;
+            }
+            fill_junk(game_code, game.header.disk_id, sample_start, &mut junk[..n]);
+            let contents = if buf[..n].iter().all(|&b| b == 0) {
+                GapContents::Zeros
+            } else if buf[..n] == junk[..n] {
+                GapContents::Junk
+            } else {
+                GapContents::Other
+            };
+            let (count, bytes) = found.entry(contents).or_default();
+            *count += 1;
+            *bytes += end - start;
+        }
+
+        let status = match (found.contains_key(&GapContents::Zeros), found.contains_key(&GapContents::Junk)) {
+            _ if found.contains_key(&GapContents::Other) => "has data that isn't zeros or junk in its gaps",
+            (true, true) => "partly scrubbed",
+            (true, false) => "scrubbed",
+            (false, true) => "not scrubbed, the original junk is there",
+            (false, false) => "no gaps big enough to tell",
+        };
+        report.field("Status", status);
+        for (contents, (count, bytes)) in &found {
+            let name = match contents {
+                GapContents::Zeros => "Zeroed gaps",
+                GapContents::Junk => "Junk gaps",
+                GapContents::Other => "Other gaps",
+            };
+            report.field(name, format!("{}, {} bytes", count, bytes));
+        }
+        if game.trimmed_to.is_some() {
+            report.field("Trimmed", "the cut off tail isn't counted");
+        }
+        report
+    }
+}
+
+// How many of each kind of asset the file system has, like `info --assets`
+// without the details of each file.
+pub struct AssetAnalyzer;
+
+impl Analyzer for AssetAnalyzer {
+    fn name(&self) -> &str {
+        "assets"
+    }
+
+    fn description(&self) -> &str {
+        "How many banners, textures, movies, sound banks, and modules there are"
+    }
+
+    fn analyze(&self, game: &Game, _iso: &mut dyn ReadSeek) -> AnalyzerReport {
+        let mut report = AnalyzerReport::default();
+        let categories = game.asset_categories();
+        if categories.is_empty() {
+            report.field("Assets", "none known");
+        }
+        for (category, files) in &categories {
+            let total: usize = files.iter().map(|f| f.size).sum();
+            report.field(category.name(), format!("{} files, {} bytes", files.len(), total));
+        }
+        report
+    }
+}
+
+impl Game {
+    // The files `known_files` knows what kind of asset they are, by kind.
+    pub fn asset_categories(&self) -> BTreeMap<Category, Vec<&FileEntry>> {
+        let mut categories: BTreeMap<Category, Vec<&FileEntry>> = BTreeMap::new();
+        for f in self.fst.entries().iter().filter_map(|e| e.as_file()) {
+            if let Some(c) = known_files::categorize(&f.info.normalized_path()) {
+                categories.entry(c).or_default().push(f);
+            }
+        }
+        categories
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+
+    // Stands in for one a library user would register
+    struct CountingAnalyzer(&'static str);
+
+    impl Analyzer for CountingAnalyzer {
+        fn name(&self) -> &str {
+            "count"
+        }
+
+        fn description(&self) -> &str {
+            self.0
+        }
+
+        fn analyze(&self, game: &Game, _iso: &mut dyn ReadSeek) -> AnalyzerReport {
+            let mut report = AnalyzerReport::default();
+            report.field("Files", game.fst.file_count);
+            report.section("Details");
+            report.field("Description", self.0);
+            report.warning("Just counting");
+            report
+        }
+    }
+
+    fn run(registry: &AnalyzerRegistry, names: &[&str]) -> Result<Vec<NamedReport>, String> {
+        let image = build_image(synthetic_tree().path());
+        registry.run(names, &open_image(&image), &mut Cursor::new(&image))
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn custom_analyzers() {
+        let mut registry = AnalyzerRegistry::with_builtins();
+        registry.register(Box::new(CountingAnalyzer("How many files there are")));
+        let reports = run(&registry, &["count", "region"]).unwrap();
+        assert_eq!(reports[0].analyzer, "count");
+        assert_eq!(
+            reports[0].report.to_text(),
+            "Files:        5\nDetails:\n  Description:  How many files there are\nWarning: Just counting\n",
+        );
+        assert_eq!(
+            serde_json::to_value(&reports[0]).unwrap(),
+            serde_json::json!({
+                "analyzer": "count",
+                "sections": [
+                    { "fields": [{ "key": "Files", "value": "5" }] },
+                    { "title": "Details", "fields": [{ "key": "Description", "value": "How many files there are" }] },
+                ],
+                "warnings": ["Just counting"],
+            }),
+        );
+        assert_eq!(reports[1].analyzer, "region");
+    }
+
+    #[test]
+    fn registering_over_a_name() {
+        let mut registry = AnalyzerRegistry::with_builtins();
+        let names = |registry: &AnalyzerRegistry| registry.iter().map(|a| a.name().to_owned()).collect::<Vec<_>>();
+        assert_eq!(names(&registry), ["region", "fingerprint", "scrub", "assets"]);
+        registry.register(Box::new(CountingAnalyzer("First")));
+        registry.register(Box::new(CountingAnalyzer("Second")));
+        assert_eq!(names(&registry), ["region", "fingerprint", "scrub", "assets", "count"]);
+        assert_eq!(registry.get("count").unwrap().description(), "Second");
+        assert!(registry.get("missing").is_none());
+        assert_eq!(run(&registry, &["region", "missing"]).unwrap_err(), "missing");
+    }
+
+    #[test]
+    fn the_builtins_on_a_clean_image() {
+        let reports = run(&AnalyzerRegistry::with_builtins(), &["region", "scrub", "assets"]).unwrap();
+        let text: Vec<String> = reports.iter().map(|r| r.report.to_text()).collect();
+        assert!(reports.iter().all(|r| r.report.warnings.is_empty()), "{:?}", text);
+        assert!(text[0].contains("Banner:            none\n"), "{}", text[0]);
+        assert!(text[1].starts_with("Status:       scrubbed\n"), "{}", text[1]);
+        assert_eq!(text[2], "Assets:  none known\n");
+    }
+}
@@ -0,0 +1,481 @@
+// What booting an image would do, step by step, with the numbers from the
+// image: the IPL reading the header and loading the apploader, and the
+// apploader reading the FST and the DOL and jumping to it. The checks
+// `verify` makes for booting are noted at the step where they'd show up.
+//
+// Everything is read straight from the image, rather than through `Game`,
+// so there's still a trace for images `Game::open` won't open.
+
+use std::io::{self, BufRead, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{
+    rescue::{MEM1_END, MEM1_START},
+    sections::{
+        apploader::{
+            boot_limit_problem,
+            Apploader,
+            BootLimitProblem,
+            APPLOADER_HEADER_SIZE,
+            APPLOADER_LOAD_ADDR,
+            APPLOADER_OFFSET,
+        },
+        dol::{segment::SegmentType, DOLHeader},
+        header::{section_offset_problem, Header, HeaderError, MAGIC_WORD},
+    },
+    MIN_ALIGNMENT,
+};
+
+// Where the header has the magic word
+const MAGIC_WORD_OFFSET: u64 = 0x1c;
+// The OS keeps its globals, like where the FST is, below this, which is
+// where retail DOLs start
+const OS_GLOBALS_END: u64 = 0x8000_3100;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Note {
+    pub severity: Severity,
+    pub message: String,
+}
+
+// Bytes copied from the disc to RAM, or zeroed in RAM without a
+// `disc_offset`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Transfer {
+    pub what: String,
+    pub disc_offset: Option<u64>,
+    pub ram_address: u64,
+    pub length: u64,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct BootStep {
+    pub action: String,
+    pub details: Vec<String>,
+    pub transfers: Vec<Transfer>,
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct BootTrace {
+    pub steps: Vec<BootStep>,
+    // Booting can't go on past the last step, like when the magic word is
+    // wrong. Errors in other steps aren't always fatal on every console or
+    // emulator, so the rest is still traced.
+    pub stopped: bool,
+}
+
+impl BootStep {
+    fn new(action: impl Into<String>) -> BootStep {
+        BootStep { action: action.into(), details: Vec::new(), transfers: Vec::new(), notes: Vec::new() }
+    }
+
+    fn error(&mut self, message: impl ToString) {
+        self.notes.push(Note { severity: Severity::Error, message: message.to_string() });
+    }
+
+    fn warning(&mut self, message: impl ToString) {
+        self.notes.push(Note { severity: Severity::Warning, message: message.to_string() });
+    }
+
+    fn copy(&mut self, what: impl Into<String>, disc_offset: u64, ram_address: u64, length: u64) {
+        self.transfers.push(Transfer { what: what.into(), disc_offset: Some(disc_offset), ram_address, length });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.notes.iter().any(|n| n.severity == Severity::Error)
+    }
+}
+
+impl BootTrace {
+    // Traces booting the image in `iso`, which is `len` bytes long. Only
+    // failing to seek fails, anything that can't be read is noted in the
+    // trace instead.
+    pub fn new(mut iso: impl BufRead + Seek, len: u64) -> io::Result<BootTrace> {
+        let mut trace = BootTrace { steps: Vec::new(), stopped: false };
+
+        let mut step = BootStep::new("Read the header at 0x0");
+        iso.seek(SeekFrom::Start(MAGIC_WORD_OFFSET))?;
+        let header = match iso.read_u32::<BigEndian>() {
+            Ok(MAGIC_WORD) => {
+                step.details.push(format!("Magic word {:#010x} at {:#x}", MAGIC_WORD, MAGIC_WORD_OFFSET));
+                Header::new(&mut iso, 0).map_err(|e| step.error(format!("The header couldn't be read: {}", e))).ok()
+            },
+            Ok(word) => {
+                step.error(format!(
+                    "The magic word at {:#x} is {:#010x}, not {:#010x}, so this isn't a GameCube disc",
+                    MAGIC_WORD_OFFSET, word, MAGIC_WORD,
+                ));
+                None
+            },
+            Err(e) => {
+                step.error(format!("The magic word couldn't be read: {}", e));
+                None
+            },
+        };
+        let Some(header) = header else {
+            return Ok(trace.stop(step));
+        };
+        step.details.push(format!("Game ID {}{}, \"{}\"", header.game_code, header.maker_code, header.title));
+        step.details.push(format!(
+            "DOL at {:#x}, FST at {:#x} ({:#x} bytes, room for {:#x})",
+            header.dol_offset, header.fst_offset, header.fst_size, header.max_fst_size,
+        ));
+        trace.steps.push(step);
+
+        let mut step = BootStep::new(format!("Load the apploader from {:#x}", APPLOADER_OFFSET));
+        let apploader = match Apploader::new(&mut iso, APPLOADER_OFFSET) {
+            Ok(a) => a,
+            Err(e) => {
+                step.error(format!("The apploader's header couldn't be read: {}", e));
+                return Ok(trace.stop(step));
+            },
+        };
+        step.details.push(format!(
+            "Built {}, {:#x} bytes of code and a {:#x} byte trailer",
+            apploader.date.trim_end_matches('\0'), apploader.code_size, apploader.trailer_size,
+        ));
+        let code_start = APPLOADER_OFFSET + APPLOADER_HEADER_SIZE as u64;
+        let apploader_size = apploader.total_size() as u64;
+        let apploader_ram = (APPLOADER_LOAD_ADDR as u64, APPLOADER_LOAD_ADDR as u64 + apploader_size);
+        step.copy("code and trailer", code_start, apploader_ram.0, apploader_size);
+        if let Some(p @ BootLimitProblem::ApploaderTooBig(_)) = boot_limit_problem(apploader.total_size(), 0) {
+            step.error(p);
+        }
+        if code_start + apploader_size > len {
+            step.error(format!("The apploader goes past the end of the image at {:#x}", len));
+        }
+        step.details.push(format!("Entry point {:#010x}", apploader.entry_point));
+        let code_end = APPLOADER_LOAD_ADDR as u64 + apploader.code_size as u64;
+        if !(APPLOADER_LOAD_ADDR as u64..code_end).contains(&apploader.entry_point) {
+            step.error(format!(
+                "The entry point isn't in the apploader's code, which is loaded to {:#010x}-{:#010x}",
+                APPLOADER_LOAD_ADDR, code_end,
+            ));
+        }
+        trace.steps.push(step);
+
+        // The apploader puts the FST at the top of RAM, leaving room for it
+        // to grow to `max_fst_size`. Where exactly depends on the apploader,
+        // this is where retail ones put it.
+        let mut step = BootStep::new(format!("The apploader reads the FST from {:#x}", header.fst_offset));
+        let fst_address = (MEM1_END as u64).saturating_sub(header.max_fst_size as u64) & !0x1f;
+        let fst_ram = (fst_address, fst_address + header.fst_size as u64);
+        step.copy("FST", header.fst_offset, fst_address, header.fst_size as u64);
+        step.details.push(format!("Room for {:#x} bytes at the top of RAM", header.max_fst_size));
+        section_problems(&mut step, &header, &apploader, len, "FST");
+        if header.fst_offset + header.fst_size as u64 > len {
+            step.error(format!("The FST goes past the end of the image at {:#x}", len));
+        }
+        if header.fst_size > header.max_fst_size {
+            step.error(format!(
+                "The FST is {:#x} bytes, more than the {:#x} the header leaves room for",
+                header.fst_size, header.max_fst_size,
+            ));
+        }
+        match boot_limit_problem(apploader.total_size(), header.max_fst_size) {
+            Some(BootLimitProblem::ApploaderTooBig(_)) | None => {},
+            Some(p) if p.is_error() => step.error(p),
+            Some(p) => step.warning(p),
+        }
+        trace.steps.push(step);
+
+        let mut step = BootStep::new(format!("The apploader loads the DOL from {:#x}", header.dol_offset));
+        section_problems(&mut step, &header, &apploader, len, "DOL");
+        let dol = match DOLHeader::new(&mut iso, header.dol_offset) {
+            Ok(dol) => dol,
+            Err(e) => {
+                step.error(format!("The DOL's header couldn't be read: {}", e));
+                return Ok(trace.stop(step));
+            },
+        };
+        if let Err(e) = dol.validate() {
+            step.error(e);
+        }
+        let mut loaded: Vec<(String, u64, u64)> = Vec::new();
+        for s in dol.iter_segments() {
+            let (name, start, end) = (s.to_string(), s.loading_address, s.loading_address + s.size as u64);
+            step.copy(name.clone(), s.offset, start, s.size as u64);
+            if s.offset + s.size as u64 > len {
+                step.error(format!("{} goes past the end of the image at {:#x}", name, len));
+            }
+            ram_problems(&mut step, &name, start, end, apploader_ram, fst_ram);
+            for (other, other_start, other_end) in &loaded {
+                if start < *other_end && *other_start < end {
+                    step.error(format!("{} is loaded on top of {}, at {:#010x}-{:#010x}", name, other, other_start, other_end));
+                }
+            }
+            loaded.push((name, start, end));
+        }
+        if loaded.is_empty() {
+            step.error("The DOL has no segments");
+        }
+        trace.steps.push(step);
+
+        let mut step = BootStep::new("The apploader zeroes the BSS");
+        step.transfers.push(Transfer {
+            what: "BSS".to_owned(),
+            disc_offset: None,
+            ram_address: dol.bss_address,
+            length: dol.bss_size as u64,
+        });
+        if dol.bss_size != 0 {
+            ram_problems(&mut step, "The BSS", dol.bss_address, dol.bss_address + dol.bss_size as u64, apploader_ram, fst_ram);
+        }
+        trace.steps.push(step);
+
+        let mut step = BootStep::new(format!("Jump to the DOL's entry point at {:#010x}", dol.entry_point));
+        match dol.segment_at_addr(dol.entry_point) {
+            Some(s) if s.seg_type == SegmentType::Text => step.details.push(format!("In {}", s)),
+            Some(s) => step.error(format!("The entry point is in {}, which isn't code", s)),
+            None => step.error("The entry point isn't in any of the DOL's segments"),
+        }
+        trace.steps.push(step);
+        Ok(trace)
+    }
+
+    fn stop(mut self, step: BootStep) -> BootTrace {
+        self.steps.push(step);
+        self.stopped = true;
+        self
+    }
+
+    // The first step with an error, counting from 1
+    pub fn first_failure(&self) -> Option<usize> {
+        self.steps.iter().position(BootStep::has_errors).map(|i| i + 1)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            s += &format!("{}. {}\n", i + 1, step.action);
+            for d in &step.details {
+                s += &format!("     {}\n", d);
+            }
+            for t in &step.transfers {
+                s += &match t.disc_offset {
+                    Some(from) => format!(
+                        "     {:#010x} -> {:#010x}, {:#x} bytes ({})\n",
+                        from, t.ram_address, t.length, t.what,
+                    ),
+                    None => format!("     Zero {:#010x}, {:#x} bytes ({})\n", t.ram_address, t.length, t.what),
+                };
+            }
+            for n in &step.notes {
+                let label = match n.severity {
+                    Severity::Error => "Error",
+                    Severity::Warning => "Warning",
+                };
+                s += &format!("     {}: {}\n", label, n.message);
+            }
+        }
+        if self.stopped {
+            s += "The boot stops here.\n";
+        }
+        match self.first_failure() {
+            Some(step) => s += &format!("\nBooting would fail at step {}.\n", step),
+            None => s += "\nNothing found that would stop it from booting.\n",
+        }
+        s
+    }
+}
+
+// `verify`'s checks of where the header says the DOL or FST is, for just
+// the one section.
+fn section_problems(step: &mut BootStep, header: &Header, apploader: &Apploader, len: u64, section: &'static str) {
+    let offset = if section == "DOL" { header.dol_offset } else { header.fst_offset };
+    if !offset.is_multiple_of(MIN_ALIGNMENT) {
+        step.error(HeaderError::MisalignedOffset { section, offset });
+    }
+    if let Some(e) = section_offset_problem(section, offset, Some(apploader), len) {
+        step.error(e);
+    } else if section == "DOL" && header.dol_offset == header.fst_offset {
+        step.error(HeaderError::SharedOffset(offset));
+    }
+}
+
+// Where something the apploader loads to `start`-`end` in RAM would be a
+// problem.
+fn ram_problems(step: &mut BootStep, name: &str, start: u64, end: u64, apploader: (u64, u64), fst: (u64, u64)) {
+    if start < MEM1_START as u64 || end > MEM1_END as u64 {
+        step.error(format!(
+            "{} goes to {:#010x}-{:#010x}, outside of RAM at {:#010x}-{:#010x}",
+            name, start, end, MEM1_START, MEM1_END,
+        ));
+        return;
+    }
+    if start < OS_GLOBALS_END {
+        step.warning(format!("{} starts at {:#010x}, on top of the OS's globals below {:#010x}", name, start, OS_GLOBALS_END));
+    }
+    if start < apploader.1 && apploader.0 < end {
+        step.error(format!("{} is loaded on top of the apploader, which is still running", name));
+    }
+    if start < fst.1 && fst.0 < end {
+        step.error(format!("{} is loaded on top of the FST, at {:#010x}-{:#010x}", name, fst.0, fst.1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{build_image, synthetic_tree};
+
+    const DOL_OFFSET: usize = 0x3000;
+    const APPLOADER_ENTRY_POINT: usize = 0x2450;
+    // In the header
+    const DOL_OFFSET_FIELD: usize = 0x420;
+    const MAX_FST_SIZE_FIELD: usize = 0x42c;
+    // In the DOL's header
+    const TEXT0_ADDRESS: usize = 0x48;
+    const BSS_ADDRESS: usize = 0xd8;
+    const BSS_SIZE: usize = 0xdc;
+    const ENTRY_POINT: usize = 0xe0;
+
+    fn set(image: &mut [u8], at: usize, value: u32) {
+        image[at..at + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    fn trace(image: &[u8]) -> BootTrace {
+        BootTrace::new(Cursor::new(image), image.len() as u64).unwrap()
+    }
+
+    // The messages of the notes in step `step`, counting from 1
+    fn notes(trace: &BootTrace, step: usize) -> Vec<&str> {
+        trace.steps[step - 1].notes.iter().map(|n| n.message.as_str()).collect()
+    }
+
+    // `change` breaks the synthetic image, which should fail at `step` with
+    // a message that has `message` in it
+    fn assert_fails_at(step: usize, message: &str, change: impl FnOnce(&mut Vec<u8>)) {
+        let mut image = build_image(synthetic_tree().path());
+        change(&mut image);
+        let trace = trace(&image);
+        assert_eq!(trace.first_failure(), Some(step), "{}", trace.to_text());
+        assert!(notes(&trace, step).iter().any(|n| n.contains(message)), "{}", trace.to_text());
+        assert!(trace.to_text().ends_with(&format!("\nBooting would fail at step {}.\n", step)));
+    }
+
+    #[test]
+    fn a_clean_image() {
+        let image = build_image(synthetic_tree().path());
+        let trace = trace(&image);
+        assert_eq!(trace.first_failure(), None, "{}", trace.to_text());
+        assert!(!trace.stopped);
+        assert_eq!(trace.steps.len(), 6);
+        assert!(trace.steps.iter().all(|s| s.notes.is_empty()), "{}", trace.to_text());
+        let copies: Vec<(&str, Option<u64>, u64, u64)> = trace.steps.iter()
+            .flat_map(|s| &s.transfers)
+            .map(|t| (t.what.as_str(), t.disc_offset, t.ram_address, t.length))
+            .collect();
+        assert_eq!(copies, [
+            ("code and trailer", Some(0x2460), 0x8120_0000, 0x20),
+            ("FST", Some(0x2800), 0x817f_ff40, 0xa7),
+            (".text0", Some(0x3100), 0x8000_3100, 0x20),
+            ("BSS", None, 0, 0),
+        ]);
+        let text = trace.to_text();
+        assert!(text.starts_with("1. Read the header at 0x0\n     Magic word 0xc2339f3d at 0x1c\n     Game ID GSLF01, \"gcmod self-test\"\n"), "{}", text);
+        assert!(text.contains("4. The apploader loads the DOL from 0x3000\n     0x00003100 -> 0x80003100, 0x20 bytes (.text0)\n"), "{}", text);
+        assert!(text.ends_with("6. Jump to the DOL's entry point at 0x80003100\n     In .text0\n\nNothing found that would stop it from booting.\n"), "{}", text);
+    }
+
+    #[test]
+    fn images_that_arent_gamecube_discs() {
+        let mut image = build_image(synthetic_tree().path());
+        image[0x1c] ^= 1;
+        let trace = trace(&image);
+        assert!(trace.stopped);
+        assert_eq!(trace.steps.len(), 1);
+        assert!(notes(&trace, 1)[0].contains("so this isn't a GameCube disc"), "{:?}", notes(&trace, 1));
+        assert!(trace.to_text().ends_with("The boot stops here.\n\nBooting would fail at step 1.\n"));
+
+        // Too short to even have one
+        let trace = BootTrace::new(Cursor::new(&image[..0x10]), 0x10).unwrap();
+        assert!(trace.stopped && trace.first_failure() == Some(1));
+    }
+
+    #[test]
+    fn broken_apploaders() {
+        assert_fails_at(2, "The entry point isn't in the apploader's code", |image| set(image, APPLOADER_ENTRY_POINT, 0x8130_0000));
+        assert_fails_at(2, "The apploader goes past the end of the image", |image| image.truncate(0x2470));
+    }
+
+    #[test]
+    fn fsts_with_no_room() {
+        assert_fails_at(3, "The FST is 0xa7 bytes, more than the 0x80 the header leaves room for", |image| {
+            set(image, MAX_FST_SIZE_FIELD, 0x80);
+        });
+    }
+
+    #[test]
+    fn dols_in_the_wrong_place() {
+        assert_fails_at(4, "0x3002", |image| set(image, DOL_OFFSET_FIELD, 0x3002));
+        // Where the apploader is
+        assert_fails_at(4, "apploader", |image| set(image, DOL_OFFSET_FIELD, 0x2450));
+        assert_fails_at(4, "past the end", |image| set(image, DOL_OFFSET_FIELD, 0x4000_0000));
+    }
+
+    #[test]
+    fn segments_that_cant_be_loaded() {
+        assert_fails_at(4, ".text0 goes to 0x81800000-0x81800020, outside of RAM", |image| {
+            set(image, DOL_OFFSET + TEXT0_ADDRESS, 0x8180_0000);
+        });
+        assert_fails_at(4, ".text0 is loaded on top of the FST", |image| {
+            set(image, DOL_OFFSET + TEXT0_ADDRESS, 0x817f_ff40);
+            set(image, DOL_OFFSET + ENTRY_POINT, 0x817f_ff40);
+        });
+        assert_fails_at(4, ".text0 is loaded on top of the apploader", |image| {
+            set(image, DOL_OFFSET + TEXT0_ADDRESS, 0x8120_0010);
+            set(image, DOL_OFFSET + ENTRY_POINT, 0x8120_0010);
+        });
+        // Only a warning
+        let mut image = build_image(synthetic_tree().path());
+        set(&mut image, DOL_OFFSET + TEXT0_ADDRESS, 0x8000_3000);
+        set(&mut image, DOL_OFFSET + ENTRY_POINT, 0x8000_3000);
+        let trace = trace(&image);
+        assert_eq!(trace.first_failure(), None, "{}", trace.to_text());
+        assert_eq!(trace.steps[3].notes[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn the_bss_and_the_entry_point() {
+        assert_fails_at(5, "The BSS is loaded on top of the apploader", |image| {
+            set(image, DOL_OFFSET + BSS_ADDRESS, 0x8120_0000);
+            set(image, DOL_OFFSET + BSS_SIZE, 0x10);
+        });
+        assert_fails_at(6, "The entry point isn't in any of the DOL's segments", |image| {
+            set(image, DOL_OFFSET + ENTRY_POINT, 0x8000_4000);
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn the_trace_as_json() {
+        let mut image = build_image(synthetic_tree().path());
+        set(&mut image, DOL_OFFSET + ENTRY_POINT, 0x8000_4000);
+        let doc = serde_json::to_value(trace(&image)).unwrap();
+        assert_eq!(doc["stopped"], false);
+        let steps = doc["steps"].as_array().unwrap();
+        assert_eq!(steps.len(), 6);
+        assert_eq!(steps[3]["transfers"][0]["disc_offset"], 0x3100);
+        assert_eq!(steps[4]["transfers"][0]["disc_offset"], serde_json::Value::Null);
+        assert_eq!(steps[5]["notes"][0]["severity"], "error");
+    }
+}
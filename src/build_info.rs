@@ -0,0 +1,59 @@
+// What build of gcmod this is, for recording in the documents it writes and
+// for `gcmod --version --verbose`. The git hash and features come from
+// build.rs.
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("GCMOD_GIT_HASH");
+// Comma separated, empty when no features are enabled.
+const FEATURES: &str = env!("GCMOD_FEATURES");
+
+// The image formats gcmod can read. Compressed formats (like gcz and ciso)
+// aren't supported yet, only whole images compressed with gzip or zstd, with
+// the stream-compress feature.
+#[cfg(not(feature = "stream-compress"))]
+pub const CONTAINER_FORMATS: &[&str] = &["iso", "gcm"];
+#[cfg(feature = "stream-compress")]
+pub const CONTAINER_FORMATS: &[&str] = &["iso", "gcm", "gzip", "zstd"];
+
+pub fn features() -> Vec<String> {
+    FEATURES.split(',').filter(|f| !f.is_empty()).map(str::to_owned).collect()
+}
+
+fn major_version(version: &str) -> Option<&str> {
+    version.split('.').next().filter(|v| !v.is_empty())
+}
+
+// A warning for a document written by a different major version of gcmod,
+// since its fields might not mean the same thing anymore.
+pub fn version_warning(what: &str, recorded: &str) -> Option<String> {
+    (major_version(recorded) != major_version(VERSION)).then(|| format!(
+        "{} was written by gcmod {}, but this is gcmod {}",
+        what, recorded, VERSION,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_info_is_filled_in() {
+        assert!(!VERSION.is_empty() && !GIT_HASH.is_empty());
+        assert!(features().iter().all(|f| !f.is_empty()));
+        assert_eq!(features().contains(&"stream-compress".to_owned()), cfg!(feature = "stream-compress"));
+        assert!(CONTAINER_FORMATS.contains(&"iso"));
+    }
+
+    #[test]
+    fn only_other_major_versions_are_warned_about() {
+        let major = major_version(VERSION).unwrap();
+        assert_eq!(version_warning("x", VERSION), None);
+        assert_eq!(version_warning("x", &format!("{}.999.0", major)), None);
+        let other = format!("{}.0.0", major.parse::<u32>().unwrap() + 1);
+        assert_eq!(
+            version_warning("The manifest", &other),
+            Some(format!("The manifest was written by gcmod {}, but this is gcmod {}", other, VERSION)),
+        );
+        assert!(version_warning("x", "").is_some());
+    }
+}
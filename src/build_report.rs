@@ -0,0 +1,183 @@
+// For `rebuild --report`, a record of how a ROM was built and what ended up
+// where, for shipping alongside it (like a BUILD_INFO.txt).
+
+use std::{
+    fmt::Write as _,
+    io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{
+    build_info,
+    hash::to_hex,
+    timestamp::utc_date,
+    RebuildOptions,
+    ROMRebuilder,
+};
+
+// The order the system data is laid out in at the start of the ROM.
+pub const SYSTEM_ORDER: &[&str] = &["ISO.hdr", "Apploader.ldr", "Game.toc", "Start.dol"];
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct BuildReport {
+    pub gcmod_version: String,
+    pub git_hash: String,
+    // Seconds since the Unix epoch, or $SOURCE_DATE_EPOCH if it's set
+    pub timestamp: u64,
+    pub date: String,
+    pub root: String,
+    pub output: String,
+    pub game_id: String,
+    pub options: ReportOptions,
+    pub sections: Vec<ReportFile>,
+    pub file_count: usize,
+    pub total_bytes: u64,
+    // Space between the start of the ROM and the end of the last file that
+    // isn't part of any file, from alignment
+    pub padding: u64,
+    // Space after the last file
+    pub free_space: u64,
+    pub sha1: String,
+    // Sorted by offset
+    pub files: Vec<ReportFile>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ReportOptions {
+    pub file_alignment: u64,
+    pub system_alignment: u64,
+    pub capacity: u64,
+    pub system_order: Vec<String>,
+    pub file_order: String,
+    pub rebuild_systemdata: bool,
+    pub keep_user_fields: bool,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ReportFile {
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BuildReport {
+    pub fn new(
+        rebuilder: &ROMRebuilder,
+        options: &RebuildOptions,
+        root: impl AsRef<Path>,
+        output: impl AsRef<Path>,
+        sha1: &[u8],
+    ) -> io::Result<BuildReport> {
+        let system_dir = format!("/{}/", rebuilder.system_paths().dir());
+        let mut written = rebuilder.written_files()?;
+        written.sort_by(|a, b| (a.0, &a.2).cmp(&(b.0, &b.2)));
+
+        let (sections, files): (Vec<_>, Vec<_>) = written.into_iter()
+            .map(|(offset, size, path)| ReportFile { path, offset, size })
+            .partition(|f| f.path.starts_with(&system_dir));
+
+        let end = sections.iter().chain(&files).map(|f| f.offset + f.size).max().unwrap_or(0);
+        let used: u64 = sections.iter().chain(&files).map(|f| f.size).sum();
+        let total_bytes = files.iter().map(|f| f.size).sum();
+
+        let file_order = if options.index_map.is_some() {
+            "original FST indices (manifest)"
+        } else if !options.rebuild_systemdata {
+            "existing FST"
+        } else {
+            "directory listing"
+        };
+        let file_order = match options.file_order {
+            Some(_) => format!("access profile, then {}", file_order),
+            None => file_order.to_owned(),
+        };
+
+        let timestamp = build_timestamp();
+        let header = rebuilder.header();
+        Ok(BuildReport {
+            gcmod_version: build_info::VERSION.to_owned(),
+            git_hash: build_info::GIT_HASH.to_owned(),
+            timestamp,
+            date: utc_date(timestamp as i64),
+            root: root.as_ref().display().to_string(),
+            output: output.as_ref().display().to_string(),
+            game_id: format!("{}{}", header.game_code, header.maker_code),
+            options: ReportOptions {
+                file_alignment: options.file_alignment,
+                system_alignment: options.system_alignment,
+                capacity: rebuilder.capacity(),
+                system_order: SYSTEM_ORDER.iter().map(|&s| s.to_owned()).collect(),
+                file_order,
+                rebuild_systemdata: options.rebuild_systemdata,
+                keep_user_fields: options.keep_user_fields,
+            },
+            sections,
+            file_count: files.len(),
+            total_bytes,
+            padding: end.saturating_sub(used),
+            free_space: rebuilder.capacity().saturating_sub(end),
+            sha1: to_hex(sha1),
+            files,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_text(&mut s);
+        s
+    }
+
+    fn write_text(&self, s: &mut String) -> std::fmt::Result {
+        writeln!(s, "Built by:          gcmod {} ({})", self.gcmod_version, self.git_hash)?;
+        writeln!(s, "Date:              {} ({})", self.date, self.timestamp)?;
+        writeln!(s, "Root:              {}", self.root)?;
+        writeln!(s, "Output:            {}", self.output)?;
+        writeln!(s, "Game ID:           {}", self.game_id)?;
+        writeln!(s, "SHA-1:             {}", self.sha1)?;
+        writeln!(s)?;
+        writeln!(s, "Options:")?;
+        writeln!(s, "  File alignment:    {}", self.options.file_alignment)?;
+        writeln!(s, "  System alignment:  {}", self.options.system_alignment)?;
+        writeln!(s, "  Capacity:          {}", self.options.capacity)?;
+        writeln!(s, "  System order:      {}", self.options.system_order.join(", "))?;
+        writeln!(s, "  File order:        {}", self.options.file_order)?;
+        writeln!(s, "  Rebuild FST:       {}", self.options.rebuild_systemdata)?;
+        writeln!(s, "  Keep user fields:  {}", self.options.keep_user_fields)?;
+        writeln!(s)?;
+        writeln!(s, "System data:")?;
+        for f in &self.sections {
+            let name = f.path.rsplit('/').next().unwrap_or(&f.path);
+            writeln!(s, "  {:<14} {:#010x}  {} bytes", name, f.offset, f.size)?;
+        }
+        writeln!(s)?;
+        writeln!(s, "Files:             {}", self.file_count)?;
+        writeln!(s, "Total bytes:       {}", self.total_bytes)?;
+        writeln!(s, "Padding:           {}", self.padding)?;
+        writeln!(s, "Free space:        {}", self.free_space)?;
+        writeln!(s)?;
+        writeln!(s, "{:<10}  {:>10}  Path", "Offset", "Size")?;
+        for f in &self.files {
+            writeln!(s, "{:#010x}  {:>10}  {}", f.offset, f.size, f.path)?;
+        }
+        Ok(())
+    }
+}
+
+// Reproducible builds set $SOURCE_DATE_EPOCH so the report, and the build
+// stamp, don't change from one build to the next.
+pub(crate) fn build_timestamp() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or_else(|| SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0))
+}
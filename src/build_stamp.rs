@@ -0,0 +1,371 @@
+// A record of how an image was built, that `rebuild --stamp` writes into
+// the header, so the image itself says which gcmod built it, when, from
+// what files, and with which layout options. Nothing else writes one, and
+// most images don't have one.
+//
+// It goes at `STAMP_OFFSET`, in the part of bi2 nobody understands, which
+// is all zeros on retail discs, and is `STAMP_SIZE` bytes:
+//   0x00  "GCMS"
+//   0x04  u16 format version, `STAMP_VERSION`
+//   0x06  u16 size of the record, `STAMP_SIZE`
+//   0x08  gcmod's version, ASCII, padded with NULs to 16 bytes
+//   0x18  u64 when it was built, in seconds since 1970 UTC
+//   0x20  SHA-1 of the files, see `tree_hash`
+//   0x34  SHA-1 of the layout options, see `options_hash`
+//   0x48  zeros
+//   0x4c  u32 CRC-32 of everything before it
+// all big-endian, like the rest of the header. NKit's stamp is elsewhere,
+// in the game name field, see `nkit::NKIT_STAMP_OFFSET`, so an image can
+// have both, and `stamps` tells them apart.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Seek, Write},
+    ops::Range,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    build_info,
+    build_report,
+    hash::{to_hex, Crc32, Sha1, Sha1Writer},
+    nkit::{self, NkitStamp},
+    sections::{header::{BI2_KNOWN_SIZE, BI2_OFFSET, GAME_HEADER_SIZE}, Field},
+    timestamp,
+    Game,
+    RebuildOptions,
+};
+
+// In the header block, see `Header::raw`
+pub const STAMP_OFFSET: usize = 0x1fe0;
+pub const STAMP_SIZE: usize = 0x50;
+pub const STAMP_MAGIC: &[u8; 4] = b"GCMS";
+pub const STAMP_VERSION: u16 = 1;
+
+const GCMOD_VERSION_SIZE: usize = 16;
+const CRC_OFFSET: usize = 0x4c;
+
+// It has to stay clear of the fields bi2 does have, and of NKit's stamp
+const _: () = assert!(STAMP_OFFSET >= BI2_OFFSET as usize + BI2_KNOWN_SIZE);
+const _: () = assert!(STAMP_OFFSET + STAMP_SIZE <= GAME_HEADER_SIZE);
+const _: () = assert!(STAMP_OFFSET > nkit::NKIT_STAMP_OFFSET + 0x20);
+
+pub fn stamp_range() -> Range<usize> {
+    STAMP_OFFSET..STAMP_OFFSET + STAMP_SIZE
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BuildStamp {
+    pub version: u16,
+    pub gcmod_version: String,
+    // Seconds since 1970 UTC
+    pub built: i64,
+    pub tree_hash: [u8; 20],
+    pub options_hash: [u8; 20],
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum StampError {
+    // From a newer gcmod, which might have laid it out differently
+    UnknownVersion(u16),
+    WrongSize(u16),
+    Checksum { stored: u32, computed: u32 },
+    // Something that isn't a stamp is where it goes, so one can't be written
+    RegionInUse,
+    // The header block is shorter than `GAME_HEADER_SIZE`
+    Truncated,
+}
+
+impl fmt::Display for StampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StampError::UnknownVersion(v) =>
+                write!(f, "The build stamp is version {}, but this gcmod only knows up to {}", v, STAMP_VERSION),
+            StampError::WrongSize(size) =>
+                write!(f, "The build stamp says it's {:#x} bytes, but it should be {:#x}", size, STAMP_SIZE),
+            StampError::Checksum { stored, computed } => write!(
+                f,
+                "The build stamp is damaged: its CRC-32 is {:08x}, but it says {:08x}",
+                computed, stored,
+            ),
+            StampError::RegionInUse => write!(
+                f,
+                "The header has something other than a build stamp at {:#x}-{:#x}, so there's nowhere to put one",
+                STAMP_OFFSET, STAMP_OFFSET + STAMP_SIZE,
+            ),
+            StampError::Truncated => write!(f, "The header is too short to have a build stamp"),
+        }
+    }
+}
+
+impl Error for StampError {}
+
+impl BuildStamp {
+    // Built now, or at $SOURCE_DATE_EPOCH, by this gcmod
+    pub fn new(tree_hash: [u8; 20], options_hash: [u8; 20]) -> BuildStamp {
+        BuildStamp {
+            version: STAMP_VERSION,
+            gcmod_version: build_info::VERSION.to_owned(),
+            built: build_report::build_timestamp() as i64,
+            tree_hash,
+            options_hash,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; STAMP_SIZE] {
+        let mut bytes = [0; STAMP_SIZE];
+        bytes[..4].copy_from_slice(STAMP_MAGIC);
+        BigEndian::write_u16(&mut bytes[0x04..], self.version);
+        BigEndian::write_u16(&mut bytes[0x06..], STAMP_SIZE as u16);
+        // Versions are short, but one that isn't is cut off rather than
+        // spilling into the next field
+        let version = self.gcmod_version.as_bytes();
+        let len = version.len().min(GCMOD_VERSION_SIZE);
+        bytes[0x08..0x08 + len].copy_from_slice(&version[..len]);
+        BigEndian::write_u64(&mut bytes[0x18..], self.built as u64);
+        bytes[0x20..0x34].copy_from_slice(&self.tree_hash);
+        bytes[0x34..0x48].copy_from_slice(&self.options_hash);
+        let crc = crc_of(&bytes[..CRC_OFFSET]);
+        BigEndian::write_u32(&mut bytes[CRC_OFFSET..], crc);
+        bytes
+    }
+
+    // `bytes` starts with the magic, and is at least `STAMP_SIZE` long.
+    fn parse(bytes: &[u8]) -> Result<BuildStamp, StampError> {
+        let version = BigEndian::read_u16(&bytes[0x04..]);
+        if version > STAMP_VERSION {
+            return Err(StampError::UnknownVersion(version));
+        }
+        let size = BigEndian::read_u16(&bytes[0x06..]);
+        if size as usize != STAMP_SIZE {
+            return Err(StampError::WrongSize(size));
+        }
+        let stored = BigEndian::read_u32(&bytes[CRC_OFFSET..]);
+        let computed = crc_of(&bytes[..CRC_OFFSET]);
+        if stored != computed {
+            return Err(StampError::Checksum { stored, computed });
+        }
+        let gcmod_version = &bytes[0x08..0x08 + GCMOD_VERSION_SIZE];
+        let gcmod_version = gcmod_version.split(|&b| b == 0).next().unwrap_or(&[]);
+        Ok(BuildStamp {
+            version,
+            gcmod_version: String::from_utf8_lossy(gcmod_version).into_owned(),
+            built: BigEndian::read_u64(&bytes[0x18..]) as i64,
+            tree_hash: bytes[0x20..0x34].try_into().unwrap(),
+            options_hash: bytes[0x34..0x48].try_into().unwrap(),
+        })
+    }
+
+    // What `info --stamp` shows
+    pub fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::number("Stamp version", self.version as u64),
+            Field::text("Built by", format!("gcmod {}", self.gcmod_version)),
+            Field::text("Built", format!("{} UTC", timestamp::utc_minute(self.built))),
+            Field::text("Tree hash", to_hex(&self.tree_hash)),
+            Field::text("Options hash", to_hex(&self.options_hash)),
+        ]
+    }
+}
+
+fn crc_of(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+// Whether `header` has something that starts like a build stamp, even a
+// damaged one.
+pub fn has_stamp(header: &[u8]) -> bool {
+    header.get(STAMP_OFFSET..).is_some_and(|s| s.starts_with(STAMP_MAGIC))
+}
+
+// The build stamp in `header`, the header block as it was read, if it has
+// one. Not having one is normal.
+pub fn read(header: &[u8]) -> Result<Option<BuildStamp>, StampError> {
+    if !has_stamp(header) {
+        return Ok(None);
+    }
+    let bytes = header.get(stamp_range()).ok_or(StampError::Truncated)?;
+    BuildStamp::parse(bytes).map(Some)
+}
+
+// Puts `stamp` in `header`, over any stamp that's already there. Anything
+// else there is left alone, and it fails instead.
+pub fn write(header: &mut [u8], stamp: &BuildStamp) -> Result<(), StampError> {
+    if header.len() < GAME_HEADER_SIZE {
+        return Err(StampError::Truncated);
+    }
+    if !has_stamp(header) && header[stamp_range()].iter().any(|&b| b != 0) {
+        return Err(StampError::RegionInUse);
+    }
+    header[stamp_range()].copy_from_slice(&stamp.to_bytes());
+    Ok(())
+}
+
+// Takes out the stamp in `header`, if there is one, leaving zeros like
+// there were before it. Returns whether there was one.
+pub fn clear(header: &mut [u8]) -> bool {
+    if !has_stamp(header) || header.len() < STAMP_OFFSET + STAMP_SIZE {
+        return false;
+    }
+    header[stamp_range()].fill(0);
+    true
+}
+
+// The stamps something left in a header, which are different things that
+// happen to both be in there.
+#[derive(Debug)]
+pub struct HeaderStamps {
+    pub nkit: Option<NkitStamp>,
+    pub build: Result<Option<BuildStamp>, StampError>,
+}
+
+pub fn stamps(header: &[u8]) -> HeaderStamps {
+    HeaderStamps { nkit: nkit::find_stamp(header), build: read(header) }
+}
+
+// A SHA-1 of everything in a file system that the game can read: for each
+// file in order of its path, like "/audio/bgm.dsp", the path, a NUL, its
+// size as a big-endian u64, then its contents. The system data isn't part
+// of it, since its offsets change with the layout. `files` are (path, size,
+// where it is), and `copy` writes the contents of one to what it's given,
+// returning how many bytes that was, which has to be its size.
+pub fn tree_hash<S>(
+    mut files: Vec<(String, u64, S)>,
+    mut copy: impl FnMut(&S, &mut dyn Write) -> io::Result<u64>,
+) -> io::Result<[u8; 20]> {
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut sha1 = Sha1Writer::new(io::sink());
+    for (path, size, source) in &files {
+        sha1.write_all(path.as_bytes())?;
+        sha1.write_all(&[0])?;
+        sha1.write_all(&size.to_be_bytes())?;
+        let copied = copy(source, &mut sha1)?;
+        if copied != *size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} was {} bytes, but {} were hashed", path, size, copied),
+            ));
+        }
+    }
+    Ok(sha1.finish().1)
+}
+
+// A SHA-1 of the options that change where things go on the ROM, so two
+// images with the same one were laid out the same way.
+pub fn options_hash(options: &RebuildOptions) -> [u8; 20] {
+    let file_order = options.file_order.as_ref()
+        .map(|p| p.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>().join(","))
+        .unwrap_or_default();
+    let text = format!(
+        "file_alignment={}\nsystem_alignment={}\nrebuild_fst={}\npreserve_indices={}\nkeep_user_fields={}\n\
+        capacity={}\nignore_debug_monitor={}\nprune_missing={}\nfile_order={}\n",
+        options.file_alignment,
+        options.system_alignment,
+        options.rebuild_systemdata,
+        options.index_map.is_some(),
+        options.keep_user_fields,
+        options.capacity,
+        options.ignore_debug_monitor,
+        options.prune_missing,
+        file_order,
+    );
+    let mut sha1 = Sha1::new();
+    sha1.update(text.as_bytes());
+    sha1.finish()
+}
+
+impl Game {
+    // `tree_hash` of the image's own files, to check a build stamp's against.
+    pub fn tree_hash(&self, mut iso: impl BufRead + Seek) -> io::Result<[u8; 20]> {
+        let files = self.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| (f.info.normalized_path(), f.size as u64, f))
+            .collect();
+        tree_hash(files, |f, out| f.extract(&mut iso, out).map(|()| f.size as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A header with a stamp, and NKit's next to it
+    fn stamped() -> (Vec<u8>, BuildStamp) {
+        let stamp = BuildStamp::new([1; 20], [2; 20]);
+        let mut header = vec![0; GAME_HEADER_SIZE];
+        header[nkit::NKIT_STAMP_OFFSET..][..4].copy_from_slice(nkit::NKIT_MAGIC);
+        write(&mut header, &stamp).unwrap();
+        (header, stamp)
+    }
+
+    #[test]
+    fn round_trips_next_to_nkits_stamp() {
+        let (header, stamp) = stamped();
+        assert_eq!(&header[STAMP_OFFSET..][..4], STAMP_MAGIC);
+        let found = stamps(&header);
+        assert_eq!(found.build, Ok(Some(stamp)));
+        assert!(found.nkit.is_some());
+
+        let unstamped = vec![0; GAME_HEADER_SIZE];
+        assert_eq!(read(&unstamped), Ok(None));
+        assert!(stamps(&unstamped).nkit.is_none());
+    }
+
+    #[test]
+    fn long_versions_are_cut_off() {
+        let stamp = BuildStamp { gcmod_version: "1.2.3-a-very-long-prerelease".to_owned(), ..BuildStamp::new([1; 20], [2; 20]) };
+        let mut header = vec![0; GAME_HEADER_SIZE];
+        write(&mut header, &stamp).unwrap();
+        let back = read(&header).unwrap().unwrap();
+        assert_eq!(back.gcmod_version, "1.2.3-a-very-lon");
+        assert_eq!(back.tree_hash, stamp.tree_hash);
+    }
+
+    #[test]
+    fn damaged_and_newer_stamps() {
+        let (header, _) = stamped();
+        let mut damaged = header.clone();
+        damaged[STAMP_OFFSET + 0x20] ^= 0xff;
+        assert!(matches!(read(&damaged), Err(StampError::Checksum { .. })));
+        let mut newer = header.clone();
+        newer[STAMP_OFFSET + 5] = 2;
+        assert_eq!(read(&newer), Err(StampError::UnknownVersion(2)));
+        let mut resized = header.clone();
+        resized[STAMP_OFFSET + 7] = 0x40;
+        assert_eq!(read(&resized), Err(StampError::WrongSize(0x40)));
+        assert_eq!(read(&header[..STAMP_OFFSET + 8]), Err(StampError::Truncated));
+    }
+
+    #[test]
+    fn clearing_and_writing_over() {
+        let (mut header, stamp) = stamped();
+        // A stamp is replaced
+        let newer = BuildStamp::new([3; 20], [4; 20]);
+        write(&mut header, &newer).unwrap();
+        assert_eq!(read(&header), Ok(Some(newer)));
+
+        assert!(clear(&mut header));
+        assert_eq!(read(&header), Ok(None));
+        assert!(header[stamp_range()].iter().all(|&b| b == 0));
+        assert!(!clear(&mut header));
+
+        // Anything else isn't
+        header[STAMP_OFFSET + 8] = 1;
+        assert_eq!(write(&mut header, &stamp), Err(StampError::RegionInUse));
+        assert_eq!(header[STAMP_OFFSET + 8], 1);
+        assert_eq!(write(&mut [0; 0x100], &stamp), Err(StampError::Truncated));
+    }
+
+    #[test]
+    fn options_hashes() {
+        let options = RebuildOptions::default();
+        assert_eq!(options_hash(&options), options_hash(&RebuildOptions::default()));
+        // Only layout options count
+        assert_eq!(options_hash(&options), options_hash(&RebuildOptions { jobs: 4, ..RebuildOptions::default() }));
+        assert_ne!(options_hash(&options), options_hash(&RebuildOptions { file_alignment: 4, ..RebuildOptions::default() }));
+    }
+}
@@ -0,0 +1,182 @@
+// Names on the ROM that only differ by case, like "Data" and "data", are one
+// file on a case-insensitive file system, like the ones macOS and Windows
+// use by default, so extracting both would leave one overwriting the other.
+// Extracting to one gives all but the first of them a new name instead, like
+// "data~1", which the manifest records so rebuilding puts the original back.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::MAIN_SEPARATOR,
+};
+
+use crate::sections::fst::{entry::Entry, FST};
+
+// Something extracted under a new name so it doesn't collide with another
+// entry, from `case_renames`.
+#[derive(Debug)]
+pub struct CaseRename {
+    pub index: usize,
+    // On the ROM, normalized
+    pub path: String,
+    pub name: String,
+    // The name in the same directory it would have collided with
+    pub collides_with: String,
+}
+
+// What two names are compared by. Lowering the upper case of each character
+// gets close to Unicode's full case folding, where "ß" and "SS" are the
+// same, without a table of its own.
+pub fn fold_case(name: &str) -> String {
+    name.chars().flat_map(char::to_uppercase).flat_map(char::to_lowercase).collect()
+}
+
+// The entries in `fst` to extract under a new name, given the names in
+// `names` that some of them already get instead of their own. In each
+// directory, the first entry with a name keeps it, and every later one that
+// folds to the same name gets the first "~1", "~2", and so on that isn't
+// taken, before a file's extension. It's the same every time for the same
+// FST.
+pub fn case_renames(fst: &FST, names: &BTreeMap<usize, String>, fold: impl Fn(&str) -> String) -> Vec<CaseRename> {
+    let name_of = |e: &Entry| {
+        let info = e.info();
+        names.get(&info.index).map_or(info.name.trim_end_matches(MAIN_SEPARATOR), String::as_str).to_owned()
+    };
+    // Every folded name in each directory, by the directory's index
+    let mut taken: HashMap<usize, HashSet<String>> = HashMap::new();
+    for e in &fst.entries()[1..] {
+        taken.entry(e.info().directory_index.unwrap_or(0)).or_default().insert(fold(&name_of(e)));
+    }
+
+    let mut first: HashMap<(usize, String), String> = HashMap::new();
+    let mut renames = Vec::new();
+    for e in &fst.entries()[1..] {
+        let dir = e.info().directory_index.unwrap_or(0);
+        let name = name_of(e);
+        let folded = fold(&name);
+        let Some(other) = first.get(&(dir, folded.clone())) else {
+            first.insert((dir, folded), name);
+            continue
+        };
+        let taken = taken.entry(dir).or_default();
+        let new_name = (1..)
+            .map(|n| numbered(&name, n, e.is_dir()))
+            .find(|n| !taken.contains(&fold(n)))
+            .unwrap();
+        taken.insert(fold(&new_name));
+        renames.push(CaseRename {
+            index: e.info().index,
+            path: e.info().normalized_path(),
+            name: new_name,
+            collides_with: other.clone(),
+        });
+    }
+    renames
+}
+
+// Like "data~1", or "file~1.bin" for a file, so it keeps its extension.
+fn numbered(name: &str, n: usize, is_dir: bool) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !is_dir && !stem.is_empty() => format!("{}~{}.{}", stem, n, extension),
+        _ => format!("{}~{}", name, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+
+    // Names that are all different on a case-sensitive file system
+    fn colliding_fst() -> FST {
+        let tree = synthetic_tree();
+        for dir in ["Data", "DATA", "Straße", "STRASSE"] {
+            fs::create_dir(tree.join(dir)).unwrap();
+            fs::write(tree.join(dir).join("x.bin"), dir).unwrap();
+        }
+        for file in ["README.TXT", "k", "K", "\u{212a}", "file~1.txt"] {
+            fs::write(tree.join(file), file).unwrap();
+        }
+        open_image(&build_image(tree.path())).fst
+    }
+
+    fn renames(fst: &FST, names: &BTreeMap<usize, String>, fold: impl Fn(&str) -> String) -> Vec<(String, String, String)> {
+        let mut renames: Vec<_> = case_renames(fst, names, fold).into_iter()
+            .map(|r| (r.path, r.name, r.collides_with))
+            .collect();
+        renames.sort();
+        renames
+    }
+
+    fn rename(path: &str, name: &str, collides_with: &str) -> (String, String, String) {
+        (path.to_owned(), name.to_owned(), collides_with.to_owned())
+    }
+
+    #[test]
+    fn folding() {
+        assert_eq!(fold_case("Data"), "data");
+        assert_eq!(fold_case("Straße"), fold_case("STRASSE"));
+        assert_eq!(fold_case("\u{212a}"), "k");
+        assert_eq!(fold_case("ΣΊΣΥΦΟΣ"), fold_case("σίσυφος"));
+        assert_ne!(fold_case("data~1"), fold_case("data"));
+    }
+
+    // In each directory, the first in the FST keeps its name, and the rest
+    // are numbered in order
+    #[test]
+    fn renaming_names_that_fold_the_same() {
+        let fst = colliding_fst();
+        let renamed = renames(&fst, &BTreeMap::new(), fold_case);
+        assert_eq!(renamed, [
+            rename("/DATA", "DATA~2", "data"),
+            rename("/Data", "Data~1", "data"),
+            // Not file~1.txt, which is taken
+            rename("/README.TXT", "README~1.TXT", "readme.txt"),
+            rename("/STRASSE", "STRASSE~1", "Straße"),
+            rename("/k", "k~1", "K"),
+            // The Kelvin sign
+            rename("/\u{212a}", "\u{212a}~2", "K"),
+        ]);
+        // What's in renamed directories keeps its name
+        assert!(renamed.iter().all(|(path, _, _)| !path.ends_with("x.bin")));
+        assert_eq!(renames(&fst, &BTreeMap::new(), fold_case), renamed);
+    }
+
+    // The comparison is whatever's passed in
+    #[test]
+    fn other_comparisons() {
+        let fst = colliding_fst();
+        assert!(renames(&fst, &BTreeMap::new(), str::to_owned).is_empty());
+        // ASCII only doesn't see ß and SS, or the Kelvin sign, as the same
+        let ascii = renames(&fst, &BTreeMap::new(), |n: &str| n.to_ascii_lowercase());
+        assert_eq!(ascii.len(), 2 + 1 + 1, "{:#?}", ascii);
+        assert!(ascii.iter().all(|(path, _, _)| !path.contains("STRASSE") && !path.contains('\u{212a}')));
+    }
+
+    // Names other renames already gave entries count instead of theirs
+    #[test]
+    fn names_already_given() {
+        let fst = colliding_fst();
+        let readme = fst.entry_for_path("/readme.txt").unwrap().info().index;
+        let upper = fst.entry_for_path("/README.TXT").unwrap().info().index;
+        let names = BTreeMap::from([(readme, "notes.txt".to_owned())]);
+        let renamed = renames(&fst, &names, fold_case);
+        assert!(renamed.iter().all(|(path, _, _)| !path.eq_ignore_ascii_case("/readme.txt")), "{:#?}", renamed);
+
+        let names = BTreeMap::from([(readme, "notes.txt".to_owned()), (upper, "NOTES.TXT".to_owned())]);
+        let renamed = renames(&fst, &names, fold_case);
+        let later = if readme < upper { "NOTES~1.TXT" } else { "notes~1.txt" };
+        assert!(renamed.iter().any(|(_, name, _)| name == later), "{:#?}", renamed);
+    }
+
+    #[test]
+    fn numbering() {
+        assert_eq!(numbered("data", 1, true), "data~1");
+        assert_eq!(numbered("v1.0", 2, true), "v1.0~2");
+        assert_eq!(numbered("file.bin", 1, false), "file~1.bin");
+        assert_eq!(numbered("archive.tar.gz", 3, false), "archive.tar~3.gz");
+        assert_eq!(numbered(".hidden", 1, false), ".hidden~1");
+        assert_eq!(numbered("noext", 1, false), "noext~1");
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build_info,
+    hash::{sha1_of, to_hex},
+    quick_id::quick_id,
+    rom_handle::RomReadHandle,
+    titles::{read_titles, Titles},
+    Game,
+    GameSummary,
+};
+
+// Written to the root of the directory that was scanned.
+pub const CATALOG_NAME: &str = ".gcmod-catalog.json";
+
+pub const IMAGE_EXTENSIONS: &[&str] = &["iso", "gcm"];
+// Compressed formats that can't be read yet, see `build_info::CONTAINER_FORMATS`.
+// They get cataloged too, with an error saying so, instead of being missing.
+pub const UNSUPPORTED_EXTENSIONS: &[&str] = &["gcz", "ciso"];
+
+// An index of the ROMs in a directory. Entries are keyed by path, size, and
+// modification time, so only images that changed need to be parsed again.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Catalog {
+    pub gcmod_version: String,
+    // Catalogs from before these were recorded don't have them
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gcmod_git_hash: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gcmod_features: Vec<String>,
+    pub entries: Vec<CatalogEntry>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CatalogEntry {
+    // Relative to the scanned directory, with forward slashes.
+    pub path: String,
+    pub size: u64,
+    // In nanoseconds since the Unix epoch.
+    pub mtime: u64,
+    // Exactly one of these is set. Images that failed to parse are kept, so
+    // they aren't tried again until they change.
+    pub summary: Option<GameSummary>,
+    pub error: Option<String>,
+    // See `quick_id`. Images that don't parse don't have one.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub quick_id: Option<String>,
+    // The SHA-1 of the whole image, in hex, only with `catalog --full`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub sha1: Option<String>,
+    // Every title, see `Game::titles`. Images that don't parse don't have them.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub titles: Option<Titles>,
+}
+
+// What happened to the images in a directory when updating its catalog.
+#[derive(Debug, Default)]
+pub struct CatalogUpdate {
+    pub parsed: usize,
+    pub cached: usize,
+    pub failed: usize,
+    pub removed: usize,
+    // Images whose hashes were worked out this time
+    pub hashed: usize,
+}
+
+impl Catalog {
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Catalog> {
+        let file = BufReader::new(File::open(path)?);
+        serde_json::from_reader(file).map_err(io::Error::from)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self).map_err(io::Error::from)
+    }
+
+    // Scans `dir` for images, only parsing the ones that aren't already in
+    // the catalog unchanged, or all of them if `refresh` is set. Images that
+    // are no longer there are dropped. Every image that parses gets a
+    // quick-id, and with `full`, every image gets a SHA-1, which means
+    // reading all of it.
+    pub fn update(&mut self, dir: impl AsRef<Path>, refresh: bool, full: bool) -> io::Result<CatalogUpdate> {
+        let dir = dir.as_ref();
+        let mut paths = Vec::new();
+        find_images(dir, dir, &mut paths)?;
+        paths.sort();
+
+        let mut old: HashMap<String, CatalogEntry> = self.entries.drain(..)
+            .map(|e| (e.path.clone(), e))
+            .collect();
+        let mut update = CatalogUpdate::default();
+
+        for path in paths {
+            let metadata = fs::metadata(dir.join(&path))?;
+            let size = metadata.len();
+            let mtime = metadata.modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+
+            let mut entry = match old.remove(&path) {
+                Some(e) if !refresh && e.size == size && e.mtime == mtime => {
+                    update.cached += 1;
+                    e
+                },
+                _ => {
+                    update.parsed += 1;
+                    let (summary, error) = match summarize(dir.join(&path)) {
+                        Ok(s) => (Some(s), None),
+                        Err(e) => {
+                            update.failed += 1;
+                            (None, Some(e.to_string()))
+                        },
+                    };
+                    CatalogEntry { path, size, mtime, summary, error, quick_id: None, sha1: None, titles: None }
+                },
+            };
+            // Entries from before hashes and titles were recorded get them now
+            let image = dir.join(&entry.path);
+            if entry.summary.is_some() && entry.titles.is_none() {
+                entry.titles = RomReadHandle::open(&image).ok()
+                    .and_then(|iso| read_titles(BufReader::new(iso), 0).ok());
+            }
+            let needs_quick_id = entry.summary.is_some() && entry.quick_id.is_none();
+            let needs_sha1 = full && entry.sha1.is_none();
+            if needs_quick_id {
+                entry.quick_id = quick_id(&image).ok().map(|(id, _)| id);
+            }
+            if needs_sha1 {
+                entry.sha1 = Some(to_hex(&sha1_of(RomReadHandle::open(&image)?)?));
+            }
+            if needs_quick_id || needs_sha1 {
+                update.hashed += 1;
+            }
+            self.entries.push(entry);
+        }
+
+        update.removed = old.len();
+        self.gcmod_version = build_info::VERSION.to_owned();
+        self.gcmod_git_hash = build_info::GIT_HASH.to_owned();
+        self.gcmod_features = build_info::features();
+        Ok(update)
+    }
+
+    // The images with this quick-id or SHA-1, which are the same dump as the
+    // image it's from, or almost certainly are for a quick-id.
+    pub fn matching<'a>(&'a self, hash: &'a str) -> impl Iterator<Item = &'a CatalogEntry> {
+        self.entries.iter().filter(move |e| {
+            [&e.quick_id, &e.sha1].into_iter().flatten().any(|h| h.eq_ignore_ascii_case(hash))
+        })
+    }
+
+    // The images whose ID or any of whose titles contains `query`, ignoring
+    // case.
+    pub fn find<'a>(&'a self, query: &str) -> impl Iterator<Item = &'a CatalogEntry> {
+        let query = query.to_lowercase();
+        self.entries.iter().filter(move |e| {
+            e.summary.as_ref().is_some_and(|s| {
+                s.id.to_lowercase().contains(&query)
+                    || s.title.to_lowercase().contains(&query)
+                    || e.titles.iter().flat_map(Titles::texts).any(|t| t.to_lowercase().contains(&query))
+            })
+        })
+    }
+}
+
+fn summarize(path: impl AsRef<Path>) -> io::Result<GameSummary> {
+    if let Some(extension) = unsupported_extension(path.as_ref()) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} images aren't supported yet", extension.to_lowercase()),
+        ));
+    }
+    let iso = BufReader::new(RomReadHandle::open(&path)?);
+    match Game::open_summary(iso, 0) {
+        // The fast path doesn't repair malformed FSTs, which a full parse
+        // might be able to
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            let iso = BufReader::new(RomReadHandle::open(path)?);
+            Game::open(iso, 0).map(|game| game.summary())
+        },
+        result => result,
+    }
+}
+
+// Adds the path of every image under `dir` to `paths`, relative to `root`.
+fn find_images(root: &Path, dir: &Path, paths: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_images(root, &path, paths)?;
+        } else if is_image(&path) {
+            let relative = path.strip_prefix(root).unwrap();
+            let parts: Vec<_> = relative.iter().map(|p| p.to_string_lossy()).collect();
+            paths.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+}
+
+fn is_image(path: &Path) -> bool {
+    has_extension(path, IMAGE_EXTENSIONS) || has_extension(path, UNSUPPORTED_EXTENSIONS)
+}
+
+fn unsupported_extension(path: &Path) -> Option<&str> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .filter(|_| has_extension(path, UNSUPPORTED_EXTENSIONS))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::{
+        synthetic,
+        test_util::{build_image, synthetic_tree, TestDir},
+    };
+
+    fn counts(update: &CatalogUpdate) -> (usize, usize, usize, usize) {
+        (update.parsed, update.cached, update.failed, update.removed)
+    }
+
+    fn touch(path: &Path) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+    }
+
+    fn library() -> TestDir {
+        let dir = TestDir::new();
+        let image = build_image(synthetic_tree().path());
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.iso"), &image).unwrap();
+        fs::write(dir.join("sub/b.GCM"), &image).unwrap();
+        fs::write(dir.join("broken.iso"), b"not an image").unwrap();
+        fs::write(dir.join("notes.txt"), b"not looked at").unwrap();
+        dir
+    }
+
+    #[test]
+    fn only_changed_images_are_parsed_again() {
+        let dir = library();
+        let mut catalog = Catalog::default();
+        assert_eq!(counts(&catalog.update(dir.path(), false, false).unwrap()), (3, 0, 1, 0));
+        let paths: Vec<&str> = catalog.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a.iso", "broken.iso", "sub/b.GCM"]);
+
+        // Failures are remembered too
+        assert_eq!(counts(&catalog.update(dir.path(), false, false).unwrap()), (0, 3, 0, 0));
+        touch(&dir.join("a.iso"));
+        assert_eq!(counts(&catalog.update(dir.path(), false, false).unwrap()), (1, 2, 0, 0));
+        assert_eq!(counts(&catalog.update(dir.path(), true, false).unwrap()), (3, 0, 1, 0));
+
+        fs::remove_file(dir.join("sub/b.GCM")).unwrap();
+        assert_eq!(counts(&catalog.update(dir.path(), false, false).unwrap()), (0, 2, 0, 1));
+        assert_eq!(catalog.entries.len(), 2);
+    }
+
+    #[test]
+    fn entries_have_a_summary_or_an_error() {
+        let dir = library();
+        let mut catalog = Catalog::default();
+        catalog.update(dir.path(), false, false).unwrap();
+        for entry in &catalog.entries {
+            assert!(entry.summary.is_some() != entry.error.is_some(), "{}", entry.path);
+            assert_eq!(entry.quick_id.is_some(), entry.summary.is_some(), "{}", entry.path);
+            assert!(entry.sha1.is_none());
+        }
+        let broken = catalog.entries.iter().find(|e| e.path == "broken.iso").unwrap();
+        assert!(broken.titles.is_none());
+    }
+
+    #[test]
+    fn compressed_images_are_marked_unsupported() {
+        let dir = TestDir::new();
+        fs::write(dir.join("a.gcz"), build_image(synthetic_tree().path())).unwrap();
+        fs::write(dir.join("b.CISO"), b"CISO").unwrap();
+        let mut catalog = Catalog::default();
+        assert_eq!(counts(&catalog.update(dir.path(), false, false).unwrap()), (2, 0, 2, 0));
+        let errors: Vec<_> = catalog.entries.iter().map(|e| (e.path.as_str(), e.error.as_deref())).collect();
+        assert_eq!(errors, [
+            ("a.gcz", Some("gcz images aren't supported yet")),
+            ("b.CISO", Some("ciso images aren't supported yet")),
+        ]);
+    }
+
+    #[test]
+    fn catalogs_read_back() {
+        let dir = library();
+        let mut catalog = Catalog::default();
+        catalog.update(dir.path(), false, true).unwrap();
+        let path = dir.join(CATALOG_NAME);
+        catalog.write(&path).unwrap();
+
+        let mut read = Catalog::read(&path).unwrap();
+        assert_eq!(read.gcmod_version, build_info::VERSION);
+        assert_eq!(read.entries.len(), 3);
+        let sha1 = read.entries[0].sha1.clone().unwrap();
+        assert_eq!(read.matching(&sha1.to_uppercase()).count(), 2);
+        assert_eq!(counts(&read.update(dir.path(), false, false).unwrap()), (0, 3, 0, 0));
+    }
+
+    #[test]
+    fn finding_by_id_or_title() {
+        let dir = library();
+        let mut catalog = Catalog::default();
+        catalog.update(dir.path(), false, false).unwrap();
+        let found = |query: &str| catalog.find(query).map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(found(&synthetic::GAME_CODE.to_lowercase()), ["a.iso", "sub/b.GCM"]);
+        assert_eq!(found("SELF-TEST"), ["a.iso", "sub/b.GCM"]);
+        assert!(found("not an image").is_empty());
+    }
+}
@@ -0,0 +1,364 @@
+// How two images lay out the files they both have, like a retail image and
+// one rebuilt from it, for seeing whether the rebuild kept files that are
+// read together close together. Files are matched by path, the way `diff`
+// does.
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{sections::fst::entry::FileEntry, Game};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FilePlacement {
+    pub path: String,
+    pub offset_a: u64,
+    pub offset_b: u64,
+    // `offset_b - offset_a`
+    pub delta: i64,
+    pub size_a: usize,
+    pub size_b: usize,
+    // The file before it on A, of the ones both have, isn't the one before
+    // it on B. Files that moved as a block only count at the ends.
+    pub order_changed: bool,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LayoutStats {
+    pub common_files: usize,
+    // Files at a different offset on B
+    pub moved: usize,
+    pub order_changed: usize,
+    pub mean_abs_delta: f64,
+    // Of the files' offsets on A and B, with ties accounted for (tau-b).
+    // 1 is the same order, -1 is the reverse. None with fewer than two
+    // files, or when every file is at the same offset in one of them.
+    pub kendall_tau: Option<f64>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LayoutComparison {
+    // In A's order
+    pub files: Vec<FilePlacement>,
+    pub only_in_a: Vec<String>,
+    pub only_in_b: Vec<String>,
+    pub stats: LayoutStats,
+}
+
+impl LayoutComparison {
+    pub fn new(a: &Game, b: &Game) -> LayoutComparison {
+        let files_a = files_by_path(a);
+        let files_b = files_by_path(b);
+        let only_in_a = files_a.keys().filter(|p| !files_b.contains_key(*p)).cloned().collect();
+        let only_in_b = files_b.keys().filter(|p| !files_a.contains_key(*p)).cloned().collect();
+
+        let mut common: Vec<(&String, &FileEntry, &FileEntry)> = files_a.iter()
+            .filter_map(|(path, fa)| files_b.get(path).map(|fb| (path, *fa, *fb)))
+            .collect();
+        common.sort_by_key(|&(path, fa, _)| (fa.file_offset, path));
+
+        // Where each file is in B's order
+        let mut order_b: Vec<usize> = (0..common.len()).collect();
+        order_b.sort_by_key(|&i| (common[i].2.file_offset, common[i].0));
+        let mut rank_b = vec![0; common.len()];
+        for (rank, &i) in order_b.iter().enumerate() {
+            rank_b[i] = rank;
+        }
+
+        let files: Vec<FilePlacement> = common.iter().enumerate()
+            .map(|(i, &(path, fa, fb))| FilePlacement {
+                path: path.clone(),
+                offset_a: fa.file_offset,
+                offset_b: fb.file_offset,
+                delta: fb.file_offset as i64 - fa.file_offset as i64,
+                size_a: fa.size,
+                size_b: fb.size,
+                order_changed: match (i.checked_sub(1), rank_b[i].checked_sub(1)) {
+                    (Some(before_a), Some(before_b)) => order_b[before_b] != before_a,
+                    (None, None) => false,
+                    _ => true,
+                },
+            })
+            .collect();
+
+        let pairs: Vec<(u64, u64)> = files.iter().map(|f| (f.offset_a, f.offset_b)).collect();
+        let stats = LayoutStats {
+            common_files: files.len(),
+            moved: files.iter().filter(|f| f.delta != 0).count(),
+            order_changed: files.iter().filter(|f| f.order_changed).count(),
+            mean_abs_delta: match files.len() {
+                0 => 0.0,
+                n => files.iter().map(|f| f.delta.unsigned_abs() as f64).sum::<f64>() / n as f64,
+            },
+            kendall_tau: kendall_tau(pairs),
+        };
+        LayoutComparison { files, only_in_a, only_in_b, stats }
+    }
+
+    // A table of every file both have, with the offsets in aligned hex
+    // columns, then the files only one has and the totals.
+    pub fn to_text(&self) -> String {
+        let hex = |o: u64| format!("{:#010x}", o);
+        let delta = |d: i64| match d {
+            0 => "0".to_owned(),
+            d if d < 0 => format!("-{:#x}", d.unsigned_abs()),
+            d => format!("+{:#x}", d),
+        };
+        let offset_width = self.files.iter()
+            .flat_map(|f| [hex(f.offset_a).len(), hex(f.offset_b).len()])
+            .chain(["Offset in A".len()])
+            .max()
+            .unwrap_or(0);
+        let delta_width = self.files.iter().map(|f| delta(f.delta).len()).chain(["Delta".len()]).max().unwrap_or(0);
+
+        let mut s = String::new();
+        if !self.files.is_empty() {
+            s += &format!(
+                "{:<ow$}  {:<ow$}  {:>dw$}  {:<9}  Path\n",
+                "Offset in A", "Offset in B", "Delta", "Order",
+                ow = offset_width, dw = delta_width,
+            );
+            for f in &self.files {
+                s += &format!(
+                    "{:<ow$}  {:<ow$}  {:>dw$}  {:<9}  {}\n",
+                    hex(f.offset_a), hex(f.offset_b), delta(f.delta),
+                    if f.order_changed { "reordered" } else { "" }, f.path,
+                    ow = offset_width, dw = delta_width,
+                );
+            }
+        }
+        for (name, paths) in [("A", &self.only_in_a), ("B", &self.only_in_b)] {
+            if !paths.is_empty() {
+                s += &format!("\nOnly in {} ({}):\n", name, paths.len());
+                for p in paths {
+                    s += &format!("  {}\n", p);
+                }
+            }
+        }
+
+        let stats = &self.stats;
+        s += &format!(
+            "\n{} files in both: {} moved, {} reordered, mean |delta| {:.0} bytes, Kendall tau {}\n",
+            stats.common_files, stats.moved, stats.order_changed, stats.mean_abs_delta,
+            stats.kendall_tau.map_or("n/a".to_owned(), |t| format!("{:.4}", t)),
+        );
+        s
+    }
+}
+
+// The first file with each path, like `FST::entry_for_path` finds.
+fn files_by_path(game: &Game) -> BTreeMap<String, &FileEntry> {
+    let mut files = BTreeMap::new();
+    for f in game.fst.entries().iter().filter_map(|e| e.as_file()) {
+        files.entry(f.info.normalized_path()).or_insert(f);
+    }
+    files
+}
+
+// Kendall's tau-b, worked out with Knight's algorithm, since counting every
+// pair of files would take too long for games with tens of thousands: sort
+// by the first value, then count the swaps a merge sort by the second makes.
+fn kendall_tau(mut pairs: Vec<(u64, u64)>) -> Option<f64> {
+    let n = pairs.len() as u64;
+    if n < 2 {
+        return None;
+    }
+    pairs.sort_unstable();
+    // Pairs of pairs tied on the first value, and on both
+    let tied_a = tied_pairs(pairs.iter().map(|p| p.0));
+    let tied_both = tied_pairs(pairs.iter().copied());
+
+    let mut b: Vec<u64> = pairs.iter().map(|p| p.1).collect();
+    let swaps = count_swaps(&mut b);
+    let tied_b = tied_pairs(b.iter().copied());
+
+    let total = n * (n - 1) / 2;
+    let denominator = ((total - tied_a) as f64 * (total - tied_b) as f64).sqrt();
+    if denominator == 0.0 {
+        return None;
+    }
+    let numerator = total as f64 - tied_a as f64 - tied_b as f64 + tied_both as f64 - 2.0 * swaps as f64;
+    Some(numerator / denominator)
+}
+
+// How many pairs have the same value, in values that are already sorted.
+fn tied_pairs<T: PartialEq>(values: impl Iterator<Item = T>) -> u64 {
+    let mut total = 0;
+    let mut run = 0u64;
+    let mut last = None;
+    for v in values {
+        if last.as_ref() == Some(&v) {
+            run += 1;
+        } else {
+            total += run * (run + 1) / 2;
+            run = 0;
+            last = Some(v);
+        }
+    }
+    total + run * (run + 1) / 2
+}
+
+// Sorts `values`, and returns how many pairs were out of order.
+fn count_swaps(values: &mut [u64]) -> u64 {
+    if values.len() < 2 {
+        return 0;
+    }
+    let mid = values.len() / 2;
+    let mut swaps = count_swaps(&mut values[..mid]) + count_swaps(&mut values[mid..]);
+    let mut merged = Vec::with_capacity(values.len());
+    let (mut i, mut j) = (0, mid);
+    while i < mid && j < values.len() {
+        if values[j] < values[i] {
+            // Everything left on the left is bigger
+            swaps += (mid - i) as u64;
+            merged.push(values[j]);
+            j += 1;
+        } else {
+            merged.push(values[i]);
+            i += 1;
+        }
+    }
+    merged.extend_from_slice(&values[i..mid]);
+    merged.extend_from_slice(&values[j..]);
+    values.copy_from_slice(&merged);
+    swaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rom_rebuilder::{RebuildOptions, ROMRebuilder},
+        report::Reporter,
+        synthetic,
+        test_util::{build_image, open_image, synthetic_tree},
+    };
+
+    // Every pair of pairs, counted one by one
+    fn brute_force_tau(pairs: &[(u64, u64)]) -> Option<f64> {
+        let (mut concordant, mut discordant, mut tied_a, mut tied_b) = (0i64, 0i64, 0i64, 0i64);
+        for (i, a) in pairs.iter().enumerate() {
+            for b in &pairs[i + 1..] {
+                match (a.0.cmp(&b.0), a.1.cmp(&b.1)) {
+                    (std::cmp::Ordering::Equal, std::cmp::Ordering::Equal) => {},
+                    (std::cmp::Ordering::Equal, _) => tied_a += 1,
+                    (_, std::cmp::Ordering::Equal) => tied_b += 1,
+                    (x, y) if x == y => concordant += 1,
+                    _ => discordant += 1,
+                }
+            }
+        }
+        let denominator = (((concordant + discordant + tied_a) * (concordant + discordant + tied_b)) as f64).sqrt();
+        (denominator != 0.0).then(|| (concordant - discordant) as f64 / denominator)
+    }
+
+    fn assert_tau(pairs: &[(u64, u64)], expected: Option<f64>) {
+        let tau = kendall_tau(pairs.to_vec());
+        let brute_force = brute_force_tau(pairs);
+        match (tau, brute_force, expected) {
+            (Some(t), Some(b), Some(e)) => {
+                assert!((t - b).abs() < 1e-9, "{:?}: {} vs {}", pairs, t, b);
+                assert!((t - e).abs() < 1e-4, "{:?}: {} vs {}", pairs, t, e);
+            },
+            (None, None, None) => {},
+            other => panic!("{:?}: {:?}", pairs, other),
+        }
+    }
+
+    #[test]
+    fn kendall_tau_of_orders() {
+        assert_tau(&[(1, 10), (2, 20), (3, 30), (4, 40)], Some(1.0));
+        assert_tau(&[(1, 40), (2, 30), (3, 20), (4, 10)], Some(-1.0));
+        // One swap of six pairs
+        assert_tau(&[(1, 10), (2, 30), (3, 20), (4, 40)], Some(2.0 / 3.0));
+        // Ties on either side, and both
+        assert_tau(&[(1, 10), (1, 20), (2, 20), (3, 5), (3, 5)], Some(-0.625));
+        assert_tau(&[(1, 1), (2, 1), (3, 2)], Some(0.8165));
+        // Nothing to order
+        assert_tau(&[], None);
+        assert_tau(&[(1, 1)], None);
+        assert_tau(&[(1, 5), (2, 5), (3, 5)], None);
+    }
+
+    #[test]
+    fn kendall_tau_matches_counting_every_pair() {
+        // A fixed shuffle with plenty of ties
+        let mut state = 12345u64;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % 50
+        };
+        for len in [2, 3, 10, 101, 300] {
+            let pairs: Vec<(u64, u64)> = (0..len).map(|_| (next(), next())).collect();
+            assert_tau(&pairs, brute_force_tau(&pairs));
+        }
+    }
+
+    #[test]
+    fn counting_swaps_and_ties() {
+        let mut values = [3, 1, 2, 5, 4];
+        assert_eq!(count_swaps(&mut values), 3);
+        assert_eq!(values, [1, 2, 3, 4, 5]);
+        assert_eq!(count_swaps(&mut [1, 1, 1]), 0);
+        assert_eq!(tied_pairs([1, 1, 2, 3, 3, 3].into_iter()), 1 + 3);
+        assert_eq!(tied_pairs([1, 2, 3].into_iter()), 0);
+    }
+
+    fn rebuilt(options: RebuildOptions) -> Game {
+        let options = RebuildOptions { capacity: synthetic::CAPACITY, ..options };
+        let mut image = Vec::new();
+        ROMRebuilder::rebuild(synthetic_tree().path(), &options, &mut image, &Reporter::quiet()).unwrap();
+        open_image(&image)
+    }
+
+    #[test]
+    fn the_same_image() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let comparison = LayoutComparison::new(&game, &game);
+        let stats = &comparison.stats;
+        assert_eq!((stats.moved, stats.order_changed, stats.mean_abs_delta), (0, 0, 0.0));
+        assert_eq!(stats.common_files, synthetic::FILES.len());
+        assert!(comparison.only_in_a.is_empty() && comparison.only_in_b.is_empty());
+    }
+
+    // Packed tighter, right after the DOL, every file moves back, and none
+    // of them change order
+    #[test]
+    fn different_alignments() {
+        let a = open_image(&build_image(synthetic_tree().path()));
+        let b = rebuilt(RebuildOptions { file_alignment: 4, ..RebuildOptions::default() });
+        let comparison = LayoutComparison::new(&a, &b);
+        let moved: Vec<(&str, i64)> = comparison.files.iter()
+            .filter(|f| f.size_a != 0)
+            .map(|f| (f.path.as_str(), f.delta))
+            .collect();
+        assert_eq!(moved, [
+            ("/readme.txt", 0x3800 - 0x8000),
+            ("/data/levels/2.bin", 0x3840 - 0x10000),
+            ("/data/levels/1.bin", 0x4840 - 0x18000),
+            ("/data/big.bin", 0x4c28 - 0x20000),
+        ]);
+        assert!(comparison.files.iter().all(|f| !f.order_changed), "{:?}", comparison.files);
+        assert_eq!(comparison.stats.kendall_tau, Some(1.0));
+        // And /empty.bin, which goes wherever the next file would
+        assert_eq!(comparison.stats.moved, 5);
+    }
+
+    #[test]
+    fn files_in_only_one() {
+        let a = open_image(&build_image(synthetic_tree().path()));
+        let tree = synthetic_tree();
+        std::fs::rename(tree.path().join("readme.txt"), tree.path().join("README.md")).unwrap();
+        let b = open_image(&build_image(tree.path()));
+        let comparison = LayoutComparison::new(&a, &b);
+        assert_eq!(comparison.only_in_a, ["/readme.txt"]);
+        assert_eq!(comparison.only_in_b, ["/README.md"]);
+        assert_eq!(comparison.stats.common_files, synthetic::FILES.len() - 1);
+        let text = comparison.to_text();
+        assert!(text.contains("\nOnly in A (1):\n  /readme.txt\n\nOnly in B (1):\n  /README.md\n"), "{}", text);
+    }
+}
@@ -0,0 +1,366 @@
+// Defaults for command-line options, from TOML files, for people who pass
+// the same flags every time. Top-level keys are global options, and each
+// table is a command's long options, without the dashes:
+//
+//     color = "always"
+//
+//     [rebuild]
+//     alignment = 4
+//     jobs = 8
+//
+// A file later in `Config::load`'s list replaces the values from the ones
+// before it, and options on the command line beat all of them. Only options
+// that make sense to always pass are here, so not paths or one-off modes.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_ENV: &str = "GCMOD_CONFIG";
+pub const LOCAL_CONFIG_NAME: &str = ".gcmod.toml";
+
+// Every key is the long option it's the default for, so a table turns back
+// into arguments without knowing what's in it, see `Config::args`.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct Defaults {
+    pub quiet: Option<bool>,
+    pub no_progress: Option<bool>,
+    pub color: Option<String>,
+    pub extract: Option<ExtractDefaults>,
+    pub info: Option<HexDefaults>,
+    pub ls: Option<LsDefaults>,
+    pub rebuild: Option<RebuildDefaults>,
+    pub diff: Option<DiffDefaults>,
+    pub verify: Option<VerifyDefaults>,
+    pub scrub: Option<ScrubDefaults>,
+    pub unscrub: Option<ScrubDefaults>,
+    pub analyze_refs: Option<HexDefaults>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct ExtractDefaults {
+    pub keep_going: Option<bool>,
+    pub strict: Option<bool>,
+    pub force: Option<bool>,
+    pub dedup: Option<String>,
+    pub xattrs: Option<bool>,
+    pub infer_extensions: Option<bool>,
+    pub fail_on_truncated: Option<bool>,
+    pub acknowledge_nkit: Option<bool>,
+    pub no_fs_hints: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct HexDefaults {
+    pub hex: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct LsDefaults {
+    pub long: Option<bool>,
+    pub large: Option<u64>,
+    pub fake_mtime: Option<String>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct RebuildDefaults {
+    pub preserve_indices: Option<bool>,
+    pub keep_user_fields: Option<bool>,
+    pub capacity: Option<Capacity>,
+    pub ignore_boot_limits: Option<bool>,
+    pub ignore_debug_monitor: Option<bool>,
+    #[cfg_attr(feature = "serde", serde(alias = "alignment"))]
+    pub file_alignment: Option<u64>,
+    pub system_alignment: Option<u64>,
+    // true, or "full"
+    pub verify_after: Option<FlagOrValue>,
+    pub allow_mixed: Option<bool>,
+    pub jobs: Option<u64>,
+    pub report_format: Option<String>,
+    pub sync: Option<String>,
+    pub acknowledge_nkit: Option<bool>,
+    pub no_sparse_gaps: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct DiffDefaults {
+    pub indices: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct VerifyDefaults {
+    pub strict_fst: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, rename_all = "kebab-case"))]
+pub struct ScrubDefaults {
+    pub force: Option<bool>,
+}
+
+// Like `--capacity`, a number of bytes or a name like "nr"
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum Capacity {
+    Bytes(u64),
+    Named(String),
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum FlagOrValue {
+    Flag(bool),
+    Value(String),
+}
+
+// Where a file came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    // ~/.config/gcmod/config.toml
+    User,
+    // The file `GCMOD_CONFIG` names, which replaces the user's
+    Env,
+    // The closest .gcmod.toml in the current directory or one of its parents
+    Local,
+}
+
+impl Layer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::User => "user",
+            Layer::Env => CONFIG_ENV,
+            Layer::Local => "local",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config file {}: {}", self.path.display(), self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+// The defaults from every file, merged.
+#[derive(Debug, Default)]
+pub struct Config {
+    // The files that were read, lowest precedence first
+    pub layers: Vec<(Layer, PathBuf)>,
+    pub defaults: Defaults,
+    // Like `defaults`, with aliases replaced by the options' names
+    table: toml::Table,
+    // Which file each value is from, by key, like "rebuild.jobs"
+    sources: BTreeMap<String, PathBuf>,
+}
+
+impl Config {
+    // Reads the user's (or `GCMOD_CONFIG`'s) file, then the local one. Files
+    // that don't exist are skipped, except one `GCMOD_CONFIG` names. Errors
+    // are `ConfigError`s.
+    pub fn load() -> io::Result<Config> {
+        let mut config = Config::default();
+        match env::var_os(CONFIG_ENV).filter(|p| !p.is_empty()) {
+            Some(path) => config.add_file(Layer::Env, Path::new(&path))?,
+            None => if let Some(path) = user_config_path().filter(|p| p.is_file()) {
+                config.add_file(Layer::User, &path)?;
+            },
+        }
+        if let Some(path) = local_config_path() {
+            config.add_file(Layer::Local, &path)?;
+        }
+        Ok(config)
+    }
+
+    // Adds the defaults in `path` over the ones so far.
+    pub fn add_file(&mut self, layer: Layer, path: &Path) -> io::Result<()> {
+        let error = |message: String| io::Error::new(
+            io::ErrorKind::InvalidData,
+            ConfigError { path: path.to_owned(), message },
+        );
+        let text = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        // Parsing the text itself, rather than a table, puts the line and
+        // the key in the error
+        let defaults: Defaults = toml::from_str(&text).map_err(|e| error(e.to_string().trim_end().to_owned()))?;
+        let table = toml::Table::try_from(&defaults).map_err(|e| error(e.to_string()))?;
+
+        for (key, value) in table {
+            // The schema decides which keys are tables, so they're the same
+            // in every file
+            if let toml::Value::Table(options) = value {
+                let merged = self.table.entry(key.clone())
+                    .or_insert_with(|| toml::Table::new().into())
+                    .as_table_mut()
+                    .unwrap();
+                for (option, value) in options {
+                    self.sources.insert(format!("{}.{}", key, option), path.to_owned());
+                    merged.insert(option, value);
+                }
+            } else {
+                self.sources.insert(key.clone(), path.to_owned());
+                self.table.insert(key, value);
+            }
+        }
+        self.defaults = self.table.clone().try_into().map_err(|e: toml::de::Error| error(e.to_string()))?;
+        self.layers.push((layer, path.to_owned()));
+        Ok(())
+    }
+
+    // The arguments for the global defaults with `command` as `None`, or
+    // for that command's. Flags set to false aren't passed at all.
+    pub fn args(&self, command: Option<&str>) -> Vec<String> {
+        self.values(command)
+            .filter_map(|(key, value)| match value {
+                toml::Value::Boolean(true) => Some(format!("--{}", key)),
+                toml::Value::Boolean(false) => None,
+                toml::Value::String(s) => Some(format!("--{}={}", key, s)),
+                other => Some(format!("--{}={}", key, other)),
+            })
+            .collect()
+    }
+
+    // The values for the global options with `command` as `None`, or for
+    // that command's.
+    pub fn values<'a>(&'a self, command: Option<&'a str>) -> impl Iterator<Item = (&'a str, &'a toml::Value)> {
+        let table = match command {
+            None => Some(&self.table),
+            Some(command) => self.table.get(command).and_then(toml::Value::as_table),
+        };
+        table.into_iter()
+            .flatten()
+            .filter(move |(_, value)| command.is_some() || !value.is_table())
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    pub fn source(&self, command: Option<&str>, key: &str) -> Option<&Path> {
+        let key = match command {
+            Some(command) => format!("{}.{}", command, key),
+            None => key.to_owned(),
+        };
+        self.sources.get(&key).map(PathBuf::as_path)
+    }
+
+    // The commands with defaults, in order.
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.table.iter().filter(|(_, v)| v.is_table()).map(|(k, _)| k.as_str())
+    }
+}
+
+// $XDG_CONFIG_HOME/gcmod/config.toml, or ~/.config/gcmod/config.toml.
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").filter(|p| !p.is_empty()).map(|h| Path::new(&h).join(".config")))?;
+    Some(config_home.join("gcmod").join("config.toml"))
+}
+
+pub fn local_config_path() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(LOCAL_CONFIG_NAME))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    fn config(files: &[(Layer, &str)]) -> (TestDir, io::Result<Config>) {
+        let dir = TestDir::new();
+        let mut config = Config::default();
+        for (i, (layer, text)) in files.iter().enumerate() {
+            let path = dir.join(format!("{}.toml", i));
+            fs::write(&path, text).unwrap();
+            if let Err(e) = config.add_file(*layer, &path) {
+                return (dir, Err(e));
+            }
+        }
+        (dir, Ok(config))
+    }
+
+    fn config_error(e: io::Error) -> String {
+        let e = e.into_inner().unwrap();
+        let e = e.downcast::<ConfigError>().unwrap();
+        assert!(e.path.ends_with("0.toml") || e.path.ends_with("1.toml"), "{}", e.path.display());
+        e.message
+    }
+
+    #[test]
+    fn later_files_win() {
+        let (dir, config) = config(&[
+            (Layer::User, "color = \"always\"\nquiet = true\n[rebuild]\nalignment = 4\njobs = 8\n"),
+            (Layer::Local, "quiet = false\n[rebuild]\njobs = 2\nverify-after = \"full\"\n[ls]\nlong = true\n"),
+        ]);
+        let config = config.unwrap();
+        assert_eq!(config.layers.iter().map(|(l, _)| *l).collect::<Vec<_>>(), [Layer::User, Layer::Local]);
+        // Flags set to false are left off, and aliases are the options' names
+        assert_eq!(config.args(None), ["--color=always"]);
+        assert_eq!(config.args(Some("rebuild")), ["--file-alignment=4", "--jobs=2", "--verify-after=full"]);
+        assert_eq!(config.args(Some("ls")), ["--long"]);
+        assert!(config.args(Some("extract")).is_empty());
+        assert_eq!(config.defaults.rebuild.as_ref().unwrap().jobs, Some(2));
+
+        assert_eq!(config.source(None, "color"), Some(dir.join("0.toml").as_path()));
+        assert_eq!(config.source(None, "quiet"), Some(dir.join("1.toml").as_path()));
+        assert_eq!(config.source(Some("rebuild"), "file-alignment"), Some(dir.join("0.toml").as_path()));
+        assert_eq!(config.source(Some("rebuild"), "jobs"), Some(dir.join("1.toml").as_path()));
+        assert_eq!(config.commands().collect::<Vec<_>>(), ["ls", "rebuild"]);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let (_dir, result) = config(&[(Layer::User, "[rebuild]\njobs = 2\noutput = \"x.iso\"\n")]);
+        let message = config_error(result.unwrap_err());
+        assert!(message.contains("unknown field `output`") && message.contains("line 3"), "{}", message);
+
+        let (_dir, result) = config(&[(Layer::User, "[rebuld]\njobs = 2\n")]);
+        assert!(config_error(result.unwrap_err()).contains("unknown field `rebuld`"));
+    }
+
+    #[test]
+    fn values_of_the_wrong_type() {
+        let (_dir, result) = config(&[(Layer::User, "[rebuild]\njobs = \"eight\"\n")]);
+        let message = config_error(result.unwrap_err());
+        assert!(message.contains("jobs") && message.contains("line 2"), "{}", message);
+
+        // In the second file, after the first was fine
+        let (_dir, result) = config(&[(Layer::User, "quiet = true\n"), (Layer::Local, "quiet = 1\n")]);
+        let e = result.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("Invalid config file ") && e.to_string().contains("1.toml"), "{}", e);
+    }
+}
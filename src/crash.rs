@@ -0,0 +1,160 @@
+// What happens when gcmod panics. Instead of only the panic's message, it
+// writes a crash report to the temporary directory to attach to an issue,
+// with what was running and where it panicked, and exits with
+// `CRASH_EXIT_CODE`.
+//
+// The report only has what was typed: the arguments as they were given, but
+// not ones from config files, with the home directory as "~". It never has
+// anything from a ROM, so it doesn't have the panic's message either, which
+// can have file names or bytes from one in it, only where it panicked and
+// what type the payload was. The message is only printed.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    io,
+    iter,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    process,
+    sync::OnceLock,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{build_info, report, timestamp::utc_date, workspace};
+
+// EX_SOFTWARE, from sysexits.h
+pub const CRASH_EXIT_CODE: i32 = 70;
+// Set to make gcmod panic once it's parsed its arguments, for testing the
+// crash report
+pub const INDUCE_PANIC_VAR: &str = "GCMOD_INDUCE_PANIC";
+
+static COMMAND: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct CrashReport {
+    pub version: String,
+    pub git_hash: String,
+    pub platform: String,
+    // Empty if the panic was before the arguments were parsed
+    pub command: String,
+    pub args: Vec<String>,
+    pub thread: String,
+    pub location: Option<String>,
+    // Like "&str" or "String", see `payload_type`
+    pub payload: &'static str,
+    pub timestamp: u64,
+}
+
+impl CrashReport {
+    pub fn new(info: &PanicHookInfo, args: &[String]) -> CrashReport {
+        CrashReport {
+            version: build_info::VERSION.to_owned(),
+            git_hash: build_info::GIT_HASH.to_owned(),
+            platform: format!("{} {}", env::consts::OS, env::consts::ARCH),
+            command: COMMAND.get().cloned().unwrap_or_default(),
+            // The first is wherever the binary is
+            args: iter::once("gcmod".to_owned()).chain(args.iter().skip(1).map(|a| sanitize(a))).collect(),
+            thread: thread::current().name().unwrap_or("unnamed").to_owned(),
+            location: info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+            payload: payload_type(info),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "gcmod crash report");
+        let _ = writeln!(s);
+        let _ = writeln!(s, "Version:    {} ({})", self.version, self.git_hash);
+        let _ = writeln!(s, "Platform:   {}", self.platform);
+        let _ = writeln!(s, "Date:       {}", utc_date(self.timestamp as i64));
+        let _ = writeln!(s, "Command:    {}", self.command);
+        let _ = writeln!(s, "Arguments:  {}", self.args.join(" "));
+        let _ = writeln!(s, "Thread:     {}", self.thread);
+        let _ = writeln!(s, "Location:   {}", self.location.as_deref().unwrap_or("unknown"));
+        let _ = writeln!(s, "Payload:    {}", self.payload);
+        s
+    }
+
+    // Into the work directory, under a name no other report has.
+    pub fn write(&self) -> io::Result<PathBuf> {
+        let name = format!("{}crash-{}-{}.txt", workspace::PREFIX, self.timestamp, process::id());
+        let path = workspace::created_work_dir()?.join(name);
+        fs::write(&path, self.to_text())?;
+        Ok(path)
+    }
+}
+
+// Replaces the default panic hook. `args` are the arguments as typed.
+pub fn install(args: Vec<String>) {
+    panic::set_hook(Box::new(move |info| {
+        // Like `gcmod ls | head`, which isn't a crash
+        if is_broken_stdout(info) {
+            process::exit(1);
+        }
+        let report = CrashReport::new(info, &args);
+        let message = sanitize(&message(info));
+        let told = match report.write() {
+            Ok(path) => format!("A crash report was written to {}. Please attach it when filing an issue.", path.display()),
+            Err(e) => format!(
+                "The crash report couldn't be written ({}). Please include this when filing an issue:\n\n{}",
+                e, report.to_text(),
+            ),
+        };
+        // Everything on stderr is an event then
+        if report::json_events() {
+            report::finish_events(Some(format!("gcmod crashed: {}. {}", message, told)));
+        } else {
+            eprintln!("gcmod crashed: {}\n{}", message, told);
+        }
+        process::exit(CRASH_EXIT_CODE);
+    }));
+}
+
+// The subcommand, for the report.
+pub fn set_command(command: &str) {
+    let _ = COMMAND.set(command.to_owned());
+}
+
+// Panics if `INDUCE_PANIC_VAR` is set.
+#[track_caller]
+pub fn induce_panic_if_asked() {
+    if env::var_os(INDUCE_PANIC_VAR).is_some() {
+        panic!("Panicking because {} is set", INDUCE_PANIC_VAR);
+    }
+}
+
+// What `panic!` was given. Anything but a message is "(no message)".
+fn message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "(no message)".to_owned())
+}
+
+fn payload_type(info: &PanicHookInfo) -> &'static str {
+    let payload = info.payload();
+    if payload.is::<&str>() {
+        "&str"
+    } else if payload.is::<String>() {
+        "String"
+    } else {
+        "other"
+    }
+}
+
+fn is_broken_stdout(info: &PanicHookInfo) -> bool {
+    info.payload().downcast_ref::<String>()
+        .is_some_and(|m| m.starts_with("failed printing to stdout") && m.contains("Broken pipe"))
+}
+
+fn sanitize(s: &str) -> String {
+    let home = env::var("HOME").or_else(|_| env::var("USERPROFILE")).ok().filter(|h| h.len() > 1);
+    match home {
+        Some(home) => s.replace(home.trim_end_matches(['/', '\\']), "~"),
+        None => s.to_owned(),
+    }
+}
@@ -0,0 +1,667 @@
+// Checks for the problems with people's setups that come up the most, for
+// `doctor`: a destination that can't hold the output, or that's case
+// insensitive when the ROM has names that only differ by case, no room or
+// no permission, and inputs that are broken links or aren't ROMs at all.
+// Each check is a function of what's being checked and a `Probe`, which is
+// everything the checks ask the system, so they don't need a real one.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    case_names::fold_case,
+    format_bytes,
+    io_util::{self, DestinationError},
+    paths::SystemPaths,
+    rom_handle::RomReadHandle,
+    sections::header::Header,
+    Game,
+    ROM_SIZE,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Operation {
+    // From a ROM to a directory
+    Extract,
+    // From a directory to a ROM
+    Rebuild,
+}
+
+impl Operation {
+    pub fn parse(s: &str) -> Option<Operation> {
+        match s {
+            "extract" => Some(Operation::Extract),
+            "rebuild" => Some(Operation::Rebuild),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Status {
+    Pass,
+    // Worth knowing about, but it won't stop anything from working
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct CheckResult {
+    pub check: &'static str,
+    pub status: Status,
+    pub message: String,
+    // What to do about it, for warnings and failures
+    pub advice: Option<String>,
+}
+
+impl CheckResult {
+    fn pass(check: &'static str, message: String) -> CheckResult {
+        CheckResult { check, status: Status::Pass, message, advice: None }
+    }
+
+    fn warn(check: &'static str, message: String, advice: Option<&str>) -> CheckResult {
+        CheckResult { check, status: Status::Warn, message, advice: advice.map(str::to_owned) }
+    }
+
+    fn fail(check: &'static str, message: String, advice: Option<&str>) -> CheckResult {
+        CheckResult { check, status: Status::Fail, message, advice: advice.map(str::to_owned) }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileSystemInfo {
+    pub name: String,
+    // `None` if it's big enough for anything gcmod writes
+    pub max_file_size: Option<u64>,
+}
+
+pub trait Probe {
+    // `None` where the platform doesn't say.
+    fn file_system(&self, dir: &Path) -> Option<FileSystemInfo>;
+    fn case_sensitive(&self, dir: &Path) -> io::Result<bool>;
+    fn available_space(&self, dir: &Path) -> Option<u64>;
+    fn writable(&self, dir: &Path) -> io::Result<()>;
+}
+
+// The real file systems.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemProbe;
+
+impl Probe for SystemProbe {
+    fn file_system(&self, dir: &Path) -> Option<FileSystemInfo> {
+        file_system(dir)
+    }
+
+    fn case_sensitive(&self, dir: &Path) -> io::Result<bool> {
+        io_util::is_case_sensitive(dir)
+    }
+
+    fn available_space(&self, dir: &Path) -> Option<u64> {
+        io_util::available_space(dir)
+    }
+
+    fn writable(&self, dir: &Path) -> io::Result<()> {
+        io_util::check_writable(dir).map_err(|e| match e {
+            DestinationError::NotWritable { source, .. } => source,
+            e => io::Error::other(e.to_string()),
+        })
+    }
+}
+
+// From `statfs`'s magic numbers, for the ones people are likely to have.
+#[cfg(target_os = "linux")]
+fn file_system(dir: &Path) -> Option<FileSystemInfo> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stat` is only read if the call succeeds.
+    let stat = unsafe {
+        if libc::statfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    let (name, max_file_size) = match stat.f_type as u32 {
+        0x4d44 => ("FAT", Some(u32::MAX as u64)),
+        0x2011_bab0 => ("exFAT", None),
+        0x5346_544e => ("NTFS", None),
+        0x6573_5546 => ("FUSE", None),
+        0xef53 => ("ext4", None),
+        0x9123_683e => ("Btrfs", None),
+        0x5846_5342 => ("XFS", None),
+        0x2fc1_2fc1 => ("ZFS", None),
+        0x0102_1994 => ("tmpfs", None),
+        0x794c_7630 => ("overlayfs", None),
+        0x6969 => ("NFS", None),
+        0xfe53_4d42 | 0xff53_4d42 | 0x517b => ("SMB", None),
+        0x9660 => ("ISO 9660", None),
+        other => return Some(FileSystemInfo { name: format!("unknown ({:#x})", other), max_file_size: None }),
+    };
+    Some(FileSystemInfo { name: name.to_owned(), max_file_size })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn file_system(_dir: &Path) -> Option<FileSystemInfo> {
+    None
+}
+
+#[derive(Debug)]
+pub struct Input {
+    pub path: PathBuf,
+    // Why it couldn't be read, if it couldn't
+    pub contents: Result<InputContents, String>,
+}
+
+#[derive(Debug)]
+pub enum InputContents {
+    Rom(Box<Game>),
+    // An extracted ROM, with the header from its system data
+    Tree(Box<Header>),
+}
+
+impl Input {
+    pub fn open(path: impl AsRef<Path>) -> Input {
+        let path = path.as_ref();
+        let contents = if path.is_dir() {
+            let header_path = SystemPaths::for_tree(path).header();
+            File::open(path.join(&header_path))
+                .map_err(|e| format!("doesn't have {} ({})", header_path, e))
+                .and_then(|f| Header::new(BufReader::new(f), 0).map_err(|e| format!("has an invalid header ({})", e)))
+                .map(|h| InputContents::Tree(Box::new(h)))
+        } else {
+            RomReadHandle::open(path)
+                .map_err(|e| format!("couldn't be opened ({})", e))
+                .and_then(|f| Game::open(BufReader::new(f), 0).map_err(|e| format!("isn't a valid ROM ({})", e)))
+                .map(|g| InputContents::Rom(Box::new(g)))
+        };
+        Input { path: path.to_owned(), contents }
+    }
+
+    fn game(&self) -> Option<&Game> {
+        match &self.contents {
+            Ok(InputContents::Rom(game)) => Some(game),
+            _ => None,
+        }
+    }
+}
+
+// What's being checked: the inputs, and with an operation, where its output
+// goes. Without one, only the inputs are checked.
+#[derive(Debug)]
+pub struct Context {
+    pub operation: Option<Operation>,
+    pub inputs: Vec<Input>,
+    // The directory to extract to, or the ROM to rebuild, if one was given.
+    // The current directory stands in for it otherwise.
+    pub destination: Option<PathBuf>,
+}
+
+impl Context {
+    // With an operation, `paths` are its arguments in order, so the input
+    // and then the destination.
+    pub fn new(operation: Option<Operation>, paths: &[PathBuf]) -> Context {
+        let (inputs, destination) = match operation {
+            Some(_) => (paths.get(..1).unwrap_or(&[]), paths.get(1).cloned()),
+            None => (paths, None),
+        };
+        Context { operation, inputs: inputs.iter().map(Input::open).collect(), destination }
+    }
+
+    // The closest directory to the destination that exists, which is where
+    // everything gets written.
+    fn destination_dir(&self) -> Option<PathBuf> {
+        let operation = self.operation?;
+        let destination = match &self.destination {
+            Some(path) => path,
+            None => return Some(PathBuf::from(".")),
+        };
+        match (operation, destination.is_dir()) {
+            (Operation::Extract, true) => return Some(destination.clone()),
+            (Operation::Extract, false) if destination.exists() => return None,
+            (Operation::Rebuild, true) => return None,
+            _ => {},
+        }
+        let parent = io_util::parent_dir(destination);
+        Some(parent.to_owned()).filter(|p| p.is_dir())
+    }
+
+    // How much gets written, and the biggest single file of it, if that's
+    // known. Rebuilding writes the whole capacity, which is `ROM_SIZE` unless
+    // it's given.
+    fn output_size(&self) -> Option<(u64, u64)> {
+        match self.operation? {
+            Operation::Extract => {
+                let game = self.inputs.first()?.game()?;
+                let largest = game.fst.entries().iter()
+                    .filter_map(|e| e.as_file())
+                    .map(|f| f.size as u64)
+                    .max()
+                    .unwrap_or(0);
+                Some((game.extracted_size(), largest))
+            },
+            Operation::Rebuild => Some((ROM_SIZE as u64, ROM_SIZE as u64)),
+        }
+    }
+}
+
+pub type Check = fn(&Context, &dyn Probe) -> Vec<CheckResult>;
+
+// In the order they're printed.
+pub const CHECKS: &[Check] = &[
+    check_inputs,
+    check_destination,
+    check_file_system,
+    check_case_sensitivity,
+    check_free_space,
+];
+
+pub fn run(context: &Context, probe: &dyn Probe) -> Vec<CheckResult> {
+    CHECKS.iter().flat_map(|check| check(context, probe)).collect()
+}
+
+// That each input exists, isn't a broken link, can be read, and parses at
+// least as far as the header.
+pub fn check_inputs(context: &Context, _probe: &dyn Probe) -> Vec<CheckResult> {
+    const NAME: &str = "input";
+    let mut results = Vec::new();
+    if context.inputs.is_empty() && context.operation.is_some() {
+        results.push(CheckResult::warn(
+            NAME,
+            "No input was given, so it wasn't checked".to_owned(),
+            Some("Pass the ROM or tree to check it too"),
+        ));
+    }
+    for input in &context.inputs {
+        let path = &input.path;
+        if let Ok(target) = fs::read_link(path) {
+            if !path.exists() {
+                results.push(CheckResult::fail(
+                    NAME,
+                    format!("{} is a link to {}, which doesn't exist", path.display(), target.display()),
+                    Some("Pass the file itself, or fix the link"),
+                ));
+                continue;
+            }
+        }
+        if let Err(e) = fs::metadata(path) {
+            results.push(CheckResult::fail(NAME, format!("{} can't be read ({})", path.display(), e), None));
+            continue;
+        }
+        let is_shortcut = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("lnk"));
+        let result = match (&input.contents, context.operation) {
+            (Ok(InputContents::Tree(_)), Some(Operation::Extract)) => CheckResult::fail(
+                NAME,
+                format!("{} is an extracted ROM, and extracting needs a ROM", path.display()),
+                None,
+            ),
+            (Ok(InputContents::Rom(_)), Some(Operation::Rebuild)) => CheckResult::fail(
+                NAME,
+                format!("{} is a ROM, and rebuilding needs an extracted one", path.display()),
+                None,
+            ),
+            (Ok(InputContents::Rom(game)), _) => CheckResult::pass(
+                NAME,
+                format!("{} is a ROM of {} ({})", path.display(), game.header.title, game.header.game_code),
+            ),
+            (Ok(InputContents::Tree(header)), _) => CheckResult::pass(
+                NAME,
+                format!("{} is an extracted ROM of {} ({})", path.display(), header.title, header.game_code),
+            ),
+            (Err(_), _) if is_shortcut => CheckResult::fail(
+                NAME,
+                format!("{} is a Windows shortcut, not a ROM", path.display()),
+                Some("Pass the file the shortcut points to"),
+            ),
+            (Err(e), _) => CheckResult::fail(NAME, format!("{} {}", path.display(), e), None),
+        };
+        results.push(result);
+    }
+    results
+}
+
+// That the destination can be made, and written to.
+pub fn check_destination(context: &Context, probe: &dyn Probe) -> Vec<CheckResult> {
+    const NAME: &str = "destination";
+    let operation = match context.operation {
+        Some(operation) => operation,
+        None => return Vec::new(),
+    };
+    if let Some(destination) = &context.destination {
+        if operation == Operation::Rebuild && destination.is_dir() {
+            return vec![CheckResult::fail(
+                NAME,
+                format!("{} is a directory, and rebuilding makes a file", destination.display()),
+                Some("Pass the path of the ROM to make"),
+            )];
+        }
+        // Rebuilding replaces whatever's there
+        let force = operation == Operation::Rebuild;
+        match io_util::check_destination_dir(destination, force) {
+            Err(e @ DestinationError::NotEmpty(_)) => return vec![CheckResult::warn(NAME, e.to_string(), None)],
+            Err(DestinationError::IsFile(_)) if force => {},
+            Err(e) => return vec![CheckResult::fail(NAME, e.to_string(), None)],
+            Ok(_) => {},
+        }
+    }
+
+    let dir = match context.destination_dir() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    vec![match probe.writable(&dir) {
+        Ok(()) => CheckResult::pass(NAME, format!("{} can be written to", dir.display())),
+        Err(e) => CheckResult::fail(
+            NAME,
+            format!("{} can't be written to ({})", dir.display(), e),
+            Some("Pick a destination you own, or fix its permissions"),
+        ),
+    }]
+}
+
+// That the destination's file system can hold the biggest file written.
+pub fn check_file_system(context: &Context, probe: &dyn Probe) -> Vec<CheckResult> {
+    const NAME: &str = "file-system";
+    let dir = match context.destination_dir() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    let info = match probe.file_system(&dir) {
+        Some(info) => info,
+        None => return vec![CheckResult::pass(NAME, format!("Couldn't tell what file system {} is on", dir.display()))],
+    };
+    let largest = context.output_size().map(|(_, largest)| largest);
+    vec![match (info.max_file_size, largest) {
+        (Some(max), Some(largest)) if largest > max => CheckResult::fail(
+            NAME,
+            format!(
+                "{} is on {}, which can't hold files over {}, and the output has one of {}",
+                dir.display(), info.name, format_bytes(max), format_bytes(largest),
+            ),
+            Some("Use a destination on a file system like exFAT or NTFS instead"),
+        ),
+        (Some(max), _) => CheckResult::pass(
+            NAME,
+            format!("{} is on {}, which holds files up to {}", dir.display(), info.name, format_bytes(max)),
+        ),
+        (None, _) => CheckResult::pass(NAME, format!("{} is on {}", dir.display(), info.name)),
+    }]
+}
+
+// That the ROM's names won't collide when extracted to a file system that
+// ignores case, which gives some of them new names, see `case_names`.
+pub fn check_case_sensitivity(context: &Context, probe: &dyn Probe) -> Vec<CheckResult> {
+    const NAME: &str = "case";
+    const ADVICE: &str = "To keep their names, extract to a case-sensitive file system, like a case-sensitive APFS volume on macOS, \
+        or a directory with `fsutil file setCaseSensitiveInfo` turned on for it on Windows";
+    if context.operation != Some(Operation::Extract) {
+        return Vec::new();
+    }
+    let dir = match context.destination_dir() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    match probe.case_sensitive(&dir) {
+        Ok(true) => return vec![CheckResult::pass(NAME, format!("{} is case-sensitive", dir.display()))],
+        Ok(false) => {},
+        Err(e) => return vec![CheckResult::warn(
+            NAME,
+            format!("Couldn't tell whether {} is case-sensitive ({})", dir.display(), e),
+            None,
+        )],
+    }
+
+    let game = match context.inputs.first().and_then(Input::game) {
+        Some(game) => game,
+        None => return vec![CheckResult::warn(
+            NAME,
+            format!("{} is case-insensitive, and the ROM's names couldn't be checked", dir.display()),
+            None,
+        )],
+    };
+    let collisions = case_collisions(game);
+    vec![match collisions.first() {
+        None => CheckResult::pass(
+            NAME,
+            format!("{} is case-insensitive, but none of the ROM's names only differ by case", dir.display()),
+        ),
+        Some((a, b)) => CheckResult::warn(
+            NAME,
+            format!(
+                "{} is case-insensitive, and {} of the ROM's names only differ by case from another, like {} and {}, \
+                so they'll be extracted under new names like data~1",
+                dir.display(), collisions.len(), a, b,
+            ),
+            Some(ADVICE),
+        ),
+    }]
+}
+
+// Pairs of paths in the same directory that are the same ignoring case.
+fn case_collisions(game: &Game) -> Vec<(String, String)> {
+    let mut seen: HashMap<(usize, String), String> = HashMap::new();
+    let mut collisions = Vec::new();
+    for e in &game.fst.entries()[1..] {
+        let info = e.info();
+        let name = fold_case(info.name.trim_end_matches(std::path::MAIN_SEPARATOR));
+        let key = (info.directory_index.unwrap_or(0), name);
+        match seen.get(&key) {
+            Some(other) => collisions.push((other.clone(), info.normalized_path())),
+            None => { seen.insert(key, info.normalized_path()); },
+        }
+    }
+    collisions
+}
+
+// That there's room for everything written.
+pub fn check_free_space(context: &Context, probe: &dyn Probe) -> Vec<CheckResult> {
+    const NAME: &str = "free-space";
+    let dir = match context.destination_dir() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    let available = match probe.available_space(&dir) {
+        Some(available) => available,
+        None => return vec![CheckResult::pass(NAME, format!("Couldn't tell how much space {} has", dir.display()))],
+    };
+    vec![match context.output_size() {
+        Some((needed, _)) if available < needed => CheckResult::fail(
+            NAME,
+            format!(
+                "{} has {} free, and {} is needed",
+                dir.display(), format_bytes(available), format_bytes(needed),
+            ),
+            Some("Free up some space, or pick a destination on another drive"),
+        ),
+        Some((needed, _)) => CheckResult::pass(
+            NAME,
+            format!("{} has {} free, and {} is needed", dir.display(), format_bytes(available), format_bytes(needed)),
+        ),
+        None => CheckResult::pass(NAME, format!("{} has {} free", dir.display(), format_bytes(available))),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_image, synthetic_tree, TestDir};
+
+    // Answers with whatever it's given, instead of asking the system
+    struct FakeProbe {
+        file_system: Option<FileSystemInfo>,
+        case_sensitive: Option<bool>,
+        available_space: Option<u64>,
+        writable: bool,
+    }
+
+    impl Default for FakeProbe {
+        fn default() -> FakeProbe {
+            FakeProbe { file_system: None, case_sensitive: Some(true), available_space: None, writable: true }
+        }
+    }
+
+    impl Probe for FakeProbe {
+        fn file_system(&self, _dir: &Path) -> Option<FileSystemInfo> {
+            self.file_system.clone()
+        }
+
+        fn case_sensitive(&self, _dir: &Path) -> io::Result<bool> {
+            self.case_sensitive.ok_or_else(|| io::Error::other("no probe"))
+        }
+
+        fn available_space(&self, _dir: &Path) -> Option<u64> {
+            self.available_space
+        }
+
+        fn writable(&self, _dir: &Path) -> io::Result<()> {
+            match self.writable {
+                true => Ok(()),
+                false => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+            }
+        }
+    }
+
+    // The synthetic ROM in a new directory, with `extra` files added to it
+    fn rom_dir(extra: &[&str]) -> TestDir {
+        let tree = synthetic_tree();
+        for name in extra {
+            fs::write(tree.join(name), b"extra").unwrap();
+        }
+        let dir = TestDir::new();
+        fs::write(dir.join("game.iso"), build_image(tree.path())).unwrap();
+        dir
+    }
+
+    fn extracting(dir: &TestDir) -> Context {
+        Context::new(Some(Operation::Extract), &[dir.join("game.iso"), dir.join("out")])
+    }
+
+    fn statuses(results: &[CheckResult]) -> Vec<(&'static str, Status)> {
+        results.iter().map(|r| (r.check, r.status)).collect()
+    }
+
+    #[test]
+    fn everything_passes() {
+        let dir = rom_dir(&[]);
+        let probe = FakeProbe {
+            file_system: Some(FileSystemInfo { name: "ext4".to_owned(), max_file_size: None }),
+            available_space: Some(1 << 40),
+            ..FakeProbe::default()
+        };
+        let results = run(&extracting(&dir), &probe);
+        assert_eq!(statuses(&results), [
+            ("input", Status::Pass),
+            ("destination", Status::Pass),
+            ("file-system", Status::Pass),
+            ("case", Status::Pass),
+            ("free-space", Status::Pass),
+        ]);
+        assert!(results[0].message.ends_with("is a ROM of gcmod self-test (GSLF)"), "{}", results[0].message);
+    }
+
+    #[test]
+    fn file_systems_with_a_size_limit() {
+        let dir = rom_dir(&[]);
+        let fat = |max_file_size| FakeProbe {
+            file_system: Some(FileSystemInfo { name: "FAT".to_owned(), max_file_size: Some(max_file_size) }),
+            ..FakeProbe::default()
+        };
+        // /data/big.bin is just over 1 MiB
+        let results = check_file_system(&extracting(&dir), &fat(1 << 20));
+        assert_eq!(statuses(&results), [("file-system", Status::Fail)]);
+        assert!(results[0].message.contains("the output has one of 1.0 MiB"), "{}", results[0].message);
+        assert_eq!(statuses(&check_file_system(&extracting(&dir), &fat(2 << 20))), [("file-system", Status::Pass)]);
+
+        // Rebuilding writes a whole retail size ROM
+        let context = Context::new(Some(Operation::Rebuild), &[dir.join("tree"), dir.join("out.iso")]);
+        let results = check_file_system(&context, &fat(ROM_SIZE as u64 - 1));
+        assert_eq!(statuses(&results), [("file-system", Status::Fail)]);
+        assert_eq!(statuses(&check_file_system(&context, &fat(u32::MAX as u64))), [("file-system", Status::Pass)]);
+    }
+
+    #[test]
+    fn case_insensitive_destinations() {
+        let insensitive = FakeProbe { case_sensitive: Some(false), ..FakeProbe::default() };
+        let dir = rom_dir(&[]);
+        let results = check_case_sensitivity(&extracting(&dir), &insensitive);
+        assert_eq!(statuses(&results), [("case", Status::Pass)]);
+
+        let dir = rom_dir(&["data/Big.bin"]);
+        let results = check_case_sensitivity(&extracting(&dir), &insensitive);
+        assert_eq!(statuses(&results), [("case", Status::Warn)]);
+        assert!(results[0].message.contains("1 of the ROM's names"), "{}", results[0].message);
+        assert!(results[0].advice.is_some());
+        // Only matters to extracting
+        assert_eq!(statuses(&check_case_sensitivity(&extracting(&dir), &FakeProbe::default())), [("case", Status::Pass)]);
+        let rebuilding = Context::new(Some(Operation::Rebuild), &[dir.join("tree"), dir.join("out.iso")]);
+        assert!(check_case_sensitivity(&rebuilding, &insensitive).is_empty());
+
+        let unknown = FakeProbe { case_sensitive: None, ..FakeProbe::default() };
+        assert_eq!(statuses(&check_case_sensitivity(&extracting(&dir), &unknown)), [("case", Status::Warn)]);
+    }
+
+    #[test]
+    fn free_space_and_permissions() {
+        let dir = rom_dir(&[]);
+        let context = extracting(&dir);
+        let needed = context.output_size().unwrap().0;
+        let space = |available_space| FakeProbe { available_space, ..FakeProbe::default() };
+        assert_eq!(statuses(&check_free_space(&context, &space(Some(needed - 1)))), [("free-space", Status::Fail)]);
+        assert_eq!(statuses(&check_free_space(&context, &space(Some(needed)))), [("free-space", Status::Pass)]);
+        assert_eq!(statuses(&check_free_space(&context, &space(None))), [("free-space", Status::Pass)]);
+
+        let read_only = FakeProbe { writable: false, ..FakeProbe::default() };
+        let results = check_destination(&context, &read_only);
+        assert_eq!(statuses(&results), [("destination", Status::Fail)]);
+        assert!(results[0].advice.is_some());
+
+        fs::write(dir.join("out"), b"").unwrap();
+        let results = check_destination(&extracting(&dir), &FakeProbe::default());
+        assert_eq!(statuses(&results), [("destination", Status::Fail)]);
+        let rebuilding = Context::new(Some(Operation::Rebuild), &[dir.join("tree"), dir.path().to_owned()]);
+        assert_eq!(statuses(&check_destination(&rebuilding, &FakeProbe::default())), [("destination", Status::Fail)]);
+    }
+
+    #[test]
+    fn inputs_that_cant_be_used() {
+        let dir = rom_dir(&[]);
+        fs::write(dir.join("game.lnk"), b"not a ROM").unwrap();
+        fs::write(dir.join("garbage.iso"), b"not a ROM").unwrap();
+        let paths = [dir.join("game.lnk"), dir.join("garbage.iso"), dir.join("missing.iso")];
+        let results = check_inputs(&Context::new(None, &paths), &FakeProbe::default());
+        assert_eq!(statuses(&results), [("input", Status::Fail); 3]);
+        assert!(results[0].message.contains("is a Windows shortcut"), "{}", results[0].message);
+        assert!(results[1].message.contains("isn't a valid ROM"), "{}", results[1].message);
+        assert!(results[2].message.contains("can't be read"), "{}", results[2].message);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("gone.iso"), dir.join("link.iso")).unwrap();
+            let results = check_inputs(&Context::new(None, &[dir.join("link.iso")]), &FakeProbe::default());
+            assert_eq!(statuses(&results), [("input", Status::Fail)]);
+            assert!(results[0].message.contains("which doesn't exist"), "{}", results[0].message);
+        }
+
+        // The wrong kind of input for the operation
+        let rebuilding = Context::new(Some(Operation::Rebuild), &[dir.join("game.iso")]);
+        assert_eq!(statuses(&check_inputs(&rebuilding, &FakeProbe::default())), [("input", Status::Fail)]);
+        let no_input = Context::new(Some(Operation::Extract), &[]);
+        assert_eq!(statuses(&check_inputs(&no_input, &FakeProbe::default())), [("input", Status::Warn)]);
+    }
+}
@@ -0,0 +1,256 @@
+// The order a game reads its files in, so a rebuild can put the ones read
+// first at the start of the files, next to each other. Profiles usually come
+// from Dolphin's file monitor, which logs the path of each file the game
+// reads when the "FileMon" log type is on, at the Info level or lower.
+//
+// The lines look like
+//   12:34:567 Core/HW/DVD/FileMonitor.cpp:76 I[FileMon]: /audio/bgm.dsp
+// though older versions left out the source location, or wrote it with
+// backslashes on Windows. Anything else in the log is skipped.
+
+use std::collections::{hash_map, HashMap};
+#[cfg(feature = "serde")]
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{case_names::fold_case, sections::fst::FST};
+
+pub const PROFILE_VERSION: u32 = 1;
+
+// What Dolphin calls the file monitor's log type in its lines
+const LOG_TAG: &str = "[filemon]:";
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProfileFile {
+    // Normalized, like "/audio/bgm.dsp"
+    pub path: String,
+    // How many times the log has it being read
+    #[cfg_attr(feature = "serde", serde(default = "one"))]
+    pub accesses: u64,
+}
+
+#[cfg(feature = "serde")]
+fn one() -> u64 {
+    1
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileOrderProfile {
+    pub version: u32,
+    // In the order they were first read
+    pub files: Vec<ProfileFile>,
+}
+
+// What `--file-order` takes: a profile, or a plain list of paths in order
+#[cfg(feature = "serde")]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+enum OrderFile {
+    Profile(FileOrderProfile),
+    Paths(Vec<String>),
+}
+
+// A profile read from a log, see `FileOrderProfile::from_log`.
+#[derive(Debug)]
+pub struct LogImport {
+    pub profile: FileOrderProfile,
+    // The file monitor's lines for paths that aren't on the ROM, as (line
+    // number, path), with lines starting at 1
+    pub unmatched: Vec<(usize, String)>,
+    // How many of the log's lines were the file monitor's
+    pub accesses: usize,
+}
+
+// The path a file monitor line says was read, if it's one of those.
+pub fn parse_log_line(line: &str) -> Option<&str> {
+    // The tag is ASCII, so lowering the case doesn't move anything
+    let at = line.to_ascii_lowercase().find(LOG_TAG)?;
+    let path = line[at + LOG_TAG.len()..].trim();
+    (!path.is_empty()).then_some(path)
+}
+
+// Like "/audio/bgm.dsp", with forward slashes, one at the start, and none at
+// the end.
+pub fn normalize_path(path: &str) -> String {
+    let parts: Vec<&str> = path.split(['/', '\\']).filter(|p| !p.is_empty()).collect();
+    format!("/{}", parts.join("/"))
+}
+
+impl FileOrderProfile {
+    // Reads a profile, or a JSON list of paths, which is taken as a profile
+    // with each file read once.
+    #[cfg(feature = "serde")]
+    pub fn read(path: impl AsRef<Path>) -> io::Result<FileOrderProfile> {
+        let file = BufReader::new(File::open(path)?);
+        let profile = match serde_json::from_reader(file).map_err(io::Error::from)? {
+            OrderFile::Profile(profile) => profile,
+            OrderFile::Paths(paths) => FileOrderProfile {
+                version: PROFILE_VERSION,
+                files: paths.into_iter().map(|path| ProfileFile { path, accesses: 1 }).collect(),
+            },
+        };
+        if profile.version > PROFILE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The profile is version {}, but this gcmod only knows up to {}", profile.version, PROFILE_VERSION),
+            ));
+        }
+        Ok(profile)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self).map_err(io::Error::from)
+    }
+
+    // Each file in the order it was first read. With `fst`, paths are
+    // matched to the ones on the ROM ignoring case, and get the ROM's case,
+    // and the ones that aren't on it are left out.
+    pub fn from_log(log: &str, fst: Option<&FST>) -> LogImport {
+        let on_rom: Option<HashMap<String, String>> = fst.map(|fst| {
+            fst.entries().iter()
+                .filter_map(|e| e.as_file())
+                .map(|f| {
+                    let path = f.info.normalized_path();
+                    (fold_case(&path), path)
+                })
+                .collect()
+        });
+        let mut files: Vec<ProfileFile> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut unmatched = Vec::new();
+        let mut accesses = 0;
+        for (i, line) in log.lines().enumerate() {
+            let Some(path) = parse_log_line(line) else {
+                continue
+            };
+            accesses += 1;
+            let path = normalize_path(path);
+            let key = fold_case(&path);
+            let path = match &on_rom {
+                Some(on_rom) => match on_rom.get(&key) {
+                    Some(path) => path.clone(),
+                    None => {
+                        unmatched.push((i + 1, path));
+                        continue
+                    },
+                },
+                None => path,
+            };
+            match positions.entry(key) {
+                hash_map::Entry::Occupied(at) => files[*at.get()].accesses += 1,
+                hash_map::Entry::Vacant(at) => {
+                    at.insert(files.len());
+                    files.push(ProfileFile { path, accesses: 1 });
+                },
+            }
+        }
+        LogImport {
+            profile: FileOrderProfile { version: PROFILE_VERSION, files },
+            unmatched,
+            accesses,
+        }
+    }
+
+    // Where each file is in the profile, by its path with the case folded,
+    // see `rank_key`. Files that are in it more than once are where they
+    // were first.
+    pub fn ranks(&self) -> HashMap<String, usize> {
+        let mut ranks = HashMap::new();
+        for (i, file) in self.files.iter().enumerate() {
+            ranks.entry(rank_key(&file.path)).or_insert(i);
+        }
+        ranks
+    }
+}
+
+// What paths are looked up in `FileOrderProfile::ranks` by.
+pub fn rank_key(path: &str) -> String {
+    fold_case(&normalize_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serde")]
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+    #[cfg(feature = "serde")]
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn log_lines() {
+        assert_eq!(parse_log_line("01:02:345 Core/HW/DVD/FileMonitor.cpp:76 I[FileMon]: /audio/bgm.dsp\r"), Some("/audio/bgm.dsp"));
+        assert_eq!(parse_log_line("01:02:345 Core\\HW\\DVD\\FileMonitor.cpp:76 I[FileMon]: AUDIO\\bgm.dsp"), Some("AUDIO\\bgm.dsp"));
+        assert_eq!(parse_log_line("01:02:345 I[FILEMON]: opening.bnr"), Some("opening.bnr"));
+        assert_eq!(parse_log_line("01:02:400 Core/HW/DVD/DVDInterface.cpp:398 I[DVDINTERFACE]: Read 0x20 bytes"), None);
+        assert_eq!(parse_log_line("01:02:345 I[FileMon]:   "), None);
+
+        assert_eq!(normalize_path("AUDIO\\bgm.dsp"), "/AUDIO/bgm.dsp");
+        assert_eq!(normalize_path("//data/levels/"), "/data/levels");
+    }
+
+    // Lines the way different versions of Dolphin have written them, and a
+    // line from another log type
+    const LOG: &str = "\
+        01:02:345 Core/HW/DVD/FileMonitor.cpp:76 I[FileMon]: /data/levels/2.bin\r\n\
+        01:02:346 Core\\HW\\DVD\\FileMonitor.cpp:76 I[FileMon]: DATA\\levels\\1.bin\n\
+        01:02:400 Core/HW/DVD/DVDInterface.cpp:398 I[DVDINTERFACE]: Read 0x20 bytes at /data/big.bin\n\
+        01:02:401 I[FileMon]: /data/levels/2.bin\n\
+        01:02:402 I[FileMon]: gone.bin\n";
+
+    fn files(import: &LogImport) -> Vec<(&str, u64)> {
+        import.profile.files.iter().map(|f| (f.path.as_str(), f.accesses)).collect()
+    }
+
+    #[test]
+    fn importing_a_log_for_a_rom() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let import = FileOrderProfile::from_log(LOG, Some(&game.fst));
+        assert_eq!(files(&import), [("/data/levels/2.bin", 2), ("/data/levels/1.bin", 1)]);
+        assert_eq!(import.unmatched, [(5, "/gone.bin".to_owned())]);
+        assert_eq!(import.accesses, 4);
+    }
+
+    // Without a ROM, nothing is unmatched, and the case is the log's
+    #[test]
+    fn importing_a_log_on_its_own() {
+        let import = FileOrderProfile::from_log(LOG, None);
+        assert_eq!(files(&import), [("/data/levels/2.bin", 2), ("/DATA/levels/1.bin", 1), ("/gone.bin", 1)]);
+        assert!(import.unmatched.is_empty());
+        assert_eq!(import.profile.ranks()[&rank_key("data/LEVELS/1.bin")], 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn reading_profiles() {
+        let dir = TestDir::new();
+        let path = dir.join("order.json");
+        FileOrderProfile::from_log(LOG, None).profile.write(&path).unwrap();
+        let profile = FileOrderProfile::read(&path).unwrap();
+        assert_eq!(profile.version, PROFILE_VERSION);
+        assert_eq!(profile.files.len(), 3);
+        assert_eq!(profile.files[0].accesses, 2);
+
+        // Paths on their own are read once each
+        fs::write(&path, r#"["/readme.txt", "/data/big.bin", "/readme.txt"]"#).unwrap();
+        let profile = FileOrderProfile::read(&path).unwrap();
+        assert!(profile.files.iter().all(|f| f.accesses == 1));
+        assert_eq!(profile.ranks()[&rank_key("/readme.txt")], 0);
+
+        fs::write(&path, r#"{ "version": 2, "files": [] }"#).unwrap();
+        let e = FileOrderProfile::read(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("version 2"), "{}", e);
+    }
+}
@@ -1,23 +1,68 @@
 use std::{
-    collections::BTreeMap,
-    fs::{create_dir, File},
-    io::{self, BufRead, Seek},
-    path::Path
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{create_dir_all, File},
+    io::{self, BufRead, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    error::Error,
+    fmt,
+    path::{Path, PathBuf, MAIN_SEPARATOR},
 };
 
-use eyre::WrapErr;
+use byteorder::{BigEndian, ReadBytesExt};
+use eyre::{eyre, WrapErr};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "serde")]
+use crate::{manifest::MANIFEST_NAME, sections::dol::pack::split_dir};
 use crate::{
+    case_names::{case_renames, fold_case},
+    format_bytes,
     format_u64,
+    format_usize,
+    io_util::{self, DedupMode, DiskFull, Embedded, FsHints, LinkKind},
+    parse_as_u64,
+    profiling,
+    known_files::{self, Category},
+    magic::{self, FileKind},
+    manifest::{Manifest, RenamedFile},
+    nkit::{self, NkitStamp},
+    provenance::Provenance,
+    report::{ProgressDetail, Reporter},
+    rom_handle::Compression,
     paths::*,
+    style::{Colors, Kind},
+    timestamp,
+    titles::{Language, Titles},
     sections::{
-        apploader::{Apploader, APPLOADER_OFFSET},
-        dol::{segment::Segment, DOLHeader},
+        apploader::{apploader_room, Apploader, ApploaderInfo, APPLOADER_OFFSET},
+        banner::{Banner, BANNER_MAX_SIZE, BANNER_PATH},
+        dol::{segment::Segment, DOLHeader, DolInfo},
         fst::{
-            entry::DirectoryEntry,
+            dedup::Dedup,
+            xattrs::OriginAttributes,
+            entry::{
+                set_bytes_needed,
+                DirectoryEntry,
+                Entry,
+                EntryInfo,
+                EntryRecord,
+                ExtractContext,
+                ExtractFailure,
+                ExtractHints,
+                FileEntry,
+                FstWalkError,
+                PaddedFile,
+            },
+            handler::{ExtractHandler, HandlerOutcome, OutputSink},
+            ParseOptions,
+            Resolution,
+            FstInfo,
             FST,
         },
-        header::{Header, GAME_HEADER_SIZE},
+        header::{self, DebugMonitor, Header, HeaderInfo, GAME_HEADER_SIZE},
+        rel::RelHeader,
+        thp::{ThpHeader, THP_PROBE_SIZE},
+        write_fields,
         Section,
     },
     NumberStyle,
@@ -25,38 +70,426 @@ use crate::{
 
 
 pub const ROM_SIZE: usize = 0x57058000;
+// The sizes of discs other than retail ones, like the ones used by NR disc
+// readers and dual layer development discs.
+pub const NR_DISC_SIZE: u64 = 0x5077_0000;
+pub const DUAL_LAYER_DISC_SIZE: u64 = 0x1_FD7F_8000;
+
+// Turns a capacity given by name ("retail", "nr", or "dual-layer") or as a
+// number of bytes into a number of bytes.
+pub fn parse_capacity(s: &str) -> Option<u64> {
+    match s.to_ascii_lowercase().as_str() {
+        "retail" => Some(ROM_SIZE as u64),
+        "nr" => Some(NR_DISC_SIZE),
+        "dual-layer" => Some(DUAL_LAYER_DISC_SIZE),
+        _ => parse_as_u64(s).ok().filter(|&n| n != 0),
+    }
+}
+
+// Written to the root of the output when files fail to extract.
+pub const EXTRACTION_ERRORS_NAME: &str = "extraction-errors.json";
+
+#[derive(Default)]
+pub struct ExtractOptions {
+    // Keep going when a file can't be extracted, instead of stopping.
+    pub keep_going: bool,
+    // Skip files stored inside of the system data, instead of extracting them
+    // with a warning.
+    pub strict: bool,
+    // Extract into a directory even if it already has things in it,
+    // overwriting any files in the way.
+    pub force: bool,
+    // Link files with the same contents to the first one extracted, instead
+    // of writing them again.
+    pub dedup: DedupMode,
+    // Write the header and FST from what was parsed instead of copying them
+    // from the ROM, since its header can't be trusted. For ROMs opened with
+    // `Game::open_rescued`.
+    pub rescued: bool,
+    // Record where each file came from on the ROM in its extended
+    // attributes.
+    pub xattrs: bool,
+    // Give files without an extension one from what's in them, see
+    // `Game::infer_extensions`. The manifest records the renames, so
+    // rebuilding puts the original names back.
+    pub infer_extensions: bool,
+    // What matching files go to instead of being copied, see `with_handler`.
+    pub handlers: Vec<ExtractHandler>,
+    // Fail to extract files that go past the end of the image, instead of
+    // extracting them with zeros on the end.
+    pub fail_on_truncated: bool,
+    // Don't warn about NKit images, see `nkit`.
+    pub acknowledge_nkit: bool,
+    // Tell the OS how the ROM is about to be read and the files written,
+    // see `ExtractHints`. The ROM can only be read ahead with its file.
+    pub fs_hints: bool,
+    pub source_file: Option<File>,
+    // Whether the destination treats names that only differ by case as the
+    // same, see `case_names`. `None` finds out by making a file there.
+    pub case_insensitive: Option<bool>,
+    // Also write the DOL's segments to Start.dol.d, like `unpack-dol` does.
+    pub dol_split: bool,
+}
+
+impl ExtractOptions {
+    // Adds a handler for files matching `pattern`, which go to the first
+    // handler they match. See `ExtractHandler`.
+    pub fn with_handler<H>(mut self, pattern: impl Into<String>, handler: H) -> ExtractOptions
+    where
+        H: Fn(&EntryInfo, &mut dyn Read, &mut OutputSink<'_>) -> io::Result<HandlerOutcome> + 'static,
+    {
+        self.handlers.push(ExtractHandler::new(pattern, handler));
+        self
+    }
+}
+
+// A file without an extension that looks like a known kind of file, from
+// `Game::infer_extensions`.
+#[derive(Debug)]
+pub struct InferredName {
+    pub index: usize,
+    // On the ROM, normalized
+    pub path: String,
+    pub kind: FileKind,
+    // The name with the extension
+    pub name: String,
+    // What's already in the same directory with that name, if anything, in
+    // which case the file keeps its own
+    pub collides_with: Option<String>,
+}
+
+// What `Game::extract_section_with_name` extracted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExtractedKind {
+    Header,
+    Apploader,
+    Dol,
+    Fst,
+    File,
+    Directory,
+    Segment,
+}
 
+#[derive(Debug)]
+pub enum SectionError {
+    // Nothing has that name. `reason` is where a path stopped leading
+    // anywhere, when that's before its end, see `Resolution::problem`.
+    // `suggestions` are paths in the FST it might have meant, see
+    // `FST::suggest`.
+    NotFound { name: String, reason: Option<String>, suggestions: Vec<String> },
+    // A DOL segment's name, but not one of the DOL's. `segments` are the
+    // names of the ones it has.
+    NoSuchSegment { name: String, segments: Vec<String> },
+    // Reading the ROM or writing `output` failed.
+    Io { output: PathBuf, source: eyre::Report },
+}
+
+impl fmt::Display for SectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionError::NotFound { name, reason: None, .. } => write!(f, "There's no section or file called {}", name),
+            SectionError::NotFound { name, reason: Some(reason), .. } =>
+                write!(f, "There's no section or file called {}: {}", name, reason),
+            SectionError::NoSuchSegment { name, .. } => write!(f, "The DOL doesn't have a {} segment", name),
+            SectionError::Io { output, .. } => write!(f, "Failed to extract to {}", output.display()),
+        }
+    }
+}
+
+impl Error for SectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SectionError::Io { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// The basic facts about a game, small enough to keep around for lots of ROMs.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GameSummary {
+    pub id: String,
+    pub title: String,
+    pub region: String,
+    pub disc: u8,
+    pub version: u8,
+    pub file_count: usize,
+    pub fs_size: usize,
+    pub dol_size: usize,
+}
+
+impl GameSummary {
+    fn new(header: &Header, dol: &DOLHeader, file_count: usize, fs_size: usize) -> GameSummary {
+        GameSummary {
+            id: format!("{}{}", header.game_code, header.maker_code),
+            title: header.title.clone(),
+            region: header::region_name(header.information.country_code).to_owned(),
+            // Disc numbers start at 0 in the header
+            disc: header.disk_id + 1,
+            version: header.version,
+            file_count,
+            fs_size,
+            dol_size: dol.dol_size,
+        }
+    }
+}
+
+// What `info` shows about a whole game, for `--json`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct GameInfo {
+    // `Titles::best` for the language asked for
+    pub title: String,
+    pub game_id: String,
+    pub version: u8,
+    pub provenance: Provenance,
+    // `NkitStamp::notice`, if it's an NKit image
+    pub nkit: Option<String>,
+    pub trimmed_to: Option<u64>,
+    pub capacity: u64,
+    // What the file it was read from was compressed with
+    pub compression: Option<String>,
+    // Only with `verbose`
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub titles: Option<Titles>,
+    pub header: HeaderInfo,
+    pub apploader: ApploaderInfo,
+    pub dol: DolInfo,
+    pub fst: FstInfo,
+    pub layout: LayoutInfo,
+}
+
+impl GameInfo {
+    // What `info` prints, with every title after the rest if there are
+    // `titles`.
+    pub fn write(&self, mut out: impl Write, style: NumberStyle, colors: &Colors) -> io::Result<()> {
+        writeln!(out, "Title: {}", self.title)?;
+        writeln!(out, "GameID: {}", self.game_id)?;
+        writeln!(out, "Version: {}", format_u64(self.version as u64, style))?;
+        writeln!(out, "{}", self.provenance)?;
+        if let Some(notice) = &self.nkit {
+            writeln!(out, "{}", colors.paint(Kind::Warning, &format!("Notice: {}", notice)))?;
+        }
+        let (disc, total) = (self.header.disc, self.header.total_discs);
+        match total {
+            None | Some(1) if disc == 1 => {},
+            None => writeln!(out, "Disc: {}", disc)?,
+            Some(total) => writeln!(out, "Disc: {} of {}", disc, total)?,
+        }
+        if let Some(len) = self.trimmed_to {
+            writeln!(out, "Trimmed image: {} MiB of {} MiB", len >> 20, self.capacity >> 20)?;
+        }
+        if let Some(compression) = &self.compression {
+            writeln!(out, "Compressed: {}, decompressed to read it", compression)?;
+        }
+
+        if let Some(titles) = &self.titles {
+            writeln!(out)?;
+            titles.write_info(&mut out)?;
+        }
+
+        writeln!(out, "\nROM Layout:")?;
+        write_layout_info(out, &self.layout, self.capacity, colors)
+    }
+}
+
+// What `info -t layout` shows. Offsets are from the start of the image.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LayoutInfo {
+    // The header, apploader, DOL, FST, and debug monitor, in order
+    pub system: Vec<LayoutRegion>,
+    pub user_area: Option<LayoutRegion>,
+    pub files_past_capacity: Vec<String>,
+    pub files_outside_user_area: Vec<String>,
+    pub unused_bytes: u64,
+    pub gaps: usize,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LayoutRegion {
+    pub name: &'static str,
+    pub start: u64,
+    // Exclusive
+    pub end: u64,
+}
+
+// Offsets of sections, like `dol.offset`, `fst.offset`, and the DOL's
+// segments, are absolute: they're from the start of the file the image was
+// read from, so they include `offset`. The header's fields and files'
+// offsets are as the image has them, from the start of the image.
 #[derive(Debug)]
 pub struct Game {
+    // Where the image starts in the file it was read from, which is 0
+    // unless it's embedded in something bigger
+    pub offset: u64,
     pub header: Header,
     pub apploader: Apploader,
     pub fst: FST,
     pub dol: DOLHeader,
+    // How big the image is, which isn't always `ROM_SIZE`.
+    pub capacity: u64,
+    // How long the file is, for trimmed images: ones shorter than
+    // `ROM_SIZE` with everything on them still there, which had the padding
+    // at the end cut off. `capacity` is `ROM_SIZE` for them, and the missing
+    // tail is read as zeros, see `io_util::ZeroExtended`.
+    pub trimmed_to: Option<u64>,
+    // The stamp from the header, if NKit made the image, see `nkit`
+    pub nkit: Option<NkitStamp>,
+    // From the header, at an absolute offset like the DOL's and FST's
+    pub debug_monitor: Option<DebugMonitor>,
 }
 
 impl Game {
 
-    pub fn open<R>(mut iso: R, offset: u64) -> io::Result<Game>
+    pub fn open<R>(iso: R, offset: u64) -> io::Result<Game>
+    where
+        R: BufRead + Seek,
+    {
+        Game::open_with(iso, offset, &ParseOptions::default())
+    }
+
+    pub fn open_with<R>(mut iso: R, offset: u64, options: &ParseOptions) -> io::Result<Game>
+    where
+        R: BufRead + Seek,
+    {
+        let header = Header::new(&mut iso, offset)?;
+        let mut apploader = Apploader::new(&mut iso, offset + APPLOADER_OFFSET)?;
+        let capacity = iso.seek(SeekFrom::End(0))?.saturating_sub(offset);
+        check_offsets(&header, &apploader, capacity)?;
+        apploader.bound_to(apploader_room(&header, capacity));
+        // Otherwise it's left to the caller to warn about
+        if let Some(problem) = apploader.oversized().filter(|_| options.strict_apploader) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, problem));
+        }
+        let nkit = nkit::find_stamp(header.raw());
+        let dol = DOLHeader::new(&mut iso, offset + header.dol_offset)?;
+        let fst = FST::open_with(&mut iso, offset + header.fst_offset, header.fst_size, options)?;
+        let debug_monitor = header.debug_monitor().map(|m| DebugMonitor { offset: offset + m.offset, ..m });
+
+        let mut game = Game {
+            offset,
+            header,
+            apploader,
+            fst,
+            dol,
+            capacity,
+            trimmed_to: None,
+            nkit,
+            debug_monitor,
+        };
+        game.detect_trim();
+        game.check_section_offsets();
+        Ok(game)
+    }
+
+    // The summary, for scanning lots of ROMs, without reading the apploader's
+    // code or any of the FST's names, see `FST::summary`. That also means
+    // malformed FSTs aren't repaired, so `InvalidData` errors might go away
+    // with a full `Game::open`.
+    pub fn open_summary<R>(mut iso: R, offset: u64) -> io::Result<GameSummary>
     where
         R: BufRead + Seek,
     {
         let header = Header::new(&mut iso, offset)?;
         let apploader = Apploader::new(&mut iso, offset + APPLOADER_OFFSET)?;
+        let len = iso.seek(SeekFrom::End(0))?.saturating_sub(offset);
+        check_offsets(&header, &apploader, len)?;
         let dol = DOLHeader::new(&mut iso, offset + header.dol_offset)?;
-        let fst = FST::new(&mut iso, offset + header.fst_offset)?;
+        let fst = FST::summary(&mut iso, offset + header.fst_offset, header.fst_size)?;
+        Ok(GameSummary::new(&header, &dol, fst.file_count, fst.total_file_size as usize))
+    }
 
-        Ok(Game {
+    // NR disc images are smaller than `ROM_SIZE` without being trimmed
+    fn detect_trim(&mut self) {
+        let content_end = self.rom_layout().0.iter()
+            .map(|s| s.start() + s.size() as u64)
+            .max()
+            .unwrap_or(0);
+        let len = self.capacity;
+        if len < ROM_SIZE as u64 && len != NR_DISC_SIZE && content_end <= len {
+            self.trimmed_to = Some(len);
+            self.capacity = ROM_SIZE as u64;
+        }
+    }
+
+    // Opens a ROM whose header is damaged, using system data found somewhere
+    // else, like by `rescue`. Whatever can still be read from the header is
+    // kept. Without an apploader (`apploader` is false), the game gets one
+    // with no code, which extracts as a placeholder.
+    pub fn open_rescued<R>(mut iso: R, dol_offset: u64, fst_offset: u64, apploader: bool) -> io::Result<Game>
+    where
+        R: BufRead + Seek,
+    {
+        let mut header = match Header::new(&mut iso, 0) {
+            Ok(header) => header,
+            Err(_) => salvaged_header(&mut iso)?,
+        };
+        let apploader = match apploader {
+            true => Apploader::new(&mut iso, APPLOADER_OFFSET).ok(),
+            false => None,
+        }.unwrap_or(Apploader {
+            date: String::new(),
+            entry_point: 0,
+            code_size: 0,
+            trailer_size: 0,
+            room: None,
+        });
+        let dol = DOLHeader::new(&mut iso, dol_offset)?;
+        let fst = FST::new(&mut iso, fst_offset)?;
+        let capacity = iso.seek(SeekFrom::End(0))?;
+
+        let max_fst_size = header.max_fst_size.max(fst.size);
+        header.set_layout(dol_offset, fst_offset, fst.size, max_fst_size);
+        let mut apploader = apploader;
+        apploader.bound_to(apploader_room(&header, capacity));
+        let nkit = nkit::find_stamp(header.raw());
+        let debug_monitor = header.debug_monitor();
+
+        let mut game = Game {
+            offset: 0,
             header,
             apploader,
             fst,
             dol,
-        })
+            capacity,
+            trimmed_to: None,
+            nkit,
+            debug_monitor,
+        };
+        game.detect_trim();
+        Ok(game)
+    }
+
+    // The DOL and FST have to be where the header says, from `offset`. Games
+    // whose header's layout was changed since they were read shouldn't be
+    // extracted from.
+    fn check_section_offsets(&self) {
+        debug_assert_eq!(self.dol.offset, self.offset + self.header.dol_offset, "DOL offset isn't absolute");
+        debug_assert_eq!(self.fst.offset, self.offset + self.header.fst_offset, "FST offset isn't absolute");
+    }
+
+    // Where the image file ends, which is before `capacity` for trimmed
+    // images, and might be before the end of the last file for cut off ones.
+    pub fn data_end(&self) -> u64 {
+        self.trimmed_to.unwrap_or(self.capacity)
+    }
+
+    // Not counting directories
+    pub fn file_count(&self) -> usize {
+        self.fst.file_count
+    }
+
+    pub fn summary(&self) -> GameSummary {
+        GameSummary::new(&self.header, &self.dol, self.fst.file_count, self.fst.total_file_size)
     }
 
-    pub fn rom_layout(&self) -> ROMLayout {
-        let size = 5
+    pub fn rom_layout(&self) -> ROMLayout<'_> {
+        let size = 6
             + self.dol.iter_segments().count()
-            + self.fst.entries.len();
+            + self.fst.entries().len();
 
         let mut layout: Vec<&dyn Section> = Vec::with_capacity(size);
         layout.push(&self.header);
@@ -66,7 +499,10 @@ impl Game {
             layout.push(s);
         }
         layout.push(&self.fst);
-        for f in self.fst.entries.iter().filter_map(|e| e.as_file()) {
+        if let Some(m) = &self.debug_monitor {
+            layout.push(m);
+        }
+        for f in self.fst.entries().iter().filter_map(|e| e.as_file()) {
             layout.push(f);
         }
 
@@ -75,141 +511,866 @@ impl Game {
         ROMLayout(layout)
     }
 
-    pub fn extract<R, P>(&mut self, mut iso: R, path: P) -> eyre::Result<()>
+    // How many bytes extracting takes, not counting the file system's own
+    // overhead.
+    pub fn extracted_size(&self) -> u64 {
+        (GAME_HEADER_SIZE
+            + self.apploader.size_on_disc()
+            + self.fst.size
+            + self.dol.dol_size
+            + self.fst.total_file_size) as u64
+    }
+
+    // Returns the files that couldn't be extracted, which is always empty
+    // unless `options.keep_going` or `options.strict` is set.
+    pub fn extract<R, P>(
+        &mut self,
+        mut iso: R,
+        path: P,
+        options: &ExtractOptions,
+        reporter: &Reporter,
+    ) -> eyre::Result<Vec<ExtractFailure>>
     where
         R: BufRead + Seek,
         P: AsRef<Path>,
     {
-        // Not using `create_dir_all` here so it fails if `path` already exists.
-        create_dir(path.as_ref())?;
-        let sys_data_path = path.as_ref().join("&&systemdata");
+        // Catch these before anything gets written, rather than failing
+        // partway through with a half extracted tree.
+        let parent = io_util::check_destination_dir(path.as_ref(), options.force)?;
+        io_util::check_writable(parent)?;
+        io_util::check_free_space(parent, self.extracted_size())?;
+
+        let system = self.system_paths()?;
+        if system.is_alternate() {
+            reporter.warning(format!(
+                "The ROM has its own /{}, so the system data is extracted to {} instead",
+                SYSTEM_DATA_DIR, system.dir(),
+            ));
+        }
+        self.warn_about_nkit(options, reporter);
+
+        // Anything already there was checked for above.
+        create_dir_all(path.as_ref())?;
+        let sys_data_path = path.as_ref().join(system.dir());
         let sys_data_path: &Path = sys_data_path.as_ref();
-        create_dir(sys_data_path)?;
+        create_dir_all(sys_data_path)?;
 
-        println!("Extracting system data...");
+        reporter.phase("system_data");
+        reporter.status("Extracting system data...");
 
-        let header_file = File::create(sys_data_path.join("ISO.hdr"))?;
-        Header::extract(&mut iso, header_file).wrap_err("Failed to extract header")?;
+        self.extract_system_data(&mut iso, sys_data_path, options, reporter)?;
 
-        let fst_file = File::create(sys_data_path.join("Game.toc"))?;
-        FST::extract(&mut iso, fst_file, self.fst.offset).wrap_err("Failed to extract FST")?;
+        let mut skip = BTreeSet::new();
+        let mut failures = Vec::new();
+        for (f, section) in self.files_in_system_data() {
+            if options.strict {
+                reporter.warning(format!("Skipping {}, it's stored inside of {}", f.info.normalized_path(), section));
+                skip.insert(f.info.index);
+                failures.push(ExtractFailure {
+                    path: f.info.normalized_path(),
+                    offset: f.file_offset,
+                    error: format!("Stored inside of {}, skipped", section),
+                });
+            } else {
+                reporter.warning(format!(
+                    "{} is stored inside of {}, so it's a copy of system data rather than a game file",
+                    f.info.normalized_path(),
+                    section,
+                ));
+            }
+        }
 
-        let apploader_file = File::create(sys_data_path.join("Apploader.ldr"))?;
-        Apploader::extract(&mut iso, apploader_file).wrap_err("Failed to extract AppLoader")?;
+        reporter.phase("extract");
+        reporter.status("Extracting file system...");
+        let mut dedup = (options.dedup != DedupMode::Off).then(|| Dedup::new(options.dedup));
+        let mut origins = self.origin_attributes(options, reporter);
+        let mut names = BTreeMap::new();
+        let mut renamed = Vec::new();
+        if options.infer_extensions {
+            for inferred in self.infer_extensions(&mut iso).wrap_err("Failed to read files to infer extensions")? {
+                match inferred.collides_with {
+                    Some(other) => reporter.warning(format!(
+                        "{} looks like {}, but it can't be renamed to {} since {} is already there",
+                        inferred.path, inferred.kind.name(), inferred.name, other,
+                    )),
+                    None => {
+                        renamed.push(RenamedFile { path: inferred.path, extracted_as: inferred.name.clone() });
+                        names.insert(inferred.index, inferred.name);
+                    },
+                }
+            }
+        }
+        let extensions_added = renamed.len();
+        let case_insensitive = match options.case_insensitive {
+            Some(insensitive) => insensitive,
+            None => io_util::is_case_sensitive(path.as_ref()).map(|s| !s).unwrap_or_else(|e| {
+                reporter.warning(format!(
+                    "Couldn't tell whether {} is case-sensitive ({}), so names that only differ by case are kept apart",
+                    path.as_ref().display(), e,
+                ));
+                true
+            }),
+        };
+        if case_insensitive {
+            for rename in case_renames(&self.fst, &names, fold_case) {
+                reporter.warning(format!(
+                    "{} only differs by case from {}, which is the same name on {}, so it's extracted as {}",
+                    rename.path, rename.collides_with, path.as_ref().display(), rename.name,
+                ));
+                match renamed.iter_mut().find(|r: &&mut RenamedFile| r.path == rename.path) {
+                    Some(r) => r.extracted_as = rename.name.clone(),
+                    None => renamed.push(RenamedFile { path: rename.path, extracted_as: rename.name.clone() }),
+                }
+                names.insert(rename.index, rename.name);
+            }
+        }
+        let mut context = ExtractContext {
+            failures: options.keep_going.then_some(&mut failures),
+            skip,
+            names,
+            dedup: dedup.as_mut(),
+            origins: origins.as_mut(),
+            handlers: &options.handlers,
+            data_end: Some(self.data_end()),
+            padded: Vec::new(),
+            fail_on_truncated: options.fail_on_truncated,
+            hashes: Some(BTreeMap::new()),
+            hints: options.fs_hints.then(|| self.extract_hints(io_util::fs_hints(), options.source_file.as_ref())),
+            sink: Some(reporter.sink()),
+        };
+        // Running out of space still writes the manifest, with the hashes of
+        // what was written, so refreshing the tree picks up from there
+        let stopped = match self.extract_file_system(&mut iso, path.as_ref(), 4, &mut context, reporter) {
+            Ok(_) => None,
+            Err(e) if e.is::<DiskFull>() => Some(e),
+            Err(e) => return Err(e).wrap_err("Failed to extract filesystem"),
+        };
+        let padded = context.padded;
+        let hashes = context.hashes.unwrap_or_default();
+        warn_about_padding(&padded, reporter);
+        warn_about_origins(origins.as_ref(), reporter);
 
-        let mut dol_file = File::create(sys_data_path.join("Start.dol"))?;
-        DOLHeader::extract(&mut iso, &mut dol_file, self.dol.offset).wrap_err("Failed to extract DOL")?;
+        let mut manifest = Manifest::new(self);
+        manifest.record_hashes(&hashes);
+        manifest.padded = padded;
+        manifest.system_dir = system.is_alternate().then(|| system.dir().to_owned());
+        manifest.renamed = renamed;
+        if let Some(e) = stopped {
+            if let Some(full) = e.downcast_ref::<DiskFull>() {
+                manifest.record_partial(full, path.as_ref());
+            }
+            // If even that doesn't fit, there's nothing to refresh from
+            #[cfg(feature = "serde")]
+            let _ = manifest.write(path.as_ref().join(MANIFEST_NAME));
+            return Err(e).wrap_err("Failed to extract filesystem");
+        }
+        if options.infer_extensions {
+            reporter.status(format!("Added extensions to {} files.", extensions_added));
+        }
+        if let Some(dedup) = dedup {
+            for warning in dedup.warnings() {
+                reporter.warning(warning);
+            }
+            let linked = dedup.duplicates.iter().filter(|d| d.link != LinkKind::Copy).count();
+            reporter.status(format!(
+                "Linked {} duplicate files, saving {} bytes.",
+                linked, dedup.bytes_saved,
+            ));
+            manifest.duplicates = dedup.duplicates;
+        }
+        #[cfg(feature = "serde")]
+        manifest.write(path.as_ref().join(MANIFEST_NAME))
+            .wrap_err("Failed to write manifest")?;
+
+        #[cfg(feature = "serde")]
+        if !failures.is_empty() {
+            let file = File::create(path.as_ref().join(EXTRACTION_ERRORS_NAME))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &failures)
+                .wrap_err("Failed to write extraction errors")?;
+        }
+        Ok(failures)
+    }
+
+    // Where extracting puts the system data, see `SystemPaths::for_fst`.
+    pub fn system_paths(&self) -> eyre::Result<SystemPaths> {
+        SystemPaths::for_fst(&self.fst).ok_or_else(|| eyre!(
+            "The ROM has its own /{} and /{}, so there's nowhere to extract the system data",
+            SYSTEM_DATA_DIR, ALTERNATE_SYSTEM_DATA_DIR,
+        ))
+    }
 
-        println!("Extracting file system...");
-        self.extract_file_system(&mut iso, path.as_ref(), 4).wrap_err("Failed to extract filesystem")?;
+    // Writes the header, FST, apploader, and DOL to `sys_data_path`, which is
+    // the tree's system data directory.
+    pub(crate) fn extract_system_data(
+        &self,
+        mut iso: impl BufRead + Seek,
+        sys_data_path: &Path,
+        options: &ExtractOptions,
+        reporter: &Reporter,
+    ) -> eyre::Result<()> {
+        let header_file = File::create(sys_data_path.join(HEADER_NAME))?;
+        let fst_file = File::create(sys_data_path.join(FST_NAME))?;
+        let apploader_file = File::create(sys_data_path.join(APPLOADER_NAME))?;
+        if options.rescued {
+            self.header.write(BufWriter::new(header_file)).wrap_err("Failed to write header")?;
+            self.fst.write(BufWriter::new(fst_file)).wrap_err("Failed to write FST")?;
+        } else {
+            let mut timer = profiling::start("header_write");
+            Header::extract(&mut iso, header_file, self.offset).wrap_err("Failed to extract header")?;
+            timer.add_bytes(GAME_HEADER_SIZE as u64);
+            drop(timer);
+            self.check_section_offsets();
+            FST::extract(&mut iso, fst_file, self.offset, self.fst.offset).wrap_err("Failed to extract FST")?;
+        }
+        if self.apploader.code_size == 0 {
+            Apploader::write_placeholder(apploader_file).wrap_err("Failed to write AppLoader")?;
+        } else {
+            self.apploader.extract(&mut iso, apploader_file, self.offset).wrap_err("Failed to extract AppLoader")?;
+        }
+
+        let mut dol_file = File::create(sys_data_path.join(DOL_NAME))?;
+        DOLHeader::extract(&mut iso, &mut dol_file, self.dol.offset).wrap_err("Failed to extract DOL")?;
+        if options.dol_split {
+            if let (dir, Some(difference)) = self.split_dol(&mut iso, sys_data_path.join(DOL_NAME))? {
+                reporter.warning(Game::split_dol_warning(&dir, difference));
+            }
+        }
         Ok(())
     }
 
+    // Writes the DOL's segments next to `dol_path`, its extracted copy, for
+    // `--dol-split`. Gives back the directory they're in, and where the DOL
+    // has data outside of them if it does, since repacking them wouldn't give
+    // back the same DOL then. `split_dol_warning` says that.
+    #[cfg(feature = "serde")]
+    pub fn split_dol(&self, iso: impl BufRead + Seek, dol_path: impl AsRef<Path>) -> eyre::Result<(PathBuf, Option<u64>)> {
+        let dir = split_dir(dol_path);
+        let (_, difference) = self.dol.unpack_checked(iso, &dir)
+            .wrap_err_with(|| format!("Failed to write the DOL's segments to {}", dir.display()))?;
+        Ok((dir, difference))
+    }
+
+    // The segments' layout is written as JSON, so there's no splitting the
+    // DOL without serde.
+    #[cfg(not(feature = "serde"))]
+    pub fn split_dol(&self, _iso: impl BufRead + Seek, _dol_path: impl AsRef<Path>) -> eyre::Result<(PathBuf, Option<u64>)> {
+        Err(eyre!("Splitting the DOL needs the serde feature"))
+    }
+
+    pub fn split_dol_warning(dir: &Path, difference: u64) -> String {
+        format!(
+            "The DOL has data outside of its segments, at {:#x}, so repacking {} won't give back the same DOL.",
+            difference, dir.display(),
+        )
+    }
+
+    pub(crate) fn warn_about_nkit(&self, options: &ExtractOptions, reporter: &Reporter) {
+        if let Some(stamp) = self.nkit.as_ref().filter(|_| !options.acknowledge_nkit) {
+            reporter.warning(format!("{} Pass --acknowledge-nkit to not be warned.", stamp.notice()));
+        }
+    }
+
+    // For `options.xattrs`, if the platform supports them.
+    pub(crate) fn origin_attributes(&self, options: &ExtractOptions, reporter: &Reporter) -> Option<OriginAttributes> {
+        if !options.xattrs {
+            return None;
+        }
+        let game_id = format!("{}{}", self.header.game_code, self.header.maker_code);
+        let origins = OriginAttributes::new(game_id);
+        if origins.is_none() {
+            reporter.warning("Extended attributes aren't supported on this platform, so they won't be set");
+        }
+        origins
+    }
+
     pub fn extract_file_system(
         &mut self,
         iso: impl BufRead + Seek,
         path: impl AsRef<Path>,
         existing_files: usize,
+        context: &mut ExtractContext,
+        reporter: &Reporter,
     ) -> eyre::Result<usize> {
         let total = self.fst.file_count + existing_files;
-        let mut count = existing_files;
-        let res = self.fst.extract_file_system(path, iso, |_| {
-            count += 1;
-            print!("\r{}/{} files written.", count, total)
-        })?;
-        println!();
-        Ok(res)
+        let total_bytes = self.fst.total_file_size as u64;
+        let mut bytes = 0;
+        reporter.sink().totals(self.fst.file_count as u64, Some(total_bytes));
+        let res = self.fst.extract_file_system(path, iso, context, |written, failed, file| {
+            let written = written + existing_files;
+            bytes += file.size as u64;
+            let message = if failed == 0 {
+                format!("{}/{} files written.", written, total)
+            } else {
+                format!("{}/{} files written, {} failed.", written, total, failed)
+            };
+            let path = file.info.normalized_path();
+            let detail = ProgressDetail { bytes: Some(bytes), total_bytes: Some(total_bytes), path: Some(&path) };
+            reporter.progress_detail((written + failed) as u64, total as u64, detail, message);
+        });
+        reporter.finish_progress();
+        res.map_err(|e| set_bytes_needed(e, total_bytes.saturating_sub(bytes)))
     }
 
+    // `filename` is a system data path like `HEADER_PATH`, an FST selector
+    // (see `FST::entry_for_selector`), or a DOL segment's name like ".text0".
     pub fn extract_section_with_name(
         &self,
         filename: impl AsRef<Path>,
         output: impl AsRef<Path>,
-        iso: impl BufRead + Seek,
-    ) -> eyre::Result<bool> {
+        mut iso: impl BufRead + Seek,
+    ) -> Result<ExtractedKind, SectionError> {
         let output = output.as_ref();
         let filename = &*filename.as_ref().to_string_lossy();
-        match filename {
-            HEADER_PATH =>
-                Header::extract(iso, &mut File::create(output)?)
-                    .map(|_| true).wrap_err("Failed to extract header"),
-            APPLOADER_PATH =>
-                Apploader::extract(iso, &mut File::create(output)?)
-                    .map(|_| true).wrap_err("Failed to extract AppLoader"),
-            DOL_PATH =>
-                DOLHeader::extract(
-                    iso,
-                    &mut File::create(output)?,
-                    self.dol.offset,
-                ).map(|_| true).wrap_err("Failed to extract DOL"),
-            FST_PATH =>
-                FST::extract(iso, &mut File::create(output)?, self.fst.offset)
-                    .map(|_| true).wrap_err("Failed to extract FST"),
-            _ => {
-                if let Some(e) = self.fst.entry_for_path(filename) {
-                    e.extract_with_name(
-                        output, &self.fst.entries,
-                        iso,
-                        &|_| {},
-                    ).map(|_| true)
-                } else if let Some((t, n)) =
-                    Segment::parse_segment_name(filename)
-                {
-                    if let Some(s) = self.dol.find_segment(t, n) {
-                        s.extract(iso, &mut File::create(output)?)
-                            .map(|_| true).wrap_err("Failed to extract DOL")
-                    } else {
-                        Ok(false)
+        self.check_section_offsets();
+        let io_error = |source: eyre::Report| SectionError::Io { output: output.to_owned(), source };
+        let create = || File::create(output).map_err(|e| io_error(e.into()));
+        let (kind, result) = match filename {
+            HEADER_PATH => (
+                ExtractedKind::Header,
+                Header::extract(iso, create()?, self.offset).wrap_err("Failed to extract header"),
+            ),
+            APPLOADER_PATH => (
+                ExtractedKind::Apploader,
+                self.apploader.extract(iso, create()?, self.offset).wrap_err("Failed to extract AppLoader"),
+            ),
+            DOL_PATH => (
+                ExtractedKind::Dol,
+                DOLHeader::extract(iso, create()?, self.dol.offset).wrap_err("Failed to extract DOL"),
+            ),
+            FST_PATH => (
+                ExtractedKind::Fst,
+                FST::extract(iso, create()?, self.offset, self.fst.offset).wrap_err("Failed to extract FST"),
+            ),
+            _ => if let Some(e) = self.fst.entry_for_selector(filename) {
+                // Files' offsets are from the start of the image
+                let kind = if e.is_dir() { ExtractedKind::Directory } else { ExtractedKind::File };
+                (kind, e.extract_with_name(
+                    output, self.fst.entries(),
+                    Embedded::new(&mut iso, self.offset),
+                    &mut ExtractContext::default(),
+                    |_, _, _| {},
+                ).map(drop))
+            } else if let Some((t, n)) = Segment::parse_segment_name(filename) {
+                let s = self.dol.find_segment(t, n).ok_or_else(|| SectionError::NoSuchSegment {
+                    name: filename.to_owned(),
+                    segments: self.dol.iter_segments().map(|s| s.to_string()).collect(),
+                })?;
+                (ExtractedKind::Segment, s.extract(iso, create()?).wrap_err("Failed to extract DOL segment"))
+            } else {
+                return Err(SectionError::NotFound {
+                    name: filename.to_owned(),
+                    reason: self.path_problem(filename),
+                    suggestions: self.fst.suggest(filename).iter().map(|e| e.info().normalized_path()).collect(),
+                });
+            },
+        };
+        result.map(|()| kind).map_err(io_error)
+    }
+
+    // Why `path` doesn't lead to an entry, if it stops before its last part,
+    // like "/audio has nothing called us" for "/audio/us/bgm.dsp".
+    fn path_problem(&self, path: &str) -> Option<String> {
+        let resolution = self.fst.resolve(path);
+        match resolution {
+            Resolution::Missing { parent: Some(ref parent), ref missing }
+                if format!("{}/{}", parent.trim_end_matches('/'), missing) == path.trim_end_matches('/') => None,
+            Resolution::Missing { parent: Some(_), .. } | Resolution::NotADirectory { .. } => resolution.problem(),
+            _ => None,
+        }
+    }
+
+    // `titles.best(language)` is the title, and with `verbose`, every title
+    // is included too.
+    // `compression` is what the file the ROM was read from was compressed
+    // with, see `RomReadHandle::compression`.
+    pub fn info(
+        &self,
+        titles: &Titles,
+        compression: Option<Compression>,
+        language: Option<Language>,
+        verbose: bool,
+    ) -> GameInfo {
+        GameInfo {
+            title: titles.best(language).text.clone(),
+            game_id: format!("{}{}", self.header.game_code, self.header.maker_code),
+            version: self.header.version,
+            provenance: self.provenance(),
+            nkit: self.nkit.as_ref().map(NkitStamp::notice),
+            trimmed_to: self.trimmed_to,
+            capacity: self.capacity,
+            compression: compression.map(|c| c.to_string()),
+            titles: verbose.then(|| titles.clone()),
+            header: self.header.info(),
+            apploader: self.apploader.info(),
+            dol: self.dol.info(),
+            fst: self.fst.info(),
+            layout: self.layout_info(),
+        }
+    }
+
+    pub fn layout_info(&self) -> LayoutInfo {
+        let mut system = vec![
+            LayoutRegion { name: "ISO.hdr", start: 0, end: GAME_HEADER_SIZE as u64 },
+            LayoutRegion {
+                name: "Apploader.ldr",
+                start: APPLOADER_OFFSET,
+                end: APPLOADER_OFFSET + self.apploader.size_on_disc() as u64,
+            },
+            LayoutRegion {
+                name: "Start.dol",
+                start: self.header.dol_offset,
+                end: self.header.dol_offset + self.dol.dol_size as u64,
+            },
+            LayoutRegion {
+                name: "Game.toc",
+                start: self.header.fst_offset,
+                end: self.header.fst_offset + self.fst.size as u64,
+            },
+        ];
+        if let Some(m) = self.header.debug_monitor() {
+            system.push(LayoutRegion { name: "Debug monitor", start: m.offset, end: m.offset + m.size as u64 });
+        }
+        system.sort_by_key(|r| r.start);
+
+        let gaps = self.rom_layout().gaps(self.capacity);
+        LayoutInfo {
+            system,
+            user_area: self.user_area().map(|(start, end)| LayoutRegion { name: "User area", start, end }),
+            files_past_capacity: self.files_past_capacity().iter().map(|f| f.info.normalized_path()).collect(),
+            files_outside_user_area: self.files_outside_user_area().iter().map(|f| f.info.normalized_path()).collect(),
+            unused_bytes: gaps.iter().map(|(start, end)| end - start).sum(),
+            gaps: gaps.len(),
+        }
+    }
+
+    pub fn write_layout(&self, out: impl Write, colors: &Colors) -> io::Result<()> {
+        write_layout_info(out, &self.layout_info(), self.capacity, colors)
+    }
+
+    // Read ahead of each file when they're stored in the order they're
+    // extracted in, which they usually are.
+    fn extract_hints<'a>(&self, hints: &'static dyn FsHints, source: Option<&'a File>) -> ExtractHints<'a> {
+        let files: Vec<&FileEntry> = self.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size != 0)
+            .collect();
+        let in_order = files.windows(2).all(|w| w[0].file_offset <= w[1].file_offset);
+        let mut next = HashMap::new();
+        if let (Some(source), true) = (source, in_order) {
+            let _ = hints.sequential(source);
+            next = files.windows(2)
+                .map(|w| (w[0].info.index, (w[1].file_offset, w[1].size as u64)))
+                .collect();
+        }
+        ExtractHints { hints, source, next }
+    }
+
+    // The files with no extension in their names that `magic::sniff`
+    // recognizes, with the extension it suggests. Names are compared
+    // without case, since the tree might be on a file system that does.
+    pub fn infer_extensions(&self, mut iso: impl Read + Seek) -> io::Result<Vec<InferredName>> {
+        // Every name in each directory so far, by the directory's index
+        let mut siblings: HashMap<usize, HashMap<String, String>> = HashMap::new();
+        for e in &self.fst.entries()[1..] {
+            let name = e.info().name.trim_end_matches(std::path::MAIN_SEPARATOR);
+            siblings.entry(e.info().directory_index.unwrap_or(0))
+                .or_default()
+                .insert(name.to_lowercase(), name.to_owned());
+        }
+
+        let mut inferred = Vec::new();
+        let mut start = Vec::with_capacity(magic::SNIFF_SIZE);
+        for f in self.fst.entries().iter().filter_map(|e| e.as_file()) {
+            if f.size == 0 || Path::new(&f.info.name).extension().is_some() {
+                continue
+            }
+            start.clear();
+            iso.seek(SeekFrom::Start(f.file_offset))?;
+            (&mut iso).take(f.size.min(magic::SNIFF_SIZE) as u64).read_to_end(&mut start)?;
+            if let Some(kind) = magic::sniff(&start) {
+                let name = format!("{}.{}", f.info.name, kind.extension());
+                // Two files that only differ by case could get the same new
+                // name, so the first one takes it
+                let names = siblings.entry(f.info.directory_index.unwrap_or(0)).or_default();
+                let collides_with = names.get(&name.to_lowercase()).cloned();
+                if collides_with.is_none() {
+                    names.insert(name.to_lowercase(), name.clone());
+                }
+                inferred.push(InferredName {
+                    index: f.info.index,
+                    path: f.info.normalized_path(),
+                    kind,
+                    name,
+                    collides_with,
+                });
+            }
+        }
+        Ok(inferred)
+    }
+
+    // The files stored at least partly inside of the system data, along with
+    // the name of the section they're in.
+    pub fn files_in_system_data(&self) -> Vec<(&FileEntry, String)> {
+        let layout = self.rom_layout();
+        self.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .filter_map(|f| match layout.classify_range(f.file_offset, f.size as u64) {
+                RangeClass::Outside => None,
+                RangeClass::Inside(s) | RangeClass::Straddles(s) => Some((f, s.name())),
+            })
+            .collect()
+    }
+
+    pub fn files_past_capacity(&self) -> Vec<&FileEntry> {
+        self.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.file_offset + f.size as u64 > self.capacity)
+            .collect()
+    }
+
+    // The user area from the header, as (start, end), if it's set.
+    pub fn user_area(&self) -> Option<(u64, u64)> {
+        let (position, length) = (self.header.user_position as u64, self.header.user_length as u64);
+        (position != 0 && length != 0).then_some((position, position + length))
+    }
+
+    // The files that aren't entirely in the user area. Empty files don't take
+    // up any space, so they're never outside of it.
+    pub fn files_outside_user_area(&self) -> Vec<&FileEntry> {
+        let Some((start, end)) = self.user_area() else {
+            return Vec::new();
+        };
+        self.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size != 0)
+            .filter(|f| f.file_offset < start || f.file_offset + f.size as u64 > end)
+            .collect()
+    }
+
+    // The banner in the root of the file system, if there is one.
+    pub fn read_banner(&self, iso: impl Read + Seek) -> Option<(&FileEntry, io::Result<Banner>)> {
+        let file = self.fst.entry_for_path(BANNER_PATH)?.as_file()?;
+        Some((file, Banner::new(iso, file.file_offset)))
+    }
+
+    pub fn write_assets(&self, mut out: impl Write, mut iso: impl Read + Seek, style: NumberStyle) -> io::Result<()> {
+        let categories = self.asset_categories();
+        if categories.is_empty() {
+            writeln!(out, "No known asset files were found.")?;
+        }
+
+        for (category, files) in &categories {
+            let total: usize = files.iter().map(|f| f.size).sum();
+            writeln!(
+                out,
+                "{}: {} files, {} bytes",
+                category.name(),
+                files.len(),
+                format_usize(total, style),
+            )?;
+
+            // A file that can't be read, like one that's cut short, is
+            // listed as unreadable, and the rest are still listed.
+            match category {
+                Category::Banner => {
+                    for f in files {
+                        let banner = Game::read_start(f, &mut iso, BANNER_MAX_SIZE)
+                            .and_then(|start| Banner::new(Cursor::new(start), 0));
+                        match banner {
+                            Ok(b) => if let Some(info) = b.infos.first() {
+                                writeln!(out, "    {}: {} ({})", f.info.normalized_path(), info.long_title, info.long_maker)?;
+                            },
+                            Err(e) if e.kind() == io::ErrorKind::InvalidData =>
+                                writeln!(out, "    {}: invalid banner ({e})", f.info.normalized_path())?,
+                            Err(e) => writeln!(out, "    {}: unreadable ({e})", f.info.normalized_path())?,
+                        }
+                    }
+                },
+                Category::Module => {
+                    // The module ID is the first field of a REL's header.
+                    for f in files {
+                        let id = Game::read_start(f, &mut iso, 4)
+                            .and_then(|start| Cursor::new(start).read_u32::<BigEndian>());
+                        match id {
+                            Ok(id) => writeln!(out, "    {}: module {}", f.info.normalized_path(), format_u64(id as u64, style))?,
+                            Err(e) => writeln!(out, "    {}: unreadable ({e})", f.info.normalized_path())?,
+                        }
+                    }
+                },
+                Category::Movie => {
+                    for f in files {
+                        match Game::read_thp_header(f, &mut iso) {
+                            Some(thp) => writeln!(out, "    {}: {}", f.info.normalized_path(), thp.summary())?,
+                            None => writeln!(out, "    {}: invalid THP", f.info.normalized_path())?,
+                        }
                     }
-                } else {
-                    Ok(false)
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_entry_info(
+        &self,
+        mut out: impl Write,
+        entry: &Entry,
+        mut iso: impl Read + Seek,
+        style: NumberStyle,
+    ) -> io::Result<()> {
+        match entry {
+            Entry::File(f) => {
+                write_fields(&mut out, &f.fields(), style)?;
+                if let Some(rel) = Game::read_rel_header(f, &mut iso) {
+                    writeln!(out)?;
+                    rel.write_info(&mut out, style)?;
                 }
+                if let Some(thp) = Game::read_thp_header(f, &mut iso) {
+                    writeln!(out)?;
+                    thp.write_info(&mut out, style)?;
+                }
+            },
+            Entry::Directory(d) => {
+                writeln!(out, "Path: {}", d.info.full_path.display())?;
+                writeln!(out, "Index: {}", d.info.index)?;
+                writeln!(out, "Entries: {}", format_usize(d.file_count, style))?;
             },
         }
+        Ok(())
     }
 
-    pub fn print_info(&self, style: NumberStyle) {
-        println!("Title: {}", self.header.title);
-        println!("GameID: {}{}", self.header.game_code, self.header.maker_code);
-        println!("Version: {}", format_u64(self.header.version as u64, style));
+    // The REL header of `file`, if it looks like a REL and has a valid one.
+    pub fn read_rel_header(file: &FileEntry, iso: impl Read + Seek) -> Option<RelHeader> {
+        if known_files::categorize(&file.info.normalized_path()) != Some(Category::Module) {
+            return None;
+        }
+        RelHeader::with_size(iso, file.file_offset, file.size as u64).ok()
+    }
 
-        println!("\nROM Layout:");
-        self.print_layout();
+    // The THP header of `file`, if it looks like a movie and has a valid one.
+    // Only the start of the file is read, so nothing in the header can point
+    // past it.
+    pub fn read_thp_header(file: &FileEntry, iso: impl Read + Seek) -> Option<ThpHeader> {
+        if known_files::categorize(&file.info.normalized_path()) != Some(Category::Movie) {
+            return None;
+        }
+        let start = Game::read_start(file, iso, THP_PROBE_SIZE).ok()?;
+        ThpHeader::new(Cursor::new(start), 0).ok()
     }
 
-    pub fn print_layout(&self) {
-        let mut regions = BTreeMap::new();
+    // Up to the first `len` bytes of `file`, and never anything past its end.
+    fn read_start(file: &FileEntry, mut iso: impl Read + Seek, len: usize) -> io::Result<Vec<u8>> {
+        iso.seek(SeekFrom::Start(file.file_offset))?;
+        let mut start = Vec::new();
+        iso.take(file.size.min(len) as u64).read_to_end(&mut start)?;
+        Ok(start)
+    }
 
-        // Format: regions.insert(start, (size, name));
-        regions.insert(0, (GAME_HEADER_SIZE, "ISO.hdr"));
-        regions.insert(
-            APPLOADER_OFFSET,
-            (self.apploader.total_size(), "Apploader.ldr")
-        );
-        regions.insert(
-            self.header.dol_offset,
-            (self.dol.dol_size, "Start.dol")
-        );
-        regions.insert(self.header.fst_offset, (self.fst.size, "Game.toc"));
+    // The lines listing what's in `dir`, and what was wrong with its entries,
+    // for the caller to warn about. `mtime` is a made-up time to list every
+    // entry with in the long format, see `timestamp::FakeMtime`. With
+    // `recursive`, everything inside of `dir` is listed, all the way down, in
+    // FST order.
+    pub fn listing(
+        &self,
+        dir: &DirectoryEntry,
+        recursive: bool,
+        long_format: bool,
+        mtime: Option<i64>,
+        mut iso: impl Read + Seek,
+        colors: &Colors,
+    ) -> (Vec<String>, Vec<FstWalkError>) {
+        let system = self.system_file_indices();
+        if recursive {
+            let lines = dir.iter_descendants(self.fst.entries())
+                .map(|e| self.entry_line(e, &system, long_format, mtime, &mut iso, colors))
+                .collect();
+            return (lines, Vec::new());
+        }
+        let mut contents = dir.iter_contents(self.fst.entries());
+        let lines = contents.by_ref()
+            .map(|e| self.entry_line(e, &system, long_format, mtime, &mut iso, colors))
+            .collect();
+        (lines, contents.errors().to_vec())
+    }
 
-        for (start, &(end, name)) in &regions {
-            println!("{:#010x}-{:#010x}: {}", start, start + end as u64, name);
+    // The line listing `entry` itself, the way `listing` lists what's in one.
+    pub fn entry_listing(
+        &self,
+        entry: &Entry,
+        long_format: bool,
+        mtime: Option<i64>,
+        mut iso: impl Read + Seek,
+        colors: &Colors,
+    ) -> String {
+        self.entry_line(entry, &self.system_file_indices(), long_format, mtime, &mut iso, colors)
+    }
+
+    // What's in `dir`, as records for machine-readable listings, and with
+    // `recursive`, everything inside of it all the way down, in FST order.
+    // The problems are like `listing`'s.
+    pub fn entry_records(&self, dir: &DirectoryEntry, recursive: bool) -> (Vec<EntryRecord>, Vec<FstWalkError>) {
+        if recursive {
+            return (dir.iter_descendants(self.fst.entries()).map(Entry::record).collect(), Vec::new());
         }
+        let mut contents = dir.iter_contents(self.fst.entries());
+        let records = contents.by_ref().map(Entry::record).collect();
+        (records, contents.errors().to_vec())
+    }
+
+    fn system_file_indices(&self) -> BTreeSet<usize> {
+        self.files_in_system_data().iter().map(|(f, _)| f.info.index).collect()
     }
 
-    pub fn print_directory(&self, dir: &DirectoryEntry, long_format: bool) {
-        for e in dir.iter_contents(&self.fst.entries) {
-            if long_format {
-                println!("{}", e.format_long());
+    fn entry_line(
+        &self,
+        e: &Entry,
+        system: &BTreeSet<usize>,
+        long_format: bool,
+        mtime: Option<i64>,
+        mut iso: impl Read + Seek,
+        colors: &Colors,
+    ) -> String {
+        let name = self.listed_path(e);
+        let name = colors.paint_entry(e, system.contains(&e.info().index), &name);
+        if !long_format {
+            return name;
+        }
+        let name = match mtime {
+            Some(t) => format!("{} {}", timestamp::utc_minute(t), name),
+            None => name.to_string(),
+        };
+        let module = e.as_file().and_then(|f| Game::read_rel_header(f, &mut iso));
+        let movie = e.as_file().and_then(|f| Game::read_thp_header(f, &mut iso));
+        match (module, movie) {
+            (Some(rel), _) => format!("{} [module {}]", e.format_long_named(&name), rel.module_id),
+            (_, Some(thp)) => format!("{} [movie {}]", e.format_long_named(&name), thp.summary()),
+            _ => e.format_long_named(&name),
+        }
+    }
+
+    // An entry's path the way listings show it, with "<unnamed entry #12>"
+    // for each entry along it without a name, see `FST::unnamed_entries`.
+    // It's only ever printed, so nothing written can end up with it.
+    fn listed_path(&self, e: &Entry) -> String {
+        let mut names = Vec::new();
+        let mut unnamed = false;
+        let mut next = Some(e);
+        // Parents that go in a circle can't go on forever
+        while let Some(e) = next.filter(|e| e.info().index != 0 && names.len() < self.fst.entries().len()) {
+            let name = e.info().name.trim_end_matches(MAIN_SEPARATOR);
+            names.push(if name.is_empty() {
+                unnamed = true;
+                format!("<unnamed entry #{}>", e.info().index)
             } else {
-                println!("{}", e.info().full_path.display());
-            }
+                name.to_owned()
+            });
+            next = self.fst.get_parent_for_entry(e.info());
+        }
+        if !unnamed {
+            return e.info().full_path.to_string_lossy().into_owned();
         }
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push(MAIN_SEPARATOR);
+            path.push_str(name);
+        }
+        if e.is_dir() {
+            path.push(MAIN_SEPARATOR);
+        }
+        path
     }
 }
 
+pub(crate) fn warn_about_padding(padded: &[PaddedFile], reporter: &Reporter) {
+    if !padded.is_empty() {
+        let total = padded.iter().map(|p| p.bytes).sum::<u64>();
+        reporter.warning(format!(
+            "{} files go past the end of the image, so they contain synthesized padding totaling {}. The manifest lists them.",
+            padded.len(), format_bytes(total),
+        ));
+    }
+}
+
+// `OriginAttributes` stops at the first failure, so once the files are
+// written, there's at most one.
+pub(crate) fn warn_about_origins(origins: Option<&OriginAttributes>, reporter: &Reporter) {
+    if let Some(failure) = origins.and_then(OriginAttributes::failure) {
+        reporter.warning(failure);
+    }
+}
+
+// Fails with the header's `offset_problem`, if it has one.
+fn check_offsets(header: &Header, apploader: &Apploader, len: u64) -> io::Result<()> {
+    match header.offset_problem(Some(apploader), len) {
+        Some(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        None => Ok(()),
+    }
+}
+
+// The parts of a header that failed to parse that are still readable: the
+// game ID if it's alphanumeric, and the title if it decodes.
+fn salvaged_header(mut iso: impl Read + Seek) -> io::Result<Header> {
+    let mut id = [0; header::GAMEID_SIZE];
+    iso.seek(SeekFrom::Start(header::GAMEID_OFFSET))?;
+    iso.read_exact(&mut id)?;
+    let (game_code, maker_code) = match std::str::from_utf8(&id) {
+        Ok(id) if id.bytes().all(|b| b.is_ascii_alphanumeric()) => id.split_at(header::GAME_CODE_SIZE),
+        _ => ("0000", "00"),
+    };
+
+    let mut title = vec![0; header::TITLE_SIZE];
+    iso.seek(SeekFrom::Start(header::TITLE_OFFSET))?;
+    iso.read_exact(&mut title)?;
+    let title = title.split(|&b| b == 0).next().unwrap_or(&[]);
+    let title = std::str::from_utf8(title).ok()
+        .filter(|t| !t.chars().any(char::is_control))
+        .unwrap_or("");
+
+    Header::default_with(game_code, maker_code, title)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+fn write_layout_info(mut out: impl Write, layout: &LayoutInfo, capacity: u64, colors: &Colors) -> io::Result<()> {
+    for r in &layout.system {
+        writeln!(out, "{:#010x}-{:#010x}: {}", r.start, r.end, colors.paint(Kind::System, r.name))?;
+    }
+
+    for path in &layout.files_past_capacity {
+        writeln!(
+            out,
+            "{} {} ends past the end of the image ({:#010x})",
+            colors.paint(Kind::Warning, "Warning:"),
+            path,
+            capacity,
+        )?;
+    }
+
+    if let Some(area) = &layout.user_area {
+        writeln!(out, "{:#010x}-{:#010x}: {}", area.start, area.end, colors.paint(Kind::UserArea, area.name))?;
+        for path in &layout.files_outside_user_area {
+            writeln!(out, "{} {} is outside of the user area", colors.paint(Kind::Warning, "Warning:"), path)?;
+        }
+    }
+
+    writeln!(
+        out,
+        "{}",
+        colors.paint(Kind::Gap, &format!("Unused: {} bytes in {} gaps", layout.unused_bytes, layout.gaps)),
+    )
+}
+
 pub struct ROMLayout<'a>(Vec<&'a dyn Section>);
 
+// Where a range of the disc is relative to the system data.
+#[derive(Copy, Clone)]
+pub enum RangeClass<'a> {
+    Outside,
+    Inside(&'a dyn Section),
+    // Partly inside of the section, and partly outside of it.
+    Straddles(&'a dyn Section),
+}
+
 impl<'a> ROMLayout<'a> {
     pub fn find_offset(&'a self, offset: u64) -> Option<&'a dyn Section> {
         self.0.binary_search_by(|s| s.compare_offset(offset))
@@ -217,7 +1378,932 @@ impl<'a> ROMLayout<'a> {
             .map(|i| self.0[i])
     }
 
+    // The closest sections ending before `offset` and starting after it,
+    // for describing offsets that aren't in any section. Of the sections
+    // starting before it, the one that ends last counts, since layouts can
+    // overlap. Empty sections are skipped.
+    pub fn neighbors(&self, offset: u64) -> (Option<&'a dyn Section>, Option<&'a dyn Section>) {
+        let mut before: Option<&'a dyn Section> = None;
+        let mut after = None;
+        for &s in self.0.iter().filter(|s| s.size() > 0) {
+            if s.start() > offset {
+                after = Some(s);
+                break
+            }
+            if s.end() < offset && before.is_none_or(|b| s.end() > b.end()) {
+                before = Some(s);
+            }
+        }
+        (before, after)
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // `len` bytes starting at `start`, compared to the system data sections.
+    // Empty ranges don't take up any space, so they're always outside.
+    pub fn classify_range(&self, start: u64, len: u64) -> RangeClass<'a> {
+        let end = start.saturating_add(len);
+        if len == 0 {
+            return RangeClass::Outside;
+        }
+        for &s in self.0.iter().filter(|s| s.is_system_data() && s.size() > 0) {
+            let (s_start, s_end) = (s.start(), s.start() + s.size() as u64);
+            if end <= s_start || start >= s_end {
+                continue
+            }
+            return if start >= s_start && end <= s_end {
+                RangeClass::Inside(s)
+            } else {
+                RangeClass::Straddles(s)
+            };
+        }
+        RangeClass::Outside
+    }
+
+    // In order of where they start.
+    pub fn iter(&self) -> impl Iterator<Item = &'a dyn Section> + '_ {
+        self.0.iter().copied()
+    }
+
+    // Every pair of sections whose data overlaps. Empty sections don't take
+    // up any space on the disc, so they never overlap anything.
+    pub fn overlaps(&self) -> Vec<(&'a dyn Section, &'a dyn Section)> {
+        let mut overlaps = Vec::new();
+        let mut furthest: Option<&'a dyn Section> = None;
+        for &s in self.0.iter().filter(|s| s.size() > 0) {
+            if let Some(f) = furthest {
+                if s.start() <= f.end() {
+                    overlaps.push((f, s));
+                }
+                if s.end() <= f.end() {
+                    continue
+                }
+            }
+            furthest = Some(s);
+        }
+        overlaps
+    }
+
+    // The parts of an image of `image_len` bytes not covered by any section,
+    // as (start, end) pairs where `end` is exclusive.
+    pub fn gaps(&self, image_len: u64) -> Vec<(u64, u64)> {
+        let mut gaps = Vec::new();
+        let mut pos = 0;
+        for s in self.0.iter().filter(|s| s.size() > 0) {
+            if pos >= image_len {
+                break
+            }
+            if s.start() > pos {
+                gaps.push((pos, s.start().min(image_len)));
+            }
+            pos = pos.max(s.end() + 1);
+        }
+        if pos < image_len {
+            gaps.push((pos, image_len));
+        }
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::BufReader, ops::Range};
+
+    use super::*;
+    use crate::{
+        sections::{dol::DOL_HEADER_LEN, fst::{entry::ENTRY_SIZE, handler}, header::HeaderError, Field},
+        synthetic,
+        test_util::{build_image, open_image, synthetic_tree, RecordingSink, TestDir},
+    };
+
+    fn assets(image: &[u8]) -> String {
+        let mut out = Vec::new();
+        open_image(image).write_assets(&mut out, Cursor::new(image), NumberStyle::Decimal).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn unreadable_assets_dont_stop_the_listing() {
+        let tree = synthetic_tree();
+        // A banner that's just its magic and part of its image
+        let mut banner = b"BNR1".to_vec();
+        banner.resize(0x100, 0xaa);
+        fs::write(tree.path().join("opening.bnr"), banner).unwrap();
+        fs::create_dir(tree.path().join("modules")).unwrap();
+        fs::write(tree.path().join("modules/a.rel"), [0, 0, 0, 7, 0, 0, 0, 0]).unwrap();
+        fs::write(tree.path().join("modules/b.rel"), [0, 0]).unwrap();
+        fs::write(tree.path().join("modules/c.rel"), [0, 0, 1, 0]).unwrap();
+        let image = build_image(tree.path());
+
+        let text = assets(&image);
+        assert!(text.contains("Banner: 1 files, 256 bytes\n    /opening.bnr: unreadable ("), "{}", text);
+        assert!(text.contains("Modules: 3 files, 14 bytes\n"), "{}", text);
+        assert!(text.contains("    /modules/a.rel: module 7\n"), "{}", text);
+        assert!(text.contains("    /modules/b.rel: unreadable ("), "{}", text);
+        assert!(text.contains("    /modules/c.rel: module 256\n"), "{}", text);
+    }
+
+    // Fails any read that touches `bad`, like a bad sector on a disc.
+    struct FailingReader {
+        inner: Cursor<Vec<u8>>,
+        bad: Range<u64>,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let pos = self.inner.position();
+            if pos < self.bad.end && self.bad.start < pos + buf.len() as u64 {
+                return Err(io::Error::other("bad sector"));
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for FailingReader {
+        fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(to)
+        }
+    }
+
+    fn failing_at(image: Vec<u8>, bad: Range<u64>) -> BufReader<FailingReader> {
+        BufReader::new(FailingReader { inner: Cursor::new(image), bad })
+    }
+
+    fn extract(image: &[u8], iso: impl BufRead + Seek, keep_going: bool) -> (TestDir, eyre::Result<Vec<ExtractFailure>>) {
+        let dir = TestDir::new();
+        let options = ExtractOptions { keep_going, ..ExtractOptions::default() };
+        let result = open_image(image).extract(iso, dir.join("out"), &options, &Reporter::quiet());
+        (dir, result)
+    }
+
+    #[test]
+    fn keep_going_extracts_the_other_files() {
+        let image = build_image(synthetic_tree().path());
+        let big = open_image(&image).fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .find(|f| f.info.normalized_path() == "/data/big.bin")
+            .unwrap()
+            .file_offset;
+        let bad = big + 0x80000..big + 0x80001;
+
+        let (dir, failures) = extract(&image, failing_at(image.clone(), bad.clone()), true);
+        let failures = failures.unwrap();
+        assert_eq!(failures.len(), 1, "{:?}", failures);
+        assert_eq!((failures[0].path.as_str(), failures[0].offset), ("/data/big.bin", big));
+        assert!(failures[0].error.contains("bad sector"), "{}", failures[0].error);
+        for &(path, size) in synthetic::FILES.iter().filter(|(p, _)| *p != "data/big.bin") {
+            assert!(fs::read(dir.join("out").join(path)).unwrap() == synthetic::contents(size), "{}", path);
+        }
+
+        #[cfg(feature = "serde")]
+        {
+            let errors = fs::read_to_string(dir.join("out").join(EXTRACTION_ERRORS_NAME)).unwrap();
+            let errors: serde_json::Value = serde_json::from_str(&errors).unwrap();
+            assert_eq!(errors[0]["path"], "/data/big.bin");
+            assert_eq!(errors[0]["offset"], big);
+        }
+
+        // Without it, the first failure stops everything
+        let (_dir, result) = extract(&image, failing_at(image.clone(), bad), false);
+        assert!(format!("{:?}", result.unwrap_err()).contains("bad sector"));
+    }
+
+    #[test]
+    fn nothing_failing_writes_no_error_list() {
+        let image = build_image(synthetic_tree().path());
+        let (dir, failures) = extract(&image, Cursor::new(&image), true);
+        assert!(failures.unwrap().is_empty());
+        assert!(!dir.join("out").join(EXTRACTION_ERRORS_NAME).exists());
+    }
+
+    #[test]
+    fn no_assets() {
+        let image = build_image(synthetic_tree().path());
+        assert_eq!(assets(&image), "No known asset files were found.\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn summary_of_the_synthetic_game() {
+        let image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let summary = game.summary();
+        assert_eq!(summary.id, format!("{}{}", synthetic::GAME_CODE, synthetic::MAKER_CODE));
+        assert_eq!(summary.title, synthetic::TITLE);
+        assert_eq!(summary.region, "PAL");
+        assert_eq!((summary.disc, summary.version), (1, 0));
+        assert_eq!(summary.file_count, synthetic::FILES.len());
+        assert_eq!(summary.fs_size, synthetic::FILES.iter().map(|&(_, size)| size).sum::<usize>());
+        assert_eq!(summary.dol_size, game.dol.dol_size);
+
+        // Without parsing the whole FST
+        let fast = Game::open_summary(Cursor::new(&image), 0).unwrap();
+        assert_eq!(serde_json::to_value(&fast).unwrap(), serde_json::to_value(&summary).unwrap());
+    }
+
+    const DOL_OFFSET: usize = 0x420;
+    const FST_OFFSET: usize = 0x424;
+
+    // Why opening fails with the DOL and FST moved, which `open_summary`
+    // has to agree with
+    fn offset_error(image: &[u8], dol: u32, fst: u32) -> HeaderError {
+        let mut image = image.to_vec();
+        image[DOL_OFFSET..DOL_OFFSET + 4].copy_from_slice(&dol.to_be_bytes());
+        image[FST_OFFSET..FST_OFFSET + 4].copy_from_slice(&fst.to_be_bytes());
+        let summary = Game::open_summary(Cursor::new(&image), 0).err().unwrap();
+        let e = Game::open(Cursor::new(&image), 0).err().unwrap();
+        assert_eq!((e.kind(), summary.to_string()), (io::ErrorKind::InvalidData, e.to_string()));
+        *e.into_inner().unwrap().downcast::<HeaderError>().unwrap()
+    }
+
+    #[test]
+    fn offsets_that_would_read_nonsense() {
+        let image = build_image(synthetic_tree().path());
+        let (dol, fst) = (0x3000, 0x2800);
+        assert!(matches!(offset_error(&image, 0, fst), HeaderError::MissingOffset { section: "DOL" }));
+        assert!(matches!(offset_error(&image, dol, 0), HeaderError::MissingOffset { section: "FST" }));
+        assert!(matches!(
+            offset_error(&image, 0x400, fst),
+            HeaderError::OffsetInHeader { section: "DOL", offset: 0x400 },
+        ));
+        assert!(matches!(
+            offset_error(&image, dol, 0x2450),
+            HeaderError::OffsetInApploader { section: "FST", offset: 0x2450, apploader_end: 0x2460 },
+        ));
+        assert!(matches!(offset_error(&image, dol, dol), HeaderError::SharedOffset(0x3000)));
+        let past_end = image.len() as u32 + 0x100;
+        assert!(matches!(offset_error(&image, dol, past_end), HeaderError::OffsetPastEnd { section: "FST", .. }));
+    }
+
+    #[test]
+    fn records_for_listings() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let data = game.fst.entry_for_path("/data").and_then(Entry::as_dir).unwrap();
+        let (records, problems) = game.entry_records(data, false);
+        let paths: Vec<&str> = records.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, ["/data/levels", "/data/big.bin"]);
+        assert!(problems.is_empty());
+        let (records, problems) = game.entry_records(data, true);
+        let paths: Vec<&str> = records.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, ["/data/levels", "/data/levels/2.bin", "/data/levels/1.bin", "/data/big.bin"]);
+        assert!(problems.is_empty());
+        assert_eq!((records[0].kind, records[0].size), ("directory", None));
+        assert_eq!((records[2].kind, records[2].size, records[2].offset), ("file", Some(1000), Some(0x18000)));
+    }
+
+    const APPLOADER_CODE_SIZE: usize = 0x2454;
+
+    // Way too big, and big enough that the code and trailer sizes add up to
+    // less than the code size in 32 bits
+    #[test]
+    fn oversized_apploaders() {
+        let image = build_image(synthetic_tree().path());
+        for (code, trailer) in [(0x7fff_ffffu32, 0u32), (0xffff_fff0, 0x20)] {
+            let mut image = image.clone();
+            image[APPLOADER_CODE_SIZE..APPLOADER_CODE_SIZE + 4].copy_from_slice(&code.to_be_bytes());
+            image[APPLOADER_CODE_SIZE + 4..APPLOADER_CODE_SIZE + 8].copy_from_slice(&trailer.to_be_bytes());
+
+            let game = open_image(&image);
+            let room = game.header.dol_offset.min(game.header.fst_offset) - APPLOADER_OFFSET;
+            let problem = game.apploader.oversized().unwrap();
+            assert_eq!(problem.room as u64, room);
+            assert!(problem.declared as u64 > room, "{:#x}", problem.declared);
+            assert_eq!(game.apploader.size_on_disc() as u64, room);
+            assert!(game.apploader.end() <= game.dol.offset.min(game.fst.offset));
+            // The rest of the image is laid out like it was
+            let gaps = game.rom_layout().gaps(game.capacity);
+            assert!(gaps.iter().any(|&(_, end)| end == game.capacity), "{:x?}", gaps);
+
+            let strict = ParseOptions::default().strict_apploader(true);
+            let e = Game::open_with(Cursor::new(&image), 0, &strict).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(e.to_string(), problem.to_string());
+        }
+        assert!(open_image(&image).apploader.oversized().is_none());
+    }
+
+    #[test]
+    fn files_outside_the_user_area() {
+        let image = build_image(synthetic_tree().path());
+        let mut game = open_image(&image);
+        let big = game.fst.entry_for_path("/data/big.bin").unwrap().as_file().unwrap();
+        let (start, end) = (big.file_offset, big.file_offset + big.size as u64);
+        game.header.set_user_area(start as u32, (end - start) as u32);
+        assert_eq!(game.user_area(), Some((start, end)));
+        let outside: Vec<String> = game.files_outside_user_area().iter().map(|f| f.info.normalized_path()).collect();
+        assert!(!outside.is_empty() && !outside.iter().any(|p| p == "/data/big.bin"), "{:?}", outside);
+
+        let layout = game.layout_info();
+        let area = layout.user_area.unwrap();
+        assert_eq!((area.start, area.end), (start, end));
+        assert_eq!(layout.files_outside_user_area, outside);
+
+        // Either being 0 means there isn't one
+        game.header.set_user_area(start as u32, 0);
+        assert!(game.user_area().is_none() && game.files_outside_user_area().is_empty());
+        game.header.set_user_area(0, 0x1000);
+        assert!(game.layout_info().user_area.is_none());
+    }
+
+    // An image followed by zeros, up to `len` bytes, without keeping them
+    struct Padded {
+        image: Cursor<Vec<u8>>,
+        len: u64,
+        pos: u64,
+    }
+
+    impl Padded {
+        fn new(image: Vec<u8>, len: u64) -> Padded {
+            Padded { image: Cursor::new(image), len, pos: 0 }
+        }
+    }
+
+    impl Read for Padded {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let image_len = self.image.get_ref().len() as u64;
+            let n = if self.pos < image_len {
+                self.image.set_position(self.pos);
+                self.image.read(buf)?
+            } else {
+                let n = buf.len().min((self.len.saturating_sub(self.pos)) as usize);
+                buf[..n].fill(0);
+                n
+            };
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Padded {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(n) => n,
+                SeekFrom::End(n) => self.len.checked_add_signed(n).unwrap(),
+                SeekFrom::Current(n) => self.pos.checked_add_signed(n).unwrap(),
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn capacities_by_name() {
+        assert_eq!(parse_capacity("retail"), Some(ROM_SIZE as u64));
+        assert_eq!(parse_capacity("NR"), Some(NR_DISC_SIZE));
+        assert_eq!(parse_capacity("dual-layer"), Some(DUAL_LAYER_DISC_SIZE));
+        assert_eq!(parse_capacity("0x1000"), Some(0x1000));
+        assert_eq!(parse_capacity("0"), None);
+        assert_eq!(parse_capacity("huge"), None);
+    }
+
+    #[test]
+    fn capacity_is_the_images_size() {
+        let image = build_image(synthetic_tree().path());
+        for len in [NR_DISC_SIZE, ROM_SIZE as u64, DUAL_LAYER_DISC_SIZE] {
+            let game = Game::open(BufReader::new(Padded::new(image.clone(), len)), 0).unwrap();
+            assert_eq!((game.capacity, game.trimmed_to), (len, None), "{:#x}", len);
+        }
+        // Anything else smaller than retail is a trimmed one
+        let game = open_image(&image);
+        assert_eq!((game.capacity, game.trimmed_to), (ROM_SIZE as u64, Some(image.len() as u64)));
+    }
+
+    #[test]
+    fn offsets_past_retail_size() {
+        let image = build_image(synthetic_tree().path());
+        let game = Game::open(BufReader::new(Padded::new(image, DUAL_LAYER_DISC_SIZE)), 0).unwrap();
+        let layout = game.rom_layout();
+        let offset = ROM_SIZE as u64 + 0x1000;
+        assert!(layout.find_offset(offset).is_none());
+        let (before, after) = layout.neighbors(offset);
+        assert_eq!(before.unwrap().name(), "/data/big.bin");
+        assert!(after.is_none());
+        let last_gap = *layout.gaps(game.capacity).last().unwrap();
+        assert_eq!(last_gap.1, DUAL_LAYER_DISC_SIZE);
+        assert!(game.files_past_capacity().is_empty());
+    }
+
+    #[test]
+    fn files_past_the_capacity() {
+        let image = build_image(synthetic_tree().path());
+        let mut game = open_image(&image);
+        let big = game.fst.entry_for_path("/data/big.bin").unwrap().as_file().unwrap();
+        game.capacity = big.file_offset + 1;
+        let past: Vec<String> = game.files_past_capacity().iter().map(|f| f.info.normalized_path()).collect();
+        assert_eq!(past, ["/data/big.bin"]);
+    }
+
+    #[test]
+    fn movies_are_described_without_extracting_them() {
+        use crate::sections::thp::{tests::thp, THP_VERSION_1_0};
+
+        let tree = synthetic_tree();
+        fs::create_dir(tree.join("movies")).unwrap();
+        fs::write(tree.join("movies/intro.thp"), thp(THP_VERSION_1_0, true)).unwrap();
+        // What the header points to isn't in the file
+        fs::write(tree.join("movies/cut.thp"), &thp(THP_VERSION_1_0, true)[..0x30]).unwrap();
+        // Not where a movie would be
+        fs::write(tree.join("data/movie.bin"), thp(THP_VERSION_1_0, true)).unwrap();
+        let image = build_image(tree.path());
+        let game = open_image(&image);
+
+        let assets = assets(&image);
+        assert!(assets.contains("    /movies/intro.thp: 640x480, 29.97 fps, 10.0s, audio\n"), "{}", assets);
+        assert!(assets.contains("    /movies/cut.thp: invalid THP\n"), "{}", assets);
+        let file = |path| game.fst.entry_for_path(path).unwrap().as_file().unwrap();
+        assert!(Game::read_thp_header(file("/data/movie.bin"), Cursor::new(&image)).is_none());
+
+        let mut info = Vec::new();
+        let entry = game.fst.entry_for_path("/movies/intro.thp").unwrap();
+        game.write_entry_info(&mut info, entry, Cursor::new(&image), NumberStyle::Decimal).unwrap();
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("Duration: 10.01s\n") && info.contains("Resolution: 640x480\n"), "{}", info);
+    }
+
+    // The image with /readme.txt's FST entry pointing at the DOL
+    fn readme_in_the_dol(image: &[u8]) -> Vec<u8> {
+        let game = open_image(image);
+        let readme = game.fst.entry_for_path("/readme.txt").unwrap().info().index;
+        let mut image = image.to_vec();
+        let entry = game.fst.offset as usize + readme * ENTRY_SIZE;
+        image[entry + 4..entry + 8].copy_from_slice(&(game.dol.offset as u32).to_be_bytes());
+        image
+    }
+
+    #[test]
+    fn ranges_in_the_system_data() {
+        let image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let layout = game.rom_layout();
+        let name = |class| match class {
+            RangeClass::Outside => "outside".to_owned(),
+            RangeClass::Inside(s) => format!("inside {}", s.name()),
+            RangeClass::Straddles(s) => format!("straddles {}", s.name()),
+        };
+        let header_end = GAME_HEADER_SIZE as u64;
+        assert_eq!(name(layout.classify_range(0x10, 0x20)), "inside ISO.hdr");
+        assert_eq!(name(layout.classify_range(header_end - 0x10, 0x20)), "straddles ISO.hdr");
+        assert_eq!(name(layout.classify_range(header_end, 0x10)), "inside Apploader.ldr");
+        // The DOL's header and each of its segments are sections of their own
+        assert_eq!(name(layout.classify_range(game.dol.offset, DOL_HEADER_LEN as u64)), "inside Start.dol");
+        assert_eq!(name(layout.classify_range(game.dol.offset, game.dol.dol_size as u64)), "straddles Start.dol");
+        let text = game.dol.iter_segments().next().unwrap();
+        assert_eq!(name(layout.classify_range(text.start(), text.size() as u64)), "inside .text0");
+        assert_eq!(name(layout.classify_range(game.fst.offset - 1, 2)), "straddles Game.toc");
+        // Files, and the gaps between the system data, aren't in it
+        let readme = game.fst.entry_for_path("/readme.txt").unwrap().as_file().unwrap();
+        assert_eq!(name(layout.classify_range(readme.file_offset, readme.size as u64)), "outside");
+        let (gap_start, gap_end) = layout.gaps(game.capacity)[0];
+        assert_eq!(name(layout.classify_range(gap_start, gap_end - gap_start)), "outside");
+        assert_eq!(name(layout.classify_range(0, 0)), "outside");
+        assert!(game.files_in_system_data().is_empty());
+    }
+
+    #[test]
+    fn files_in_the_system_data_are_copies_of_it() {
+        let image = readme_in_the_dol(&build_image(synthetic_tree().path()));
+        let mut game = open_image(&image);
+        let found: Vec<(String, String)> = game.files_in_system_data().iter()
+            .map(|(f, section)| (f.info.normalized_path(), section.clone()))
+            .collect();
+        assert_eq!(found, [("/readme.txt".to_owned(), "Start.dol".to_owned())]);
+
+        let (dir, failures) = extract(&image, Cursor::new(&image), false);
+        assert!(failures.unwrap().is_empty());
+        let dol = game.dol.offset as usize;
+        assert!(fs::read(dir.join("out/readme.txt")).unwrap() == image[dol..dol + 64]);
+
+        // Skipped with --strict
+        let dir = TestDir::new();
+        let options = ExtractOptions { strict: true, ..ExtractOptions::default() };
+        let failures = game.extract(Cursor::new(&image), dir.join("out"), &options, &Reporter::quiet()).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].path, "/readme.txt");
+        assert!(failures[0].error.contains("Stored inside of Start.dol"), "{}", failures[0].error);
+        assert!(!dir.join("out/readme.txt").exists());
+        assert!(dir.join("out/data/big.bin").exists());
+    }
+
+    #[test]
+    fn progress_of_an_extraction() {
+        let image = build_image(synthetic_tree().path());
+        let mut game = open_image(&image);
+        let dir = TestDir::new();
+        let sink = RecordingSink::default();
+        let reporter = Reporter::quiet().with_sink(sink.clone());
+        game.extract(Cursor::new(&image), dir.join("out"), &ExtractOptions::default(), &reporter).unwrap();
+        let total = game.fst.total_file_size as u64;
+        assert_eq!(sink.0.borrow().totals, Some((game.fst.file_count as u64, Some(total))));
+        sink.check(game.fst.file_count, total);
+    }
+
+    // Into halves, as "<name>.0" and "<name>.1"
+    fn split(info: &EntryInfo, contents: &mut dyn Read, sink: &mut OutputSink<'_>) -> io::Result<HandlerOutcome> {
+        let mut data = Vec::new();
+        contents.read_to_end(&mut data)?;
+        let (first, second) = data.split_at(data.len() / 2);
+        for (n, half) in [first, second].iter().enumerate() {
+            let mut out = sink(Path::new(&format!("{}.{}", info.name, n)))?;
+            out.write_all(half)?;
+            out.flush()?;
+        }
+        Ok(HandlerOutcome::Handled)
+    }
+
+    #[test]
+    fn extracting_with_handlers() {
+        let tree = synthetic_tree();
+        let split_file: Vec<u8> = (0..200).collect();
+        fs::write(tree.join("data/split.bin"), &split_file).unwrap();
+        let mut rarc = magic::RARC_MAGIC.to_vec();
+        rarc.extend_from_slice(&[1, 2, 3, 4]);
+        let mut yaz0 = magic::YAZ0_MAGIC.to_vec();
+        yaz0.extend_from_slice(&8u32.to_be_bytes());
+        yaz0.extend_from_slice(&[0; 8]);
+        yaz0.push(0xff);
+        yaz0.extend_from_slice(&rarc);
+        fs::write(tree.join("pack.szs"), yaz0).unwrap();
+        fs::write(tree.join("plain.szs"), b"not compressed").unwrap();
+        fs::write(tree.join("escape.bin"), b"").unwrap();
+        let image = build_image(tree.path());
+        let mut game = open_image(&image);
+        let file_count = game.file_count() as u64;
+
+        let dir = TestDir::new();
+        let options = ExtractOptions { keep_going: true, ..ExtractOptions::default() }
+            .with_handler("/data/split.bin", split)
+            .with_handler("*.szs", handler::decompress_yaz0)
+            .with_handler("escape.bin", |_, _, sink| {
+                sink(Path::new("../escaped"))?;
+                Ok(HandlerOutcome::Handled)
+            })
+            // Only the files the ones before didn't match
+            .with_handler("*.bin", |info, _, _| {
+                assert!(info.name != "split.bin" && info.name != "escape.bin", "{}", info.name);
+                Ok(HandlerOutcome::Fallback)
+            });
+        let sink = RecordingSink::default();
+        let reporter = Reporter::quiet().with_sink(sink.clone());
+        let failures = game.extract(Cursor::new(&image), dir.join("out"), &options, &reporter).unwrap();
+
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].path, "/escape.bin");
+        assert!(failures[0].error.contains("outside of its directory"), "{}", failures[0].error);
+        assert!(!dir.join("escaped").exists() && !dir.join("out/escape.bin").exists());
+        assert_eq!(fs::read(dir.join("out/data/split.bin.0")).unwrap(), &split_file[..100]);
+        assert_eq!(fs::read(dir.join("out/data/split.bin.1")).unwrap(), &split_file[100..]);
+        assert!(!dir.join("out/data/split.bin").exists());
+        assert_eq!(fs::read(dir.join("out/pack.arc")).unwrap(), rarc);
+        assert!(!dir.join("out/pack.szs").exists());
+        // Not Yaz0, so it falls back to being copied
+        assert_eq!(fs::read(dir.join("out/plain.szs")).unwrap(), b"not compressed");
+        assert!(fs::read(dir.join("out/data/big.bin")).unwrap() == synthetic::contents(1060921));
+        // Once for each file, however many outputs it has
+        assert_eq!(sink.0.borrow().finished.len() as u64, file_count);
+    }
+
+    // A section with nothing but where it is
+    struct Span(&'static str, u64, usize);
+
+    impl Section for Span {
+        fn fields(&self) -> Vec<Field> {
+            Vec::new()
+        }
+
+        fn name(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn start(&self) -> u64 {
+            self.1
+        }
+
+        fn size(&self) -> usize {
+            self.2
+        }
+    }
+
+    #[test]
+    fn neighbors() {
+        let spans = [Span("a", 0x100, 0x100), Span("empty", 0x280, 0), Span("b", 0x300, 0x80), Span("c", 0x400, 0x10)];
+        let layout = ROMLayout(spans.iter().map(|s| s as &dyn Section).collect());
+        let names = |offset| {
+            let (before, after) = layout.neighbors(offset);
+            (before.map(|s| s.name()), after.map(|s| s.name()))
+        };
+        let name = |n: &str| Some(n.to_owned());
+        assert_eq!(names(0), (None, name("a")));
+        assert_eq!(names(0xff), (None, name("a")));
+        assert_eq!(names(0x200), (name("a"), name("b")));
+        // Empty sections are skipped
+        assert_eq!(names(0x280), (name("a"), name("b")));
+        assert_eq!(names(0x2ff), (name("a"), name("b")));
+        assert_eq!(names(0x380), (name("b"), name("c")));
+        assert_eq!(names(0x410), (name("c"), None));
+        assert_eq!(names(u64::MAX), (name("c"), None));
+    }
+
+    // The one that ends last is the one before
+    #[test]
+    fn neighbors_of_overlapping_sections() {
+        let spans = [Span("long", 0x100, 0x400), Span("inside", 0x200, 0x10), Span("after", 0x800, 0x10)];
+        let layout = ROMLayout(spans.iter().map(|s| s as &dyn Section).collect());
+        let (before, after) = layout.neighbors(0x600);
+        assert_eq!((before.unwrap().name(), after.unwrap().name()), ("long".to_owned(), "after".to_owned()));
+    }
+
+    const BASE: usize = 64 * 1024;
+
+    // Every kind of section `extract_section_with_name` does, from an image
+    // embedded part of the way into a file, comes out the same as from the
+    // image on its own
+    #[test]
+    fn extracting_sections_from_an_embedded_image() {
+        let image = build_image(synthetic_tree().path());
+        // Not zeros, so reading from the start of the file instead of the
+        // image doesn't go unnoticed
+        let mut embedded = vec![0xee; BASE];
+        embedded.extend(&image);
+        let game = open_image(&image);
+        let embedded_game = Game::open(Cursor::new(&embedded), BASE as u64).unwrap();
+        assert_eq!(embedded_game.offset, BASE as u64);
+        assert_eq!(embedded_game.dol.offset, BASE as u64 + game.dol.offset);
+        assert_eq!(embedded_game.fst.offset, BASE as u64 + game.fst.offset);
+
+        let dir = TestDir::new();
+        let sections = [
+            (HEADER_PATH, ExtractedKind::Header),
+            (APPLOADER_PATH, ExtractedKind::Apploader),
+            (DOL_PATH, ExtractedKind::Dol),
+            (FST_PATH, ExtractedKind::Fst),
+            ("/readme.txt", ExtractedKind::File),
+            ("/data/levels", ExtractedKind::Directory),
+            (".text0", ExtractedKind::Segment),
+        ];
+        for (i, (name, kind)) in sections.into_iter().enumerate() {
+            let (plain, from_embedded) = (dir.join(format!("{}-plain", i)), dir.join(format!("{}-embedded", i)));
+            assert_eq!(game.extract_section_with_name(name, &plain, Cursor::new(&image)).unwrap(), kind, "{}", name);
+            assert_eq!(embedded_game.extract_section_with_name(name, &from_embedded, Cursor::new(&embedded)).unwrap(), kind, "{}", name);
+            if kind == ExtractedKind::Directory {
+                for file in ["1.bin", "2.bin"] {
+                    let (a, b) = (fs::read(plain.join(file)).unwrap(), fs::read(from_embedded.join(file)).unwrap());
+                    assert!(a == b, "{}/{}", name, file);
+                }
+            } else {
+                assert!(fs::read(&plain).unwrap() == fs::read(&from_embedded).unwrap(), "{}", name);
+            }
+        }
+        // And they're the image's own bytes
+        assert!(fs::read(dir.join("0-plain")).unwrap() == image[..GAME_HEADER_SIZE]);
+        let fst = &image[game.fst.offset as usize..][..game.header.fst_size];
+        assert!(fs::read(dir.join("3-plain")).unwrap() == fst);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Hint {
+        Sequential,
+        WillNeed(u64, u64),
+        Preallocate(u64),
+    }
+
+    // Each test that records gets its own, since they run at the same time
+    #[derive(Debug)]
+    struct Recorded(std::sync::Mutex<Vec<Hint>>);
+
+    impl Recorded {
+        fn take(&self) -> Vec<Hint> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl FsHints for Recorded {
+        fn sequential(&self, _file: &File) -> io::Result<()> {
+            self.0.lock().unwrap().push(Hint::Sequential);
+            Ok(())
+        }
+
+        fn will_need(&self, _file: &File, offset: u64, len: u64) -> io::Result<()> {
+            self.0.lock().unwrap().push(Hint::WillNeed(offset, len));
+            Ok(())
+        }
+
+        fn preallocate(&self, _file: &File, len: u64) -> io::Result<()> {
+            self.0.lock().unwrap().push(Hint::Preallocate(len));
+            Err(io::Error::other("only a hint"))
+        }
+    }
+
+    fn extract_with_hints(game: &mut Game, image: &[u8], to: &Path, hints: Option<ExtractHints>) -> eyre::Result<usize> {
+        let mut context = ExtractContext { hints, ..ExtractContext::default() };
+        game.fst.extract_file_system(to, Cursor::new(image), &mut context, |_, _, _| {})
+    }
+
+    #[test]
+    fn hints_while_extracting() {
+        static HINTS: Recorded = Recorded(std::sync::Mutex::new(Vec::new()));
+        let image = build_image(synthetic_tree().path());
+        let mut game = open_image(&image);
+        let dir = TestDir::new();
+        fs::write(dir.join("image.iso"), &image).unwrap();
+        let source = File::open(dir.join("image.iso")).unwrap();
+
+        let hints = game.extract_hints(&HINTS, Some(&source));
+        assert_eq!(HINTS.take(), [Hint::Sequential]);
+        let readme = game.fst.entry_for_path("/readme.txt").unwrap().info().index;
+        assert_eq!(hints.next[&readme], (0x10000, 4096));
+        extract_with_hints(&mut game, &image, &dir.join("hinted"), Some(hints)).unwrap();
+
+        // Each file is read ahead of while the one before it is copied, and
+        // gets its size up front. Failing hints don't fail anything.
+        let recorded = HINTS.take();
+        assert!(recorded.contains(&Hint::WillNeed(0x10000, 4096)), "{:?}", recorded);
+        assert!(recorded.contains(&Hint::WillNeed(0x20000, 1060921)), "{:?}", recorded);
+        assert!(recorded.contains(&Hint::Preallocate(1060921)), "{:?}", recorded);
+        let (will_need, preallocate) = (
+            recorded.iter().filter(|h| matches!(h, Hint::WillNeed(..))).count(),
+            recorded.iter().filter(|h| matches!(h, Hint::Preallocate(_))).count(),
+        );
+        assert_eq!((will_need, preallocate), (3, 5), "{:?}", recorded);
+
+        // The same files as without any
+        extract_with_hints(&mut game, &image, &dir.join("plain"), None).unwrap();
+        let hints = ExtractHints { hints: &io_util::NoHints, source: Some(&source), next: HashMap::new() };
+        extract_with_hints(&mut game, &image, &dir.join("none"), Some(hints)).unwrap();
+        for file in ["readme.txt", "data/big.bin", "data/levels/1.bin", "empty.bin"] {
+            let plain = fs::read(dir.join("plain").join(file)).unwrap();
+            assert!(fs::read(dir.join("hinted").join(file)).unwrap() == plain, "{}", file);
+            assert!(fs::read(dir.join("none").join(file)).unwrap() == plain, "{}", file);
+        }
+    }
+
+    #[test]
+    fn nothing_to_read_ahead_of() {
+        static HINTS: Recorded = Recorded(std::sync::Mutex::new(Vec::new()));
+        let image = build_image(synthetic_tree().path());
+        let mut game = open_image(&image);
+        // Without a file to read from
+        assert!(game.extract_hints(&HINTS, None).next.is_empty());
+        assert_eq!(HINTS.take(), []);
+
+        // Or when the files aren't in the order they're extracted in
+        let dir = TestDir::new();
+        fs::write(dir.join("image.iso"), &image).unwrap();
+        let source = File::open(dir.join("image.iso")).unwrap();
+        let index = game.fst.entry_for_path("/readme.txt").unwrap().info().index;
+        game.fst.entry_mut(index).unwrap().as_file_mut().unwrap().file_offset = 0x30000;
+        assert!(game.extract_hints(&HINTS, Some(&source)).next.is_empty());
+        assert_eq!(HINTS.take(), []);
+    }
+
+    #[test]
+    fn listed_paths_for_unnamed_entries() {
+        let mut image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        assert_eq!(game.fst.unnamed_entries().count(), 0);
+        let levels = game.fst.entry_for_path("/data/levels").unwrap().info().index;
+        let name_offset = game.fst.offset as usize
+            + game.fst.entries().len() * ENTRY_SIZE
+            + game.fst.entry(levels).unwrap().info().filename_offset as usize;
+        let end = game.fst.offset as usize + game.header.fst_size;
+        image[name_offset..end].fill(0);
+
+        let game = open_image(&image);
+        let unnamed: Vec<usize> = game.fst.unnamed_entries().map(|e| e.info().index).collect();
+        assert_eq!(unnamed, [5, 6, 7, 8]);
+        let listed = |i| game.listed_path(game.fst.entry(i).unwrap());
+        assert_eq!(listed(4), "/data/");
+        assert_eq!(listed(5), "/data/<unnamed entry #5>/");
+        assert_eq!(listed(6), "/data/<unnamed entry #5>/<unnamed entry #6>");
+        // Only in the listing
+        assert!(game.fst.entries().iter().all(|e| !e.info().name.contains("unnamed")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn splitting_the_dol() {
+        let image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let dir = TestDir::new();
+        let dol_path = dir.join("Start.dol");
+        let mut dol = Vec::new();
+        DOLHeader::extract(&mut Cursor::new(&image), &mut dol, game.dol.offset).unwrap();
+        let (split, difference) = game.split_dol(Cursor::new(&image), &dol_path).unwrap();
+        assert_eq!(split, dir.join("Start.dol.d"));
+        assert_eq!(difference, None);
+        let mut repacked = Vec::new();
+        DOLHeader::pack(&split, &mut repacked).unwrap();
+        assert!(repacked == dol);
+    }
+
+    // Stands in for a destination that fills up after `left` more bytes
+    #[cfg(feature = "serde")]
+    struct FullAfter<W> {
+        inner: W,
+        left: usize,
+    }
+
+    #[cfg(feature = "serde")]
+    impl<W: Write> Write for FullAfter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::ErrorKind::StorageFull.into());
+            }
+            let written = self.inner.write(&buf[..buf.len().min(self.left)])?;
+            self.left -= written;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    // Writes /data/levels/2.bin, the first file after /readme.txt and
+    // /empty.bin, through `FullAfter`
+    #[cfg(feature = "serde")]
+    fn full_after(bytes: usize) -> ExtractOptions {
+        ExtractOptions::default().with_handler("/data/levels/2.bin", move |info: &EntryInfo, contents: &mut dyn Read, sink: &mut OutputSink<'_>| {
+            let mut out = FullAfter { inner: sink(Path::new(&info.name))?, left: bytes };
+            io::copy(contents, &mut out)?;
+            out.flush()?;
+            Ok(HandlerOutcome::Handled)
+        })
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn partial_files_that_stay_are_in_the_manifest() {
+        let image = build_image(synthetic_tree().path());
+        let dir = TestDir::new();
+        let out = dir.join("out");
+        // Something that can't be removed like a file is in its place
+        let stuck = out.join("data/levels/2.bin");
+        let options = ExtractOptions::default().with_handler("/data/levels/2.bin", move |_: &EntryInfo, _: &mut dyn Read, _: &mut OutputSink<'_>| {
+            fs::create_dir_all(stuck.join("in"))?;
+            Err(io::ErrorKind::StorageFull.into())
+        });
+        let e = open_image(&image).extract(Cursor::new(&image), &out, &options, &Reporter::quiet()).unwrap_err();
+        assert!(e.downcast_ref::<DiskFull>().unwrap().left_behind.is_some());
+        let manifest = Manifest::read(out.join(MANIFEST_NAME)).unwrap();
+        let partial = manifest.partial.unwrap();
+        assert_eq!((partial.path.as_str(), partial.output.as_path()), ("/data/levels/2.bin", Path::new("data/levels/2.bin")));
+
+        // Refreshing writes it again, and then it's not partial anymore
+        fs::remove_dir_all(out.join("data/levels/2.bin")).unwrap();
+        let summary = open_image(&image).refresh(Cursor::new(&image), &out, &ExtractOptions::default(), false, &Reporter::quiet()).unwrap();
+        assert_eq!(summary.changed, ["/data/levels/2.bin", "/data/levels/1.bin", "/data/big.bin"]);
+        assert!(fs::read(out.join("data/levels/2.bin")).unwrap() == synthetic::contents(4096));
+        assert!(Manifest::read(out.join(MANIFEST_NAME)).unwrap().partial.is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn running_out_of_space_and_refreshing() {
+        let image = build_image(synthetic_tree().path());
+        let dir = TestDir::new();
+        let out = dir.join("out");
+        let options = ExtractOptions { keep_going: true, ..full_after(100) };
+        let e = open_image(&image).extract(Cursor::new(&image), &out, &options, &Reporter::quiet()).unwrap_err();
+        let full = e.downcast_ref::<DiskFull>().unwrap();
+        assert_eq!(full.path, "/data/levels/2.bin");
+        assert_eq!(full.output, out.join("data/levels/2.bin"));
+        // It and /data/levels/1.bin and /data/big.bin after it
+        assert_eq!(full.bytes_needed, 4096 + 1000 + 1_060_921);
+        assert!(full.left_behind.is_none());
+        assert!(!out.join("data/levels/2.bin").exists() && !out.join("data/levels/1.bin").exists());
+        assert!(fs::read(out.join("readme.txt")).unwrap() == synthetic::contents(64));
+        assert!(!out.join(EXTRACTION_ERRORS_NAME).exists());
+        // What was finished has a hash, and nothing else does
+        let manifest = Manifest::read(out.join(MANIFEST_NAME)).unwrap();
+        let hashed: Vec<&str> = manifest.entries.iter().filter(|e| e.sha1.is_some()).map(|e| e.path.as_str()).collect();
+        assert_eq!(hashed, ["/readme.txt", "/empty.bin"]);
+
+        let refresh = |options: &ExtractOptions| {
+            open_image(&image).refresh(Cursor::new(&image), &out, options, false, &Reporter::quiet())
+        };
+        let summary = refresh(&ExtractOptions::default()).unwrap();
+        assert_eq!(summary.changed, ["/data/levels/2.bin", "/data/levels/1.bin", "/data/big.bin"]);
+        assert_eq!(summary.unchanged, 2);
+        for &(path, size) in synthetic::FILES {
+            assert!(fs::read(out.join(path)).unwrap() == synthetic::contents(size), "{}", path);
+        }
+
+        // Refreshing stops the same way, and picks up again the same way
+        fs::remove_file(out.join("data/levels/2.bin")).unwrap();
+        fs::remove_file(out.join("data/big.bin")).unwrap();
+        let e = refresh(&full_after(4000)).unwrap_err();
+        let full = e.downcast_ref::<DiskFull>().unwrap();
+        assert_eq!(full.bytes_needed, 4096 + 1_060_921);
+        assert!(!out.join("data/levels/2.bin").exists());
+        let summary = refresh(&ExtractOptions::default()).unwrap();
+        assert_eq!(summary.changed, ["/data/levels/2.bin", "/data/big.bin"]);
+        for &(path, size) in synthetic::FILES {
+            assert!(fs::read(out.join(path)).unwrap() == synthetic::contents(size), "{}", path);
+        }
+    }
 }
@@ -0,0 +1,202 @@
+// Stubs of Dolphin's per-game settings files, GameSettings/<ID>.ini, with
+// the ROM's details in comments and empty sections to fill in, and the same
+// details as a wiki infobox.
+//
+// Dolphin looks for a game's settings under its three character ID (for
+// every region), its six character ID, and its six character ID with "r"
+// and the revision after it (for one revision). Every disc of a game has
+// the same ID, so there's no telling them apart by name.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+};
+
+use crate::{build_info, Game};
+
+#[derive(Debug)]
+pub enum IniNameError {
+    // Dolphin's IDs are six letters and digits
+    InvalidGameId(String),
+}
+
+impl fmt::Display for IniNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IniNameError::InvalidGameId(id) =>
+                write!(f, "The game ID {:?} isn't six letters and digits, so Dolphin has no name for its settings", id),
+        }
+    }
+}
+
+impl Error for IniNameError {}
+
+pub fn game_id(game: &Game) -> String {
+    format!("{}{}", game.header.game_code, game.header.maker_code)
+}
+
+// Like "GALE01.ini", or "GALE01r2.ini" for revisions after the first, which
+// only that revision loads.
+pub fn ini_file_name(game_id: &str, revision: u8) -> Result<String, IniNameError> {
+    if game_id.len() != 6 || !game_id.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(IniNameError::InvalidGameId(game_id.to_owned()));
+    }
+    Ok(match revision {
+        0 => format!("{}.ini", game_id),
+        r => format!("{}r{}.ini", game_id, r),
+    })
+}
+
+// Whether Dolphin loads the file called `name` for the game, see the top of
+// the file.
+pub fn ini_applies_to(name: &str, game_id: &str, revision: u8) -> bool {
+    let Some(stem) = name.strip_suffix(".ini") else {
+        return false;
+    };
+    stem == game_id
+        || game_id.get(..3) == Some(stem)
+        || stem.strip_prefix(game_id).and_then(|r| r.strip_prefix('r')) == Some(&revision.to_string())
+}
+
+// The ID at the start of an INI's first line, like "# GALE01 - Super Smash
+// Bros. Melee", which is how Dolphin's own INIs start and `GameIni` writes.
+pub fn embedded_game_id(text: &str) -> Option<&str> {
+    let id = text.lines().next()?.strip_prefix("# ")?.split(' ').next()?;
+    (id.len() == 6 && id.bytes().all(|b| b.is_ascii_alphanumeric())).then_some(id)
+}
+
+#[derive(Debug)]
+pub struct GameIni {
+    pub game_id: String,
+    pub title: String,
+    // Each one is a line, without the "# "
+    pub comments: Vec<String>,
+    // Written as they are, see `gecko::parse_codes`
+    pub gecko: Option<String>,
+}
+
+impl GameIni {
+    // `source` is the ROM's file name, for the comments.
+    pub fn for_game(game: &Game, source: &str) -> GameIni {
+        // The provenance has the apploader's date
+        let comments = vec![
+            game.provenance().to_string(),
+            format!("Revision: {}", game.header.version),
+            format!("Written by gcmod {} ({}) from {}", build_info::VERSION, build_info::GIT_HASH, source),
+        ];
+        GameIni {
+            game_id: game_id(game),
+            title: game.header.title.clone(),
+            comments,
+            gecko: None,
+        }
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "# {} - {}", self.game_id, self.title)?;
+        for comment in &self.comments {
+            writeln!(writer, "# {}", comment)?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "[Core]")?;
+        writeln!(writer, "# Values set here will override the main Dolphin settings.")?;
+        writeln!(writer)?;
+        writeln!(writer, "[Gecko]")?;
+        if let Some(codes) = &self.gecko {
+            writeln!(writer, "{}", codes.trim_end())?;
+        }
+        writer.flush()
+    }
+}
+
+// A MediaWiki infobox with what the ROM says about itself. Fields it
+// doesn't know, like the developer, are left out.
+pub fn write_wiki_infobox(game: &Game, mut writer: impl Write) -> io::Result<()> {
+    let provenance = game.provenance();
+    let mut fields = vec![
+        ("title", game.header.title.clone()),
+        ("gameid", game_id(game)),
+        ("revision", game.header.version.to_string()),
+    ];
+    fields.extend(provenance.publisher.map(|p| ("publisher", p.to_owned())));
+    fields.extend(provenance.region.map(|r| ("region", r.to_owned())));
+    fields.extend(provenance.total_discs.map(|t| ("discs", t.to_string())));
+    fields.extend(provenance.build_date.map(|d| ("builddate", d)));
+
+    writeln!(writer, "{{{{Infobox VG")?;
+    for (name, value) in fields {
+        writeln!(writer, "|{} = {}", name, value)?;
+    }
+    writeln!(writer, "}}}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+
+    #[test]
+    fn file_names_for_each_revision() {
+        assert_eq!(ini_file_name("GALE01", 0).unwrap(), "GALE01.ini");
+        assert_eq!(ini_file_name("GALE01", 2).unwrap(), "GALE01r2.ini");
+        for id in ["GALE0", "GALE011", "GALE-1", "GÄLE1"] {
+            assert!(matches!(ini_file_name(id, 0), Err(IniNameError::InvalidGameId(_))), "{}", id);
+        }
+    }
+
+    #[test]
+    fn names_dolphin_loads() {
+        for name in ["GALE01.ini", "GAL.ini", "GALE01r2.ini"] {
+            assert!(ini_applies_to(name, "GALE01", 2), "{}", name);
+        }
+        for name in ["GALE01r1.ini", "GALE01", "GALP01.ini", "GA.ini", "GALE01r.ini", "gale01.ini"] {
+            assert!(!ini_applies_to(name, "GALE01", 2), "{}", name);
+        }
+        // Revision 0's INI has no suffix
+        assert!(!ini_applies_to("GALE01r0.ini", "GALE01", 1));
+    }
+
+    #[test]
+    fn game_ids_in_existing_inis() {
+        assert_eq!(embedded_game_id("# GALE01 - Super Smash Bros. Melee\n[Core]\n"), Some("GALE01"));
+        assert_eq!(embedded_game_id("# GALE01\n"), Some("GALE01"));
+        for text in ["", "[Core]\n# GALE01 - Melee\n", "#GALE01 - Melee", "# GALE0 - Melee", "# Notes\n"] {
+            assert_eq!(embedded_game_id(text), None, "{:?}", text);
+        }
+    }
+
+    fn written(ini: &GameIni) -> String {
+        let mut out = Vec::new();
+        ini.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writing_a_stub() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let mut ini = GameIni::for_game(&game, "image.iso");
+        let text = written(&ini);
+        assert!(text.starts_with("# GSLF01 - gcmod self-test\n# Built: "), "{}", text);
+        assert!(text.contains("\n# Revision: 0\n# Written by gcmod "), "{}", text);
+        assert!(text.contains(") from image.iso\n\n[Core]\n"), "{}", text);
+        assert!(text.ends_with("\n[Gecko]\n"), "{}", text);
+        assert_eq!(embedded_game_id(&text), Some("GSLF01"));
+
+        // Codes go in unchanged, with the file ending after them
+        ini.gecko = Some("$Code [Me]\n*A note\n04123456 60000000\n\n\n".to_owned());
+        assert!(written(&ini).ends_with("\n[Gecko]\n$Code [Me]\n*A note\n04123456 60000000\n"), "{}", written(&ini));
+    }
+
+    #[test]
+    fn infoboxes_leave_out_what_isnt_known() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let mut out = Vec::new();
+        write_wiki_infobox(&game, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("{{Infobox VG\n|title = gcmod self-test\n|gameid = GSLF01\n|revision = 0\n"), "{}", text);
+        assert!(text.contains("|region = PAL\n"), "{}", text);
+        assert!(!text.contains("builddate") && !text.contains("discs"), "{}", text);
+        assert!(text.ends_with("}}\n"), "{}", text);
+    }
+}
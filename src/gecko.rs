@@ -0,0 +1,135 @@
+// Gecko codes in the text format Dolphin's game INIs use:
+//
+//     $Infinite Health [Author]
+//     *A note about the code
+//     04123456 60000000
+//
+// A line starting with `$` names a code, `*` lines are notes on it, and the
+// lines after it are its code, two hexadecimal words each. This only checks
+// that codes are shaped right, not what they do.
+
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub struct GeckoCode {
+    pub name: String,
+    // From the brackets at the end of the name line
+    pub creator: Option<String>,
+    pub notes: Vec<String>,
+    pub lines: Vec<(u32, u32)>,
+}
+
+// Lines are numbered from 1.
+#[derive(Debug)]
+pub enum CodesError {
+    // Code or notes before the first `$` line
+    OutsideCode { line: usize },
+    EmptyName { line: usize },
+    // Codes go in the [Gecko] section, so they can't start another one
+    SectionHeader { line: usize },
+    InvalidLine { line: usize, text: String },
+    EmptyCode { line: usize, name: String },
+}
+
+impl fmt::Display for CodesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodesError::OutsideCode { line } =>
+                write!(f, "Line {} isn't part of a code, which starts with a line like `$Name`", line),
+            CodesError::EmptyName { line } => write!(f, "The code on line {} doesn't have a name", line),
+            CodesError::SectionHeader { line } =>
+                write!(f, "Line {} starts an INI section, which can't go in the [Gecko] section", line),
+            CodesError::InvalidLine { line, text } =>
+                write!(f, "Line {} isn't two hexadecimal words like `04123456 60000000`: {:?}", line, text),
+            CodesError::EmptyCode { line, name } => write!(f, "The code {:?} on line {} doesn't have any lines", name, line),
+        }
+    }
+}
+
+impl Error for CodesError {}
+
+pub fn parse_codes(text: &str) -> Result<Vec<GeckoCode>, CodesError> {
+    let mut codes: Vec<(usize, GeckoCode)> = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let text = raw.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if text.starts_with('[') {
+            return Err(CodesError::SectionHeader { line });
+        }
+        if let Some(name) = text.strip_prefix('$') {
+            let (name, creator) = match name.trim_end().strip_suffix(']').and_then(|n| n.rsplit_once('[')) {
+                Some((name, creator)) => (name.trim(), Some(creator.trim().to_owned())),
+                None => (name.trim(), None),
+            };
+            if name.is_empty() {
+                return Err(CodesError::EmptyName { line });
+            }
+            codes.push((line, GeckoCode { name: name.to_owned(), creator, notes: Vec::new(), lines: Vec::new() }));
+            continue;
+        }
+        let (_, code) = codes.last_mut().ok_or(CodesError::OutsideCode { line })?;
+        if let Some(note) = text.strip_prefix('*') {
+            code.notes.push(note.to_owned());
+        } else {
+            let words = parse_line(text).ok_or_else(|| CodesError::InvalidLine { line, text: text.to_owned() })?;
+            code.lines.push(words);
+        }
+    }
+    if let Some((line, code)) = codes.iter().find(|(_, c)| c.lines.is_empty()) {
+        return Err(CodesError::EmptyCode { line: *line, name: code.name.clone() });
+    }
+    Ok(codes.into_iter().map(|(_, c)| c).collect())
+}
+
+fn parse_line(text: &str) -> Option<(u32, u32)> {
+    let mut words = text.split_whitespace().map(|w| {
+        (w.len() == 8 && w.bytes().all(|b| b.is_ascii_hexdigit()))
+            .then(|| u32::from_str_radix(w, 16).ok())
+            .flatten()
+    });
+    match (words.next(), words.next(), words.next()) {
+        (Some(Some(a)), Some(Some(b)), None) => Some((a, b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_codes() {
+        let codes = parse_codes(
+            "$Infinite Health [Someone]\n*A note\n*Another\n04123456 60000000\n  C2000000 00000001 \n\n$No Creator\n00000000 FFFFFFFF\n",
+        ).unwrap();
+        assert_eq!(codes.len(), 2);
+        assert_eq!((codes[0].name.as_str(), codes[0].creator.as_deref()), ("Infinite Health", Some("Someone")));
+        assert_eq!(codes[0].notes, ["A note", "Another"]);
+        assert_eq!(codes[0].lines, [(0x04123456, 0x60000000), (0xc2000000, 1)]);
+        assert_eq!((codes[1].name.as_str(), codes[1].creator.as_deref()), ("No Creator", None));
+        assert_eq!(codes[1].lines, [(0, 0xffffffff)]);
+        assert!(parse_codes("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn codes_that_arent_shaped_right() {
+        let error = |text: &str| parse_codes(text).err().unwrap();
+        assert!(matches!(error("04123456 60000000\n"), CodesError::OutsideCode { line: 1 }));
+        assert!(matches!(error("\n*A note\n$Code\n"), CodesError::OutsideCode { line: 2 }));
+        assert!(matches!(error("$ [Someone]\n04123456 60000000\n"), CodesError::EmptyName { line: 1 }));
+        assert!(matches!(error("$Code\n04123456 60000000\n[Core]\n"), CodesError::SectionHeader { line: 3 }));
+        for line in ["0412345 60000000", "04123456 6000000G", "04123456", "04123456 60000000 00000000", "+4123456 60000000"] {
+            match error(&format!("$Code\n{}\n", line)) {
+                CodesError::InvalidLine { line: 2, text } => assert_eq!(text, line),
+                e => panic!("{}: {}", line, e),
+            }
+        }
+        match error("$First\n04123456 60000000\n$Empty\n*Just a note\n$Last\n04123456 60000000\n") {
+            CodesError::EmptyCode { line: 3, name } => assert_eq!(name, "Empty"),
+            e => panic!("{}", e),
+        }
+    }
+}
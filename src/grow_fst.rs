@@ -0,0 +1,375 @@
+// Makes room after the FST for it to grow in place, by raising the header's
+// `max_fst_size` and moving whatever is in the way (usually the DOL) to a
+// gap further on. Retail images have `max_fst_size` equal to the FST's size,
+// so adding a single file to one means rebuilding it.
+//
+// Nothing is written until everything has somewhere to go. Then it's written
+// in an order that leaves a working image at every step: the moved data is
+// copied first, while the old copies are still what the header and FST point
+// to, then the pointers are changed, and only then is the space that was
+// freed zeroed.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::{
+    align,
+    rom_handle::RomWriteHandle,
+    sections::{apploader::{boot_limit_problem, BootLimitProblem}, fst::entry::ENTRY_SIZE, Section},
+    Game,
+    DEFAULT_ALIGNMENT,
+    DEFAULT_SYSTEM_ALIGNMENT,
+    MIN_ALIGNMENT,
+    WRITE_CHUNK_SIZE,
+};
+
+// Where one of the sections in the way goes.
+#[derive(Clone, Debug)]
+pub struct Move {
+    // A file's index in the FST, or None for the DOL
+    pub file_index: Option<usize>,
+    pub name: String,
+    pub from: u64,
+    pub to: u64,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct GrowPlan {
+    pub old_max_fst_size: usize,
+    pub max_fst_size: usize,
+    pub moves: Vec<Move>,
+    // Not too big for the IPL, but close, see `BootLimitProblem::NearLimit`
+    pub boot_warning: Option<BootLimitProblem>,
+}
+
+#[derive(Debug)]
+pub enum GrowError {
+    // Only the DOL and files can move
+    InTheWay { name: String },
+    NoRoom { name: String, size: u64, in_user_area: bool },
+    PastEnd { end: u64, image_len: u64 },
+    BootLimit(BootLimitProblem),
+    // The FST had to be recovered to read it, so entries aren't where their
+    // indices say on the ROM, see `FST::repairs`
+    Repaired,
+}
+
+impl fmt::Display for GrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrowError::InTheWay { name } => write!(f, "{} is right after the FST, and can't be moved", name),
+            GrowError::NoRoom { name, size, in_user_area } => write!(
+                f,
+                "There's no gap {}big enough to move {} ({} bytes) to",
+                if *in_user_area { "in the user area " } else { "on the image " },
+                name, size,
+            ),
+            GrowError::PastEnd { end, image_len } => write!(
+                f,
+                "The FST would have room up to {:#x}, past the end of the image at {:#x}",
+                end, image_len,
+            ),
+            GrowError::BootLimit(p) => write!(f, "{}", p),
+            GrowError::Repaired => f.write_str(
+                "The FST is malformed and had to be repaired to read it, so it can't be changed in place. Extract and rebuild the ROM instead",
+            ),
+        }
+    }
+}
+
+impl Error for GrowError {}
+
+impl GrowPlan {
+    // Works out how to give the FST `headroom` more bytes than it takes up
+    // now, on an image of `image_len` bytes. The plan has no moves when the
+    // header already leaves that much room.
+    pub fn new(game: &Game, image_len: u64, headroom: u64) -> Result<GrowPlan, GrowError> {
+        if !game.fst.repairs.is_empty() {
+            return Err(GrowError::Repaired);
+        }
+        let fst_start = game.fst.offset;
+        let fst_size = game.header.fst_size.max(game.fst.size) as u64;
+        let old_max = game.header.max_fst_size;
+        let max_fst_size = (align(fst_size + headroom, 4) as usize).max(old_max);
+        let room_start = fst_start + fst_size;
+        let room_end = fst_start + max_fst_size as u64;
+        if room_end > image_len {
+            return Err(GrowError::PastEnd { end: room_end, image_len });
+        }
+        let boot_warning = match boot_limit_problem(game.apploader.total_size(), max_fst_size) {
+            Some(p) if p.is_error() => return Err(GrowError::BootLimit(p)),
+            p => p,
+        };
+
+        let layout = game.rom_layout();
+        let mut free: Vec<(u64, u64)> = Vec::new();
+        for (start, end) in layout.gaps(image_len) {
+            // The new room isn't free for anything else
+            if start < room_start.min(end) {
+                free.push((start, room_start.min(end)));
+            }
+            if end > room_end.max(start) {
+                free.push((room_end.max(start), end));
+            }
+        }
+
+        let in_the_way = |start: u64, size: u64| size != 0 && start < room_end && start + size > room_start;
+        let mut moves = Vec::new();
+        if in_the_way(game.dol.offset, game.dol.dol_size as u64) {
+            let dol_size = game.dol.dol_size as u64;
+            moves.push((None, game.dol.name(), game.dol.offset, dol_size, DEFAULT_SYSTEM_ALIGNMENT, None));
+        }
+        // Files in the user area stay in it, see `Game::files_outside_user_area`
+        let user_area = game.user_area().map(|(start, end)| (game.offset + start, game.offset + end));
+        for f in game.fst.entries().iter().filter_map(|e| e.as_file()) {
+            let (offset, size) = (f.file_offset, f.size as u64);
+            if in_the_way(offset, size) {
+                let within = user_area.filter(|&(start, end)| offset >= start && offset + size <= end);
+                moves.push((Some(f.info.index), f.info.normalized_path(), offset, size, DEFAULT_ALIGNMENT, within));
+            }
+        }
+        if room_start < game.apploader.start() + game.apploader.size_on_disc() as u64 {
+            return Err(GrowError::InTheWay { name: "The apploader".to_owned() });
+        }
+
+        let moves = moves.into_iter()
+            .map(|(file_index, name, from, size, alignment, within)| {
+                // Packed tightly if that's the only way it fits
+                let to = take_space(&mut free, size, alignment, within)
+                    .or_else(|| take_space(&mut free, size, MIN_ALIGNMENT, within))
+                    .ok_or_else(|| GrowError::NoRoom { name: name.clone(), size, in_user_area: within.is_some() })?;
+                Ok(Move { file_index, name, from, to, size })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(GrowPlan { old_max_fst_size: old_max, max_fst_size, moves, boot_warning })
+    }
+
+    // Carries out the plan on `iso`, the image it was made for, syncing
+    // between the steps so they reach the disk in order.
+    pub fn apply(&self, game: &Game, iso: &mut RomWriteHandle) -> io::Result<()> {
+        if self.max_fst_size == self.old_max_fst_size {
+            return Ok(());
+        }
+        let mut buf = vec![0; WRITE_CHUNK_SIZE];
+        for m in &self.moves {
+            copy_within(&mut *iso, m.from, m.to, m.size, &mut buf)?;
+        }
+        iso.sync_data()?;
+
+        let mut header = game.header.clone();
+        for m in &self.moves {
+            match m.file_index {
+                // The offset is the second word of the entry
+                Some(index) => {
+                    iso.seek(SeekFrom::Start(game.fst.offset + (index * ENTRY_SIZE) as u64 + 4))?;
+                    iso.write_u32::<BigEndian>((m.to - game.offset) as u32)?;
+                },
+                None => header.dol_offset = m.to - game.offset,
+            }
+        }
+        header.set_layout(header.dol_offset, header.fst_offset, header.fst_size, self.max_fst_size);
+        header.write_changes(&game.header, &mut *iso, 0)?;
+        iso.sync_data()?;
+
+        let fst_size = game.header.fst_size.max(game.fst.size) as u64;
+        let start = game.fst.offset + fst_size;
+        write_zeros_at(&mut *iso, start, self.max_fst_size as u64 - fst_size, &mut buf)?;
+        iso.sync_data()
+    }
+}
+
+// Takes `size` bytes starting at a multiple of `alignment` from the first of
+// `free` with room for them, and inside of `within` if it's given.
+pub(crate) fn take_space(free: &mut Vec<(u64, u64)>, size: u64, alignment: u64, within: Option<(u64, u64)>) -> Option<u64> {
+    let (low, high) = within.unwrap_or((0, u64::MAX));
+    let (i, start) = free.iter().enumerate().find_map(|(i, &(start, end))| {
+        let aligned = align(start.max(low), alignment);
+        (aligned + size <= end.min(high)).then_some((i, aligned))
+    })?;
+    let (gap_start, gap_end) = free.remove(i);
+    if start + size < gap_end {
+        free.insert(i, (start + size, gap_end));
+    }
+    if gap_start < start {
+        free.insert(i, (gap_start, start));
+    }
+    Some(start)
+}
+
+fn copy_within(mut iso: impl Read + Write + Seek, from: u64, to: u64, size: u64, buf: &mut [u8]) -> io::Result<()> {
+    let mut done = 0;
+    while done < size {
+        let n = (size - done).min(buf.len() as u64) as usize;
+        iso.seek(SeekFrom::Start(from + done))?;
+        iso.read_exact(&mut buf[..n])?;
+        iso.seek(SeekFrom::Start(to + done))?;
+        iso.write_all(&buf[..n])?;
+        done += n as u64;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_zeros_at(mut iso: impl Write + Seek, start: u64, size: u64, buf: &mut [u8]) -> io::Result<()> {
+    buf.fill(0);
+    iso.seek(SeekFrom::Start(start))?;
+    let mut left = size;
+    while left > 0 {
+        let n = left.min(buf.len() as u64) as usize;
+        iso.write_all(&buf[..n])?;
+        left -= n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::{BufReader, Cursor}};
+
+    use super::*;
+    use crate::{
+        sections::apploader::BOOT_AREA_SIZE,
+        synthetic,
+        test_util::{build_image, open_image, synthetic_tree, TestDir},
+    };
+
+    fn image() -> Vec<u8> {
+        build_image(synthetic_tree().path())
+    }
+
+    fn moved(plan: &GrowPlan) -> Vec<&str> {
+        plan.moves.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn taking_space() {
+        let mut free = vec![(0x100, 0x180), (0x1000, 0x3000)];
+        assert_eq!(take_space(&mut free, 0x40, 0x100, None), Some(0x100));
+        assert_eq!(free, [(0x140, 0x180), (0x1000, 0x3000)]);
+        // Too big for what's left of the first, then aligned in the second
+        assert_eq!(take_space(&mut free, 0x800, 0x800, None), Some(0x1000));
+        assert_eq!(take_space(&mut free, 0x10, 0x800, None), Some(0x1800));
+        assert_eq!(free, [(0x140, 0x180), (0x1810, 0x3000)]);
+        assert_eq!(take_space(&mut free, 0x10, 4, Some((0x2000, 0x2010))), Some(0x2000));
+        assert_eq!(free, [(0x140, 0x180), (0x1810, 0x2000), (0x2010, 0x3000)]);
+        assert_eq!(take_space(&mut free, 0x10, 4, Some((0x2000, 0x2010))), None);
+        assert_eq!(take_space(&mut free, 0x2000, 4, None), None);
+    }
+
+    #[test]
+    fn what_has_to_move() {
+        let image = image();
+        let game = open_image(&image);
+        let len = image.len() as u64;
+        let fst_end = game.fst.offset + game.fst.size as u64;
+
+        // Up to the DOL
+        let plan = GrowPlan::new(&game, len, 0x3000 - fst_end - 0x10).unwrap();
+        assert!(plan.moves.is_empty());
+        assert_eq!(plan.old_max_fst_size, game.fst.size);
+        assert_eq!(plan.max_fst_size, align(0x3000 - game.fst.offset - 0x10, 4) as usize);
+
+        let plan = GrowPlan::new(&game, len, 0x1000).unwrap();
+        assert_eq!(moved(&plan), [game.dol.name()]);
+        // Past the first two files, but not the rest
+        let plan = GrowPlan::new(&game, len, 0x10000).unwrap();
+        assert_eq!(moved(&plan), [game.dol.name(), "/readme.txt".to_owned(), "/data/levels/2.bin".to_owned()]);
+        let room = (game.fst.offset, game.fst.offset + plan.max_fst_size as u64);
+        let (user_start, user_end) = game.user_area().unwrap();
+        for m in &plan.moves {
+            assert!(m.to >= room.1 || m.to + m.size <= room.0, "{:?}", m);
+            if m.file_index.is_some() {
+                assert!(m.to >= user_start && m.to + m.size <= user_end, "{:?}", m);
+            }
+        }
+        for pair in plan.moves.windows(2) {
+            assert!(pair[0].to + pair[0].size <= pair[1].to || pair[1].to + pair[1].size <= pair[0].to, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn room_that_cant_be_made() {
+        let image = image();
+        let game = open_image(&image);
+        let len = image.len() as u64;
+        assert!(matches!(GrowPlan::new(&game, len, len), Err(GrowError::PastEnd { .. })));
+        let e = GrowPlan::new(&game, 4 * len, BOOT_AREA_SIZE as u64).unwrap_err();
+        assert!(matches!(e, GrowError::BootLimit(_)), "{}", e);
+        // The biggest file has nowhere to go in the user area
+        match GrowPlan::new(&game, len, 0x20000).unwrap_err() {
+            GrowError::NoRoom { name, in_user_area: true, .. } => assert_eq!(name, "/data/big.bin"),
+            e => panic!("{}", e),
+        }
+        assert!(GrowPlan::new(&game, len, 0x1000).unwrap().boot_warning.is_none());
+    }
+
+    // `fst` with `count` more empty files in the root, each with a copy of
+    // `template`'s entry under a new name
+    fn with_more_files(fst: &[u8], template: usize, count: usize) -> Vec<u8> {
+        let entries = u32::from_be_bytes(fst[8..12].try_into().unwrap()) as usize;
+        let (table, names) = fst.split_at(entries * ENTRY_SIZE);
+        let (mut table, mut names) = (table.to_vec(), names.to_vec());
+        table[8..12].copy_from_slice(&((entries + count) as u32).to_be_bytes());
+        for i in 0..count {
+            let mut entry = table[template * ENTRY_SIZE..][..ENTRY_SIZE].to_vec();
+            entry[1..4].copy_from_slice(&(names.len() as u32).to_be_bytes()[1..]);
+            table.extend(entry);
+            names.extend(format!("extra{:04}.bin\0", i).as_bytes());
+        }
+        [table, names].concat()
+    }
+
+    #[test]
+    fn growing_keeps_everything_readable() {
+        let before = image();
+        let dir = TestDir::new();
+        let path = dir.join("image.iso");
+        fs::write(&path, &before).unwrap();
+        let mut iso = RomWriteHandle::open_for_modification(&path, fs::OpenOptions::new().read(true)).unwrap();
+        let game = Game::open(BufReader::new(&mut iso), 0).unwrap();
+        let plan = GrowPlan::new(&game, before.len() as u64, 0x10000).unwrap();
+        plan.apply(&game, &mut iso).unwrap();
+
+        // Then the FST gets bigger than there was room for before, in place
+        let grown = Game::open(BufReader::new(&mut iso), 0).unwrap();
+        let mut fst = vec![0; grown.fst.size];
+        iso.seek(SeekFrom::Start(grown.fst.offset)).unwrap();
+        iso.read_exact(&mut fst).unwrap();
+        let empty = grown.fst.entry_for_path("/empty.bin").unwrap().info().index;
+        let bigger = with_more_files(&fst, empty, 1000);
+        assert!(bigger.len() > plan.old_max_fst_size && bigger.len() <= plan.max_fst_size, "{:#x}", bigger.len());
+        iso.seek(SeekFrom::Start(grown.fst.offset)).unwrap();
+        iso.write_all(&bigger).unwrap();
+        let mut header = grown.header.clone();
+        header.set_layout(header.dol_offset, header.fst_offset, bigger.len(), header.max_fst_size);
+        header.write_changes(&grown.header, &mut iso, 0).unwrap();
+        drop(iso);
+
+        let after = fs::read(&path).unwrap();
+        let grown = open_image(&after);
+        assert_eq!(grown.header.max_fst_size, plan.max_fst_size);
+        assert!(grown.fst.repairs.is_empty());
+        let old = |offset: u64, size: u64| &before[offset as usize..][..size as usize];
+        let new = |offset: u64, size: u64| &after[offset as usize..][..size as usize];
+        assert!(new(grown.dol.offset, grown.dol.dol_size as u64) == old(game.dol.offset, game.dol.dol_size as u64));
+        for (a, b) in game.fst.entries().iter().zip(grown.fst.entries()) {
+            if let (Some(a), Some(b)) = (a.as_file(), b.as_file()) {
+                assert!(new(b.file_offset, b.size as u64) == old(a.file_offset, a.size as u64), "{}", a.info.normalized_path());
+            }
+        }
+        // What's left of the new room is zeros, and nothing's in it
+        let fst_end = grown.fst.offset + grown.fst.size as u64;
+        assert_eq!(grown.fst.size, grown.header.fst_size);
+        assert!(new(fst_end, plan.max_fst_size as u64 - grown.fst.size as u64).iter().all(|&b| b == 0));
+        assert!(grown.rom_layout().overlaps().is_empty());
+        let fst = &Game::open(Cursor::new(&after), 0).unwrap().fst;
+        assert_eq!(fst.entries().len(), synthetic::FILES.len() + 4 + 1000);
+        assert!(fst.entry_for_path("/extra0999.bin").unwrap().is_file());
+        assert!(fst.strict_validate(new(fst.offset, fst.size as u64)).is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::{self, Read, Write};
+
+// The standard CRC-32 (IEEE, reflected), as used by redump.
+pub struct Crc32 {
+    table: [u32; 256],
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        Crc32 { table, value: 0xffffffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.value = self.table[((self.value ^ b as u32) & 0xff) as usize] ^ (self.value >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.value ^ 0xffffffff
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+// Passes everything through to `inner` while keeping a CRC-32 of it.
+pub struct CrcWriter<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> CrcWriter<W> {
+    pub fn new(inner: W) -> CrcWriter<W> {
+        CrcWriter { inner, crc: Crc32::new() }
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc.finish()
+    }
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// SHA-1, for the checksums mod distributions usually list.
+pub struct Sha1 {
+    state: [u32; 5],
+    // Bytes that don't fill a block yet
+    buffer: [u8; 64],
+    buffered: usize,
+    length: u64,
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            buffer: [0; 64],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if self.buffered > 0 {
+            let n = data.len().min(64 - self.buffered);
+            self.buffer[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+            if self.buffered < 64 {
+                return;
+            }
+            let block = self.buffer;
+            self.compress(&block);
+            self.buffered = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block.try_into().unwrap());
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let bit_length = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80];
+        padding.resize(1 + (119 - self.buffered) % 64, 0);
+        padding.extend_from_slice(&bit_length.to_be_bytes());
+        self.update(&padding);
+
+        let mut digest = [0; 20];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Sha1 {
+        Sha1::new()
+    }
+}
+
+// Passes everything through to `inner` while keeping a SHA-1 of it, so the
+// output's hash is ready as soon as it's written.
+pub struct Sha1Writer<W> {
+    inner: W,
+    sha1: Sha1,
+}
+
+impl<W: Write> Sha1Writer<W> {
+    pub fn new(inner: W) -> Sha1Writer<W> {
+        Sha1Writer { inner, sha1: Sha1::new() }
+    }
+
+    pub fn finish(self) -> (W, [u8; 20]) {
+        (self.inner, self.sha1.finish())
+    }
+}
+
+impl<W: Write> Write for Sha1Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.sha1.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// The SHA-1 of everything in `reader`.
+pub fn sha1_of(mut reader: impl Read) -> io::Result<[u8; 20]> {
+    let mut out = Sha1Writer::new(io::sink());
+    io::copy(&mut reader, &mut out)?;
+    Ok(out.finish().1)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The inverse of `to_hex`, or `None` if `s` isn't hex.
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf43926);
+        let mut writer = CrcWriter::new(io::sink());
+        writer.write_all(b"123456789").unwrap();
+        assert_eq!(writer.crc(), 0xcbf43926);
+    }
+
+    #[test]
+    fn sha1_known_values() {
+        assert_eq!(to_hex(&Sha1::new().finish()), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(to_hex(&sha1_of(&b"abc"[..]).unwrap()), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // Across the 64 byte block, in pieces
+        let text = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let mut sha1 = Sha1::new();
+        sha1.update(&text[..10]);
+        sha1.update(&text[10..]);
+        assert_eq!(to_hex(&sha1.finish()), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn sha1_writer_passes_everything_through() {
+        let mut writer = Sha1Writer::new(Vec::new());
+        // In odd sized writes, so the blocks are split every which way
+        for _ in 0..1000 {
+            writer.write_all(&[b'a'; 999]).unwrap();
+        }
+        writer.write_all(&[b'a'; 1000]).unwrap();
+        let (written, sha1) = writer.finish();
+        assert!(written.len() == 1_000_000 && written.iter().all(|&b| b == b'a'));
+        assert_eq!(to_hex(&sha1), "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+        assert_eq!(sha1, sha1_of(&written[..]).unwrap());
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(from_hex(&to_hex(&[0, 0xab, 0xff])), Some(vec![0, 0xab, 0xff]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
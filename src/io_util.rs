@@ -0,0 +1,1087 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+const PROBE_NAME: &str = ".gcmod-write-probe";
+const CASE_PROBE_NAME: &str = ".gcmod-case-probe";
+
+#[derive(Debug)]
+pub enum DestinationError {
+    NotWritable { path: PathBuf, source: io::Error },
+    InsufficientSpace { path: PathBuf, needed: u64, available: u64 },
+    IsFile(PathBuf),
+    NotEmpty(PathBuf),
+    ParentMissing(PathBuf),
+}
+
+impl fmt::Display for DestinationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DestinationError::NotWritable { path, .. } =>
+                write!(f, "Destination {} is not writable", path.display()),
+            DestinationError::InsufficientSpace { path, needed, available } =>
+                write!(
+                    f,
+                    "Insufficient free space in {} ({} bytes needed, {} bytes available)",
+                    path.display(), needed, available,
+                ),
+            DestinationError::IsFile(path) =>
+                write!(f, "Destination {} already exists and is a file", path.display()),
+            DestinationError::NotEmpty(path) => write!(
+                f,
+                "Destination {} already exists and isn't empty, pass --force to extract into it anyway",
+                path.display(),
+            ),
+            DestinationError::ParentMissing(path) =>
+                write!(f, "The directory {} doesn't exist", path.display()),
+        }
+    }
+}
+
+impl Error for DestinationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DestinationError::NotWritable { source, .. } => Some(source),
+            DestinationError::InsufficientSpace { .. }
+                | DestinationError::IsFile(_)
+                | DestinationError::NotEmpty(_)
+                | DestinationError::ParentMissing(_) => None,
+        }
+    }
+}
+
+// Extracting stopped because the destination filled up partway through.
+// The file that was being written is removed, or if it can't be, the
+// manifest says it's partial, so nothing in the tree is cut short without
+// saying so.
+#[derive(Debug)]
+pub struct DiskFull {
+    // On the ROM, normalized
+    pub path: String,
+    pub output: PathBuf,
+    // What the files that weren't written yet take up, this one included
+    pub bytes_needed: u64,
+    pub available: Option<u64>,
+    // Removing what was written of `output` failed too
+    pub left_behind: Option<io::Error>,
+}
+
+impl fmt::Display for DiskFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Ran out of space writing {} to {}, {} more bytes are needed to finish",
+            self.path, self.output.display(), self.bytes_needed,
+        )?;
+        if let Some(available) = self.available {
+            write!(f, " ({} bytes available)", available)?;
+        }
+        if let Some(e) = &self.left_behind {
+            write!(f, ", and the partly written file couldn't be removed ({})", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DiskFull {}
+
+// Whether `e` is the file system or a quota running out of space, which is
+// ENOSPC or EDQUOT on Unix and ERROR_DISK_FULL on Windows.
+pub fn is_disk_full(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded)
+}
+
+// The directory something at `path` would be created in.
+pub fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+// Makes sure a directory can be extracted to `path`. It can either not exist
+// yet, in a directory that does, or be an empty directory. With `force` it
+// can be a directory with anything in it. Returns the closest directory that
+// exists, for checking the other things about the destination.
+pub fn check_destination_dir(path: &Path, force: bool) -> Result<&Path, DestinationError> {
+    match fs::metadata(path) {
+        Ok(m) if !m.is_dir() => Err(DestinationError::IsFile(path.to_owned())),
+        Ok(_) => {
+            let empty = fs::read_dir(path)
+                .map(|mut entries| entries.next().is_none())
+                .map_err(|source| DestinationError::NotWritable { path: path.to_owned(), source })?;
+            if empty || force {
+                Ok(path)
+            } else {
+                Err(DestinationError::NotEmpty(path.to_owned()))
+            }
+        },
+        Err(_) => {
+            let parent = parent_dir(path);
+            if parent.is_dir() {
+                Ok(parent)
+            } else {
+                Err(DestinationError::ParentMissing(parent.to_owned()))
+            }
+        },
+    }
+}
+
+// Makes sure files can be created in `dir` by creating and removing one.
+pub fn check_writable(dir: impl AsRef<Path>) -> Result<(), DestinationError> {
+    let dir = dir.as_ref();
+    let probe = dir.join(PROBE_NAME);
+    File::create(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|source| DestinationError::NotWritable { path: dir.to_owned(), source })
+}
+
+// Makes a file in `dir`, then tries to make the same name in upper case,
+// which only works if they're different files.
+pub fn is_case_sensitive(dir: impl AsRef<Path>) -> io::Result<bool> {
+    let lower = dir.as_ref().join(CASE_PROBE_NAME);
+    let upper = dir.as_ref().join(CASE_PROBE_NAME.to_uppercase());
+    OpenOptions::new().write(true).create_new(true).open(&lower)?;
+    let result = match OpenOptions::new().write(true).create_new(true).open(&upper) {
+        Ok(_) => {
+            let _ = fs::remove_file(&upper);
+            Ok(true)
+        },
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e),
+    };
+    let _ = fs::remove_file(&lower);
+    result
+}
+
+// Makes sure there are at least `needed` bytes free on the file system `dir`
+// is on. If there's no way to tell, this always passes.
+pub fn check_free_space(dir: impl AsRef<Path>, needed: u64) -> Result<(), DestinationError> {
+    let dir = dir.as_ref();
+    match available_space(dir) {
+        Some(available) if available < needed => Err(DestinationError::InsufficientSpace {
+            path: dir.to_owned(),
+            needed,
+            available,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+pub fn available_space(dir: impl AsRef<Path>) -> Option<u64> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_ref().as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stat` is only read if the call succeeds.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_dir: impl AsRef<Path>) -> Option<u64> {
+    None
+}
+
+// Renames `from` to `to`, falling back to copying and removing `from` when
+// they're on different file systems. Returns whether it had to fall back.
+pub fn rename_or_copy(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<bool> {
+    rename_or_copy_with(from.as_ref(), to.as_ref(), |from, to| fs::rename(from, to))
+}
+
+// `rename_or_copy` with `rename` in place of `fs::rename`, so tests can have
+// it cross file systems without there being two of them.
+fn rename_or_copy_with(
+    from: &Path,
+    to: &Path,
+    rename: impl FnOnce(&Path, &Path) -> io::Result<()>,
+) -> io::Result<bool> {
+    match rename(from, to) {
+        Ok(()) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            // Never leaves part of a copy at `to`
+            if let Err(e) = fs::copy(from, to) {
+                let _ = fs::remove_file(to);
+                return Err(e);
+            }
+            fs::remove_file(from)?;
+            Ok(true)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+// Waits for the entries in `dir`, like a file just renamed into it, to be on
+// the disk. Returns false on platforms and file systems that can't sync a
+// directory.
+#[cfg(unix)]
+pub fn sync_dir(dir: impl AsRef<Path>) -> io::Result<bool> {
+    match File::open(dir)?.sync_all() {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput || e.kind() == io::ErrorKind::Unsupported => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn sync_dir(_dir: impl AsRef<Path>) -> io::Result<bool> {
+    Ok(false)
+}
+
+// How much a new file waits to be on the disk before `finish_file` is done
+// with it, for `rebuild --sync`.
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum SyncMode {
+    None,
+    // The file's contents, before it's renamed into place
+    Data,
+    // And the directory it's in after, so the rename is too
+    Full,
+}
+
+// The calls `finish_file` makes, so tests can see them.
+pub trait FileOps {
+    fn sync_data(&self, file: &File) -> io::Result<()>;
+    // See `rename_or_copy`
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<bool>;
+    // See `sync_dir`
+    fn sync_dir(&self, dir: &Path) -> io::Result<bool>;
+}
+
+#[derive(Debug)]
+pub struct RealFileOps;
+
+impl FileOps for RealFileOps {
+    fn sync_data(&self, file: &File) -> io::Result<()> {
+        file.sync_data()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<bool> {
+        rename_or_copy(from, to)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<bool> {
+        sync_dir(dir)
+    }
+}
+
+// How `finish_file` got the file into place.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Finished {
+    // False if the directory should have been synced but can't be on this
+    // platform.
+    pub dir_synced: bool,
+    // The temporary file was on a different file system, so it was copied
+    // instead of renamed.
+    pub copied: bool,
+}
+
+// Which step of `finish_file` failed.
+#[derive(Debug)]
+pub enum FinishError {
+    Sync(io::Error),
+    Rename(io::Error),
+    SyncDir(io::Error),
+}
+
+impl fmt::Display for FinishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FinishError::Sync(e) => write!(f, "Couldn't sync the file: {}", e),
+            FinishError::Rename(e) => write!(f, "Couldn't move the file into place: {}", e),
+            FinishError::SyncDir(e) => write!(f, "Couldn't sync the file's directory: {}", e),
+        }
+    }
+}
+
+impl Error for FinishError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FinishError::Sync(e) | FinishError::Rename(e) | FinishError::SyncDir(e) => Some(e),
+        }
+    }
+}
+
+// Moves `file`, written in full at `temp`, to `path`, syncing as much as
+// `mode` says. `temp` should be next to `path`, so the rename doesn't have
+// to copy, but it's copied if it turns out to be on another file system.
+// It's removed if the file doesn't make it to `path`.
+pub fn finish_file(
+    file: File,
+    temp: &Path,
+    path: &Path,
+    mode: SyncMode,
+    ops: &dyn FileOps,
+) -> Result<Finished, FinishError> {
+    // Before the rename, so there's never a file at `path` that isn't all
+    // there
+    if mode >= SyncMode::Data {
+        if let Err(e) = ops.sync_data(&file) {
+            let _ = fs::remove_file(temp);
+            return Err(FinishError::Sync(e));
+        }
+    }
+    // Some platforms can't rename a file that's still open
+    drop(file);
+    let copied = match ops.rename(temp, path) {
+        Ok(copied) => copied,
+        Err(e) => {
+            let _ = fs::remove_file(temp);
+            return Err(FinishError::Rename(e));
+        },
+    };
+    // The copy is a different file from the one that was synced
+    if copied && mode >= SyncMode::Data {
+        File::open(path).and_then(|copy| ops.sync_data(&copy)).map_err(FinishError::Sync)?;
+    }
+    let dir_synced = match mode {
+        SyncMode::Full => ops.sync_dir(parent_dir(path)).map_err(FinishError::SyncDir)?,
+        _ => true,
+    };
+    Ok(Finished { dir_synced, copied })
+}
+
+// A reader that acts like it goes on for `len` bytes, reading zeros past the
+// end of `inner`. For trimmed images, which had the padding at the end of
+// the disc cut off.
+#[derive(Debug)]
+pub struct ZeroExtended<R> {
+    inner: R,
+    // Where `inner` ends
+    inner_len: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> ZeroExtended<R> {
+    // Starts out exactly as long as `inner`, see `extend_to`.
+    pub fn new(mut inner: R) -> io::Result<ZeroExtended<R>> {
+        let inner_len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        Ok(ZeroExtended { inner, inner_len, len: inner_len, pos: 0 })
+    }
+}
+
+impl<R> ZeroExtended<R> {
+    // Never makes it shorter than `inner`.
+    pub fn extend_to(&mut self, len: u64) {
+        self.len = len.max(self.inner_len);
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Read for ZeroExtended<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = if self.pos < self.inner_len {
+            let n = buf.len().min((self.inner_len - self.pos) as usize);
+            self.inner.read(&mut buf[..n])?
+        } else {
+            let n = buf.len().min(self.len.saturating_sub(self.pos) as usize);
+            buf[..n].fill(0);
+            n
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for ZeroExtended<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        }.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative position"))?;
+        // Reads past the end of `inner` don't touch it
+        self.inner.seek(SeekFrom::Start(pos.min(self.inner_len)))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+// A view of an image that starts `base` bytes into `inner`, so offsets from
+// the image itself, like files' offsets in the FST, can be used as they are.
+#[derive(Debug)]
+pub struct Embedded<R> {
+    inner: R,
+    base: u64,
+}
+
+impl<R> Embedded<R> {
+    pub fn new(inner: R, base: u64) -> Embedded<R> {
+        Embedded { inner, base }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Embedded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: BufRead> BufRead for Embedded<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+impl<R: Seek> Seek for Embedded<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => SeekFrom::Start(self.base + p),
+            other => other,
+        };
+        let pos = self.inner.seek(pos)?;
+        pos.checked_sub(self.base)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to before the start of the image"))
+    }
+}
+
+// How `extract --dedup` stores files with the same contents as one that was
+// already extracted.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum DedupMode {
+    #[default]
+    Off,
+    Hardlink,
+    Reflink,
+}
+
+impl DedupMode {
+    pub fn parse(s: &str) -> Option<DedupMode> {
+        match s {
+            "off" => Some(DedupMode::Off),
+            "hardlink" => Some(DedupMode::Hardlink),
+            "reflink" => Some(DedupMode::Reflink),
+            _ => None,
+        }
+    }
+}
+
+// What `link_duplicate` ended up doing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum LinkKind {
+    Reflink,
+    Hardlink,
+    Copy,
+}
+
+// Like `File::create`, but replaces whatever's at `path` instead of writing
+// into it, so files hard linked to it are left alone.
+pub fn replace_file(path: impl AsRef<Path>) -> io::Result<File> {
+    let path = path.as_ref();
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    File::create(path)
+}
+
+// Makes `to` a copy of `from` that shares its storage if possible. Reflinks
+// fall back to hard links, and hard links to plain copies, when the file
+// system doesn't support them. Anything already at `to` is replaced.
+pub fn link_duplicate(from: impl AsRef<Path>, to: impl AsRef<Path>, mode: DedupMode) -> io::Result<LinkKind> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    match fs::remove_file(to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    if mode == DedupMode::Reflink && reflink(from, to).is_ok() {
+        return Ok(LinkKind::Reflink);
+    }
+    if mode != DedupMode::Off && fs::hard_link(from, to).is_ok() {
+        return Ok(LinkKind::Hardlink);
+    }
+    fs::copy(from, to)?;
+    Ok(LinkKind::Copy)
+}
+
+// A copy-on-write clone, for file systems like Btrfs and XFS.
+#[cfg(target_os = "linux")]
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let source = File::open(from)?;
+    let dest = File::create(to)?;
+    // SAFETY: both descriptors stay open for the length of the call.
+    let result = unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) };
+    if result == 0 {
+        Ok(())
+    } else {
+        let error = io::Error::last_os_error();
+        drop(dest);
+        let _ = fs::remove_file(to);
+        Err(error)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Reflinks aren't supported on this platform"))
+}
+
+// Tells the OS how files are about to be used, so it can read ahead, or
+// keep a file it's writing in one piece. They're only hints, so platforms
+// and file systems without them succeed without doing anything.
+pub trait FsHints: fmt::Debug {
+    // `file` is about to be read in order.
+    fn sequential(&self, file: &File) -> io::Result<()>;
+    // The `len` bytes at `offset` in `file` are about to be read.
+    fn will_need(&self, file: &File, offset: u64, len: u64) -> io::Result<()>;
+    // `file`, which was just created, is about to be `len` bytes long.
+    fn preallocate(&self, file: &File, len: u64) -> io::Result<()>;
+}
+
+// The hints this platform has, which are only Linux's for now.
+pub fn fs_hints() -> &'static dyn FsHints {
+    #[cfg(target_os = "linux")]
+    return &fadvise::Fadvise;
+    #[cfg(not(target_os = "linux"))]
+    return &NoHints;
+}
+
+// For platforms without any hints, or to turn them off.
+#[derive(Debug)]
+pub struct NoHints;
+
+impl FsHints for NoHints {
+    fn sequential(&self, _file: &File) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn will_need(&self, _file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn preallocate(&self, _file: &File, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod fadvise {
+    use std::{fs::File, io, os::fd::AsRawFd};
+
+    use super::FsHints;
+
+    #[derive(Debug)]
+    pub struct Fadvise;
+
+    // What a file system without the call says, which isn't a problem.
+    fn unsupported(errno: i32) -> bool {
+        errno == libc::ENOSYS || errno == libc::EOPNOTSUPP
+    }
+
+    fn advise(file: &File, offset: u64, len: u64, advice: libc::c_int) -> io::Result<()> {
+        // Past what an off_t holds is the same as to the end
+        let offset = offset.min(libc::off_t::MAX as u64) as libc::off_t;
+        let len = len.min(libc::off_t::MAX as u64) as libc::off_t;
+        // SAFETY: the descriptor stays open for the length of the call.
+        // This returns the error instead of setting errno.
+        match unsafe { libc::posix_fadvise(file.as_raw_fd(), offset, len, advice) } {
+            0 => Ok(()),
+            e if unsupported(e) => Ok(()),
+            e => Err(io::Error::from_raw_os_error(e)),
+        }
+    }
+
+    impl FsHints for Fadvise {
+        fn sequential(&self, file: &File) -> io::Result<()> {
+            // A length of 0 is the whole file
+            advise(file, 0, 0, libc::POSIX_FADV_SEQUENTIAL)
+        }
+
+        fn will_need(&self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+            if len == 0 {
+                return Ok(());
+            }
+            advise(file, offset, len, libc::POSIX_FADV_WILLNEED)
+        }
+
+        fn preallocate(&self, file: &File, len: u64) -> io::Result<()> {
+            if len == 0 {
+                return Ok(());
+            }
+            let len = len.min(libc::off_t::MAX as u64) as libc::off_t;
+            // SAFETY: as above.
+            if unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } == 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                // It still gets its size up front
+                Some(errno) if unsupported(errno) => file.set_len(len as u64),
+                _ => Err(e),
+            }
+        }
+    }
+}
+
+// Small named values stored with a file instead of in it, like extended
+// attributes. Alternate data streams would be the Windows version.
+pub trait FileAttributes: fmt::Debug {
+    fn set(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()>;
+    // `None` if the file doesn't have the attribute.
+    fn get(&self, path: &Path, name: &str) -> io::Result<Option<Vec<u8>>>;
+}
+
+// This platform's `FileAttributes`, if it has any. The file system can still
+// turn them down, with `io::ErrorKind::Unsupported`.
+pub fn file_attributes() -> Option<&'static dyn FileAttributes> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    return Some(&xattr::Xattrs);
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+    return None;
+}
+
+// Only some Unixes have the xattr calls, and macOS's take a couple more
+// arguments.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+mod xattr {
+    use std::{
+        ffi::CString,
+        io,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+    };
+
+    use super::FileAttributes;
+
+    #[derive(Debug)]
+    pub struct Xattrs;
+
+    fn c_strings(path: &Path, name: &str) -> io::Result<(CString, CString)> {
+        let invalid = |_| io::Error::new(io::ErrorKind::InvalidInput, "Path or attribute name has a NUL in it");
+        Ok((
+            CString::new(path.as_os_str().as_bytes()).map_err(invalid)?,
+            CString::new(name).map_err(invalid)?,
+        ))
+    }
+
+    fn last_error() -> io::Error {
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENOTSUP) => io::Error::new(io::ErrorKind::Unsupported, "The file system doesn't support extended attributes"),
+            _ => e,
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    const NO_ATTRIBUTE: i32 = libc::ENOATTR;
+    #[cfg(not(target_os = "macos"))]
+    const NO_ATTRIBUTE: i32 = libc::ENODATA;
+
+    impl FileAttributes for Xattrs {
+        fn set(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+            let (path, name) = c_strings(path, name)?;
+            // SAFETY: the strings are NUL terminated and `value` is valid for
+            // its length.
+            let result = unsafe {
+                #[cfg(target_os = "macos")]
+                { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0, 0) }
+                #[cfg(not(target_os = "macos"))]
+                { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) }
+            };
+            if result == 0 { Ok(()) } else { Err(last_error()) }
+        }
+
+        fn get(&self, path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+            let (path, name) = c_strings(path, name)?;
+            // Attributes are small, and gcmod's are tiny
+            let mut value = vec![0u8; 256];
+            // SAFETY: as above, and `value` has room for `value.len()` bytes.
+            let len = unsafe {
+                #[cfg(target_os = "macos")]
+                { libc::getxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len(), 0, 0) }
+                #[cfg(not(target_os = "macos"))]
+                { libc::getxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) }
+            };
+            if len < 0 {
+                let e = last_error();
+                return match e.raw_os_error() {
+                    Some(NO_ATTRIBUTE) => Ok(None),
+                    _ => Err(e),
+                };
+            }
+            value.truncate(len as usize);
+            Ok(Some(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::TestDir;
+
+    // Makes `dir` read-only, or returns false if that doesn't stop this
+    // process writing to it, like when it's root.
+    #[cfg(unix)]
+    fn make_read_only(dir: &Path) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o555)).unwrap();
+        let probe = dir.join("probe");
+        if File::create(&probe).is_ok() {
+            fs::remove_file(probe).unwrap();
+            return false;
+        }
+        true
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_dirs_arent_writable() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TestDir::new();
+        let read_only = dir.join("read-only");
+        fs::create_dir(&read_only).unwrap();
+        if !make_read_only(&read_only) {
+            eprintln!("skipped, read-only directories can be written to here");
+            return;
+        }
+        let result = check_writable(&read_only);
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o755)).unwrap();
+        match result {
+            Err(DestinationError::NotWritable { path, .. }) => assert_eq!(path, read_only),
+            other => panic!("{:?}", other),
+        }
+        assert!(!read_only.join(PROBE_NAME).exists());
+    }
+
+    #[test]
+    fn writable_dirs_are_left_as_they_were() {
+        let dir = TestDir::new();
+        check_writable(dir.path()).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn free_space_is_checked() {
+        let dir = TestDir::new();
+        assert!(available_space(dir.path()).is_some());
+        check_free_space(dir.path(), 0).unwrap();
+        match check_free_space(dir.path(), u64::MAX) {
+            Err(DestinationError::InsufficientSpace { needed, available, .. }) => {
+                assert_eq!(needed, u64::MAX);
+                assert!(available < needed);
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn disk_full_errors() {
+        assert!(is_disk_full(&io::Error::from_raw_os_error(libc::ENOSPC)));
+        assert!(is_disk_full(&io::Error::from_raw_os_error(libc::EDQUOT)));
+        assert!(!is_disk_full(&io::Error::from_raw_os_error(libc::EACCES)));
+    }
+
+    // Whichever hints this platform has, they work on real files, even for
+    // ranges past the end, and files get their full size up front
+    #[test]
+    fn platform_hints() {
+        let dir = TestDir::new();
+        fs::write(dir.join("source"), [1; 0x1000]).unwrap();
+        let source = File::open(dir.join("source")).unwrap();
+        let out = File::create(dir.join("out")).unwrap();
+        for hints in [fs_hints(), &NoHints] {
+            hints.sequential(&source).unwrap();
+            hints.will_need(&source, 0x800, 0x800).unwrap();
+            hints.will_need(&source, 0x10_0000, 0x1000).unwrap();
+            hints.will_need(&source, u64::MAX, u64::MAX).unwrap();
+            hints.preallocate(&out, 0).unwrap();
+        }
+        fs_hints().preallocate(&out, 0x2000).unwrap();
+        #[cfg(target_os = "linux")]
+        assert_eq!(out.metadata().unwrap().len(), 0x2000);
+        #[cfg(not(target_os = "linux"))]
+        assert_eq!(out.metadata().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn destinations() {
+        let dir = TestDir::new();
+        let new = dir.join("new");
+        assert_eq!(check_destination_dir(&new, false).unwrap(), dir.path());
+        assert!(matches!(check_destination_dir(&new.join("deeper"), false), Err(DestinationError::ParentMissing(_))));
+
+        fs::create_dir(&new).unwrap();
+        assert_eq!(check_destination_dir(&new, false).unwrap(), new);
+        fs::write(new.join("a"), "").unwrap();
+        assert!(matches!(check_destination_dir(&new, false), Err(DestinationError::NotEmpty(_))));
+        assert_eq!(check_destination_dir(&new, true).unwrap(), new);
+        assert!(matches!(check_destination_dir(&new.join("a"), true), Err(DestinationError::IsFile(_))));
+    }
+
+    #[test]
+    fn renames_on_the_same_file_system() {
+        let dir = TestDir::new();
+        fs::write(dir.join("from"), "contents").unwrap();
+        assert!(!rename_or_copy(dir.join("from"), dir.join("to")).unwrap());
+        assert_eq!(fs::read(dir.join("to")).unwrap(), b"contents");
+        assert!(!dir.join("from").exists());
+    }
+
+    // Needs a writable directory on a different file system from the
+    // temporary directory, which is usually /dev/shm on Linux.
+    #[cfg(unix)]
+    #[test]
+    fn copies_across_file_systems() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = TestDir::new();
+        let device = fs::metadata(dir.path()).unwrap().dev();
+        let Some(other) = ["/dev/shm", "/run/user"].iter()
+            .map(Path::new)
+            .find(|p| fs::metadata(p).is_ok_and(|m| m.dev() != device) && check_writable(p).is_ok())
+        else {
+            eprintln!("skipped, there's no other file system to copy to");
+            return;
+        };
+        let other = TestDir::new_in(other);
+
+        fs::write(dir.join("from"), "contents").unwrap();
+        assert!(rename_or_copy(dir.join("from"), other.join("to")).unwrap());
+        assert_eq!(fs::read(other.join("to")).unwrap(), b"contents");
+        assert!(!dir.join("from").exists());
+    }
+
+    #[cfg(unix)]
+    fn same_file(a: &Path, b: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        let (a, b) = (fs::metadata(a).unwrap(), fs::metadata(b).unwrap());
+        (a.dev(), a.ino()) == (b.dev(), b.ino())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn duplicates_are_hard_linked() {
+        let dir = TestDir::new();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, "contents").unwrap();
+        fs::write(&to, "in the way").unwrap();
+        assert_eq!(link_duplicate(&from, &to, DedupMode::Hardlink).unwrap(), LinkKind::Hardlink);
+        assert!(same_file(&from, &to));
+
+        // Without touching the file it's linked to
+        let mut replaced = replace_file(&to).unwrap();
+        io::Write::write_all(&mut replaced, b"new contents").unwrap();
+        assert_eq!(fs::read_to_string(&from).unwrap(), "contents");
+        assert!(!same_file(&from, &to));
+    }
+
+    // Whether this file system has reflinks or not, the contents end up the
+    // same, and falling back goes to hard links
+    #[cfg(unix)]
+    #[test]
+    fn reflinks_fall_back_to_hard_links() {
+        let dir = TestDir::new();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, "contents").unwrap();
+        match link_duplicate(&from, &to, DedupMode::Reflink).unwrap() {
+            LinkKind::Reflink => assert!(!same_file(&from, &to)),
+            LinkKind::Hardlink => assert!(same_file(&from, &to)),
+            LinkKind::Copy => panic!("hard links work here"),
+        }
+        assert_eq!(fs::read_to_string(&to).unwrap(), "contents");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn without_dedup_duplicates_are_copied() {
+        let dir = TestDir::new();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, "contents").unwrap();
+        assert_eq!(link_duplicate(&from, &to, DedupMode::Off).unwrap(), LinkKind::Copy);
+        assert!(!same_file(&from, &to));
+        assert_eq!(fs::read_to_string(&to).unwrap(), "contents");
+    }
+
+    #[test]
+    fn dedup_modes() {
+        assert_eq!(DedupMode::parse("hardlink"), Some(DedupMode::Hardlink));
+        assert_eq!(DedupMode::parse("reflink"), Some(DedupMode::Reflink));
+        assert_eq!(DedupMode::parse("off"), Some(DedupMode::Off));
+        assert_eq!(DedupMode::parse("symlink"), None);
+    }
+
+    #[test]
+    fn zero_extended_reads() {
+        let mut r = ZeroExtended::new(Cursor::new(vec![1u8; 10])).unwrap();
+        let mut all = Vec::new();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(all, [1; 10]);
+
+        r.extend_to(16);
+        r.seek(SeekFrom::Start(6)).unwrap();
+        all.clear();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(all, [1, 1, 1, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(r.seek(SeekFrom::End(-2)).unwrap(), 14);
+        let mut buf = [0xff; 4];
+        assert_eq!(r.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [0, 0, 0xff, 0xff]);
+        // Back into what's really there
+        r.seek(SeekFrom::Current(-7)).unwrap();
+        assert_eq!(r.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 1);
+        assert!(r.seek(SeekFrom::Current(-100)).is_err());
+    }
+
+    #[test]
+    fn zero_extended_never_shrinks() {
+        let mut r = ZeroExtended::new(Cursor::new(vec![1u8; 10])).unwrap();
+        r.extend_to(4);
+        assert_eq!(r.seek(SeekFrom::End(0)).unwrap(), 10);
+        let mut all = Vec::new();
+        r.seek(SeekFrom::Start(0)).unwrap();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(all.len(), 10);
+    }
+
+    // Does what `RealFileOps` does, writing down each call, except for the
+    // one named in `fail`
+    #[derive(Default)]
+    struct RecordedOps {
+        calls: std::cell::RefCell<Vec<&'static str>>,
+        fail: Option<&'static str>,
+        dirs_unsupported: bool,
+        // Renaming fails like `temp` is on another file system
+        cross_device: bool,
+    }
+
+    impl RecordedOps {
+        fn call(&self, name: &'static str, op: impl FnOnce() -> io::Result<bool>) -> io::Result<bool> {
+            self.calls.borrow_mut().push(name);
+            match self.fail {
+                Some(fail) if fail == name => Err(io::Error::other("injected")),
+                _ => op(),
+            }
+        }
+    }
+
+    impl FileOps for RecordedOps {
+        fn sync_data(&self, file: &File) -> io::Result<()> {
+            self.call("sync_data", || RealFileOps.sync_data(file).map(|()| true)).map(drop)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<bool> {
+            self.call("rename", || if self.cross_device {
+                rename_or_copy_with(from, to, |_, _| Err(io::ErrorKind::CrossesDevices.into()))
+            } else {
+                RealFileOps.rename(from, to)
+            })
+        }
+
+        fn sync_dir(&self, dir: &Path) -> io::Result<bool> {
+            self.call("sync_dir", || if self.dirs_unsupported { Ok(false) } else { RealFileOps.sync_dir(dir) })
+        }
+    }
+
+    fn finish(dir: &TestDir, mode: SyncMode, ops: &RecordedOps) -> Result<Finished, FinishError> {
+        let (temp, path) = (dir.join("out.iso.tmp"), dir.join("out.iso"));
+        fs::write(&temp, b"rom").unwrap();
+        finish_file(File::options().write(true).open(&temp).unwrap(), &temp, &path, mode, ops)
+    }
+
+    #[test]
+    fn syncing_in_each_mode() {
+        for (mode, calls) in [
+            (SyncMode::None, &["rename"][..]),
+            (SyncMode::Data, &["sync_data", "rename"]),
+            (SyncMode::Full, &["sync_data", "rename", "sync_dir"]),
+        ] {
+            let dir = TestDir::new();
+            let ops = RecordedOps::default();
+            assert_eq!(finish(&dir, mode, &ops).unwrap(), Finished { dir_synced: true, copied: false }, "{:?}", mode);
+            assert_eq!(*ops.calls.borrow(), calls, "{:?}", mode);
+            assert_eq!(fs::read(dir.join("out.iso")).unwrap(), b"rom");
+            assert!(!dir.join("out.iso.tmp").exists());
+        }
+
+        let dir = TestDir::new();
+        let ops = RecordedOps { dirs_unsupported: true, ..RecordedOps::default() };
+        assert!(!finish(&dir, SyncMode::Full, &ops).unwrap().dir_synced);
+        assert!(dir.join("out.iso").exists());
+    }
+
+    #[test]
+    fn finishing_across_file_systems() {
+        for (mode, calls) in [
+            (SyncMode::None, &["rename"][..]),
+            // The copy is synced too
+            (SyncMode::Data, &["sync_data", "rename", "sync_data"]),
+            (SyncMode::Full, &["sync_data", "rename", "sync_data", "sync_dir"]),
+        ] {
+            let dir = TestDir::new();
+            let ops = RecordedOps { cross_device: true, ..RecordedOps::default() };
+            assert_eq!(finish(&dir, mode, &ops).unwrap(), Finished { dir_synced: true, copied: true }, "{:?}", mode);
+            assert_eq!(*ops.calls.borrow(), calls, "{:?}", mode);
+            assert_eq!(fs::read(dir.join("out.iso")).unwrap(), b"rom");
+            assert!(!dir.join("out.iso.tmp").exists());
+        }
+    }
+
+    #[test]
+    fn failed_copies_leave_nothing_behind() {
+        let dir = TestDir::new();
+        fs::create_dir(dir.join("from")).unwrap();
+        let cross_device = |_: &Path, _: &Path| Err(io::ErrorKind::CrossesDevices.into());
+        assert!(rename_or_copy_with(&dir.join("from"), &dir.join("to"), cross_device).is_err());
+        assert!(!dir.join("to").exists());
+        assert!(dir.join("from").exists());
+    }
+
+    #[test]
+    fn failing_to_finish() {
+        let dir = TestDir::new();
+        let ops = RecordedOps { fail: Some("sync_data"), ..RecordedOps::default() };
+        assert!(matches!(finish(&dir, SyncMode::Data, &ops), Err(FinishError::Sync(_))));
+        // Never renamed, and the unsynced file is gone
+        assert_eq!(*ops.calls.borrow(), ["sync_data"]);
+        assert!(!dir.join("out.iso").exists() && !dir.join("out.iso.tmp").exists());
+
+        let ops = RecordedOps { fail: Some("rename"), ..RecordedOps::default() };
+        assert!(matches!(finish(&dir, SyncMode::Full, &ops), Err(FinishError::Rename(_))));
+        assert_eq!(*ops.calls.borrow(), ["sync_data", "rename"]);
+        assert!(!dir.join("out.iso").exists() && !dir.join("out.iso.tmp").exists());
+
+        // It's already in place by then
+        let ops = RecordedOps { fail: Some("sync_dir"), ..RecordedOps::default() };
+        assert!(matches!(finish(&dir, SyncMode::Full, &ops), Err(FinishError::SyncDir(_))));
+        assert!(dir.join("out.iso").exists());
+    }
+}
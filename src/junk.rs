@@ -0,0 +1,163 @@
+// Regenerates the pseudo random "junk" Nintendo's mastering tools put in the
+// unused areas of a disc. The generator is a lagged Fibonacci generator, the
+// same one implemented by Wiimm's ISO Tools and Dolphin. Every 0x40000 byte
+// block of the disc has its own seed, derived from the game code, the disc
+// number, and the position of the block.
+
+pub const JUNK_BLOCK_SIZE: u64 = 0x40000;
+
+const SEED_SIZE: usize = 17;
+const LFG_K: usize = 521;
+const LFG_J: usize = 32;
+const BUFFER_BYTES: usize = LFG_K * 4;
+
+pub struct JunkGenerator {
+    buffer: [u32; LFG_K],
+    position: usize,
+}
+
+impl JunkGenerator {
+    pub fn new(game_code: [u8; 4], disc_number: u8, block: u64) -> JunkGenerator {
+        // 0x40000 bytes is 8 sectors of 0x8000 bytes, the seed uses the sector.
+        let sector = (block * 8) as u32;
+        let mut seed = (u32::from_be_bytes(game_code) ^ disc_number as u32)
+            .wrapping_mul(0x260bcd5)
+            ^ sector.wrapping_mul(0x1ef29123);
+
+        let mut buffer = [0; LFG_K];
+        for word in &mut buffer[..SEED_SIZE] {
+            for _ in 0..32 {
+                seed = seed.wrapping_mul(0x5d588b65).wrapping_add(1);
+                *word = (*word >> 1) | (seed & 0x80000000);
+            }
+        }
+        buffer[16] ^= (buffer[0] >> 9) ^ (buffer[16] << 23);
+
+        for i in SEED_SIZE..LFG_K {
+            buffer[i] = (buffer[i - 17] << 23) ^ (buffer[i - 16] >> 9) ^ buffer[i - 1];
+        }
+
+        // The second big-endian byte of each word (bits 16-23) comes from a
+        // shift by 18 bits instead of 16. Doing that here once keeps `fill` a
+        // plain copy.
+        for x in &mut buffer {
+            *x = (*x & 0xff00ffff) | ((*x >> 2) & 0x00ff0000);
+        }
+
+        let mut generator = JunkGenerator { buffer, position: 0 };
+        for _ in 0..4 {
+            generator.forward();
+        }
+        generator
+    }
+
+    fn forward(&mut self) {
+        for i in 0..LFG_J {
+            self.buffer[i] ^= self.buffer[i + LFG_K - LFG_J];
+        }
+        for i in LFG_J..LFG_K {
+            self.buffer[i] ^= self.buffer[i - LFG_J];
+        }
+    }
+
+    pub fn skip(&mut self, mut count: usize) {
+        while count > 0 {
+            let len = count.min(BUFFER_BYTES - self.position);
+            self.position += len;
+            count -= len;
+            if self.position == BUFFER_BYTES {
+                self.forward();
+                self.position = 0;
+            }
+        }
+    }
+
+    pub fn fill(&mut self, out: &mut [u8]) {
+        let mut written = 0;
+        while written < out.len() {
+            let len = (out.len() - written).min(BUFFER_BYTES - self.position);
+            for (i, b) in out[written..written + len].iter_mut().enumerate() {
+                let pos = self.position + i;
+                *b = self.buffer[pos / 4].to_be_bytes()[pos % 4];
+            }
+            self.position += len;
+            written += len;
+            if self.position == BUFFER_BYTES {
+                self.forward();
+                self.position = 0;
+            }
+        }
+    }
+}
+
+// Fills `out` with the junk that belongs at `offset` on the disc.
+pub fn fill_junk(game_code: [u8; 4], disc_number: u8, offset: u64, out: &mut [u8]) {
+    let mut done = 0;
+    while done < out.len() {
+        let pos = offset + done as u64;
+        let block = pos / JUNK_BLOCK_SIZE;
+        let in_block = pos % JUNK_BLOCK_SIZE;
+        let len = ((JUNK_BLOCK_SIZE - in_block) as usize).min(out.len() - done);
+
+        let mut generator = JunkGenerator::new(game_code, disc_number, block);
+        generator.skip(in_block as usize);
+        generator.fill(&mut out[done..done + len]);
+        done += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME_CODE: [u8; 4] = *b"GSLF";
+
+    fn junk(disc_number: u8, offset: u64, len: usize) -> Vec<u8> {
+        let mut out = vec![0; len];
+        fill_junk(GAME_CODE, disc_number, offset, &mut out);
+        out
+    }
+
+    // WIT's GetLFG doesn't pre-shift the buffer, it takes each word's bytes
+    // as x >> 24, x >> 18, x >> 8, x while copying them out. These are the
+    // bytes that way gives for Melee (GALE), disc 0, so they'd catch the
+    // shift landing on the wrong byte or the seed using the wrong sector.
+    #[test]
+    fn known_answer() {
+        let mut out = [0; 16];
+        fill_junk(*b"GALE", 0, 0x100_0000, &mut out);
+        assert_eq!(out, *b"\x26\x93\x57\x39\x23\xc7\xec\x6a\x9f\xd5\x7c\x90\x82\xa3\xa8\xc2");
+        fill_junk(*b"GALE", 0, 0x100_1234, &mut out);
+        assert_eq!(out, *b"\x03\xdc\xe7\x85\x87\xe8\x44\x47\x93\xd8\x11\x69\x96\x80\xed\xe7");
+    }
+
+    #[test]
+    fn pieces_match_the_whole() {
+        let whole = junk(0, 0, 3 * JUNK_BLOCK_SIZE as usize);
+        // Across the generator's buffer, and across blocks
+        for (start, len) in [(0, 1), (3, BUFFER_BYTES), (BUFFER_BYTES - 1, 2), (0x3fffe, 5), (0x40000, 0x40001)] {
+            assert_eq!(junk(0, start as u64, len), &whole[start..start + len], "{:#x}+{:#x}", start, len);
+        }
+    }
+
+    #[test]
+    fn skipping_matches_filling() {
+        let mut skipped = JunkGenerator::new(GAME_CODE, 0, 5);
+        skipped.skip(BUFFER_BYTES + 7);
+        let mut filled = JunkGenerator::new(GAME_CODE, 0, 5);
+        filled.fill(&mut vec![0; BUFFER_BYTES + 7]);
+        let (mut a, mut b) = ([0; 64], [0; 64]);
+        skipped.fill(&mut a);
+        filled.fill(&mut b);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn seeds_differ() {
+        let first = junk(0, 0, 64);
+        assert_ne!(first, vec![0; 64]);
+        assert_eq!(first, junk(0, 0, 64));
+        assert_ne!(first, junk(1, 0, 64));
+        assert_ne!(first, junk(0, JUNK_BLOCK_SIZE, 64));
+    }
+}
@@ -0,0 +1,110 @@
+// A table of the kinds of files commonly found on GameCube ROMs, recognized
+// by their names.
+
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Category {
+    Banner,
+    Texture,
+    Movie,
+    SoundBank,
+    Module,
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Banner => "Banner",
+            Category::Texture => "Textures",
+            Category::Movie => "Movies",
+            Category::SoundBank => "Sound banks",
+            Category::Module => "Modules",
+        }
+    }
+}
+
+// Patterns with a slash are matched against the entry's full path, and
+// patterns without one against just its name. The first match wins.
+pub const KNOWN_FILES: &[(&str, Category)] = &[
+    ("/opening.bnr", Category::Banner),
+    ("/*.tpl", Category::Texture),
+    ("*.thp", Category::Movie),
+    // MusyX sound data
+    ("*.ssm", Category::SoundBank),
+    ("*.sdir", Category::SoundBank),
+    ("*.samp", Category::SoundBank),
+    ("*.pool", Category::SoundBank),
+    ("*.proj", Category::SoundBank),
+    ("*.rel", Category::Module),
+];
+
+// `path` should be normalized, see `EntryInfo::normalized_path`.
+pub fn categorize(path: &str) -> Option<Category> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    KNOWN_FILES.iter()
+        .find(|(pattern, _)| {
+            let text = if pattern.contains('/') { path } else { name };
+            glob_match(pattern, text)
+        })
+        .map(|&(_, category)| category)
+}
+
+// Matches `text` against a pattern where `*` matches any run of characters
+// other than '/' and `?` matches any single character. Case insensitive,
+// since file names on ROMs are too.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to go back to when a match after a `*` fails.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star.filter(|&(_, st)| text[st] != '/') {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*.rel", "a.rel"));
+        assert!(glob_match("*.rel", ".rel"));
+        assert!(glob_match("*.REL", "a.rel"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*a*b", "xaxxb"));
+        assert!(!glob_match("*.rel", "a.rel.bak"));
+        assert!(!glob_match("a?c", "ac"));
+        // `*` stays in one directory
+        assert!(glob_match("/*.tpl", "/a.tpl"));
+        assert!(!glob_match("/*.tpl", "/textures/a.tpl"));
+    }
+
+    #[test]
+    fn categories() {
+        assert_eq!(categorize("/opening.bnr"), Some(Category::Banner));
+        assert_eq!(categorize("/menu/opening.bnr"), None);
+        assert_eq!(categorize("/title.TPL"), Some(Category::Texture));
+        assert_eq!(categorize("/textures/title.tpl"), None);
+        assert_eq!(categorize("/movies/intro.thp"), Some(Category::Movie));
+        assert_eq!(categorize("/audio/bank.ssm"), Some(Category::SoundBank));
+        assert_eq!(categorize("/audio/Music.proj"), Some(Category::SoundBank));
+        assert_eq!(categorize("/rel/stage.rel"), Some(Category::Module));
+        assert_eq!(categorize("/readme.txt"), None);
+    }
+}
@@ -0,0 +1,397 @@
+// Every fixed field in the disc header, bi2, the apploader's header, and the
+// DOL's header, for looking up what's at an offset without an image, like
+// `explain` does, and for saying which field couldn't be read in errors.
+// The table is built from the constants the parsers use where there are
+// any, and `check` makes sure it still lines up with them.
+
+use std::{fmt, io, ops::Range};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::sections::{
+    apploader::{APPLOADER_DATE_SIZE, APPLOADER_HEADER_SIZE, APPLOADER_OFFSET, APPLOADER_SIZE_ADDR},
+    dol::{
+        BSS_ADDRESS_OFFSET,
+        BSS_SIZE_OFFSET,
+        DATA_SEG_COUNT,
+        DOL_HEADER_LEN,
+        DOL_PADDING_OFFSET,
+        DOL_OFFSET_OFFSET,
+        ENTRY_POINT_OFFSET,
+        SEGMENT_ADDRESSES_OFFSET,
+        SEGMENT_OFFSETS_OFFSET,
+        SEGMENT_SIZES_OFFSET,
+        TEXT_SEG_COUNT,
+    },
+    fst::{FST_OFFSET_OFFSET, FST_SIZE_OFFSET},
+    header::*,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Block {
+    // The first 0x440 bytes, boot.bin
+    Header,
+    Bi2,
+    // Just its header, the code after it varies
+    Apploader,
+    // Just its header, wherever the header says the DOL is
+    Dol,
+}
+
+impl Block {
+    pub const ALL: [Block; 4] = [Block::Header, Block::Bi2, Block::Apploader, Block::Dol];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Block::Header => "header",
+            Block::Bi2 => "bi2",
+            Block::Apploader => "apploader",
+            Block::Dol => "dol",
+        }
+    }
+
+    // Where it is in the image. The DOL can be anywhere.
+    pub fn base(self) -> Option<u64> {
+        match self {
+            Block::Header => Some(0),
+            Block::Bi2 => Some(BI2_OFFSET),
+            Block::Apploader => Some(APPLOADER_OFFSET),
+            Block::Dol => None,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            Block::Header => BI2_OFFSET as usize,
+            Block::Bi2 => BI2_SIZE,
+            Block::Apploader => APPLOADER_HEADER_SIZE,
+            Block::Dol => DOL_HEADER_LEN,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FieldRef {
+    pub block: Block,
+    pub name: &'static str,
+    // From the start of `block`
+    pub offset: u64,
+    // Of each element, for arrays
+    pub width: usize,
+    // 1 for anything that isn't an array
+    pub count: usize,
+    pub description: &'static str,
+    // What it's read into, like "Header::fst_offset"
+    pub field: Option<&'static str>,
+    // The header and bi2 fields `Header` tracks changes to
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub header_field: Option<HeaderField>,
+}
+
+impl FieldRef {
+    const fn new(block: Block, name: &'static str, offset: u64, width: usize, description: &'static str) -> FieldRef {
+        FieldRef { block, name, offset, width, count: 1, description, field: None, header_field: None }
+    }
+
+    const fn field(mut self, field: &'static str) -> FieldRef {
+        self.field = Some(field);
+        self
+    }
+
+    const fn header_field(mut self, field: &'static str, header_field: HeaderField) -> FieldRef {
+        self.header_field = Some(header_field);
+        self.field(field)
+    }
+
+    const fn array(mut self, count: usize) -> FieldRef {
+        self.count = count;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.width * self.count
+    }
+
+    // In `block`
+    pub fn range(&self) -> Range<u64> {
+        self.offset..self.offset + self.size() as u64
+    }
+
+    // Like "header+0x424"
+    pub fn location(&self) -> String {
+        format!("{}+{:#x}", self.block.name(), self.offset)
+    }
+}
+
+const fn word(block: Block, name: &'static str, offset: u64, description: &'static str) -> FieldRef {
+    FieldRef::new(block, name, offset, 4, description)
+}
+
+pub static FIELDS: &[FieldRef] = &[
+    FieldRef::new(Block::Header, "game_code", GAMEID_OFFSET, GAME_CODE_SIZE,
+        "The game code, like GALE. The last letter is the region.")
+        .header_field("Header::game_code", HeaderField::GameCode),
+    FieldRef::new(Block::Header, "maker_code", GAMEID_OFFSET + GAME_CODE_SIZE as u64, MAKER_CODE_SIZE,
+        "The publisher's licensee code, like 01 for Nintendo.")
+        .header_field("Header::maker_code", HeaderField::MakerCode),
+    FieldRef::new(Block::Header, "disk_id", DISK_ID_OFFSET, DISK_ID_SIZE, "Which disc this is, from 0.")
+        .header_field("Header::disk_id", HeaderField::DiskId),
+    FieldRef::new(Block::Header, "version", DISK_ID_OFFSET + DISK_ID_SIZE as u64, VERSION_SIZE, "The revision, from 0.")
+        .header_field("Header::version", HeaderField::Version),
+    FieldRef::new(Block::Header, "audio_streaming", AUDIO_STREAMING_OFFSET, AUDIO_STREAMING_SIZE,
+        "Whether the game streams audio from the disc.")
+        .header_field("Header::audio_streaming", HeaderField::AudioStreaming),
+    FieldRef::new(Block::Header, "stream_buffer_size", STREAM_BUFFER_SIZE_OFFSET, STREAM_BUFFER_SIZE_SIZE,
+        "The size of the buffer for streaming audio.")
+        .header_field("Header::stream_buffer_size", HeaderField::StreamBufferSize),
+    FieldRef::new(Block::Header, "unused_1", STREAM_BUFFER_SIZE_OFFSET + STREAM_BUFFER_SIZE_SIZE as u64, UNUSED_REGION_1_SIZE,
+        "Unused."),
+    FieldRef::new(Block::Header, "magic_word", TITLE_OFFSET - MAGIC_WORD_SIZE as u64, MAGIC_WORD_SIZE,
+        "0xc2339f3d on every GameCube disc."),
+    FieldRef::new(Block::Header, "title", TITLE_OFFSET, GAME_NAME_SIZE, "The game's title, ending in a 0 byte.")
+        .header_field("Header::title", HeaderField::Title),
+    word(Block::Header, "debug_monitor_offset", 0x400, "Where the debug monitor is on development discs, or 0.")
+        .header_field("Header::debug_monitor_offset", HeaderField::DebugMonitorOffset),
+    word(Block::Header, "debug_monitor_load_addr", 0x404, "Where in memory the debug monitor is loaded.")
+        .header_field("Header::debug_monitor_load_addr", HeaderField::DebugMonitorLoadAddr),
+    FieldRef::new(Block::Header, "unused_2", 0x408, UNUSED_REGION_2_SIZE, "Unused."),
+    FieldRef::new(Block::Header, "dol_offset", DOL_OFFSET_OFFSET, DOL_OFFSET_SIZE, "Where the DOL, Start.dol, is.")
+        .header_field("Header::dol_offset", HeaderField::DolOffset),
+    FieldRef::new(Block::Header, "fst_offset", FST_OFFSET_OFFSET, FST_OFFSET_SIZE, "Where the FST, Game.toc, is.")
+        .header_field("Header::fst_offset", HeaderField::FstOffset),
+    FieldRef::new(Block::Header, "fst_size", FST_SIZE_OFFSET, FST_SIZE_SIZE, "How big the FST is.")
+        .header_field("Header::fst_size", HeaderField::FstSize),
+    FieldRef::new(Block::Header, "max_fst_size", FST_SIZE_OFFSET + FST_SIZE_SIZE as u64, FST_MAX_SIZE_SIZE,
+        "The biggest FST on any of the game's discs, which the apploader sets aside memory for.")
+        .header_field("Header::max_fst_size", HeaderField::MaxFstSize),
+    FieldRef::new(Block::Header, "user_position", 0x430, USER_POSITION_SIZE, "Where the user area, with the files, starts.")
+        .header_field("Header::user_position", HeaderField::UserPosition),
+    FieldRef::new(Block::Header, "user_length", 0x434, USER_LENGTH_SIZE, "How big the user area is.")
+        .header_field("Header::user_length", HeaderField::UserLength),
+    FieldRef::new(Block::Header, "unknown", 0x438, UNKNOWN_REGION_SIZE, "Not understood, and kept as it is.")
+        .header_field("Header::unknown", HeaderField::Unknown),
+    FieldRef::new(Block::Header, "unused_3", 0x43c, UNUSED_REGION_3_SIZE, "Unused."),
+
+    FieldRef::new(Block::Bi2, "debug_monitor_size", 0x00, DEBUG_MONITOR_SIZE_SIZE,
+        "How much space the debug monitor has on development discs.")
+        .header_field("HeaderInformation::debug_monitor_size", HeaderField::DebugMonitorSize),
+    FieldRef::new(Block::Bi2, "simulated_memory_size", 0x04, SIMULATED_MEMORY_SIZE,
+        "How much memory the game is told there is, 24MiB on retail games.")
+        .header_field("HeaderInformation::simulated_memory_size", HeaderField::SimulatedMemorySize),
+    FieldRef::new(Block::Bi2, "argument_offset", 0x08, ARGUMENT_OFFSET_SIZE, "Where the arguments passed to the game are.")
+        .header_field("HeaderInformation::argument_offset", HeaderField::ArgumentOffset),
+    FieldRef::new(Block::Bi2, "debug_flag", 0x0c, DEBUG_FLAG_SIZE, "Turns on debugging on development hardware.")
+        .header_field("HeaderInformation::debug_flag", HeaderField::DebugFlag),
+    FieldRef::new(Block::Bi2, "track_location", 0x10, TRACK_LOCATION_SIZE, "Where the streamed audio track is.")
+        .header_field("HeaderInformation::track_location", HeaderField::TrackLocation),
+    FieldRef::new(Block::Bi2, "track_size", 0x14, TRACK_SIZE_SIZE, "How big the streamed audio track is.")
+        .header_field("HeaderInformation::track_size", HeaderField::TrackSize),
+    FieldRef::new(Block::Bi2, "country_code", 0x18, COUNTRY_CODE_SIZE,
+        "The region: 0 for Japan, 1 for the US, 2 for PAL, and 4 for Korea.")
+        .header_field("HeaderInformation::country_code", HeaderField::CountryCode),
+    FieldRef::new(Block::Bi2, "total_discs", TOTAL_DISCS_OFFSET - BI2_OFFSET, TOTAL_DISCS_SIZE,
+        "How many discs the game has, 0 on most single disc games.")
+        .header_field("HeaderInformation::total_discs", HeaderField::TotalDiscs),
+    FieldRef::new(Block::Bi2, "long_file_name", 0x20, LONG_FILE_NAME_SIZE, "Whether the FST can have long file names.")
+        .header_field("HeaderInformation::long_file_name", HeaderField::LongFileName),
+    FieldRef::new(Block::Bi2, "pad_spec", 0x24, PAD_SPEC_SIZE, "Which kind of controller the game was built for.")
+        .header_field("HeaderInformation::pad_spec", HeaderField::PadSpec),
+    FieldRef::new(Block::Bi2, "dol_limit", 0x28, DOL_LIMIT_SIZE, "The biggest the DOL can be, or 0 for no limit.")
+        .header_field("HeaderInformation::dol_limit", HeaderField::DolLimit),
+    FieldRef::new(Block::Bi2, "unknown", BI2_KNOWN_SIZE as u64, BI2_SIZE - BI2_KNOWN_SIZE, "Not understood, and kept as it is."),
+
+    FieldRef::new(Block::Apploader, "date", 0x00, APPLOADER_DATE_SIZE,
+        "When the SDK the apploader is from was built, like 2002/07/19.")
+        .field("Apploader::date"),
+    FieldRef::new(Block::Apploader, "padding", APPLOADER_DATE_SIZE as u64, 0x10 - APPLOADER_DATE_SIZE, "Unused."),
+    word(Block::Apploader, "entry_point", 0x10, "Where in memory the apploader's code starts.")
+        .field("Apploader::entry_point"),
+    word(Block::Apploader, "code_size", APPLOADER_SIZE_ADDR - APPLOADER_OFFSET, "How big the apploader's code is.")
+        .field("Apploader::code_size"),
+    word(Block::Apploader, "trailer_size", APPLOADER_SIZE_ADDR - APPLOADER_OFFSET + 4, "How big the trailer after the code is.")
+        .field("Apploader::trailer_size"),
+    word(Block::Apploader, "unused", 0x1c, "Unused."),
+
+    word(Block::Dol, "text_offsets", SEGMENT_OFFSETS_OFFSET,
+        "Where each text segment is, from the start of the DOL, or 0 for ones that aren't used.")
+        .field("Segment::offset").array(TEXT_SEG_COUNT),
+    word(Block::Dol, "data_offsets", SEGMENT_OFFSETS_OFFSET + TEXT_SEG_COUNT as u64 * 4,
+        "Where each data segment is, from the start of the DOL, or 0 for ones that aren't used.")
+        .field("Segment::offset").array(DATA_SEG_COUNT),
+    word(Block::Dol, "text_addresses", SEGMENT_ADDRESSES_OFFSET, "Where in memory each text segment is loaded.")
+        .field("Segment::loading_address").array(TEXT_SEG_COUNT),
+    word(Block::Dol, "data_addresses", SEGMENT_ADDRESSES_OFFSET + TEXT_SEG_COUNT as u64 * 4,
+        "Where in memory each data segment is loaded.")
+        .field("Segment::loading_address").array(DATA_SEG_COUNT),
+    word(Block::Dol, "text_sizes", SEGMENT_SIZES_OFFSET, "How big each text segment is, or 0 for ones that aren't used.")
+        .field("Segment::size").array(TEXT_SEG_COUNT),
+    word(Block::Dol, "data_sizes", SEGMENT_SIZES_OFFSET + TEXT_SEG_COUNT as u64 * 4,
+        "How big each data segment is, or 0 for ones that aren't used.")
+        .field("Segment::size").array(DATA_SEG_COUNT),
+    word(Block::Dol, "bss_address", BSS_ADDRESS_OFFSET, "Where in memory the BSS starts. The OS zeroes it before booting.")
+        .field("DOLHeader::bss_address"),
+    word(Block::Dol, "bss_size", BSS_SIZE_OFFSET, "How big the BSS is.")
+        .field("DOLHeader::bss_size"),
+    word(Block::Dol, "entry_point", ENTRY_POINT_OFFSET, "Where in memory the game's code starts running.")
+        .field("DOLHeader::entry_point"),
+    FieldRef::new(Block::Dol, "padding", DOL_PADDING_OFFSET, DOL_HEADER_LEN - DOL_PADDING_OFFSET as usize,
+        "Unused, and zeros on retail discs."),
+];
+
+pub fn find(block: Block, name: &str) -> Option<&'static FieldRef> {
+    FIELDS.iter().find(|f| f.block == block && f.name == name)
+}
+
+// A field an offset is in.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FieldMatch {
+    pub field: &'static FieldRef,
+    // Which element, for arrays
+    pub index: Option<usize>,
+    // How far into the field, or the element, the offset is
+    pub byte: u64,
+}
+
+impl fmt::Display for FieldMatch {
+    // Like "dol+0x0c: text_offsets[3], byte 0 of 4"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = self.field;
+        let element = self.index.map_or(0, |i| (i * field.width) as u64);
+        write!(f, "{}+{:#x}: {}", field.block.name(), field.offset + element + self.byte, field.name)?;
+        if let Some(i) = self.index {
+            write!(f, "[{}]", i)?;
+        }
+        match field.width {
+            1 => Ok(()),
+            width => write!(f, ", byte {} of {}", self.byte, width),
+        }
+    }
+}
+
+// The fields `offset` could be in: as an offset in the image, it's in the
+// header, bi2, or the apploader's header if it's anywhere, and as an offset
+// in the DOL, it could be in the DOL's header too.
+pub fn describe(offset: u64) -> Vec<FieldMatch> {
+    FIELDS.iter()
+        .filter_map(|field| {
+            let within = offset.checked_sub(field.block.base().unwrap_or(0))?;
+            field.range().contains(&within).then(|| {
+                let into = within - field.offset;
+                let width = field.width as u64;
+                FieldMatch {
+                    field,
+                    index: (field.count > 1).then_some((into / width) as usize),
+                    byte: into % width,
+                }
+            })
+        })
+        .collect()
+}
+
+// For errors reading a field, saying which one it was, like "Failed reading
+// fst_size (header+0x428)".
+pub fn reading(block: Block, name: &'static str) -> impl FnOnce(io::Error) -> io::Error {
+    move |e| {
+        let location = find(block, name).map_or_else(|| block.name().to_owned(), FieldRef::location);
+        io::Error::new(e.kind(), format!("Failed reading {} ({}): {}", name, location, e))
+    }
+}
+
+// Ways the table doesn't match the constants and `HeaderField`, or has
+// gaps or overlaps.
+pub fn check() -> Vec<String> {
+    let mut problems = Vec::new();
+    for block in Block::ALL {
+        let mut end = 0;
+        for f in FIELDS.iter().filter(|f| f.block == block) {
+            if f.offset != end {
+                problems.push(format!("{} is at {}, but the field before it ends at {:#x}", f.name, f.location(), end));
+            }
+            end = f.range().end;
+        }
+        if end != block.size() as u64 {
+            problems.push(format!("The {} fields end at {:#x}, but it's {:#x} bytes", block.name(), end, block.size()));
+        }
+    }
+
+    for f in FIELDS {
+        if let Some(header_field) = f.header_field {
+            let base = f.block.base().unwrap_or(0) as usize;
+            let range = base + f.offset as usize..base + f.offset as usize + f.size();
+            if header_field.range() != range {
+                problems.push(format!(
+                    "{} is at {:#x}..{:#x}, but HeaderField::{:?} is at {:#x}..{:#x}",
+                    f.name, range.start, range.end, header_field, header_field.range().start, header_field.range().end,
+                ));
+            }
+        }
+    }
+    let tracked = FIELDS.iter().filter(|f| f.header_field.is_some()).count();
+    if tracked != HeaderField::ALL.len() {
+        problems.push(format!("{} of the {} HeaderFields are in the table", tracked, HeaderField::ALL.len()));
+    }
+
+    let ends = [
+        ("bi2", BI2_OFFSET + BI2_SIZE as u64, GAME_HEADER_SIZE as u64),
+        ("The header", GAME_HEADER_SIZE as u64, APPLOADER_OFFSET),
+    ];
+    for (name, end, next) in ends {
+        if end != next {
+            problems.push(format!("{} ends at {:#x}, but what's after it starts at {:#x}", name, end, next));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+
+    #[test]
+    fn the_table_matches_the_constants() {
+        assert_eq!(check(), Vec::<String>::new());
+    }
+
+    // And the fields are where the parsers found them on a real image
+    #[test]
+    fn fields_on_an_image() {
+        let image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let word_at = |block, name| {
+            let field = find(block, name).unwrap();
+            let at = (field.block.base().unwrap_or(game.dol.offset) + field.offset) as usize;
+            u32::from_be_bytes(image[at..at + 4].try_into().unwrap()) as u64
+        };
+        assert_eq!(word_at(Block::Header, "dol_offset"), game.header.dol_offset);
+        assert_eq!(word_at(Block::Header, "fst_offset"), game.header.fst_offset);
+        assert_eq!(word_at(Block::Header, "fst_size"), game.header.fst_size as u64);
+        assert_eq!(word_at(Block::Bi2, "country_code"), game.header.information.country_code as u64);
+        assert_eq!(word_at(Block::Apploader, "code_size"), game.apploader.code_size as u64);
+        assert_eq!(word_at(Block::Dol, "bss_address"), game.dol.bss_address);
+        assert_eq!(word_at(Block::Dol, "entry_point"), game.dol.entry_point);
+    }
+
+    #[test]
+    fn describing_offsets() {
+        let names = |offset| describe(offset).iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        assert_eq!(names(0x420), ["header+0x420: dol_offset, byte 0 of 4"]);
+        // In the header of an image, and in a DOL's header
+        assert_eq!(names(0x1e), ["header+0x1e: magic_word, byte 2 of 4", "dol+0x1e: data_offsets[0], byte 2 of 4"]);
+        assert_eq!(names(0x56), ["header+0x56: title, byte 54 of 992", "dol+0x56: text_addresses[3], byte 2 of 4"]);
+        assert_eq!(names(0x2454), ["apploader+0x14: code_size, byte 0 of 4"]);
+        assert!(names(0x5000).is_empty());
+    }
+
+    #[test]
+    fn errors_say_which_field() {
+        let e = reading(Block::Header, "fst_offset")(io::Error::new(io::ErrorKind::UnexpectedEof, "too short"));
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(e.to_string(), "Failed reading fst_offset (header+0x424): too short");
+    }
+}
@@ -1,28 +1,91 @@
 use std::num::ParseIntError;
 
+pub mod analyzer;
+pub mod boot_trace;
+pub mod build_info;
+pub mod build_report;
+pub mod build_stamp;
+pub mod case_names;
+#[cfg(feature = "serde")]
+pub mod catalog;
+pub mod compare_layout;
+#[cfg(feature = "serde")]
+pub mod config;
+pub mod crash;
+pub mod doctor;
+pub mod file_order;
 mod game;
+pub mod game_ini;
+pub mod gecko;
+pub mod grow_fst;
+pub mod hash;
+pub mod io_util;
+pub mod junk;
+pub mod known_files;
+pub mod layout_ref;
+pub mod magic;
+pub mod manifest;
+#[cfg(feature = "serde")]
+pub mod meta;
+pub mod nkit;
+pub mod paths;
+pub mod profiling;
+pub mod provenance;
+pub mod quick_id;
+#[cfg(feature = "serde")]
+mod rebuild_plan;
+#[cfg(feature = "serde")]
+mod refresh;
+pub mod replace_batch;
+pub mod report;
+pub mod rescue;
+pub mod rom_handle;
 mod rom_rebuilder;
+pub mod scrub;
 pub mod sections;
+pub mod style;
+pub mod synthetic;
+pub mod table;
+#[cfg(test)]
+mod test_util;
+pub mod timestamp;
+pub mod titles;
+pub mod tree_check;
+pub mod workspace;
 
-pub use game::{Game, ROM_SIZE};
-pub use rom_rebuilder::ROMRebuilder;
+pub use game::{
+    parse_capacity,
+    ExtractOptions,
+    ExtractedKind,
+    Game,
+    GameSummary,
+    InferredName,
+    DUAL_LAYER_DISC_SIZE,
+    EXTRACTION_ERRORS_NAME,
+    NR_DISC_SIZE,
+    RangeClass,
+    ROMLayout,
+    ROM_SIZE,
+    SectionError,
+};
+#[cfg(feature = "serde")]
+pub use rebuild_plan::{PlanMismatch, PlanMismatches, PlannedFile, RebuildPlan};
+#[cfg(feature = "serde")]
+pub use refresh::{RefreshSummary, TRASH_NAME};
+pub use rom_rebuilder::{AlignmentError, AlignmentSource, BuildMismatch, IndexConflict, IndexConflicts, RebuildOptions, ROMRebuilder, TreeMismatch, UpdateOutcome};
 
 // 1048576 = 2^20 = 1MiB, there's no real good reason behind this choice
 pub const WRITE_CHUNK_SIZE: usize = 1048576;
 
 // 32KiB
 pub const DEFAULT_ALIGNMENT: u64 = 32 * 1024;
+// Between the apploader, FST, DOL, and the files. A DVD sector, so the
+// system data packs together without landing mid-sector.
+pub const DEFAULT_SYSTEM_ALIGNMENT: u64 = 2048;
 pub const MIN_ALIGNMENT: u64 = 4;
 
-pub mod paths {
-    pub const APPLOADER_PATH: &str = "&&systemdata/Apploader.ldr";
-    pub const DOL_PATH: &str = "&&systemdata/Start.dol";
-    pub const FST_PATH: &str = "&&systemdata/Game.toc";
-    pub const HEADER_PATH: &str = "&&systemdata/ISO.hdr";
-}
-
 pub fn align(n: u64, m: u64) -> u64 {
-    let extra = if n % m == 0 { 0 } else { 1 };
+    let extra = if n.is_multiple_of(m) { 0 } else { 1 };
     ((n / m) + extra) * m
 }
 
@@ -51,18 +114,123 @@ pub fn format_usize(num: usize, style: NumberStyle) -> String {
     }
 }
 
+// A size for people to read, like "1.2 MiB".
+pub fn format_bytes(n: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if n < 1024 {
+        return format!("{} bytes", n);
+    }
+    let mut size = n as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// Like `xxd`, with rows lined up on multiples of 16 of `start`, the address
+// of the first byte.
+pub fn hex_dump(bytes: &[u8], start: u64) -> String {
+    let end = start + bytes.len() as u64;
+    let mut out = String::new();
+    let mut row = start & !0xF;
+    while row < end {
+        let mut hex = String::new();
+        let mut text = String::new();
+        for addr in row..row + 16 {
+            if addr == row + 8 {
+                hex.push(' ');
+            }
+            if (start..end).contains(&addr) {
+                let b = bytes[(addr - start) as usize];
+                hex.push_str(&format!("{:02x} ", b));
+                text.push(if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+            } else {
+                hex.push_str("   ");
+                text.push(' ');
+            }
+        }
+        out.push_str(&format!("{:08x}: {} {}\n", row, hex, text.trim_end()));
+        row += 16;
+    }
+    out
+}
+
+// What 4 or 8 big-endian bytes are as integers and floats, like
+// ["u32: 1065353216 (0x3f800000)", "i32: 1065353216", "f32: 1.0"].
+pub fn describe_value(bytes: &[u8]) -> Vec<String> {
+    if let Ok(b) = <[u8; 4]>::try_from(bytes) {
+        let u = u32::from_be_bytes(b);
+        vec![
+            format!("u32: {} ({:#010x})", u, u),
+            format!("i32: {}", u as i32),
+            format!("f32: {:?}", f32::from_bits(u)),
+        ]
+    } else if let Ok(b) = <[u8; 8]>::try_from(bytes) {
+        let u = u64::from_be_bytes(b);
+        vec![
+            format!("u64: {} ({:#018x})", u, u),
+            format!("i64: {}", u as i64),
+            format!("f64: {:?}", f64::from_bits(u)),
+        ]
+    } else {
+        vec![]
+    }
+}
+
 pub fn parse_as_u64(text: &str) -> Result<u64, ParseIntError> {
     if text.starts_with("0x") || text.starts_with("0X") {
         u64::from_str_radix(&text[2..], 16)
     } else {
-        u64::from_str_radix(text, 10)
+        text.parse::<u64>()
     }
 }
 
+// A number of bytes, like "4096", "0x1000", "64K" or "2M".
+pub fn parse_size(text: &str) -> Option<u64> {
+    let (number, unit) = match text.char_indices().last()? {
+        (i, 'k' | 'K') => (&text[..i], 1024),
+        (i, 'm' | 'M') => (&text[..i], 1024 * 1024),
+        _ => (text, 1),
+    };
+    parse_as_u64(number).ok()?.checked_mul(unit)
+}
+
 pub fn parse_as_usize(text: &str) -> Result<usize, ParseIntError> {
     if text.starts_with("0x") || text.starts_with("0X") {
         usize::from_str_radix(&text[2..], 16)
     } else {
-        usize::from_str_radix(text, 10)
+        text.parse::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dumps_line_up_on_16_bytes() {
+        assert_eq!(hex_dump(b"GSLF01\0\0", 0x8000_3100), format!("80003100: 47 53 4c 46 30 31 00 00 {:26}GSLF01..\n", ""));
+        // Starting and ending partway through rows
+        assert_eq!(hex_dump(&[0xaa; 20], 0x8000_310c), format!(
+            "80003100: {:36} aa aa aa aa  {:12}....\n80003110: {} {}  {}\n",
+            "", "", "aa ".repeat(8), "aa ".repeat(8).trim_end(), ".".repeat(16),
+        ));
+        assert_eq!(hex_dump(&[], 0x10), "");
+    }
+
+    #[test]
+    fn values_of_4_and_8_bytes() {
+        assert_eq!(describe_value(&1.0f32.to_bits().to_be_bytes()), [
+            "u32: 1065353216 (0x3f800000)", "i32: 1065353216", "f32: 1.0",
+        ]);
+        assert_eq!(describe_value(&[0xff; 4]), ["u32: 4294967295 (0xffffffff)", "i32: -1", "f32: NaN"]);
+        assert_eq!(describe_value(&(-2.5f64).to_bits().to_be_bytes()), [
+            "u64: 13836183955189006336 (0xc004000000000000)", "i64: -4610560118520545280", "f64: -2.5",
+        ]);
+        for len in [0, 1, 2, 3, 5, 16] {
+            assert!(describe_value(&vec![0; len]).is_empty(), "{}", len);
+        }
     }
 }
@@ -0,0 +1,203 @@
+// Guesses what kind of file something is from its first bytes, for files
+// whose names don't say, like the extensionless ones some games are full
+// of. Only formats with a recognizable header are here, plus a guess for
+// plain text, which is checked last since almost anything short could be it.
+
+use crate::sections::{rel::REL_MAX_SECTIONS, thp::THP_MAGIC};
+
+// How many bytes from the start of a file `sniff` looks at. Fewer is fine,
+// some checks just fail without enough.
+pub const SNIFF_SIZE: usize = 0x200;
+
+pub const YAZ0_MAGIC: &[u8; 4] = b"Yaz0";
+pub const RARC_MAGIC: &[u8; 4] = b"RARC";
+pub const TPL_MAGIC: &[u8; 4] = &[0x00, 0x20, 0xaf, 0x30];
+
+const DSP_HEADER_SIZE: usize = 0x60;
+const REL_HEADER_MIN_SIZE: usize = 0x40;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileKind {
+    // Compressed with Yaz0, usually an archive underneath
+    Yaz0,
+    Rarc,
+    Tpl,
+    Thp,
+    // A standard DSP ADPCM header, the usual format for sound effects
+    Dsp,
+    Rel,
+    Text,
+}
+
+impl FileKind {
+    // The extension the file would usually have, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            FileKind::Yaz0 => "szs",
+            FileKind::Rarc => "arc",
+            FileKind::Tpl => "tpl",
+            FileKind::Thp => "thp",
+            FileKind::Dsp => "dsp",
+            FileKind::Rel => "rel",
+            FileKind::Text => "txt",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FileKind::Yaz0 => "Yaz0 compressed data",
+            FileKind::Rarc => "RARC archive",
+            FileKind::Tpl => "TPL texture",
+            FileKind::Thp => "THP movie",
+            FileKind::Dsp => "DSP ADPCM audio",
+            FileKind::Rel => "REL module",
+            FileKind::Text => "text",
+        }
+    }
+}
+
+// `start` is the beginning of the file, up to `SNIFF_SIZE` bytes of it.
+pub fn sniff(start: &[u8]) -> Option<FileKind> {
+    let magic = start.get(..4)?;
+    if magic == YAZ0_MAGIC {
+        Some(FileKind::Yaz0)
+    } else if magic == RARC_MAGIC {
+        Some(FileKind::Rarc)
+    } else if magic == TPL_MAGIC {
+        Some(FileKind::Tpl)
+    } else if magic == THP_MAGIC {
+        Some(FileKind::Thp)
+    } else if is_rel(start) {
+        Some(FileKind::Rel)
+    } else if is_dsp(start) {
+        Some(FileKind::Dsp)
+    } else if is_text(start) {
+        Some(FileKind::Text)
+    } else {
+        None
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+// RELs have no magic, but the links to the next and previous modules are
+// always 0 on disc, and the section table comes right after the header,
+// which is a different size for each version.
+fn is_rel(start: &[u8]) -> bool {
+    if start.len() < REL_HEADER_MIN_SIZE {
+        return false;
+    }
+    let section_count = u32_at(start, 0x0c);
+    let section_info_offset = u32_at(start, 0x10);
+    let header_size = match u32_at(start, 0x1c) {
+        1 => 0x40,
+        2 => 0x48,
+        3 => 0x4c,
+        _ => return false,
+    };
+    u32_at(start, 0x04) == 0
+        && u32_at(start, 0x08) == 0
+        && (1..=REL_MAX_SECTIONS).contains(&section_count)
+        && section_info_offset == header_size
+}
+
+// DSP headers have no magic either, so this checks the fields that are only
+// ever one thing: the format is always ADPCM (0), and there's exactly the
+// right number of nibbles for the samples. Each 8 byte frame is a header
+// byte and then 14 samples, and the last frame is only as long as it needs.
+fn is_dsp(start: &[u8]) -> bool {
+    if start.len() < DSP_HEADER_SIZE {
+        return false;
+    }
+    let samples = u32_at(start, 0x00) as u64;
+    let nibbles = u32_at(start, 0x04) as u64;
+    let sample_rate = u32_at(start, 0x08);
+    let looped = u16_at(start, 0x0c);
+    let format = u16_at(start, 0x0e);
+    let expected_nibbles = samples / 14 * 16 + match samples % 14 {
+        0 => 0,
+        rest => rest + 2,
+    };
+    samples != 0
+        && format == 0
+        && looped <= 1
+        && (4000..=96000).contains(&sample_rate)
+        && nibbles == expected_nibbles
+}
+
+// Valid UTF-8 without control characters other than whitespace. The end
+// might cut a character in half, which is fine.
+fn is_text(start: &[u8]) -> bool {
+    let text = match std::str::from_utf8(start) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&start[..e.valid_up_to()]).unwrap(),
+        Err(_) => return false,
+    };
+    !text.trim().is_empty()
+        && text.chars().all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_magic(magic: &[u8]) -> Vec<u8> {
+        let mut start = magic.to_vec();
+        start.resize(SNIFF_SIZE, 0xff);
+        start
+    }
+
+    fn rel(version: u32, section_info_offset: u32) -> Vec<u8> {
+        let mut start = vec![0; 0x60];
+        start[0x0c..0x10].copy_from_slice(&20u32.to_be_bytes());
+        start[0x10..0x14].copy_from_slice(&section_info_offset.to_be_bytes());
+        start[0x1c..0x20].copy_from_slice(&version.to_be_bytes());
+        start
+    }
+
+    fn dsp(samples: u32, nibbles: u32, sample_rate: u32) -> Vec<u8> {
+        let mut start = vec![0; DSP_HEADER_SIZE];
+        start[0x00..0x04].copy_from_slice(&samples.to_be_bytes());
+        start[0x04..0x08].copy_from_slice(&nibbles.to_be_bytes());
+        start[0x08..0x0c].copy_from_slice(&sample_rate.to_be_bytes());
+        start
+    }
+
+    #[test]
+    fn each_kind() {
+        assert_eq!(sniff(&with_magic(YAZ0_MAGIC)), Some(FileKind::Yaz0));
+        assert_eq!(sniff(&with_magic(RARC_MAGIC)), Some(FileKind::Rarc));
+        assert_eq!(sniff(&with_magic(TPL_MAGIC)), Some(FileKind::Tpl));
+        assert_eq!(sniff(&with_magic(THP_MAGIC)), Some(FileKind::Thp));
+        for (version, header_size) in [(1, 0x40), (2, 0x48), (3, 0x4c)] {
+            assert_eq!(sniff(&rel(version, header_size)), Some(FileKind::Rel), "version {}", version);
+        }
+        // 14 samples to a frame, and the last one only as long as it needs
+        assert_eq!(sniff(&dsp(28, 32, 32000)), Some(FileKind::Dsp));
+        assert_eq!(sniff(&dsp(15, 19, 32000)), Some(FileKind::Dsp));
+        assert_eq!(sniff(b"Title: \xe3\x81\x82\r\n\tline two\n"), Some(FileKind::Text));
+        // Cut off in the middle of a character
+        assert_eq!(sniff(b"text \xe3\x81"), Some(FileKind::Text));
+    }
+
+    #[test]
+    fn lookalikes() {
+        assert_eq!(sniff(b"Ya"), None);
+        assert_eq!(sniff(&rel(4, 0x4c)), None);
+        assert_eq!(sniff(&rel(2, 0x40)), None);
+        // A REL header, cut short
+        assert_eq!(sniff(&rel(1, 0x40)[..0x30]), None);
+        assert_eq!(sniff(&dsp(28, 33, 32000)), None);
+        assert_eq!(sniff(&dsp(28, 32, 100)), None);
+        assert_eq!(sniff(&dsp(0, 0, 32000)), None);
+        assert_eq!(sniff(b"   \n\t  "), None);
+        assert_eq!(sniff(b"text\0with a null"), None);
+        assert_eq!(sniff(b"not \xff utf-8"), None);
+    }
+}
@@ -1,105 +1,838 @@
 use std::{
-    fs::{remove_file, File},
-    io::BufReader,
-    path::Path,
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    fs::{self, remove_file, File, OpenOptions},
+    io::{self, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use clap::{clap_app, AppSettings};
+use clap::{clap_app, AppSettings, ArgMatches};
 
 use eyre::{eyre, bail, ensure, OptionExt, WrapErr};
 use gcmod::{
+    AlignmentError,
+    AlignmentSource,
+    build_info,
+    build_report::BuildReport,
+    build_stamp,
+    BuildMismatch,
     DEFAULT_ALIGNMENT,
+    DEFAULT_SYSTEM_ALIGNMENT,
+    EXTRACTION_ERRORS_NAME,
+    ExtractOptions,
+    TRASH_NAME,
     Game,
+    analyzer::AnalyzerRegistry,
+    boot_trace::{BootTrace, Severity},
+    catalog::{Catalog, CATALOG_NAME},
+    compare_layout::LayoutComparison,
+    config::{self, Config},
+    crash,
+    doctor::{self, Operation, Status, SystemProbe},
+    file_order::FileOrderProfile,
+    format_bytes,
     format_u64,
     format_usize,
+    game_ini::{self, GameIni},
+    gecko,
+    grow_fst::GrowPlan,
+    hash::{sha1_of, to_hex, CrcWriter, Sha1Writer},
+    io_util::{self, DedupMode, FinishError, RealFileOps, SyncMode, ZeroExtended},
+    layout_ref::{self, FieldMatch},
+    manifest::{Manifest, MANIFEST_NAME},
+    meta::{diff_values, Metadata, MetadataUpdate},
     MIN_ALIGNMENT,
     NumberStyle,
+    describe_value,
+    hex_dump,
     parse_as_u64,
+    parse_as_usize,
+    parse_capacity,
+    parse_size,
+    profiling,
+    quick_id::quick_id,
+    paths::{SystemPaths, APPLOADER_PATH, DOL_PATH, FST_PATH, HEADER_PATH, SYSTEM_DATA_DIR},
     ROM_SIZE,
+    WRITE_CHUNK_SIZE,
+    RebuildOptions,
+    RebuildPlan,
+    SectionError,
+    replace_batch::{parse_mapping, ReplacePlan},
+    report::{self, ProgressDetail, ProgressStyle, Reporter},
+    rescue::{self, CandidateKind, Confidence},
+    rom_handle::{RomReadHandle, RomWriteHandle},
     ROMRebuilder,
+    UpdateOutcome,
+    scrub::{rewrite_gaps_to_new_file, GapFill},
+    synthetic,
+    style::{ColorChoice, Colors, DEFAULT_LARGE_FILE_SIZE},
+    table::{write_table, TableFormat},
+    timestamp::FakeMtime,
+    titles::Language,
+    tree_check::{self, TreeCheck},
+    workspace::{self, Workspace},
     sections::{
-        apploader::Apploader,
+        apploader::{boot_limit_problem, Apploader, APPLOADER_OFFSET},
+        banner::Banner,
         dol::DOLHeader,
-        fst::FST,
+        fst::{
+            entry::{DirectoryEntry, Entry},
+            probe::ProbeHit,
+            xattrs,
+            ParseOptions,
+            Resolution,
+            FST,
+            INDEX_SELECTOR_PREFIX,
+            SUBTREE_SELECTOR_SUFFIX,
+        },
         header::Header,
+        rel::RelHeader,
+        print_fields,
         Section,
     },
 };
 
 fn main() -> eyre::Result<()> {
+    crash::install(env::args_os().map(|a| a.to_string_lossy().into_owned()).collect());
     let app = clap_app!(app =>
+        (@arg version: -V --version "Prints version information.")
+        (@arg verbose: --verbose requires[version] "With --version, also prints the git commit, enabled features, and supported image formats.")
+        (@arg quiet: -q --quiet +global "Only print results, without status messages or progress.")
+        (@arg no_progress: --("no-progress") +global "Don't print progress. Without this, progress is only updated in place on a terminal.")
+        (@arg color: --color +takes_value +global possible_value[auto always never]
+            "Whether to color listings. `auto` colors them when printing to a terminal, unless NO_COLOR is set.")
+        (@arg progress_json: --("progress-json") +global
+            "Print progress, warnings, and errors to stderr as JSON, one event per line, for frontends. Nothing else goes to stderr. See the README for the events.")
+        (@arg work_dir: --("work-dir") +takes_value +global
+            "Where to put temporary files, like a decompressed ROM that doesn't fit in memory, and crash reports. Defaults to $GCMOD_WORK_DIR, or the system's temporary directory.")
+        (@arg profile: --profile +global conflicts_with[progress_json]
+            "Time the slow parts of the command, like parsing, copying files, and filling gaps, and print a table of them to stderr at the end.")
         (@subcommand extract =>
             (about: "Extract a ROM's contents to disk.")
             (@arg rom_path: +required)
             (@arg output: +required)
-            (@arg rom_section: -s --section +takes_value "Specify a single section to extract from the ROM, rather than everything.")
+            (@arg rom_section: -s --section +takes_value "Specify a single section to extract from the ROM, rather than everything. Files can also be given by index, like idx:12. A directory followed by #fst, like /audio#fst, extracts a standalone FST with just that directory in it.")
+            (@arg rebase_offsets: --("rebase-offsets") requires[rom_section]
+                "With a #fst section, move the files' offsets so the first one is at 0, instead of keeping their offsets on the ROM.")
+            (@arg keep_going: --("keep-going") conflicts_with[rom_section]
+                "Keep extracting when a file can't be extracted, and list the files that failed in extraction-errors.json.")
+            (@arg strict: --strict conflicts_with[rom_section]
+                "Skip files stored inside of the header, apploader, DOL or FST, instead of extracting them with a warning.")
+            (@arg force: --force conflicts_with[rom_section]
+                "Extract into the output directory even if it isn't empty, overwriting any files in the way.")
+            (@arg dedup: --dedup +takes_value possible_value[off hardlink reflink] conflicts_with[rom_section]
+                "Link files with the same contents to the first one extracted, instead of writing them again. Hard linked files share their contents, so editing one edits all of them. Reflinks don't, but need a file system that supports them, and fall back to hard links. Defaults to off.")
+            (@arg xattrs: --xattrs conflicts_with[rom_section]
+                "Record each file's offset, size, and index on the ROM, and the game ID, in its extended attributes, which `gcmod xattr` shows. Hard linked duplicates share one set.")
+            (@arg fail_on_truncated: --("fail-on-truncated") conflicts_with[rom_section]
+                "Treat files that go past the end of a trimmed image as errors, instead of extracting them with zeros for the missing part.")
+            (@arg acknowledge_nkit: --("acknowledge-nkit")
+                "Don't warn that an NKit image's files were moved, so the tree can't be rebuilt into the original dump.")
+            (@arg no_fs_hints: --("no-fs-hints")
+                "Don't tell the OS that the ROM is about to be read in order, or how big each file will be. This only does anything on Linux.")
+            (@arg case_insensitive: --("case-insensitive")
+                "Give names that only differ by case from another new ones, like data~1, even if the output directory is case-sensitive, for a tree that will be copied somewhere that isn't. Without this, it's only done when the output directory isn't.")
+            (@arg refresh: --refresh conflicts_with[rom_section]
+                "Bring an output directory extracted from another version of the ROM up to date with this one, using the hashes in its manifest. Only files that changed or were added are written, and files that aren't on the ROM anymore are moved to .gcmod-trash.")
+            (@arg purge: --purge requires[refresh]
+                "With --refresh, delete the files that aren't on the ROM anymore instead of moving them to .gcmod-trash.")
+            (@arg dol_split: --("dol-split")
+                "Also write each of the DOL's segments to its own file in Start.dol.d, next to Start.dol, like unpack-dol does, with a segments.json that repack-dol can put them back together from, a bss.txt with the BSS's address and size, and a layout.ld linker script. Works with the whole ROM or with -s &&systemdata/Start.dol.")
+            (@arg infer_extensions: --("infer-extensions") +takes_value min_values(0) require_equals(true) possible_value[report] conflicts_with[rom_section]
+                "Give files without an extension one based on what's in them, like .szs for Yaz0 compressed files. The manifest records the original names, so rebuilding puts them back. With =report, just list what would be renamed without extracting anything.")
         )
         (@subcommand info =>
             (about: "Display information about the ROM.")
             (@arg rom_path: +required)
             (@arg hex_output: -h --hex "Displays numbers in hexadecimal.")
             (@arg type: -t --type +takes_value +case_insensitive
-                possible_value[header dol fst apploader layout]
+                possible_value[header dol fst apploader banner layout rel]
                 "Print a given type of information about the ROM.")
             (@arg offset: -o --offset +takes_value
                 conflicts_with[type mem_addr]
-                "Print information about whichever section is at the given offset.")
+                "Print information about whichever section is at the given offset, or about the unused space around it.")
             (@arg mem_addr: -m --("mem-addr") +takes_value
                 conflicts_with[type offset]
                 "Print information about the DOL segment that will be loaded into a given address in memory.")
+            (@arg read: --read +takes_value requires[mem_addr]
+                "With --mem-addr, also print this many bytes from the DOL that get loaded at that address. 4 or 8 bytes are also shown as numbers.")
+            (@arg entry_path: -p --path +takes_value
+                conflicts_with[type offset mem_addr]
+                "Print information about the file or directory at the given path in the ROM.")
+            (@arg entry_index: -i --index +takes_value
+                conflicts_with[type offset mem_addr entry_path]
+                "Print information about the file or directory with the given index in the file system table.")
+            (@arg assets: --assets
+                conflicts_with[type offset mem_addr entry_path entry_index]
+                "Print a summary of the banner, textures, movies, sound banks, and modules in the file system.")
+            (@arg analyzers: --analyzers +takes_value
+                conflicts_with[type offset mem_addr entry_path entry_index assets]
+                "Run these analyzers, separated by commas, like region,scrub, and print each one's report. \"list\" lists them.")
+            (@arg format: --format +takes_value possible_value[text json] requires[analyzers]
+                "How to print the analyzers' reports.")
+            (@arg stamp: --stamp
+                conflicts_with[type offset mem_addr entry_path entry_index assets analyzers]
+                "Print the build stamp `rebuild --stamp` wrote into the header: which gcmod built the ROM, when, and the hashes of its files and layout options.")
+            (@arg json: --json
+                conflicts_with[offset mem_addr entry_path entry_index assets analyzers stamp]
+                "Print the summary, or whichever -t type is given, as JSON. Numbers are always decimal, so --hex doesn't change anything.")
+            (@arg lang: --lang +takes_value
+                "Show the game's title in this language, like en, ja, or german, from the banner if it has one. By default, it's the language of the game's region.")
+            (@arg verbose: -v --verbose
+                "Also list every title the game has: the header's, and the short and long ones in each of the banner's languages.")
+        )
+        (@subcommand explain =>
+            (about: "Print which fixed field of the header, bi2, the apploader's header, or the DOL's header is at an offset. Doesn't need a ROM.")
+            (@arg offset: +required
+                "An offset in the image, or in the DOL. Fields it could be in either way are both shown.")
         )
         // TODO: add flags for searching and crap
-        // Add more `ls` style flags (LS_COLORS!)
+        // Add more `ls` style flags
         // Add a flag to recursively list, default to / or the dir they pass
         (@subcommand ls =>
             (about: "Lists the files on the ROM.")
             (@arg rom_path: +required)
-            (@arg dir: "The name or path of the directory in the ROM to list.")
+            (@arg dir: "The name or path of the directory in the ROM to list, or its index, like idx:12.")
             (@arg long: -l --long "List the files in an `ls -l`-style format.")
+            (@arg directory: -d --directory conflicts_with[format] "List the directory itself instead of what's in it.")
+            (@arg fake_mtime: --("fake-mtime") +takes_value requires[long]
+                "Give every entry a made-up time, since the ROM has none: \"apploader\" for the apploader's date, \"epoch\", or a time like 2002-07-19T12:00:00Z.")
+            (@arg large: --large +takes_value
+                "Highlight files at least this many bytes long. Defaults to 16MiB.")
+            (@arg format: --format +takes_value possible_value[csv tsv] conflicts_with[long]
+                "List everything in the directory, all the way down, as a table for spreadsheets.")
+            (@arg no_header: --("no-header") requires[format] "Leave out the table's header row.")
+            (@arg out: --out +takes_value requires[format] "Write the table to this file instead of stdout.")
+            (@arg recursive: -R --recursive conflicts_with[directory format]
+                "List everything in the directory, all the way down.")
+            (@arg json: --json conflicts_with[long format]
+                "Print each entry as a JSON object on its own line, with its name, path, type, size, offset, and index.")
         )
         (@subcommand rebuild =>
             (about: "Rebuilds a ROM.")
             (@arg root_path: +required)
-            (@arg output: +required)
+            (@arg output: required_unless[update plan_out])
+            (@arg update: --update +takes_value conflicts_with[output]
+                "Update a ROM previously rebuilt from the same directory, only rewriting the files that changed since then.")
             (@arg no_rebuild_fst: --("no-rebuild-fst") "It this flag is passed, the existing file system table will be used, rather than creating a new one.")
-            (@arg alignment: -a --alignment +takes_value
-                "Specifies the alignment in bytes for the files in the filesystem. The default is 32768 bytes (32KiB) and the minimum is 2 bytes.")
+            (@arg preserve_indices: --("preserve-indices") conflicts_with[no_rebuild_fst]
+                "Give every entry in the new file system table the same index it had on the ROM it was extracted from.")
+            (@arg prune_missing: --("prune-missing") requires[no_rebuild_fst]
+                "With --no-rebuild-fst, leave files that were deleted from the tree on the ROM as empty files, rather than failing.")
+            (@arg keep_user_fields: --("keep-user-fields")
+                "Keep the user position and length from the existing header, rather than setting them to where the files are.")
+            (@arg capacity: --capacity +takes_value
+                "The size of the disc to build, either \"retail\" (the default), \"nr\", \"dual-layer\", or a number of bytes.")
+            (@arg ignore_boot_limits: --("ignore-boot-limits")
+                "Rebuild even if the apploader and FST are too big for a real console to boot, like for ROMs only meant for emulators.")
+            (@arg ignore_debug_monitor: --("ignore-debug-monitor")
+                "Put files in the area a development image's header reserves for the debug monitor, and take the debug monitor out of the header, instead of leaving the area alone.")
+            (@arg file_alignment: -a --("file-alignment") +takes_value alias("alignment")
+                "Specifies the alignment in bytes for the files in the filesystem. The default is 32768 bytes (32KiB). It has to be a multiple of 4 bytes, and should be a power of two.")
+            (@arg extend: --extend requires[update]
+                "If the ROM being updated is shorter than the capacity, like a trimmed image, grow it to full size so it can be updated in place.")
+            (@arg verify_after: --("verify-after") +takes_value min_values(0) require_equals(true) possible_value[full]
+                "Open the ROM again after writing it, and check it for problems and against what was built. With =full, also compare every file on it to its source file.")
+            (@arg system_alignment: --("system-alignment") +takes_value
+                "Specifies the alignment in bytes for the FST, the DOL, and the start of the files. The default is 2048 bytes. It has to be a multiple of 4 bytes, and should be a power of two.")
+            (@arg allow_mixed: --("allow-mixed")
+                "Rebuild even if the tree's ISO.hdr is for a different game than the one it was extracted from.")
+            (@arg acknowledge_nkit: --("acknowledge-nkit")
+                "Don't warn that a tree extracted from an NKit image can't be rebuilt into the original dump.")
+            (@arg no_sparse_gaps: --("no-sparse-gaps")
+                "Write out the zeros in big gaps between files, instead of leaving holes in the new file.")
+            (@arg sync: --sync +takes_value possible_value[none data full]
+                "How much to make sure is on the disk before finishing, for removable drives: \"none\" (the default) leaves it to the OS, \"data\" waits for the ROM's contents before moving it into place, and \"full\" also waits for the move itself.")
+            (@arg jobs: -j --jobs +takes_value
+                "With 2 or more, read the source files on a separate thread while writing the ROM, keeping up to this many 1MiB chunks in memory. The default is 1, which does one thing at a time.")
+            (@arg report: --report +takes_value conflicts_with[update]
+                "Write a report on the build to this file, with the options used, where everything went, and the ROM's SHA-1.")
+            (@arg report_format: --("report-format") +takes_value possible_value[text json] requires[report]
+                "The format of the report. Defaults to text.")
+            (@arg plan_out: --("plan-out") +takes_value conflicts_with[output update plan_in]
+                "Work out where everything goes and write that to this file as JSON, without building the ROM. Build it later with --plan-in.")
+            (@arg plan_hashes: --("plan-hashes") requires[plan_out]
+                "Record each file's SHA-1 in the plan, so --plan-in notices files that changed without changing size.")
+            (@arg file_order: --("file-order") +takes_value conflicts_with[no_rebuild_fst]
+                "Put the files listed in this profile first, in its order, like one from `layout-profile import`, or a JSON list of paths. The rest follow in the order the file system table lists them.")
+            (@arg plan_in: --("plan-in") +takes_value conflicts_with[update no_rebuild_fst preserve_indices report file_order]
+                "Build the ROM from a plan written by --plan-out, after checking the files are still the ones that were planned. The layout options are the plan's.")
+            (@arg stamp: --stamp conflicts_with[plan_in]
+                "Write a build stamp into the header, with this gcmod's version, the time, and hashes of the files and the layout options, which `info --stamp` shows and `verify --check-stamp` checks. Without it, a stamp the tree's header already has is taken out.")
+        )
+        (@subcommand diff =>
+            (about: "Compares the file systems of two ROMs.")
+            (@arg rom_a: +required)
+            (@arg rom_b: +required)
+            (@arg indices: --indices "Show how the file system table index of each path changed.")
+        )
+        (@subcommand compare_layout =>
+            (name: "compare-layout")
+            (about: "Shows how two ROMs lay out the files they both have, like a retail ROM and one rebuilt from it.")
+            (@arg rom_a: +required)
+            (@arg rom_b: +required)
+            (@arg format: --format +takes_value possible_value[text json]
+                "How to print the comparison. The default is text: each file's offset in both ROMs, how far it moved, and whether the file before it changed, then the files only one has, then the totals.")
+        )
+        (@subcommand layout_profile =>
+            (name: "layout-profile")
+            (about: "Makes profiles of the order a game reads its files in, for rebuild --file-order.")
+            (@setting SubcommandRequiredElseHelp)
+            (@setting VersionlessSubcommands)
+            (@subcommand import =>
+                (about: "Makes a profile from a Dolphin log with the FileMon log type on, with each file in the order it was first read.")
+                (@arg log: +required)
+                (@arg out: --out +takes_value +required "The file to write the profile to.")
+                (@arg rom: --rom +takes_value
+                    "Match the paths in the log to the files on this ROM, ignoring case, and leave out and list the ones that aren't on it.")
+            )
+        )
+        (@subcommand dump_meta =>
+            (name: "dump-meta")
+            (about: "Writes everything gcmod knows about a ROM as JSON, for use by other programs.")
+            (@arg rom_path: +required)
+            (@arg out: --out +takes_value "The file to write to, rather than stdout.")
+        )
+        (@subcommand apply_meta =>
+            (name: "apply-meta")
+            (about: "Writes the header and banner text from a document made by dump-meta to a ROM.")
+            (@arg rom_path: +required)
+            (@arg meta_path: +required)
+        )
+        (@subcommand set =>
+            (about: "Changes fields in the header of a ROM or an extracted ROM.")
+            (@arg rom_path: +required "A ROM, or the root of an extracted ROM.")
+            (@arg audio_streaming: --("audio-streaming") +takes_value possible_value[on off]
+                "Turns audio streaming on or off.")
+            (@arg stream_buffer: --("stream-buffer") +takes_value requires[audio_streaming]
+                "The stream buffer size to use when turning audio streaming on, from 1 to 15.")
+            (@arg disc: --disc +takes_value
+                "The disc number of this disc in a multi-disc game, starting from 1.")
+            (@arg total_discs: --("total-discs") +takes_value requires[disc]
+                "The number of discs in the game. Defaults to at least the disc number.")
+        )
+        (@subcommand verify =>
+            (about: "Checks a ROM for problems. Exits with an error if it has any that would stop it from working.")
+            (@arg rom_path: +required)
+            (@arg strict_fst: --("strict-fst")
+                "Also check that the file system table follows all the rules retail ones do, and count breaking any of them as an error.")
+            (@arg check_stamp: --("check-stamp")
+                "Also check that the ROM has a build stamp from `rebuild --stamp`, and that its files are still the ones it was built from.")
+        )
+        (@subcommand boot_trace =>
+            (name: "boot-trace")
+            (about: "Goes through what booting a ROM would do, step by step, and notes where anything would go wrong.")
+            (@arg rom_path: +required)
+            (@arg format: --format +takes_value possible_value[text json]
+                "How to print the trace. The default is text: each step, with what it copies from where on the disc to where in RAM, and any problems under it.")
+        )
+        (@subcommand exec =>
+            (about: "Checks a ROM, and only if it passes, runs a command with it, like an emulator. The command opens the ROM's path again, unless --pass-fd gives it the file that was checked.")
+            (@arg rom_path: +required)
+            (@arg require: --require +takes_value
+                "The checks to run first, separated by commas: verify, for what `verify` checks, and boot-check, for what `boot-trace` would stop at. The default is verify.")
+            (@arg pass_fd: --("pass-fd")
+                "Give the command /proc/self/fd/N for the ROM instead of its path, so it reads the same file that was checked. Without this, the ROM could be replaced between the checks and the command opening its path. Only on Linux.")
+            (@arg command: +required +multiple +last
+                "The command to run, after --. Every {} in it is replaced with the ROM, and the ROM is added to the end if there isn't one.")
+        )
+        (@subcommand init =>
+            (about: "Creates the system data for a new ROM from scratch, ready to be rebuilt.")
+            (@arg dir: +required "The directory to create, which will be the root of the ROM.")
+            (@arg game_code: --("game-code") +takes_value +required "The 4 character game code. The last character is the region.")
+            (@arg maker_code: --("maker-code") +takes_value +required "The 2 character maker code.")
+            (@arg title: --title +takes_value +required)
+            (@arg dol: --dol +takes_value +required "The DOL to boot.")
+            (@arg apploader: --apploader +takes_value
+                "The apploader to use. Without one, the ROM will only boot in emulators that skip the apploader.")
+        )
+        (@subcommand self_test =>
+            (name: "self-test")
+            (about: "Checks that gcmod works here: builds a small ROM, extracts it, rebuilds it, and checks it comes out the same.")
+            (@arg with: --with +takes_value
+                "Check a real ROM instead, only reading it: parse it, check its layout for problems, and hash it.")
+        )
+        (@subcommand doctor =>
+            (about: "Checks for problems with where a ROM is being extracted or rebuilt, like not enough space, before they come up.")
+            (@arg for: --for +takes_value possible_value[extract rebuild]
+                "What the paths are for, so the input, then the destination. Without it, every path is checked as an input.")
+            (@arg paths: ... "ROMs or extracted ROMs, and with --for, the destination after the input.")
+        )
+        (@subcommand config =>
+            (about: "Shows the defaults for options set in config files.")
+            (@setting SubcommandRequiredElseHelp)
+            (@setting VersionlessSubcommands)
+            (@subcommand show =>
+                (about: "Prints the defaults from every config file together, with the file each one came from.")
+            )
+        )
+        (@subcommand clean_workdir =>
+            (name: "clean-workdir")
+            (about: "Removes what gcmod left in the work directory, like what failed commands were working on and crash reports.")
+            (@arg older_than: --("older-than") +takes_value
+                "Only remove what's at least this many hours old. Defaults to 24, so nothing a running command is using is removed.")
+            (@arg dry_run: -n --("dry-run") "List what would be removed without removing it.")
+        )
+        (@subcommand catalog =>
+            (about: "Keeps an index of the ROMs in a directory, so they can be listed and searched quickly.")
+            (@arg dir: +required)
+            (@arg refresh: --refresh conflicts_with[list find match] "Parse every ROM again, even ones that haven't changed.")
+            (@arg list: --list conflicts_with[find match] "List the ROMs in the index without scanning the directory.")
+            (@arg find: --find +takes_value conflicts_with[match] "List the ROMs in the index whose game ID or any of whose titles contains this, from the header or the banner.")
+            (@arg match: --match +takes_value
+                "List the ROMs in the index that are the same dump as this one, by quick-id (see `gcmod hash --quick`), without scanning the directory.")
+            (@arg full: --full
+                "Also hash every ROM in full when scanning, which reads all of it. With --match, match by those hashes instead of quick-ids.")
+        )
+        (@subcommand hash =>
+            (about: "Prints the SHA-1 and CRC-32 of a ROM, or with --quick, a quick-id that identifies the dump without reading all of it.")
+            (@arg rom_path: +required)
+            (@arg quick: --quick
+                "Only hash the header, FST, DOL, and the start and end of the file data. That's enough to tell dumps apart, but not to check one is intact.")
+        )
+        (@subcommand scrub =>
+            (about: "Copies a ROM, zeroing all of the bytes that aren't part of any section.")
+            (@arg rom_path: +required)
+            (@arg output: +required)
+            (@arg force: -f --force "Run even if the ROM has problems, like sections that overlap, a repaired FST, or anything that would stop it from booting.")
+            (@arg capacity: --capacity +takes_value
+                "The size of the disc to write, either \"retail\", \"nr\", \"dual-layer\", or a number of bytes. Defaults to the ROM's own size, or retail for a trimmed image.")
+            (@arg expect_crc: --("expect-crc") +takes_value "Fail, leaving no output, if its CRC-32 isn't this (hexadecimal) value.")
+        )
+        (@subcommand unscrub =>
+            (about: "Copies a ROM, regenerating the original junk data in all of the bytes that aren't part of any section.")
+            (@arg rom_path: +required)
+            (@arg output: +required)
+            (@arg force: -f --force "Run even if the ROM has problems, like sections that overlap, a repaired FST, or anything that would stop it from booting.")
+            (@arg capacity: --capacity +takes_value
+                "The size of the disc to write, either \"retail\", \"nr\", \"dual-layer\", or a number of bytes. Defaults to the ROM's own size, or retail for a trimmed image.")
+            (@arg expect_crc: --("expect-crc") +takes_value "Fail, leaving no output, if its CRC-32 isn't this (hexadecimal) value.")
+        )
+        (@subcommand rescue =>
+            (about: "Searches a ROM with a damaged header for its apploader, DOL, and FST, and lists what it finds.")
+            (@arg rom_path: +required)
+            (@arg extract: --extract +takes_value "Extract the ROM to this directory using the most likely DOL and FST found.")
+        )
+        (@subcommand analyze_refs =>
+            (name: "analyze-refs")
+            (about: "Guesses which files the DOL loads, by looking for their paths in its data segments.")
+            (@arg rom_path: required_unless[dol])
+            (@arg dol: --dol +takes_value requires[fst] conflicts_with[rom_path]
+                "Read the DOL from this file, like an extracted Start.dol, instead of from a ROM.")
+            (@arg fst: --fst +takes_value requires[dol]
+                "With --dol, read the file system table from this file, like an extracted Game.toc.")
+            (@arg hex_output: -h --hex "Displays numbers in hexadecimal.")
+        )
+        (@subcommand unpack_dol =>
+            (name: "unpack-dol")
+            (about: "Writes each segment of a DOL to its own file, with a segments.json that repack-dol uses to put them back together, a bss.txt, and a layout.ld linker script.")
+            (@arg input: +required "A ROM, or a DOL on its own, like an extracted Start.dol.")
+            (@arg dir: +required "The directory to write the segments to.")
+            (@arg force: -f --force "Write the segments even if the directory isn't empty.")
+        )
+        (@subcommand repack_dol =>
+            (name: "repack-dol")
+            (about: "Builds a DOL from the segments in a directory made by unpack-dol.")
+            (@arg dir: +required)
+            (@arg output: +required)
+        )
+        (@subcommand export_ini =>
+            (name: "export-ini")
+            (about: "Writes a Dolphin game settings file for a ROM, named for its game ID, with the ROM's details in comments and empty sections to fill in.")
+            (@arg rom_path: +required)
+            (@arg out: -o --out +takes_value
+                "The file to write, or a directory to write it to under the name Dolphin looks for. Defaults to the current directory.")
+            (@arg gecko: --gecko +takes_value "Put the Gecko codes in this file, in Dolphin's format, in the [Gecko] section as they are.")
+            (@arg wiki: --wiki conflicts_with[out gecko force] "Print the ROM's details as a wiki infobox instead.")
+            (@arg force: -f --force "Replace the file if it already exists.")
         )
-    ).setting(AppSettings::SubcommandRequired);
+        (@subcommand probe =>
+            (about: "Checks whether the ROM has a file or directory at a path, without reading all of its file system table. Exits with 3 if it doesn't.")
+            (@arg rom_path: +required)
+            (@arg path: +required "The path on the ROM, like /audio/bgm.dsp.")
+        )
+        (@subcommand grow_fst =>
+            (name: "grow-fst")
+            (about: "Makes room for the ROM's file system table to grow in place, moving the DOL or any files right after it elsewhere on the ROM.")
+            (@arg rom_path: +required)
+            (@arg headroom: --headroom +takes_value +required "How many more bytes the file system table can take up, like 64K.")
+        )
+        (@subcommand replace_batch =>
+            (name: "replace-batch")
+            (about: "Replaces files on the ROM in place, all at once, from a file with lines like \"/audio/bgm.dsp = new/bgm.dsp\". Files that got bigger move to free space on the ROM.")
+            (@arg rom_path: +required)
+            (@arg mapping: +required "The file listing the replacements. Local paths in it are relative to where it is.")
+        )
+        (@subcommand xattr =>
+            (about: "Shows where on the ROM a file extracted with --xattrs came from.")
+            (@arg file: +required)
+        )
+    )
+        .setting(AppSettings::DisableVersion)
+        .setting(AppSettings::VersionlessSubcommands)
+        .setting(AppSettings::ArgRequiredElseHelp)
+        // So a flag on the command line can replace one from a config file
+        .global_setting(AppSettings::AllArgsOverrideSelf);
+
+    let config = Config::load()?;
+    // The options before the command that take a value, as they'd be
+    // written on the command line
+    let value_options: Vec<String> = app.p.opts.iter()
+        .flat_map(|o| o.s.long.map(|l| format!("--{}", l)).into_iter().chain(o.s.short.map(|c| format!("-{}", c))))
+        .collect();
+    let (args, with_defaults) = add_defaults(env::args_os().collect(), &config, &value_options);
+    // Nothing but events go to stderr with --progress-json, even when the
+    // command line is wrong
+    let json_command = args.iter().any(|a| a == "--progress-json").then(|| {
+        command_index(&args, &value_options).map_or_else(String::new, |i| args[i].to_string_lossy().into_owned())
+    });
+    let matches = match app.get_matches_from_safe(args) {
+        Ok(matches) => matches,
+        Err(e) if e.use_stderr() && json_command.is_some() => {
+            report::start_events(&json_command.unwrap());
+            report::finish_events(Some(e.message));
+            std::process::exit(1)
+        },
+        Err(e) if with_defaults && e.use_stderr() => {
+            eprintln!("{}\n\nSome of these options came from config files, see `gcmod config show`.", e.message);
+            std::process::exit(1)
+        },
+        Err(e) => e.exit(),
+    };
+    if matches.is_present("version") {
+        print_version(matches.is_present("verbose"));
+        return Ok(());
+    }
+
+    let mut profile = None;
+    if let (command, Some(cmd)) = matches.subcommand() {
+        crash::set_command(command);
+        if let Some(dir) = cmd.value_of("work_dir") {
+            workspace::set_work_dir(dir).wrap_err_with(|| format!("Couldn't create the work directory {}", dir))?;
+        }
+        if cmd.is_present("profile") {
+            profile = profiling::collect();
+        }
+        // The crash hook sends the done event if there's a panic
+        if cmd.is_present("progress_json") {
+            report::start_events(command);
+        }
+    }
+    crash::induce_panic_if_asked();
 
-    match app.get_matches().subcommand() {
+    let result = match matches.subcommand() {
+        ("extract", Some(cmd)) if cmd.value_of("infer_extensions") == Some("report") =>
+            report_inferred_extensions(cmd.value_of("rom_path").unwrap()),
+        ("extract", Some(cmd)) if cmd.is_present("refresh") =>
+            refresh_extraction(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("output").unwrap(),
+                extract_options(cmd),
+                cmd.is_present("purge"),
+                &reporter(cmd),
+            ),
         ("extract", Some(cmd)) =>
             extract_iso(
                 cmd.value_of("rom_path").unwrap(),
                 cmd.value_of("output").unwrap(),
                 cmd.value_of("rom_section"),
+                cmd.is_present("rebase_offsets"),
+                extract_options(cmd),
+                &reporter(cmd),
             ),
-        ("info", Some(cmd)) =>
-            get_info(
+        ("info", Some(cmd)) => get_info(cmd),
+        ("explain", Some(cmd)) => explain(cmd.value_of("offset").unwrap()),
+        ("ls", Some(cmd)) if cmd.is_present("format") =>
+            write_file_table(
                 cmd.value_of("rom_path").unwrap(),
-                cmd.value_of("type"),
-                cmd.value_of("offset"),
-                cmd.value_of("mem_addr"),
-                if cmd.is_present("hex_output") {
-                    NumberStyle::Hexadecimal
-                } else {
-                    NumberStyle::Decimal
-                },
+                cmd.value_of("dir"),
+                TableFormat::parse(cmd.value_of("format").unwrap()).unwrap(),
+                !cmd.is_present("no_header"),
+                cmd.value_of("out"),
             ),
         ("ls", Some(cmd)) =>
             ls_files(
                 cmd.value_of("rom_path").unwrap(),
                 cmd.value_of("dir"),
-                cmd.is_present("long"),
+                ListStyle {
+                    long: cmd.is_present("long"),
+                    directory: cmd.is_present("directory"),
+                    recursive: cmd.is_present("recursive"),
+                    json: cmd.is_present("json"),
+                },
+                cmd.value_of("fake_mtime"),
+                &colors(cmd)?,
             ),
         ("rebuild", Some(cmd)) =>
-            rebuild_iso(
+            rebuild_options(cmd).and_then(|options| rebuild_iso(
                 cmd.value_of("root_path").unwrap(),
+                cmd.value_of("output"),
+                cmd.value_of("update"),
+                cmd.is_present("extend"),
+                &options,
+                &RebuildSteps {
+                    allow_mixed: cmd.is_present("allow_mixed"),
+                    acknowledge_nkit: cmd.is_present("acknowledge_nkit"),
+                    sparse_gaps: !cmd.is_present("no_sparse_gaps"),
+                    verify: cmd.is_present("verify_after").then(|| match cmd.value_of("verify_after") {
+                        Some(_) => VerifyAfter::Full,
+                        None => VerifyAfter::Plan,
+                    }),
+                    report: cmd.value_of("report").map(|path| match cmd.value_of("report_format") {
+                        Some("json") => (path, ReportFormat::Json),
+                        _ => (path, ReportFormat::Text),
+                    }),
+                    plan_out: cmd.value_of("plan_out").map(|path| (path, cmd.is_present("plan_hashes"))),
+                    plan_in: cmd.value_of("plan_in"),
+                    sync: match cmd.value_of("sync") {
+                        Some("full") => SyncMode::Full,
+                        Some("data") => SyncMode::Data,
+                        _ => SyncMode::None,
+                    },
+                },
+                &reporter(cmd),
+            )),
+        ("diff", Some(cmd)) =>
+            diff_roms(
+                cmd.value_of("rom_a").unwrap(),
+                cmd.value_of("rom_b").unwrap(),
+                cmd.is_present("indices"),
+            ),
+        ("compare-layout", Some(cmd)) =>
+            compare_layout(
+                cmd.value_of("rom_a").unwrap(),
+                cmd.value_of("rom_b").unwrap(),
+                cmd.value_of("format") == Some("json"),
+            ),
+        ("layout-profile", Some(cmd)) => match cmd.subcommand() {
+            ("import", Some(cmd)) =>
+                import_layout_profile(
+                    cmd.value_of("log").unwrap(),
+                    cmd.value_of("out").unwrap(),
+                    cmd.value_of("rom"),
+                ),
+            _ => Err(eyre!("No layout-profile subcommand given, see gcmod layout-profile --help")),
+        },
+        ("dump-meta", Some(cmd)) =>
+            dump_metadata(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("out"),
+            ),
+        ("apply-meta", Some(cmd)) =>
+            apply_metadata(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("meta_path").unwrap(),
+            ),
+        ("set", Some(cmd)) =>
+            set_header_fields(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("audio_streaming").map(|v| v == "on"),
+                cmd.value_of("stream_buffer"),
+                cmd.value_of("disc"),
+                cmd.value_of("total_discs"),
+            ),
+        ("verify", Some(cmd)) =>
+            verify_rom(cmd.value_of("rom_path").unwrap(), cmd.is_present("strict_fst"), cmd.is_present("check_stamp")),
+        ("boot-trace", Some(cmd)) =>
+            boot_trace(cmd.value_of("rom_path").unwrap(), cmd.value_of("format") == Some("json")),
+        ("exec", Some(cmd)) =>
+            exec_rom(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("require").unwrap_or("verify"),
+                cmd.is_present("pass_fd"),
+                cmd.values_of_os("command").unwrap().collect(),
+            ),
+        ("init", Some(cmd)) =>
+            init_project(
+                cmd.value_of("dir").unwrap(),
+                cmd.value_of("game_code").unwrap(),
+                cmd.value_of("maker_code").unwrap(),
+                cmd.value_of("title").unwrap(),
+                cmd.value_of("dol").unwrap(),
+                cmd.value_of("apploader"),
+                &reporter(cmd),
+            ),
+        ("doctor", Some(cmd)) =>
+            run_doctor(
+                cmd.value_of("for").and_then(Operation::parse),
+                cmd.values_of("paths").into_iter().flatten().map(PathBuf::from).collect(),
+            ),
+        ("config", Some(cmd)) => match cmd.subcommand() {
+            ("show", Some(_)) => { show_config(&config); Ok(()) },
+            _ => Err(eyre!("No config subcommand given, see gcmod config --help")),
+        },
+        ("self-test", Some(cmd)) =>
+            self_test(cmd.value_of("with")),
+        ("clean-workdir", Some(cmd)) =>
+            clean_work_dir(cmd.value_of("older_than"), cmd.is_present("dry_run")),
+        ("catalog", Some(cmd)) =>
+            catalog_roms(
+                cmd.value_of("dir").unwrap(),
+                cmd.is_present("refresh"),
+                cmd.is_present("list"),
+                cmd.value_of("find"),
+                cmd.value_of("match"),
+                cmd.is_present("full"),
+                &reporter(cmd),
+            ),
+        ("hash", Some(cmd)) =>
+            hash_rom(cmd.value_of("rom_path").unwrap(), cmd.is_present("quick"), &reporter(cmd)),
+        ("scrub", Some(cmd)) =>
+            rewrite_rom_gaps(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("output").unwrap(),
+                false,
+                cmd.is_present("force"),
+                cmd.value_of("capacity"),
+                cmd.value_of("expect_crc"),
+                &reporter(cmd),
+            ),
+        ("unscrub", Some(cmd)) =>
+            rewrite_rom_gaps(
+                cmd.value_of("rom_path").unwrap(),
                 cmd.value_of("output").unwrap(),
-                cmd.value_of("alignment"),
-                !cmd.is_present("no_rebuild_fst"),
+                true,
+                cmd.is_present("force"),
+                cmd.value_of("capacity"),
+                cmd.value_of("expect_crc"),
+                &reporter(cmd),
+            ),
+        ("rescue", Some(cmd)) =>
+            rescue_rom(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("extract"),
+                &reporter(cmd),
+            ),
+        ("analyze-refs", Some(cmd)) =>
+            analyze_references(
+                cmd.value_of("rom_path"),
+                cmd.value_of("dol").zip(cmd.value_of("fst")),
+                if cmd.is_present("hex_output") { NumberStyle::Hexadecimal } else { NumberStyle::Decimal },
+            ),
+        ("unpack-dol", Some(cmd)) =>
+            unpack_dol(
+                cmd.value_of("input").unwrap(),
+                cmd.value_of("dir").unwrap(),
+                cmd.is_present("force"),
             ),
-        _ => unreachable!(),
+        ("repack-dol", Some(cmd)) =>
+            repack_dol(cmd.value_of("dir").unwrap(), cmd.value_of("output").unwrap()),
+        ("export-ini", Some(cmd)) =>
+            export_ini(
+                cmd.value_of("rom_path").unwrap(),
+                cmd.value_of("out"),
+                cmd.value_of("gecko"),
+                cmd.is_present("wiki"),
+                cmd.is_present("force"),
+            ),
+        ("probe", Some(cmd)) =>
+            probe_rom(cmd.value_of("rom_path").unwrap(), cmd.value_of("path").unwrap()),
+        ("grow-fst", Some(cmd)) =>
+            grow_fst(cmd.value_of("rom_path").unwrap(), cmd.value_of("headroom").unwrap()),
+        ("replace-batch", Some(cmd)) =>
+            replace_batch(cmd.value_of("rom_path").unwrap(), cmd.value_of("mapping").unwrap()),
+        ("xattr", Some(cmd)) =>
+            show_origin_attributes(cmd.value_of("file").unwrap()),
+        _ => Err(eyre!("No subcommand given, see --help")),
+    };
+
+    if let Some(collector) = profile {
+        eprint!("\n{}", collector.table());
+    }
+    if !report::json_events() {
+        return result;
+    }
+    // The error is in the done event, so it isn't printed again
+    report::finish_events(result.as_ref().err().map(|e| format!("{:#}", e)));
+    std::process::exit(if result.is_ok() { 0 } else { 1 })
+}
+
+// Puts the arguments for the defaults in with `args`: the global ones right
+// after the program name, and the command's right after the command. Since
+// every option replaces itself, the ones actually passed, which come later,
+// win. Also returns whether any were added. `value_options` are the options
+// that can come before the command whose value is the next argument, unless
+// it's joined on with `=`.
+fn add_defaults(args: Vec<OsString>, config: &Config, value_options: &[String]) -> (Vec<OsString>, bool) {
+    let Some(i) = command_index(&args, value_options) else {
+        return (args, false);
+    };
+    let command = args[i].to_string_lossy().into_owned();
+
+    let global_args = config.args(None);
+    let command_args = config.args(Some(&command));
+    let added = !global_args.is_empty() || !command_args.is_empty();
+    let mut merged = Vec::with_capacity(args.len() + global_args.len() + command_args.len());
+    merged.push(args[0].clone());
+    merged.extend(global_args.into_iter().map(OsString::from));
+    merged.extend_from_slice(&args[1..=i]);
+    merged.extend(command_args.into_iter().map(OsString::from));
+    merged.extend_from_slice(&args[i + 1..]);
+    (merged, added)
+}
+
+// Where the command is in `args`, past the options before it, see
+// `add_defaults`.
+fn command_index(args: &[OsString], value_options: &[String]) -> Option<usize> {
+    let mut i = 1;
+    loop {
+        match args.get(i).map(|a| a.to_string_lossy()) {
+            None => return None,
+            Some(a) if value_options.iter().any(|o| *o == a) => i += 2,
+            Some(a) if a.starts_with('-') => i += 1,
+            Some(_) => return Some(i),
+        }
+    }
+}
+
+fn show_config(config: &Config) {
+    if config.layers.is_empty() {
+        let user = config::user_config_path()
+            .map_or_else(|| "~/.config/gcmod/config.toml".to_owned(), |p| p.display().to_string());
+        println!(
+            "No config files. gcmod reads {} (or the file {} names), then the closest {} in this directory or one above it.",
+            user, config::CONFIG_ENV, config::LOCAL_CONFIG_NAME,
+        );
+        return;
+    }
+
+    println!("# Files read, later ones winning:");
+    for (layer, path) in &config.layers {
+        println!("#   {}: {}", layer.name(), path.display());
+    }
+    // As TOML, with where each value is from in a comment
+    let print_values = |command: Option<&str>| {
+        let lines: Vec<_> = config.values(command)
+            .map(|(key, value)| (format!("{} = {}", key, value), config.source(command, key).unwrap()))
+            .collect();
+        let width = lines.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+        for (line, source) in lines {
+            println!("{:<width$}  # {}", line, source.display());
+        }
+    };
+    if config.values(None).next().is_some() {
+        println!();
+        print_values(None);
+    }
+    for command in config.commands() {
+        println!("\n[{}]", command);
+        print_values(Some(command));
+    }
+}
+
+fn print_version(verbose: bool) {
+    println!("gcmod {}", build_info::VERSION);
+    if verbose {
+        println!("Commit: {}", build_info::GIT_HASH);
+        let features = build_info::features();
+        if features.is_empty() {
+            println!("Features: none");
+        } else {
+            println!("Features: {}", features.join(", "));
+        }
+        println!("Image formats: {}", build_info::CONTAINER_FORMATS.join(", "));
     }
 }
 
@@ -107,144 +840,758 @@ fn extract_iso(
     input: impl AsRef<Path>,
     output: impl AsRef<Path>,
     file_in_iso: Option<impl AsRef<Path>>,
+    rebase_offsets: bool,
+    options: ExtractOptions,
+    reporter: &Reporter,
 ) -> eyre::Result<()> {
     let output = output.as_ref();
 
     if let Some(file) = file_in_iso {
-        return extract_section(input.as_ref(), file.as_ref(), output);
+        let file = file.as_ref().to_string_lossy();
+        if let Some(dir) = file.strip_suffix(SUBTREE_SELECTOR_SUFFIX) {
+            return extract_subtree(input.as_ref(), dir, output, rebase_offsets);
+        }
+        return extract_section(input.as_ref(), &*file, output, options.dol_split);
+    }
+
+    let (mut game, mut iso) = try_to_open_game(input.as_ref(), 0)?;
+    let mut options = options;
+    if options.fs_hints {
+        options.source_file = iso.get_ref().get_ref().try_clone_file().ok();
     }
+    let failures = game.extract(&mut iso, output, &options, reporter).wrap_err("Failed to extract game")?;
+    ensure!(
+        failures.is_empty(),
+        "{} files failed to extract, see {}",
+        failures.len(),
+        output.join(EXTRACTION_ERRORS_NAME).display(),
+    );
+    Ok(())
+}
 
-    ensure!(!output.exists(), "Output path {} already exists.", output.display());
+fn extract_options(cmd: &ArgMatches) -> ExtractOptions {
+    ExtractOptions {
+        keep_going: cmd.is_present("keep_going"),
+        strict: cmd.is_present("strict"),
+        force: cmd.is_present("force"),
+        dedup: cmd.value_of("dedup").map_or(DedupMode::Off, |d| DedupMode::parse(d).unwrap()),
+        rescued: false,
+        xattrs: cmd.is_present("xattrs"),
+        infer_extensions: cmd.is_present("infer_extensions"),
+        handlers: Vec::new(),
+        fail_on_truncated: cmd.is_present("fail_on_truncated"),
+        acknowledge_nkit: cmd.is_present("acknowledge_nkit"),
+        // Otherwise the output directory is checked
+        case_insensitive: cmd.is_present("case_insensitive").then_some(true),
+        fs_hints: !cmd.is_present("no_fs_hints"),
+        // Only known once the ROM's open
+        source_file: None,
+        dol_split: cmd.is_present("dol_split"),
+    }
+}
 
+fn refresh_extraction(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: ExtractOptions,
+    purge: bool,
+    reporter: &Reporter,
+) -> eyre::Result<()> {
+    let output = output.as_ref();
     let (mut game, mut iso) = try_to_open_game(input.as_ref(), 0)?;
-    game.extract(&mut iso, output).wrap_err("Failed to extract game")
+    let summary = game.refresh(&mut iso, output, &options, purge, reporter).wrap_err("Failed to refresh the extracted game")?;
+    reporter.result(format!(
+        "{} added, {} changed, {} removed, {} unchanged.",
+        summary.added.len(), summary.changed.len(), summary.removed.len(), summary.unchanged,
+    ));
+    if !purge && !summary.removed.is_empty() {
+        reporter.result(format!("The removed files were moved to {}.", output.join(TRASH_NAME).display()));
+    }
+    ensure!(
+        summary.failures.is_empty(),
+        "{} files failed to extract, see {}",
+        summary.failures.len(),
+        output.join(EXTRACTION_ERRORS_NAME).display(),
+    );
+    Ok(())
+}
+
+fn report_inferred_extensions(input: impl AsRef<Path>) -> eyre::Result<()> {
+    let (game, mut iso) = try_to_open_game(input, 0)?;
+    let inferred = game.infer_extensions(&mut iso).wrap_err("Failed to read files to infer extensions")?;
+    let mut renamed = 0;
+    for i in &inferred {
+        match &i.collides_with {
+            Some(other) => println!("{} ({}): not renamed, {} is already there", i.path, i.kind.name(), other),
+            None => {
+                println!("{} -> {} ({})", i.path, i.name, i.kind.name());
+                renamed += 1;
+            },
+        }
+    }
+    println!("{} of {} files would be renamed.", renamed, game.file_count());
+    Ok(())
 }
 
-fn print_iso_info(input: impl AsRef<Path>, offset: u64, style: NumberStyle) -> eyre::Result<()> {
-    let (game, _) = try_to_open_game(input, offset)?;
-    game.print_info(style);
+fn print_iso_info(
+    input: impl AsRef<Path>,
+    offset: u64,
+    language: Option<Language>,
+    verbose: bool,
+    json: bool,
+    style: NumberStyle,
+    colors: &Colors,
+) -> eyre::Result<()> {
+    let (game, mut iso) = try_to_open_game(input, offset)?;
+    let titles = game.titles(&mut iso);
+    if let Some(e) = &titles.banner_error {
+        report::warn(format!("Couldn't read the banner ({e}), so the title is the header's."));
+    }
+    let compression = iso.get_ref().get_ref().compression();
+    let info = game.info(&titles, compression, language, verbose);
+    if json {
+        return print_json(&info);
+    }
+    info.write(io::stdout().lock(), style, colors)?;
     Ok(())
 }
 
+fn reporter(cmd: &ArgMatches) -> Reporter {
+    Reporter::for_output(cmd.is_present("quiet"), cmd.is_present("no_progress"))
+}
+
+// For decompressing a compressed ROM before opening it, which commands
+// without a `Reporter` of their own do too. The progress is only shown on a
+// terminal, or as events, so it never ends up in output that's captured.
+fn spool_reporter() -> Reporter {
+    let progress = if report::json_events() {
+        ProgressStyle::Json
+    } else if io::stdout().is_terminal() {
+        ProgressStyle::Terminal
+    } else {
+        ProgressStyle::Hidden
+    };
+    Reporter::new(false, progress)
+}
+
+fn colors(cmd: &ArgMatches) -> eyre::Result<Colors> {
+    let choice = match cmd.value_of("color") {
+        Some(c) => ColorChoice::parse(c).ok_or_else(|| eyre!("Invalid color choice {:?}", c))?,
+        None => ColorChoice::Auto,
+    };
+    let large_file_size = match cmd.value_of("large") {
+        Some(size) => parse_as_u64(size).wrap_err("Invalid large file size")?,
+        None => DEFAULT_LARGE_FILE_SIZE,
+    };
+    Ok(Colors::new(choice, large_file_size))
+}
+
+fn rebuild_options(cmd: &ArgMatches) -> eyre::Result<RebuildOptions> {
+    let alignment = |name, flag, default| -> eyre::Result<u64> {
+        match cmd.value_of(name) {
+            Some(a) => {
+                let a = parse_as_u64(a).wrap_err("Invalid alignment")?;
+                AlignmentError::check(a, AlignmentSource::Flag(flag))?;
+                Ok(a)
+            },
+            None => Ok(default),
+        }
+    };
+    let capacity = match cmd.value_of("capacity") {
+        Some(c) => parse_capacity(c).ok_or_else(|| eyre!("Invalid capacity {:?}", c))?,
+        None => ROM_SIZE as u64,
+    };
+
+    let jobs = match cmd.value_of("jobs") {
+        Some(j) => match j.parse() {
+            Ok(j) if j >= 1 => j,
+            _ => bail!("Invalid number of jobs {:?}. Must be >= 1", j),
+        },
+        None => 1,
+    };
+
+    let manifest_path = Path::new(cmd.value_of("root_path").unwrap()).join(MANIFEST_NAME);
+    let manifest = if cmd.is_present("preserve_indices") {
+        let manifest = Manifest::read(&manifest_path)
+            .wrap_err("Couldn't read the manifest from the extracted ROM")?;
+        if let Some(w) = build_info::version_warning("The manifest", &manifest.gcmod_version) {
+            report::warn(w);
+        }
+        Some(manifest)
+    } else if manifest_path.is_file() {
+        // Only for its renames, which a tree without a manifest doesn't have
+        match Manifest::read(&manifest_path) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                report::warn(format!(
+                    "Couldn't read the manifest ({}), so any files renamed when extracting keep their new names.",
+                    e,
+                ));
+                None
+            },
+        }
+    } else {
+        None
+    };
+    let index_map = manifest.as_ref()
+        .filter(|_| cmd.is_present("preserve_indices"))
+        .map(Manifest::index_map);
+    let file_order = cmd.value_of("file_order")
+        .map(|path| FileOrderProfile::read(path).wrap_err_with(|| format!("Couldn't read the file order profile {}", path)))
+        .transpose()?;
+
+    Ok(RebuildOptions {
+        file_alignment: alignment("file_alignment", "--file-alignment", DEFAULT_ALIGNMENT)?,
+        system_alignment: alignment("system_alignment", "--system-alignment", DEFAULT_SYSTEM_ALIGNMENT)?,
+        rebuild_systemdata: !cmd.is_present("no_rebuild_fst"),
+        index_map,
+        keep_user_fields: cmd.is_present("keep_user_fields"),
+        capacity,
+        ignore_boot_limits: cmd.is_present("ignore_boot_limits"),
+        ignore_debug_monitor: cmd.is_present("ignore_debug_monitor"),
+        jobs,
+        prune_missing: cmd.is_present("prune_missing"),
+        system_paths: manifest.as_ref().map(Manifest::system_paths).unwrap_or_default(),
+        renames: manifest.map(|m| m.renames()).unwrap_or_default(),
+        file_order,
+        stamp: cmd.is_present("stamp"),
+    })
+}
+
+// How much `rebuild --verify-after` checks.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum VerifyAfter {
+    // The usual checks, plus the FST against the build
+    Plan,
+    // And every file's contents
+    Full,
+}
+
+#[derive(Copy, Clone)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+// What `rebuild` does besides building the ROM.
+struct RebuildSteps<'a> {
+    // Rebuild a tree whose manifest is for a different game than its header
+    allow_mixed: bool,
+    // Don't warn about trees from NKit images
+    acknowledge_nkit: bool,
+    // Leave big gaps as holes in new files, see `ROMRebuilder::write_new_file`
+    sparse_gaps: bool,
+    verify: Option<VerifyAfter>,
+    // Where to write a build report, only for new ROMs
+    report: Option<(&'a str, ReportFormat)>,
+    // Where to write the plan instead of building, and whether it has hashes
+    plan_out: Option<(&'a str, bool)>,
+    // A plan to build instead of working one out
+    plan_in: Option<&'a str>,
+    sync: SyncMode,
+}
+
 fn rebuild_iso(
     root_path: impl AsRef<Path>,
-    iso_path: impl AsRef<Path>,
-    alignment: Option<&str>,
-    rebuild_systemdata: bool,
+    iso_path: Option<&str>,
+    update_path: Option<&str>,
+    extend: bool,
+    options: &RebuildOptions,
+    steps: &RebuildSteps,
+    reporter: &Reporter,
 ) -> eyre::Result<()> {
-    let alignment = match alignment {
-        Some(a) => match parse_as_u64(a) {
-            Ok(a) if a >= MIN_ALIGNMENT => a,
-            Ok(_) => bail!("Invalid alignment. Must be >= {MIN_ALIGNMENT}"),
-            Err(e) => Err(e).wrap_err("Invalid alignment")?,
+    let root_path = root_path.as_ref();
+    ensure!(root_path.exists(), "Couldn't find root.");
+
+    if let Some(iso_path) = iso_path {
+        ensure!(!Path::new(iso_path).exists(), "{} already exists.", iso_path);
+    }
+    if let Some(update_path) = update_path {
+        ensure!(Path::new(update_path).is_file(), "{} doesn't exist.", update_path);
+    }
+
+    reporter.phase("check");
+    for warning in tree_check::check_tree(root_path).wrap_err("Couldn't check the tree")? {
+        if warning.check == TreeCheck::Nkit && steps.acknowledge_nkit {
+            continue;
+        }
+        ensure!(
+            !warning.is_error() || steps.allow_mixed,
+            "{} Pass --allow-mixed to rebuild it anyway.",
+            warning,
+        );
+        reporter.warning(warning);
+    }
+
+    if let Some((plan_path, hashes)) = steps.plan_out {
+        let mut plan = RebuildPlan::compute(root_path, options).wrap_err("Failed to plan the rebuild")?;
+        for warning in &plan.warnings {
+            reporter.warning(warning);
+        }
+        if hashes {
+            reporter.phase("hash");
+            plan.add_hashes(root_path, reporter).wrap_err("Failed to hash the files")?;
+        }
+        plan.write(plan_path).wrap_err_with(|| format!("Couldn't write the plan to {}", plan_path))?;
+        reporter.result(format!("Wrote the plan for {} files to {}.", plan.files.len(), plan_path));
+        return Ok(());
+    }
+
+    let rebuilder = match steps.plan_in {
+        Some(plan_path) => {
+            let plan = RebuildPlan::read(plan_path)
+                .wrap_err_with(|| format!("Couldn't read the plan from {}", plan_path))?;
+            if let Some(w) = build_info::version_warning("The plan", &plan.gcmod_version) {
+                reporter.warning(w);
+            }
+            plan.prepare(root_path, options.jobs).wrap_err("Failed to rebuild ISO from the plan")?
+        },
+        None => {
+            let plan = ROMRebuilder::plan(root_path, options).wrap_err("Failed to rebuild ISO")?;
+            for warning in plan.warnings() {
+                reporter.warning(warning);
+            }
+            plan
         },
-        None => DEFAULT_ALIGNMENT,
     };
 
+    let Some(update_path) = update_path else {
+        let iso_path = iso_path.unwrap();
+        let sha1 = write_new_iso(&rebuilder, iso_path, steps.report.is_some(), steps, reporter)?;
+        if let (Some((report_path, format)), Some(sha1)) = (steps.report, sha1) {
+            let report = BuildReport::new(&rebuilder, options, root_path, iso_path, &sha1)
+                .wrap_err("Failed to make the build report")?;
+            let contents = match format {
+                ReportFormat::Text => report.to_text(),
+                ReportFormat::Json => serde_json::to_string_pretty(&report)? + "\n",
+            };
+            std::fs::write(report_path, contents)
+                .wrap_err_with(|| format!("Couldn't write the build report to {}", report_path))?;
+            reporter.status(format!("Wrote the build report to {}.", report_path));
+        }
+        return match steps.verify {
+            Some(mode) => verify_rebuilt_iso(&rebuilder, iso_path, mode, reporter),
+            None => Ok(()),
+        };
+    };
+
+    let mut iso = RomWriteHandle::open_for_modification(update_path, OpenOptions::new().read(true))
+        .wrap_err_with(|| format!("Couldn't open {}", update_path))?;
+    let since = iso.modified()?;
+    if extend && iso.size()? < options.capacity {
+        iso.extend(options.capacity).wrap_err("Failed to extend ISO")?;
+        reporter.status(format!("Extended {} to {} bytes.", update_path, options.capacity));
+    }
+    reporter.phase("update");
+    match rebuilder.update(&mut iso, since).wrap_err("Failed to update ISO")? {
+        UpdateOutcome::Updated { files_rewritten, bytes_written } => reporter.result(format!(
+            "Rewrote {} of {} files ({} bytes written).",
+            files_rewritten, rebuilder.file_count(), bytes_written,
+        )),
+        UpdateOutcome::LayoutChanged(reason) => {
+            reporter.status(format!("{}, rebuilding the whole ROM.", reason));
+            write_new_iso(&rebuilder, update_path, false, steps, reporter)?;
+        },
+    }
+    // Updating writes in place, so there's no rename to sync
+    if steps.sync >= SyncMode::Data {
+        reporter.status("Syncing...");
+        iso.sync_data().wrap_err("Failed to sync the updated ISO")?;
+    }
+    match steps.verify {
+        Some(mode) => verify_rebuilt_iso(&rebuilder, update_path, mode, reporter),
+        None => Ok(()),
+    }
+}
+
+// Opens the ROM `rebuilder` just wrote and checks it. Whatever is wrong
+// with it, it's left in place to look at.
+fn verify_rebuilt_iso(
+    rebuilder: &ROMRebuilder,
+    iso_path: impl AsRef<Path>,
+    mode: VerifyAfter,
+    reporter: &Reporter,
+) -> eyre::Result<()> {
+    reporter.phase("verify");
+    reporter.status("Verifying the rebuilt ROM...");
+    let (game, mut iso) = try_to_open_game(iso_path.as_ref(), 0)
+        .wrap_err("The rebuilt ROM couldn't be opened")?;
+    let mut problems = rom_problems(&game);
+    let mismatches = rebuilder.check_output(&game, &mut iso, mode == VerifyAfter::Full, reporter)
+        .wrap_err("Failed to check the rebuilt ROM")?;
+    for m in mismatches {
+        let category = match m {
+            BuildMismatch::ContentsDiffer { .. } => "Contents",
+            _ => "Build plan",
+        };
+        problems.error(category, m);
+    }
+
+    if problems.0.is_empty() {
+        reporter.status("No problems found.");
+        return Ok(());
+    }
+    problems.print_by_category();
+    let errors = problems.errors().len();
+    ensure!(
+        errors == 0,
+        "The rebuilt ROM has {} problems, it was left at {} to look at",
+        errors,
+        iso_path.as_ref().display(),
+    );
+    Ok(())
+}
+
+// With `hash`, the SHA-1 of the ROM is worked out as it's written.
+fn write_new_iso(
+    rebuilder: &ROMRebuilder,
+    iso_path: impl AsRef<Path>,
+    hash: bool,
+    steps: &RebuildSteps,
+    reporter: &Reporter,
+) -> eyre::Result<Option<[u8; 20]>> {
     let iso_path = iso_path.as_ref();
-    let root_path = root_path.as_ref();
+    let sync = steps.sync;
 
-    ensure!(!iso_path.exists(), "{} already exists.", iso_path.display());
-    ensure!(root_path.exists(), "Couldn't find root.");
+    // Write to a temporary file first so a failed rebuild never leaves a
+    // partial ISO at `iso_path`.
+    let temp_path = workspace::sibling_temp(iso_path).ok_or_eyre("Invalid output path")?;
 
-    let iso = File::create(iso_path).wrap_err("Failed to create ISO")?;
-    if let Err(e) = ROMRebuilder::rebuild(root_path, alignment, iso, rebuild_systemdata) {
-        remove_file(iso_path).unwrap();
-        Err(e).wrap_err("Failed to rebuild ISO")
+    let mut iso = File::create(&temp_path).wrap_err("Failed to create ISO")?;
+    reporter.phase("write");
+    let written = if steps.sparse_gaps {
+        rebuilder.write_new_file(&mut iso, hash, reporter)
+    } else if hash {
+        let mut hasher = Sha1Writer::new(&mut iso);
+        rebuilder.write(&mut hasher, reporter).map(|_| Some(hasher.finish().1))
     } else {
-        Ok(())
+        rebuilder.write(&mut iso, reporter).map(|_| None)
+    };
+    let sha1 = match written {
+        Ok(sha1) => sha1,
+        Err(e) => {
+            let _ = remove_file(&temp_path);
+            return Err(e).wrap_err("Failed to rebuild ISO");
+        },
+    };
+    if sync >= SyncMode::Data {
+        reporter.status("Syncing...");
+    }
+    let finished = io_util::finish_file(iso, &temp_path, iso_path, sync, &RealFileOps).map_err(|e| match e {
+        FinishError::Sync(e) => eyre::Report::new(e).wrap_err("Failed to sync the rebuilt ISO"),
+        FinishError::Rename(e) => eyre::Report::new(e).wrap_err("Failed to move the rebuilt ISO into place"),
+        FinishError::SyncDir(e) => eyre::Report::new(e).wrap_err("Failed to sync the directory of the rebuilt ISO"),
+    })?;
+    if finished.copied {
+        reporter.warning("The temporary file was on a different file system, so it was copied into place instead of renamed.");
+    }
+    if !finished.dir_synced {
+        reporter.warning("Directories can't be synced here, so the rebuilt ISO's name might not be on the disk yet.");
     }
+    Ok(sha1)
 }
 
-fn get_info(
-    path: impl AsRef<Path>,
-    section_type: Option<&str>,
-    offset: Option<&str>,
-    mem_addr: Option<&str>,
-    style: NumberStyle,
-) -> eyre::Result<()> {
-    if let Some(offset) = offset {
-        find_offset(path.as_ref(), offset, style)
-    } else if let Some(addr) = mem_addr {
-        find_mem_addr(path.as_ref(), addr, style)
+fn get_info(cmd: &ArgMatches) -> eyre::Result<()> {
+    let path = Path::new(cmd.value_of("rom_path").unwrap());
+    let section_type = cmd.value_of("type");
+    let entry_path = cmd.value_of("entry_path")
+        .map(str::to_owned)
+        .or_else(|| cmd.value_of("entry_index").map(|i| format!("{INDEX_SELECTOR_PREFIX}{i}")));
+    let style = if cmd.is_present("hex_output") {
+        NumberStyle::Hexadecimal
+    } else {
+        NumberStyle::Decimal
+    };
+
+    if let Some(offset) = cmd.value_of("offset") {
+        find_offset(path, offset, style)
+    } else if let Some(addr) = cmd.value_of("mem_addr") {
+        let read = cmd.value_of("read")
+            .map(|n| parse_as_usize(n).wrap_err("Invalid number of bytes to read"))
+            .transpose()?;
+        find_mem_addr(path, addr, read, style)
+    } else if let Some(entry_path) = entry_path {
+        let (game, mut iso) = try_to_open_game(path, 0)?;
+        let entry = game.fst.entry_for_selector(&entry_path)
+            .ok_or_else(|| eyre!("{} does not exist", entry_path))?;
+        game.write_entry_info(io::stdout().lock(), entry, &mut iso, style)?;
+        Ok(())
+    } else if let Some(names) = cmd.value_of("analyzers") {
+        run_analyzers(path, names, &AnalyzerRegistry::with_builtins(), cmd.value_of("format") == Some("json"))
+    } else if cmd.is_present("stamp") {
+        print_stamp(path, style)
+    } else if cmd.is_present("assets") {
+        let (game, mut iso) = try_to_open_game(path, 0)?;
+        game.write_assets(io::stdout().lock(), &mut iso, style).wrap_err("Failed to read asset files")
     } else {
-        let mut f = File::open(path.as_ref())
+        let mut f = RomReadHandle::open(path)
             .map(BufReader::new)
             .wrap_err("Couldn't open file")?;
         let game = Game::open(&mut f, 0);
+        let json = cmd.is_present("json");
         match section_type {
             Some("header") => {
-                game
+                let header = game
                     .map(|g| g.header)
                     .or_else(|_| Header::new(f, 0))
-                    .wrap_err("Invalid iso or header")?
-                    .print_info(style);
+                    .wrap_err("Invalid iso or header")?;
+                if json { print_json(&header.info())? } else { header.print_info(style) }
             },
             Some("dol") => {
-                game
+                let dol = game
                     .map(|g| g.dol)
                     .or_else(|_| DOLHeader::new(f, 0))
-                    .wrap_err("Invalid iso or DOL")?
-                    .print_info(style);
+                    .wrap_err("Invalid iso or DOL")?;
+                if json { print_json(&dol.info())? } else { dol.print_info(style) }
             },
             Some("fst") => {
-                game
+                let fst = game
                     .map(|g| g.fst)
                     .or_else(|_| FST::new(f, 0))
-                    .wrap_err("Invalid iso or file system table")?
-                    .print_info(style);
+                    .wrap_err("Invalid iso or file system table")?;
+                if json { print_json(&fst.info())? } else { fst.print_info(style) }
             },
             Some("apploader") | Some("app_loader") | Some("app-loader") => {
-                game
+                let apploader = game
                     .map(|g| g.apploader)
                     .or_else(|_| Apploader::new(f, 0))
-                    .wrap_err("Invalid iso or apploader")?
-                    .print_info(style);
+                    .wrap_err("Invalid iso or apploader")?;
+                if json { print_json(&apploader.info())? } else { apploader.print_info(style) }
+            },
+            // Or a standalone opening.bnr
+            Some("banner") => {
+                let banner = match game {
+                    Ok(g) => match g.read_banner(&mut f) {
+                        Some((_, banner)) => banner,
+                        None => bail!("The ROM has no banner"),
+                    },
+                    Err(_) => Banner::new(f, 0),
+                }.wrap_err("Invalid iso or banner")?;
+                if json { print_json(&banner)? } else { banner.print_info(style) }
+            },
+            Some("layout") => { print_layout(path, json, &colors(cmd)?)?; }
+            // RELs aren't part of the system data, so this is only for
+            // standalone files.
+            Some("rel") => {
+                let rel = RelHeader::new(f, 0).wrap_err("Invalid REL")?;
+                if json { print_json(&rel.info())? } else { rel.write_info(io::stdout().lock(), style)? }
             },
-            Some("layout") => { print_layout(path.as_ref())?; }
             Some(_) => unreachable!(),
-            None => { print_iso_info(path.as_ref(), 0, style)? },
+            None => {
+                let language = cmd.value_of("lang")
+                    .map(|l| Language::parse(l).ok_or_else(|| eyre!("Unknown language {:?}, it can be en, de, fr, es, it, nl, ja, or ko", l)))
+                    .transpose()?;
+                print_iso_info(path, 0, language, cmd.is_present("verbose"), json, style, &colors(cmd)?)?
+            },
         }
         Ok(())
     }
 }
 
-fn print_layout(path: impl AsRef<Path>) -> eyre::Result<()> {
-    let (game, _) = try_to_open_game(path.as_ref(), 0)?;
-    game.print_layout();
+fn print_stamp(path: &Path, style: NumberStyle) -> eyre::Result<()> {
+    let mut f = RomReadHandle::open(path).map(BufReader::new).wrap_err("Couldn't open file")?;
+    let header = Header::new(&mut f, 0).wrap_err("Invalid iso or header")?;
+    let stamps = build_stamp::stamps(header.raw());
+    match stamps.build? {
+        Some(stamp) => print_fields(&stamp.fields(), style),
+        None => println!("The ROM has no build stamp. `rebuild --stamp` writes one."),
+    }
+    // Not the same thing, but easy to mistake for one
+    if let Some(nkit) = stamps.nkit {
+        println!();
+        println!("{}", nkit.notice());
+    }
+    Ok(())
+}
+
+// Pretty printed, on a line of its own.
+fn print_json(value: &impl serde::Serialize) -> eyre::Result<()> {
+    serde_json::to_writer_pretty(io::stdout().lock(), value)?;
+    println!();
+    Ok(())
+}
+
+fn explain(offset: &str) -> eyre::Result<()> {
+    let offset = parse_as_u64(offset).wrap_err("Invalid offset")?;
+    let matches = layout_ref::describe(offset);
+    if matches.is_empty() {
+        println!(
+            "Nothing fixed is at {:#x}: it's past the header, bi2, and the apploader's header in an image, and past the header in a DOL. `info -o` says what's there on a particular ROM.",
+            offset,
+        );
+        return Ok(());
+    }
+    let (in_image, in_dol): (Vec<&FieldMatch>, Vec<&FieldMatch>) = matches.iter().partition(|m| m.field.block.base().is_some());
+    for (heading, matches) in [("As an offset in the image:", in_image), ("As an offset in the DOL:", in_dol)] {
+        if matches.is_empty() {
+            continue;
+        }
+        println!("{}", heading);
+        for m in matches {
+            match m.field.field {
+                Some(field) => println!("  {} ({})", m, field),
+                None => println!("  {}", m),
+            }
+            println!("      {}", m.field.description);
+        }
+    }
+    Ok(())
+}
+
+// `names` is a comma separated list of what's in `registry`, or "list".
+fn run_analyzers(path: &Path, names: &str, registry: &AnalyzerRegistry, json: bool) -> eyre::Result<()> {
+    if names == "list" {
+        if json {
+            let list: Vec<_> = registry.iter()
+                .map(|a| serde_json::json!({ "name": a.name(), "description": a.description() }))
+                .collect();
+            serde_json::to_writer_pretty(io::stdout().lock(), &list)?;
+            println!();
+        } else {
+            let width = registry.iter().map(|a| a.name().len()).max().unwrap_or(0);
+            for a in registry.iter() {
+                println!("{:<w$}  {}", a.name(), a.description(), w = width);
+            }
+        }
+        return Ok(());
+    }
+    let names: Vec<&str> = names.split(',').map(str::trim).filter(|n| !n.is_empty()).collect();
+    ensure!(!names.is_empty(), "No analyzers given, pass --analyzers list to see them");
+    let (game, mut iso) = try_to_open_game(path, 0)?;
+    let reports = registry.run(&names, &game, &mut iso)
+        .map_err(|name| eyre!("There's no analyzer called {:?}, pass --analyzers list to see them", name))?;
+    if json {
+        serde_json::to_writer_pretty(io::stdout().lock(), &reports)?;
+        println!();
+    } else {
+        for (i, r) in reports.iter().enumerate() {
+            if i != 0 {
+                println!();
+            }
+            println!("[{}]", r.analyzer);
+            print!("{}", r.report.to_text());
+        }
+    }
     Ok(())
 }
 
+fn print_layout(path: impl AsRef<Path>, json: bool, colors: &Colors) -> eyre::Result<()> {
+    let (game, _) = try_to_open_game(path.as_ref(), 0)?;
+    if json {
+        print_json(&game.layout_info())
+    } else {
+        game.write_layout(io::stdout().lock(), colors)?;
+        Ok(())
+    }
+}
+
 fn find_offset(header_path: impl AsRef<Path>, offset: &str, style: NumberStyle) -> eyre::Result<()> {
+    let (game, _) = try_to_open_game(header_path.as_ref(), 0).wrap_err("Failed to open game")?;
     let offset = parse_as_u64(offset).ok()
-        .filter(|o| (*o as usize) < ROM_SIZE)
+        .filter(|&o| o < game.capacity)
         .ok_or_else(|| eyre!(
             "Invalid offset. Offset must be a number > 0 and < {}",
-            format_usize(ROM_SIZE, style),
+            format_u64(game.capacity, style),
         ))?;
 
-    let (game, _) = try_to_open_game(header_path.as_ref(), 0).wrap_err("Failed to open game")?;
     let layout = game.rom_layout();
-    let section = layout.find_offset(offset)
-        .ok_or_eyre("There isn't any data at this offset.")?;
-
-    section.print_info(style);
+    match layout.find_offset(offset) {
+        Some(section) => section.print_info(style),
+        None => {
+            let (before, after) = layout.neighbors(offset);
+            println!("{}", describe_unclaimed(offset, before, after, game.capacity, style));
+        },
+    }
     Ok(())
 }
 
-fn find_mem_addr(path: impl AsRef<Path>, mem_addr: &str, style: NumberStyle) -> eyre::Result<()> {
+// What's around an offset that isn't in any section. Space right before a
+// file that's less than the file's alignment is counted as alignment slack
+// for the file before it, since that's where rebuilding puts it.
+fn describe_unclaimed(
+    offset: u64,
+    before: Option<&dyn Section>,
+    after: Option<&dyn Section>,
+    capacity: u64,
+    style: NumberStyle,
+) -> String {
+    let fmt = |n| format_u64(n, style);
+    let region_start = before.map_or(0, |b| b.end() + 1);
+    let region_end = after.map_or(capacity, |a| a.start());
+    let mut description = format!(
+        "Offset {} is in {} bytes of ",
+        fmt(offset), fmt(region_end - region_start),
+    );
+
+    let ends = |s: &dyn Section| format!("{} (ends {})", s.name(), fmt(s.end()));
+    let starts = |s: &dyn Section| format!("{} (starts {})", s.name(), fmt(s.start()));
+    match (before, after) {
+        (Some(b), Some(a)) => {
+            // The biggest alignment `a` is on, up to what rebuilding uses
+            let alignment = (1 << a.start().trailing_zeros().min(63)).min(DEFAULT_ALIGNMENT);
+            if !b.is_system_data() && !a.is_system_data()
+                && alignment >= MIN_ALIGNMENT
+                && region_end - region_start < alignment
+            {
+                description += &format!(
+                    "alignment slack after {}, before {} on a {} byte boundary",
+                    ends(b), starts(a), fmt(alignment),
+                );
+            } else {
+                description += &format!("padding between {} and {}", ends(b), starts(a));
+            }
+        },
+        (Some(b), None) => description += &format!("free space at the end of the image, after {}", ends(b)),
+        (None, Some(a)) => description += &format!("padding before {}", starts(a)),
+        (None, None) => description += "free space, there aren't any sections",
+    }
+
+    // Measured like offsets are, so the byte right after a section is 1 away
+    description += &match before {
+        Some(b) => format!("\nDistance from the end of {}: {}", b.name(), fmt(offset - b.end())),
+        None => format!("\nDistance from the start of the image: {}", fmt(offset)),
+    };
+    description += &match after {
+        Some(a) => format!("\nDistance to the start of {}: {}", a.name(), fmt(a.start() - offset)),
+        None => format!("\nDistance to the end of the image: {}", fmt(capacity - offset)),
+    };
+    description
+}
+
+// With `read`, also prints that many bytes from the segment, starting at
+// `mem_addr`.
+fn find_mem_addr(path: impl AsRef<Path>, mem_addr: &str, read: Option<usize>, style: NumberStyle) -> eyre::Result<()> {
     let mem_addr = parse_as_u64(mem_addr)
         .wrap_err("Invalid address")?;
 
-    let (game, _) = try_to_open_game(path.as_ref(), 0).wrap_err("Failed to open game")?;
+    let (game, mut iso) = try_to_open_game(path.as_ref(), 0).wrap_err("Failed to open game")?;
 
-    let seg = game.dol.segment_at_addr(mem_addr)
-        .ok_or_eyre("No DOL segment will be loaded at this address")?;
+    let Some(seg) = game.dol.segment_at_addr(mem_addr) else {
+        ensure!(game.dol.bss_contains(mem_addr), "No DOL segment will be loaded at this address");
+        println!("{:#010x} is zero-initialized at runtime (BSS), no file bytes", mem_addr);
+        return Ok(());
+    };
 
     let offset = mem_addr - seg.loading_address;
     println!("Segment: {seg}");
     println!("Offset from start of segment: {}", format_u64(offset, style));
 
+    let Some(n) = read else { return Ok(()) };
+    // Past the end of the segment is either another segment or nothing
+    let left = seg.size - offset as usize;
+    let mut bytes = vec![0; n.min(left)];
+    iso.seek(SeekFrom::Start(seg.offset + offset))?;
+    iso.read_exact(&mut bytes).wrap_err("Couldn't read the segment")?;
+
+    println!();
+    print!("{}", hex_dump(&bytes, mem_addr));
+    if n > left {
+        println!("The segment ends after {} bytes.", left);
+        return Ok(());
+    }
+    for line in describe_value(&bytes) {
+        println!("{}", line);
+    }
     Ok(())
 }
 
@@ -252,7 +1599,14 @@ fn extract_section(
     iso_path: impl AsRef<Path>,
     section_filename: impl AsRef<Path>,
     output: impl AsRef<Path>,
+    dol_split: bool,
 ) -> eyre::Result<()> {
+    let section_filename = section_filename.as_ref();
+    ensure!(
+        !dol_split || section_filename == Path::new(DOL_PATH),
+        "--dol-split only works when extracting everything, or {}",
+        DOL_PATH,
+    );
     let (game, mut iso) = try_to_open_game(iso_path.as_ref(), 0).wrap_err("Failed to open game")?;
 
     let result = game.extract_section_with_name(
@@ -261,38 +1615,1462 @@ fn extract_section(
         &mut iso,
     );
 
-    match result {
-        Ok(true) => Ok(()),
-        Ok(false) => Err(eyre!("Couldn't find a section with that name.")),
-        Err(_) => Err(eyre!("Error extracting section.")),
+    // Failing to write has the whole chain of errors, and not finding
+    // anything has what there is instead
+    let e = match result {
+        Ok(_) if dol_split => {
+            let (dir, difference) = game.split_dol(&mut iso, output.as_ref())?;
+            if let Some(difference) = difference {
+                report::warn(Game::split_dol_warning(&dir, difference));
+            }
+            println!("Wrote {} segments to {}.", game.dol.iter_segments().count(), dir.display());
+            return Ok(());
+        },
+        Ok(_) => return Ok(()),
+        Err(e @ SectionError::Io { .. }) => return Err(e.into()),
+        Err(e) => e,
+    };
+    let (label, names) = match &e {
+        SectionError::NotFound { suggestions, .. } => ("Did you mean:", suggestions.as_slice()),
+        SectionError::NoSuchSegment { segments, .. } => ("The DOL's segments are:", segments.as_slice()),
+        SectionError::Io { .. } => ("", &[][..]),
+    };
+    if names.is_empty() {
+        Err(eyre!("{}.", e))
+    } else {
+        Err(eyre!("{}. {}\n  {}", e, label, names.join("\n  ")))
     }
 }
 
-fn ls_files(rom_path: impl AsRef<Path>, path: Option<impl AsRef<Path>>, long_format: bool) -> eyre::Result<()> {
-    let path = path.as_ref().map(|path| path.as_ref());
+fn extract_subtree(
+    iso_path: impl AsRef<Path>,
+    dir: &str,
+    output: impl AsRef<Path>,
+    rebase_offsets: bool,
+) -> eyre::Result<()> {
+    let (game, _) = try_to_open_game(iso_path.as_ref(), 0).wrap_err("Failed to open game")?;
+    let subtree = game.fst.subtree(dir, rebase_offsets)?;
+    let output = output.as_ref();
+    let file = File::create(output).wrap_err_with(|| format!("Couldn't create {}", output.display()))?;
+    let mut file = BufWriter::new(file);
+    subtree.write(&mut file)
+        .and_then(|_| file.flush())
+        .wrap_err("Failed to write FST")
+}
+
+// How `ls` lists entries
+struct ListStyle {
+    long: bool,
+    // The directory itself instead of what's in it
+    directory: bool,
+    recursive: bool,
+    // One JSON object per line, see `EntryRecord`
+    json: bool,
+}
+
+fn ls_files(
+    rom_path: impl AsRef<Path>,
+    path: Option<&str>,
+    style: ListStyle,
+    fake_mtime: Option<&str>,
+    colors: &Colors,
+) -> eyre::Result<()> {
+    let fake_mtime = fake_mtime.map(FakeMtime::parse).transpose()?;
+    let (game, mut iso) = try_to_open_game(rom_path, 0)?;
+    let unnamed = game.fst.unnamed_entries().count();
+    if unnamed != 0 {
+        report::warn(format!(
+            "{} of the {} entries in the file system table have no name, so its string table is probably \
+            damaged or cut off, and paths can't find them. They're listed as <unnamed entry #N>, and can still be \
+            listed and extracted by index, like idx:N. If the image was cut short, `gcmod rescue` may find more of it.",
+            unnamed, game.fst.entries().len() - 1,
+        ));
+    }
+    let entry = selected_entry(&game, path)?;
+    if style.json {
+        let (records, problems) = match entry.as_dir() {
+            Some(dir) if !style.directory => game.entry_records(dir, style.recursive),
+            _ => (vec![entry.record()], Vec::new()),
+        };
+        for e in problems {
+            report::warn(e);
+        }
+        let mut out = io::stdout().lock();
+        for record in records {
+            serde_json::to_writer(&mut out, &record)?;
+            writeln!(out)?;
+        }
+        return Ok(());
+    }
+    let mtime = fake_mtime.map(|m| m.for_game(&game)).transpose()?;
+    // A file is listed on its own, like `ls` does
+    let (lines, problems) = match entry.as_dir() {
+        Some(dir) if !style.directory => game.listing(dir, style.recursive, style.long, mtime, &mut iso, colors),
+        _ => (vec![game.entry_listing(entry, style.long, mtime, &mut iso, colors)], Vec::new()),
+    };
+    for e in problems {
+        report::warn(e);
+    }
+    let mut out = io::stdout().lock();
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+// What `probe` exits with when the path isn't on the ROM, so scripts can
+// tell it apart from errors
+const NOT_FOUND_EXIT_CODE: i32 = 3;
+
+fn probe_rom(rom_path: impl AsRef<Path>, path: &str) -> eyre::Result<()> {
+    let rom_path = rom_path.as_ref();
+    ensure!(rom_path.exists(), "The file {} doesn't exist.", rom_path.display());
+    let mut iso = BufReader::new(RomReadHandle::open(rom_path).wrap_err("Couldn't open ISO file")?);
+    let header = Header::new(&mut iso, 0).wrap_err("Invalid ISO")?;
+    let hit = FST::probe_path(&mut iso, header.fst_offset, header.fst_size, path)
+        .wrap_err("Couldn't read the file system table")?;
+    match hit {
+        Some(ProbeHit::File { index, offset, size }) =>
+            println!("Found {}: file {} at {:#010x}, {} bytes", path, index, offset, size),
+        Some(ProbeHit::Directory { index }) => println!("Found {}: directory {}", path, index),
+        None => {
+            println!("Not found: {}", path);
+            report::finish_events(None);
+            std::process::exit(NOT_FOUND_EXIT_CODE);
+        },
+    }
+    Ok(())
+}
+
+fn grow_fst(rom_path: impl AsRef<Path>, headroom: &str) -> eyre::Result<()> {
+    let rom_path = rom_path.as_ref();
+    let headroom = parse_size(headroom).ok_or_else(|| eyre!("Invalid headroom {:?}", headroom))?;
+    let mut iso = RomWriteHandle::open_for_modification(rom_path, OpenOptions::new().read(true))
+        .wrap_err_with(|| format!("Couldn't open {}", rom_path.display()))?;
+    let game = Game::open(BufReader::new(&mut iso), 0).wrap_err("Invalid ROM")?;
 
+    // Everything that moves has to go in space nothing else uses
+    let overlaps = game.rom_layout().overlaps();
+    if let Some((a, b)) = overlaps.first() {
+        bail!("{} overlaps {}, so there's no telling which space is free", a.name(), b.name());
+    }
+    let plan = GrowPlan::new(&game, iso.size()?, headroom)?;
+    if plan.max_fst_size == plan.old_max_fst_size {
+        println!("The file system table already has room for {} more bytes.", plan.old_max_fst_size - game.fst.size);
+        return Ok(());
+    }
+    if let Some(w) = &plan.boot_warning {
+        report::warn(w.to_string());
+    }
+    for m in &plan.moves {
+        println!("Moving {} ({} bytes) from {:#010x} to {:#010x}", m.name, m.size, m.from, m.to);
+    }
+    plan.apply(&game, &mut iso).wrap_err("Failed to write the ROM")?;
+    println!(
+        "The file system table can now take up {} bytes, up from {}.",
+        plan.max_fst_size, plan.old_max_fst_size,
+    );
+    Ok(())
+}
+
+fn replace_batch(rom_path: impl AsRef<Path>, mapping_path: impl AsRef<Path>) -> eyre::Result<()> {
+    let (rom_path, mapping_path) = (rom_path.as_ref(), mapping_path.as_ref());
+    let text = fs::read_to_string(mapping_path)
+        .wrap_err_with(|| format!("Couldn't read {}", mapping_path.display()))?;
+    let mapping = parse_mapping(&text, mapping_path.parent().unwrap_or(Path::new("")))?;
+    let sizes = mapping.iter()
+        .map(|m| Ok(fs::metadata(&m.local_path).wrap_err_with(|| format!("Couldn't read {}", m.local_path.display()))?.len()))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let mut iso = RomWriteHandle::open_for_modification(rom_path, OpenOptions::new().read(true))
+        .wrap_err_with(|| format!("Couldn't open {}", rom_path.display()))?;
+    let game = Game::open(BufReader::new(&mut iso), 0).wrap_err("Invalid ROM")?;
+    // Files that move have to go in space nothing else uses, and files
+    // replaced in place can't share their data with anything
+    let overlaps = game.rom_layout().overlaps();
+    if let Some((a, b)) = overlaps.first() {
+        bail!("{} overlaps {}, so there's no telling which space is free", a.name(), b.name());
+    }
+    let plan = ReplacePlan::new(&game, iso.size()?, &mapping, &sizes)?;
+    for r in &plan.replacements {
+        match r.in_place() {
+            true => println!("Replacing {} ({} bytes, was {}) at {:#010x}", r.path, r.size, r.old_size, r.offset),
+            false => println!(
+                "Moving {} ({} bytes, was {}) from {:#010x} to {:#010x}",
+                r.path, r.size, r.old_size, r.old_offset, r.offset,
+            ),
+        }
+    }
+    if let Some((start, end)) = plan.user_area {
+        println!("Growing the user area to {:#010x}-{:#010x}", start, end);
+    }
+    plan.apply(&game, &mut iso).wrap_err("Failed to write the ROM")?;
+    println!("Replaced {} files.", plan.replacements.len());
+    Ok(())
+}
+
+fn write_file_table(
+    rom_path: impl AsRef<Path>,
+    path: Option<&str>,
+    format: TableFormat,
+    header: bool,
+    out: Option<&str>,
+) -> eyre::Result<()> {
     let (game, _) = try_to_open_game(rom_path, 0)?;
-    let dir = match path {
-        Some(p) => game.fst.entry_for_path(p).and_then(|e| e.as_dir()),
-        None => Some(game.fst.root()),
+    let dir = selected_dir(&game, path)?;
+    match out {
+        Some(out) => {
+            let file = File::create(out).wrap_err_with(|| format!("Couldn't create {}", out))?;
+            write_table(BufWriter::new(file), dir, game.fst.entries(), format, header)
+        },
+        None => write_table(io::stdout().lock(), dir, game.fst.entries(), format, header),
+    }.wrap_err("Couldn't write the table")
+}
+
+fn selected_dir<'a>(game: &'a Game, path: Option<&str>) -> eyre::Result<&'a DirectoryEntry> {
+    let entry = selected_entry(game, path)?;
+    entry.as_dir().ok_or_else(|| eyre!("{} is a file, not a directory", entry.info().normalized_path()))
+}
+
+// The root without `path`.
+fn selected_entry<'a>(game: &'a Game, path: Option<&str>) -> eyre::Result<&'a Entry> {
+    let Some(path) = path else {
+        return Ok(&game.fst.entries()[0]);
+    };
+    if path.starts_with(INDEX_SELECTOR_PREFIX) {
+        return game.fst.entry_for_selector(path).ok_or_else(|| eyre!("There's no entry {}", path));
+    }
+    match game.fst.resolve(path) {
+        Resolution::Found(e) => Ok(e),
+        Resolution::NotADirectory { file: Entry::File(f) } => Err(eyre!(
+            "{} doesn't exist: {} is a file, not a directory ({} bytes at {:#x})",
+            path, f.info.normalized_path(), f.size, f.file_offset,
+        )),
+        Resolution::Missing { parent: None, missing } => Err(eyre!("Nothing on the ROM is called {}", missing)),
+        r => Err(eyre!("{} doesn't exist: {}", path, r.problem().unwrap_or_default())),
+    }
+}
+
+// What `clean-workdir` leaves alone without --older-than
+const DEFAULT_CLEAN_AGE_HOURS: u64 = 24;
+
+fn clean_work_dir(older_than: Option<&str>, dry_run: bool) -> eyre::Result<()> {
+    let hours = match older_than {
+        Some(h) => h.parse().map_err(|_| eyre!("Invalid number of hours {:?}", h))?,
+        None => DEFAULT_CLEAN_AGE_HOURS,
+    };
+    let dir = workspace::work_dir();
+    if !dir.is_dir() {
+        println!("The work directory {} doesn't exist, so there's nothing to remove.", dir.display());
+        return Ok(());
+    }
+    let removed = workspace::clean(&dir, Duration::from_secs(hours * 60 * 60), dry_run)
+        .wrap_err_with(|| format!("Couldn't clean up {}", dir.display()))?;
+    for r in &removed {
+        println!("{} ({})", r.path.display(), format_bytes(r.bytes));
+    }
+    let total = removed.iter().map(|r| r.bytes).sum();
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    println!("{} {} of gcmod's files and directories from {}, {} in all.", verb, removed.len(), dir.display(), format_bytes(total));
+    Ok(())
+}
+
+fn import_layout_profile(log: impl AsRef<Path>, out: impl AsRef<Path>, rom: Option<&str>) -> eyre::Result<()> {
+    let log = log.as_ref();
+    // Logs aren't always all UTF-8, and only the paths matter
+    let text = fs::read(log).wrap_err_with(|| format!("Couldn't read {}", log.display()))?;
+    let text = String::from_utf8_lossy(&text);
+    let game = rom.map(|rom| try_to_open_game(rom, 0).map(|(game, _)| game)).transpose()?;
+    let import = FileOrderProfile::from_log(&text, game.as_ref().map(|g| &g.fst));
+    ensure!(import.accesses != 0, "{} doesn't have any lines from Dolphin's file monitor (FileMon)", log.display());
+
+    if !import.unmatched.is_empty() {
+        let mut message = format!("{} of the {} reads in the log are of files that aren't on the ROM, so they're left out:", import.unmatched.len(), import.accesses);
+        for (line, path) in &import.unmatched {
+            message.push_str(&format!("\n  line {}: {}", line, path));
+        }
+        report::warn(message);
+    }
+    let out = out.as_ref();
+    import.profile.write(out).wrap_err_with(|| format!("Couldn't write {}", out.display()))?;
+    println!(
+        "Wrote the order of {} files, read {} times, to {}",
+        import.profile.files.len(),
+        import.profile.files.iter().map(|f| f.accesses).sum::<u64>(),
+        out.display(),
+    );
+    Ok(())
+}
+
+fn diff_roms(a: impl AsRef<Path>, b: impl AsRef<Path>, indices: bool) -> eyre::Result<()> {
+    let (game_a, _) = try_to_open_game(a.as_ref(), 0)?;
+    let (game_b, _) = try_to_open_game(b.as_ref(), 0)?;
+
+    let entries_a: BTreeMap<_, _> = game_a.fst.entries().iter()
+        .map(|e| (e.info().normalized_path(), e))
+        .collect();
+    let entries_b: BTreeMap<_, _> = game_b.fst.entries().iter()
+        .map(|e| (e.info().normalized_path(), e))
+        .collect();
+
+    for (path, a) in &entries_a {
+        let Some(b) = entries_b.get(path) else {
+            println!("- {path}");
+            continue
+        };
+        if indices {
+            if a.info().index != b.info().index {
+                println!("  {path}: {} -> {}", a.info().index, b.info().index);
+            }
+        } else if let (Some(fa), Some(fb)) = (a.as_file(), b.as_file()) {
+            if fa.size != fb.size {
+                println!("  {path}: {} -> {} bytes", fa.size, fb.size);
+            }
+        }
+    }
+    for path in entries_b.keys().filter(|p| !entries_a.contains_key(*p)) {
+        println!("+ {path}");
+    }
+    Ok(())
+}
+
+fn compare_layout(a: impl AsRef<Path>, b: impl AsRef<Path>, json: bool) -> eyre::Result<()> {
+    let (game_a, _) = try_to_open_game(a.as_ref(), 0)?;
+    let (game_b, _) = try_to_open_game(b.as_ref(), 0)?;
+    let comparison = LayoutComparison::new(&game_a, &game_b);
+    if json {
+        serde_json::to_writer_pretty(io::stdout().lock(), &comparison)?;
+        println!();
+    } else {
+        print!("{}", comparison.to_text());
+    }
+    Ok(())
+}
+
+fn dump_metadata(rom_path: impl AsRef<Path>, out: Option<&str>) -> eyre::Result<()> {
+    let (game, mut iso) = try_to_open_game(rom_path, 0)?;
+    let banner = game.read_banner(&mut iso).map(|(_, b)| b);
+    let metadata = Metadata::new(&game, banner);
+
+    match out {
+        Some(out) => {
+            let file = BufWriter::new(File::create(out)
+                .wrap_err_with(|| format!("Couldn't create {}", out))?);
+            serde_json::to_writer_pretty(file, &metadata)?;
+        },
+        None => {
+            serde_json::to_writer_pretty(io::stdout().lock(), &metadata)?;
+            println!();
+        },
+    }
+    Ok(())
+}
+
+fn apply_metadata(rom_path: impl AsRef<Path>, meta_path: impl AsRef<Path>) -> eyre::Result<()> {
+    let (rom_path, meta_path) = (rom_path.as_ref(), meta_path.as_ref());
+    let json = fs::read_to_string(meta_path)
+        .wrap_err_with(|| format!("Couldn't read {}", meta_path.display()))?;
+
+    let mut file = RomWriteHandle::open_for_modification(rom_path, OpenOptions::new().read(true))
+        .wrap_err_with(|| format!("Couldn't open {}", rom_path.display()))?;
+    let mut iso = BufReader::new(&mut file);
+    let game = Game::open(&mut iso, 0).wrap_err("Invalid ROM")?;
+    let banner = game.read_banner(&mut iso)
+        .map(|(f, b)| b.map(|b| (f.file_offset, b)))
+        .transpose()
+        .wrap_err("Invalid banner")?;
+    drop(iso);
+
+    let mut update = MetadataUpdate::from_json(&json, &game.header)
+        .wrap_err_with(|| format!("{} isn't a valid metadata document", meta_path.display()))?;
+    if let Some(w) = build_info::version_warning("The metadata document", &update.gcmod_version) {
+        report::warn(w);
+    }
+
+    // The offsets in the header describe the rest of the ROM, so they can't
+    // be changed here any more than the file system can.
+    let mut ignored = Vec::new();
+    let header = &mut update.header;
+    if (header.dol_offset, header.fst_offset, header.fst_size, header.max_fst_size)
+        != (game.header.dol_offset, game.header.fst_offset, game.header.fst_size, game.header.max_fst_size)
+    {
+        ignored.push("header offsets and sizes");
+        header.dol_offset = game.header.dol_offset;
+        header.fst_offset = game.header.fst_offset;
+        header.fst_size = game.header.fst_size;
+        header.max_fst_size = game.header.max_fst_size;
+    }
+    update.header.validate()?;
+
+    let current_banner = banner.as_ref().map(|(_, b)| b);
+    let new_banner = match (&update.banner, current_banner) {
+        (Some(new), Some(old)) => {
+            ensure!(
+                new.version == old.version && new.infos.len() == old.infos.len(),
+                "The banner's version can't be changed",
+            );
+            Some(new)
+        },
+        (Some(_), None) => bail!("The document has a banner, but the ROM doesn't"),
+        (None, _) => None,
     };
 
-    let Some(dir) = dir else { bail!("Directory {} does not exist", path.unwrap_or(Path::new("/")).display()); };
+    let current = Metadata::new(&game, None);
+    ignored.extend(update.ignored_changes(&current));
+    if !ignored.is_empty() {
+        println!("Ignoring changes to {}, they can't be applied.", ignored.join(", "));
+    }
+
+    let mut changes = diff_values(
+        "header",
+        &serde_json::to_value(&game.header)?,
+        &serde_json::to_value(&update.header)?,
+    );
+    if let (Some(new), Some(old)) = (new_banner, current_banner) {
+        changes.extend(diff_values("banner", &serde_json::to_value(old)?, &serde_json::to_value(new)?));
+    }
+    if changes.is_empty() {
+        println!("No changes.");
+        return Ok(());
+    }
+    for (path, old, new) in &changes {
+        println!("{path}: {old} -> {new}");
+    }
 
-    game.print_directory(dir, long_format);
+    // The banner goes first since its text is only checked as it's written.
+    if let (Some(new), Some((offset, _))) = (new_banner, &banner) {
+        new.write_text(&mut file, *offset).wrap_err("Failed to write banner")?;
+    }
+    update.header.write_changes(&game.header, &mut file, 0)
+        .wrap_err("Failed to write header")?;
     Ok(())
 }
 
-fn try_to_open_game<P>(path: P, offset: u64) -> eyre::Result<(Game, BufReader<File>)>
+fn set_header_fields(
+    path: impl AsRef<Path>,
+    audio_streaming: Option<bool>,
+    stream_buffer: Option<&str>,
+    disc: Option<&str>,
+    total_discs: Option<&str>,
+) -> eyre::Result<()> {
+    let path = path.as_ref();
+    ensure!(path.exists(), "The file {} doesn't exist.", path.display());
+
+    // Extracted ROMs keep a copy of the header that gets used when rebuilding.
+    let header_path = if path.is_dir() {
+        path.join(SystemPaths::for_tree(path).header())
+    } else {
+        path.to_owned()
+    };
+
+    let mut file = RomWriteHandle::open_for_modification(&header_path, OpenOptions::new().read(true))
+        .wrap_err_with(|| format!("Couldn't open {}", header_path.display()))?;
+    let mut header = Header::new(BufReader::new(&mut file), 0)
+        .wrap_err("Invalid header")?;
+
+    if let Some(enabled) = audio_streaming {
+        let buffer_size = stream_buffer
+            .map(|b| parse_as_u64(b).ok().and_then(|b| u8::try_from(b).ok()))
+            .map(|b| b.ok_or_eyre("Invalid stream buffer size"))
+            .transpose()?;
+        header.set_audio_streaming(enabled, buffer_size)?;
+        header.patch_audio_streaming(&mut file, 0)
+            .wrap_err("Failed to write header")?;
+        println!(
+            "Audio streaming: {} (stream buffer size {})",
+            if enabled { "on" } else { "off" },
+            header.stream_buffer_size,
+        );
+    }
+
+    if let Some(disc) = disc {
+        let disc = parse_as_u64(disc).ok().and_then(|d| u8::try_from(d).ok())
+            .ok_or_eyre("Invalid disc number")?;
+        let total = total_discs
+            .map(|t| parse_as_u64(t).ok().and_then(|t| u32::try_from(t).ok()))
+            .map(|t| t.ok_or_eyre("Invalid number of discs"))
+            .transpose()?;
+        let original = header.clone();
+        header.set_disc(disc, total)?;
+        header.write_changes(&original, &mut file, 0)
+            .wrap_err("Failed to write header")?;
+        println!("Disc: {} of {}", disc, header.information.total_discs);
+    }
+    Ok(())
+}
+
+fn verify_rom(path: impl AsRef<Path>, strict_fst: bool, check_stamp: bool) -> eyre::Result<()> {
+    let (game, mut iso) = try_to_open_game(path, 0)?;
+    let mut problems = rom_problems(&game);
+    if strict_fst {
+        let size = (game.header.fst_size as u64).min(game.capacity.saturating_sub(game.fst.offset));
+        let fst_bytes = game.fst.read_raw(&mut iso, size as usize, &ParseOptions::default())
+            .wrap_err("Couldn't read the FST")?;
+        for v in game.fst.strict_validate(&fst_bytes) {
+            problems.error("Strict FST", v);
+        }
+    }
+    let mut stamp_matches = false;
+    if check_stamp {
+        match build_stamp::read(game.header.raw()) {
+            Ok(Some(stamp)) => {
+                let tree_hash = game.tree_hash(&mut iso).wrap_err("Couldn't hash the ROM's files")?;
+                if tree_hash == stamp.tree_hash {
+                    stamp_matches = true;
+                } else {
+                    problems.error("Stamp", format!(
+                        "The ROM's files have changed since it was built: their hash is {}, but the build stamp says {}",
+                        to_hex(&tree_hash), to_hex(&stamp.tree_hash),
+                    ));
+                }
+            },
+            Ok(None) => problems.error("Stamp", "The ROM has no build stamp. `rebuild --stamp` writes one."),
+            // `rom_problems` already warned about why
+            Err(_) => problems.error("Stamp", "The build stamp can't be checked, since it's damaged"),
+        }
+    }
+    let (errors, warnings) = (problems.errors(), problems.warnings());
+
+    for w in &warnings {
+        println!("Warning: {w}");
+    }
+    for e in &errors {
+        println!("Error: {e}");
+    }
+    ensure!(errors.is_empty(), "Found {} problems", errors.len());
+    if stamp_matches {
+        println!("The build stamp matches the ROM's files.");
+    }
+    if warnings.is_empty() {
+        println!("No problems found.");
+    }
+    Ok(())
+}
+
+fn boot_trace(path: impl AsRef<Path>, json: bool) -> eyre::Result<()> {
+    let mut iso = BufReader::new(RomReadHandle::open(path.as_ref()).wrap_err("Couldn't open ROM")?);
+    let len = iso.get_ref().size()?;
+    let trace = BootTrace::new(&mut iso, len).wrap_err("Couldn't read the ROM")?;
+    if json {
+        serde_json::to_writer_pretty(io::stdout().lock(), &trace)?;
+        println!();
+    } else {
+        print!("{}", trace.to_text());
+    }
+    ensure!(trace.first_failure().is_none(), "The ROM wouldn't boot");
+    Ok(())
+}
+
+// The checks `exec` can run before the command. Each has its own exit code,
+// so a launcher can tell which one failed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ExecCheck {
+    Verify,
+    BootCheck,
+}
+
+const VERIFY_FAILED_EXIT_CODE: i32 = 4;
+const BOOT_CHECK_FAILED_EXIT_CODE: i32 = 5;
+
+impl ExecCheck {
+    fn parse(s: &str) -> Option<ExecCheck> {
+        match s {
+            "verify" => Some(ExecCheck::Verify),
+            "boot-check" => Some(ExecCheck::BootCheck),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ExecCheck::Verify => "verify",
+            ExecCheck::BootCheck => "boot-check",
+        }
+    }
+
+    fn exit_code(self) -> i32 {
+        match self {
+            ExecCheck::Verify => VERIFY_FAILED_EXIT_CODE,
+            ExecCheck::BootCheck => BOOT_CHECK_FAILED_EXIT_CODE,
+        }
+    }
+}
+
+// Everything is checked through one handle, which stays open until the
+// command starts. The command opens the path again, though, so the ROM can
+// still be swapped out in between, except with `pass_fd`, which gives the
+// command the handle itself.
+fn exec_rom(path: impl AsRef<Path>, require: &str, pass_fd: bool, command: Vec<&OsStr>) -> eyre::Result<()> {
+    let path = path.as_ref();
+    let checks = require.split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| ExecCheck::parse(c).ok_or_else(|| eyre!("Unknown check {:?}, it can be verify or boot-check", c)))
+        .collect::<eyre::Result<Vec<_>>>()?;
+    ensure!(!pass_fd || cfg!(target_os = "linux"), "--pass-fd only works on Linux");
+    ensure!(path.exists(), "The file {} doesn't exist.", path.display());
+
+    let handle = RomReadHandle::open(path).wrap_err("Couldn't open ISO file")?;
+    let passed = pass_fd.then(|| handle.try_clone_file())
+        .transpose()
+        .wrap_err("--pass-fd only works for uncompressed ROMs")?;
+    let len = handle.size()?;
+    let (game, mut iso) = open_game(handle, 0)?;
+    for check in checks {
+        let (ok, report) = match check {
+            ExecCheck::Verify => {
+                let errors = rom_problems(&game).errors().iter().map(|e| format!("Error: {}\n", e)).collect::<String>();
+                (errors.is_empty(), errors)
+            },
+            ExecCheck::BootCheck => {
+                let trace = BootTrace::new(&mut iso, len).wrap_err("Couldn't read the ROM")?;
+                (trace.first_failure().is_none(), trace.to_text())
+            },
+        };
+        if !ok {
+            print!("{}", report);
+            eprintln!("{} failed, so the command wasn't run.", check.name());
+            report::finish_events(None);
+            io::stdout().flush()?;
+            std::process::exit(check.exit_code());
+        }
+    }
+
+    let rom = match &passed {
+        Some(file) => inheritable_fd_path(file)?,
+        None => path.as_os_str().to_owned(),
+    };
+    let args = substitute_rom(&command, &rom)?;
+    let mut child = std::process::Command::new(&args[0]);
+    child.args(&args[1..]);
+    run_command(child, (iso, passed))
+}
+
+// The command, with every {} in it replaced by `rom`, which goes on the end
+// if nothing had one.
+fn substitute_rom(command: &[&OsStr], rom: &OsStr) -> eyre::Result<Vec<OsString>> {
+    let mut found = false;
+    let mut args = Vec::with_capacity(command.len() + 1);
+    for &arg in command {
+        let arg = match arg.to_str() {
+            Some("{}") => rom.to_owned(),
+            Some(a) if a.contains("{}") => {
+                let rom = rom.to_str()
+                    .ok_or_else(|| eyre!("{:?} isn't UTF-8, so it can only be passed as an argument of its own", rom))?;
+                a.replace("{}", rom).into()
+            },
+            _ => {
+                args.push(arg.to_owned());
+                continue
+            },
+        };
+        found = true;
+        args.push(arg);
+    }
+    if !found {
+        args.push(rom.to_owned());
+    }
+    Ok(args)
+}
+
+// A path to `file` that the command can open, which works even if the file
+// has been renamed or removed since.
+#[cfg(target_os = "linux")]
+fn inheritable_fd_path(file: &File) -> eyre::Result<OsString> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    // Everything Rust opens is closed on exec, and this has to survive it.
+    // SAFETY: `fd` stays open for as long as `file` does.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } == -1 {
+        return Err(io::Error::last_os_error()).wrap_err("Couldn't pass the ROM to the command");
+    }
+    Ok(format!("/proc/self/fd/{}", fd).into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn inheritable_fd_path(_file: &File) -> eyre::Result<OsString> {
+    bail!("--pass-fd only works on Linux")
+}
+
+// Replaces gcmod with the command, which keeps anything in `_held` that's
+// inheritable open in it, and the rest open until it starts.
+#[cfg(unix)]
+fn run_command(mut command: std::process::Command, _held: impl Sized) -> eyre::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    // Only returns if it couldn't be run
+    let e = command.exec();
+    Err(e).wrap_err_with(|| format!("Couldn't run {:?}", command.get_program()))
+}
+
+// Without exec, gcmod waits for the command and exits with its exit code.
+// `held` stays open until it's started.
+#[cfg(not(unix))]
+fn run_command(mut command: std::process::Command, held: impl Sized) -> eyre::Result<()> {
+    let mut child = command.spawn().wrap_err_with(|| format!("Couldn't run {:?}", command.get_program()))?;
+    drop(held);
+    let status = child.wait()?;
+    report::finish_events(None);
+    std::process::exit(status.code().unwrap_or(1))
+}
+
+// What `verify` and `rebuild --verify-after` find, by the kind of check that
+// found it.
+#[derive(Default)]
+struct Problems(Vec<Problem>);
+
+struct Problem {
+    category: &'static str,
+    is_error: bool,
+    message: String,
+}
+
+impl Problems {
+    fn error(&mut self, category: &'static str, message: impl ToString) {
+        self.0.push(Problem { category, is_error: true, message: message.to_string() });
+    }
+
+    fn warning(&mut self, category: &'static str, message: impl ToString) {
+        self.0.push(Problem { category, is_error: false, message: message.to_string() });
+    }
+
+    fn errors(&self) -> Vec<&str> {
+        self.0.iter().filter(|p| p.is_error).map(|p| p.message.as_str()).collect()
+    }
+
+    fn warnings(&self) -> Vec<&str> {
+        self.0.iter().filter(|p| !p.is_error).map(|p| p.message.as_str()).collect()
+    }
+
+    // Each category's problems under its own heading, errors first.
+    fn print_by_category(&self) {
+        let mut categories = Vec::new();
+        for p in &self.0 {
+            if !categories.contains(&p.category) {
+                categories.push(p.category);
+            }
+        }
+        for category in categories {
+            println!("{}:", category);
+            let (errors, warnings): (Vec<_>, Vec<_>) = self.0.iter()
+                .filter(|p| p.category == category)
+                .partition(|p| p.is_error);
+            for p in errors {
+                println!("    Error: {}", p.message);
+            }
+            for p in warnings {
+                println!("    Warning: {}", p.message);
+            }
+        }
+    }
+}
+
+fn rom_problems(game: &Game) -> Problems {
+    let mut problems = Problems::default();
+
+    // Files inside of the system data get their own error below.
+    for (a, b) in game.rom_layout().overlaps() {
+        if a.is_system_data() == b.is_system_data() {
+            problems.error("Layout", format!("{} overlaps {}", a.name(), b.name()));
+        }
+    }
+    for (f, section) in game.files_in_system_data() {
+        problems.error("Layout", format!("{} is stored inside of {}", f.info.normalized_path(), section));
+    }
+    for f in game.files_past_capacity() {
+        problems.error("Layout", format!(
+            "{} ends past the end of the image ({:#010x})",
+            f.info.normalized_path(),
+            game.capacity,
+        ));
+    }
+    if let Some(e) = game.header.audio_streaming_problem() {
+        problems.error("Header", e);
+    }
+    for f in game.files_outside_user_area() {
+        problems.warning("Layout", format!("{} is outside of the user area", f.info.normalized_path()));
+    }
+    if let Some(e) = game.header.disc_problem() {
+        problems.warning("Header", e);
+    }
+    if let Err(e) = build_stamp::read(game.header.raw()) {
+        problems.warning("Header", e);
+    }
+    if let Some(e) = game.header.alignment_problem() {
+        problems.error("Boot", e);
+    }
+    if let Some(p) = game.apploader.oversized() {
+        problems.error("Boot", p);
+    }
+    match boot_limit_problem(game.apploader.total_size(), game.header.max_fst_size) {
+        Some(p) if p.is_error() => problems.error("Boot", p),
+        Some(p) => problems.warning("Boot", p),
+        None => {},
+    }
+    for r in &game.fst.repairs {
+        problems.error("FST", format!("{}, which gcmod worked around to read it", r));
+    }
+    // Fine on a console, but it's where extracting puts the system data
+    match game.system_paths() {
+        Ok(system) if system.is_alternate() => problems.warning("FST", format!(
+            "The ROM has its own /{}, so extracting puts the system data in {} instead",
+            SYSTEM_DATA_DIR, system.dir(),
+        )),
+        Ok(_) => {},
+        Err(e) => problems.warning("FST", e),
+    }
+    problems
+}
+
+fn init_project(
+    dir: impl AsRef<Path>,
+    game_code: &str,
+    maker_code: &str,
+    title: &str,
+    dol_path: impl AsRef<Path>,
+    apploader_path: Option<impl AsRef<Path>>,
+    reporter: &Reporter,
+) -> eyre::Result<()> {
+    let (dir, dol_path) = (dir.as_ref(), dol_path.as_ref());
+    if dir.exists() {
+        ensure!(
+            dir.is_dir() && fs::read_dir(dir)?.next().is_none(),
+            "{} already exists and isn't an empty directory.",
+            dir.display(),
+        );
+    }
+
+    // Check everything before creating anything
+    let header = Header::default_with(game_code, maker_code, title)?;
+    let mut dol = File::open(dol_path)
+        .wrap_err_with(|| format!("Couldn't open {}", dol_path.display()))?;
+    DOLHeader::new(&mut dol, 0)
+        .wrap_err_with(|| format!("{} isn't a valid DOL", dol_path.display()))?;
+    if let Some(path) = &apploader_path {
+        let path = path.as_ref();
+        let apploader = File::open(path)
+            .wrap_err_with(|| format!("Couldn't open {}", path.display()))?;
+        Apploader::new(apploader, 0)
+            .wrap_err_with(|| format!("{} isn't a valid apploader", path.display()))?;
+    }
+
+    fs::create_dir_all(dir.join(HEADER_PATH).parent().unwrap())
+        .wrap_err_with(|| format!("Couldn't create {}", dir.display()))?;
+    header.write(File::create(dir.join(HEADER_PATH))?)?;
+    fs::copy(dol_path, dir.join(DOL_PATH))?;
+    match apploader_path {
+        Some(path) => { fs::copy(path, dir.join(APPLOADER_PATH))?; },
+        None => {
+            reporter.warning("No apploader given, the ROM will only boot in emulators that skip the apploader.");
+            Apploader::write_placeholder(File::create(dir.join(APPLOADER_PATH))?)?;
+        },
+    }
+    // Rebuilding will replace this with the real file system table.
+    FST::empty().write(File::create(dir.join(FST_PATH))?)?;
+
+    reporter.result(format!(
+        "Created {}, put the game's files in it and run `gcmod rebuild {} <output>`.",
+        dir.display(),
+        dir.display(),
+    ));
+    Ok(())
+}
+
+fn run_doctor(operation: Option<Operation>, paths: Vec<PathBuf>) -> eyre::Result<()> {
+    if operation.is_some() {
+        ensure!(paths.len() <= 2, "With --for, pass the input and then the destination");
+    } else {
+        ensure!(!paths.is_empty(), "Pass something to check, or --for to check where it'll be written");
+    }
+    let context = doctor::Context::new(operation, &paths);
+    let results = doctor::run(&context, &SystemProbe);
+    for result in &results {
+        println!("{}  {:<12} {}", result.status, result.check, result.message);
+        if let Some(advice) = &result.advice {
+            println!("{:18} {}", "", advice);
+        }
+    }
+    let failed = results.iter().filter(|r| r.status == Status::Fail).count();
+    ensure!(failed == 0, "{} {} failed", failed, if failed == 1 { "check" } else { "checks" });
+    println!("No checks failed.");
+    Ok(())
+}
+
+// Runs the stages of `self-test` in order, stopping at the first one that
+// fails, since the rest depend on it.
+#[derive(Default)]
+struct SelfTest {
+    failed: bool,
+}
+
+impl SelfTest {
+    fn stage<T>(&mut self, name: &str, f: impl FnOnce() -> eyre::Result<T>) -> Option<T> {
+        let started = Instant::now();
+        let result = f();
+        let ms = started.elapsed().as_secs_f64() * 1000.0;
+        match result {
+            Ok(value) => {
+                println!("PASS  {:<12} {:>9.1} ms", name, ms);
+                Some(value)
+            },
+            Err(e) => {
+                println!("FAIL  {:<12} {:>9.1} ms  {:#}", name, ms, e);
+                self.failed = true;
+                None
+            },
+        }
+    }
+}
+
+fn self_test(rom_path: Option<&str>) -> eyre::Result<()> {
+    let mut test = SelfTest::default();
+    match rom_path {
+        Some(path) => self_test_rom(path, &mut test),
+        None => self_test_round_trip(&mut test),
+    };
+    ensure!(!test.failed, "The self-test failed");
+    println!("All stages passed.");
+    Ok(())
+}
+
+// Everything happens in a workspace, which is deleted afterwards, or left
+// for looking into if a stage fails.
+fn self_test_round_trip(test: &mut SelfTest) -> Option<()> {
+    let scratch = test.stage("setup", || {
+        Workspace::new("self-test").wrap_err("Couldn't create a directory in the work directory")
+    })?;
+    let dir = scratch.path();
+    let (tree, image) = (dir.join("tree"), dir.join("original.iso"));
+    let (extracted, rebuilt) = (dir.join("extracted"), dir.join("rebuilt.iso"));
+    let options = || RebuildOptions { capacity: synthetic::CAPACITY, ..RebuildOptions::default() };
+
+    test.stage("build", || synthetic::write_image(&tree, &image).wrap_err("Couldn't build the ROM"))?;
+    test.stage("extract", || {
+        let (mut game, iso) = try_to_open_game(&image, 0)?;
+        let failures = game.extract(iso, &extracted, &ExtractOptions::default(), &Reporter::quiet())?;
+        ensure!(failures.is_empty(), "{} files couldn't be extracted", failures.len());
+        for &(path, size) in synthetic::FILES {
+            let contents = fs::read(extracted.join(path)).wrap_err_with(|| format!("Couldn't read {}", path))?;
+            ensure!(contents == synthetic::contents(size), "{} came out different", path);
+        }
+        Ok(())
+    })?;
+    test.stage("rebuild", || {
+        let manifest = Manifest::read(extracted.join(MANIFEST_NAME)).wrap_err("Couldn't read the manifest")?;
+        let options = RebuildOptions { index_map: Some(manifest.index_map()), ..options() };
+        let file = File::create(&rebuilt)?;
+        ROMRebuilder::rebuild(&extracted, &options, file, &Reporter::quiet()).wrap_err("Couldn't rebuild the ROM")
+    })?;
+    test.stage("verify", || {
+        let (game, _) = try_to_open_game(&rebuilt, 0)?;
+        let errors = rom_problems(&game).errors().join("; ");
+        ensure!(errors.is_empty(), "The rebuilt ROM has problems: {}", errors);
+        let (original, rebuilt) = (rom_sha1(&image)?, rom_sha1(&rebuilt)?);
+        ensure!(
+            original == rebuilt,
+            "The rebuilt ROM's SHA-1 is {}, but the original's is {}",
+            to_hex(&rebuilt), to_hex(&original),
+        );
+        Ok(())
+    })?;
+    test.stage("cleanup", || scratch.finish().wrap_err("Couldn't remove the self-test's directory"))
+}
+
+// Only reads the ROM. Warnings don't fail it, but they're listed.
+fn self_test_rom(path: &str, test: &mut SelfTest) -> Option<()> {
+    let (game, _) = test.stage("parse", || try_to_open_game(path, 0))?;
+    let gaps = test.stage("layout", || Ok(game.rom_layout().gaps(game.capacity)))?;
+    println!("      {} bytes unused in {} gaps", gaps.iter().map(|(s, e)| e - s).sum::<u64>(), gaps.len());
+    let problems = test.stage("consistency", || {
+        let problems = rom_problems(&game);
+        let errors = problems.errors();
+        ensure!(errors.is_empty(), "{}", errors.join("; "));
+        Ok(problems)
+    })?;
+    for w in problems.warnings() {
+        println!("      Warning: {}", w);
+    }
+    let sha1 = test.stage("hash", || rom_sha1(path))?;
+    println!("      SHA-1: {}", to_hex(&sha1));
+    Some(())
+}
+
+fn rom_sha1(path: impl AsRef<Path>) -> eyre::Result<[u8; 20]> {
+    let mut rom = RomReadHandle::open(path.as_ref())?;
+    let mut hasher = Sha1Writer::new(io::sink());
+    io::copy(&mut rom, &mut hasher)?;
+    Ok(hasher.finish().1)
+}
+
+// With `full`, matches by the whole image's SHA-1 instead of its quick-id.
+fn catalog_roms(
+    dir: impl AsRef<Path>,
+    refresh: bool,
+    list: bool,
+    find: Option<&str>,
+    match_rom: Option<&str>,
+    full: bool,
+    reporter: &Reporter,
+) -> eyre::Result<()> {
+    let dir = dir.as_ref();
+    ensure!(dir.is_dir(), "{} isn't a directory.", dir.display());
+    let catalog_path = dir.join(CATALOG_NAME);
+
+    if list || find.is_some() || match_rom.is_some() {
+        let catalog = Catalog::read(&catalog_path).wrap_err_with(|| format!(
+            "Couldn't read the index, run `gcmod catalog {}` first",
+            dir.display(),
+        ))?;
+        let hash = match match_rom {
+            Some(rom) if full => {
+                let rom = RomReadHandle::open(rom).wrap_err_with(|| format!("Couldn't open {}", rom))?;
+                Some(to_hex(&sha1_of(rom).wrap_err("Couldn't hash the ROM")?))
+            },
+            Some(rom) => Some(quick_id(rom).wrap_err_with(|| format!("Couldn't read {}", rom))?.0),
+            None => None,
+        };
+        let entries: Vec<_> = match (find, &hash) {
+            (_, Some(hash)) => catalog.matching(hash).collect(),
+            (Some(query), None) => catalog.find(query).collect(),
+            (None, None) => catalog.entries.iter().collect(),
+        };
+        if full && hash.is_some() {
+            let unhashed = catalog.entries.iter().filter(|e| e.sha1.is_none()).count();
+            if unhashed != 0 {
+                reporter.warning(format!(
+                    "{} of the ROMs in the index weren't hashed in full, run `gcmod catalog --full {}` to hash them.",
+                    unhashed, dir.display(),
+                ));
+            }
+        }
+        if hash.is_some() && entries.is_empty() {
+            reporter.status("No ROMs in the index match.");
+        }
+        for e in entries {
+            match (&e.summary, &e.error) {
+                (Some(s), _) => reporter.result(format!(
+                    "{}  {} ({}, disc {}, version {})  {}",
+                    s.id,
+                    e.titles.as_ref().map_or(&s.title, |t| &t.best(None).text),
+                    s.region, s.disc, s.version, e.path,
+                )),
+                (None, error) => reporter.result(format!(
+                    "??????  {}: {}",
+                    e.path,
+                    error.as_deref().unwrap_or("unknown error"),
+                )),
+            }
+        }
+        return Ok(());
+    }
+
+    let mut catalog = if catalog_path.exists() {
+        Catalog::read(&catalog_path).unwrap_or_else(|e| {
+            reporter.warning(format!("Couldn't read the existing index ({e}), starting over."));
+            Catalog::default()
+        })
+    } else {
+        Catalog::default()
+    };
+    let update = catalog.update(dir, refresh, full)
+        .wrap_err_with(|| format!("Couldn't scan {}", dir.display()))?;
+    catalog.write(&catalog_path)
+        .wrap_err_with(|| format!("Couldn't write {}", catalog_path.display()))?;
+
+    reporter.result(format!(
+        "{} ROMs indexed: {} parsed ({} failed), {} unchanged, {} removed, {} hashed.",
+        catalog.entries.len(), update.parsed, update.failed, update.cached, update.removed, update.hashed,
+    ));
+    Ok(())
+}
+
+fn hash_rom(path: impl AsRef<Path>, quick: bool, reporter: &Reporter) -> eyre::Result<()> {
+    let path = path.as_ref();
+    ensure!(path.exists(), "The file {} doesn't exist.", path.display());
+    if quick {
+        let (id, plan) = quick_id(path).wrap_err("Couldn't read the ROM")?;
+        reporter.result(format!("Quick-id: {}", id));
+        reporter.status(format!(
+            "Sampled {} of {} bytes:",
+            format_u64(plan.sampled_bytes(), NumberStyle::Decimal),
+            format_u64(plan.image_len, NumberStyle::Decimal),
+        ));
+        for r in &plan.regions {
+            reporter.status(format!("  {:<12} {:#010x}  {} bytes", r.name, r.offset, r.size));
+        }
+        reporter.status(
+            "A quick-id is for identifying a dump, not a cryptographic hash of it: images that only differ outside of \
+            these regions have the same one. Hash without --quick to check that a dump is intact.",
+        );
+        return Ok(());
+    }
+
+    let mut rom = RomReadHandle::open(path).wrap_err("Couldn't open the ROM")?;
+    let total = rom.seek(SeekFrom::End(0))?;
+    rom.seek(SeekFrom::Start(0))?;
+    let mut out = Sha1Writer::new(CrcWriter::new(io::sink()));
+    let mut buf = vec![0; WRITE_CHUNK_SIZE];
+    let mut done = 0;
+    loop {
+        let n = rom.read(&mut buf).wrap_err("Couldn't read the ROM")?;
+        if n == 0 {
+            break;
+        }
+        out.write_all(&buf[..n])?;
+        done += n as u64;
+        reporter.progress(done, total, format!("{}% hashed.", done * 100 / total.max(1)));
+    }
+    reporter.finish_progress();
+    let (crc, sha1) = out.finish();
+    reporter.result(format!("SHA-1:  {}", to_hex(&sha1)));
+    reporter.result(format!("CRC-32: {:08x}", crc.crc()));
+    Ok(())
+}
+
+fn rewrite_rom_gaps(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    unscrub: bool,
+    force: bool,
+    capacity: Option<&str>,
+    expect_crc: Option<&str>,
+    reporter: &Reporter,
+) -> eyre::Result<()> {
+    let output = output.as_ref();
+    ensure!(!output.exists(), "{} already exists.", output.display());
+
+    let expect_crc = expect_crc
+        .map(|c| u32::from_str_radix(c.trim_start_matches("0x"), 16))
+        .transpose()
+        .wrap_err("Invalid CRC")?;
+    let capacity = capacity
+        .map(|c| parse_capacity(c).ok_or_else(|| eyre!("Invalid capacity {:?}", c)))
+        .transpose()?;
+
+    let (game, mut iso) = try_to_open_game(input.as_ref(), 0)?;
+    let layout = game.rom_layout();
+
+    // What the unused space is only comes out exactly when the layout and
+    // the FST are what they say they are, and the ROM would boot
+    let mut concerns: Vec<String> = layout.overlaps().iter()
+        .map(|(a, b)| format!("{} overlaps {}", a.name(), b.name()))
+        .collect();
+    let boot = BootTrace::new(&mut iso, game.capacity).wrap_err("Couldn't read the ROM")?;
+    let boot_errors = boot.steps.iter()
+        .flat_map(|s| &s.notes)
+        .filter(|n| n.severity == Severity::Error)
+        .map(|n| n.message.clone());
+    for concern in rom_problems(&game).errors().into_iter().map(str::to_owned).chain(boot_errors) {
+        if !concerns.contains(&concern) {
+            concerns.push(concern);
+        }
+    }
+    if !concerns.is_empty() {
+        for c in &concerns {
+            reporter.warning(c);
+        }
+        ensure!(force, "The ROM has problems, so its unused space can't be found exactly. Pass --force to continue anyway.");
+    }
+
+    let image_len = capacity.unwrap_or(game.capacity);
+    let content_end = layout.iter().map(|s| s.start() + s.size() as u64).max().unwrap_or(0);
+    ensure!(
+        content_end <= image_len,
+        "The ROM's contents go to {:#x}, so they don't fit in a disc of {:#x} bytes",
+        content_end, image_len,
+    );
+    // Past the end of the image reads as zeros, like for a trimmed one
+    iso.get_mut().extend_to(image_len);
+    let gaps = layout.gaps(image_len);
+    let fill = if unscrub {
+        let game_code = game.header.game_code.as_bytes().try_into()
+            .map_err(|_| eyre!("Invalid game code {:?}", game.header.game_code))?;
+        GapFill::Junk { game_code, disc_number: game.header.disk_id }
+    } else {
+        GapFill::Zeros
+    };
+
+    // Written to a temporary file that's only renamed to `output` once its
+    // CRC-32 is known to be right, so a wrong image never ends up there.
+    let temp_path = workspace::sibling_temp(output).ok_or_eyre("Invalid output path")?;
+    let mut out = File::create(&temp_path).wrap_err("Failed to create output")?;
+    reporter.phase("write");
+    let result = rewrite_gaps_to_new_file(&mut iso, &mut out, image_len, &gaps, fill, |done, total| {
+        let detail = ProgressDetail { bytes: Some(done), total_bytes: Some(total), path: None };
+        reporter.progress_detail(done, total, detail, format!("{}% written.", done * 100 / total))
+    });
+    reporter.finish_progress();
+    drop(out);
+
+    let crc = match result {
+        Ok(crc) => crc,
+        Err(e) => {
+            let _ = remove_file(&temp_path);
+            return Err(e).wrap_err("Failed to write output");
+        },
+    };
+    reporter.result(format!("CRC-32: {:08x}", crc));
+
+    if let Some(expected) = expect_crc {
+        if crc != expected {
+            let _ = remove_file(&temp_path);
+            bail!("CRC-32 mismatch, expected {:08x}, so {} wasn't written", expected, output.display());
+        }
+    }
+    match io_util::rename_or_copy(&temp_path, output) {
+        Ok(false) => {},
+        Ok(true) => reporter.warning("The temporary file was on a different file system, so it was copied into place instead of renamed."),
+        Err(e) => {
+            let _ = remove_file(&temp_path);
+            return Err(e).wrap_err_with(|| format!("Failed to move the output to {}", output.display()));
+        },
+    }
+    Ok(())
+}
+
+// `pieces` is an extracted DOL and FST, to use instead of `rom`.
+fn analyze_references(rom: Option<&str>, pieces: Option<(&str, &str)>, style: NumberStyle) -> eyre::Result<()> {
+    match (rom, pieces) {
+        (_, Some((dol_path, fst_path))) => {
+            let mut dol_file = BufReader::new(File::open(dol_path).wrap_err("Couldn't open the DOL")?);
+            let dol = DOLHeader::new(&mut dol_file, 0).wrap_err("Couldn't read the DOL")?;
+            let fst_file = BufReader::new(File::open(fst_path).wrap_err("Couldn't open the FST")?);
+            let fst = FST::new(fst_file, 0).wrap_err("Couldn't read the FST")?;
+            print_references(&dol, dol_file, &fst, style)
+        },
+        (Some(rom), None) => {
+            let (game, iso) = try_to_open_game(rom, 0)?;
+            print_references(&game.dol, iso, &game.fst, style)
+        },
+        (None, None) => bail!("No ROM given"),
+    }
+}
+
+fn print_references(dol: &DOLHeader, reader: impl Read + Seek, fst: &FST, style: NumberStyle) -> eyre::Result<()> {
+    println!("Text: {} bytes", format_usize(dol.text_size(), style));
+    println!("Data: {} bytes", format_usize(dol.data_size(), style));
+    let refs = dol.find_references(reader, fst).wrap_err("Couldn't read the DOL's data segments")?;
+    println!();
+    println!("This is a heuristic. Files count as referenced if their paths are in the DOL's data segments.");
+    println!("Unreferenced files might still be loaded, by paths built at runtime or named in a REL.");
+    for (label, files) in [("Referenced", &refs.referenced), ("Unreferenced", &refs.unreferenced)] {
+        let total = files.iter().map(|f| f.size).sum();
+        println!();
+        println!("{}: {} files, {} bytes", label, files.len(), format_usize(total, style));
+        for f in files {
+            println!("  {:>10} {}", format_usize(f.size, style), f.info.normalized_path());
+        }
+    }
+    Ok(())
+}
+
+// `input` is a DOL file if it ends in .dol, and a ROM otherwise.
+fn unpack_dol(input: impl AsRef<Path>, dir: impl AsRef<Path>, force: bool) -> eyre::Result<()> {
+    let (input, dir) = (input.as_ref(), dir.as_ref());
+    io_util::check_destination_dir(dir, force)?;
+    let is_dol = input.extension().is_some_and(|e| e.eq_ignore_ascii_case("dol"));
+    let (manifest, difference) = if is_dol {
+        let mut file = BufReader::new(File::open(input).wrap_err("Couldn't open the DOL")?);
+        let dol = DOLHeader::new(&mut file, 0).wrap_err("Couldn't read the DOL")?;
+        dol.unpack_checked(file, dir)
+    } else {
+        let (game, iso) = try_to_open_game(input, 0)?;
+        game.dol.unpack_checked(iso, dir)
+    }.wrap_err_with(|| format!("Failed to write the segments to {}", dir.display()))?;
+    if let Some(offset) = difference {
+        report::warn(format!(
+            "The DOL has data outside of its segments, at {:#x}, so repacking them won't give back the same DOL.",
+            offset,
+        ));
+    }
+    println!("Wrote {} segments to {}.", manifest.segments.len(), dir.display());
+    Ok(())
+}
+
+fn repack_dol(dir: impl AsRef<Path>, output: impl AsRef<Path>) -> eyre::Result<()> {
+    let (dir, output) = (dir.as_ref(), output.as_ref());
+    ensure!(!output.exists(), "{} already exists.", output.display());
+    let file = File::create(output).wrap_err_with(|| format!("Couldn't create {}", output.display()))?;
+    match DOLHeader::pack(dir, BufWriter::new(file)) {
+        Ok(dol) => {
+            println!("Wrote a {} byte DOL with {} segments to {}.", dol.dol_size, dol.iter_segments().count(), output.display());
+            Ok(())
+        },
+        Err(e) => {
+            let _ = remove_file(output);
+            Err(e).wrap_err_with(|| format!("Failed to build a DOL from {}", dir.display()))
+        },
+    }
+}
+
+fn export_ini(
+    rom_path: impl AsRef<Path>,
+    out: Option<&str>,
+    gecko: Option<&str>,
+    wiki: bool,
+    force: bool,
+) -> eyre::Result<()> {
+    let rom_path = rom_path.as_ref();
+    let (game, _) = try_to_open_game(rom_path, 0)?;
+    if wiki {
+        return game_ini::write_wiki_infobox(&game, io::stdout().lock()).wrap_err("Failed to write the infobox");
+    }
+
+    let game_id = game_ini::game_id(&game);
+    let revision = game.header.version;
+    let name = game_ini::ini_file_name(&game_id, revision)?;
+    let output = match out.map(Path::new) {
+        Some(out) if out.is_dir() => out.join(&name),
+        Some(out) => out.to_owned(),
+        None => PathBuf::from(&name),
+    };
+    let out_name = output.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    if !game_ini::ini_applies_to(&out_name, &game_id, revision) {
+        report::warn(format!("Dolphin won't load {} for {}, which it looks for as {}.", out_name, game_id, name));
+    }
+    if output.exists() {
+        ensure!(force, "{} already exists, pass --force to replace it.", output.display());
+        let existing = fs::read_to_string(&output).wrap_err_with(|| format!("Couldn't read {}", output.display()))?;
+        if let Some(id) = game_ini::embedded_game_id(&existing).filter(|id| *id != game_id) {
+            report::warn(format!("{} is for {}, not {}, and is being replaced.", output.display(), id, game_id));
+        }
+    }
+
+    let mut ini = GameIni::for_game(&game, &rom_path.file_name().unwrap_or_default().to_string_lossy());
+    if let Some(path) = gecko {
+        let codes = fs::read_to_string(path).wrap_err_with(|| format!("Couldn't read {}", path))?;
+        let parsed = gecko::parse_codes(&codes).wrap_err_with(|| format!("Invalid Gecko codes in {}", path))?;
+        println!("Adding {} Gecko codes.", parsed.len());
+        ini.gecko = Some(codes);
+    }
+    let file = File::create(&output).wrap_err_with(|| format!("Couldn't create {}", output.display()))?;
+    ini.write(BufWriter::new(file)).wrap_err_with(|| format!("Failed to write {}", output.display()))?;
+    println!("Wrote {}.", output.display());
+    Ok(())
+}
+
+fn show_origin_attributes(path: impl AsRef<Path>) -> eyre::Result<()> {
+    let path = path.as_ref();
+    ensure!(path.exists(), "The file {} doesn't exist.", path.display());
+    let attributes = xattrs::read_origin_attributes(path)
+        .wrap_err_with(|| format!("Couldn't read the attributes of {}", path.display()))?;
+    ensure!(!attributes.is_empty(), "{} doesn't have any gcmod attributes", path.display());
+    for (name, value) in attributes {
+        let label = match name {
+            xattrs::OFFSET_ATTRIBUTE => "Offset",
+            xattrs::SIZE_ATTRIBUTE => "Size",
+            xattrs::INDEX_ATTRIBUTE => "Index",
+            _ => "Game ID",
+        };
+        match value.parse::<u64>() {
+            Ok(n) if name == xattrs::OFFSET_ATTRIBUTE => println!("{}: {} ({:#x})", label, n, n),
+            _ => println!("{}: {}", label, value),
+        }
+    }
+    Ok(())
+}
+
+fn rescue_rom(input: impl AsRef<Path>, extract_to: Option<&str>, reporter: &Reporter) -> eyre::Result<()> {
+    let mut iso = BufReader::new(RomReadHandle::open(input.as_ref()).wrap_err("Couldn't open ROM")?);
+    let len = iso.get_ref().size()?;
+
+    // Usually why the ROM needs rescuing
+    if let Ok(header) = Header::new(&mut iso, 0) {
+        let apploader = Apploader::new(&mut iso, APPLOADER_OFFSET).ok();
+        if let Some(problem) = header.offset_problem(apploader.as_ref(), len) {
+            reporter.warning(format!("{}, so it can't be used to read the ROM.", problem));
+        }
+    }
+
+    reporter.phase("scan");
+    reporter.status("Scanning for system data...");
+    let mut candidates = Vec::new();
+    candidates.extend(rescue::check_apploader(&mut iso, len)?);
+    candidates.extend(rescue::scan_fsts(&mut iso, len, |done, total| {
+        reporter.progress(done, total, format!("{}% scanned.", done * 100 / total))
+    })?);
+    reporter.finish_progress();
+    candidates.extend(rescue::scan_dol_headers(&mut iso, len)?);
+    for offset in rescue::dol_offsets_after(&candidates) {
+        candidates.extend(rescue::check_dol_header_at(&mut iso, offset, len)?);
+    }
+
+    for kind in [CandidateKind::Apploader, CandidateKind::Dol, CandidateKind::Fst] {
+        let mut found = candidates.iter().filter(|c| c.kind == kind).peekable();
+        if found.peek().is_none() {
+            reporter.result(format!("No {} found.", kind));
+        }
+        for c in found {
+            reporter.result(format!(
+                "{} at {:#010x} ({:#x} bytes), {} confidence: {}",
+                c.kind, c.offset, c.size, c.confidence, c.details,
+            ));
+        }
+    }
+
+    let Some(output) = extract_to else {
+        return Ok(());
+    };
+    let dol = rescue::best(&candidates, CandidateKind::Dol).ok_or_eyre("Can't extract without a DOL")?;
+    let fst = rescue::best(&candidates, CandidateKind::Fst).ok_or_eyre("Can't extract without an FST")?;
+    let apploader = rescue::best(&candidates, CandidateKind::Apploader)
+        .is_some_and(|c| c.confidence > Confidence::Low);
+    if !apploader {
+        reporter.warning("No usable apploader was found, so a placeholder is extracted instead.");
+    }
+    reporter.status(format!("Using the DOL at {:#x} and the FST at {:#x}.", dol.offset, fst.offset));
+
+    let mut game = Game::open_rescued(&mut iso, dol.offset, fst.offset, apploader)
+        .wrap_err("Couldn't open the ROM with the system data found")?;
+    let options = ExtractOptions {
+        keep_going: true,
+        rescued: true,
+        ..Default::default()
+    };
+    let failures = game.extract(&mut iso, output, &options, reporter).wrap_err("Failed to extract game")?;
+    ensure!(
+        failures.is_empty(),
+        "{} files failed to extract, see {}",
+        failures.len(),
+        Path::new(output).join(EXTRACTION_ERRORS_NAME).display(),
+    );
+    Ok(())
+}
+
+// Trimmed images read as zeros past the end of the file, so they can be read
+// like any other.
+fn try_to_open_game<P>(path: P, offset: u64) -> eyre::Result<(Game, BufReader<ZeroExtended<RomReadHandle>>)>
 where
     P: AsRef<Path>,
 {
     let path = path.as_ref();
     ensure!(path.exists(), "The file {} doesn't exist.", path.display());
 
-    let iso = File::open(path).wrap_err("Couldn't open ISO file")?;
-    let mut iso = BufReader::new(iso);
-    Game::open(&mut iso, offset)
-        .map(|game| (game, iso))
-        .wrap_err("Invalid ISO")
+    let spooling = spool_reporter();
+    let mut started = false;
+    let iso = RomReadHandle::open_with_progress(path, |done, total| {
+        if !std::mem::replace(&mut started, true) {
+            spooling.phase("spool");
+        }
+        spooling.progress(done, total, format!("Decompressing... {}%", done * 100 / total.max(1)));
+    });
+    spooling.finish_progress();
+    open_game(iso.wrap_err("Couldn't open ISO file")?, offset)
+}
+
+// Like `try_to_open_game`, for a ROM that's already open.
+fn open_game(iso: RomReadHandle, offset: u64) -> eyre::Result<(Game, BufReader<ZeroExtended<RomReadHandle>>)> {
+    let mut iso = BufReader::new(ZeroExtended::new(iso)?);
+    let game = Game::open(&mut iso, offset).wrap_err("Invalid ISO")?;
+    if !game.fst.repairs.is_empty() {
+        report::warn("The file system table is malformed, so it was repaired to read it. `gcmod verify` lists what was wrong.");
+    }
+    if let Some(problem) = game.apploader.oversized() {
+        report::warn(format!("{}, so only those are counted as the apploader.", problem));
+    }
+    if game.trimmed_to.is_some() {
+        iso.get_mut().extend_to(offset + game.capacity);
+    }
+    Ok((game, iso))
 }
@@ -0,0 +1,188 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "serde")]
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build_info,
+    hash::to_hex,
+    io_util::DiskFull,
+    paths::SystemPaths,
+    sections::fst::{dedup::DuplicateFile, entry::PaddedFile},
+    Game,
+};
+
+// The manifest is written to the root of an extracted ROM. Rebuilding skips
+// anything starting with a dot, so it never ends up on the rebuilt ROM.
+pub const MANIFEST_NAME: &str = ".gcmod";
+
+// Information about where everything was on the ROM a tree was extracted
+// from, which isn't otherwise recoverable from the extracted files.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Manifest {
+    pub gcmod_version: String,
+    // Manifests from before these were recorded don't have them
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gcmod_git_hash: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gcmod_features: Vec<String>,
+    pub game_id: String,
+    pub entries: Vec<ManifestEntry>,
+    // Files that were extracted as links to other files with the same
+    // contents, see `extract --dedup`.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub duplicates: Vec<DuplicateFile>,
+    // Files and directories that were extracted under a different name than
+    // they have on the ROM, see `extract --infer-extensions` and
+    // `case_names`.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub renamed: Vec<RenamedFile>,
+    // Files that went past the end of the image, so their ends are zeros
+    // that weren't really there.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub padded: Vec<PaddedFile>,
+    // Where the system data is, if it isn't &&systemdata because the ROM
+    // has one of its own, see `SystemPaths::for_fst`.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub system_dir: Option<String>,
+    // The file that was being written when extracting ran out of space, if
+    // what was written of it couldn't be removed, see `DiskFull::left_behind`.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub partial: Option<PartialFile>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ManifestEntry {
+    // With forward slashes and no trailing slash, see `EntryInfo::normalized_path`.
+    pub path: String,
+    pub index: usize,
+    pub directory: bool,
+    // The SHA-1 of the file as extracted, in hex, for `extract --refresh` to
+    // tell which files changed. Older manifests don't have them.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub sha1: Option<String>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RenamedFile {
+    // On the ROM, normalized like `ManifestEntry::path`
+    pub path: String,
+    // Just the name it has in the tree, which is in the same directory,
+    // even if that was renamed too
+    pub extracted_as: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PartialFile {
+    // On the ROM, normalized like `ManifestEntry::path`
+    pub path: String,
+    // Relative to the root of the tree
+    pub output: PathBuf,
+}
+
+impl Manifest {
+    pub fn new(game: &Game) -> Manifest {
+        let entries = game.fst.entries().iter()
+            .map(|e| ManifestEntry {
+                path: e.info().normalized_path(),
+                index: e.info().index,
+                directory: e.is_dir(),
+                sha1: None,
+            })
+            .collect();
+
+        Manifest {
+            gcmod_version: build_info::VERSION.to_owned(),
+            gcmod_git_hash: build_info::GIT_HASH.to_owned(),
+            gcmod_features: build_info::features(),
+            game_id: format!("{}{}", game.header.game_code, game.header.maker_code),
+            entries,
+            duplicates: Vec::new(),
+            renamed: Vec::new(),
+            padded: Vec::new(),
+            system_dir: None,
+            partial: None,
+        }
+    }
+
+    pub fn system_paths(&self) -> SystemPaths {
+        self.system_dir.as_deref().map(SystemPaths::new).unwrap_or_default()
+    }
+
+    // Sets the hashes of the files in `hashes`, which are by index.
+    pub fn record_hashes(&mut self, hashes: &BTreeMap<usize, [u8; 20]>) {
+        for entry in &mut self.entries {
+            if let Some(hash) = hashes.get(&entry.index) {
+                entry.sha1 = Some(to_hex(hash));
+            }
+        }
+    }
+
+    // Records the file `full` stopped at as cut short, if it was left in the
+    // tree at `root`. It loses its hash, so refreshing writes it again.
+    pub fn record_partial(&mut self, full: &DiskFull, root: &Path) {
+        if full.left_behind.is_none() {
+            return;
+        }
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.path == full.path) {
+            entry.sha1 = None;
+        }
+        self.partial = Some(PartialFile {
+            path: full.path.clone(),
+            output: full.output.strip_prefix(root).unwrap_or(&full.output).to_owned(),
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Manifest> {
+        let file = BufReader::new(File::open(path)?);
+        serde_json::from_reader(file).map_err(io::Error::from)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self).map_err(io::Error::from)
+    }
+
+    // Maps each path to the FST index it had on the original ROM. The paths
+    // are the ones `FSTRebuilder` makes, which are relative to the root,
+    // except for the root itself, which is "/".
+    pub fn index_map(&self) -> HashMap<PathBuf, usize> {
+        self.entries.iter()
+            .map(|e| {
+                let path = match e.path.as_str() {
+                    "/" => "/",
+                    p => p.trim_start_matches('/'),
+                };
+                (PathBuf::from(path), e.index)
+            })
+            .collect()
+    }
+
+    // Maps the path of each renamed file or directory, relative to the root,
+    // to its name on the ROM, for `RebuildOptions::renames`. The paths have
+    // the names the directories they're in have on the ROM, and their own
+    // name in the tree.
+    pub fn renames(&self) -> HashMap<PathBuf, String> {
+        self.renamed.iter()
+            .filter_map(|r| {
+                let path = Path::new(r.path.trim_start_matches('/'));
+                let name = path.file_name()?.to_str()?.to_owned();
+                Some((path.with_file_name(&r.extracted_as), name))
+            })
+            .collect()
+    }
+}
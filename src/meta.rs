@@ -0,0 +1,294 @@
+// Everything gcmod parses out of a ROM, in one document for tools that don't
+// link against gcmod. Anything that changes the shape of the JSON has to bump
+// `SCHEMA_VERSION`.
+
+use std::{io, path};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_json::Value;
+
+use crate::{
+    sections::{
+        apploader::Apploader,
+        banner::Banner,
+        dol::segment::Segment,
+        fst::entry::{DirectoryEntry, Entry},
+        header::Header,
+    },
+    build_info,
+    provenance::Provenance,
+    titles::Titles,
+    Game,
+};
+
+// 2: Added `banner`
+// 3: Renamed `header.information.unknown` to `total_discs`, and added
+//    `long_file_name`, `pad_spec` and `dol_limit` after it
+// 4: Added `gcmod_git_hash` and `gcmod_features`
+// 5: Added `provenance`
+// 6: Added `titles`
+// 7: `layout` ends are exclusive, like `gaps`, instead of the last byte
+pub const SCHEMA_VERSION: u32 = 7;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Metadata<'a> {
+    pub schema_version: u32,
+    pub gcmod_version: &'static str,
+    pub gcmod_git_hash: &'static str,
+    pub gcmod_features: Vec<String>,
+    pub image_size: u64,
+    pub header: &'a Header,
+    pub provenance: Provenance,
+    pub banner: Option<Banner>,
+    pub titles: Titles,
+    pub apploader: &'a Apploader,
+    pub dol: DolMetadata<'a>,
+    pub fst: FstMetadata,
+    // Ends are exclusive, like everything else.
+    pub layout: Vec<LayoutRegion>,
+    // Unused space
+    pub gaps: Vec<Gap>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DolMetadata<'a> {
+    pub offset: u64,
+    pub size: usize,
+    pub entry_point: u64,
+    pub segments: Vec<&'a Segment>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FstMetadata {
+    pub offset: u64,
+    pub size: usize,
+    pub file_count: usize,
+    pub total_file_system_size: usize,
+    pub root: EntryMetadata,
+}
+
+// Paths are normalized, see `EntryInfo::normalized_path`, and names don't
+// have the trailing slash directory names do.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
+pub enum EntryMetadata {
+    File {
+        index: usize,
+        name: String,
+        path: String,
+        offset: u64,
+        size: usize,
+    },
+    Directory {
+        index: usize,
+        name: String,
+        path: String,
+        entries: Vec<EntryMetadata>,
+    },
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LayoutRegion {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Gap {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl<'a> Metadata<'a> {
+    // `banner` is what `Game::read_banner` found, if anything.
+    pub fn new(game: &'a Game, banner: Option<io::Result<Banner>>) -> Metadata<'a> {
+        let titles = Titles::new(&game.header, banner.as_ref());
+        let image_size = game.capacity;
+        let layout = game.rom_layout();
+        let regions = layout.iter()
+            .map(|s| LayoutRegion {
+                name: s.name(),
+                start: s.start(),
+                end: s.start() + s.size() as u64,
+            })
+            .collect();
+        let gaps = layout.gaps(image_size).into_iter()
+            .map(|(start, end)| Gap { start, end })
+            .collect();
+
+        Metadata {
+            schema_version: SCHEMA_VERSION,
+            gcmod_version: build_info::VERSION,
+            gcmod_git_hash: build_info::GIT_HASH,
+            gcmod_features: build_info::features(),
+            image_size,
+            header: &game.header,
+            provenance: game.provenance(),
+            banner: banner.and_then(Result::ok),
+            titles,
+            apploader: &game.apploader,
+            dol: DolMetadata {
+                offset: game.dol.offset,
+                size: game.dol.dol_size,
+                entry_point: game.dol.entry_point,
+                segments: game.dol.iter_segments().collect(),
+            },
+            fst: FstMetadata {
+                offset: game.fst.offset,
+                size: game.fst.size,
+                file_count: game.fst.file_count,
+                total_file_system_size: game.fst.total_file_size,
+                root: directory_metadata(game.fst.root(), game.fst.entries()),
+            },
+            layout: regions,
+            gaps,
+        }
+    }
+}
+
+// The parts of a metadata document that `apply-meta` reads. Everything other
+// than the header and banner text is only there so a dumped document can be
+// applied as is, and is never written to the ROM.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct MetadataUpdate {
+    pub schema_version: u32,
+    pub gcmod_version: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gcmod_git_hash: Value,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gcmod_features: Value,
+    pub header: Header,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub provenance: Value,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub banner: Option<Banner>,
+    // Only what's in the header and banner already
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub titles: Value,
+    pub image_size: Value,
+    pub apploader: Value,
+    pub dol: Value,
+    pub fst: Value,
+    pub layout: Value,
+    pub gaps: Value,
+}
+
+impl MetadataUpdate {
+    // Fails on documents from newer versions of gcmod, even if they'd parse,
+    // since fields could have changed meaning. Header fields older documents
+    // don't have are taken from `current`, so applying them leaves those alone.
+    pub fn from_json(json: &str, current: &Header) -> serde_json::Result<MetadataUpdate> {
+        let mut doc: Value = serde_json::from_str(json)?;
+        match doc.get("schema_version").and_then(Value::as_u64) {
+            Some(v) if (1..=SCHEMA_VERSION as u64).contains(&v) => {
+                if let Some(header) = doc.get_mut("header") {
+                    if let Some(Value::Object(info)) = header.get_mut("information") {
+                        if let Some(total_discs) = info.remove("unknown") {
+                            info.insert("total_discs".to_owned(), total_discs);
+                        }
+                    }
+                    fill_missing(header, &serde_json::to_value(current)?);
+                }
+                serde_json::from_value(doc)
+            },
+            Some(v) => Err(serde::de::Error::custom(format!(
+                "unsupported schema version {} (the newest supported version is {})",
+                v, SCHEMA_VERSION,
+            ))),
+            None => Err(serde::de::Error::missing_field("schema_version")),
+        }
+    }
+
+    // The names of the read-only parts of the document that don't match
+    // `current`.
+    pub fn ignored_changes(&self, current: &Metadata) -> Vec<&'static str> {
+        let current = match serde_json::to_value(current) {
+            Ok(v) => v,
+            Err(_) => return Vec::new(),
+        };
+        [
+            ("image_size", &self.image_size),
+            ("apploader", &self.apploader),
+            ("dol", &self.dol),
+            ("fst", &self.fst),
+            ("layout", &self.layout),
+            ("gaps", &self.gaps),
+        ]
+            .into_iter()
+            .filter(|&(name, value)| current.get(name) != Some(value))
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+// Copies every key of `defaults` that `value` doesn't have into it, all the
+// way down.
+fn fill_missing(value: &mut Value, defaults: &Value) {
+    if let (Value::Object(value), Value::Object(defaults)) = (value, defaults) {
+        for (key, default) in defaults {
+            match value.get_mut(key) {
+                Some(v) => fill_missing(v, default),
+                None => {
+                    value.insert(key.clone(), default.clone());
+                },
+            }
+        }
+    }
+}
+
+// Every value that differs between `old` and `new`, by its dotted path
+// (like "header.information.country_code").
+pub fn diff_values(prefix: &str, old: &Value, new: &Value) -> Vec<(String, Value, Value)> {
+    match (old, new) {
+        (Value::Object(a), Value::Object(b)) => a.iter()
+            .flat_map(|(key, value)| {
+                let missing = Value::Null;
+                diff_values(&format!("{prefix}.{key}"), value, b.get(key).unwrap_or(&missing))
+            })
+            .collect(),
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => a.iter().zip(b)
+            .enumerate()
+            .flat_map(|(i, (x, y))| diff_values(&format!("{prefix}[{i}]"), x, y))
+            .collect(),
+        _ if old != new => vec![(prefix.to_owned(), old.clone(), new.clone())],
+        _ => Vec::new(),
+    }
+}
+
+fn directory_metadata(dir: &DirectoryEntry, entries: &[Entry]) -> EntryMetadata {
+    let contents = dir.iter_contents(entries)
+        .map(|e| match e {
+            Entry::File(f) => EntryMetadata::File {
+                index: f.info.index,
+                name: f.info.name.clone(),
+                path: f.info.normalized_path(),
+                offset: f.file_offset,
+                size: f.size,
+            },
+            Entry::Directory(d) => directory_metadata(d, entries),
+        })
+        .collect();
+
+    EntryMetadata::Directory {
+        index: dir.info.index,
+        // The root's name is whatever the first entry's is
+        name: if dir.info.index == 0 {
+            String::new()
+        } else {
+            dir.info.name.trim_end_matches(path::MAIN_SEPARATOR).to_owned()
+        },
+        path: dir.info.normalized_path(),
+        entries: contents,
+    }
+}
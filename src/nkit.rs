@@ -0,0 +1,92 @@
+// NKit shrinks images by dropping the junk data between files and moving
+// the files together, and keeps what it needs to put them back. It stamps
+// the images it makes with "NKIT" in the header's game name field, past
+// where any title goes. They read like any other image, but everything
+// gcmod extracts or rebuilds from one has NKit's layout, so it can't match
+// the original dump, like redump's.
+
+// In the header block, see `Header::raw`
+pub const NKIT_STAMP_OFFSET: usize = 0x200;
+pub const NKIT_MAGIC: &[u8; 4] = b"NKIT";
+
+// Where the version follows the magic, like "v01"
+const VERSION_SIZE: usize = 4;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NkitStamp {
+    // None when what follows the magic isn't printable
+    pub version: Option<String>,
+}
+
+impl NkitStamp {
+    // For `info`, and as the warning when extracting or rebuilding.
+    pub fn notice(&self) -> String {
+        let version = self.version.as_ref().map(|v| format!(" ({})", v)).unwrap_or_default();
+        format!(
+            "This is an NKit image{}. NKit moved its files and left out the junk data, so what's extracted or \
+            rebuilt from it has NKit's offsets and won't match the original dump. Convert it back with NKit first \
+            to get one that does.",
+            version,
+        )
+    }
+}
+
+// Looks for the stamp in `header`, the header block as it was read.
+pub fn find_stamp(header: &[u8]) -> Option<NkitStamp> {
+    let stamp = header.get(NKIT_STAMP_OFFSET..)?;
+    if !stamp.starts_with(NKIT_MAGIC) {
+        return None;
+    }
+    let version = stamp.get(NKIT_MAGIC.len()..NKIT_MAGIC.len() + VERSION_SIZE)
+        .and_then(|v| std::str::from_utf8(v).ok())
+        .map(|v| v.trim_matches(|c: char| c == '\0' || c == ' '))
+        .filter(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_graphic()))
+        .map(str::to_owned);
+    Some(NkitStamp { version })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+
+    fn stamped(stamp: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; 0x440];
+        header[NKIT_STAMP_OFFSET..NKIT_STAMP_OFFSET + stamp.len()].copy_from_slice(stamp);
+        header
+    }
+
+    #[test]
+    fn finding_the_stamp() {
+        assert_eq!(find_stamp(&stamped(b"NKITv01\0")), Some(NkitStamp { version: Some("v01".to_owned()) }));
+        assert_eq!(find_stamp(&stamped(b"NKIT v2 ")), Some(NkitStamp { version: Some("v2".to_owned()) }));
+        // Still NKit's, just without a version to show
+        for stamp in [&b"NKIT\0\0\0\0"[..], b"NKIT\xff\x01v1", b"NKITv 1"] {
+            assert_eq!(find_stamp(&stamped(stamp)), Some(NkitStamp { version: None }), "{:?}", stamp);
+        }
+        assert_eq!(find_stamp(&stamped(b"NKITv01")[..NKIT_STAMP_OFFSET + 6]), Some(NkitStamp { version: None }));
+    }
+
+    #[test]
+    fn headers_without_it() {
+        assert_eq!(find_stamp(&stamped(b"")), None);
+        assert_eq!(find_stamp(&stamped(b"nkit")), None);
+        assert_eq!(find_stamp(&stamped(b"NKI")), None);
+        // Somewhere else in the header
+        let mut header = stamped(b"");
+        header[0x20..0x24].copy_from_slice(NKIT_MAGIC);
+        assert_eq!(find_stamp(&header), None);
+        assert_eq!(find_stamp(&header[..NKIT_STAMP_OFFSET + 2]), None);
+        assert_eq!(find_stamp(&[]), None);
+    }
+
+    #[test]
+    fn games_record_it() {
+        let mut image = build_image(synthetic_tree().path());
+        assert_eq!(open_image(&image).nkit, None);
+        image[NKIT_STAMP_OFFSET..NKIT_STAMP_OFFSET + 7].copy_from_slice(b"NKITv01");
+        let game = open_image(&image);
+        assert_eq!(game.nkit, Some(NkitStamp { version: Some("v01".to_owned()) }));
+        assert!(game.nkit.unwrap().notice().starts_with("This is an NKit image (v01)."));
+    }
+}
@@ -0,0 +1,182 @@
+// Where the system data is in an extracted ROM. It's usually &&systemdata
+// in the root, which no real file system has, but nothing stops a ROM from
+// having its own file or directory with that name. Then the system data goes
+// in `ALTERNATE_SYSTEM_DATA_DIR` instead, so the two don't end up mixed
+// together, and the manifest records that it did.
+
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use crate::manifest::Manifest;
+use crate::{
+    manifest::MANIFEST_NAME,
+    sections::fst::{entry::Entry, FST},
+};
+
+pub const SYSTEM_DATA_DIR: &str = "&&systemdata";
+pub const ALTERNATE_SYSTEM_DATA_DIR: &str = "&&systemdata.gcmod";
+
+pub const HEADER_NAME: &str = "ISO.hdr";
+pub const APPLOADER_NAME: &str = "Apploader.ldr";
+pub const FST_NAME: &str = "Game.toc";
+pub const DOL_NAME: &str = "Start.dol";
+
+// In `SYSTEM_DATA_DIR`. Use `SystemPaths::for_tree` for trees that might
+// have been extracted from a ROM with its own.
+pub const APPLOADER_PATH: &str = "&&systemdata/Apploader.ldr";
+pub const DOL_PATH: &str = "&&systemdata/Start.dol";
+pub const FST_PATH: &str = "&&systemdata/Game.toc";
+pub const HEADER_PATH: &str = "&&systemdata/ISO.hdr";
+
+// The paths are relative to the root of the tree, with forward slashes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SystemPaths {
+    dir: String,
+}
+
+impl Default for SystemPaths {
+    fn default() -> SystemPaths {
+        SystemPaths::new(SYSTEM_DATA_DIR)
+    }
+}
+
+impl SystemPaths {
+    pub fn new(dir: impl Into<String>) -> SystemPaths {
+        SystemPaths { dir: dir.into() }
+    }
+
+    // The directory the manifest in `root` says, or `SYSTEM_DATA_DIR` if
+    // there's no manifest or it can't be read, which it never can be without
+    // the serde feature.
+    pub fn for_tree(root: impl AsRef<Path>) -> SystemPaths {
+        let path = root.as_ref().join(MANIFEST_NAME);
+        if !path.is_file() {
+            return SystemPaths::default();
+        }
+        #[cfg(feature = "serde")]
+        return Manifest::read(path).map(|m| m.system_paths()).unwrap_or_default();
+        #[cfg(not(feature = "serde"))]
+        SystemPaths::default()
+    }
+
+    // Where to extract the system data of a ROM with `fst`, or `None` if
+    // its root has both directories. Names are compared ignoring case, since
+    // they'd be the same on a case-insensitive file system.
+    pub fn for_fst(fst: &FST) -> Option<SystemPaths> {
+        let root = fst.entries().first().and_then(Entry::as_dir);
+        let taken = |dir: &str| root.is_some_and(|root| {
+            // Directories' names end in a slash
+            root.iter_contents(fst.entries()).any(|e| e.info().name.trim_end_matches('/').eq_ignore_ascii_case(dir))
+        });
+        [SYSTEM_DATA_DIR, ALTERNATE_SYSTEM_DATA_DIR].into_iter()
+            .find(|dir| !taken(dir))
+            .map(SystemPaths::new)
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    pub fn is_alternate(&self) -> bool {
+        self.dir != SYSTEM_DATA_DIR
+    }
+
+    pub fn header(&self) -> String {
+        self.join(HEADER_NAME)
+    }
+
+    pub fn apploader(&self) -> String {
+        self.join(APPLOADER_NAME)
+    }
+
+    pub fn fst(&self) -> String {
+        self.join(FST_NAME)
+    }
+
+    pub fn dol(&self) -> String {
+        self.join(DOL_NAME)
+    }
+
+    // Whether `path`, in the tree at `root`, is one of the system files.
+    pub fn is_system_file(&self, root: &Path, path: &Path) -> bool {
+        path.parent() == Some(&root.join(&self.dir))
+            && path.file_name().is_some_and(|name| {
+                [HEADER_NAME, APPLOADER_NAME, FST_NAME, DOL_NAME].iter().any(|n| name == *n)
+            })
+    }
+
+    fn join(&self, name: &str) -> String {
+        format!("{}/{}", self.dir, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        report::Reporter,
+        synthetic,
+        test_util::{open_image, synthetic_tree},
+        RebuildOptions,
+        ROMRebuilder,
+    };
+    #[cfg(feature = "serde")]
+    use crate::test_util::TestDir;
+
+    // The FST of the synthetic game with `dirs` in its root, which can
+    // include &&systemdata, with the system data in `system` instead
+    fn fst_with(dirs: &[&str], system: &str) -> FST {
+        let tree = synthetic_tree();
+        fs::rename(tree.join(SYSTEM_DATA_DIR), tree.join(system)).unwrap();
+        for dir in dirs {
+            fs::create_dir(tree.join(dir)).unwrap();
+            fs::write(tree.join(dir).join("file.bin"), b"file").unwrap();
+        }
+        let options = RebuildOptions {
+            capacity: synthetic::CAPACITY,
+            system_paths: SystemPaths::new(system),
+            ..RebuildOptions::default()
+        };
+        let mut image = Vec::new();
+        ROMRebuilder::rebuild(tree.path(), &options, &mut image, &Reporter::quiet()).unwrap();
+        open_image(&image).fst
+    }
+
+    #[test]
+    fn where_the_system_data_goes() {
+        let dir = |fst: &FST| SystemPaths::for_fst(fst).map(|s| s.dir().to_owned());
+        assert_eq!(dir(&fst_with(&[], SYSTEM_DATA_DIR)).as_deref(), Some(SYSTEM_DATA_DIR));
+        let alternate = Some(ALTERNATE_SYSTEM_DATA_DIR);
+        assert_eq!(dir(&fst_with(&["&&systemdata"], "sys")).as_deref(), alternate);
+        // Which would be the same directory on some file systems
+        assert_eq!(dir(&fst_with(&["&&SystemData"], "sys")).as_deref(), alternate);
+        // Only in the root
+        assert_eq!(dir(&fst_with(&["data/&&systemdata"], "sys")).as_deref(), Some(SYSTEM_DATA_DIR));
+        assert_eq!(dir(&fst_with(&["&&systemdata", "&&systemdata.gcmod"], "sys")), None);
+    }
+
+    #[test]
+    fn system_files() {
+        let root = Path::new("/tree");
+        let alternate = SystemPaths::new(ALTERNATE_SYSTEM_DATA_DIR);
+        assert_eq!(alternate.header(), "&&systemdata.gcmod/ISO.hdr");
+        assert!(alternate.is_system_file(root, Path::new("/tree/&&systemdata.gcmod/Start.dol")));
+        assert!(!alternate.is_system_file(root, Path::new("/tree/&&systemdata/Start.dol")));
+        assert!(!alternate.is_system_file(root, Path::new("/tree/&&systemdata.gcmod/readme.txt")));
+        assert!(!SystemPaths::default().is_system_file(root, Path::new("/tree/data/&&systemdata/ISO.hdr")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn the_manifest_says_where_it_is() {
+        let tree = TestDir::new();
+        assert_eq!(SystemPaths::for_tree(tree.path()), SystemPaths::default());
+        fs::write(tree.join(MANIFEST_NAME), r#"{"gcmod_version": "0", "game_id": "GSLF01", "entries": [], "system_dir": "other"}"#).unwrap();
+        assert_eq!(SystemPaths::for_tree(tree.path()).dir(), "other");
+        // Unreadable manifests don't
+        fs::write(tree.join(MANIFEST_NAME), "{").unwrap();
+        assert_eq!(SystemPaths::for_tree(tree.path()), SystemPaths::default());
+    }
+}
@@ -0,0 +1,168 @@
+// For `--profile`, which times the slow parts of a command so a report like
+// "rebuilding is slow on my NAS" can come with numbers. Timers go to a
+// process-wide sink, like `report`'s events, since the work is spread all
+// over the library. Without a sink, a timer doesn't even read the clock.
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+static SINK: OnceLock<&'static dyn ProfileSink> = OnceLock::new();
+
+// Where timings go. Library users can bring their own with `set_sink`.
+pub trait ProfileSink: Send + Sync {
+    fn record(&self, operation: &'static str, elapsed: Duration, bytes: u64);
+}
+
+// Sends every timing from now on to `sink`. There's only one for the whole
+// process, so this is false if there already was one.
+pub fn set_sink(sink: &'static dyn ProfileSink) -> bool {
+    SINK.set(sink).is_ok()
+}
+
+// Turns profiling on with a `Collector`, returning it for its table at the
+// end. `None` if there was already a sink.
+pub fn collect() -> Option<&'static Collector> {
+    let collector: &'static Collector = Box::leak(Box::default());
+    set_sink(collector).then_some(collector)
+}
+
+pub fn enabled() -> bool {
+    SINK.get().is_some()
+}
+
+// Times `operation` from now until it's dropped.
+pub fn start(operation: &'static str) -> Timer {
+    Timer {
+        started: enabled().then(Instant::now),
+        operation,
+        bytes: 0,
+    }
+}
+
+pub struct Timer {
+    started: Option<Instant>,
+    operation: &'static str,
+    bytes: u64,
+}
+
+impl Timer {
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let (Some(started), Some(sink)) = (self.started, SINK.get()) {
+            sink.record(self.operation, started.elapsed(), self.bytes);
+        }
+    }
+}
+
+// Copies are grouped by how big the file is, since lots of small files and
+// a few big ones are slow for different reasons.
+pub fn copy_operation(size: u64) -> &'static str {
+    match size {
+        0..0x10000 => "copy <64KiB",
+        0x10000..0x100000 => "copy 64KiB-1MiB",
+        0x100000..0x1000000 => "copy 1MiB-16MiB",
+        _ => "copy >=16MiB",
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Totals {
+    pub calls: u64,
+    pub elapsed: Duration,
+    pub bytes: u64,
+}
+
+// Adds up the timings for each operation.
+#[derive(Debug, Default)]
+pub struct Collector {
+    totals: Mutex<BTreeMap<&'static str, Totals>>,
+}
+
+impl ProfileSink for Collector {
+    fn record(&self, operation: &'static str, elapsed: Duration, bytes: u64) {
+        let mut totals = self.totals.lock().unwrap();
+        let t = totals.entry(operation).or_default();
+        t.calls += 1;
+        t.elapsed += elapsed;
+        t.bytes += bytes;
+    }
+}
+
+impl Collector {
+    pub fn totals(&self) -> BTreeMap<&'static str, Totals> {
+        self.totals.lock().unwrap().clone()
+    }
+
+    // The totals as a table, slowest first.
+    pub fn table(&self) -> String {
+        let mut rows: Vec<_> = self.totals().into_iter().collect();
+        rows.sort_by_key(|(_, t)| Reverse(t.elapsed));
+        let width = rows.iter().map(|(op, _)| op.len()).chain(["Operation".len()]).max().unwrap();
+
+        let mut s = format!("{:<width$}  {:>8}  {:>12}  {:>14}  {:>9}\n", "Operation", "Calls", "Time (ms)", "Bytes", "MB/s");
+        for (op, t) in rows {
+            let secs = t.elapsed.as_secs_f64();
+            let rate = if t.bytes == 0 || secs == 0.0 {
+                "-".to_owned()
+            } else {
+                format!("{:.1}", t.bytes as f64 / secs / 1e6)
+            };
+            // Writing to a String can't fail
+            let _ = writeln!(
+                s,
+                "{:<width$}  {:>8}  {:>12.3}  {:>14}  {:>9}",
+                op, t.calls, secs * 1000.0, t.bytes, rate,
+            );
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_by_size() {
+        assert_eq!(copy_operation(0), "copy <64KiB");
+        assert_eq!(copy_operation(0xffff), "copy <64KiB");
+        assert_eq!(copy_operation(0x10000), "copy 64KiB-1MiB");
+        assert_eq!(copy_operation(0x100000), "copy 1MiB-16MiB");
+        assert_eq!(copy_operation(0x1000000), "copy >=16MiB");
+    }
+
+    #[test]
+    fn collected_totals() {
+        let collector = Collector::default();
+        collector.record("fst_parse", Duration::from_millis(2), 100);
+        collector.record("zero_fill", Duration::from_millis(500), 1_000_000);
+        collector.record("fst_parse", Duration::from_millis(3), 50);
+
+        let totals = collector.totals();
+        let parse = totals["fst_parse"];
+        assert_eq!((parse.calls, parse.elapsed, parse.bytes), (2, Duration::from_millis(5), 150));
+        assert_eq!(
+            collector.table(),
+            "Operation     Calls     Time (ms)           Bytes       MB/s\n\
+            zero_fill         1       500.000         1000000        2.0\n\
+            fst_parse         2         5.000             150        0.0\n",
+        );
+    }
+
+    // Nothing in the unit tests turns profiling on
+    #[test]
+    fn timers_without_a_sink_dont_read_the_clock() {
+        assert!(!enabled());
+        assert!(start("fst_parse").started.is_none());
+    }
+}
@@ -0,0 +1,114 @@
+// The one-line summary of where a ROM came from at the top of `info`, for
+// cataloging dumps. Every part of it can be missing or garbage on homebrew
+// and damaged images, so each one is optional and shows up as "unknown".
+
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{
+    sections::header::{maker_name, region_name},
+    Game,
+};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Provenance {
+    // YYYY-MM-DD, from the apploader, see `Apploader::build_date`
+    pub build_date: Option<String>,
+    pub region: Option<&'static str>,
+    // From 1
+    pub disc: u32,
+    // bi2's total, leaving out 0, which older images have
+    pub total_discs: Option<u32>,
+    pub maker_code: String,
+    pub publisher: Option<&'static str>,
+}
+
+impl Game {
+    pub fn provenance(&self) -> Provenance {
+        let header = &self.header;
+        let region = region_name(header.information.country_code);
+        Provenance {
+            build_date: self.apploader.build_date(),
+            region: (region != "Unknown").then_some(region),
+            disc: header.disk_id as u32 + 1,
+            total_discs: Some(header.information.total_discs).filter(|&t| t != 0),
+            maker_code: header.maker_code.clone(),
+            publisher: maker_name(&header.maker_code),
+        }
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.build_date {
+            Some(date) => write!(f, "Built: {} (apploader SDK date)", date)?,
+            None => write!(f, "Built: unknown")?,
+        }
+        write!(f, " · Region: {}", self.region.unwrap_or("unknown"))?;
+        match self.total_discs {
+            Some(total) => write!(f, " · Disc {} of {}", self.disc, total)?,
+            None => write!(f, " · Disc {} of unknown", self.disc)?,
+        }
+        let code = self.maker_code.trim_matches(char::from(0));
+        match (self.publisher, code.is_empty()) {
+            (Some(name), _) => write!(f, " · Publisher: {} ({})", name, code),
+            (None, false) => write!(f, " · Publisher: unknown ({})", code),
+            (None, true) => write!(f, " · Publisher: unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util::{build_image, open_image, synthetic_tree}, Game};
+
+    fn synthetic() -> Game {
+        open_image(&build_image(synthetic_tree().path()))
+    }
+
+    #[test]
+    fn everything_known() {
+        let mut game = synthetic();
+        game.apploader.date = "2002/07/19".to_owned();
+        game.header.information.country_code = 1;
+        game.header.information.total_discs = 2;
+        game.header.disk_id = 1;
+        assert_eq!(
+            game.provenance().to_string(),
+            "Built: 2002-07-19 (apploader SDK date) · Region: NTSC-U · Disc 2 of 2 · Publisher: Nintendo (01)",
+        );
+    }
+
+    // The synthetic image has a placeholder apploader and no disc count
+    #[test]
+    fn unknown_parts() {
+        let mut game = synthetic();
+        let provenance = game.provenance();
+        assert_eq!((&provenance.build_date, provenance.total_discs), (&None, None));
+        assert_eq!(
+            provenance.to_string(),
+            "Built: unknown · Region: PAL · Disc 1 of unknown · Publisher: Nintendo (01)",
+        );
+
+        game.header.information.country_code = 0x1234;
+        game.header.maker_code = "ZZ".to_owned();
+        assert_eq!(
+            game.provenance().to_string(),
+            "Built: unknown · Region: unknown · Disc 1 of unknown · Publisher: unknown (ZZ)",
+        );
+        game.header.maker_code = "\0\0".to_owned();
+        assert!(game.provenance().to_string().ends_with(" · Publisher: unknown"));
+    }
+
+    #[test]
+    fn dates_that_arent_real() {
+        let mut game = synthetic();
+        for date in ["0000/00/00", "2002/13/01", "2002/07/32", "2002-07-19", "02/07/19", "2002/07/19/01", ""] {
+            game.apploader.date = date.to_owned();
+            assert_eq!(game.provenance().build_date, None, "{:?}", date);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// A fingerprint for telling whether two images are the same dump without
+// reading all of either. It's a SHA-1 of a sample of the image: the header,
+// the FST, the DOL, the first and last MiB of the file data, and the image's
+// length. Where those are comes from the image's own layout, so the sample
+// covers the same parts of every image, wherever they are.
+//
+// Two images that only differ outside of the sample, like in a file in the
+// middle of the file data, get the same quick-id. So it's only for
+// identifying images, not for checking that one is intact, which takes a
+// hash of the whole thing.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    hash::{to_hex, Sha1},
+    rom_handle::RomReadHandle,
+    sections::{apploader::APPLOADER_OFFSET, dol::DOLHeader, fst::{entry::Entry, FST}, header::Header},
+    Game,
+};
+
+// Bumped whenever what's sampled changes, so quick-ids from different
+// versions of the sample can't be mistaken for each other.
+pub const QUICK_ID_PREFIX: &str = "q1-";
+
+// How much of each end of the file data is sampled
+pub const FILE_DATA_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SampledRegion {
+    pub name: &'static str,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SamplePlan {
+    pub image_len: u64,
+    // In the order they're hashed. Regions can overlap, like a DOL that's
+    // after the start of the file data.
+    pub regions: Vec<SampledRegion>,
+}
+
+impl SamplePlan {
+    // Works out the plan from the image's header, DOL, and FST. Malformed
+    // FSTs the quick way to read them can't handle are read the slow way,
+    // which repairs them.
+    pub fn for_image<R>(mut iso: R) -> io::Result<SamplePlan>
+    where
+        R: BufRead + Seek,
+    {
+        let image_len = iso.seek(SeekFrom::End(0))?;
+        let header = Header::new(&mut iso, 0)?;
+        let dol = DOLHeader::new(&mut iso, header.dol_offset)?;
+        let files = match FST::summary(&mut iso, header.fst_offset, header.fst_size) {
+            Ok(fst) => (fst.min_offset != 0).then_some((fst.min_offset, fst.max_offset)),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                iso.seek(SeekFrom::Start(0))?;
+                file_data(&Game::open(&mut iso, 0)?)
+            },
+            Err(e) => return Err(e),
+        };
+        Ok(SamplePlan::new(
+            image_len,
+            (header.fst_offset, header.fst_size as u64),
+            (dol.offset, dol.dol_size as u64),
+            files,
+        ))
+    }
+
+    // `fst` and `dol` are each an offset and a size, and `files` is where
+    // the file data starts and ends. Anything past `image_len` is left out.
+    pub fn new(image_len: u64, fst: (u64, u64), dol: (u64, u64), files: Option<(u64, u64)>) -> SamplePlan {
+        let mut regions = vec![
+            SampledRegion { name: "header", offset: 0, size: APPLOADER_OFFSET },
+            SampledRegion { name: "fst", offset: fst.0, size: fst.1 },
+            SampledRegion { name: "dol", offset: dol.0, size: dol.1 },
+        ];
+        if let Some((start, end)) = files.filter(|(start, end)| end > start) {
+            let first = (end - start).min(FILE_DATA_SAMPLE_SIZE);
+            regions.push(SampledRegion { name: "files start", offset: start, size: first });
+            // Small file data is all in the first sample
+            let last_start = end.saturating_sub(FILE_DATA_SAMPLE_SIZE).max(start + first);
+            if last_start < end {
+                regions.push(SampledRegion { name: "files end", offset: last_start, size: end - last_start });
+            }
+        }
+        for r in &mut regions {
+            r.size = r.size.min(image_len.saturating_sub(r.offset));
+        }
+        regions.retain(|r| r.size != 0);
+        SamplePlan { image_len, regions }
+    }
+
+    pub fn sampled_bytes(&self) -> u64 {
+        self.regions.iter().map(|r| r.size).sum()
+    }
+
+    // Hashes the regions of `iso`, the image the plan is for. The length
+    // and where each region is go in too, so images laid out differently
+    // don't match just because the bytes do.
+    pub fn quick_id(&self, mut iso: impl Read + Seek) -> io::Result<String> {
+        let mut sha1 = Sha1::new();
+        sha1.update(QUICK_ID_PREFIX.as_bytes());
+        sha1.update(&self.image_len.to_be_bytes());
+        let mut buf = vec![0; 64 * 1024];
+        for r in &self.regions {
+            sha1.update(&r.offset.to_be_bytes());
+            sha1.update(&r.size.to_be_bytes());
+            iso.seek(SeekFrom::Start(r.offset))?;
+            let mut left = r.size;
+            while left > 0 {
+                let n = left.min(buf.len() as u64) as usize;
+                iso.read_exact(&mut buf[..n])?;
+                sha1.update(&buf[..n]);
+                left -= n as u64;
+            }
+        }
+        Ok(format!("{}{}", QUICK_ID_PREFIX, to_hex(&sha1.finish())))
+    }
+}
+
+// The quick-id of the image at `path`, with the plan it was sampled by.
+pub fn quick_id(path: impl AsRef<Path>) -> io::Result<(String, SamplePlan)> {
+    let mut iso = BufReader::new(RomReadHandle::open(path)?);
+    let plan = SamplePlan::for_image(&mut iso)?;
+    Ok((plan.quick_id(&mut iso)?, plan))
+}
+
+fn file_data(game: &Game) -> Option<(u64, u64)> {
+    let files = game.fst.entries().iter().filter_map(Entry::as_file);
+    let start = files.clone().filter(|f| f.size != 0).map(|f| f.file_offset).min()?;
+    let end = files.map(|f| f.file_offset + f.size as u64).max()?;
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn id(image: &[u8]) -> String {
+        let plan = SamplePlan::for_image(Cursor::new(image)).unwrap();
+        plan.quick_id(Cursor::new(image)).unwrap()
+    }
+
+    fn file_offset(image: &[u8], path: &str) -> usize {
+        open_image(image).fst.entry_for_path(path).unwrap().as_file().unwrap().file_offset as usize
+    }
+
+    // What's sampled comes from where each image's sections are
+    #[test]
+    fn plans_follow_the_layout() {
+        let image = build_image(synthetic_tree().path());
+        let plan = SamplePlan::for_image(Cursor::new(&image)).unwrap();
+        let game = open_image(&image);
+        let big = game.fst.entry_for_path("/data/big.bin").unwrap().as_file().unwrap();
+        let end = big.file_offset + big.size as u64;
+        let regions: Vec<(&str, u64, u64)> = plan.regions.iter().map(|r| (r.name, r.offset, r.size)).collect();
+        assert_eq!(regions, [
+            ("header", 0, APPLOADER_OFFSET),
+            ("fst", game.header.fst_offset, game.header.fst_size as u64),
+            ("dol", game.dol.offset, game.dol.dol_size as u64),
+            ("files start", 0x8000, MIB),
+            ("files end", 0x8000 + MIB, end - 0x8000 - MIB),
+        ]);
+        assert_eq!(plan.image_len, image.len() as u64);
+    }
+
+    #[test]
+    fn plans_for_small_and_cut_off_images() {
+        let plan = SamplePlan::new(0x100000, (0x2800, 0x100), (0x3000, 0x200), Some((0x8000, 0x9000)));
+        let regions: Vec<(&str, u64, u64)> = plan.regions.iter().map(|r| (r.name, r.offset, r.size)).collect();
+        assert_eq!(regions, [("header", 0, 0x2440), ("fst", 0x2800, 0x100), ("dol", 0x3000, 0x200), ("files start", 0x8000, 0x1000)]);
+
+        // Cut off inside the DOL, so the rest is left out
+        let plan = SamplePlan::new(0x3100, (0x2800, 0x100), (0x3000, 0x200), Some((0x8000, 0x400000)));
+        let regions: Vec<(&str, u64, u64)> = plan.regions.iter().map(|r| (r.name, r.offset, r.size)).collect();
+        assert_eq!(regions, [("header", 0, 0x2440), ("fst", 0x2800, 0x100), ("dol", 0x3000, 0x100)]);
+        assert_eq!(plan.sampled_bytes(), 0x2640);
+
+        // No file data at all
+        assert_eq!(SamplePlan::new(0x100000, (0x2800, 0x100), (0x3000, 0x200), Some((0x8000, 0x8000))).regions.len(), 3);
+        assert_eq!(SamplePlan::new(0x100000, (0x2800, 0x100), (0x3000, 0x200), None).regions.len(), 3);
+    }
+
+    #[test]
+    fn changes_in_the_sample_change_the_id() {
+        let image = build_image(synthetic_tree().path());
+        let original = id(&image);
+        assert!(original.starts_with(QUICK_ID_PREFIX));
+        assert_eq!(id(&image), original);
+
+        let readme = file_offset(&image, "/readme.txt");
+        let big = file_offset(&image, "/data/big.bin");
+        let big_end = big + fs::read(synthetic_tree().join("data/big.bin")).unwrap().len();
+        // The title, a name in the FST, and the DOL's data, then both ends
+        // of the file data
+        for offset in [0x21, 0x2800 + 0xa0, 0x3000 + 0x110, readme + 10, big_end - 1] {
+            let mut changed = image.clone();
+            changed[offset] ^= 1;
+            assert_ne!(id(&changed), original, "{:#x}", offset);
+        }
+        let mut longer = image.clone();
+        longer.extend([0; 0x8000]);
+        assert_ne!(id(&longer), original);
+    }
+
+    // The documented limitation: a file in the middle of the file data
+    // isn't sampled, so images that only differ there have the same id
+    #[test]
+    fn changes_outside_of_the_sample_dont() {
+        let tree = synthetic_tree();
+        fs::write(tree.join("data/big.bin"), vec![0x5a; 3 * MIB as usize]).unwrap();
+        let image = build_image(tree.path());
+        let middle = file_offset(&image, "/data/big.bin") + 3 * MIB as usize / 2;
+        let plan = SamplePlan::for_image(Cursor::new(&image)).unwrap();
+        assert!(plan.regions.iter().all(|r| !(r.offset..r.offset + r.size).contains(&(middle as u64))));
+
+        let mut changed = image.clone();
+        changed[middle] ^= 0xff;
+        assert_eq!(id(&changed), id(&image));
+    }
+}
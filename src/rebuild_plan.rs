@@ -0,0 +1,350 @@
+// For `rebuild --plan-out` and `--plan-in`, which split a rebuild in two:
+// working out where everything goes, which only needs the tree's metadata,
+// and copying the bytes, which can happen later or on another machine with
+// its own copy of the tree.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Cursor, Write},
+    path::{Component, Path},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build_info,
+    hash::{from_hex, to_hex, Sha1Writer},
+    paths::*,
+    report::Reporter,
+    sections::header::Header,
+    rom_rebuilder::WritePlan,
+    RebuildOptions,
+    ROMRebuilder,
+};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RebuildPlan {
+    pub gcmod_version: String,
+    pub capacity: u64,
+    // Where the last file ends, if the FST was rebuilt
+    pub space_used: Option<usize>,
+    // The header and FST the plan made, in hex. They're written over the
+    // ones in the tree before building, since everything else depends on
+    // them.
+    pub header: String,
+    pub fst: String,
+    // Everything on the ROM, including the system data, sorted by offset
+    pub files: Vec<PlannedFile>,
+    // Files left empty, which aren't in `files`, see
+    // `RebuildOptions::prune_missing`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub pruned: Vec<String>,
+    // The directories with a different name on the ROM, by where they are
+    // in the tree, like `PlannedFile::rom_name`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub renamed_dirs: BTreeMap<String, String>,
+    // Where the system data is in the tree, if it isn't &&systemdata, see
+    // `RebuildOptions::system_paths`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub system_dir: Option<String>,
+    // `ROMRebuilder::warnings`, from `compute`. They aren't written to the
+    // plan.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PlannedFile {
+    // Relative to the root, with forward slashes
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+    // Only with `rebuild --plan-hashes`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub sha1: Option<String>,
+    // The file's name on the ROM, if it isn't the one in `path`, see
+    // `RebuildOptions::renames`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub rom_name: Option<String>,
+}
+
+// How a tree differs from the plan for it, from `RebuildPlan::prepare`.
+#[derive(Debug)]
+pub enum PlanMismatch {
+    Missing(String),
+    Resized { path: String, planned: u64, actual: u64 },
+    Changed(String),
+}
+
+impl fmt::Display for PlanMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanMismatch::Missing(p) => write!(f, "{} is missing", p),
+            PlanMismatch::Resized { path, planned, actual } =>
+                write!(f, "{} is {} bytes, but was planned as {}", path, actual, planned),
+            PlanMismatch::Changed(p) => write!(f, "{} has changed since it was planned", p),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PlanMismatches(pub Vec<PlanMismatch>);
+
+impl fmt::Display for PlanMismatches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The tree doesn't match the plan:")?;
+        for m in &self.0 {
+            write!(f, "\n    {}", m)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for PlanMismatches {}
+
+impl RebuildPlan {
+    // Like `ROMRebuilder::plan`, which updates the system data in `root` the
+    // same way.
+    pub fn compute(root: impl AsRef<Path>, options: &RebuildOptions) -> io::Result<RebuildPlan> {
+        let root = root.as_ref();
+        let rebuilder = ROMRebuilder::plan(root, options)?;
+        let files = rebuilder.files().iter()
+            .map(|(offset, filename)| Ok(PlannedFile {
+                path: relative_path(root, filename),
+                offset: *offset,
+                size: filename.metadata()?.len(),
+                sha1: None,
+                rom_name: rebuilder.rom_name(filename).map(str::to_owned),
+            }))
+            .collect::<io::Result<_>>()?;
+        let file_paths: HashSet<&Path> = rebuilder.files().iter().map(|(_, f)| f.as_path()).collect();
+        let renamed_dirs = rebuilder.renames().iter()
+            .filter(|(path, _)| !file_paths.contains(path.as_path()))
+            .map(|(path, name)| (relative_path(root, path), name.clone()))
+            .collect();
+        Ok(RebuildPlan {
+            gcmod_version: build_info::VERSION.to_owned(),
+            capacity: rebuilder.capacity(),
+            space_used: rebuilder.space_used(),
+            header: to_hex(&std::fs::read(root.join(options.system_paths.header()))?),
+            fst: to_hex(&std::fs::read(root.join(options.system_paths.fst()))?),
+            files,
+            pruned: rebuilder.pruned().to_vec(),
+            renamed_dirs,
+            system_dir: options.system_paths.is_alternate().then(|| options.system_paths.dir().to_owned()),
+            warnings: rebuilder.warnings().to_vec(),
+        })
+    }
+
+    // Records the SHA-1 of every file but the header and FST, so `prepare`
+    // notices files that changed without changing size. This reads the
+    // whole tree.
+    pub fn add_hashes(&mut self, root: impl AsRef<Path>, reporter: &Reporter) -> io::Result<()> {
+        let total = self.files.len();
+        let system = self.system_paths();
+        for (i, file) in self.files.iter_mut().enumerate() {
+            if !is_from_plan(&system, &file.path) {
+                file.sha1 = Some(to_hex(&hash_file(root.as_ref().join(&file.path))?));
+            }
+            reporter.progress((i + 1) as u64, total as u64, format!("{}/{} files hashed.", i + 1, total));
+        }
+        reporter.finish_progress();
+        Ok(())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<RebuildPlan> {
+        let file = BufReader::new(File::open(path)?);
+        serde_json::from_reader(file).map_err(io::Error::from)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        writeln!(file)?;
+        file.flush()
+    }
+
+    // Checks that the files in `root` are the ones that were planned, then
+    // writes the plan's header and FST into it. Nothing is written if any
+    // file doesn't match, and the error is a `PlanMismatches`.
+    pub fn prepare(&self, root: impl AsRef<Path>, jobs: usize) -> io::Result<ROMRebuilder> {
+        let root = root.as_ref();
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let header = from_hex(&self.header).ok_or_else(|| invalid("The plan's header isn't valid hex".to_owned()))?;
+        let fst = from_hex(&self.fst).ok_or_else(|| invalid("The plan's FST isn't valid hex".to_owned()))?;
+
+        let system = self.system_paths();
+        // The plan might not come from this machine, so it can't point
+        // anywhere outside of the root
+        let in_root = |path: &str| {
+            let relative = Path::new(path);
+            if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(invalid(format!("The plan has an invalid path {:?}", path)));
+            }
+            Ok(root.join(relative))
+        };
+        let mut files = Vec::with_capacity(self.files.len());
+        let mut renames = HashMap::new();
+        for (path, name) in &self.renamed_dirs {
+            renames.insert(in_root(path)?, name.clone());
+        }
+        let mut mismatches = Vec::new();
+        for file in &self.files {
+            let filename = in_root(&file.path)?;
+            if !is_from_plan(&system, &file.path) {
+                match filename.metadata() {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound =>
+                        mismatches.push(PlanMismatch::Missing(file.path.clone())),
+                    Err(e) => return Err(e),
+                    Ok(m) if m.len() != file.size => mismatches.push(PlanMismatch::Resized {
+                        path: file.path.clone(),
+                        planned: file.size,
+                        actual: m.len(),
+                    }),
+                    Ok(_) => if let Some(expected) = &file.sha1 {
+                        if !to_hex(&hash_file(&filename)?).eq_ignore_ascii_case(expected) {
+                            mismatches.push(PlanMismatch::Changed(file.path.clone()));
+                        }
+                    },
+                }
+            }
+            if let Some(name) = &file.rom_name {
+                renames.insert(filename.clone(), name.clone());
+            }
+            files.push((file.offset, filename));
+        }
+        if !mismatches.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, PlanMismatches(mismatches)));
+        }
+
+        std::fs::write(root.join(system.header()), &header)?;
+        std::fs::write(root.join(system.fst()), &fst)?;
+        Ok(ROMRebuilder::from_parts(
+            WritePlan::new(files, root).validate()?,
+            self.space_used,
+            self.capacity,
+            jobs,
+            Header::new(Cursor::new(&header), 0)?,
+            root.to_owned(),
+            self.pruned.clone(),
+        ).with_renames(renames).with_system_paths(system))
+    }
+
+    pub fn system_paths(&self) -> SystemPaths {
+        self.system_dir.as_deref().map(SystemPaths::new).unwrap_or_default()
+    }
+
+    // Builds the ROM planned for `root` into `output`.
+    pub fn execute(
+        &self,
+        root: impl AsRef<Path>,
+        jobs: usize,
+        output: impl Write,
+        reporter: &Reporter,
+    ) -> io::Result<()> {
+        self.prepare(root, jobs)?.write(output, reporter)
+    }
+}
+
+// The header and FST are in the plan itself, so what's in the tree doesn't
+// matter.
+fn is_from_plan(system: &SystemPaths, path: &str) -> bool {
+    path == system.header() || path == system.fst()
+}
+
+fn relative_path(root: &Path, filename: &Path) -> String {
+    let relative = filename.strip_prefix(root).unwrap_or(filename);
+    relative.iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn hash_file(path: impl AsRef<Path>) -> io::Result<[u8; 20]> {
+    let mut hasher = Sha1Writer::new(io::sink());
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finish().1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{synthetic, test_util::{build_image, synthetic_tree}};
+
+    fn options() -> RebuildOptions {
+        RebuildOptions { capacity: synthetic::CAPACITY, ..RebuildOptions::default() }
+    }
+
+    // Written out and read back, like `--plan-out` then `--plan-in`
+    fn round_trip(plan: &RebuildPlan) -> RebuildPlan {
+        serde_json::from_str(&serde_json::to_string(plan).unwrap()).unwrap()
+    }
+
+    fn mismatches(e: io::Error) -> Vec<String> {
+        let inner = e.into_inner().unwrap();
+        inner.downcast_ref::<PlanMismatches>().unwrap().0.iter().map(PlanMismatch::to_string).collect()
+    }
+
+    #[test]
+    fn executing_a_plan_is_a_rebuild() {
+        let tree = synthetic_tree();
+        let plan = round_trip(&RebuildPlan::compute(tree.path(), &options()).unwrap());
+        assert_eq!(plan.capacity, synthetic::CAPACITY);
+        assert!(plan.files.windows(2).all(|pair| pair[0].offset <= pair[1].offset));
+        assert!(plan.files.iter().any(|f| f.path == "data/levels/1.bin" && f.offset == 0x18000 && f.size == 1000));
+
+        let mut image = Vec::new();
+        plan.execute(tree.path(), 1, &mut image, &Reporter::quiet()).unwrap();
+        assert!(image == build_image(synthetic_tree().path()));
+    }
+
+    #[test]
+    fn trees_that_changed_since_the_plan() {
+        let tree = synthetic_tree();
+        let mut plan = RebuildPlan::compute(tree.path(), &options()).unwrap();
+        plan.add_hashes(tree.path(), &Reporter::quiet()).unwrap();
+        let plan = round_trip(&plan);
+        assert!(plan.files.iter().filter(|f| !is_from_plan(&plan.system_paths(), &f.path)).all(|f| f.sha1.is_some()));
+
+        fs::remove_file(tree.join("empty.bin")).unwrap();
+        fs::write(tree.join("data/levels/1.bin"), [0; 10]).unwrap();
+        let mut readme = fs::read(tree.join("readme.txt")).unwrap();
+        readme[0] ^= 0xff;
+        fs::write(tree.join("readme.txt"), readme).unwrap();
+        // Not checked, since the plan has its own
+        fs::write(tree.join("&&systemdata/ISO.hdr"), [0; 4]).unwrap();
+
+        let mut image = Vec::new();
+        let e = plan.execute(tree.path(), 1, &mut image, &Reporter::quiet()).unwrap_err();
+        let mut found = mismatches(e);
+        found.sort();
+        assert_eq!(found, [
+            "data/levels/1.bin is 10 bytes, but was planned as 1000",
+            "empty.bin is missing",
+            "readme.txt has changed since it was planned",
+        ]);
+        assert!(image.is_empty());
+        // Nothing was written into the tree either
+        assert_eq!(fs::read(tree.join("&&systemdata/ISO.hdr")).unwrap(), [0; 4]);
+    }
+
+    #[test]
+    fn plans_cant_point_outside_of_the_root() {
+        let tree = synthetic_tree();
+        let mut plan = RebuildPlan::compute(tree.path(), &options()).unwrap();
+        plan.files[1].path = "../readme.txt".to_owned();
+        let e = plan.prepare(tree.path(), 1).err().unwrap();
+        assert!(e.to_string().contains("invalid path \"../readme.txt\""), "{}", e);
+        plan.files[1].path = "/etc/passwd".to_owned();
+        assert!(plan.prepare(tree.path(), 1).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+// `extract --refresh`, which brings a tree extracted from one revision of an
+// image up to date with another. The hashes in the tree's manifest say which
+// files changed, so only those and new files get written, and files that
+// aren't on the image anymore go to the trash.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{self, create_dir_all, File},
+    io::{self, BufRead, BufWriter, Seek},
+    path::{Path, PathBuf},
+};
+
+use eyre::{ensure, WrapErr};
+
+use crate::{
+    game::{warn_about_origins, warn_about_padding},
+    hash::{to_hex, Sha1Writer},
+    io_util::{self, DiskFull},
+    manifest::{Manifest, RenamedFile, MANIFEST_NAME},
+    paths::SYSTEM_DATA_DIR,
+    report::Reporter,
+    sections::fst::entry::{set_bytes_needed, Entry, ExtractContext, ExtractFailure, PaddedFile},
+    ExtractOptions,
+    Game,
+    EXTRACTION_ERRORS_NAME,
+};
+
+// Where refreshing moves files that were removed, in the root of the tree.
+// Rebuilding skips anything starting with a dot, so they stay off the ROM.
+// Files already there from an earlier refresh get replaced.
+pub const TRASH_NAME: &str = ".gcmod-trash";
+
+// Paths are on the ROM, normalized like `ManifestEntry::path`.
+#[derive(Debug, Default)]
+pub struct RefreshSummary {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+    // Only with `ExtractOptions::keep_going`
+    pub failures: Vec<ExtractFailure>,
+}
+
+impl Game {
+    // Updates the tree at `path`, which has to have a manifest, to match this
+    // ROM. `keep_going`, `xattrs`, `handlers`, and `fail_on_truncated` from
+    // `options` apply to the files that get written, and the rest don't. The
+    // system data is always written again. With `purge`, removed files are
+    // deleted instead of moved to `TRASH_NAME`.
+    pub fn refresh<R, P>(
+        &mut self,
+        mut iso: R,
+        path: P,
+        options: &ExtractOptions,
+        purge: bool,
+        reporter: &Reporter,
+    ) -> eyre::Result<RefreshSummary>
+    where
+        R: BufRead + Seek,
+        P: AsRef<Path>,
+    {
+        let root = path.as_ref();
+        let mut old = Manifest::read(root.join(MANIFEST_NAME))
+            .wrap_err_with(|| format!("Couldn't read the manifest in {}, which refreshing needs", root.display()))?;
+        let game_id = format!("{}{}", self.header.game_code, self.header.maker_code);
+        ensure!(
+            old.game_id == game_id,
+            "{} was extracted from {}, not {}",
+            root.display(), old.game_id, game_id,
+        );
+        io_util::check_writable(root)?;
+        self.warn_about_nkit(options, reporter);
+
+        // Files and directories keep the names they were extracted under
+        let renamed: HashMap<String, String> = old.renamed.iter()
+            .map(|r| (r.path.clone(), r.extracted_as.clone()))
+            .collect();
+        let tree_path = |rom_path: &str| -> PathBuf {
+            let mut path = root.to_owned();
+            let mut so_far = String::new();
+            for name in rom_path.split('/').filter(|n| !n.is_empty()) {
+                so_far.push('/');
+                so_far.push_str(name);
+                path.push(renamed.get(&so_far).map_or(name, String::as_str));
+            }
+            path
+        };
+        let old_files: HashMap<&str, Option<&str>> = old.entries.iter()
+            .filter(|e| !e.directory)
+            .map(|e| (e.path.as_str(), e.sha1.as_deref()))
+            .collect();
+
+        reporter.phase("compare");
+        reporter.status("Comparing files...");
+        let files: Vec<(usize, String)> = self.fst.entries().iter()
+            .filter_map(Entry::as_file)
+            .map(|f| (f.info.index, f.info.normalized_path()))
+            .collect();
+        let mut summary = RefreshSummary::default();
+        let mut hashes = BTreeMap::new();
+        let mut extract = BTreeSet::new();
+        for (i, (index, rom_path)) in files.iter().enumerate() {
+            let file = self.fst.entries()[*index].as_file().unwrap();
+            let hash = file.sha1(&mut iso).wrap_err_with(|| format!("Failed to hash {}", rom_path))?;
+            hashes.insert(*index, hash);
+            let output = tree_path(rom_path);
+            // Without a hash from the manifest, what's in the tree is the
+            // best guess at what was on the old ROM
+            let unchanged = match old_files.get(rom_path.as_str()) {
+                None => {
+                    summary.added.push(rom_path.clone());
+                    extract.insert(*index);
+                    None
+                },
+                Some(Some(old_hash)) => Some(*old_hash == to_hex(&hash) && output.exists()),
+                Some(None) => Some(hash_file(&output).ok() == Some(hash)),
+            };
+            match unchanged {
+                Some(true) => summary.unchanged += 1,
+                Some(false) => {
+                    summary.changed.push(rom_path.clone());
+                    extract.insert(*index);
+                },
+                None => {},
+            }
+            reporter.progress((i + 1) as u64, files.len() as u64, format!("{}/{} files compared.", i + 1, files.len()));
+        }
+        reporter.finish_progress();
+
+        // Removed first, since a new file or directory can have the same
+        // path as a removed one
+        let new_paths: HashSet<&str> = files.iter().map(|(_, p)| p.as_str()).collect();
+        summary.removed = old_files.keys()
+            .filter(|p| !new_paths.contains(*p))
+            .map(|p| p.to_string())
+            .collect();
+        summary.removed.sort_unstable();
+        let trash = root.join(TRASH_NAME);
+        for rom_path in &summary.removed {
+            let output = tree_path(rom_path);
+            if fs::symlink_metadata(&output).is_err() {
+                continue;
+            }
+            let result = if purge {
+                fs::remove_file(&output)
+            } else {
+                let trashed = trash.join(output.strip_prefix(root).unwrap());
+                create_dir_all(io_util::parent_dir(&trashed))
+                    .and_then(|()| match fs::remove_file(&trashed) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                        _ => Ok(()),
+                    })
+                    .and_then(|()| io_util::rename_or_copy(&output, &trashed).map(drop))
+            };
+            result.wrap_err_with(|| format!("Failed to remove {}", output.display()))?;
+        }
+        // Directories that aren't on the ROM anymore go too, unless there's
+        // something else in them. The deepest ones go first, so their
+        // parents can be empty.
+        let new_dirs: HashSet<String> = self.fst.entries().iter()
+            .filter(|e| e.is_dir())
+            .map(|e| e.info().normalized_path())
+            .collect();
+        let mut removed_dirs: Vec<&str> = old.entries.iter()
+            .filter(|e| e.directory && !new_dirs.contains(&e.path))
+            .map(|e| e.path.as_str())
+            .collect();
+        removed_dirs.sort_by_key(|p| Reverse(p.matches('/').count()));
+        for dir in removed_dirs {
+            let _ = fs::remove_dir(root.join(dir.trim_start_matches('/')));
+        }
+
+        // The system data moves if the ROM gained or lost its own
+        // &&systemdata, and what was in the old place goes
+        let system = self.system_paths()?;
+        let old_system = old.system_paths();
+        if system != old_system {
+            if system.is_alternate() {
+                reporter.warning(format!(
+                    "The ROM has its own /{}, so the system data is extracted to {} instead",
+                    SYSTEM_DATA_DIR, system.dir(),
+                ));
+            }
+            for file in [old_system.header(), old_system.apploader(), old_system.fst(), old_system.dol()] {
+                match fs::remove_file(root.join(&file)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound =>
+                        return Err(e).wrap_err_with(|| format!("Failed to remove {}", file)),
+                    _ => {},
+                }
+            }
+            let _ = fs::remove_dir(root.join(old_system.dir()));
+        }
+        let sys_data_path = root.join(system.dir());
+        create_dir_all(&sys_data_path)?;
+        reporter.phase("system_data");
+        reporter.status("Extracting system data...");
+        self.extract_system_data(&mut iso, &sys_data_path, options, reporter)?;
+
+        reporter.phase("extract");
+        reporter.status("Extracting changed files...");
+        let mut origins = self.origin_attributes(options, reporter);
+        let mut failures = Vec::new();
+        let mut context = ExtractContext {
+            failures: options.keep_going.then_some(&mut failures),
+            skip: files.iter().map(|(i, _)| *i).filter(|i| !extract.contains(i)).collect(),
+            names: self.fst.entries().iter()
+                .filter_map(|e| renamed.get(&e.info().normalized_path()).map(|name| (e.info().index, name.clone())))
+                .collect(),
+            dedup: None,
+            origins: origins.as_mut(),
+            handlers: &options.handlers,
+            data_end: Some(self.data_end()),
+            padded: Vec::new(),
+            fail_on_truncated: options.fail_on_truncated,
+            hashes: None,
+            hints: None,
+            sink: Some(reporter.sink()),
+        };
+        let total = extract.len();
+        let mut bytes_left: u64 = extract.iter()
+            .filter_map(|&i| self.fst.entries()[i].as_file())
+            .map(|f| f.size as u64)
+            .sum();
+        reporter.sink().totals(total as u64, Some(bytes_left));
+        self.fst.extract_file_system(root, &mut iso, &mut context, |written, failed, file| {
+            bytes_left = bytes_left.saturating_sub(file.size as u64);
+            reporter.progress((written + failed) as u64, total as u64, format!("{}/{} files written.", written, total));
+        })
+            .map_err(|e| set_bytes_needed(e, bytes_left))
+            .inspect_err(|e| {
+                // The old manifest stays, so it has to say the file it
+                // stopped at is cut short
+                if let Some(full) = e.downcast_ref::<DiskFull>().filter(|f| f.left_behind.is_some()) {
+                    old.record_partial(full, root);
+                    #[cfg(feature = "serde")]
+                    let _ = old.write(root.join(MANIFEST_NAME));
+                }
+            })
+            .wrap_err("Failed to extract filesystem")?;
+        reporter.finish_progress();
+        warn_about_padding(&context.padded, reporter);
+        warn_about_origins(origins.as_ref(), reporter);
+
+        // A file that failed keeps whatever it had, so its hash can't be
+        // trusted next time
+        for failure in &failures {
+            if let Some((index, _)) = files.iter().find(|(_, p)| *p == failure.path) {
+                hashes.remove(index);
+            }
+        }
+
+        let data_end = self.data_end();
+        let mut manifest = Manifest::new(self);
+        manifest.record_hashes(&hashes);
+        manifest.system_dir = system.is_alternate().then(|| system.dir().to_owned());
+        let new_entries: HashSet<String> = self.fst.entries().iter().map(|e| e.info().normalized_path()).collect();
+        manifest.renamed = old.renamed.iter()
+            .filter(|r| new_entries.contains(&r.path))
+            .map(|r| RenamedFile { path: r.path.clone(), extracted_as: r.extracted_as.clone() })
+            .collect();
+        manifest.padded = self.fst.entries().iter()
+            .filter_map(Entry::as_file)
+            .filter_map(|f| {
+                let bytes = f.bytes_past(data_end);
+                (bytes != 0).then(|| PaddedFile { path: f.info.normalized_path(), bytes })
+            })
+            .collect();
+        // Links stay what they were as long as neither side was touched
+        let touched: HashSet<&str> = summary.added.iter()
+            .chain(&summary.changed)
+            .chain(&summary.removed)
+            .map(String::as_str)
+            .collect();
+        manifest.duplicates = old.duplicates.into_iter()
+            .filter(|d| !touched.contains(d.path.as_str()) && !touched.contains(d.original.as_str()))
+            .collect();
+        manifest.write(root.join(MANIFEST_NAME)).wrap_err("Failed to write manifest")?;
+
+        if !failures.is_empty() {
+            let file = File::create(root.join(EXTRACTION_ERRORS_NAME))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &failures)
+                .wrap_err("Failed to write extraction errors")?;
+        }
+        summary.failures = failures;
+        Ok(summary)
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<[u8; 20]> {
+    let mut out = Sha1Writer::new(io::sink());
+    io::copy(&mut File::open(path)?, &mut out)?;
+    Ok(out.finish().1)
+}
@@ -0,0 +1,463 @@
+// Replaces a batch of files on an image in place, with the FST rewritten
+// once for all of them, instead of rebuilding the whole image.
+//
+// Nothing is written until every file has somewhere to go. Files that are
+// the same size or smaller are written over the old ones, and bigger ones
+// go in gaps, leaving their old copies alone. The moved files' data is
+// written first, and nothing else, so until the FST is written in one go,
+// the image is just as it was. Then the header is written if the user area
+// has to grow, then the files replaced in place, and only then is the space
+// that was freed zeroed. An image cut short after the FST was written can
+// have files replaced in place that don't have all of their new contents
+// yet. Running the same batch again finishes it.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    grow_fst::{take_space, write_zeros_at},
+    rom_handle::RomWriteHandle,
+    sections::fst::{entry::ENTRY_SIZE, INDEX_SELECTOR_PREFIX},
+    Game,
+    DEFAULT_ALIGNMENT,
+    MIN_ALIGNMENT,
+    WRITE_CHUNK_SIZE,
+};
+
+// One line of a mapping file: the path on the ROM, and the file to put
+// there.
+#[derive(Clone, Debug)]
+pub struct MappingLine {
+    pub rom_path: String,
+    pub local_path: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+pub struct Replacement {
+    pub index: usize,
+    // Normalized, like "/audio/bgm.dsp"
+    pub path: String,
+    pub source: PathBuf,
+    pub old_offset: u64,
+    pub old_size: u64,
+    // The same as `old_offset` for files replaced in place
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl Replacement {
+    pub fn in_place(&self) -> bool {
+        self.offset == self.old_offset
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplacePlan {
+    // In the order the mapping had them
+    pub replacements: Vec<Replacement>,
+    // The user area grown to fit the files that moved out of it, as
+    // (start, end), if any did
+    pub user_area: Option<(u64, u64)>,
+}
+
+#[derive(Debug)]
+pub enum ReplaceError {
+    // Lines start at 1
+    Syntax { line: usize, text: String },
+    NotFound(String),
+    NotAFile(String),
+    Duplicate(String),
+    NoRoom { path: String, size: u64 },
+    // Stored inside of the header, apploader, DOL or FST, so writing over
+    // it would break the image
+    InSystemData { path: String, section: String },
+    // Like `GrowError::Repaired`
+    Repaired,
+}
+
+impl fmt::Display for ReplaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplaceError::Syntax { line, text } =>
+                write!(f, "Line {} isn't like \"/path/on/rom = local/file\": {:?}", line, text),
+            ReplaceError::NotFound(path) => write!(f, "There's nothing at {} on the ROM", path),
+            ReplaceError::NotAFile(path) => write!(f, "{} is a directory, not a file", path),
+            ReplaceError::Duplicate(path) => write!(f, "{} is replaced more than once", path),
+            ReplaceError::NoRoom { path, size } =>
+                write!(f, "There's no gap big enough for the new {} ({} bytes)", path, size),
+            ReplaceError::InSystemData { path, section } =>
+                write!(f, "{} is stored inside of {}, so it can't be replaced", path, section),
+            ReplaceError::Repaired => f.write_str(
+                "The FST is malformed and had to be repaired to read it, so it can't be changed in place. Extract and rebuild the ROM instead",
+            ),
+        }
+    }
+}
+
+impl Error for ReplaceError {}
+
+// Reads lines like "/audio/bgm.dsp = new/bgm.dsp", or "idx:12 = new/bgm.dsp"
+// for the entry with that index. Blank lines and lines starting with '#' are
+// skipped, and local paths are relative to `base`.
+pub fn parse_mapping(text: &str, base: &Path) -> Result<Vec<MappingLine>, ReplaceError> {
+    let mut lines = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue
+        }
+        let syntax = || ReplaceError::Syntax { line: i + 1, text: line.to_owned() };
+        let (rom_path, local_path) = trimmed.split_once('=').ok_or_else(syntax)?;
+        let (rom_path, local_path) = (rom_path.trim(), local_path.trim());
+        if rom_path.is_empty() || local_path.is_empty() {
+            return Err(syntax());
+        }
+        // Entries can be given by index too, like "idx:12"
+        let rom_path = if rom_path.starts_with(INDEX_SELECTOR_PREFIX) {
+            rom_path.to_owned()
+        } else {
+            format!("/{}", rom_path.trim_start_matches('/'))
+        };
+        lines.push(MappingLine {
+            rom_path,
+            local_path: base.join(local_path),
+        });
+    }
+    Ok(lines)
+}
+
+impl ReplacePlan {
+    // Works out where each file in `mapping` goes on `game`, an image of
+    // `image_len` bytes. `sizes` are the new files' sizes, in the same
+    // order. Files in the user area stay in it if there's room, and the
+    // whole batch fails if any file doesn't fit anywhere.
+    pub fn new(game: &Game, image_len: u64, mapping: &[MappingLine], sizes: &[u64]) -> Result<ReplacePlan, ReplaceError> {
+        if !game.fst.repairs.is_empty() {
+            return Err(ReplaceError::Repaired);
+        }
+        let mut seen = HashSet::new();
+        let in_system_data = game.files_in_system_data();
+        let mut replacements = Vec::with_capacity(mapping.len());
+        for (line, &size) in mapping.iter().zip(sizes) {
+            let entry = game.fst.entry_for_selector(&line.rom_path)
+                .ok_or_else(|| ReplaceError::NotFound(line.rom_path.clone()))?;
+            let file = entry.as_file().ok_or_else(|| ReplaceError::NotAFile(line.rom_path.clone()))?;
+            let path = file.info.normalized_path();
+            if !seen.insert(file.info.index) {
+                return Err(ReplaceError::Duplicate(path));
+            }
+            if let Some((_, section)) = in_system_data.iter().find(|(f, _)| f.info.index == file.info.index) {
+                return Err(ReplaceError::InSystemData { path, section: section.clone() });
+            }
+            replacements.push(Replacement {
+                index: file.info.index,
+                path,
+                source: line.local_path.clone(),
+                old_offset: file.file_offset,
+                old_size: file.size as u64,
+                offset: file.file_offset,
+                size,
+            });
+        }
+
+        // The old copies of the files that move stay until the FST is
+        // written, so only what's free now can be used
+        let mut free = game.rom_layout().gaps(image_len);
+        let user_area = game.user_area().map(|(start, end)| (game.offset + start, game.offset + end));
+        // Biggest first, since they're the hardest to fit
+        let mut growing: Vec<&mut Replacement> = replacements.iter_mut().filter(|r| r.size > r.old_size).collect();
+        growing.sort_by_key(|r| (std::cmp::Reverse(r.size), r.index));
+        let mut new_user_area = user_area;
+        for r in growing {
+            let within = user_area
+                .filter(|&(start, end)| r.old_size == 0 || (r.old_offset >= start && r.old_offset + r.old_size <= end));
+            // Packed tightly if that's the only way it fits, and outside of
+            // the user area if it doesn't fit in it
+            r.offset = [within, None].into_iter()
+                .flat_map(|within| [(DEFAULT_ALIGNMENT, within), (MIN_ALIGNMENT, within)])
+                .find_map(|(alignment, within)| take_space(&mut free, r.size, alignment, within))
+                .ok_or_else(|| ReplaceError::NoRoom { path: r.path.clone(), size: r.size })?;
+            if let Some((start, end)) = &mut new_user_area {
+                *start = (*start).min(r.offset);
+                *end = (*end).max(r.offset + r.size);
+            }
+        }
+        Ok(ReplacePlan {
+            replacements,
+            user_area: new_user_area.filter(|&area| Some(area) != user_area),
+        })
+    }
+
+    // Writes the contents of the files that move, which only goes in space
+    // nothing uses. On its own, like if the batch is cut short, the image is
+    // still what it was, every file included.
+    pub fn write_data(&self, iso: &mut RomWriteHandle) -> io::Result<()> {
+        // The files replaced in place are written after the FST, when it's
+        // too late to stop
+        for r in &self.replacements {
+            check_size(r, &File::open(&r.source)?)?;
+        }
+        self.copy_files(iso, |r| !r.in_place())
+    }
+
+    fn copy_files(&self, iso: &mut RomWriteHandle, which: impl Fn(&Replacement) -> bool) -> io::Result<()> {
+        let mut buf = vec![0; WRITE_CHUNK_SIZE];
+        for r in self.replacements.iter().filter(|r| which(r)) {
+            let file = File::open(&r.source)?;
+            copy_file(file, &mut *iso, r, &mut buf)?;
+        }
+        Ok(())
+    }
+
+    // Points the FST at the new files, in one write, then grows the user
+    // area if it has to, writes the files replaced in place, and zeroes the
+    // space the old files took up that nothing uses anymore. `game` is what
+    // the image was opened as, before `write_data`.
+    pub fn commit(&self, game: &Game, iso: &mut RomWriteHandle) -> io::Result<()> {
+        let mut fst = vec![0; game.fst.size];
+        iso.seek(SeekFrom::Start(game.fst.offset))?;
+        iso.read_exact(&mut fst)?;
+        for r in &self.replacements {
+            // The offset and size are the second and third words of the entry
+            let entry = &mut fst[r.index * ENTRY_SIZE..(r.index + 1) * ENTRY_SIZE];
+            BigEndian::write_u32(&mut entry[4..8], (r.offset - game.offset) as u32);
+            BigEndian::write_u32(&mut entry[8..12], r.size as u32);
+        }
+        iso.seek(SeekFrom::Start(game.fst.offset))?;
+        iso.write_all(&fst)?;
+        iso.sync_data()?;
+
+        if let Some((start, end)) = self.user_area {
+            let mut header = game.header.clone();
+            header.set_user_area((start - game.offset) as u32, (end - start) as u32);
+            header.write_changes(&game.header, &mut *iso, game.offset)?;
+            iso.sync_data()?;
+        }
+        self.copy_files(iso, Replacement::in_place)?;
+        iso.sync_data()?;
+
+        let mut buf = vec![0; WRITE_CHUNK_SIZE];
+        for r in &self.replacements {
+            let (freed, len) = match r.in_place() {
+                true => (r.offset + r.size, r.old_size.saturating_sub(r.size)),
+                false => (r.old_offset, r.old_size),
+            };
+            if len > 0 {
+                write_zeros_at(&mut *iso, freed, len, &mut buf)?;
+            }
+        }
+        iso.sync_data()
+    }
+
+    // Everything, syncing between the steps so they reach the disk in order.
+    pub fn apply(&self, game: &Game, iso: &mut RomWriteHandle) -> io::Result<()> {
+        self.write_data(iso)?;
+        iso.sync_data()?;
+        self.commit(game, iso)
+    }
+}
+
+fn check_size(r: &Replacement, file: &File) -> io::Result<()> {
+    if file.metadata()?.len() != r.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} changed size since the batch was planned", r.source.display()),
+        ));
+    }
+    Ok(())
+}
+
+// Copies all of `file` to where `r` goes, checking it's still the size it
+// was planned for.
+fn copy_file(mut file: File, mut iso: impl Write + Seek, r: &Replacement, buf: &mut [u8]) -> io::Result<()> {
+    check_size(r, &file)?;
+    iso.seek(SeekFrom::Start(r.offset))?;
+    let mut left = r.size;
+    while left > 0 {
+        let n = left.min(buf.len() as u64) as usize;
+        file.read_exact(&mut buf[..n])?;
+        iso.write_all(&buf[..n])?;
+        left -= n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+    use crate::{
+        synthetic,
+        test_util::{build_image, open_image, synthetic_tree, TestDir},
+    };
+
+    fn line(rom_path: &str) -> MappingLine {
+        MappingLine { rom_path: rom_path.to_owned(), local_path: PathBuf::from("new") }
+    }
+
+    // Each file's index, offset and size, from the FST
+    fn files(game: &Game) -> Vec<(usize, u64, usize)> {
+        game.fst.entries().iter().filter_map(|e| e.as_file()).map(|f| (f.info.index, f.file_offset, f.size)).collect()
+    }
+
+    fn contents(image: &[u8], path: &str) -> Vec<u8> {
+        let game = open_image(image);
+        let file = game.fst.entry_for_path(path).and_then(|e| e.as_file()).unwrap();
+        image[file.file_offset as usize..file.file_offset as usize + file.size].to_vec()
+    }
+
+    // An image in `dir`, and a batch that replaces /data/levels/1.bin in
+    // place with a file the same size, and moves /data/levels/2.bin to fit
+    // a bigger one
+    fn batch(dir: &TestDir) -> (PathBuf, Vec<MappingLine>, [u64; 2]) {
+        let path = dir.join("image.iso");
+        fs::write(&path, build_image(synthetic_tree().path())).unwrap();
+        fs::write(dir.join("same.bin"), [0xa5; 1000]).unwrap();
+        fs::write(dir.join("grown.bin"), [0x5a; 10000]).unwrap();
+        let mapping = parse_mapping("/data/levels/1.bin = same.bin\n/data/levels/2.bin = grown.bin\n", dir.path()).unwrap();
+        (path, mapping, [1000, 10000])
+    }
+
+    fn open(path: &Path) -> (RomWriteHandle, Game) {
+        let mut iso = RomWriteHandle::open_for_modification(path, fs::OpenOptions::new().read(true)).unwrap();
+        let game = Game::open(io::BufReader::new(&mut iso), 0).unwrap();
+        (iso, game)
+    }
+
+    #[test]
+    fn cut_short_before_the_fst_is_written() {
+        let dir = TestDir::new();
+        let (path, mapping, sizes) = batch(&dir);
+        let original = fs::read(&path).unwrap();
+        let (mut iso, game) = open(&path);
+        let plan = ReplacePlan::new(&game, iso.size().unwrap(), &mapping, &sizes).unwrap();
+        assert!(plan.replacements[0].in_place() && !plan.replacements[1].in_place(), "{:?}", plan);
+        plan.write_data(&mut iso).unwrap();
+        drop(iso);
+
+        // Every file is just as it was, even the one replaced in place, and
+        // the only thing written is the moved file, in free space
+        let cut_short = fs::read(&path).unwrap();
+        assert_eq!(files(&open_image(&cut_short)), files(&game));
+        for &(name, size) in synthetic::FILES {
+            assert!(contents(&cut_short, &format!("/{}", name)) == synthetic::contents(size), "{}", name);
+        }
+        let moved = plan.replacements[1].offset as usize;
+        assert!(cut_short[moved..moved + 10000].iter().all(|&b| b == 0x5a));
+        let changed = original.iter().zip(&cut_short).filter(|(a, b)| a != b).count();
+        assert_eq!(changed, 10000);
+
+        // Running the batch again finishes it, with the moved file in the
+        // same place
+        let (mut iso, cut_short) = open(&path);
+        let again = ReplacePlan::new(&cut_short, iso.size().unwrap(), &mapping, &sizes).unwrap();
+        assert_eq!(again.replacements[1].offset, plan.replacements[1].offset);
+        again.apply(&cut_short, &mut iso).unwrap();
+        drop(iso);
+        let image = fs::read(&path).unwrap();
+        assert_eq!(contents(&image, "/data/levels/1.bin"), [0xa5; 1000]);
+        assert_eq!(contents(&image, "/data/levels/2.bin"), [0x5a; 10000]);
+        assert!(contents(&image, "/readme.txt") == synthetic::contents(64));
+        // The old copy of the moved file is zeroed
+        let old = plan.replacements[1].old_offset as usize;
+        assert!(image[old..old + 4096].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn sources_that_change_size_stop_it_before_anything_is_written() {
+        let dir = TestDir::new();
+        let (path, mapping, sizes) = batch(&dir);
+        let (mut iso, game) = open(&path);
+        let plan = ReplacePlan::new(&game, iso.size().unwrap(), &mapping, &sizes).unwrap();
+        // The file replaced in place, which is written last
+        fs::write(dir.join("same.bin"), [0xa5; 999]).unwrap();
+        let original = fs::read(&path).unwrap();
+        let e = plan.apply(&game, &mut iso).unwrap_err();
+        assert!(e.to_string().ends_with("same.bin changed size since the batch was planned"), "{}", e);
+        drop(iso);
+        assert!(fs::read(&path).unwrap() == original);
+    }
+
+    #[test]
+    fn smaller_files_leave_zeros_after_them() {
+        let dir = TestDir::new();
+        let (path, _, _) = batch(&dir);
+        fs::write(dir.join("small.bin"), [0x11; 100]).unwrap();
+        let mapping = parse_mapping("/data/levels/2.bin = small.bin", dir.path()).unwrap();
+        let (mut iso, game) = open(&path);
+        let plan = ReplacePlan::new(&game, iso.size().unwrap(), &mapping, &[100]).unwrap();
+        assert!(plan.replacements[0].in_place() && plan.user_area.is_none());
+        plan.apply(&game, &mut iso).unwrap();
+        drop(iso);
+        let image = fs::read(&path).unwrap();
+        let offset = plan.replacements[0].offset as usize;
+        assert_eq!(contents(&image, "/data/levels/2.bin"), [0x11; 100]);
+        assert!(image[offset + 100..offset + 4096].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn the_whole_batch_fails_if_a_file_doesnt_fit() {
+        let image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let mapping = [line("/readme.txt"), line("/data/big.bin")];
+        let e = ReplacePlan::new(&game, image.len() as u64, &mapping, &[10, synthetic::CAPACITY]).unwrap_err();
+        assert!(matches!(&e, ReplaceError::NoRoom { path, .. } if path == "/data/big.bin"), "{:?}", e);
+    }
+
+    #[test]
+    fn files_in_the_system_data_cant_be_replaced() {
+        let mut image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let readme = game.fst.entry_for_path("/readme.txt").unwrap().info().index;
+        let entry = game.fst.offset as usize + readme * ENTRY_SIZE;
+        image[entry + 4..entry + 8].copy_from_slice(&(game.dol.offset as u32).to_be_bytes());
+        let game = Game::open(Cursor::new(&image), 0).unwrap();
+
+        let e = ReplacePlan::new(&game, image.len() as u64, &[line("/readme.txt")], &[10]).unwrap_err();
+        assert!(matches!(&e, ReplaceError::InSystemData { path, section } if path == "/readme.txt" && section == "Start.dol"), "{:?}", e);
+        assert_eq!(e.to_string(), "/readme.txt is stored inside of Start.dol, so it can't be replaced");
+        // The rest can be
+        ReplacePlan::new(&game, image.len() as u64, &[line("/data/big.bin")], &[10]).unwrap();
+    }
+
+    #[test]
+    fn mappings() {
+        let text = "# a comment\n\naudio/bgm.dsp = new/bgm.dsp\n  /a b.bin=c d.bin  \nidx:12 = x.bin\n";
+        let lines = parse_mapping(text, Path::new("base")).unwrap();
+        let lines: Vec<(&str, &Path)> = lines.iter().map(|l| (l.rom_path.as_str(), l.local_path.as_path())).collect();
+        assert_eq!(lines, [
+            ("/audio/bgm.dsp", Path::new("base/new/bgm.dsp")),
+            ("/a b.bin", Path::new("base/c d.bin")),
+            ("idx:12", Path::new("base/x.bin")),
+        ]);
+        for (text, line) in [("a.bin\n", 1), ("# ok\n = a.bin\n", 2), ("a.bin =\n", 1)] {
+            assert!(matches!(parse_mapping(text, Path::new("")), Err(ReplaceError::Syntax { line: l, .. }) if l == line), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn files_by_index() {
+        let image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let readme = game.fst.entry_for_path("/readme.txt").unwrap().info().index;
+        let plan = ReplacePlan::new(&game, image.len() as u64, &[line(&format!("idx:{}", readme))], &[10]).unwrap();
+        assert_eq!(plan.replacements[0].path, "/readme.txt");
+
+        let e = ReplacePlan::new(&game, image.len() as u64, &[line("idx:1000")], &[10]).unwrap_err();
+        assert_eq!(e.to_string(), "There's nothing at idx:1000 on the ROM");
+        let e = ReplacePlan::new(&game, image.len() as u64, &[line("idx:0")], &[10]).unwrap_err();
+        assert!(matches!(e, ReplaceError::NotAFile(_)), "{:?}", e);
+        let by_index = line(&format!("idx:{}", readme));
+        let e = ReplacePlan::new(&game, image.len() as u64, &[line("/readme.txt"), by_index], &[10, 10]).unwrap_err();
+        assert!(matches!(e, ReplaceError::Duplicate(_)), "{:?}", e);
+    }
+}
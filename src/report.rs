@@ -0,0 +1,322 @@
+// Where the messages commands print along the way go. Results, what a
+// command was run to find out, are always printed. Status messages and
+// progress can be turned off, and progress only overwrites itself with `\r`
+// on terminals, so captured output is the same from run to run.
+//
+// With `--progress-json`, everything that would go to stderr is JSON
+// instead, one event per line, for frontends to parse. See `Event` for what
+// they look like. That's process-wide, since warnings come from all over the
+// library, not just from commands with a `Reporter`.
+
+use std::{
+    cell::Cell,
+    fmt::{self, Display},
+    io::{self, IsTerminal, Write},
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+#[cfg(feature = "serde")]
+use crate::build_info;
+
+// Goes up whenever an event changes in a way that could break a frontend,
+// like a field being removed or changing meaning. New fields don't count.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+static EVENTS: OnceLock<EventStream> = OnceLock::new();
+
+#[derive(Debug)]
+struct EventStream {
+    command: String,
+    started: Instant,
+    stats: Mutex<Stats>,
+}
+
+// For the `done` event. The counts are from the last progress event.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Stats {
+    pub elapsed_ms: u64,
+    pub warnings: u64,
+    pub current: u64,
+    pub total: u64,
+    pub bytes: u64,
+}
+
+// One line of `--progress-json` output, like
+// {"event":"progress","phase":"extract","current":12,"total":40,"bytes":8192,"total_bytes":65536,"path":"/audio/x.adp"}.
+// Fields that aren't known for a command are left out.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "event", rename_all = "snake_case"))]
+pub enum Event<'a> {
+    // First, before anything else
+    Start { schema_version: u32, command: &'a str, gcmod_version: &'a str },
+    // A new step of the command, which the progress after it is part of
+    Phase { phase: &'a str },
+    Progress {
+        phase: &'a str,
+        current: u64,
+        total: u64,
+        #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+        bytes: Option<u64>,
+        #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+        total_bytes: Option<u64>,
+        #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+        path: Option<&'a str>,
+    },
+    Warning { message: String },
+    // Last, whether the command worked or not
+    Done {
+        ok: bool,
+        #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+        error: Option<String>,
+        stats: Stats,
+    },
+}
+
+// Turns on `--progress-json` for `command`, and sends the start event.
+#[cfg(feature = "serde")]
+pub fn start_events(command: &str) {
+    let stream = EventStream {
+        command: command.to_owned(),
+        started: Instant::now(),
+        stats: Mutex::new(Stats::default()),
+    };
+    if EVENTS.set(stream).is_ok() {
+        emit(&Event::Start {
+            schema_version: EVENT_SCHEMA_VERSION,
+            command,
+            gcmod_version: build_info::VERSION,
+        });
+    }
+}
+
+pub fn json_events() -> bool {
+    EVENTS.get().is_some()
+}
+
+// Sends the done event, with `error` if the command failed.
+pub fn finish_events(error: Option<String>) {
+    let Some(stream) = EVENTS.get() else { return };
+    let mut stats = stream.stats.lock().map(|s| s.clone()).unwrap_or_default();
+    stats.elapsed_ms = stream.started.elapsed().as_millis() as u64;
+    emit(&Event::Done { ok: error.is_none(), error, stats });
+}
+
+// Prints a warning to stderr, or sends it as an event.
+pub fn warn(message: impl Display) {
+    match EVENTS.get() {
+        Some(stream) => {
+            if let Ok(mut stats) = stream.stats.lock() {
+                stats.warnings += 1;
+            }
+            emit(&Event::Warning { message: message.to_string() });
+        },
+        None => eprintln!("Warning: {}", message),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn emit(event: &Event) {
+    let mut stderr = io::stderr().lock();
+    // Nowhere to report it if stderr is gone
+    if serde_json::to_writer(&mut stderr, event).is_ok() {
+        let _ = writeln!(stderr);
+    }
+}
+
+// Events can only be turned on with serde, see `start_events`.
+#[cfg(not(feature = "serde"))]
+fn emit(_event: &Event) {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ProgressStyle {
+    // One line that keeps getting rewritten
+    Terminal,
+    // A line each time another tenth of the work is done
+    Plain,
+    // Events on stderr, for `--progress-json`
+    Json,
+    Hidden,
+}
+
+// What a progress update knows besides how many things are done, for
+// `--progress-json`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ProgressDetail<'a> {
+    pub bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    // The file just done
+    pub path: Option<&'a str>,
+}
+
+// What rebuilds and extractions tell something embedding gcmod as they go,
+// like a GUI with its own progress bar, see `Reporter::with_sink`. Files are
+// numbered from 0 in the order they're written, and paths are normalized,
+// like "/audio/bgm.dsp". Nothing here is ever printed.
+pub trait ProgressSink {
+    // Before anything's written: how many files there are, and how many
+    // bytes will be written all together, if that's known
+    fn totals(&self, _files: u64, _bytes: Option<u64>) {}
+
+    fn file_started(&self, _index: u64, _path: &str) {}
+
+    // `bytes` more were written, since the last time
+    fn bytes_written(&self, _bytes: u64) {}
+
+    // Sent for files that fail too, when the command keeps going past them
+    fn file_finished(&self, _index: u64, _path: &str) {}
+}
+
+// The sink a `Reporter` has unless it's given another one
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+impl fmt::Debug for dyn ProgressSink + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressSink")
+    }
+}
+
+#[derive(Debug)]
+pub struct Reporter {
+    status: bool,
+    progress: ProgressStyle,
+    // The last tenth printed in `Plain` style
+    last_step: Cell<Option<u64>>,
+    // Whether there's a progress line the next message needs to go below
+    line_open: Cell<bool>,
+    // See `phase`
+    phase: Cell<Option<&'static str>>,
+    sink: Box<dyn ProgressSink>,
+}
+
+impl Reporter {
+    pub fn new(status: bool, progress: ProgressStyle) -> Reporter {
+        Reporter {
+            status,
+            progress,
+            last_step: Cell::new(None),
+            line_open: Cell::new(false),
+            phase: Cell::new(None),
+            sink: Box::new(NoProgress),
+        }
+    }
+
+    // Also sends the progress of rebuilds and extractions to `sink`, however
+    // the rest is shown.
+    pub fn with_sink(mut self, sink: impl ProgressSink + 'static) -> Reporter {
+        self.sink = Box::new(sink);
+        self
+    }
+
+    pub fn sink(&self) -> &dyn ProgressSink {
+        &*self.sink
+    }
+
+    // `quiet` hides status messages and progress, and `no_progress` just
+    // progress, unless progress is going out as events. Otherwise progress
+    // is plain lines unless stdout is a terminal.
+    pub fn for_output(quiet: bool, no_progress: bool) -> Reporter {
+        let progress = if json_events() {
+            ProgressStyle::Json
+        } else if quiet || no_progress {
+            ProgressStyle::Hidden
+        } else if io::stdout().is_terminal() {
+            ProgressStyle::Terminal
+        } else {
+            ProgressStyle::Plain
+        };
+        Reporter::new(!quiet, progress)
+    }
+
+    // Only prints results, for using gcmod as a library. See `with_sink` for
+    // getting the progress some other way.
+    pub fn quiet() -> Reporter {
+        Reporter::new(false, ProgressStyle::Hidden)
+    }
+
+    pub fn status(&self, message: impl Display) {
+        if self.status {
+            self.finish_progress();
+            println!("{}", message);
+        }
+    }
+
+    pub fn result(&self, message: impl Display) {
+        self.finish_progress();
+        println!("{}", message);
+    }
+
+    // Warnings go to stderr, even when status messages are hidden.
+    pub fn warning(&self, message: impl Display) {
+        self.finish_progress();
+        warn(message);
+    }
+
+    // Starts a new step of the command, like "write" or "verify". The
+    // progress after this is for it. Only events show phases, so this
+    // doesn't print anything otherwise.
+    pub fn phase(&self, phase: &'static str) {
+        self.finish_progress();
+        self.phase.set(Some(phase));
+        if self.progress == ProgressStyle::Json {
+            emit(&Event::Phase { phase });
+        }
+    }
+
+    // `done` out of `total` things are done, described by `message`, like
+    // "12/40 files written.".
+    pub fn progress(&self, done: u64, total: u64, message: impl Display) {
+        self.progress_detail(done, total, ProgressDetail::default(), message)
+    }
+
+    pub fn progress_detail(&self, done: u64, total: u64, detail: ProgressDetail, message: impl Display) {
+        match self.progress {
+            ProgressStyle::Terminal => {
+                print!("\r{}", message);
+                let _ = io::stdout().flush();
+                self.line_open.set(true);
+            },
+            ProgressStyle::Plain => {
+                let step = done.saturating_mul(10).checked_div(total).unwrap_or(10).min(10);
+                if self.last_step.get() < Some(step) {
+                    self.last_step.set(Some(step));
+                    println!("{}", message);
+                }
+            },
+            ProgressStyle::Json => {
+                let Some(stream) = EVENTS.get() else { return };
+                if let Ok(mut stats) = stream.stats.lock() {
+                    stats.current = done;
+                    stats.total = total;
+                    stats.bytes = detail.bytes.unwrap_or(stats.bytes);
+                }
+                emit(&Event::Progress {
+                    phase: self.phase.get().unwrap_or(&stream.command),
+                    current: done,
+                    total,
+                    bytes: detail.bytes,
+                    total_bytes: detail.total_bytes,
+                    path: detail.path,
+                });
+            },
+            ProgressStyle::Hidden => {},
+        }
+    }
+
+    // Ends the current progress line, so whatever comes next starts on a
+    // line of its own.
+    pub fn finish_progress(&self) {
+        if self.line_open.replace(false) {
+            println!();
+        }
+        self.last_step.set(None);
+    }
+}
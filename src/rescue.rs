@@ -0,0 +1,445 @@
+// For `rescue`, which looks for the system data of ROMs whose header is too
+// damaged to say where it is. Each scanner only looks for one kind of
+// structure, and rates each thing it finds by how many checks it passes.
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead, Read, Seek, SeekFrom},
+};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    sections::{
+        apploader::{APPLOADER_LOAD_ADDR, APPLOADER_MAX_SIZE, APPLOADER_OFFSET},
+        dol::{BSS_ADDRESS_OFFSET, BSS_SIZE_OFFSET, DOL_HEADER_LEN},
+        fst::{entry::ENTRY_SIZE, FST},
+    },
+    align,
+    DEFAULT_ALIGNMENT,
+    DEFAULT_SYSTEM_ALIGNMENT,
+    MIN_ALIGNMENT,
+    WRITE_CHUNK_SIZE,
+};
+
+// Where everything the DOL loads has to go
+pub const MEM1_START: u32 = 0x8000_0000;
+pub const MEM1_END: u32 = 0x8180_0000;
+
+const TEXT_SEG_COUNT: usize = 7;
+const TOTAL_SEG_COUNT: usize = 18;
+const SEG_ADDRESSES_OFFSET: usize = 0x48;
+const SEG_SIZES_OFFSET: usize = 0x90;
+const ENTRY_POINT_OFFSET: usize = 0xE0;
+// Everything after the entry point is unused, and zero on retail discs
+const DOL_PADDING_OFFSET: usize = 0xE4;
+
+// A directory with no name and no parent, which only the root is
+const FST_ROOT_PREFIX: [u8; 8] = [1, 0, 0, 0, 0, 0, 0, 0];
+// Far more than any retail game has, but small enough to rule out most
+// random matches
+const MAX_FST_ENTRIES: usize = 0x10_0000;
+
+// "YYYY/MM/DD"
+const APPLOADER_DATE_SIZE: usize = 10;
+const APPLOADER_HEADER_READ_SIZE: usize = 0x20;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CandidateKind {
+    Apploader,
+    Dol,
+    Fst,
+}
+
+impl fmt::Display for CandidateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CandidateKind::Apploader => "Apploader",
+            CandidateKind::Dol => "DOL",
+            CandidateKind::Fst => "FST",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Candidate {
+    pub kind: CandidateKind,
+    pub offset: u64,
+    pub size: usize,
+    pub confidence: Confidence,
+    // What was found, like the number of entries in an FST
+    pub details: String,
+}
+
+// The most likely candidate of `kind`. Ties go to the one closest to the
+// start of the image, since that's where the system data normally is.
+pub fn best(candidates: &[Candidate], kind: CandidateKind) -> Option<&Candidate> {
+    candidates.iter()
+        .filter(|c| c.kind == kind)
+        .max_by(|a, b| a.confidence.cmp(&b.confidence).then(b.offset.cmp(&a.offset)))
+}
+
+// Looks for DOL headers at each `DEFAULT_ALIGNMENT` boundary of the first
+// `len` bytes of `iso`. Every segment has to be inside of the image and load
+// into MEM1, and the more of the rest of the header looks right, the more
+// confident the match is.
+pub fn scan_dol_headers(mut iso: impl Read + Seek, len: u64) -> io::Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+    let mut offset = 0;
+    while offset + DOL_HEADER_LEN as u64 <= len {
+        candidates.extend(check_dol_header_at(&mut iso, offset, len)?);
+        offset += DEFAULT_ALIGNMENT;
+    }
+    Ok(candidates)
+}
+
+// Checks for a DOL header at just `offset`, for places a DOL is likely to be
+// that aren't aligned, like right after the FST.
+pub fn check_dol_header_at(mut iso: impl Read + Seek, offset: u64, len: u64) -> io::Result<Option<Candidate>> {
+    if offset + DOL_HEADER_LEN as u64 > len {
+        return Ok(None);
+    }
+    let mut buf = [0; DOL_HEADER_LEN];
+    iso.seek(SeekFrom::Start(offset))?;
+    iso.read_exact(&mut buf)?;
+    Ok(check_dol_header(&buf, offset, len))
+}
+
+fn check_dol_header(buf: &[u8], offset: u64, len: u64) -> Option<Candidate> {
+    let word = |at: usize| BigEndian::read_u32(&buf[at..]);
+    let mut text_segments = 0;
+    let mut data_segments = 0;
+    let mut end = DOL_HEADER_LEN as u64;
+    let mut entry_in_text = false;
+    let entry_point = word(ENTRY_POINT_OFFSET);
+
+    for i in 0..TOTAL_SEG_COUNT {
+        let seg_offset = word(i * 4);
+        let address = word(SEG_ADDRESSES_OFFSET + i * 4);
+        let size = word(SEG_SIZES_OFFSET + i * 4);
+        if size == 0 {
+            continue
+        }
+        let seg_end = seg_offset as u64 + size as u64;
+        let in_mem1 = address >= MEM1_START && address as u64 + size as u64 <= MEM1_END as u64;
+        if (seg_offset as usize) < DOL_HEADER_LEN || offset + seg_end > len || !in_mem1 {
+            return None;
+        }
+        if i < TEXT_SEG_COUNT {
+            text_segments += 1;
+            entry_in_text |= (address..address + size).contains(&entry_point);
+        } else {
+            data_segments += 1;
+        }
+        end = end.max(seg_end);
+    }
+    // There's nothing to run without code
+    if text_segments == 0 {
+        return None;
+    }
+
+    let bss_address = word(BSS_ADDRESS_OFFSET as usize);
+    let bss_size = word(BSS_SIZE_OFFSET as usize);
+    let bss_in_mem1 = bss_size == 0
+        || (bss_address >= MEM1_START && bss_address as u64 + bss_size as u64 <= MEM1_END as u64);
+    let padding_clear = buf[DOL_PADDING_OFFSET..].iter().all(|&b| b == 0);
+
+    let passed = [entry_in_text, bss_in_mem1, padding_clear].iter().filter(|&&p| p).count();
+    let confidence = match passed {
+        3 => Confidence::High,
+        2 => Confidence::Medium,
+        _ => Confidence::Low,
+    };
+    let mut details = format!(
+        "{} text and {} data segments, entry point {:#010x}",
+        text_segments, data_segments, entry_point,
+    );
+    if !entry_in_text {
+        details.push_str(" (outside of the text segments)");
+    }
+    Some(Candidate {
+        kind: CandidateKind::Dol,
+        offset,
+        size: end as usize,
+        confidence,
+        details,
+    })
+}
+
+// Where DOLs that `scan_dol_headers` doesn't check could be: right after the
+// other system data found, which is where they usually are, either packed
+// in or on the boundary rebuilding puts system data on.
+pub fn dol_offsets_after(candidates: &[Candidate]) -> BTreeSet<u64> {
+    candidates.iter()
+        .filter(|c| c.kind != CandidateKind::Dol)
+        .flat_map(|c| {
+            let end = c.offset + c.size as u64;
+            [align(end, MIN_ALIGNMENT), align(end, DEFAULT_SYSTEM_ALIGNMENT)]
+        })
+        .filter(|offset| offset % DEFAULT_ALIGNMENT != 0)
+        .collect()
+}
+
+// Looks for FSTs at every 4 byte boundary of the first `len` bytes of `iso`,
+// by the pattern of their root entry. Matches only count if the whole table
+// parses. They're more likely to be real if all of the names decode and all
+// of the files fit inside of the image.
+// `progress` gets called with the amount of bytes scanned so far and the
+// total.
+pub fn scan_fsts(
+    mut iso: impl BufRead + Seek,
+    len: u64,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<Vec<Candidate>> {
+    let mut matches = Vec::new();
+    // Enough extra to read a whole entry that starts at the end of a chunk
+    let mut buf = vec![0; WRITE_CHUNK_SIZE + ENTRY_SIZE];
+    let mut pos = 0;
+    while pos < len {
+        iso.seek(SeekFrom::Start(pos))?;
+        let want = (len - pos).min(buf.len() as u64) as usize;
+        let read = read_up_to(&mut iso, &mut buf[..want])?;
+        if read < ENTRY_SIZE {
+            break
+        }
+        let last = (read - ENTRY_SIZE).min(WRITE_CHUNK_SIZE - 1);
+        for i in (0..=last).step_by(4) {
+            let entry = &buf[i..i + ENTRY_SIZE];
+            if entry[..8] != FST_ROOT_PREFIX {
+                continue
+            }
+            let count = BigEndian::read_u32(&entry[8..]) as usize;
+            let offset = pos + i as u64;
+            // A root on its own describes nothing worth rescuing
+            if (2..=MAX_FST_ENTRIES).contains(&count) && offset + (count * ENTRY_SIZE) as u64 <= len {
+                matches.push(offset);
+            }
+        }
+        pos += WRITE_CHUNK_SIZE as u64;
+        progress(pos.min(len), len);
+    }
+
+    let mut candidates = Vec::new();
+    for offset in matches {
+        if let Ok(fst) = FST::new(&mut iso, offset) {
+            candidates.push(check_fst(&fst, len));
+        }
+    }
+    Ok(candidates)
+}
+
+fn check_fst(fst: &FST, len: u64) -> Candidate {
+    let names_decode = fst.entries().iter().skip(1).all(|e| {
+        let name = e.info().name.as_str();
+        !name.is_empty() && !name.chars().any(|c| c == char::REPLACEMENT_CHARACTER || c.is_control())
+    });
+    let files_fit = fst.entries().iter()
+        .filter_map(|e| e.as_file())
+        .all(|f| f.file_offset + f.size as u64 <= len);
+    let confidence = match (names_decode, files_fit) {
+        (true, true) => Confidence::High,
+        (true, false) => Confidence::Medium,
+        _ => Confidence::Low,
+    };
+    let mut details = format!("{} entries, {} of them files", fst.entries().len(), fst.file_count);
+    if !names_decode {
+        details.push_str(", some names don't decode");
+    }
+    if !files_fit {
+        details.push_str(", some files are past the end of the image");
+    }
+    Candidate {
+        kind: CandidateKind::Fst,
+        offset: fst.offset,
+        size: fst.size,
+        confidence,
+        details,
+    }
+}
+
+// The apploader only ever starts at `APPLOADER_OFFSET`, so this just checks
+// whether it still has its date string. A size that fits below the IPL and
+// an entry point where the IPL loads it make it a confident match.
+pub fn check_apploader(mut iso: impl Read + Seek, len: u64) -> io::Result<Option<Candidate>> {
+    if APPLOADER_OFFSET + APPLOADER_HEADER_READ_SIZE as u64 > len {
+        return Ok(None);
+    }
+    let mut buf = [0; APPLOADER_HEADER_READ_SIZE];
+    iso.seek(SeekFrom::Start(APPLOADER_OFFSET))?;
+    iso.read_exact(&mut buf)?;
+
+    let date = &buf[..APPLOADER_DATE_SIZE];
+    let is_date = date.iter().enumerate().all(|(i, &b)| match i {
+        4 | 7 => b == b'/',
+        _ => b.is_ascii_digit(),
+    });
+    if !is_date {
+        return Ok(None);
+    }
+
+    let entry_point = BigEndian::read_u32(&buf[0x10..]);
+    let code_size = BigEndian::read_u32(&buf[0x14..]) as usize;
+    let trailer_size = BigEndian::read_u32(&buf[0x18..]) as usize;
+    let size = code_size.saturating_add(trailer_size);
+    let loads_below_ipl = (APPLOADER_LOAD_ADDR as u64..APPLOADER_LOAD_ADDR as u64 + APPLOADER_MAX_SIZE as u64)
+        .contains(&(entry_point as u64));
+    let fits = code_size != 0
+        && size <= APPLOADER_MAX_SIZE
+        && APPLOADER_OFFSET + (APPLOADER_HEADER_READ_SIZE + size) as u64 <= len;
+    let confidence = match (fits, loads_below_ipl) {
+        (true, true) => Confidence::High,
+        (true, false) => Confidence::Medium,
+        _ => Confidence::Low,
+    };
+    Ok(Some(Candidate {
+        kind: CandidateKind::Apploader,
+        offset: APPLOADER_OFFSET,
+        size: APPLOADER_HEADER_READ_SIZE + if fits { size } else { 0 },
+        confidence,
+        details: format!(
+            "dated {}, entry point {:#010x}",
+            String::from_utf8_lossy(date), entry_point,
+        ),
+    }))
+}
+
+// Like `read_exact`, but stops at the end of `reader` instead of failing.
+fn read_up_to(mut reader: impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        junk::fill_junk,
+        test_util::{build_image, synthetic_tree},
+    };
+
+    const LEN: usize = 0x40000;
+    const DOL_OFFSET: usize = 0x3000;
+    const DOL_SIZE: usize = 288;
+    const FST_OFFSET: usize = 0x2800;
+    const FST_SIZE: usize = 0xa7;
+
+    fn junk() -> Vec<u8> {
+        let mut buf = vec![0; LEN];
+        fill_junk(*b"GSLF", 0, 0, &mut buf);
+        buf
+    }
+
+    // The synthetic game's `len` bytes at `from`, copied into `buf` at `to`
+    fn plant(buf: &mut [u8], image: &[u8], from: usize, len: usize, to: usize) {
+        buf[to..to + len].copy_from_slice(&image[from..from + len]);
+    }
+
+    #[test]
+    fn junk_has_nothing_in_it() {
+        let buf = junk();
+        assert!(scan_dol_headers(Cursor::new(&buf), LEN as u64).unwrap().is_empty());
+        assert!(scan_fsts(Cursor::new(&buf), LEN as u64, |_, _| {}).unwrap().is_empty());
+        assert!(check_apploader(Cursor::new(&buf), LEN as u64).unwrap().is_none());
+    }
+
+    #[test]
+    fn dols_at_odd_offsets() {
+        let image = build_image(synthetic_tree().path());
+        let mut buf = junk();
+        plant(&mut buf, &image, DOL_OFFSET, DOL_SIZE, 0x18000);
+        plant(&mut buf, &image, DOL_OFFSET, DOL_SIZE, 0x12345);
+
+        let found = scan_dol_headers(Cursor::new(&buf), LEN as u64).unwrap();
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!((found[0].offset, found[0].size, found[0].confidence), (0x18000, DOL_SIZE, Confidence::High));
+        // Only aligned offsets are scanned, others have to be checked
+        let odd = check_dol_header_at(Cursor::new(&buf), 0x12345, LEN as u64).unwrap().unwrap();
+        assert_eq!((odd.offset, odd.size), (0x12345, DOL_SIZE));
+        // Cut off by the end of the image
+        assert!(check_dol_header_at(Cursor::new(&buf), 0x12345, 0x12345 + 0x110).unwrap().is_none());
+    }
+
+    #[test]
+    fn fsts_at_odd_offsets() {
+        let image = build_image(synthetic_tree().path());
+        let mut buf = junk();
+        plant(&mut buf, &image, FST_OFFSET, FST_SIZE, 0x20004);
+        let found = scan_fsts(Cursor::new(&buf), LEN as u64, |_, _| {}).unwrap();
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!((found[0].offset, found[0].size), (0x20004, FST_SIZE));
+        // Its files are past the end of this buffer
+        assert_eq!(found[0].confidence, Confidence::Medium);
+        assert!(found[0].details.starts_with("9 entries, 5 of them files"), "{}", found[0].details);
+
+        // With them there too
+        let found = scan_fsts(Cursor::new(&image), image.len() as u64, |_, _| {}).unwrap();
+        let best = best(&found, CandidateKind::Fst).unwrap();
+        assert_eq!((best.offset, best.confidence), (FST_OFFSET as u64, Confidence::High));
+    }
+
+    #[test]
+    fn apploaders_by_their_date() {
+        let image = build_image(synthetic_tree().path());
+        let mut buf = junk();
+        let offset = APPLOADER_OFFSET as usize;
+        plant(&mut buf, &image, offset, APPLOADER_HEADER_READ_SIZE, offset);
+        let found = check_apploader(Cursor::new(&buf), LEN as u64).unwrap().unwrap();
+        assert_eq!(found.offset, APPLOADER_OFFSET);
+
+        buf[offset + 4] = b'-';
+        assert!(check_apploader(Cursor::new(&buf), LEN as u64).unwrap().is_none());
+    }
+
+    #[test]
+    fn the_best_candidate() {
+        let candidate = |offset, confidence| Candidate {
+            kind: CandidateKind::Dol,
+            offset,
+            size: 0,
+            confidence,
+            details: String::new(),
+        };
+        let candidates = [candidate(0x100, Confidence::Low), candidate(0x300, Confidence::High), candidate(0x200, Confidence::High)];
+        assert_eq!(best(&candidates, CandidateKind::Dol).unwrap().offset, 0x200);
+        assert!(best(&candidates, CandidateKind::Fst).is_none());
+    }
+
+    // Packed right after the FST, or on the next system data boundary
+    #[test]
+    fn dols_after_the_rest_of_the_system_data() {
+        let image = build_image(synthetic_tree().path());
+        let found = scan_fsts(Cursor::new(&image), image.len() as u64, |_, _| {}).unwrap();
+        let offsets = dol_offsets_after(&found);
+        assert_eq!(offsets.into_iter().collect::<Vec<_>>(), [0x28a8, 0x3000]);
+        let dol = check_dol_header_at(Cursor::new(&image), DOL_OFFSET as u64, image.len() as u64).unwrap().unwrap();
+        assert_eq!(dol.confidence, Confidence::High);
+    }
+}
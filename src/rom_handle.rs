@@ -0,0 +1,512 @@
+// Opened ROMs. Commands that only read a ROM open it as a `RomReadHandle`,
+// which is always opened read-only, so nothing they do (even panicking
+// mid-way) can change it. Commands that change a ROM in place need a
+// `RomWriteHandle`, which locks the file so two gcmod processes can't change
+// it at the same time, and the functions that write to a ROM take one.
+//
+// ROMs can also be gzip or zstd compressed as a whole, like for storage, with
+// the `stream-compress` feature. Those can't be read from just anywhere, so
+// opening one decompresses all of it first: to memory if it's small, and
+// otherwise to a temporary file that's deleted as soon as it's closed, or if
+// decompressing fails.
+
+#[cfg(feature = "stream-compress")]
+use std::cell::Cell;
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+// Decompressed ROMs up to this size stay in memory
+#[cfg(feature = "stream-compress")]
+pub const MEMORY_SPOOL_LIMIT: usize = 0x400_0000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // The compression a file starting with `start` has, if it's one of the
+    // ones a ROM can be opened from.
+    pub fn detect(start: &[u8]) -> Option<Compression> {
+        if start.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if start.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Source {
+    File(File),
+    #[cfg_attr(not(feature = "stream-compress"), allow(dead_code))]
+    Memory(Cursor<Vec<u8>>),
+}
+
+#[derive(Debug)]
+pub struct RomReadHandle {
+    source: Source,
+    // What the file on disk was compressed with, if anything
+    compression: Option<Compression>,
+}
+
+impl RomReadHandle {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<RomReadHandle> {
+        RomReadHandle::open_with_progress(path, |_, _| {})
+    }
+
+    // Like `open`, calling `progress` with how many of the compressed file's
+    // bytes have been decompressed so far and how many there are, while
+    // decompressing one.
+    pub fn open_with_progress(
+        path: impl AsRef<Path>,
+        progress: impl FnMut(u64, u64),
+    ) -> io::Result<RomReadHandle> {
+        let mut file = OpenOptions::new().read(true).open(path)?;
+        let Some(compression) = detect_compression(&mut file)? else {
+            return Ok(RomReadHandle { source: Source::File(file), compression: None });
+        };
+        let source = spool(file, compression, progress)?;
+        Ok(RomReadHandle { source, compression: Some(compression) })
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    // The size of the file, which is less than the ROM's capacity for
+    // trimmed images. For a compressed one, it's the decompressed size.
+    pub fn size(&self) -> io::Result<u64> {
+        match &self.source {
+            Source::File(file) => Ok(file.metadata()?.len()),
+            Source::Memory(data) => Ok(data.get_ref().len() as u64),
+        }
+    }
+
+    // Another handle to the same open file, for things like
+    // `io_util::FsHints` that don't read it themselves. ROMs decompressed to
+    // memory don't have one.
+    pub fn try_clone_file(&self) -> io::Result<File> {
+        match &self.source {
+            Source::File(file) => file.try_clone(),
+            Source::Memory(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "The ROM is in memory")),
+        }
+    }
+}
+
+impl Read for RomReadHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.source {
+            Source::File(file) => file.read(buf),
+            Source::Memory(data) => data.read(buf),
+        }
+    }
+}
+
+impl Seek for RomReadHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.source {
+            Source::File(file) => file.seek(pos),
+            Source::Memory(data) => data.seek(pos),
+        }
+    }
+}
+
+// Leaves `file` at the start either way.
+fn detect_compression(file: &mut File) -> io::Result<Option<Compression>> {
+    let mut start = Vec::with_capacity(ZSTD_MAGIC.len());
+    Read::by_ref(file).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut start)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(Compression::detect(&start))
+}
+
+#[cfg(feature = "stream-compress")]
+fn spool(file: File, compression: Compression, progress: impl FnMut(u64, u64)) -> io::Result<Source> {
+    spool_with(file, compression, MEMORY_SPOOL_LIMIT, crate::workspace::temp_file, progress)
+}
+
+// Decompresses `file` into memory, or once it's past `memory_limit` bytes,
+// into the file `temp_file` makes.
+#[cfg(feature = "stream-compress")]
+fn spool_with(
+    file: File,
+    compression: Compression,
+    memory_limit: usize,
+    mut temp_file: impl FnMut() -> io::Result<File>,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<Source> {
+    let total = file.metadata()?.len();
+    let read = Cell::new(0);
+    let counted = Counted { inner: file, count: &read };
+    let mut decoder: Box<dyn Read + '_> = match compression {
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(counted)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(counted)?),
+    };
+    let failed = |e: io::Error| io::Error::new(e.kind(), format!("Couldn't decompress the {} ROM: {}", compression, e));
+
+    let mut data = Vec::new();
+    let mut temp: Option<File> = None;
+    let mut buf = vec![0; crate::WRITE_CHUNK_SIZE];
+    let mut reported = 0;
+    loop {
+        let n = decoder.read(&mut buf).map_err(failed)?;
+        if n == 0 {
+            break;
+        }
+        match &mut temp {
+            Some(temp) => temp.write_all(&buf[..n])?,
+            None if data.len() + n > memory_limit => {
+                // Deleted when it's closed, which includes returning early
+                // from here
+                let mut file = temp_file()?;
+                file.write_all(&data)?;
+                file.write_all(&buf[..n])?;
+                data = Vec::new();
+                temp = Some(file);
+            },
+            None => data.extend_from_slice(&buf[..n]),
+        }
+        // Decompressing can take many reads of output for each read of input
+        if read.get() != reported {
+            reported = read.get();
+            progress(reported, total);
+        }
+    }
+    Ok(match temp {
+        Some(mut file) => {
+            file.seek(SeekFrom::Start(0))?;
+            Source::File(file)
+        },
+        None => Source::Memory(Cursor::new(data)),
+    })
+}
+
+// Counts the bytes read through it
+#[cfg(feature = "stream-compress")]
+struct Counted<'a> {
+    inner: File,
+    count: &'a Cell<u64>,
+}
+
+#[cfg(feature = "stream-compress")]
+impl Read for Counted<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+#[cfg(not(feature = "stream-compress"))]
+fn spool(_file: File, compression: Compression, _progress: impl FnMut(u64, u64)) -> io::Result<Source> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "The ROM is {} compressed, and this gcmod was built without the stream-compress feature to open it. Decompress it first.",
+            compression,
+        ),
+    ))
+}
+
+#[derive(Debug)]
+pub struct RomWriteHandle {
+    file: File,
+}
+
+impl RomWriteHandle {
+    // Opens `path` with `options`, plus read and write access, and locks it
+    // until the handle is dropped. Fails right away if another process has it
+    // locked. Reading is for checking it isn't compressed.
+    pub fn open_for_modification(path: impl AsRef<Path>, options: &OpenOptions) -> io::Result<RomWriteHandle> {
+        let mut file = options.clone().read(true).write(true).open(path)?;
+        if let Some(compression) = detect_compression(&mut file)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The ROM is {} compressed, so it can't be changed in place. Decompress it first.", compression),
+            ));
+        }
+        lock(&file)?;
+        Ok(RomWriteHandle { file })
+    }
+
+    pub fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    pub fn modified(&self) -> io::Result<std::time::SystemTime> {
+        self.file.metadata()?.modified()
+    }
+
+    // Grows the ROM to `len` bytes of zeros, like for a trimmed image. The
+    // new part doesn't take up any space on file systems with sparse files.
+    pub fn extend(&mut self, len: u64) -> io::Result<()> {
+        if self.size()? < len {
+            self.file.set_len(len)?;
+        }
+        Ok(())
+    }
+
+    // Waits for everything written so far to be on the disk.
+    pub fn sync_data(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+impl Read for RomWriteHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for RomWriteHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for RomWriteHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+// An advisory lock, so it only keeps out other processes that also lock.
+// The OS releases it when the file is closed.
+#[cfg(unix)]
+fn lock(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    if e.kind() == io::ErrorKind::WouldBlock {
+        Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "Another process (probably gcmod) is already modifying this ROM",
+        ))
+    } else {
+        Err(e)
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::TestDir;
+
+    fn rom(dir: &TestDir) -> std::path::PathBuf {
+        let path = dir.path().join("rom.iso");
+        fs::write(&path, vec![0xab; 0x1000]).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_one_writer_at_a_time() {
+        let dir = TestDir::new();
+        let path = rom(&dir);
+        let options = OpenOptions::new();
+        let first = RomWriteHandle::open_for_modification(&path, &options).unwrap();
+        let e = RomWriteHandle::open_for_modification(&path, &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+        assert!(e.to_string().contains("already modifying"), "{}", e);
+        // Reading doesn't take the lock
+        let mut data = Vec::new();
+        RomReadHandle::open(&path).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 0x1000);
+
+        drop(first);
+        RomWriteHandle::open_for_modification(&path, &options).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reading_needs_no_write_access() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new();
+        let path = rom(&dir);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444)).unwrap();
+        let mut rom = RomReadHandle::open(&path).unwrap();
+        assert_eq!((rom.size().unwrap(), rom.compression()), (0x1000, None));
+        let mut data = [0; 4];
+        rom.seek(SeekFrom::Start(0x10)).unwrap();
+        rom.read_exact(&mut data).unwrap();
+        assert_eq!(data, [0xab; 4]);
+    }
+
+    #[test]
+    fn growing_a_rom() {
+        let dir = TestDir::new();
+        let path = rom(&dir);
+        let mut rom = RomWriteHandle::open_for_modification(&path, &OpenOptions::new()).unwrap();
+        rom.extend(0x3000).unwrap();
+        assert_eq!(rom.size().unwrap(), 0x3000);
+        // Never shrinks it
+        rom.extend(0x10).unwrap();
+        assert_eq!(rom.size().unwrap(), 0x3000);
+        drop(rom);
+        let data = fs::read(&path).unwrap();
+        assert!(data[..0x1000].iter().all(|&b| b == 0xab) && data[0x1000..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn compressed_roms_cant_be_changed_in_place() {
+        let dir = TestDir::new();
+        let path = dir.path().join("rom.iso.gz");
+        let mut data = GZIP_MAGIC.to_vec();
+        data.resize(0x100, 0);
+        fs::write(&path, &data).unwrap();
+        let e = RomWriteHandle::open_for_modification(&path, &OpenOptions::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(fs::read(&path).unwrap() == data);
+        assert_eq!(Compression::detect(&ZSTD_MAGIC), Some(Compression::Zstd));
+        assert_eq!(Compression::detect(b"GSLF"), None);
+    }
+
+    #[cfg(feature = "stream-compress")]
+    mod stream_compress {
+        use std::io::Write;
+
+        use super::*;
+        use crate::test_util::{build_image, synthetic_tree};
+
+        fn gzip(data: &[u8]) -> Vec<u8> {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+
+        fn zstd(data: &[u8]) -> Vec<u8> {
+            zstd::encode_all(data, 1).unwrap()
+        }
+
+        fn read_all(mut rom: RomReadHandle) -> Vec<u8> {
+            let mut data = Vec::new();
+            rom.read_to_end(&mut data).unwrap();
+            data
+        }
+
+        #[test]
+        fn compressed_roms_read_like_the_image() {
+            let image = build_image(synthetic_tree().path());
+            let dir = TestDir::new();
+            for (name, compressed, compression) in [
+                ("rom.iso.gz", gzip(&image), Compression::Gzip),
+                ("rom.iso.zst", zstd(&image), Compression::Zstd),
+            ] {
+                let path = dir.join(name);
+                fs::write(&path, &compressed).unwrap();
+                let mut progress = Vec::new();
+                let rom = RomReadHandle::open_with_progress(&path, |done, total| progress.push((done, total))).unwrap();
+                assert_eq!(rom.compression(), Some(compression));
+                assert_eq!(rom.size().unwrap(), image.len() as u64);
+                // In memory, so there's no file to give hints for
+                assert_eq!(rom.try_clone_file().unwrap_err().kind(), io::ErrorKind::Unsupported);
+                assert!(read_all(rom) == image, "{}", name);
+                // Up to all of the compressed file, and never backwards
+                assert_eq!(progress.last(), Some(&(compressed.len() as u64, compressed.len() as u64)), "{}", name);
+                assert!(progress.windows(2).all(|w| w[0].0 < w[1].0), "{:?}", progress);
+            }
+        }
+
+        // Past the memory limit, it goes to the temporary file instead
+        #[test]
+        fn big_roms_are_spooled_to_a_file() {
+            let image = build_image(synthetic_tree().path());
+            let dir = TestDir::new();
+            let path = dir.join("rom.iso.gz");
+            fs::write(&path, gzip(&image)).unwrap();
+            let mut made = 0;
+            let temp_file = || {
+                made += 1;
+                tempfile::tempfile_in(dir.path())
+            };
+            let source = spool_with(File::open(&path).unwrap(), Compression::Gzip, 0x10000, temp_file, |_, _| {}).unwrap();
+            assert_eq!(made, 1);
+            let Source::File(mut file) = source else { panic!("it's in memory") };
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            assert!(data == image);
+        }
+
+        // The files in `dir` this process has open, even without names
+        #[cfg(target_os = "linux")]
+        fn open_files_in(dir: &Path) -> usize {
+            fs::read_dir("/proc/self/fd").unwrap()
+                .filter_map(|fd| fs::read_link(fd.unwrap().path()).ok())
+                .filter(|target| target.starts_with(dir))
+                .count()
+        }
+
+        #[test]
+        fn interrupted_spools_leave_nothing_behind() {
+            let image = build_image(synthetic_tree().path());
+            let dir = TestDir::new();
+            let compressed = gzip(&image);
+            let path = dir.join("rom.iso.gz");
+            fs::write(&path, &compressed[..compressed.len() / 2]).unwrap();
+            let spools = dir.join("spools");
+            fs::create_dir(&spools).unwrap();
+            let mut made = 0;
+            let temp_file = || {
+                made += 1;
+                tempfile::tempfile_in(&spools)
+            };
+            let e = spool_with(File::open(&path).unwrap(), Compression::Gzip, 0x10000, temp_file, |_, _| {}).err().unwrap();
+            assert!(e.to_string().starts_with("Couldn't decompress the gzip ROM: "), "{}", e);
+            // It got as far as the file, which is gone now
+            assert_eq!(made, 1);
+            assert_eq!(fs::read_dir(&spools).unwrap().count(), 0);
+            #[cfg(target_os = "linux")]
+            assert_eq!(open_files_in(&spools), 0);
+
+            // And the same through opening it, in memory
+            let e = RomReadHandle::open(&path).unwrap_err();
+            assert!(e.to_string().starts_with("Couldn't decompress the gzip ROM: "), "{}", e);
+        }
+    }
+
+    #[cfg(not(feature = "stream-compress"))]
+    #[test]
+    fn compressed_roms_need_the_feature() {
+        let dir = TestDir::new();
+        let path = dir.path().join("rom.iso.zst");
+        let mut data = ZSTD_MAGIC.to_vec();
+        data.resize(0x100, 0);
+        fs::write(&path, &data).unwrap();
+        let e = RomReadHandle::open(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("zstd compressed, and this gcmod was built without the stream-compress feature"), "{}", e);
+    }
+}
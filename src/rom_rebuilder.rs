@@ -1,28 +1,235 @@
 use std::{
     cmp,
-    fs::{read_dir, File},
-    io::{self, BufReader, Read, Write},
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs::{self, read_dir, File},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
     iter,
-    path::{self, Path, PathBuf},
-    sync::OnceLock,
+    path::{Path, PathBuf, MAIN_SEPARATOR},
+    sync::{mpsc, OnceLock},
+    thread,
+    time::SystemTime,
 };
 
 use crate::{
     align,
+    build_stamp::{self, BuildStamp},
+    file_order::{rank_key, FileOrderProfile},
+    hash::Sha1,
     paths::*,
+    profiling,
+    report::{ProgressDetail, Reporter},
+    rom_handle::RomWriteHandle,
     sections::{
-        apploader::APPLOADER_OFFSET,
+        apploader::{boot_limit_problem, Apploader, APPLOADER_OFFSET},
         fst::{
-            entry::{DirectoryEntry, Entry, EntryInfo, FileEntry},
+            aligned_file_size,
+            entry::{ChildNames, DirectoryEntry, Entry, EntryInfo, FileEntry},
             FST
         },
         header::Header,
     },
     DEFAULT_ALIGNMENT,
+    DEFAULT_SYSTEM_ALIGNMENT,
+    Game,
+    MIN_ALIGNMENT,
+    ROM_SIZE,
     WRITE_CHUNK_SIZE,
 };
 
-pub const ROM_SIZE: usize = 0x57058000;
+// Filename offsets are 24 bits, so the string table can't be any bigger.
+pub const MAX_STRING_TABLE_SIZE: u64 = 1 << 24;
+// Not a limit of the format, but some games can't handle longer names.
+pub const MAX_COMPATIBLE_NAME_LEN: usize = 255;
+// Gaps between files at least this big are skipped over instead of written
+// when the ROM goes to a new file, see `ROMRebuilder::write_new_file`.
+// Smaller ones are written, so the file isn't split into lots of tiny holes.
+pub const SPARSE_GAP_THRESHOLD: u64 = 64 * 1024;
+
+pub struct RebuildOptions {
+    // What each file's offset is a multiple of.
+    pub file_alignment: u64,
+    // What the offsets of the FST, the DOL, and the start of the files are
+    // multiples of.
+    pub system_alignment: u64,
+    pub rebuild_systemdata: bool,
+    // Maps paths on the ROM (like "/audio/us") to the FST index they should
+    // end up at, usually from a manifest. The rebuild fails if the tree
+    // can't be laid out to match it exactly.
+    pub index_map: Option<HashMap<PathBuf, usize>>,
+    // Copy `user_position` and `user_length` from the existing header instead
+    // of setting them to where the files actually end up.
+    pub keep_user_fields: bool,
+    // The size of the output, usually `ROM_SIZE`.
+    pub capacity: u64,
+    // Rebuild even if the apploader and FST are too big for a real console to
+    // boot, for ROMs only meant for emulators.
+    pub ignore_boot_limits: bool,
+    // Lay files out over the area a development image's header reserves for
+    // the debug monitor, instead of around it, and take it out of the header.
+    pub ignore_debug_monitor: bool,
+    // With more than 1, source files are read on another thread while the
+    // ROM is written, with up to this many chunks in memory at once.
+    pub jobs: usize,
+    // Without `rebuild_systemdata`, files in the FST that aren't in the tree
+    // are left on the ROM as empty files instead of failing the rebuild.
+    pub prune_missing: bool,
+    // Files and directories that go on the ROM under a different name than
+    // they have in the tree, by their paths relative to the root, like
+    // "data/a.szs" to "a". The directories in the paths have their names on
+    // the ROM. Usually from a manifest, see `Manifest::renames`.
+    pub renames: HashMap<PathBuf, String>,
+    // Where the system data is in the tree. Usually from a manifest, see
+    // `Manifest::system_paths`.
+    pub system_paths: SystemPaths,
+    // Lay the files in the profile out first, in its order, then the rest
+    // in FST order, instead of all of them in FST order. It doesn't change
+    // the FST's order.
+    pub file_order: Option<FileOrderProfile>,
+    // Write a build stamp into the header saying how the ROM was built, see
+    // `build_stamp`. Without it, a stamp the tree's header already has is
+    // taken out, since it wouldn't describe this build.
+    pub stamp: bool,
+}
+
+impl Default for RebuildOptions {
+    fn default() -> RebuildOptions {
+        RebuildOptions {
+            file_alignment: DEFAULT_ALIGNMENT,
+            system_alignment: DEFAULT_SYSTEM_ALIGNMENT,
+            rebuild_systemdata: true,
+            index_map: None,
+            keep_user_fields: false,
+            capacity: ROM_SIZE as u64,
+            ignore_boot_limits: false,
+            ignore_debug_monitor: false,
+            jobs: 1,
+            prune_missing: false,
+            renames: HashMap::new(),
+            system_paths: SystemPaths::default(),
+            file_order: None,
+            stamp: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IndexConflict {
+    // In the tree, but not in the index map.
+    Added(PathBuf),
+    // In the index map, but not in the tree.
+    Removed(PathBuf),
+    // In both, but the tree's structure forces it to a different index.
+    Moved { path: PathBuf, recorded: usize, actual: usize },
+}
+
+impl fmt::Display for IndexConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexConflict::Added(p) => write!(f, "{} was added", p.display()),
+            IndexConflict::Removed(p) => write!(f, "{} was removed", p.display()),
+            IndexConflict::Moved { path, recorded, actual } =>
+                write!(f, "{} would move from index {} to {}", path.display(), recorded, actual),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexConflicts(pub Vec<IndexConflict>);
+
+impl fmt::Display for IndexConflicts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The original FST indices can't be preserved ({} conflicts):", self.0.len())?;
+        for c in &self.0 {
+            write!(f, "\n    {}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for IndexConflicts {}
+
+// Where an alignment came from, to say which one was wrong.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AlignmentSource {
+    // A command line flag, like "--file-alignment"
+    Flag(&'static str),
+    // A field of `RebuildOptions`, like "file_alignment"
+    Field(&'static str),
+}
+
+impl fmt::Display for AlignmentSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlignmentSource::Flag(flag) => write!(f, "{}", flag),
+            AlignmentSource::Field(field) => write!(f, "RebuildOptions::{}", field),
+        }
+    }
+}
+
+// An alignment that isn't a multiple of `MIN_ALIGNMENT`, which would put
+// files at offsets the disc drive can't read from.
+#[derive(Debug)]
+pub struct AlignmentError {
+    pub alignment: u64,
+    pub source: AlignmentSource,
+}
+
+impl AlignmentError {
+    pub fn check(alignment: u64, source: AlignmentSource) -> Result<(), AlignmentError> {
+        match alignment >= MIN_ALIGNMENT && alignment.is_multiple_of(MIN_ALIGNMENT) {
+            true => Ok(()),
+            false => Err(AlignmentError { alignment, source }),
+        }
+    }
+}
+
+impl fmt::Display for AlignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid alignment {} from {}. It has to be a nonzero multiple of {}, since the disc drive can't read from other offsets",
+            self.alignment, self.source, MIN_ALIGNMENT,
+        )
+    }
+}
+
+impl Error for AlignmentError {}
+
+// How the tree differs from the existing FST with `--no-rebuild-fst`, which
+// can't add or remove anything. Paths are normalized, see
+// `EntryInfo::normalized_path`.
+#[derive(Debug, Default)]
+pub struct TreeMismatch {
+    // In the FST, but not in the tree
+    pub missing: Vec<String>,
+    // In the tree, but not in the FST
+    pub extra: Vec<String>,
+}
+
+impl TreeMismatch {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl fmt::Display for TreeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The tree doesn't match the existing file system table:")?;
+        for (name, paths) in [("Missing from the tree", &self.missing), ("Not in the FST", &self.extra)] {
+            if !paths.is_empty() {
+                write!(f, "\n    {} ({}):", name, paths.len())?;
+                for p in paths {
+                    write!(f, "\n        {}", p)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for TreeMismatch {}
 
 // TODO: modify the config struct to include stuff like whether the system data should be rebuilt
 // and the paths for stuff like the dol, apploader, fst, and so on...
@@ -30,26 +237,41 @@ pub const ROM_SIZE: usize = 0x57058000;
 // Header -> apploader -> fst -> dol -> fs
 
 struct ROMConfig<'a> {
-    alignment: u64,
+    file_alignment: u64,
+    system_alignment: u64,
     root_path: &'a Path,
     files: Vec<(u64, PathBuf)>,
     space_used: Option<usize>,
+    // See `ROMRebuilder::file_system_footprint`
+    file_system_footprint: Option<u64>,
+    keep_user_fields: bool,
+    capacity: u64,
+    jobs: usize,
+    renames: &'a HashMap<PathBuf, String>,
+    system: &'a SystemPaths,
+    // See `ROMRebuilder::warnings`
+    warnings: Vec<String>,
 }
 
 struct FSTRebuilderInfo {
     entries: Vec<Entry>,
+    // With each file padded to `alignment`, which is where the next one goes
     file_system_size: u64,
+    total_file_size: usize,
     filename_offset: u64,
     file_count: usize,
     parent_index: Option<usize>,
     current_path: PathBuf,
     alignment: u64,
+    // Names some games can't handle
+    warnings: Vec<String>,
 }
 
 impl FSTRebuilderInfo {
     fn add_entry(&mut self, entry: Entry) {
         if let Some(file) = entry.as_file() {
-            self.file_system_size += align(file.size as u64, self.alignment);
+            self.file_system_size += aligned_file_size(file.size, self.alignment);
+            self.total_file_size += file.size;
             self.file_count += 1;
         }
         self.entries.push(entry);
@@ -59,28 +281,53 @@ impl FSTRebuilderInfo {
 struct FSTRebuilder<'a> {
     apploader_size: usize,
     dol_size: usize,
+    index_map: Option<&'a HashMap<PathBuf, usize>>,
+    file_order: Option<&'a FileOrderProfile>,
+    // The debug monitor's area from the tree's header, as (start, end),
+    // which nothing is laid out over
+    reserved: Option<(u64, u64)>,
+    // The header has a debug monitor, and it's being ignored
+    clear_debug_monitor: bool,
     config: ROMConfig<'a>,
 }
 
 impl<'a> FSTRebuilder<'a> {
-    fn new<P: ?Sized>(root: &'a P, alignment: u64) -> io::Result<FSTRebuilder<'a>>
+    fn new<P>(
+        root: &'a P,
+        options: &'a RebuildOptions,
+    ) -> io::Result<FSTRebuilder<'a>>
     where
-        P: AsRef<Path>,
+        P: AsRef<Path> + ?Sized,
     {
-        let apploader = File::open(root.as_ref().join(APPLOADER_PATH))?;
+        let apploader = File::open(root.as_ref().join(options.system_paths.apploader()))?;
         let apploader_size = apploader.metadata()?.len() as usize;
 
-        let dol = File::open(root.as_ref().join(DOL_PATH))?;
+        let dol = File::open(root.as_ref().join(options.system_paths.dol()))?;
         let dol_size = dol.metadata()?.len() as usize;
 
+        let header = File::open(root.as_ref().join(options.system_paths.header()))?;
+        let debug_monitor = Header::new(BufReader::new(header), 0)?.debug_monitor();
+
         Ok(FSTRebuilder {
             apploader_size,
             dol_size,
+            index_map: options.index_map.as_ref(),
+            file_order: options.file_order.as_ref(),
+            reserved: debug_monitor.filter(|_| !options.ignore_debug_monitor).map(|m| m.range()),
+            clear_debug_monitor: debug_monitor.is_some() && options.ignore_debug_monitor,
             config: ROMConfig {
-                alignment,
+                file_alignment: options.file_alignment,
+                system_alignment: options.system_alignment,
                 root_path: root.as_ref(),
                 files: vec![],
                 space_used: None,
+                file_system_footprint: None,
+                keep_user_fields: options.keep_user_fields,
+                capacity: options.capacity,
+                jobs: options.jobs,
+                renames: &options.renames,
+                system: &options.system_paths,
+                warnings: Vec::new(),
             },
         })
     }
@@ -89,7 +336,9 @@ impl<'a> FSTRebuilder<'a> {
         let root_entry = Entry::Directory(DirectoryEntry {
             info: EntryInfo {
                 index: 0,
-                name: path::MAIN_SEPARATOR.to_string(),
+                // The root's name shares offset 0 with the first entry's, so
+                // it only ends up in the string table when there are none.
+                name: String::new(),
                 filename_offset: 0,
                 directory_index: None,
                 full_path: "/".into(),
@@ -97,53 +346,149 @@ impl<'a> FSTRebuilder<'a> {
             parent_index: 0,
             next_index: 0,
             file_count: 0,
+            children: ChildNames::default(),
         });
         let mut rb_info = FSTRebuilderInfo {
             entries: Vec::new(),
             file_system_size: 0,
+            total_file_size: 0,
             filename_offset: 0,
             file_count: 0,
             parent_index: None,
             current_path: "".into(),
-            alignment: self.config.alignment,
+            alignment: self.config.file_alignment,
+            warnings: Vec::new(),
         };
 
         self.rebuild_dir_info(self.config.root_path, root_entry, &mut rb_info)?;
+        self.config.warnings.append(&mut rb_info.warnings);
+
+        if let Some(map) = self.index_map {
+            FSTRebuilder::check_indices(map, &rb_info.entries)?;
+        }
+        // The files' indices in the order their data goes in
+        let order = match self.file_order {
+            Some(profile) => {
+                let alignment = self.config.file_alignment;
+                FSTRebuilder::order_files(&mut rb_info.entries, profile, alignment, &mut self.config.warnings)
+            },
+            None => rb_info.entries.iter().filter(|e| !e.is_dir()).map(|e| e.info().index).collect(),
+        };
 
-        let size = rb_info.entries.len() * 12 + rb_info.filename_offset as usize;
-        let offset = align(APPLOADER_OFFSET + self.apploader_size as u64, self.config.alignment);
+        // An empty file system still has the root's empty name
+        let string_table_size = cmp::max(rb_info.filename_offset, 1);
+        let size = rb_info.entries.len() * 12 + string_table_size as usize;
+        let system_alignment = self.config.system_alignment;
+        let reserved = self.reserved;
+        // How much further along everything ends up for going around the
+        // debug monitor
+        let mut skipped = 0;
+        let mut place = |offset: u64, size: u64, alignment: u64| {
+            let placed = past_reserved(offset, size, reserved, alignment);
+            skipped += placed - offset;
+            placed
+        };
+        let offset = place(align(APPLOADER_OFFSET + self.apploader_size as u64, system_alignment), size as u64, system_alignment);
 
-        let dol_offset = align(offset + size as u64, self.config.alignment);
-        let file_system_offset = align(dol_offset + self.dol_size as u64, self.config.alignment);
+        let dol_offset = place(align(offset + size as u64, system_alignment), self.dol_size as u64, system_alignment);
+        let file_system_offset = place(align(dol_offset + self.dol_size as u64, system_alignment), 0, system_alignment);
+        // File offsets so far are from the first file, which still has to be
+        // on a file alignment boundary itself.
+        let first_file_offset = align(file_system_offset, self.config.file_alignment);
 
         // Move this loop/don't iteratate over all these again?
         let mut max_eof = 0;
-        for e in &mut rb_info.entries {
-            if let Some(ref mut f) = e.as_file_mut() {
-                f.file_offset += file_system_offset;
+        // Once a file goes past the debug monitor, so does every one after it
+        let mut shift = 0;
+        for i in order {
+            if let Some(f) = rb_info.entries[i].as_file_mut() {
+                let offset = f.file_offset + first_file_offset + shift;
+                f.file_offset = place(offset, f.size as u64, self.config.file_alignment);
+                shift += f.file_offset - offset;
                 max_eof = cmp::max(max_eof, f.file_offset as usize + f.size);
             }
         }
+        if let Some((start, end)) = reserved {
+            let capacity = self.config.capacity;
+            if max_eof as u64 > capacity && max_eof as u64 - skipped <= capacity {
+                self.config.warnings.push(format!(
+                    "Leaving room for the debug monitor at {:#x}-{:#x} puts the files past the end of the ROM. Pass --ignore-debug-monitor to put files there instead.",
+                    start, end,
+                ));
+            }
+        }
 
-        let fst = FST {
+        let fst = FST::with_entries(
             offset,
-            file_count: rb_info.file_count,
-            entries: rb_info.entries,
-            total_file_system_size: rb_info.file_system_size as usize,
+            rb_info.entries,
+            rb_info.file_count,
+            rb_info.total_file_size,
             size,
-        };
-        let fst_path = self.config.root_path.join(FST_PATH);
+        );
+        // What `info` says the resulting image's files take up
+        self.config.file_system_footprint = Some(fst.total_aligned_size(self.config.file_alignment));
+        let fst_path = self.config.root_path.join(self.config.system.fst());
         fst.write(File::create(fst_path)?)?;
 
         self.config.space_used = Some(max_eof);
 
+        // The user area is where the files are, from the start of the first
+        // one through the end of the last.
+        let user_end = align(max_eof as u64, self.config.file_alignment);
+        let user_area = (first_file_offset, user_end.saturating_sub(first_file_offset));
+
         Ok(HeaderRebuilder {
             dol_offset,
+            user_area,
             fst,
+            clear_debug_monitor: self.clear_debug_monitor,
             config: self.config,
         })
     }
 
+    // Gives the files new offsets from the first file, with the ones in
+    // `profile` first, in its order, and returns their indices in the order
+    // their data now goes in. Adds a warning to `warnings` about files in the
+    // profile that aren't in the tree.
+    fn order_files(
+        entries: &mut [Entry],
+        profile: &FileOrderProfile,
+        alignment: u64,
+        warnings: &mut Vec<String>,
+    ) -> Vec<usize> {
+        let ranks = profile.ranks();
+        let mut files: Vec<(usize, usize)> = entries.iter()
+            .filter_map(Entry::as_file)
+            .map(|f| {
+                let rank = ranks.get(&rank_key(&f.info.full_path.to_string_lossy())).copied();
+                (rank.unwrap_or(usize::MAX), f.info.index)
+            })
+            .collect();
+        files.sort();
+
+        let placed: HashSet<usize> = files.iter().map(|&(rank, _)| rank).collect();
+        let missing: Vec<&str> = profile.files.iter()
+            .filter(|f| !placed.contains(&ranks[&rank_key(&f.path)]))
+            .map(|f| f.path.as_str())
+            .collect();
+        if !missing.is_empty() {
+            warnings.push(format!(
+                "{} of the {} files in the file order profile aren't in the tree, like {}, so they're skipped.",
+                missing.len(), profile.files.len(), missing[0],
+            ));
+        }
+
+        let mut offset = 0;
+        files.into_iter()
+            .map(|(_, index)| {
+                let f = entries[index].as_file_mut().unwrap();
+                f.file_offset = offset;
+                offset += aligned_file_size(f.size, alignment);
+                index
+            })
+            .collect()
+    }
+
     fn rebuild_dir_info(
         &self,
         fs_path: impl AsRef<Path>,
@@ -176,26 +521,56 @@ impl<'a> FSTRebuilder<'a> {
     }
 
     fn add_entries_in_directory(&self, path: impl AsRef<Path>, rb_info: &mut FSTRebuilderInfo) -> io::Result<usize> {
+        let mut dir_entries = read_dir(path.as_ref())?.collect::<io::Result<Vec<_>>>()?;
+        if let Some(map) = self.index_map {
+            // Entries are numbered in the order they're added, so adding each
+            // directory's children in the order of their recorded indices
+            // gives back the original numbering if the tree hasn't changed.
+            dir_entries.sort_by_cached_key(|e| {
+                let path = rb_info.current_path.join(self.rom_name(&rb_info.current_path, &e.file_name().to_string_lossy()));
+                (map.get(&path).copied().unwrap_or(usize::MAX), e.file_name())
+            });
+        }
+
         let mut immediate_children_added = 0;
-        for e in read_dir(path.as_ref())? {
-            let e = e?;
+        // Name on the ROM -> name in the tree
+        let mut names = HashMap::new();
+        for e in dir_entries {
             let filename = e.file_name();
             let filename = filename.to_string_lossy();
 
-            if FSTRebuilder::is_file_ignored(&filename) {
+            let in_root = rb_info.current_path.as_os_str().is_empty();
+            if FSTRebuilder::is_file_ignored(&filename, in_root, self.config.system) {
                 continue
             }
+            let tree_name = filename;
+            let filename = self.rom_name(&rb_info.current_path, &tree_name).to_owned();
+            // Only a rename can make two entries with the same name
+            if let Some(other) = names.insert(filename.clone(), tree_name.clone().into_owned()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} and {} would both be {} on the ROM, since one was renamed when it was extracted",
+                        rb_info.current_path.join(other).display(),
+                        rb_info.current_path.join(&*tree_name).display(),
+                        rb_info.current_path.join(&filename).display(),
+                    ),
+                ));
+            }
+
+            let full_path = rb_info.current_path.join(&filename);
+            rb_info.warnings.extend(FSTRebuilder::check_name(&filename, &full_path, rb_info.filename_offset)?);
 
             let index = rb_info.entries.len();
             let info = EntryInfo {
                 index,
-                name: filename.clone().into_owned(),
+                name: filename,
                 filename_offset: rb_info.filename_offset,
                 directory_index: rb_info.parent_index,
-                full_path: rb_info.current_path.join(&*filename),
+                full_path,
             };
-            // plus 1 for the null byte
-            rb_info.filename_offset += info.name.chars().count() as u64 + 1;
+            // Names are written as UTF-8, plus 1 for the null byte
+            rb_info.filename_offset += info.name.len() as u64 + 1;
 
             if e.file_type()?.is_dir() {
                 let parent_index = info.directory_index.unwrap_or(0);
@@ -204,6 +579,7 @@ impl<'a> FSTRebuilder<'a> {
                     parent_index,
                     next_index: 0,
                     file_count: 0,
+                    children: ChildNames::default(),
                 });
                 self.rebuild_dir_info(e.path(), entry, rb_info)?;
             } else {
@@ -221,31 +597,119 @@ impl<'a> FSTRebuilder<'a> {
         Ok(immediate_children_added)
     }
 
-    fn is_file_ignored(name: &str) -> bool {
-        name.starts_with(".") || name == "&&systemdata"
+    // Since entries are added depth first, every directory's contents are
+    // contiguous no matter what order the children are added in. So the
+    // only thing left to check is that every index ended up as recorded.
+    fn check_indices(map: &HashMap<PathBuf, usize>, entries: &[Entry]) -> io::Result<()> {
+        let mut conflicts = Vec::new();
+        for e in entries {
+            let info = e.info();
+            match map.get(&info.full_path) {
+                None => conflicts.push(IndexConflict::Added(info.full_path.clone())),
+                Some(&recorded) if recorded != info.index =>
+                    conflicts.push(IndexConflict::Moved {
+                        path: info.full_path.clone(),
+                        recorded,
+                        actual: info.index,
+                    }),
+                Some(_) => {},
+            }
+        }
+
+        let paths: HashSet<&PathBuf> = entries.iter().map(|e| &e.info().full_path).collect();
+        let mut removed: Vec<_> = map.keys()
+            .filter(|p| !paths.contains(p))
+            .cloned()
+            .collect();
+        removed.sort();
+        conflicts.extend(removed.into_iter().map(IndexConflict::Removed));
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData, IndexConflicts(conflicts)))
+        }
+    }
+
+    // Makes sure `name` can be stored in the string table, which currently
+    // takes up `table_size` bytes.
+    // Fails for names the FST can't store, and gives a warning for ones
+    // some games can't handle.
+    fn check_name(name: &str, full_path: &Path, table_size: u64) -> io::Result<Option<String>> {
+        if name.bytes().any(|b| b == 0 || b == b'/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has a NUL or '/' in its name", full_path.display()),
+            ));
+        }
+
+        let warning = (name.len() > MAX_COMPATIBLE_NAME_LEN).then(|| format!(
+            "{} has a name longer than {} bytes, some games can't handle that.",
+            full_path.display(),
+            MAX_COMPATIBLE_NAME_LEN,
+        ));
+
+        let new_size = table_size + name.len() as u64 + 1;
+        if new_size > MAX_STRING_TABLE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The file names are too long to fit in the file system table, adding {} makes them take up {} bytes (the max is {})",
+                    full_path.display(),
+                    new_size,
+                    MAX_STRING_TABLE_SIZE,
+                ),
+            ));
+        }
+        Ok(warning)
+    }
+
+    // The name the file called `name` in `dir`, relative to the root, gets
+    // on the ROM.
+    fn rom_name<'n>(&'n self, dir: &Path, name: &'n str) -> &'n str {
+        self.config.renames.get(&dir.join(name)).map_or(name, String::as_str)
+    }
+
+    // Dot files, and the system data directory, whose files go on the ROM
+    // as the system data
+    fn is_file_ignored(name: &str, in_root: bool, system: &SystemPaths) -> bool {
+        name.starts_with(".") || (in_root && name == system.dir())
     }
 }
 
 struct HeaderRebuilder<'a> {
     dol_offset: u64,
+    // (user_position, user_length)
+    user_area: (u64, u64),
     fst: FST,
+    clear_debug_monitor: bool,
     config: ROMConfig<'a>,
 }
 
 impl<'a> HeaderRebuilder<'a> {
-   fn rebuild(self) -> io::Result<FileSystemRebuilder<'a>> {
-        let header_path = self.config.root_path.join(HEADER_PATH);
+   fn rebuild(mut self) -> io::Result<FileSystemRebuilder<'a>> {
+        let header_path = self.config.root_path.join(self.config.system.header());
         let header_buf = BufReader::new(File::open(&header_path)?);
         let mut header = Header::new(header_buf, 0)?;
 
-        header.dol_offset = self.dol_offset;
-        header.fst_offset = self.fst.offset;
-        header.fst_size = self.fst.size;
+        // TODO: Is this okay to assume max_fst_size = fst.size?
+        header.set_layout(self.dol_offset, self.fst.offset, self.fst.size, self.fst.size);
 
-        // TODO: Is this okay to assume?
-        header.max_fst_size = self.fst.size;
+        if !self.config.keep_user_fields {
+            let (position, length) = self.user_area;
+            header.set_user_area(position as u32, length as u32);
+        }
+        // Files can go where it was, so it can't be loaded anymore
+        if self.clear_debug_monitor {
+            self.config.warnings.push("The debug monitor is taken out of the header, since files can go where it was.".to_owned());
+            header.clear_debug_monitor();
+        }
 
-        header.write(File::create(&header_path)?)?;
+        // Anything in ISO.hdr nothing here changed stays as it was
+        let mut timer = profiling::start("header_write");
+        header.write_patched(File::create(&header_path)?)?;
+        timer.add_bytes(header.size_on_disc() as u64);
+        drop(timer);
 
         Ok(FileSystemRebuilder {
             fst: self.fst,
@@ -263,51 +727,165 @@ struct FileSystemRebuilder<'a> {
 
 impl<'a> FileSystemRebuilder<'a> {
     fn rebuild(mut self) -> io::Result<ROMRebuilder> {
-        let apploader_path = self.config.root_path.join(APPLOADER_PATH);
-        let dol_path = self.config.root_path.join(DOL_PATH);
-        let fst_path = self.config.root_path.join(FST_PATH);
-        let header_path = self.config.root_path.join(HEADER_PATH);
+        let system = self.config.system;
+        let apploader_path = self.config.root_path.join(system.apploader());
+        let dol_path = self.config.root_path.join(system.dol());
+        let fst_path = self.config.root_path.join(system.fst());
+        let header_path = self.config.root_path.join(system.header());
 
         self.config.files.push((APPLOADER_OFFSET, apploader_path));
         self.config.files.push((self.header.dol_offset, dol_path));
         self.config.files.push((self.fst.offset, fst_path));
         self.config.files.push((0, header_path));
 
-        FileSystemRebuilder::fill_files(&mut self.config.files, self.fst.entries[0].as_dir().unwrap(), self.config.root_path, &self.fst);
-
-        self.config.files.sort();
+        // The name in the tree of each renamed entry, by where it is on the
+        // ROM, relative to the root
+        let root = self.config.root_path;
+        let tree_names: HashMap<PathBuf, String> = self.config.renames.iter()
+            .filter_map(|(path, name)| Some((path.with_file_name(name), path.file_name()?.to_str()?.to_owned())))
+            .collect();
+        let mut renames = HashMap::new();
+        let mut tree = FileTree { files: &mut self.config.files, tree_names: &tree_names, renames: &mut renames };
+        tree.fill(self.fst.entries()[0].as_dir().unwrap(), Path::new(""), root, &self.fst);
 
+        // Checked in `ROMRebuilder::plan`, once pruned files are taken out
         Ok(ROMRebuilder {
             files: self.config.files,
             space_used: self.config.space_used,
+            file_system_footprint: self.config.file_system_footprint,
+            capacity: self.config.capacity,
+            jobs: self.config.jobs,
+            header: self.header,
+            root_path: self.config.root_path.to_owned(),
+            pruned: Vec::new(),
+            renames,
+            system: system.clone(),
+            warnings: self.config.warnings,
         })
     }
 
-    fn fill_files(
-        files: &mut Vec<(u64, PathBuf)>,
-        dir: &DirectoryEntry,
-        prefix: impl AsRef<Path>,
-        fst: &FST,
-    ) {
-        for entry in dir.iter_contents(&fst.entries) {
-            match entry {
-                Entry::File(ref file) => {
-                    // let offset = if file.size == 0 { 0 } else { file.file_offset };
-                    files.push((
-                        // offset,
-                        file.file_offset,
-                        prefix.as_ref().join(&file.info.name),
-                    ));
-                },
-                Entry::Directory(ref sub_dir) => {
-                    FileSystemRebuilder::fill_files(
-                        files,
-                        sub_dir,
-                        prefix.as_ref().join(&sub_dir.info.name),
-                        fst,
-                    );
+}
+
+// Where each file in the FST is in the tree, through any renamed
+// directories.
+struct FileTree<'f> {
+    files: &'f mut Vec<(u64, PathBuf)>,
+    tree_names: &'f HashMap<PathBuf, String>,
+    // The renamed entries, by where they are in the tree, for
+    // `ROMRebuilder::renames`
+    renames: &'f mut HashMap<PathBuf, String>,
+}
+
+impl FileTree<'_> {
+    // `rom_dir` is relative to the root, and `tree_dir` isn't.
+    fn fill(&mut self, dir: &DirectoryEntry, rom_dir: &Path, tree_dir: &Path, fst: &FST) {
+        for entry in dir.iter_contents(fst.entries()) {
+            let name = entry.info().name.trim_end_matches(MAIN_SEPARATOR);
+            let rom_path = rom_dir.join(name);
+            let tree_path = match self.tree_names.get(&rom_path) {
+                Some(tree_name) => {
+                    let path = tree_dir.join(tree_name);
+                    self.renames.insert(path.clone(), name.to_owned());
+                    path
                 },
+                None => tree_dir.join(name),
             };
+            match entry {
+                // Empty files keep their offsets, which `WritePlan` doesn't
+                // check
+                Entry::File(ref file) => self.files.push((file.file_offset, tree_path)),
+                Entry::Directory(ref sub_dir) => self.fill(sub_dir, &rom_path, &tree_path, fst),
+            }
+        }
+    }
+}
+
+// The files a rebuild writes, by where they go on the ROM, with the system
+// data's files too. They're written in order of offset with zeros in
+// between, so files with any bytes in them can't share an offset or
+// overlap. Empty files don't take up any space, so they're only in the
+// FST, and their offsets don't matter.
+pub(crate) struct WritePlan<'a> {
+    files: Vec<(u64, PathBuf)>,
+    root: &'a Path,
+}
+
+impl<'a> WritePlan<'a> {
+    pub(crate) fn new(files: Vec<(u64, PathBuf)>, root: &'a Path) -> WritePlan<'a> {
+        WritePlan { files, root }
+    }
+
+    // Sorts the files by offset, and checks the ones that aren't empty
+    // against each other. The sort is stable, so files at the same offset
+    // stay in the order they were added.
+    pub(crate) fn validate(mut self) -> io::Result<Vec<(u64, PathBuf)>> {
+        self.files.sort_by_key(|&(offset, _)| offset);
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        // The last file that isn't empty, and the one that ends last so
+        // far, with where it goes
+        let mut previous: Option<(&Path, u64)> = None;
+        let mut furthest: Option<(&Path, u64, u64)> = None;
+        for (offset, filename) in &self.files {
+            let size = filename.metadata()?.len();
+            if size == 0 {
+                continue;
+            }
+            match (previous, furthest) {
+                (Some((other, other_offset)), _) if other_offset == *offset =>
+                    return Err(invalid(format!(
+                        "{} and {} would both be written at {:#x}",
+                        rom_path(self.root, other), rom_path(self.root, filename), offset,
+                    ))),
+                (_, Some((other, other_offset, end))) if *offset < end =>
+                    return Err(invalid(format!(
+                        "{} would be written at {:#x}, inside of {}, which goes from {:#x} to {:#x}",
+                        rom_path(self.root, filename), offset, rom_path(self.root, other), other_offset, end,
+                    ))),
+                _ => {},
+            }
+            previous = Some((filename, *offset));
+            if furthest.is_none_or(|(_, _, end)| offset + size > end) {
+                furthest = Some((filename, *offset, offset + size));
+            }
+        }
+        Ok(self.files)
+    }
+}
+
+// What `ROMRebuilder::update` did to the existing ROM.
+#[derive(Debug)]
+pub enum UpdateOutcome {
+    Updated { files_rewritten: usize, bytes_written: u64 },
+    // Nothing was written, the reason is why the ROM needs a full rebuild.
+    LayoutChanged(String),
+}
+
+// How a ROM differs from the rebuild that was supposed to produce it, from
+// `ROMRebuilder::check_output`.
+#[derive(Debug)]
+pub enum BuildMismatch {
+    // Planned, but not in the ROM's FST.
+    Missing(String),
+    // In the ROM's FST, but not planned.
+    Unexpected(String),
+    Moved { path: String, planned: u64, actual: u64 },
+    Resized { path: String, planned: u64, actual: u64 },
+    // The bytes on the ROM aren't the source file's, starting at `offset`
+    // into the file.
+    ContentsDiffer { path: String, offset: u64 },
+}
+
+impl fmt::Display for BuildMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildMismatch::Missing(p) => write!(f, "{} is missing from the FST", p),
+            BuildMismatch::Unexpected(p) => write!(f, "{} is in the FST, but wasn't part of the build", p),
+            BuildMismatch::Moved { path, planned, actual } =>
+                write!(f, "{} is at {:#x}, but was planned for {:#x}", path, actual, planned),
+            BuildMismatch::Resized { path, planned, actual } =>
+                write!(f, "{} is {} bytes, but its source file is {}", path, actual, planned),
+            BuildMismatch::ContentsDiffer { path, offset } =>
+                write!(f, "{} differs from its source file at byte {:#x}", path, offset),
         }
     }
 }
@@ -315,95 +893,1712 @@ impl<'a> FileSystemRebuilder<'a> {
 pub struct ROMRebuilder {
     files: Vec<(u64, PathBuf)>,
     space_used: Option<usize>,
+    file_system_footprint: Option<u64>,
+    capacity: u64,
+    jobs: usize,
+    header: Header,
+    root_path: PathBuf,
+    // Files that are on the ROM empty because they weren't in the tree, see
+    // `RebuildOptions::prune_missing`. They aren't in `files`.
+    pruned: Vec<String>,
+    // The files in `files`, and the directories they're in, that have a
+    // different name on the ROM, see `RebuildOptions::renames`
+    renames: HashMap<PathBuf, String>,
+    system: SystemPaths,
+    warnings: Vec<String>,
 }
 
 impl ROMRebuilder {
-    pub fn rebuild(root: impl AsRef<Path>, alignment: u64, output: impl Write, rebuild_systemdata: bool) -> io::Result<()> {
+    // `plan` and `write`, with the plan's warnings going to `reporter`
+    pub fn rebuild(
+        root: impl AsRef<Path>,
+        options: &RebuildOptions,
+        output: impl Write,
+        reporter: &Reporter,
+    ) -> io::Result<()> {
+        let plan = ROMRebuilder::plan(root, options)?;
+        for warning in plan.warnings() {
+            reporter.warning(warning);
+        }
+        plan.write(output, reporter)
+    }
+
+    // Works out where everything goes, updating the system data in `root`,
+    // without writing the ROM itself.
+    pub fn plan(root: impl AsRef<Path>, options: &RebuildOptions) -> io::Result<ROMRebuilder> {
+        let _timer = profiling::start("layout_plan");
         let root = root.as_ref();
-        if rebuild_systemdata {
-            FSTRebuilder::new(root, alignment)?
+        let mut warnings = Vec::new();
+        let alignments = [
+            ("file", options.file_alignment, "file_alignment"),
+            ("system", options.system_alignment, "system_alignment"),
+        ];
+        for (name, alignment, field) in alignments {
+            AlignmentError::check(alignment, AlignmentSource::Field(field))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            // Multiples of 4 work, but the padding they leave is uneven
+            if !alignment.is_power_of_two() {
+                warnings.push(format!(
+                    "The {} alignment {} isn't a power of two, so how much padding each file gets varies, and the ROM can take up more space than you'd expect.",
+                    name, alignment,
+                ));
+            }
+        }
+        let mut rebuilder = if options.rebuild_systemdata {
+            FSTRebuilder::new(root, options)?
                 .rebuild()?
                 .rebuild()?
                 .rebuild()?
-                .write(output)
         } else {
-            let fst_file = File::open(root.join(FST_PATH))?;
-            let header_file = File::open(root.join(HEADER_PATH))?;
+            let fst_file = File::open(root.join(options.system_paths.fst()))?;
+            let fst_size = fst_file.metadata()?.len() as usize;
+            let header_file = File::open(root.join(options.system_paths.header()))?;
 
-            let mut fst = FST::new(BufReader::new(fst_file), 0)?;
+            let mut fst = FST::open(BufReader::new(fst_file), 0, fst_size)?;
             let header = Header::new(BufReader::new(header_file), 0)?;
             fst.offset = header.fst_offset;
+            let pruned = reconcile(&mut fst, root, options)?;
+            warnings.extend(pruned.iter().map(|path| format!("{} isn't in the tree, so it's left empty", path)));
 
             FileSystemRebuilder {
                 fst,
                 header,
                 config: ROMConfig {
-                    alignment,
+                    file_alignment: options.file_alignment,
+                    system_alignment: options.system_alignment,
                     root_path: root,
                     files: vec![],
                     space_used: None,
+                    file_system_footprint: None,
+                    keep_user_fields: true,
+                    capacity: options.capacity,
+                    jobs: options.jobs,
+                    renames: &options.renames,
+                    system: &options.system_paths,
+                    warnings: Vec::new(),
                 }
-            }.rebuild()?.write(output)
+            }.rebuild()?.without(pruned)
+        };
+        // In the order they came up
+        warnings.append(&mut rebuilder.warnings);
+        rebuilder.warnings = warnings;
+        // Pruned files aren't in the tree, so they can't be checked
+        let files = std::mem::take(&mut rebuilder.files);
+        rebuilder.files = WritePlan::new(files, root).validate()?;
+
+        if let Some(p) = rebuilder.header.alignment_problem() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, p));
+        }
+        let apploader = Apploader::new(File::open(root.join(options.system_paths.apploader()))?, 0)?;
+        match boot_limit_problem(apploader.total_size(), rebuilder.header.max_fst_size) {
+            Some(p) if p.is_error() && !options.ignore_boot_limits =>
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, p)),
+            Some(p) => rebuilder.warnings.push(p.to_string()),
+            None => {},
+        }
+        rebuilder.stamp_header(options)?;
+        Ok(rebuilder)
+    }
+
+    // Writes a build stamp into the tree's header with `options.stamp`, or
+    // takes out an old one without it.
+    fn stamp_header(&mut self, options: &RebuildOptions) -> io::Result<()> {
+        let header_path = self.root_path.join(self.system.header());
+        let mut bytes = fs::read(&header_path)?;
+        if options.stamp {
+            let stamp = BuildStamp::new(self.tree_hash()?, build_stamp::options_hash(options));
+            build_stamp::write(&mut bytes, &stamp).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        } else if build_stamp::clear(&mut bytes) {
+            self.warnings.push("The header's build stamp was taken out, since it wouldn't describe this build. `--stamp` writes a new one.".to_owned());
+        } else {
+            return Ok(());
+        }
+        fs::write(&header_path, &bytes)?;
+        self.header = Header::parse_from_bytes(&bytes)?;
+        Ok(())
+    }
+
+    // What `build_stamp::tree_hash` gives for the files this puts on the
+    // ROM, which is what `Game::tree_hash` gives for the ROM once it's
+    // written.
+    pub fn tree_hash(&self) -> io::Result<[u8; 20]> {
+        let mut files = Vec::with_capacity(self.files.len() + self.pruned.len());
+        for (_, filename) in &self.files {
+            if self.system.is_system_file(&self.root_path, filename) { continue }
+            let size = filename.metadata()?.len();
+            files.push((self.rom_path(filename), size, Some(filename.as_path())));
+        }
+        files.extend(self.pruned.iter().map(|path| (path.clone(), 0, None)));
+        build_stamp::tree_hash(files, |filename, out| match filename {
+            Some(filename) => io::copy(&mut File::open(filename)?, out),
+            None => Ok(0),
+        })
+    }
+
+    // The number of files that go on the ROM, including the system data.
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    // Brings `iso`, a ROM rebuilt from the same tree before, up to date by
+    // only writing the system data and the files modified after `since`. If
+    // any file would move or change size, nothing is written. Only a locked
+    // `RomWriteHandle` gets written to in place.
+    pub fn update(&self, iso: &mut RomWriteHandle, since: SystemTime) -> io::Result<UpdateOutcome> {
+        self.update_in(iso, since)
+    }
+
+    // `update`, for anything that can be written to, like a test's buffer.
+    fn update_in(&self, mut iso: impl Read + Write + Seek, since: SystemTime) -> io::Result<UpdateOutcome> {
+        if let Some(reason) = self.layout_change(&mut iso)? {
+            return Ok(UpdateOutcome::LayoutChanged(reason));
+        }
+
+        let mut files_rewritten = 0;
+        let mut bytes_written = 0;
+        for (i, &(offset, ref filename)) in self.files.iter().enumerate() {
+            let file = File::open(filename)?;
+            let metadata = file.metadata()?;
+            let size = metadata.len();
+            if size == 0 { continue }
+
+            // The system data can change size without moving anything, so
+            // the padding after it has to be cleared too.
+            let is_system_file = self.system.is_system_file(&self.root_path, filename);
+            if !is_system_file && metadata.modified()? <= since { continue }
+
+            let mut timer = profiling::start(profiling::copy_operation(size));
+            iso.seek(SeekFrom::Start(offset))?;
+            timer.add_bytes(io::copy(&mut file.take(size), &mut iso)?);
+            drop(timer);
+            bytes_written += size;
+            if is_system_file {
+                let next = self.files[i + 1..].iter()
+                    .map(|&(o, _)| o)
+                    .find(|&o| o >= offset + size)
+                    .unwrap_or(self.capacity);
+                write_zeros((next - offset - size) as usize, &mut iso)?;
+                bytes_written += next - offset - size;
+            }
+            files_rewritten += 1;
+        }
+        iso.flush()?;
+
+        Ok(UpdateOutcome::Updated { files_rewritten, bytes_written })
+    }
+
+    // Why the files in `iso` aren't where this rebuild would put them, if
+    // they aren't.
+    fn layout_change(&self, mut iso: impl Read + Seek) -> io::Result<Option<String>> {
+        if iso.seek(SeekFrom::End(0))? != self.capacity {
+            return Ok(Some("The existing ROM is a different size".to_owned()));
+        }
+        let old = match Game::open(BufReader::new(&mut iso), 0) {
+            Ok(game) => game,
+            Err(e) => return Ok(Some(format!("The existing ROM couldn't be read ({})", e))),
+        };
+
+        if old.header.dol_offset != self.header.dol_offset {
+            return Ok(Some("The DOL moved".to_owned()));
+        }
+        if old.header.fst_offset != self.header.fst_offset {
+            return Ok(Some("The file system table moved".to_owned()));
+        }
+
+        let mut old_files: Vec<_> = old.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| (f.file_offset, f.size as u64, f.info.normalized_path()))
+            .collect();
+        let mut new_files = self.planned_files()?;
+        old_files.sort();
+        new_files.sort();
+
+        Ok((old_files != new_files).then(|| "Files were added, removed, or resized".to_owned()))
+    }
+
+    // The offset, size, and path on the ROM of each file in the file system,
+    // leaving out the system data.
+    fn planned_files(&self) -> io::Result<Vec<(u64, u64, String)>> {
+        let mut files = Vec::with_capacity(self.files.len());
+        for (offset, filename) in &self.files {
+            if self.system.is_system_file(&self.root_path, filename) { continue }
+            let size = filename.metadata()?.len();
+            files.push((*offset, size, self.rom_path(filename)));
+        }
+        Ok(files)
+    }
+
+    // Like `planned_files`, but with the system data too, which is in the
+    // system data directory, like "/&&systemdata".
+    pub fn written_files(&self) -> io::Result<Vec<(u64, u64, String)>> {
+        self.files.iter()
+            .map(|(offset, filename)| Ok((*offset, filename.metadata()?.len(), self.rom_path(filename))))
+            .collect()
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    // What planning worked around or changed that whoever asked for it
+    // should hear about, like a long name or the debug monitor being taken
+    // out of the header. `rebuild` sends them to its reporter.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // What the files take up with their padding, if the FST was rebuilt,
+    // which is what `FST::total_aligned_size` gives for the new image at the
+    // same file alignment.
+    pub fn file_system_footprint(&self) -> Option<u64> {
+        self.file_system_footprint
+    }
+
+    // For `RebuildPlan`, which records what's planned and builds it again
+    // somewhere else.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_parts(
+        files: Vec<(u64, PathBuf)>,
+        space_used: Option<usize>,
+        capacity: u64,
+        jobs: usize,
+        header: Header,
+        root_path: PathBuf,
+        pruned: Vec<String>,
+    ) -> ROMRebuilder {
+        ROMRebuilder {
+            files,
+            space_used,
+            file_system_footprint: None,
+            capacity,
+            jobs,
+            header,
+            root_path,
+            pruned,
+            renames: HashMap::new(),
+            system: SystemPaths::default(),
+            warnings: Vec::new(),
+        }
+    }
+
+    // Leaves `pruned`, paths on the ROM, out of the files to write.
+    pub(crate) fn without(mut self, pruned: Vec<String>) -> ROMRebuilder {
+        let files = std::mem::take(&mut self.files);
+        self.files = files.into_iter()
+            .filter(|(_, filename)| !pruned.contains(&self.rom_path(filename)))
+            .collect();
+        self.pruned = pruned;
+        self
+    }
+
+    // Gives the files in `renames`, by where they are in the tree, a
+    // different name on the ROM.
+    #[cfg(feature = "serde")]
+    pub(crate) fn with_renames(mut self, renames: HashMap<PathBuf, String>) -> ROMRebuilder {
+        self.renames = renames;
+        self
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn with_system_paths(mut self, system: SystemPaths) -> ROMRebuilder {
+        self.system = system;
+        self
+    }
+
+    pub fn system_paths(&self) -> &SystemPaths {
+        &self.system
+    }
+
+    // The name `filename`, one of `files`, has on the ROM, if it isn't its
+    // own.
+    #[cfg(feature = "serde")]
+    pub(crate) fn rom_name(&self, filename: &Path) -> Option<&str> {
+        self.renames.get(filename).map(String::as_str)
+    }
+
+    // Everything in the tree with a different name on the ROM, including
+    // directories.
+    #[cfg(feature = "serde")]
+    pub(crate) fn renames(&self) -> &HashMap<PathBuf, String> {
+        &self.renames
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn pruned(&self) -> &[String] {
+        &self.pruned
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn files(&self) -> &[(u64, PathBuf)] {
+        &self.files
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn space_used(&self) -> Option<usize> {
+        self.space_used
+    }
+
+    // Where `filename`, one of `files`, is on the ROM, like "/audio/us/a.adp".
+    // Any of the directories it's in could have been renamed too.
+    fn rom_path(&self, filename: &Path) -> String {
+        let Ok(relative) = filename.strip_prefix(&self.root_path) else {
+            return rom_path(&self.root_path, filename);
+        };
+        let mut tree_path = self.root_path.clone();
+        relative.iter()
+            .map(|c| {
+                tree_path.push(c);
+                match self.renames.get(&tree_path) {
+                    Some(name) => format!("/{}", name),
+                    None => format!("/{}", c.to_string_lossy()),
+                }
+            })
+            .collect()
+    }
+
+    // Compares `game`, opened from `iso` after writing it, against what was
+    // planned: every planned file has to be in its FST at the same offset
+    // and size, and nothing else can be. With `contents`, every file on the
+    // ROM, including the system data, is also compared to its source file.
+    pub fn check_output(
+        &self,
+        game: &Game,
+        mut iso: impl Read + Seek,
+        contents: bool,
+        reporter: &Reporter,
+    ) -> io::Result<Vec<BuildMismatch>> {
+        let mut mismatches = Vec::new();
+        let mut actual: HashMap<String, &FileEntry> = game.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .map(|f| (f.info.normalized_path(), f))
+            .collect();
+        for (offset, size, path) in self.planned_files()? {
+            match actual.remove(&path) {
+                None => mismatches.push(BuildMismatch::Missing(path)),
+                Some(f) if f.file_offset != offset =>
+                    mismatches.push(BuildMismatch::Moved { path, planned: offset, actual: f.file_offset }),
+                Some(f) if f.size as u64 != size =>
+                    mismatches.push(BuildMismatch::Resized { path, planned: size, actual: f.size as u64 }),
+                Some(_) => {},
+            }
+        }
+        for path in &self.pruned {
+            match actual.remove(path) {
+                None => mismatches.push(BuildMismatch::Missing(path.clone())),
+                Some(f) if f.size != 0 =>
+                    mismatches.push(BuildMismatch::Resized { path: path.clone(), planned: 0, actual: f.size as u64 }),
+                Some(_) => {},
+            }
+        }
+        let mut unexpected: Vec<_> = actual.into_keys().collect();
+        unexpected.sort();
+        mismatches.extend(unexpected.into_iter().map(BuildMismatch::Unexpected));
+
+        if contents {
+            let total = self.files.len();
+            for (i, (offset, filename)) in self.files.iter().enumerate() {
+                if let Some(at) = first_difference(&mut iso, *offset, File::open(filename)?)? {
+                    mismatches.push(BuildMismatch::ContentsDiffer { path: self.rom_path(filename), offset: at });
+                }
+                reporter.progress((i + 1) as u64, total as u64, format!("{}/{} files checked.", i + 1, total));
+            }
+            reporter.finish_progress();
         }
+        Ok(mismatches)
     }
 
-    fn write(
+    pub fn write(
         &self,
-        mut output: impl Write,
+        output: impl Write,
+        reporter: &Reporter,
     ) -> io::Result<()> {
+        self.write_to(Zeroed(output), reporter)
+    }
+
+    // Like `write`, but into `file`, which has to be empty, like one that was
+    // just created. Past what's written, an empty file reads as zeros, so big
+    // gaps are left as holes instead of written, and take no space on file
+    // systems with sparse files. Anything else gets every gap written like
+    // `write` does. With `hash`, returns the SHA-1 of the ROM.
+    pub fn write_new_file(&self, file: &mut File, hash: bool, reporter: &Reporter) -> io::Result<Option<[u8; 20]>> {
+        let metadata = file.metadata()?;
+        let sparse = metadata.is_file() && metadata.len() == 0 && file.stream_position()? == 0;
+        let mut output = NewFile { file, sparse, sha1: hash.then(Sha1::new) };
+        self.write_to(&mut output, reporter)?;
+        Ok(output.sha1.map(Sha1::finish))
+    }
+
+    fn write_to(&self, mut output: impl RomOutput, reporter: &Reporter) -> io::Result<()> {
+        reporter.sink().totals(self.files.len() as u64, None);
+        if self.jobs > 1 {
+            return self.write_pipelined(output, reporter);
+        }
+
         let mut bytes_written = 0;
-        let total_files = self.files.len();
 
         for (i, &(offset, ref filename)) in self.files.iter().enumerate() {
+            self.file_started(i, reporter);
             let file = File::open(filename)?;
             let size = file.metadata()?.len();
 
-            if size == 0 { continue }
+            if size == 0 {
+                self.file_written(i, bytes_written, reporter);
+                continue
+            }
 
-            write_zeros((offset - bytes_written) as usize, &mut output)?;
+            output.write_gap(offset - bytes_written)?;
             bytes_written = offset;
 
-            io::copy(
+            let mut timer = profiling::start(profiling::copy_operation(size));
+            timer.add_bytes(io::copy(
                 &mut file.take(size),
                 &mut output,
-            ).map(drop)?;
+            )?);
+            drop(timer);
             bytes_written += size;
+            reporter.sink().bytes_written(size);
 
-            if bytes_written as usize > ROM_SIZE {
-                println!();
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Error: not enough space. Try decreasing the file alignment with the -a option (the default is {} bytes).",
-                        DEFAULT_ALIGNMENT,
-                    ),
-                ));
+            if bytes_written > self.capacity {
+                reporter.finish_progress();
+                return Err(not_enough_space());
             }
-            print!("\r{}/{} files added.", i + 1, total_files);
+            self.file_written(i, bytes_written, reporter);
         }
-        println!();
-        write_zeros(ROM_SIZE - bytes_written as usize, &mut output)?;
+        self.finish_write(bytes_written, output, reporter)
+    }
+
+    // Like `write`, but the source files are read on another thread, so
+    // reading the next chunk overlaps with writing the last one. The output
+    // is exactly the same.
+    fn write_pipelined(&self, mut output: impl RomOutput, reporter: &Reporter) -> io::Result<()> {
+        thread::scope(|s| {
+            let (full_tx, full_rx) = mpsc::sync_channel(self.jobs);
+            // The buffers go back and forth, so there are never more than
+            // `jobs` of them.
+            let (empty_tx, empty_rx) = mpsc::channel();
+            for _ in 0..self.jobs {
+                let _ = empty_tx.send(Vec::with_capacity(WRITE_CHUNK_SIZE));
+            }
+            s.spawn(|| read_chunks(&self.files, empty_rx, full_tx));
+
+            let mut bytes_written = 0;
+            // Empty files don't have any chunks, so they're reported with
+            // the next file that does
+            let mut reported = 0;
+            // From a file's first chunk to its last, which includes any
+            // waiting on the reader, since that's part of copying it too
+            let mut timer = None;
+            for chunk in full_rx {
+                let chunk = chunk.inspect_err(|_| reporter.finish_progress())?;
+                if chunk.first {
+                    for i in reported..=chunk.index {
+                        self.file_started(i, reporter);
+                    }
+                    output.write_gap(chunk.offset - bytes_written)?;
+                    bytes_written = chunk.offset;
+                    timer = Some(profiling::start(profiling::copy_operation(chunk.size)));
+                }
+                output.write_all(&chunk.data)?;
+                bytes_written += chunk.data.len() as u64;
+                reporter.sink().bytes_written(chunk.data.len() as u64);
+                if let Some(t) = &mut timer {
+                    t.add_bytes(chunk.data.len() as u64);
+                }
+
+                if chunk.last {
+                    timer = None;
+                    if bytes_written > self.capacity {
+                        reporter.finish_progress();
+                        return Err(not_enough_space());
+                    }
+                    for i in reported..=chunk.index {
+                        self.file_written(i, bytes_written, reporter);
+                    }
+                    reported = chunk.index + 1;
+                }
+                // The reader might already be done
+                let _ = empty_tx.send(chunk.data);
+            }
+            for i in reported..self.files.len() {
+                self.file_started(i, reporter);
+                self.file_written(i, bytes_written, reporter);
+            }
+            self.finish_write(bytes_written, output, reporter)
+        })
+    }
+
+    fn file_started(&self, i: usize, reporter: &Reporter) {
+        reporter.sink().file_started(i as u64, &self.rom_path(&self.files[i].1));
+    }
+
+    // Reports `self.files[i]` written, with the ROM written up to
+    // `bytes_written`.
+    fn file_written(&self, i: usize, bytes_written: u64, reporter: &Reporter) {
+        let total = self.files.len();
+        let path = self.rom_path(&self.files[i].1);
+        reporter.sink().file_finished(i as u64, &path);
+        let detail = ProgressDetail {
+            bytes: Some(bytes_written),
+            // The system data is past the end of an empty file system
+            total_bytes: self.space_used.map(|s| (s as u64).max(bytes_written)),
+            path: Some(&path),
+        };
+        reporter.progress_detail((i + 1) as u64, total as u64, detail, format!("{}/{} files added.", i + 1, total));
+    }
+
+    fn finish_write(&self, bytes_written: u64, mut output: impl RomOutput, reporter: &Reporter) -> io::Result<()> {
+        reporter.finish_progress();
+        output.write_gap(self.capacity - bytes_written)?;
+        output.finish()?;
 
         if let Some(space) = self.space_used {
-            let percent_used = ((space as f64 / ROM_SIZE as f64) * 100.0) as usize;
-            println!("{:2}% of space filled ({}/{} bytes).", percent_used, space, ROM_SIZE);
+            let percent_used = ((space as f64 / self.capacity as f64) * 100.0) as usize;
+            reporter.status(format!("{:2}% of space filled ({}/{} bytes).", percent_used, space, self.capacity));
         }
 
         Ok(())
     }
 }
 
-fn write_zeros(mut remaining: usize, mut output: impl Write) -> io::Result<()> {
-    static ZEROS: OnceLock<Box<[u8]>> = OnceLock::new();
-    let zeros = ZEROS.get_or_init(||
-        iter::repeat(0).take(WRITE_CHUNK_SIZE).collect()
-    );
+// Where `ROMRebuilder::write_to` writes the ROM.
+trait RomOutput: Write {
+    // Writes `len` zeros, or leaves them to be zeros.
+    fn write_gap(&mut self, len: u64) -> io::Result<()>;
 
-    while remaining > 0 {
-        let count = cmp::min(WRITE_CHUNK_SIZE, remaining);
-        output.write_all(&zeros[..count])?;
-        remaining -= count;
+    // Called once everything's written.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<O: RomOutput> RomOutput for &mut O {
+    fn write_gap(&mut self, len: u64) -> io::Result<()> {
+        (**self).write_gap(len)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+// Writes every gap out.
+struct Zeroed<W>(W);
+
+impl<W: Write> Write for Zeroed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> RomOutput for Zeroed<W> {
+    fn write_gap(&mut self, len: u64) -> io::Result<()> {
+        write_zeros(len as usize, &mut self.0)
+    }
+}
+
+// A file for `ROMRebuilder::write_new_file`, keeping a SHA-1 of what's
+// written. With `sparse`, big gaps are seeked over, and the zeros only go
+// into the SHA-1.
+struct NewFile<'a> {
+    file: &'a mut File,
+    sparse: bool,
+    sha1: Option<Sha1>,
+}
+
+impl Write for NewFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        if let Some(sha1) = &mut self.sha1 {
+            sha1.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl RomOutput for NewFile<'_> {
+    fn write_gap(&mut self, len: u64) -> io::Result<()> {
+        if !self.sparse || len < SPARSE_GAP_THRESHOLD {
+            return write_zeros(len as usize, self);
+        }
+        self.file.seek(SeekFrom::Current(len as i64))?;
+        if let Some(sha1) = &mut self.sha1 {
+            write_zeros(len as usize, Sha1Sink(sha1))?;
+        }
+        Ok(())
+    }
+
+    // Seeking past the end doesn't make the file any longer, so a ROM
+    // that ends in a skipped gap needs its length set.
+    fn finish(&mut self) -> io::Result<()> {
+        let end = self.file.stream_position()?;
+        if self.file.metadata()?.len() < end {
+            self.file.set_len(end)?;
+        }
+        Ok(())
+    }
+}
+
+struct Sha1Sink<'a>(&'a mut Sha1);
+
+impl Write for Sha1Sink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Part of a file for `write_pipelined`.
+struct Chunk {
+    // The file's place in `ROMRebuilder::files`
+    index: usize,
+    // Where the file starts on the ROM
+    offset: u64,
+    // How big the whole file is
+    size: u64,
+    first: bool,
+    last: bool,
+    data: Vec<u8>,
+}
+
+// Reads `files` in order into buffers from `empty`, sending them to `full`.
+// Stops at the first error, or once the writer hangs up.
+fn read_chunks(
+    files: &[(u64, PathBuf)],
+    empty: mpsc::Receiver<Vec<u8>>,
+    full: mpsc::SyncSender<io::Result<Chunk>>,
+) {
+    let read_file = |index: usize, offset: u64, filename: &Path| -> io::Result<bool> {
+        let mut file = File::open(filename)?;
+        let size = file.metadata()?.len();
+        let mut remaining = size;
+        while remaining > 0 {
+            let Ok(mut data) = empty.recv() else { return Ok(false) };
+            let n = cmp::min(remaining, WRITE_CHUNK_SIZE as u64) as usize;
+            data.resize(n, 0);
+            file.read_exact(&mut data)?;
+            let chunk = Chunk {
+                index,
+                offset,
+                size,
+                first: remaining == size,
+                last: remaining == n as u64,
+                data,
+            };
+            remaining -= n as u64;
+            if full.send(Ok(chunk)).is_err() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
+
+    for (index, (offset, filename)) in files.iter().enumerate() {
+        match read_file(index, *offset, filename) {
+            Ok(true) => {},
+            Ok(false) => return,
+            Err(e) => {
+                let _ = full.send(Err(e));
+                return;
+            },
+        }
+    }
+}
+
+fn rom_path(root_path: &Path, filename: &Path) -> String {
+    let relative = filename.strip_prefix(root_path).unwrap_or(filename);
+    relative.iter()
+        .map(|c| format!("/{}", c.to_string_lossy()))
+        .collect()
+}
+
+// Checks that `fst`, the tree's existing FST, has exactly what's in the
+// tree. With `prune_missing`, files that aren't in the tree are emptied
+// instead, and the FST is written back to the tree with them that way.
+// Returns the files that were.
+fn reconcile(fst: &mut FST, root: &Path, options: &RebuildOptions) -> io::Result<Vec<String>> {
+    let mut in_tree = HashSet::new();
+    tree_paths(root, "", options, &mut in_tree)?;
+
+    let mut mismatch = TreeMismatch::default();
+    let mut in_fst = HashSet::new();
+    for e in &fst.entries()[1..] {
+        let path = e.info().normalized_path();
+        if !in_tree.contains(&(path.clone(), e.is_dir())) {
+            mismatch.missing.push(path.clone());
+        }
+        in_fst.insert((path, e.is_dir()));
+    }
+    mismatch.extra = in_tree.into_iter()
+        .filter(|p| !in_fst.contains(p))
+        .map(|(path, _)| path)
+        .collect();
+    mismatch.extra.sort();
+
+    // Only files are emptied. A missing directory stays an (empty)
+    // directory on the ROM, which is fine.
+    if options.prune_missing && mismatch.extra.is_empty() && !mismatch.missing.is_empty() {
+        let missing: HashSet<_> = mismatch.missing.into_iter().collect();
+        let mut pruned = Vec::new();
+        let mut pruned_size = 0;
+        for e in fst.entries_mut() {
+            let path = e.info().normalized_path();
+            if let Entry::File(f) = e {
+                if missing.contains(&path) {
+                    pruned_size += f.size;
+                    f.size = 0;
+                    pruned.push(path);
+                }
+            }
+        }
+        fst.total_file_size -= pruned_size;
+        fst.write(File::create(root.join(options.system_paths.fst()))?)?;
+        return Ok(pruned);
+    }
+    if mismatch.is_empty() {
+        Ok(Vec::new())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, mismatch))
+    }
+}
+
+// Adds the normalized path of everything in `tree_dir` that rebuilding
+// would put on the ROM to `paths`, with whether it's a directory. `dir` is
+// where `tree_dir` is on the ROM, which is different if it was renamed.
+fn tree_paths(
+    tree_dir: &Path,
+    dir: &str,
+    options: &RebuildOptions,
+    paths: &mut HashSet<(String, bool)>,
+) -> io::Result<()> {
+    for e in read_dir(tree_dir)? {
+        let e = e?;
+        let name = e.file_name();
+        let name = name.to_string_lossy();
+        if FSTRebuilder::is_file_ignored(&name, dir.is_empty(), &options.system_paths) {
+            continue
+        }
+        let name = options.renames.get(&Path::new(dir.trim_start_matches('/')).join(&*name))
+            .map_or(name, |n| n.as_str().into());
+        let path = format!("{}/{}", dir, name);
+        let is_dir = e.file_type()?.is_dir();
+        if is_dir {
+            tree_paths(&e.path(), &path, options, paths)?;
+        }
+        paths.insert((path, is_dir));
+    }
+    Ok(())
+}
+
+// Where something `size` bytes long that would go at `offset` goes instead,
+// which is the first multiple of `alignment` after the debug monitor if it
+// would overlap it. Anything empty is moved if it'd start inside of it.
+fn past_reserved(offset: u64, size: u64, reserved: Option<(u64, u64)>, alignment: u64) -> u64 {
+    match reserved {
+        Some((start, end)) if offset < end && offset + size.max(1) > start => align(end, alignment),
+        _ => offset,
+    }
+}
+
+fn not_enough_space() -> io::Error {
+    io::Error::other(
+        format!(
+            "Error: not enough space. Try decreasing the file alignment with the --file-alignment option (the default is {} bytes).",
+            DEFAULT_ALIGNMENT,
+        ),
+    )
+}
+
+// Where the bytes at `offset` in `iso` first differ from `file`, if they do.
+fn first_difference(mut iso: impl Read + Seek, offset: u64, mut file: impl Read) -> io::Result<Option<u64>> {
+    let mut expected = vec![0; WRITE_CHUNK_SIZE];
+    let mut actual = vec![0; WRITE_CHUNK_SIZE];
+    iso.seek(SeekFrom::Start(offset))?;
+    let mut done = 0;
+    loop {
+        let n = file.read(&mut expected)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if let Err(e) = iso.read_exact(&mut actual[..n]) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(Some(done)),
+                _ => Err(e),
+            };
+        }
+        if let Some(i) = (0..n).find(|&i| expected[i] != actual[i]) {
+            return Ok(Some(done + i as u64));
+        }
+        done += n as u64;
+    }
+}
+
+fn write_zeros(mut remaining: usize, mut output: impl Write) -> io::Result<()> {
+    if remaining == 0 {
+        return Ok(());
+    }
+    let mut timer = profiling::start("zero_fill");
+    timer.add_bytes(remaining as u64);
+    static ZEROS: OnceLock<Box<[u8]>> = OnceLock::new();
+    let zeros = ZEROS.get_or_init(||
+        iter::repeat_n(0, WRITE_CHUNK_SIZE).collect()
+    );
+
+    while remaining > 0 {
+        let count = cmp::min(WRITE_CHUNK_SIZE, remaining);
+        output.write_all(&zeros[..count])?;
+        remaining -= count;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{manifest::Manifest, paths::HEADER_PATH, synthetic, test_util::{synthetic_tree, RecordingSink, TestDir}};
+
+    fn options() -> RebuildOptions {
+        RebuildOptions { capacity: synthetic::CAPACITY, ..RebuildOptions::default() }
+    }
+
+    fn build(tree: &Path, options: &RebuildOptions) -> io::Result<Game> {
+        let mut image = Vec::new();
+        ROMRebuilder::rebuild(tree, options, &mut image, &Reporter::quiet())?;
+        Game::open(Cursor::new(image), 0)
+    }
+
+    fn with_map(map: HashMap<PathBuf, usize>) -> RebuildOptions {
+        RebuildOptions { index_map: Some(map), ..options() }
+    }
+
+    fn conflicts(e: io::Error) -> Vec<IndexConflict> {
+        let e = e.into_inner().unwrap();
+        e.downcast::<IndexConflicts>().unwrap().0
+    }
+
+    // Every directory's descendants are right after it and before its
+    // `next_index`.
+    fn assert_contiguous(fst: &FST) {
+        for e in &fst.entries()[1..] {
+            let mut child = e.info().index;
+            while let Some(parent) = fst.entries()[child].info().directory_index {
+                let dir = fst.entries()[parent].as_dir().unwrap();
+                assert!(parent < e.info().index && e.info().index < dir.next_index, "{}", e.info().full_path.display());
+                child = parent;
+            }
+        }
+    }
+
+    #[test]
+    fn preserved_indices_come_out_the_same() {
+        let tree = synthetic_tree();
+        let original = build(tree.path(), &options()).unwrap();
+        let map = Manifest::new(&original).index_map();
+        let rebuilt = build(tree.path(), &with_map(map.clone())).unwrap();
+        assert_eq!(Manifest::new(&rebuilt).index_map(), map);
+        assert_contiguous(&rebuilt.fst);
+    }
+
+    #[test]
+    fn siblings_are_ordered_by_the_map() {
+        let tree = synthetic_tree();
+        let mut map = Manifest::new(&build(tree.path(), &options()).unwrap()).index_map();
+        let (a, b) = (PathBuf::from("data/levels/1.bin"), PathBuf::from("data/levels/2.bin"));
+        let (ia, ib) = (map[&a], map[&b]);
+        map.insert(a, ib);
+        map.insert(b, ia);
+
+        let rebuilt = build(tree.path(), &with_map(map.clone())).unwrap();
+        assert_eq!(Manifest::new(&rebuilt).index_map(), map);
+        assert_contiguous(&rebuilt.fst);
+    }
+
+    #[test]
+    fn changed_trees_are_reported() {
+        let tree = synthetic_tree();
+        let map = Manifest::new(&build(tree.path(), &options()).unwrap()).index_map();
+        fs::write(tree.path().join("data/new.bin"), "new").unwrap();
+        fs::remove_file(tree.path().join("readme.txt")).unwrap();
+
+        let e = build(tree.path(), &with_map(map)).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let conflicts = conflicts(e);
+        assert!(conflicts.iter().any(|c| matches!(c, IndexConflict::Added(p) if p == Path::new("data/new.bin"))), "{:?}", conflicts);
+        assert!(conflicts.iter().any(|c| matches!(c, IndexConflict::Removed(p) if p == Path::new("readme.txt"))), "{:?}", conflicts);
+    }
+
+    #[test]
+    fn indices_outside_the_directory_conflict() {
+        let tree = synthetic_tree();
+        let mut map = Manifest::new(&build(tree.path(), &options()).unwrap()).index_map();
+        // readme.txt is in the root, but this index is in /data's span, so
+        // the best it can do is come after all of /data
+        let levels = map[Path::new("data/levels")];
+        map.insert("readme.txt".into(), levels);
+
+        let conflicts = conflicts(build(tree.path(), &with_map(map)).err().unwrap());
+        assert!(
+            conflicts.iter().any(|c| matches!(c, IndexConflict::Moved { path, recorded, .. } if path == Path::new("readme.txt") && *recorded == levels)),
+            "{:?}", conflicts,
+        );
+    }
+
+    #[test]
+    fn non_ascii_names_are_sized_in_bytes() {
+        let tree = synthetic_tree();
+        fs::write(tree.path().join("café.bin"), "a").unwrap();
+        fs::create_dir(tree.path().join("naïve")).unwrap();
+        fs::write(tree.path().join("naïve/über.txt"), "b").unwrap();
+        // Names after the accented ones are where a wrong offset would show
+        fs::write(tree.path().join("naïve/zebra.txt"), "c").unwrap();
+
+        let game = build(tree.path(), &options()).unwrap();
+        let mut paths: Vec<String> = game.fst.entries().iter().map(|e| e.info().normalized_path()).collect();
+        paths.sort();
+        for path in ["/café.bin", "/naïve", "/naïve/über.txt", "/naïve/zebra.txt", "/readme.txt", "/data/big.bin"] {
+            assert!(paths.iter().any(|p| p == path), "{} isn't in {:?}", path, paths);
+        }
+        // Each name starts right after the one before it in the string table
+        let mut names: Vec<_> = game.fst.entries()[1..].iter().map(|e| e.info()).collect();
+        names.sort_by_key(|info| info.filename_offset);
+        let mut expected = 0;
+        for info in names {
+            assert_eq!(info.filename_offset, expected, "{}", info.name);
+            expected += info.name.trim_end_matches('/').len() as u64 + 1;
+        }
+    }
+
+    #[test]
+    fn names_the_fst_cant_store() {
+        let check = |name: &str, table_size| FSTRebuilder::check_name(name, &Path::new("dir").join(name), table_size);
+        for name in ["a\0b", "a/b"] {
+            let e = check(name, 0).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            assert!(e.to_string().contains("has a NUL or '/' in its name"), "{}", e);
+        }
+        // Too long for some games, but it fits
+        let warning = check(&"a".repeat(300), 0).unwrap().unwrap();
+        assert!(warning.contains("has a name longer than 255 bytes"), "{}", warning);
+        assert_eq!(check("abc", 0).unwrap(), None);
+    }
+
+    #[test]
+    fn the_string_table_has_a_limit() {
+        let check = |table_size| FSTRebuilder::check_name("abc", Path::new("dir/abc"), table_size);
+        check(MAX_STRING_TABLE_SIZE - 4).unwrap();
+        let e = check(MAX_STRING_TABLE_SIZE - 3).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        let text = e.to_string();
+        assert!(text.contains("dir/abc") && text.contains(&(MAX_STRING_TABLE_SIZE + 1).to_string()), "{}", text);
+    }
+
+    #[test]
+    fn the_user_area_is_where_the_files_are() {
+        let options = options();
+        let game = build(synthetic_tree().path(), &options).unwrap();
+        let files: Vec<_> = game.fst.entries().iter().filter_map(|e| e.as_file()).collect();
+        let first = files.iter().map(|f| f.file_offset).min().unwrap();
+        let last = files.iter().map(|f| f.file_offset + f.size as u64).max().unwrap();
+        assert_eq!(game.user_area(), Some((first, align(last, options.file_alignment))));
+        assert!(game.files_outside_user_area().is_empty());
+    }
+
+    #[test]
+    fn user_fields_can_be_kept() {
+        let tree = synthetic_tree();
+        let header_path = tree.path().join(HEADER_PATH);
+        let mut header = Header::new(BufReader::new(File::open(&header_path).unwrap()), 0).unwrap();
+        header.set_user_area(0x1234_0000, 0x10_0000);
+        header.write(File::create(&header_path).unwrap()).unwrap();
+
+        let kept = build(tree.path(), &RebuildOptions { keep_user_fields: true, ..options() }).unwrap();
+        assert_eq!((kept.header.user_position, kept.header.user_length), (0x1234_0000, 0x10_0000));
+        assert!(!kept.files_outside_user_area().is_empty());
+
+        header.write(File::create(&header_path).unwrap()).unwrap();
+        let rebuilt = build(tree.path(), &options()).unwrap();
+        assert_ne!(rebuilt.header.user_position, 0x1234_0000);
+        assert!(rebuilt.files_outside_user_area().is_empty());
+    }
+
+    // Counts what's written through it
+    struct Counting<T> {
+        inner: T,
+        written: u64,
+    }
+
+    impl<T: Read> Read for Counting<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl<T: Write> Write for Counting<T> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = self.inner.write(buf)?;
+            self.written += n as u64;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl<T: Seek> Seek for Counting<T> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    fn image(tree: &Path) -> Vec<u8> {
+        let mut image = Vec::new();
+        ROMRebuilder::rebuild(tree, &options(), &mut image, &Reporter::quiet()).unwrap();
+        image
+    }
+
+    #[test]
+    fn updating_only_writes_what_changed() {
+        let tree = synthetic_tree();
+        let old = image(tree.path());
+        let since = SystemTime::now();
+        let changed = tree.path().join("data/levels/1.bin");
+        let mut contents = fs::read(&changed).unwrap();
+        contents.iter_mut().for_each(|b| *b = !*b);
+        fs::write(&changed, &contents).unwrap();
+        File::options().write(true).open(&changed).unwrap()
+            .set_modified(since + std::time::Duration::from_secs(1)).unwrap();
+
+        let rebuilder = ROMRebuilder::plan(tree.path(), &options()).unwrap();
+        let mut iso = Counting { inner: Cursor::new(old), written: 0 };
+        let outcome = rebuilder.update_in(&mut iso, since).unwrap();
+        let UpdateOutcome::Updated { files_rewritten, bytes_written } = outcome else {
+            panic!("{:?}", outcome);
+        };
+        assert_eq!(bytes_written, iso.written);
+        let updated = iso.inner.into_inner();
+        let fresh = image(tree.path());
+        assert!(updated == fresh, "the update isn't the same as rebuilding");
+
+        // The system data, and the one file
+        let system_files = rebuilder.file_count() - rebuilder.planned_files().unwrap().len();
+        assert_eq!(files_rewritten, system_files + 1);
+        assert!(iso.written < fresh.len() as u64 / 4, "{} of {} bytes were written", iso.written, fresh.len());
+    }
+
+    #[test]
+    fn updating_a_different_layout_writes_nothing() {
+        let tree = synthetic_tree();
+        let old = image(tree.path());
+        fs::write(tree.path().join("readme.txt"), "a different size").unwrap();
+
+        let rebuilder = ROMRebuilder::plan(tree.path(), &options()).unwrap();
+        let mut iso = Counting { inner: Cursor::new(old.clone()), written: 0 };
+        let outcome = rebuilder.update_in(&mut iso, SystemTime::UNIX_EPOCH).unwrap();
+        assert!(matches!(&outcome, UpdateOutcome::LayoutChanged(r) if r.contains("resized")), "{:?}", outcome);
+        assert_eq!(iso.written, 0);
+        assert!(iso.inner.into_inner() == old);
+    }
+
+    fn image_with(tree: &Path, jobs: usize) -> io::Result<Vec<u8>> {
+        let mut image = Vec::new();
+        let options = RebuildOptions { jobs, capacity: 4 * synthetic::CAPACITY, ..options() };
+        ROMRebuilder::rebuild(tree, &options, &mut image, &Reporter::quiet())?;
+        Ok(image)
+    }
+
+    #[test]
+    fn pipelined_writes_are_the_same() {
+        let tree = synthetic_tree();
+        // Lots of empty files and ones a few chunks long, next to each other
+        for i in 0..20 {
+            fs::write(tree.path().join(format!("data/empty{:02}.bin", i)), "").unwrap();
+        }
+        fs::write(tree.path().join("data/chunks.bin"), synthetic::contents(WRITE_CHUNK_SIZE * 3 + 1)).unwrap();
+        fs::write(tree.path().join("data/exact.bin"), synthetic::contents(WRITE_CHUNK_SIZE)).unwrap();
+
+        let sequential = image_with(tree.path(), 1).unwrap();
+        for jobs in [2, 3, 8] {
+            assert!(image_with(tree.path(), jobs).unwrap() == sequential, "--jobs {} wrote different bytes", jobs);
+        }
+    }
+
+    // Not a real benchmark, just how long writing 64 MiB of files to a file
+    // takes with and without the reader thread. The tree goes on another
+    // file system when there's one, usually /dev/shm on Linux, since that's
+    // where reading ahead helps. Run it with
+    // `cargo test --release -- --ignored --nocapture rebuild_timings`.
+    #[test]
+    #[ignore]
+    fn rebuild_timings() {
+        use std::time::Instant;
+
+        let out = TestDir::new();
+        let other = ["/dev/shm", "/run/user"].iter()
+            .map(Path::new)
+            .find(|p| crate::io_util::check_writable(p).is_ok());
+        let tree = match other {
+            Some(other) => TestDir::new_in(other),
+            None => TestDir::new(),
+        };
+        synthetic::write_tree(tree.path()).unwrap();
+        for i in 0..32 {
+            fs::write(tree.path().join(format!("data/big{:02}.bin", i)), synthetic::contents(2 * 1024 * 1024)).unwrap();
+        }
+
+        println!("The tree is in {}, and the image in {}", tree.path().display(), out.path().display());
+        for jobs in [1, 2, 4] {
+            let options = RebuildOptions { jobs, capacity: 24 * synthetic::CAPACITY, ..options() };
+            let start = Instant::now();
+            let image = File::create(out.path().join("image.iso")).unwrap();
+            ROMRebuilder::rebuild(tree.path(), &options, image, &Reporter::quiet()).unwrap();
+            println!("--jobs {}: {:?}", jobs, start.elapsed());
+        }
+    }
+
+    #[test]
+    fn pipelined_read_errors_stop_the_write() {
+        let tree = synthetic_tree();
+        let rebuilder = ROMRebuilder::plan(tree.path(), &RebuildOptions { jobs: 2, ..options() }).unwrap();
+        fs::remove_file(tree.path().join("data/levels/1.bin")).unwrap();
+        let e = rebuilder.write(Vec::new(), &Reporter::quiet()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound, "{}", e);
+    }
+
+    fn file_mut<'g>(game: &'g mut Game, path: &str) -> &'g mut FileEntry {
+        let index = game.fst.entries().iter().position(|e| e.info().normalized_path() == path).unwrap();
+        game.fst.entry_mut(index).unwrap().as_file_mut().unwrap()
+    }
+
+    #[test]
+    fn the_output_is_checked_against_the_plan() {
+        let tree = synthetic_tree();
+        let rebuilder = ROMRebuilder::plan(tree.path(), &options()).unwrap();
+        let mut iso = image(tree.path());
+        let check = |game: &Game, iso: &[u8], contents| {
+            rebuilder.check_output(game, Cursor::new(iso), contents, &Reporter::quiet()).unwrap()
+        };
+        let game = Game::open(Cursor::new(&iso), 0).unwrap();
+        assert!(check(&game, &iso, true).is_empty());
+
+        let mut changed = Game::open(Cursor::new(&iso), 0).unwrap();
+        file_mut(&mut changed, "/readme.txt").file_offset += 0x8000;
+        file_mut(&mut changed, "/data/big.bin").size -= 1;
+        let mismatches = check(&changed, &iso, false);
+        assert!(matches!(&mismatches[..], [
+            BuildMismatch::Moved { path: a, .. },
+            BuildMismatch::Resized { path: b, .. },
+        ] | [
+            BuildMismatch::Resized { path: b, .. },
+            BuildMismatch::Moved { path: a, .. },
+        ] if a == "/readme.txt" && b == "/data/big.bin"), "{:?}", mismatches);
+
+        let readme = game.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .find(|f| f.info.normalized_path() == "/readme.txt")
+            .unwrap()
+            .file_offset as usize;
+        iso[readme + 10] ^= 0xff;
+        let mismatches = check(&game, &iso, true);
+        assert!(matches!(&mismatches[..], [BuildMismatch::ContentsDiffer { path, offset: 10 }] if path == "/readme.txt"), "{:?}", mismatches);
+        // Without contents, the layout is all that's checked
+        assert!(check(&game, &iso, false).is_empty());
+    }
+
+    #[test]
+    fn files_missing_from_the_output_or_not_planned() {
+        let tree = synthetic_tree();
+        let iso = image(tree.path());
+        let game = Game::open(Cursor::new(&iso), 0).unwrap();
+        fs::remove_file(tree.path().join("readme.txt")).unwrap();
+        fs::write(tree.path().join("data/new.bin"), "new").unwrap();
+        let rebuilder = ROMRebuilder::plan(tree.path(), &options()).unwrap();
+
+        let mismatches = rebuilder.check_output(&game, Cursor::new(&iso), false, &Reporter::quiet()).unwrap();
+        assert!(mismatches.iter().any(|m| matches!(m, BuildMismatch::Missing(p) if p == "/data/new.bin")), "{:?}", mismatches);
+        assert!(mismatches.iter().any(|m| matches!(m, BuildMismatch::Unexpected(p) if p == "/readme.txt")), "{:?}", mismatches);
+    }
+
+    fn aligned(system_alignment: u64, file_alignment: u64) -> Game {
+        build(synthetic_tree().path(), &RebuildOptions { system_alignment, file_alignment, ..options() }).unwrap()
+    }
+
+    // Relative to the first file
+    fn file_offsets(game: &Game) -> Vec<u64> {
+        let mut offsets: Vec<u64> = game.fst.entries().iter().filter_map(|e| e.as_file()).map(|f| f.file_offset).collect();
+        offsets.sort();
+        offsets.iter().map(|o| o - offsets[0]).collect()
+    }
+
+    #[test]
+    fn system_and_file_alignment_are_separate() {
+        for (system, file) in [(0x100, 4), (0x8000, 4), (0x100, 0x8000), (4, 0x20)] {
+            let game = aligned(system, file);
+            let apploader_end = APPLOADER_OFFSET + game.apploader.total_size() as u64;
+            assert_eq!(game.header.fst_offset, align(apploader_end, system), "{:#x} {:#x}", system, file);
+            assert_eq!(game.header.dol_offset, align(game.header.fst_offset + game.header.fst_size as u64, system));
+            assert!(game.header.alignment_problem().is_none());
+            let dol_end = game.header.dol_offset + game.dol.dol_size as u64;
+            let first_file = align(align(dol_end, system), file);
+            for f in game.fst.entries().iter().filter_map(|e| e.as_file()) {
+                assert!(f.file_offset >= first_file && f.file_offset % file == 0, "{:#x} {:#x}: {:?}", system, file, f);
+            }
+        }
+        // Changing one leaves the other's part of the layout alone
+        assert_eq!(file_offsets(&aligned(0x100, 4)), file_offsets(&aligned(0x8000, 4)));
+        let (a, b) = (aligned(0x800, 4), aligned(0x800, 0x8000));
+        assert_eq!((a.header.fst_offset, a.header.dol_offset), (b.header.fst_offset, b.header.dol_offset));
+    }
+
+    #[test]
+    fn alignments_have_to_be_multiples_of_4() {
+        for options in [
+            RebuildOptions { system_alignment: 2, ..options() },
+            RebuildOptions { file_alignment: 6, ..options() },
+            RebuildOptions { system_alignment: 0, ..options() },
+        ] {
+            let e = build(synthetic_tree().path(), &options).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", e);
+        }
+    }
+
+    fn existing_fst() -> RebuildOptions {
+        RebuildOptions { rebuild_systemdata: false, ..options() }
+    }
+
+    fn tree_mismatch(e: io::Error) -> TreeMismatch {
+        let e = e.into_inner().unwrap();
+        *e.downcast::<TreeMismatch>().unwrap()
+    }
+
+    // A tree that's been rebuilt once, so its system data has the layout
+    fn built_tree() -> TestDir {
+        let tree = synthetic_tree();
+        build(tree.path(), &options()).unwrap();
+        tree
+    }
+
+    #[test]
+    fn trees_that_match_the_existing_fst() {
+        let tree = built_tree();
+        // Ignored, like when the FST is rebuilt
+        fs::write(tree.join(".DS_Store"), b"x").unwrap();
+        let game = build(tree.path(), &existing_fst()).unwrap();
+        assert_eq!(game.fst.entries().len(), 9);
+    }
+
+    #[test]
+    fn trees_that_dont_match_the_existing_fst() {
+        let tree = built_tree();
+        fs::remove_file(tree.join("readme.txt")).unwrap();
+        fs::remove_dir(tree.join("empty")).unwrap();
+        fs::write(tree.join("new.bin"), b"new").unwrap();
+        fs::create_dir(tree.join("data/extra")).unwrap();
+
+        let mismatch = tree_mismatch(ROMRebuilder::plan(tree.path(), &existing_fst()).err().unwrap());
+        assert_eq!(mismatch.missing, ["/readme.txt", "/empty"]);
+        assert_eq!(mismatch.extra, ["/data/extra", "/new.bin"]);
+        assert_eq!(
+            mismatch.to_string(),
+            "The tree doesn't match the existing file system table:\n    \
+            Missing from the tree (2):\n        /readme.txt\n        /empty\n    \
+            Not in the FST (2):\n        /data/extra\n        /new.bin",
+        );
+
+        // Pruning only helps with what's missing
+        let options = RebuildOptions { prune_missing: true, ..existing_fst() };
+        let mismatch = tree_mismatch(ROMRebuilder::plan(tree.path(), &options).err().unwrap());
+        assert_eq!((mismatch.missing.len(), mismatch.extra.len()), (2, 2));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn pruning_missing_files() {
+        let tree = built_tree();
+        fs::remove_file(tree.join("readme.txt")).unwrap();
+        fs::remove_dir(tree.join("empty")).unwrap();
+        assert!(ROMRebuilder::plan(tree.path(), &existing_fst()).is_err());
+
+        let options = RebuildOptions { prune_missing: true, ..existing_fst() };
+        let rebuilder = ROMRebuilder::plan(tree.path(), &options).unwrap();
+        assert_eq!(rebuilder.pruned(), ["/readme.txt"]);
+        let original = build(synthetic_tree().path(), &self::options()).unwrap();
+        let game = build(tree.path(), &options).unwrap();
+        let readme = game.fst.entry_for_path("/readme.txt").unwrap().as_file().unwrap();
+        assert_eq!(readme.size, 0);
+        assert!(game.fst.entry_for_path("/empty/").unwrap().is_dir());
+        assert_eq!(game.fst.total_file_size, original.fst.total_file_size - 64);
+        // The tree's FST has it that way now too
+        let fst = FST::new(Cursor::new(fs::read(tree.join("&&systemdata/Game.toc")).unwrap()), 0).unwrap();
+        assert_eq!(fst.entry_for_path("/readme.txt").unwrap().as_file().unwrap().size, 0);
+    }
+
+    // `files` are names in a new directory, and how big they are
+    fn write_plan(dir: &TestDir, files: &[(u64, &str, usize)]) -> io::Result<Vec<(u64, String)>> {
+        let files = files.iter()
+            .map(|&(offset, name, size)| {
+                fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+                fs::write(dir.join(name), vec![1; size]).unwrap();
+                (offset, dir.join(name))
+            })
+            .collect();
+        WritePlan::new(files, dir.path()).validate()
+            .map(|files| files.into_iter().map(|(offset, f)| (offset, rom_path(dir.path(), &f))).collect())
+    }
+
+    fn plan_error(e: io::Error) -> String {
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e);
+        e.to_string()
+    }
+
+    #[test]
+    fn empty_files_dont_take_an_offset() {
+        let dir = TestDir::new();
+        // The header, with an empty file given its offset
+        let plan = write_plan(&dir, &[(0x2440, "Apploader.ldr", 0x20), (0, "ISO.hdr", 0x2440), (0, "empty.bin", 0)]).unwrap();
+        let expected = [(0, "/ISO.hdr"), (0, "/empty.bin"), (0x2440, "/Apploader.ldr")];
+        assert_eq!(plan, expected.map(|(offset, path)| (offset, path.to_owned())));
+
+        // Two in the same directory, and in the middle of a file
+        let dir = TestDir::new();
+        let files = [(0x8000, "d/a.bin", 0x100), (0x8000, "d/b", 0), (0x8000, "d/c", 0), (0x8010, "d/e", 0)];
+        let plan = write_plan(&dir, &files).unwrap();
+        // Still in the order they were given, at the same offset
+        let paths: Vec<&str> = plan.iter().map(|(_, p)| p.as_str()).collect();
+        assert_eq!(paths, ["/d/a.bin", "/d/b", "/d/c", "/d/e"]);
+    }
+
+    #[test]
+    fn files_that_collide() {
+        let dir = TestDir::new();
+        let files = [(0x8000, "a.bin", 0x10), (0x8000, "b.bin", 0x10)];
+        assert_eq!(plan_error(write_plan(&dir, &files).unwrap_err()), "/a.bin and /b.bin would both be written at 0x8000");
+
+        let dir = TestDir::new();
+        let files = [(0x9000, "b.bin", 0x10), (0x8000, "a.bin", 0x2000)];
+        assert_eq!(
+            plan_error(write_plan(&dir, &files).unwrap_err()),
+            "/b.bin would be written at 0x9000, inside of /a.bin, which goes from 0x8000 to 0xa000",
+        );
+
+        // Past the end of one that's inside another, which still overlaps
+        let dir = TestDir::new();
+        let files = [(0x8000, "a.bin", 0x1000), (0x8100, "b.bin", 0x10), (0x8200, "c.bin", 0x10)];
+        assert!(plan_error(write_plan(&dir, &files).unwrap_err()).contains("/b.bin would be written at 0x8100, inside of /a.bin"));
+        // Right after is fine
+        let dir = TestDir::new();
+        assert!(write_plan(&dir, &[(0x8000, "a.bin", 0x1000), (0x9000, "b.bin", 0x10)]).is_ok());
+    }
+
+    // With the files far enough apart that the gaps between them count
+    fn spread_out() -> (TestDir, ROMRebuilder, Vec<u8>) {
+        let tree = synthetic_tree();
+        let options = RebuildOptions { file_alignment: 4 * SPARSE_GAP_THRESHOLD, ..options() };
+        let rebuilder = ROMRebuilder::plan(tree.path(), &options).unwrap();
+        let mut image = Vec::new();
+        rebuilder.write(&mut image, &Reporter::quiet()).unwrap();
+        (tree, rebuilder, image)
+    }
+
+    fn sha1_of(bytes: &[u8]) -> [u8; 20] {
+        let mut sha1 = Sha1::new();
+        sha1.update(bytes);
+        sha1.finish()
+    }
+
+    #[test]
+    fn new_files_get_the_same_rom() {
+        let (_tree, rebuilder, image) = spread_out();
+        let dir = TestDir::new();
+        let mut file = File::create(dir.join("new.iso")).unwrap();
+        let sha1 = rebuilder.write_new_file(&mut file, true, &Reporter::quiet()).unwrap();
+        drop(file);
+        assert!(fs::read(dir.join("new.iso")).unwrap() == image);
+        assert_eq!(sha1, Some(sha1_of(&image)));
+
+        let mut file = File::create(dir.join("unhashed.iso")).unwrap();
+        assert_eq!(rebuilder.write_new_file(&mut file, false, &Reporter::quiet()).unwrap(), None);
+        drop(file);
+        assert!(fs::read(dir.join("unhashed.iso")).unwrap() == image);
+    }
+
+    // Where it can be told, the gaps really were left as holes
+    #[cfg(unix)]
+    #[test]
+    fn new_files_take_less_space() {
+        use std::os::unix::fs::MetadataExt;
+
+        let (_tree, rebuilder, image) = spread_out();
+        let dir = TestDir::new();
+        let probe = File::create(dir.join("probe")).unwrap();
+        probe.set_len(image.len() as u64).unwrap();
+        if probe.metadata().unwrap().blocks() != 0 {
+            // No sparse files here
+            return;
+        }
+        let mut file = File::create(dir.join("new.iso")).unwrap();
+        rebuilder.write_new_file(&mut file, false, &Reporter::quiet()).unwrap();
+        let metadata = file.metadata().unwrap();
+        assert_eq!(metadata.len(), image.len() as u64);
+        assert!(metadata.blocks() * 512 < image.len() as u64 / 2, "{} blocks", metadata.blocks());
+    }
+
+    // Whatever's in a file that isn't new would show through the holes
+    #[test]
+    fn files_that_arent_empty_get_every_gap_written() {
+        let (_tree, rebuilder, image) = spread_out();
+        let dir = TestDir::new();
+        fs::write(dir.join("old.iso"), vec![0xff; image.len() + 10]).unwrap();
+        let mut file = File::options().write(true).open(dir.join("old.iso")).unwrap();
+        let sha1 = rebuilder.write_new_file(&mut file, true, &Reporter::quiet()).unwrap();
+        drop(file);
+        let written = fs::read(dir.join("old.iso")).unwrap();
+        assert!(written[..image.len()] == image[..]);
+        assert_eq!(sha1, Some(sha1_of(&image)));
+    }
+
+    // Where each file with something in it ends up, in order
+    fn placed_files(options: &RebuildOptions) -> Vec<(String, u64)> {
+        let game = build(synthetic_tree().path(), options).unwrap();
+        let mut files: Vec<(String, u64)> = game.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size != 0)
+            .map(|f| (f.info.normalized_path(), f.file_offset))
+            .collect();
+        files.sort_by_key(|f| f.1);
+        files
+    }
+
+    // Reserves `size` bytes at `offset` for the debug monitor in the tree's
+    // header, like on a development image
+    fn with_debug_monitor(tree: &Path, offset: u32, size: u32) {
+        use crate::sections::header::HeaderField;
+
+        let path = tree.join(HEADER_PATH);
+        let mut header = Header::new(BufReader::new(File::open(&path).unwrap()), 0).unwrap();
+        header.debug_monitor_offset = offset;
+        header.information.debug_monitor_size = size;
+        header.mark_dirty(HeaderField::DebugMonitorOffset);
+        header.mark_dirty(HeaderField::DebugMonitorSize);
+        header.write_patched(File::create(&path).unwrap()).unwrap();
+    }
+
+    fn file_offsets_by_path(game: &Game) -> Vec<(String, u64)> {
+        game.fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .filter(|f| f.size != 0)
+            .map(|f| (f.info.normalized_path(), f.file_offset))
+            .collect()
+    }
+
+    #[test]
+    fn moving_past_the_debug_monitor() {
+        let reserved = Some((0x200, 0x300));
+        assert_eq!(past_reserved(0x100, 0x100, reserved, 0x20), 0x100);
+        assert_eq!(past_reserved(0x100, 0x101, reserved, 0x20), 0x300);
+        assert_eq!(past_reserved(0x2f0, 0x10, reserved, 0x40), 0x300);
+        assert_eq!(past_reserved(0x2f0, 0x10, reserved, 0x200), 0x400);
+        assert_eq!(past_reserved(0x300, 0x100, reserved, 0x20), 0x300);
+        // Empty, but starting inside of it
+        assert_eq!(past_reserved(0x200, 0, reserved, 4), 0x300);
+        assert_eq!(past_reserved(0x1fc, 0, reserved, 4), 0x1fc);
+        assert_eq!(past_reserved(0x200, 0x100, None, 4), 0x200);
+    }
+
+    #[test]
+    fn nothing_goes_in_the_debug_monitors_area() {
+        let plain = build(synthetic_tree().path(), &options()).unwrap();
+        // Over the FST and DOL, and the first files
+        let (start, end) = (0x2800, 0x12800);
+        let tree = synthetic_tree();
+        with_debug_monitor(tree.path(), start as u32, (end - start) as u32);
+        let game = build(tree.path(), &options()).unwrap();
+        assert_eq!(game.debug_monitor.map(|m| m.range()), Some((start, end)));
+        assert!(plain.fst.offset < end && game.fst.offset >= end, "{:#x}", game.fst.offset);
+        assert!(game.dol.offset >= end, "{:#x}", game.dol.offset);
+        let files = file_offsets_by_path(&game);
+        assert!(files.iter().all(|&(_, offset)| offset >= end), "{:x?}", files);
+        let gaps = game.rom_layout().gaps(game.capacity);
+        assert!(gaps.iter().all(|&(s, e)| e <= start || s >= end), "{:x?}", gaps);
+
+        // Put back where they'd be without it, and taken out of the header
+        let ignored = build(tree.path(), &RebuildOptions { ignore_debug_monitor: true, ..options() }).unwrap();
+        assert!(ignored.debug_monitor.is_none());
+        assert_eq!(ignored.header.debug_monitor_offset, 0);
+        assert_eq!(ignored.header.information.debug_monitor_size, 0);
+        assert_eq!((ignored.fst.offset, ignored.dol.offset), (plain.fst.offset, plain.dol.offset));
+        assert_eq!(file_offsets_by_path(&ignored), file_offsets_by_path(&plain));
+    }
+
+    #[test]
+    fn a_debug_monitor_among_the_files() {
+        let tree = synthetic_tree();
+        with_debug_monitor(tree.path(), 0x10000, 0x100);
+        let game = build(tree.path(), &options()).unwrap();
+        // Everything from the file that was there on moves along by one
+        // alignment
+        assert_eq!(file_offsets_by_path(&game), [
+            ("/readme.txt".to_owned(), 0x8000),
+            ("/data/levels/2.bin".to_owned(), 0x18000),
+            ("/data/levels/1.bin".to_owned(), 0x20000),
+            ("/data/big.bin".to_owned(), 0x28000),
+        ]);
+    }
+
+    #[test]
+    fn the_footprint_is_what_the_rebuilt_files_take_up() {
+        let tree = synthetic_tree();
+        for alignment in [4, 12, 4096, options().file_alignment] {
+            let options = RebuildOptions { file_alignment: alignment, ..options() };
+            let plan = ROMRebuilder::plan(tree.path(), &options).unwrap();
+            let mut image = Vec::new();
+            plan.write(&mut image, &Reporter::quiet()).unwrap();
+            let game = Game::open(Cursor::new(image), 0).unwrap();
+            assert_eq!(plan.file_system_footprint(), Some(game.fst.total_aligned_size(alignment)), "{}", alignment);
+            // Not just the files' sizes
+            assert!(game.fst.total_aligned_size(alignment) > game.fst.total_file_size as u64, "{}", alignment);
+        }
+    }
+
+    #[test]
+    fn stamped_builds() {
+        let tree = synthetic_tree();
+        let unstamped = build(tree.path(), &options()).unwrap();
+        assert_eq!(build_stamp::read(unstamped.header.raw()), Ok(None));
+
+        let options = RebuildOptions { stamp: true, ..options() };
+        let plan = ROMRebuilder::plan(tree.path(), &options).unwrap();
+        let mut image = Vec::new();
+        plan.write(&mut image, &Reporter::quiet()).unwrap();
+        let game = Game::open(Cursor::new(&image), 0).unwrap();
+        let stamp = build_stamp::read(game.header.raw()).unwrap().unwrap();
+        assert_eq!(stamp.options_hash, build_stamp::options_hash(&options));
+        let planned = plan.tree_hash().unwrap();
+        assert_eq!(stamp.tree_hash, planned);
+        assert_eq!(game.tree_hash(Cursor::new(&image)).unwrap(), planned);
+        // Only the stamp is different
+        let mut cleared = game.header.raw().to_vec();
+        assert!(build_stamp::clear(&mut cleared));
+        assert!(cleared == unstamped.header.raw());
+    }
+
+    // Pipelined, which reports files in a different place than one job does
+    #[test]
+    fn progress_of_a_rebuild() {
+        let tree = synthetic_tree();
+        for jobs in [1, 2] {
+            let plan = ROMRebuilder::plan(tree.path(), &RebuildOptions { jobs, ..options() }).unwrap();
+            let sink = RecordingSink::default();
+            plan.write(io::sink(), &Reporter::quiet().with_sink(sink.clone())).unwrap();
+            let bytes = plan.written_files().unwrap().iter().map(|&(_, size, _)| size).sum();
+            sink.check(plan.file_count(), bytes);
+        }
+    }
+
+    #[test]
+    fn files_in_the_profiles_order() {
+        use crate::{compare_layout::LayoutComparison, file_order::ProfileFile};
+
+        let tree = synthetic_tree();
+        let original = build(tree.path(), &options()).unwrap();
+        let order = ["/data/big.bin", "/readme.txt", "/data/levels/2.bin"];
+        let profile = FileOrderProfile {
+            version: 1,
+            files: order.iter().map(|&path| ProfileFile { path: path.to_owned(), accesses: 1 }).collect(),
+        };
+        let game = build(tree.path(), &RebuildOptions { file_order: Some(profile), ..options() }).unwrap();
+
+        let paths = |game: &Game| game.fst.entries().iter().map(|e| e.info().normalized_path()).collect::<Vec<_>>();
+        assert_eq!(paths(&game), paths(&original));
+        // Empty files take up no room, so they can be anywhere among the rest
+        let mut files = LayoutComparison::new(&original, &game).files;
+        files.retain(|f| f.size_b != 0);
+        files.sort_by_key(|f| f.offset_b);
+        let (first, rest) = files.split_at(order.len());
+        assert_eq!(first.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), order);
+        // And the rest are in FST order, like they'd be without a profile
+        assert!(rest.windows(2).all(|w| w[0].offset_a < w[1].offset_a));
+        assert_eq!(first[0].offset_b, original.fst.entry_for_path("/readme.txt").and_then(Entry::as_file).unwrap().file_offset);
+    }
+
+    // Left to the caller to show, and not printed
+    #[test]
+    fn planning_gives_back_its_warnings() {
+        let tree = synthetic_tree();
+        assert_eq!(ROMRebuilder::plan(tree.path(), &options()).unwrap().warnings(), Vec::<String>::new());
+
+        with_debug_monitor(tree.path(), 0x10000, 0x100);
+        let profile = FileOrderProfile {
+            version: 1,
+            files: vec![crate::file_order::ProfileFile { path: "/gone.bin".to_owned(), accesses: 1 }],
+        };
+        let options = RebuildOptions {
+            file_alignment: 12,
+            ignore_debug_monitor: true,
+            file_order: Some(profile),
+            ..options()
+        };
+        let warnings = ROMRebuilder::plan(tree.path(), &options).unwrap().warnings().to_vec();
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+        assert!(warnings[0].starts_with("The file alignment 12 isn't a power of two"), "{:?}", warnings);
+        assert!(warnings[1].starts_with("1 of the 1 files in the file order profile aren't in the tree"), "{:?}", warnings);
+        assert!(warnings[2].starts_with("The debug monitor is taken out of the header"), "{:?}", warnings);
+    }
+
+    #[test]
+    fn alignments_that_arent_powers_of_two() {
+        let offsets = placed_files(&RebuildOptions { file_alignment: 12, ..options() });
+        assert_eq!(offsets, [
+            ("/readme.txt".to_owned(), 0x3804),
+            ("/data/levels/2.bin".to_owned(), 0x384c),
+            ("/data/levels/1.bin".to_owned(), 0x4854),
+            ("/data/big.bin".to_owned(), 0x4c44),
+        ]);
+        assert!(offsets.iter().all(|&(_, o)| o % 12 == 0 && o % MIN_ALIGNMENT == 0), "{:x?}", offsets);
+
+        let offsets = placed_files(&RebuildOptions { file_alignment: 4096, ..options() });
+        assert_eq!(offsets, [
+            ("/readme.txt".to_owned(), 0x4000),
+            ("/data/levels/2.bin".to_owned(), 0x5000),
+            ("/data/levels/1.bin".to_owned(), 0x6000),
+            ("/data/big.bin".to_owned(), 0x7000),
+        ]);
+    }
+
+    #[test]
+    fn alignments_the_drive_cant_read_from() {
+        let tree = synthetic_tree();
+        for (options, alignment, field) in [
+            (RebuildOptions { file_alignment: 6, ..options() }, 6, "file_alignment"),
+            (RebuildOptions { file_alignment: 0, ..options() }, 0, "file_alignment"),
+            (RebuildOptions { system_alignment: 2050, ..options() }, 2050, "system_alignment"),
+        ] {
+            let e = build(tree.path(), &options).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            let e = e.into_inner().unwrap().downcast::<AlignmentError>().unwrap();
+            assert_eq!(e.alignment, alignment);
+            assert!(matches!(e.source, AlignmentSource::Field(f) if f == field), "{:?}", e);
+        }
+        let e = AlignmentError::check(1002, AlignmentSource::Flag("--file-alignment")).unwrap_err();
+        assert!(e.to_string().starts_with("Invalid alignment 1002 from --file-alignment."), "{}", e);
+        AlignmentError::check(1000, AlignmentSource::Flag("--file-alignment")).unwrap();
+    }
+}
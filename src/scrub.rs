@@ -0,0 +1,325 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use crate::{
+    align,
+    hash::Crc32,
+    junk::fill_junk,
+    rom_rebuilder::SPARSE_GAP_THRESHOLD,
+    WRITE_CHUNK_SIZE,
+};
+
+#[derive(Copy, Clone)]
+pub enum GapFill {
+    Zeros,
+    Junk { game_code: [u8; 4], disc_number: u8 },
+}
+
+// Copies an image of `image_len` bytes from `iso` to `output`, replacing the
+// bytes in each of `gaps` according to `fill`. The gaps must be sorted and
+// must not overlap, like the ones from `ROMLayout::gaps`.
+// `progress` gets called with the amount of bytes written so far and the
+// total. Returns the CRC-32 of everything written.
+pub fn rewrite_gaps(
+    iso: impl Read + Seek,
+    output: impl Write,
+    image_len: u64,
+    gaps: &[(u64, u64)],
+    fill: GapFill,
+    progress: impl FnMut(u64, u64),
+) -> io::Result<u32> {
+    rewrite_to(iso, Zeroed(output), image_len, gaps, fill, progress)
+}
+
+// Like `rewrite_gaps`, but into `file`, which has to be empty, like one that
+// was just created. Big gaps of zeros are seeked over instead of written, so
+// they take no space on file systems with sparse files. Anything else, like a
+// file that's being updated in place, gets every zero written.
+pub fn rewrite_gaps_to_new_file(
+    iso: impl Read + Seek,
+    file: &mut File,
+    image_len: u64,
+    gaps: &[(u64, u64)],
+    fill: GapFill,
+    progress: impl FnMut(u64, u64),
+) -> io::Result<u32> {
+    let metadata = file.metadata()?;
+    if metadata.is_file() && metadata.len() == 0 && file.stream_position()? == 0 {
+        rewrite_to(iso, NewFile(file), image_len, gaps, fill, progress)
+    } else {
+        rewrite_gaps(iso, file, image_len, gaps, fill, progress)
+    }
+}
+
+fn rewrite_to(
+    mut iso: impl Read + Seek,
+    mut output: impl GapOutput,
+    image_len: u64,
+    gaps: &[(u64, u64)],
+    fill: GapFill,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<u32> {
+    let mut crc = Crc32::new();
+    let mut buf = vec![0; WRITE_CHUNK_SIZE];
+    let mut pos = 0;
+
+    iso.seek(SeekFrom::Start(0))?;
+    for &(start, end) in gaps.iter().chain(Some(&(image_len, image_len))) {
+        while pos < start {
+            let len = (start - pos).min(WRITE_CHUNK_SIZE as u64) as usize;
+            iso.read_exact(&mut buf[..len])?;
+            output.write_all(&buf[..len])?;
+            crc.update(&buf[..len]);
+            pos += len as u64;
+            progress(pos, image_len);
+        }
+
+        // Zeros are only left out of the output, the CRC-32 still needs them
+        let skipped = matches!(fill, GapFill::Zeros) && output.skip_zeros(end - pos)?;
+        // Junk never starts right where data ends, the bytes up to the next
+        // 4 byte boundary are always zero.
+        let junk_start = align(start, 4).min(end);
+        while pos < end {
+            let len = (end - pos).min(WRITE_CHUNK_SIZE as u64) as usize;
+            let chunk = &mut buf[..len];
+            chunk.fill(0);
+            if let GapFill::Junk { game_code, disc_number } = fill {
+                let skip = junk_start.saturating_sub(pos).min(len as u64) as usize;
+                fill_junk(game_code, disc_number, pos + skip as u64, &mut chunk[skip..]);
+            }
+            if !skipped {
+                output.write_all(chunk)?;
+            }
+            crc.update(chunk);
+            pos += len as u64;
+            progress(pos, image_len);
+        }
+        iso.seek(SeekFrom::Start(pos))?;
+    }
+
+    output.finish()?;
+    Ok(crc.finish())
+}
+
+trait GapOutput: Write {
+    // Leaves the next `len` bytes as zeros without writing them, if that's
+    // worth doing. Returns whether it did.
+    fn skip_zeros(&mut self, _len: u64) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    // Called once everything's written.
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+// Writes every gap out.
+struct Zeroed<W>(W);
+
+impl<W: Write> Write for Zeroed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> GapOutput for Zeroed<W> {}
+
+// An empty file for `rewrite_gaps_to_new_file`, which big gaps are seeked
+// over in.
+struct NewFile<'a>(&'a mut File);
+
+impl Write for NewFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl GapOutput for NewFile<'_> {
+    fn skip_zeros(&mut self, len: u64) -> io::Result<bool> {
+        if len < SPARSE_GAP_THRESHOLD {
+            return Ok(false);
+        }
+        self.0.seek(SeekFrom::Current(len as i64))?;
+        Ok(true)
+    }
+
+    // Seeking past the end doesn't make the file any longer, so an image
+    // that ends in a skipped gap needs its length set.
+    fn finish(&mut self) -> io::Result<()> {
+        let end = self.0.stream_position()?;
+        if self.0.metadata()?.len() < end {
+            self.0.set_len(end)?;
+        }
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use std::fs;
+
+    use super::*;
+    use crate::{junk::JUNK_BLOCK_SIZE, test_util::TestDir};
+
+    const GAME_CODE: [u8; 4] = *b"GSLF";
+    const JUNK: GapFill = GapFill::Junk { game_code: GAME_CODE, disc_number: 0 };
+
+    // Past a write chunk and a few junk blocks, with gaps that start off a 4
+    // byte boundary and cross both.
+    const IMAGE_LEN: u64 = WRITE_CHUNK_SIZE as u64 + 3 * JUNK_BLOCK_SIZE;
+    const GAPS: &[(u64, u64)] = &[
+        (0x101, 0x2000),
+        (JUNK_BLOCK_SIZE - 0x10, JUNK_BLOCK_SIZE + 0x10),
+        (WRITE_CHUNK_SIZE as u64 - 3, WRITE_CHUNK_SIZE as u64 + JUNK_BLOCK_SIZE + 7),
+        (IMAGE_LEN - 0x1000, IMAGE_LEN),
+    ];
+
+    fn in_gap(pos: u64) -> bool {
+        GAPS.iter().any(|&(start, end)| (start..end).contains(&pos))
+    }
+
+    // Data that's never zero outside the gaps
+    fn data() -> Vec<u8> {
+        (0..IMAGE_LEN).map(|i| if in_gap(i) { 0 } else { (i % 255) as u8 + 1 }).collect()
+    }
+
+    fn rewrite(image: &[u8], fill: GapFill) -> (Vec<u8>, u32) {
+        let mut out = Vec::new();
+        let crc = rewrite_gaps(Cursor::new(image), &mut out, IMAGE_LEN, GAPS, fill, |_, _| {}).unwrap();
+        (out, crc)
+    }
+
+    #[test]
+    fn zeros_only_change_the_gaps() {
+        let mut junked = data();
+        fill_junk(GAME_CODE, 0, 0, &mut junked[0x2000..0x3000]);
+        junked[0x104..0x2000].fill(0xff);
+        let (scrubbed, _) = rewrite(&junked, GapFill::Zeros);
+        let mut expected = junked.clone();
+        for &(start, end) in GAPS {
+            expected[start as usize..end as usize].fill(0);
+        }
+        assert_eq!(scrubbed, expected);
+    }
+
+    #[test]
+    fn junk_starts_on_a_4_byte_boundary() {
+        let (unscrubbed, _) = rewrite(&data(), JUNK);
+        // The first gap starts at 0x101, so junk starts at 0x104
+        assert_eq!(&unscrubbed[0x101..0x104], &[0, 0, 0]);
+        let mut junk = vec![0; 0x2000 - 0x104];
+        fill_junk(GAME_CODE, 0, 0x104, &mut junk);
+        assert_eq!(&unscrubbed[0x104..0x2000], &junk[..]);
+        let (start, end) = GAPS[2];
+        let mut junk = vec![0; (end - align(start, 4)) as usize];
+        fill_junk(GAME_CODE, 0, align(start, 4), &mut junk);
+        assert_eq!(&unscrubbed[align(start, 4) as usize..end as usize], &junk[..]);
+    }
+
+    #[test]
+    fn scrub_then_unscrub_round_trips() {
+        let original = rewrite(&data(), JUNK).0;
+        let (scrubbed, scrubbed_crc) = rewrite(&original, GapFill::Zeros);
+        assert_eq!(scrubbed, data());
+        let (unscrubbed, unscrubbed_crc) = rewrite(&scrubbed, JUNK);
+        assert_eq!(unscrubbed, original);
+        // And the same again
+        assert_eq!(rewrite(&scrubbed, JUNK), (original.clone(), unscrubbed_crc));
+        assert_eq!(rewrite(&unscrubbed, GapFill::Zeros), (scrubbed, scrubbed_crc));
+    }
+
+    #[test]
+    fn crc_is_of_the_output() {
+        let (out, crc) = rewrite(&data(), JUNK);
+        let mut expected = crate::hash::Crc32::new();
+        expected.update(&out);
+        assert_eq!(crc, expected.finish());
+    }
+
+    #[test]
+    fn progress_reaches_the_total_in_chunks() {
+        let mut calls = Vec::new();
+        let image = data();
+        rewrite_gaps(Cursor::new(&image), io::sink(), IMAGE_LEN, GAPS, GapFill::Zeros, |done, total| {
+            calls.push((done, total));
+        }).unwrap();
+        assert_eq!(calls.last(), Some(&(IMAGE_LEN, IMAGE_LEN)));
+        assert!(calls.iter().all(|&(_, total)| total == IMAGE_LEN));
+        let mut last = 0;
+        for &(done, _) in &calls {
+            assert!(done > last && done - last <= WRITE_CHUNK_SIZE as u64);
+            last = done;
+        }
+    }
+
+    fn rewrite_to_file(file: &mut File, image: &[u8], fill: GapFill) -> u32 {
+        rewrite_gaps_to_new_file(Cursor::new(image), file, IMAGE_LEN, GAPS, fill, |_, _| {}).unwrap()
+    }
+
+    #[test]
+    fn new_files_get_the_same_image() {
+        let dir = TestDir::new();
+        for fill in [GapFill::Zeros, JUNK] {
+            let mut file = File::create(dir.join("new.iso")).unwrap();
+            let crc = rewrite_to_file(&mut file, &data(), fill);
+            drop(file);
+            assert!((fs::read(dir.join("new.iso")).unwrap(), crc) == rewrite(&data(), fill));
+        }
+    }
+
+    // Where it can be told, the gaps really were left as holes
+    #[cfg(unix)]
+    #[test]
+    fn scrubbed_new_files_take_less_space() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TestDir::new();
+        let probe = File::create(dir.join("probe")).unwrap();
+        probe.set_len(IMAGE_LEN).unwrap();
+        if probe.metadata().unwrap().blocks() != 0 {
+            // No sparse files here
+            return;
+        }
+        let mut file = File::create(dir.join("new.iso")).unwrap();
+        rewrite_to_file(&mut file, &data(), GapFill::Zeros);
+        let metadata = file.metadata().unwrap();
+        assert_eq!(metadata.len(), IMAGE_LEN);
+        let holes: u64 = GAPS.iter()
+            .map(|&(start, end)| end - start)
+            .filter(|&len| len >= SPARSE_GAP_THRESHOLD)
+            .sum();
+        assert!(metadata.blocks() * 512 <= IMAGE_LEN - holes + 0x10000, "{} blocks", metadata.blocks());
+    }
+
+    // Whatever's in a file being updated in place would show through the holes
+    #[test]
+    fn files_that_arent_empty_get_every_zero_written() {
+        let dir = TestDir::new();
+        fs::write(dir.join("old.iso"), vec![0xff; IMAGE_LEN as usize + 10]).unwrap();
+        let mut file = File::options().write(true).open(dir.join("old.iso")).unwrap();
+        let crc = rewrite_to_file(&mut file, &data(), GapFill::Zeros);
+        drop(file);
+        let written = fs::read(dir.join("old.iso")).unwrap();
+        assert!((written[..IMAGE_LEN as usize].to_vec(), crc) == rewrite(&data(), GapFill::Zeros));
+    }
+
+    #[test]
+    fn short_input_fails() {
+        let image = data();
+        let result = rewrite_gaps(Cursor::new(&image[..0x1000]), io::sink(), IMAGE_LEN, GAPS, GapFill::Zeros, |_, _| {});
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
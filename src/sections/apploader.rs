@@ -1,27 +1,130 @@
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::{
     align,
-    format_u64,
-    format_usize,
-    NumberStyle,
-    sections::Section,
+    layout_ref::{reading, Block},
+    sections::{header::Header, Field, Section},
 };
 
 pub const APPLOADER_OFFSET: u64 = 0x2440;
-const APPLOADER_DATE_SIZE: usize = 0x0A;
+pub const APPLOADER_DATE_SIZE: usize = 0x0A;
 // const APPLOADER_ENTRY_POINT_ADDR: u64 = 0x2450;
 // const APPLOADER_ENTRY_POINT_SIZE: u64 = 0xA0;
-const APPLOADER_SIZE_ADDR: u64 = 0x2454;
+pub const APPLOADER_SIZE_ADDR: u64 = 0x2454;
+pub const APPLOADER_HEADER_SIZE: usize = 0x20;
+// Where the IPL loads the apploader's code.
+pub const APPLOADER_LOAD_ADDR: u32 = 0x8120_0000;
+
+// The IPL itself starts at 0x81300000, so the apploader's code and trailer
+// (see `Apploader::total_size`) have to fit in the 1MiB below that.
+pub const APPLOADER_MAX_SIZE: usize = 0x10_0000;
+// The apploader and the FST share the memory the IPL sets aside for booting.
+// In practice retail IPLs don't boot games where they take up more than this
+// between them.
+pub const BOOT_AREA_SIZE: usize = 0x40_0000;
+// Getting within this much of either limit is worth a warning.
+pub const BOOT_LIMIT_MARGIN: usize = 0x4_0000;
+
+#[derive(Debug)]
+pub enum BootLimitProblem {
+    ApploaderTooBig(usize),
+    BootAreaTooBig { apploader_size: usize, fst_size: usize },
+    // Still bootable, but not by much
+    NearLimit { apploader_size: usize, fst_size: usize },
+}
+
+impl BootLimitProblem {
+    pub fn is_error(&self) -> bool {
+        !matches!(self, BootLimitProblem::NearLimit { .. })
+    }
+}
+
+impl fmt::Display for BootLimitProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootLimitProblem::ApploaderTooBig(size) => write!(
+                f,
+                "The apploader is {:#x} bytes, but it can be at most {:#x} bytes",
+                size, APPLOADER_MAX_SIZE,
+            ),
+            BootLimitProblem::BootAreaTooBig { apploader_size, fst_size } => write!(
+                f,
+                "The apploader ({:#x} bytes) and FST ({:#x} bytes) take up {:#x} bytes, more than the {:#x} the IPL can boot",
+                apploader_size, fst_size, apploader_size + fst_size, BOOT_AREA_SIZE,
+            ),
+            BootLimitProblem::NearLimit { apploader_size, fst_size } => write!(
+                f,
+                "The apploader ({:#x} bytes) and FST ({:#x} bytes) are close to the most the IPL can boot",
+                apploader_size, fst_size,
+            ),
+        }
+    }
+}
+
+impl Error for BootLimitProblem {}
+
+// Whether the IPL can load an apploader and FST of these sizes. Sizes equal
+// to the limits are still fine.
+pub fn boot_limit_problem(apploader_size: usize, fst_size: usize) -> Option<BootLimitProblem> {
+    let total = apploader_size.saturating_add(fst_size);
+    if apploader_size > APPLOADER_MAX_SIZE {
+        Some(BootLimitProblem::ApploaderTooBig(apploader_size))
+    } else if total > BOOT_AREA_SIZE {
+        Some(BootLimitProblem::BootAreaTooBig { apploader_size, fst_size })
+    } else if apploader_size > APPLOADER_MAX_SIZE - BOOT_LIMIT_MARGIN || total > BOOT_AREA_SIZE - BOOT_LIMIT_MARGIN {
+        Some(BootLimitProblem::NearLimit { apploader_size, fst_size })
+    } else {
+        None
+    }
+}
+
+// The apploader's header says it's bigger than the space it has on the
+// disc, see `Apploader::bound_to`.
+#[derive(Debug)]
+pub struct OversizedApploader {
+    // `Apploader::total_size`
+    pub declared: usize,
+    pub room: usize,
+}
+
+impl fmt::Display for OversizedApploader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The apploader's header says it's {:#x} bytes, but there are only {:#x} bytes before the DOL and FST",
+            self.declared, self.room,
+        )
+    }
+}
+
+impl Error for OversizedApploader {}
+
+// How much space the apploader has on an image `len` bytes long: up to
+// whichever of the DOL and FST comes first.
+pub fn apploader_room(header: &Header, len: u64) -> usize {
+    let end = header.dol_offset.min(header.fst_offset).min(len);
+    end.saturating_sub(APPLOADER_OFFSET) as usize
+}
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Apploader {
     pub date: String,
     pub entry_point: u64,
+    // As the header has them, even if they're garbage
     pub code_size: usize,
     pub trailer_size: usize,
+    // See `bound_to`. None for apploaders read on their own.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub room: Option<usize>,
 }
 
 impl Apploader {
@@ -32,55 +135,138 @@ impl Apploader {
         let reader = &mut reader;
         reader.seek(SeekFrom::Start(offset))?;
         let mut date = String::new();
-        reader.take(APPLOADER_DATE_SIZE as u64).read_to_string(&mut date)?;
+        reader.take(APPLOADER_DATE_SIZE as u64).read_to_string(&mut date).map_err(reading(Block::Apploader, "date"))?;
         reader.seek(SeekFrom::Current(6))?; // it's just fluff
 
-        let entry_point = reader.read_u32::<BigEndian>()? as u64;
-        let code_size = reader.read_u32::<BigEndian>()? as usize;
-        let trailer_size = reader.read_u32::<BigEndian>()? as usize;
+        let entry_point = reader.read_u32::<BigEndian>().map_err(reading(Block::Apploader, "entry_point"))? as u64;
+        let code_size = reader.read_u32::<BigEndian>().map_err(reading(Block::Apploader, "code_size"))? as usize;
+        let trailer_size = reader.read_u32::<BigEndian>().map_err(reading(Block::Apploader, "trailer_size"))? as usize;
 
         Ok(Apploader {
             date,
             entry_point,
             code_size,
             trailer_size,
+            room: None,
         })
     }
 
+    // Writes an apploader that's just a header, for when there's no real one.
+    // ROMs with it will only boot in emulators that skip the apploader.
+    pub fn write_placeholder(mut writer: impl Write) -> io::Result<()> {
+        let mut buf = [0; APPLOADER_HEADER_SIZE];
+        buf[..APPLOADER_DATE_SIZE].copy_from_slice(b"0000/00/00");
+        (&mut buf[0x10..]).write_u32::<BigEndian>(APPLOADER_LOAD_ADDR)?;
+        // The "code" is the header itself, so the size matches the file.
+        (&mut buf[0x14..]).write_u32::<BigEndian>(APPLOADER_HEADER_SIZE as u32)?;
+        writer.write_all(&buf)
+    }
+
+    // The date as YYYY-MM-DD, if it's a real one. It's when the SDK the
+    // apploader came from was built, which is usually close to the game's
+    // build date. Placeholder apploaders have 0000/00/00.
+    pub fn build_date(&self) -> Option<String> {
+        let mut parts = self.date.split('/');
+        let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+        let valid = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+        if parts.next().is_some() || !valid(year, 4) || !valid(month, 2) || !valid(day, 2) {
+            return None;
+        }
+        let (y, m, d): (u32, u32, u32) = (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+        (y != 0 && (1..=12).contains(&m) && (1..=31).contains(&d))
+            .then(|| format!("{}-{}-{}", year, month, day))
+    }
+
+    // What the header says, which is what the IPL loads. See `size_on_disc`
+    // for what's actually there.
     pub fn total_size(&self) -> usize {
         // self.code_size + self.trailer_size
         align((self.code_size + self.trailer_size) as u64, 32) as usize
     }
 
-    pub fn extract<R, W>(mut iso: R, mut file: W) -> io::Result<()>
+    // Sets how much space the apploader has on the disc, see
+    // `apploader_room`, so that's the most it's counted as taking up.
+    pub fn bound_to(&mut self, room: usize) {
+        self.room = Some(room);
+    }
+
+    // `total_size`, but no more than the space it has, once it's bounded.
+    pub fn size_on_disc(&self) -> usize {
+        self.room.map_or(self.total_size(), |room| self.total_size().min(room))
+    }
+
+    pub fn oversized(&self) -> Option<OversizedApploader> {
+        self.room
+            .filter(|&room| self.total_size() > room)
+            .map(|room| OversizedApploader { declared: self.total_size(), room })
+    }
+
+    // `base` is where the image starts in `iso`. Only what's on the disc is
+    // copied, see `size_on_disc`.
+    pub fn extract<R, W>(&self, mut iso: R, mut file: W, base: u64) -> io::Result<()>
     where
         R: Read + Seek,
         W: Write,
     {
-        iso.seek(SeekFrom::Start(APPLOADER_SIZE_ADDR))?;
-        let code_size = iso.read_u32::<BigEndian>()? as u64;
-        let trailer_size = iso.read_u32::<BigEndian>()? as u64;
-        iso.seek(SeekFrom::Start(APPLOADER_OFFSET))?;
-
-        let aligned_size = align(code_size + trailer_size, 32);
+        iso.seek(SeekFrom::Start(base + APPLOADER_OFFSET))?;
         io::copy(
-            &mut iso.take(aligned_size),
+            &mut iso.take(self.size_on_disc() as u64),
             &mut file,
         ).map(drop)
     }
 }
 
+// What `info -t apploader` shows, for `--json`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ApploaderInfo {
+    pub offset: u64,
+    pub date: String,
+    pub entry_point: u64,
+    pub code_size: usize,
+    pub trailer_size: usize,
+    // See `Apploader::total_size`
+    pub size: usize,
+    // See `Apploader::size_on_disc`
+    pub size_on_disc: usize,
+}
+
+impl Apploader {
+    pub fn info(&self) -> ApploaderInfo {
+        ApploaderInfo {
+            offset: APPLOADER_OFFSET,
+            date: self.date.clone(),
+            entry_point: self.entry_point,
+            code_size: self.code_size,
+            trailer_size: self.trailer_size,
+            size: self.total_size(),
+            size_on_disc: self.size_on_disc(),
+        }
+    }
+}
+
 impl Section for Apploader {
-    fn print_info(&self, style: NumberStyle) {
-        println!("Offset: {}", format_u64(APPLOADER_OFFSET, style));
-        println!("Date: {}", self.date);
-        println!("Code size: {} bytes", format_usize(self.code_size, style));
-        println!("Trailer size: {} bytes", format_usize(self.trailer_size, style));
-        println!("Entry point: not yet implemented");
-        println!(
-            "Size (including code and trailer, aligned to 32 bytes): {}",
-            format_usize(self.total_size(), style),
-        );
+    fn fields(&self) -> Vec<Field> {
+        let info = self.info();
+        let mut fields = vec![
+            Field::number("Offset", info.offset),
+            Field::text("Date", info.date),
+            Field::bytes("Code size", info.code_size as u64),
+            Field::bytes("Trailer size", info.trailer_size as u64),
+            Field::number("Entry point", info.entry_point),
+            Field::number("Size (including code and trailer, aligned to 32 bytes)", info.size as u64),
+        ];
+        if info.size_on_disc != info.size {
+            fields.push(Field::number(
+                "Size on the disc (all there's room for before the DOL and FST)",
+                info.size_on_disc as u64,
+            ));
+        }
+        fields
+    }
+
+    fn name(&self) -> String {
+        "Apploader.ldr".to_owned()
     }
 
     fn start(&self) -> u64 {
@@ -88,6 +274,71 @@ impl Section for Apploader {
     }
 
     fn size(&self) -> usize {
-        self.total_size()
+        self.size_on_disc()
+    }
+
+    fn is_system_data(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_error(apploader_size: usize, fst_size: usize) -> Option<bool> {
+        boot_limit_problem(apploader_size, fst_size).map(|p| p.is_error())
+    }
+
+    fn kind(apploader_size: usize, fst_size: usize) -> &'static str {
+        match boot_limit_problem(apploader_size, fst_size) {
+            None => "fine",
+            Some(BootLimitProblem::NearLimit { .. }) => "near",
+            Some(BootLimitProblem::ApploaderTooBig(_)) => "apploader",
+            Some(BootLimitProblem::BootAreaTooBig { .. }) => "total",
+        }
+    }
+
+    #[test]
+    fn the_apploader_limit() {
+        let near = APPLOADER_MAX_SIZE - BOOT_LIMIT_MARGIN;
+        assert_eq!(kind(near, 0), "fine");
+        assert_eq!(kind(near + 1, 0), "near");
+        assert_eq!(kind(APPLOADER_MAX_SIZE - 1, 0), "near");
+        assert_eq!(kind(APPLOADER_MAX_SIZE, 0), "near");
+        assert_eq!(kind(APPLOADER_MAX_SIZE + 1, 0), "apploader");
+        // Even when the total is over too
+        assert_eq!(kind(APPLOADER_MAX_SIZE + 1, BOOT_AREA_SIZE), "apploader");
+    }
+
+    #[test]
+    fn the_boot_area_limit() {
+        let apploader = 0x2000;
+        let near = BOOT_AREA_SIZE - BOOT_LIMIT_MARGIN - apploader;
+        assert_eq!(kind(apploader, near), "fine");
+        assert_eq!(kind(apploader, near + 1), "near");
+        assert_eq!(kind(apploader, BOOT_AREA_SIZE - apploader - 1), "near");
+        assert_eq!(kind(apploader, BOOT_AREA_SIZE - apploader), "near");
+        assert_eq!(kind(apploader, BOOT_AREA_SIZE - apploader + 1), "total");
+        assert_eq!(kind(apploader, usize::MAX), "total");
+    }
+
+    #[test]
+    fn only_going_over_is_an_error() {
+        assert_eq!(is_error(0x2000, 0x1000), None);
+        assert_eq!(is_error(APPLOADER_MAX_SIZE, BOOT_AREA_SIZE - APPLOADER_MAX_SIZE), Some(false));
+        assert_eq!(is_error(APPLOADER_MAX_SIZE + 1, 0), Some(true));
+        assert_eq!(is_error(0, BOOT_AREA_SIZE + 1), Some(true));
+    }
+
+    // The trailer counts too, and it's all rounded up to 32 bytes
+    #[test]
+    fn total_sizes() {
+        let mut bytes = vec![0; APPLOADER_HEADER_SIZE];
+        bytes[0x14..0x18].copy_from_slice(&((APPLOADER_MAX_SIZE - 0x20) as u32).to_be_bytes());
+        bytes[0x18..0x1c].copy_from_slice(&0x1u32.to_be_bytes());
+        let apploader = Apploader::new(io::Cursor::new(&bytes), 0).unwrap();
+        assert_eq!(apploader.total_size(), APPLOADER_MAX_SIZE);
+        assert_eq!(kind(apploader.total_size(), 0), "near");
     }
 }
@@ -0,0 +1,201 @@
+// The banner is the `opening.bnr` file in the root of the file system. It has
+// the image and text shown for the game in the IPL's menu.
+// http://hitmen.c02.at/files/yagcd/yagcd/chap14.html#sec14.1
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    rom_handle::RomWriteHandle,
+    sections::{Field, FieldValue, Section},
+};
+
+pub const BANNER_PATH: &str = "/opening.bnr";
+
+pub const BANNER_MAGIC_SIZE: usize = 4;
+pub const BANNER_IMAGE_OFFSET: u64 = 0x20;
+pub const BANNER_IMAGE_SIZE: usize = 0x1800;
+pub const BANNER_INFO_OFFSET: u64 = 0x1820;
+pub const BANNER_INFO_SIZE: usize = 0x140;
+// A BNR2's, with all its infos
+pub const BANNER_MAX_SIZE: usize = BANNER_INFO_OFFSET as usize + BNR2_INFO_COUNT * BANNER_INFO_SIZE;
+
+pub const SHORT_TITLE_SIZE: usize = 0x20;
+pub const SHORT_MAKER_SIZE: usize = 0x20;
+pub const LONG_TITLE_SIZE: usize = 0x40;
+pub const LONG_MAKER_SIZE: usize = 0x40;
+pub const DESCRIPTION_SIZE: usize = 0x80;
+
+// BNR2 banners are used by PAL games and have this many info blocks, one per
+// language, in the order of `LANGUAGES`. BNR1 banners have just one.
+pub const BNR2_INFO_COUNT: usize = 6;
+pub const LANGUAGES: [&str; BNR2_INFO_COUNT] =
+    ["English", "German", "French", "Spanish", "Italian", "Dutch"];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BannerVersion {
+    BNR1,
+    BNR2,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct BannerInfo {
+    pub short_title: String,
+    pub short_maker: String,
+    pub long_title: String,
+    pub long_maker: String,
+    pub description: String,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Banner {
+    pub version: BannerVersion,
+    pub infos: Vec<BannerInfo>,
+    // Where it was read from. It isn't part of the banner's text, so it's
+    // left out of dump-meta's documents.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub offset: u64,
+}
+
+impl Banner {
+    pub fn new<R>(mut file: R, offset: u64) -> io::Result<Banner>
+    where
+        R: Read + Seek,
+    {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut magic = [0; BANNER_MAGIC_SIZE];
+        file.read_exact(&mut magic)?;
+        let (version, count) = match &magic {
+            b"BNR1" => (BannerVersion::BNR1, 1),
+            b"BNR2" => (BannerVersion::BNR2, BNR2_INFO_COUNT),
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid banner magic",
+            )),
+        };
+
+        file.seek(SeekFrom::Start(offset + BANNER_INFO_OFFSET))?;
+        let mut infos = Vec::with_capacity(count);
+        for _ in 0..count {
+            infos.push(BannerInfo {
+                short_title: read_text(&mut file, SHORT_TITLE_SIZE)?,
+                short_maker: read_text(&mut file, SHORT_MAKER_SIZE)?,
+                long_title: read_text(&mut file, LONG_TITLE_SIZE)?,
+                long_maker: read_text(&mut file, LONG_MAKER_SIZE)?,
+                description: read_text(&mut file, DESCRIPTION_SIZE)?,
+            });
+        }
+
+        Ok(Banner { version, infos, offset })
+    }
+
+    // Overwrites the text of the banner at `offset` in `iso`, which has to
+    // be the same version as this one. The image is left alone.
+    pub fn write_text(&self, iso: &mut RomWriteHandle, offset: u64) -> io::Result<()> {
+        self.write_text_in(iso, offset)
+    }
+
+    fn write_text_in(&self, mut file: impl Write + Seek, offset: u64) -> io::Result<()> {
+        // Encode everything first, so nothing gets written if any of it is
+        // invalid.
+        let mut blocks = Vec::with_capacity(self.infos.len());
+        for info in &self.infos {
+            let mut block = Vec::with_capacity(BANNER_INFO_SIZE);
+            write_text(&mut block, &info.short_title, SHORT_TITLE_SIZE)?;
+            write_text(&mut block, &info.short_maker, SHORT_MAKER_SIZE)?;
+            write_text(&mut block, &info.long_title, LONG_TITLE_SIZE)?;
+            write_text(&mut block, &info.long_maker, LONG_MAKER_SIZE)?;
+            write_text(&mut block, &info.description, DESCRIPTION_SIZE)?;
+            blocks.push(block);
+        }
+
+        file.seek(SeekFrom::Start(offset + BANNER_INFO_OFFSET))?;
+        for block in blocks {
+            file.write_all(&block)?;
+        }
+        file.flush()
+    }
+}
+
+impl BannerInfo {
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::text("Title", &self.long_title),
+            Field::text("Short title", &self.short_title),
+            Field::text("Maker", &self.long_maker),
+            Field::text("Short maker", &self.short_maker),
+            Field::text("Description", &self.description),
+        ]
+    }
+}
+
+impl Section for Banner {
+    // A BNR2's text is under each language.
+    fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![
+            Field::number("Offset", self.offset),
+            Field::text("Version", format!("{:?}", self.version)),
+        ];
+        match self.version {
+            BannerVersion::BNR1 => fields.extend(self.infos.iter().flat_map(BannerInfo::fields)),
+            BannerVersion::BNR2 => {
+                let languages = self.infos.iter()
+                    .zip(LANGUAGES)
+                    .map(|(info, language)| {
+                        let mut fields = vec![Field::text("Language", language)];
+                        fields.extend(info.fields());
+                        fields
+                    })
+                    .collect();
+                fields.push(Field { label: "Languages", value: FieldValue::Sections(languages) });
+            },
+        }
+        fields
+    }
+
+    fn name(&self) -> String {
+        BANNER_PATH.trim_start_matches('/').to_owned()
+    }
+
+    fn start(&self) -> u64 {
+        self.offset
+    }
+
+    fn size(&self) -> usize {
+        BANNER_INFO_OFFSET as usize + self.infos.len() * BANNER_INFO_SIZE
+    }
+}
+
+// The text fields are fixed size and NUL padded. This treats them as
+// Latin-1, which is right for everything other than Japanese banners.
+fn write_text(mut file: impl Write, text: &str, size: usize) -> io::Result<()> {
+    let mut buf: Vec<u8> = text.chars()
+        .map(|c| u8::try_from(c as u32))
+        .collect::<Result<_, _>>()
+        .map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} can't be written to the banner, it isn't Latin-1", text),
+        ))?;
+    if buf.len() > size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is too long for the banner, it can be at most {} characters", text, size),
+        ));
+    }
+    buf.resize(size, 0);
+    file.write_all(&buf)
+}
+
+fn read_text(mut file: impl Read, size: usize) -> io::Result<String> {
+    let mut buf = vec![0; size];
+    file.read_exact(&mut buf)?;
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(size);
+    Ok(buf[..end].iter().map(|&b| b as char).collect())
+}
@@ -1,27 +1,98 @@
 use std::{
     cmp::max,
+    error::Error,
+    fmt,
     io::{self, Read, Seek, SeekFrom, Write},
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
-use crate::{format_u64, format_usize, sections::Section, NumberStyle};
+use crate::{
+    layout_ref::{reading, Block},
+    sections::{Field, FieldValue, Section},
+};
 
+#[cfg(feature = "serde")]
+pub mod pack;
+pub mod refs;
 pub mod segment;
 use segment::{Segment, SegmentType};
 
-const TEXT_SEG_COUNT: usize = 7;
-const DATA_SEG_COUNT: usize = 11;
+pub const TEXT_SEG_COUNT: usize = 7;
+pub const DATA_SEG_COUNT: usize = 11;
 const TOTAL_SEG_COUNT: usize = TEXT_SEG_COUNT + DATA_SEG_COUNT;
 
 pub const DOL_OFFSET_OFFSET: u64 = 0x0420;
 pub const DOL_HEADER_LEN: usize = 0x100;
+// Each of these is a word for each text segment, then one for each data
+// segment.
+pub const SEGMENT_OFFSETS_OFFSET: u64 = 0x00;
+pub const SEGMENT_ADDRESSES_OFFSET: u64 = 0x48;
+pub const SEGMENT_SIZES_OFFSET: u64 = 0x90;
+pub const BSS_ADDRESS_OFFSET: u64 = 0xD8;
+pub const BSS_SIZE_OFFSET: u64 = 0xDC;
+pub const ENTRY_POINT_OFFSET: u64 = 0xE0;
+// Everything after the entry point is unused
+pub const DOL_PADDING_OFFSET: u64 = 0xE4;
+
+// Why a DOL can't be written the way it is.
+#[derive(Debug)]
+pub enum DOLError {
+    // Like .text7, past the 7 text and 11 data segments there's room for
+    NoSuchSegment(Segment),
+    DuplicateSegment(Segment),
+    SegmentInHeader(Segment),
+    Overlap(Segment, Segment),
+    // The header only has 32 bits for every offset, size, and address
+    SegmentTooLarge(Segment),
+    FieldTooLarge { field: &'static str, value: u64 },
+}
+
+impl fmt::Display for DOLError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DOLError::NoSuchSegment(s) => write!(
+                f,
+                "There's no {}, a DOL only has .text0 to .text{} and .data0 to .data{}",
+                s, TEXT_SEG_COUNT - 1, DATA_SEG_COUNT - 1,
+            ),
+            DOLError::DuplicateSegment(s) => write!(f, "There's more than one {}", s),
+            DOLError::SegmentInHeader(s) => write!(
+                f,
+                "{} would start at {:#x}, inside of the header, which is {:#x} bytes",
+                s, s.offset, DOL_HEADER_LEN,
+            ),
+            DOLError::Overlap(a, b) => write!(
+                f,
+                "{} and {} overlap, {} goes from {:#x} to {:#x} and {} from {:#x} to {:#x}",
+                a, b,
+                a, a.offset, a.offset + a.size as u64,
+                b, b.offset, b.offset + b.size as u64,
+            ),
+            DOLError::SegmentTooLarge(s) => write!(
+                f,
+                "{} doesn't fit in a DOL, its offset, size, and address all have to be under 4 GiB",
+                s,
+            ),
+            DOLError::FieldTooLarge { field, value } =>
+                write!(f, "The {} {:#x} doesn't fit in a DOL, it has to be under 4 GiB", field, value),
+        }
+    }
+}
+
+impl Error for DOLError {}
 
 #[derive(Debug)]
 pub struct DOLHeader {
     pub offset: u64,
     pub dol_size: usize,
     pub entry_point: u64,
+    // Memory that's zeroed when the DOL is loaded. Some data segments are
+    // usually loaded over parts of it.
+    pub bss_address: u64,
+    pub bss_size: usize,
     segments: Vec<Segment>,
     // This is the index in `segments` where the data segments are. The segments
     // before this index are all text segments.
@@ -33,7 +104,7 @@ impl DOLHeader {
     where
         R: Read + Seek,
     {
-        file.seek(SeekFrom::Start(offset + 0x90))?;
+        file.seek(SeekFrom::Start(offset + SEGMENT_SIZES_OFFSET))?;
         let mut segments = Vec::new();
 
         let mut data_segments_index = 0;
@@ -45,7 +116,8 @@ impl DOLHeader {
                 data_segments_index = segments.len();
                 num -= TEXT_SEG_COUNT as u64;
             }
-            let size = file.read_u32::<BigEndian>()? as usize;
+            let field = if is_text { "text_sizes" } else { "data_sizes" };
+            let size = file.read_u32::<BigEndian>().map_err(reading(Block::Dol, field))? as usize;
             if size != 0 {
                 let mut s = if is_text {
                     Segment::text()
@@ -65,8 +137,9 @@ impl DOLHeader {
             } else {
                 0
             };
-            file.seek(SeekFrom::Start(offset + (previous + s.seg_num) * 4))?;
-            s.offset = offset + file.read_u32::<BigEndian>()? as u64;
+            file.seek(SeekFrom::Start(offset + SEGMENT_OFFSETS_OFFSET + (previous + s.seg_num) * 4))?;
+            let field = if previous == 0 { "text_offsets" } else { "data_offsets" };
+            s.offset = offset + file.read_u32::<BigEndian>().map_err(reading(Block::Dol, field))? as u64;
         }
 
         for s in &mut segments[..] {
@@ -76,26 +149,133 @@ impl DOLHeader {
                 0
             };
             file.seek(SeekFrom::Start(
-                offset + 0x48 + (previous + s.seg_num) * 4
+                offset + SEGMENT_ADDRESSES_OFFSET + (previous + s.seg_num) * 4
             ))?;
-            s.loading_address = file.read_u32::<BigEndian>()? as u64;
+            let field = if previous == 0 { "text_addresses" } else { "data_addresses" };
+            s.loading_address = file.read_u32::<BigEndian>().map_err(reading(Block::Dol, field))? as u64;
         }
 
-        file.seek(SeekFrom::Start(offset + 0xE0))?;
-        let entry_point = file.read_u32::<BigEndian>()? as u64;
+        file.seek(SeekFrom::Start(offset + BSS_ADDRESS_OFFSET))?;
+        let bss_address = file.read_u32::<BigEndian>().map_err(reading(Block::Dol, "bss_address"))? as u64;
+        file.seek(SeekFrom::Start(offset + BSS_SIZE_OFFSET))?;
+        let bss_size = file.read_u32::<BigEndian>().map_err(reading(Block::Dol, "bss_size"))? as usize;
+
+        file.seek(SeekFrom::Start(offset + ENTRY_POINT_OFFSET))?;
+        let entry_point = file.read_u32::<BigEndian>().map_err(reading(Block::Dol, "entry_point"))? as u64;
 
+        // A DOL with no segments is just a header
         let dol_size = segments.iter()
-            .map(|s| (s.offset - offset) as usize + s.size).max().unwrap();
+            .map(|s| (s.offset - offset) as usize + s.size)
+            .max()
+            .unwrap_or(DOL_HEADER_LEN);
 
         Ok(DOLHeader {
             offset,
             dol_size,
             entry_point,
+            bss_address,
+            bss_size,
             segments,
             data_segments_index,
         })
     }
 
+    // Makes a header for a DOL at offset 0, so the segments' offsets are from
+    // the start of the DOL. Segments can be in any order, and empty ones are
+    // left out, since a size of 0 is how the header says a segment isn't
+    // there. `bss` is the address and size.
+    pub fn from_segments(
+        segments: impl IntoIterator<Item = Segment>,
+        bss: (u64, usize),
+        entry_point: u64,
+    ) -> Result<DOLHeader, DOLError> {
+        let mut segments: Vec<Segment> = segments.into_iter().filter(|s| s.size != 0).collect();
+        segments.sort_by_key(|s| (s.seg_type == SegmentType::Data, s.seg_num));
+        let data_segments_index = segments.iter()
+            .position(|s| s.seg_type == SegmentType::Data)
+            .unwrap_or(segments.len());
+        let dol_size = segments.iter()
+            .map(|s| s.offset as usize + s.size)
+            .max()
+            .unwrap_or(DOL_HEADER_LEN);
+        let header = DOLHeader {
+            offset: 0,
+            dol_size,
+            entry_point,
+            bss_address: bss.0,
+            bss_size: bss.1,
+            segments,
+            data_segments_index,
+        };
+        header.validate()?;
+        Ok(header)
+    }
+
+    // Checks that the header can be written: every segment is one there's a
+    // slot for, once, after the header, and not on top of another one.
+    pub fn validate(&self) -> Result<(), DOLError> {
+        for (field, value) in [
+            ("entry point", self.entry_point),
+            ("BSS address", self.bss_address),
+            ("BSS size", self.bss_size as u64),
+        ] {
+            if value > u32::MAX as u64 {
+                return Err(DOLError::FieldTooLarge { field, value });
+            }
+        }
+
+        // Relative to the DOL from here on, like in the header
+        let mut segments: Vec<Segment> = self.segments.iter()
+            .map(|s| Segment { offset: s.offset - self.offset, ..*s })
+            .collect();
+        for (i, s) in segments.iter().enumerate() {
+            let count = match s.seg_type {
+                SegmentType::Text => TEXT_SEG_COUNT,
+                SegmentType::Data => DATA_SEG_COUNT,
+            };
+            if s.seg_num >= count as u64 {
+                return Err(DOLError::NoSuchSegment(*s));
+            }
+            if segments[..i].iter().any(|o| o.seg_type == s.seg_type && o.seg_num == s.seg_num) {
+                return Err(DOLError::DuplicateSegment(*s));
+            }
+            if s.offset < DOL_HEADER_LEN as u64 {
+                return Err(DOLError::SegmentInHeader(*s));
+            }
+            if s.offset + s.size as u64 > u32::MAX as u64 || s.loading_address > u32::MAX as u64 {
+                return Err(DOLError::SegmentTooLarge(*s));
+            }
+        }
+        segments.sort_by_key(|s| s.offset);
+        for pair in segments.windows(2) {
+            if pair[0].offset + pair[0].size as u64 > pair[1].offset {
+                return Err(DOLError::Overlap(pair[0], pair[1]));
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the 0x100 byte header, after checking it with `validate`.
+    // Anything in the header that isn't a field, like the padding at the
+    // end, is written as zeros.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut header = [0; DOL_HEADER_LEN];
+        let mut put = |at: u64, value: u64| {
+            header[at as usize..at as usize + 4].copy_from_slice(&(value as u32).to_be_bytes());
+        };
+        for s in &self.segments {
+            let slot = s.header_slot() as u64 * 4;
+            put(SEGMENT_OFFSETS_OFFSET + slot, s.offset - self.offset);
+            put(SEGMENT_ADDRESSES_OFFSET + slot, s.loading_address);
+            put(SEGMENT_SIZES_OFFSET + slot, s.size as u64);
+        }
+        put(BSS_ADDRESS_OFFSET, self.bss_address);
+        put(BSS_SIZE_OFFSET, self.bss_size as u64);
+        put(ENTRY_POINT_OFFSET, self.entry_point);
+        writer.write_all(&header)
+    }
+
     pub fn find_segment(
         &self,
         seg_type: SegmentType,
@@ -114,6 +294,8 @@ impl DOLHeader {
         self.segments.iter()
     }
 
+    // `dol_addr` is absolute, like `DOLHeader::offset`, so for an image
+    // embedded in something bigger, it includes where the image starts.
     pub fn extract<R, W>(mut iso: R, mut file: W, dol_addr: u64) -> io::Result<()>
     where
         R: Read + Seek,
@@ -123,20 +305,20 @@ impl DOLHeader {
         let mut dol_size = 0;
 
         for i in 0..(TEXT_SEG_COUNT as u64) {
-            iso.seek(SeekFrom::Start(dol_addr + 0x00 + i * 4))?;
+            iso.seek(SeekFrom::Start(dol_addr + SEGMENT_OFFSETS_OFFSET + i * 4))?;
             let seg_offset = iso.read_u32::<BigEndian>()?;
 
-            iso.seek(SeekFrom::Start(dol_addr + 0x90 + i * 4))?;
+            iso.seek(SeekFrom::Start(dol_addr + SEGMENT_SIZES_OFFSET + i * 4))?;
             let seg_size = iso.read_u32::<BigEndian>()?;
 
             dol_size = max(seg_offset + seg_size, dol_size);
         }
 
         for i in 0..(DATA_SEG_COUNT as u64) {
-            iso.seek(SeekFrom::Start(dol_addr + 0x1c + i * 4))?;
+            iso.seek(SeekFrom::Start(dol_addr + SEGMENT_OFFSETS_OFFSET + (TEXT_SEG_COUNT as u64 + i) * 4))?;
             let seg_offset = iso.read_u32::<BigEndian>()?;
 
-            iso.seek(SeekFrom::Start(dol_addr + 0xac + i * 4))?;
+            iso.seek(SeekFrom::Start(dol_addr + SEGMENT_SIZES_OFFSET + (TEXT_SEG_COUNT as u64 + i) * 4))?;
             let seg_size = iso.read_u32::<BigEndian>()?;
 
             dol_size = max(seg_offset + seg_size, dol_size);
@@ -155,20 +337,67 @@ impl DOLHeader {
             mem_addr < s.loading_address + s.size as u64
         )
     }
+
+    // Whether `mem_addr` is in the BSS. Check `segment_at_addr` first, since
+    // a segment loaded over the BSS is what's actually there.
+    pub fn bss_contains(&self, mem_addr: u64) -> bool {
+        self.bss_address <= mem_addr && mem_addr < self.bss_address + self.bss_size as u64
+    }
 }
 
-impl Section for DOLHeader {
-    fn print_info(&self, style: NumberStyle) {
-        println!("Offset: {}", format_u64(self.offset, style));
-        println!("Size: {} bytes", format_usize(self.dol_size, style));
-        println!("Header Size: {} bytes", format_usize(DOL_HEADER_LEN, style));
-        println!("Entry point: {}", format_u64(self.entry_point, style));
-        println!("Segments:");
-        for s in &self.segments {
-            println!();
-            s.print_info(style);
+// What `info -t dol` shows, for `--json`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DolInfo {
+    pub offset: u64,
+    pub size: usize,
+    pub header_size: usize,
+    pub entry_point: u64,
+    pub bss_address: u64,
+    pub bss_size: usize,
+    pub text_size: usize,
+    pub data_size: usize,
+    pub segments: Vec<Segment>,
+}
+
+impl DOLHeader {
+    pub fn info(&self) -> DolInfo {
+        DolInfo {
+            offset: self.offset,
+            size: self.dol_size,
+            header_size: DOL_HEADER_LEN,
+            entry_point: self.entry_point,
+            bss_address: self.bss_address,
+            bss_size: self.bss_size,
+            text_size: self.text_size(),
+            data_size: self.data_size(),
+            segments: self.segments.clone(),
         }
     }
+}
+
+impl Section for DOLHeader {
+    fn fields(&self) -> Vec<Field> {
+        let info = self.info();
+        vec![
+            Field::number("Offset", info.offset),
+            Field::bytes("Size", info.size as u64),
+            Field::bytes("Header Size", info.header_size as u64),
+            Field::number("Entry point", info.entry_point),
+            Field::number("BSS address", info.bss_address),
+            Field::bytes("BSS size", info.bss_size as u64),
+            Field::bytes("Text", info.text_size as u64),
+            Field::bytes("Data", info.data_size as u64),
+            Field {
+                label: "Segments",
+                value: FieldValue::Sections(info.segments.iter().map(Section::fields).collect()),
+            },
+        ]
+    }
+
+    fn name(&self) -> String {
+        "Start.dol".to_owned()
+    }
 
     fn start(&self) -> u64 {
         self.offset
@@ -177,4 +406,101 @@ impl Section for DOLHeader {
     fn size(&self) -> usize {
         DOL_HEADER_LEN
     }
+
+    fn is_system_data(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // Found by fuzzing: a DOL with no segments used to panic
+    #[test]
+    fn no_segments() {
+        let dol = DOLHeader::new(Cursor::new(vec![0; DOL_HEADER_LEN]), 0).unwrap();
+        assert_eq!(dol.dol_size, DOL_HEADER_LEN);
+        assert_eq!(dol.iter_segments().count(), 0);
+    }
+
+    #[test]
+    fn truncated_headers_fail() {
+        let e = DOLHeader::new(Cursor::new(vec![0; ENTRY_POINT_OFFSET as usize + 3]), 0).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    fn segment(seg_type: SegmentType, seg_num: u64, offset: u64, size: usize) -> Segment {
+        Segment { offset, size, loading_address: 0x8000_3000 + offset, seg_type, seg_num }
+    }
+
+    fn text(seg_num: u64, offset: u64, size: usize) -> Segment {
+        segment(SegmentType::Text, seg_num, offset, size)
+    }
+
+    fn data(seg_num: u64, offset: u64, size: usize) -> Segment {
+        segment(SegmentType::Data, seg_num, offset, size)
+    }
+
+    fn written(header: &DOLHeader) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        // Out of order, with a gap in the numbers, and an empty one that's
+        // left out
+        let segments = [data(3, 0x400, 0x40), text(0, 0x100, 0x200), text(6, 0x300, 0x20), data(0, 0x380, 0), data(1, 0x320, 0x20)];
+        let header = DOLHeader::from_segments(segments, (0x8040_0000, 0x1000), 0x8000_3100).unwrap();
+        assert_eq!(header.dol_size, 0x440);
+        let names: Vec<String> = header.iter_segments().map(|s| s.to_string()).collect();
+        assert_eq!(names, [".text0", ".text6", ".data1", ".data3"]);
+
+        let bytes = written(&header);
+        assert_eq!(bytes.len(), DOL_HEADER_LEN);
+        assert_eq!(bytes[SEGMENT_OFFSETS_OFFSET as usize + 6 * 4..][..4], 0x300u32.to_be_bytes());
+        assert_eq!(bytes[SEGMENT_SIZES_OFFSET as usize + (TEXT_SEG_COUNT + 3) * 4..][..4], 0x40u32.to_be_bytes());
+        assert_eq!(bytes[ENTRY_POINT_OFFSET as usize..][..4], 0x8000_3100u32.to_be_bytes());
+        assert!(bytes[DOL_PADDING_OFFSET as usize..].iter().all(|&b| b == 0));
+
+        let mut dol = bytes.clone();
+        dol.resize(header.dol_size, 0);
+        let parsed = DOLHeader::new(Cursor::new(&dol), 0).unwrap();
+        assert_eq!((parsed.entry_point, parsed.bss_address, parsed.bss_size, parsed.dol_size), (0x8000_3100, 0x8040_0000, 0x1000, 0x440));
+        let fields = |h: &DOLHeader| -> Vec<_> {
+            h.iter_segments().map(|s| (s.to_string(), s.offset, s.size, s.loading_address)).collect()
+        };
+        assert_eq!(fields(&parsed), fields(&header));
+        assert_eq!(written(&parsed), bytes);
+
+        // Relative to the DOL, wherever it is on the ROM
+        let mut rom = vec![0; 0x1000];
+        rom.extend_from_slice(&dol);
+        assert_eq!(written(&DOLHeader::new(Cursor::new(&rom), 0x1000).unwrap()), bytes);
+    }
+
+    fn invalid(segments: &[Segment]) -> String {
+        DOLHeader::from_segments(segments.iter().copied(), (0, 0), 0).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn headers_that_cant_be_written() {
+        assert_eq!(invalid(&[text(7, 0x100, 0x20)]), "There's no .text7, a DOL only has .text0 to .text6 and .data0 to .data10");
+        assert_eq!(invalid(&[data(11, 0x100, 0x20)]), "There's no .data11, a DOL only has .text0 to .text6 and .data0 to .data10");
+        assert_eq!(invalid(&[text(1, 0x100, 0x20), text(1, 0x200, 0x20)]), "There's more than one .text1");
+        assert_eq!(invalid(&[data(0, 0xe0, 0x20)]), ".data0 would start at 0xe0, inside of the header, which is 0x100 bytes");
+        assert_eq!(
+            invalid(&[text(0, 0x100, 0x100), data(0, 0x1f0, 0x20)]),
+            ".text0 and .data0 overlap, .text0 goes from 0x100 to 0x200 and .data0 from 0x1f0 to 0x210",
+        );
+        assert!(invalid(&[text(0, 0xffff_fff0, 0x20)]).contains(".text0 doesn't fit in a DOL"));
+        let e = DOLHeader::from_segments([], (0, 0), 0x1_0000_0000).unwrap_err();
+        assert_eq!(e.to_string(), "The entry point 0x100000000 doesn't fit in a DOL, it has to be under 4 GiB");
+        // Right up against each other is fine
+        assert!(DOLHeader::from_segments([text(0, 0x100, 0x100), data(0, 0x200, 0x20)], (0, 0), 0).is_ok());
+    }
 }
@@ -0,0 +1,286 @@
+// A DOL taken apart into a file for each segment, plus segments.json for
+// everything else in the header, so the segments can be edited or replaced
+// and put back together into a DOL. bss.txt and layout.ld say the same for
+// other tools: the BSS's address and size, and a GNU ld script that links
+// the segment files back to their addresses.
+
+use std::{
+    ffi::OsString,
+    fs::{self, create_dir_all, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::sections::dol::{segment::Segment, DOLHeader, DOL_HEADER_LEN};
+
+pub const SEGMENTS_MANIFEST_NAME: &str = "segments.json";
+pub const BSS_NAME: &str = "bss.txt";
+pub const LINKER_SCRIPT_NAME: &str = "layout.ld";
+// `extract --dol-split` puts the segments of Start.dol in Start.dol.d
+pub const SPLIT_DIR_SUFFIX: &str = ".d";
+
+// Where segments without an offset in the manifest start
+const SEGMENT_ALIGNMENT: u64 = 0x20;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct SegmentsManifest {
+    pub entry_point: u64,
+    pub bss_address: u64,
+    pub bss_size: usize,
+    pub segments: Vec<SegmentFile>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct SegmentFile {
+    // Like ".text0"
+    pub name: String,
+    // Relative to the manifest's directory. The segment is all of it.
+    pub file: String,
+    // From the start of the DOL. Without one, the segment goes right after
+    // the one before it in the list, or the header for the first one, so
+    // segments that change size can be laid out again.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub offset: Option<u64>,
+    pub loading_address: u64,
+}
+
+// Where `extract --dol-split` puts the segments of the DOL at `dol_path`.
+pub fn split_dir(dol_path: impl AsRef<Path>) -> PathBuf {
+    let mut name = OsString::from(dol_path.as_ref().as_os_str());
+    name.push(SPLIT_DIR_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl SegmentsManifest {
+    // For linking the segment files, which have to be in the current
+    // directory, into an image with everything at its address, like
+    // `ld -T layout.ld`. The BSS isn't loaded, so it can overlap the small
+    // data segments, which it often does.
+    pub fn linker_script(&self) -> String {
+        let mut s = String::from("/* Made by gcmod. repack-dol reads segments.json, not this. */\n");
+        // ENTRY only takes a symbol. The segment files are raw bytes, and the
+        // output is a plain big-endian ELF, which any build of ld can write.
+        s += &format!("_start = {:#x};\nENTRY(_start)\nOUTPUT_FORMAT(elf32-big)\nTARGET(binary)\n", self.entry_point);
+        let files: Vec<String> = self.segments.iter().map(|segment| format!("\"{}\"", segment.file)).collect();
+        s += &format!("INPUT({})\n\nSECTIONS\n{{\n", files.join(" "));
+        for segment in &self.segments {
+            s += &format!("    {} {:#x} : {{ \"{}\" }}\n", segment.name, segment.loading_address, segment.file);
+        }
+        if self.bss_size != 0 {
+            s += &format!("    .bss {:#x} (NOLOAD) : {{ . += {:#x}; }}\n", self.bss_address, self.bss_size);
+        }
+        s + "}\n"
+    }
+}
+
+impl DOLHeader {
+    // Writes each segment to `dir`, named like "text0.bin", and the manifest
+    // that `pack` uses to put them back together, plus bss.txt and
+    // layout.ld.
+    #[cfg(feature = "serde")]
+    pub fn unpack<R>(&self, mut dol: R, dir: impl AsRef<Path>) -> io::Result<SegmentsManifest>
+    where
+        R: Read + Seek,
+    {
+        let dir = dir.as_ref();
+        create_dir_all(dir)?;
+        let mut manifest = SegmentsManifest {
+            entry_point: self.entry_point,
+            bss_address: self.bss_address,
+            bss_size: self.bss_size,
+            segments: Vec::new(),
+        };
+        for s in self.iter_segments() {
+            let name = s.to_string();
+            let file = format!("{}.bin", name.trim_start_matches('.'));
+            let mut output = BufWriter::new(File::create(dir.join(&file))?);
+            s.extract(&mut dol, &mut output)?;
+            output.flush()?;
+            manifest.segments.push(SegmentFile {
+                name,
+                file,
+                offset: Some(s.offset - self.offset),
+                loading_address: s.loading_address,
+            });
+        }
+        let output = BufWriter::new(File::create(dir.join(SEGMENTS_MANIFEST_NAME))?);
+        serde_json::to_writer_pretty(output, &manifest)?;
+        fs::write(dir.join(BSS_NAME), format!("address {:#x}\nsize {:#x}\n", self.bss_address, self.bss_size))?;
+        fs::write(dir.join(LINKER_SCRIPT_NAME), manifest.linker_script())?;
+        Ok(manifest)
+    }
+
+    // Like `unpack`, then checks that `pack` puts the segments back together
+    // into the same bytes. It doesn't for DOLs with anything that isn't in a
+    // segment, like data between them or in the header's padding. Returns
+    // where the repacked DOL would first differ, if it would.
+    #[cfg(feature = "serde")]
+    pub fn unpack_checked<R>(&self, mut dol: R, dir: impl AsRef<Path>) -> io::Result<(SegmentsManifest, Option<u64>)>
+    where
+        R: Read + Seek,
+    {
+        let manifest = self.unpack(&mut dol, &dir)?;
+        let mut original = Vec::with_capacity(self.dol_size);
+        dol.seek(SeekFrom::Start(self.offset))?;
+        dol.take(self.dol_size as u64).read_to_end(&mut original)?;
+        let mut repacked = Vec::with_capacity(self.dol_size);
+        DOLHeader::pack(&dir, &mut repacked)?;
+        let difference = original.iter().zip(&repacked)
+            .position(|(a, b)| a != b)
+            .or_else(|| (original.len() != repacked.len()).then_some(original.len().min(repacked.len())));
+        Ok((manifest, difference.map(|d| d as u64)))
+    }
+
+    // Writes the DOL in `dir`, which has a manifest like `unpack` makes, to
+    // `output`. Gaps between segments are zeros. Returns the header it was
+    // written with, which is at offset 0.
+    #[cfg(feature = "serde")]
+    pub fn pack<W>(dir: impl AsRef<Path>, mut output: W) -> io::Result<DOLHeader>
+    where
+        W: Write,
+    {
+        let dir = dir.as_ref();
+        let manifest_path = dir.join(SEGMENTS_MANIFEST_NAME);
+        let manifest: SegmentsManifest = serde_json::from_reader(BufReader::new(File::open(&manifest_path)?))
+            .map_err(|e| invalid_data(format!("Invalid {}: {}", manifest_path.display(), e)))?;
+
+        let mut segments = Vec::new();
+        let mut end = DOL_HEADER_LEN as u64;
+        for entry in &manifest.segments {
+            let (seg_type, seg_num) = Segment::parse_segment_name(&entry.name)
+                .ok_or_else(|| invalid_data(format!("Invalid segment name {:?} in {}", entry.name, manifest_path.display())))?;
+            let size = fs::metadata(dir.join(&entry.file))?.len();
+            let offset = entry.offset.unwrap_or_else(|| end.next_multiple_of(SEGMENT_ALIGNMENT));
+            end = offset + size;
+            segments.push((
+                Segment { offset, size: size as usize, loading_address: entry.loading_address, seg_type, seg_num },
+                &entry.file,
+            ));
+        }
+
+        let header = DOLHeader::from_segments(
+            segments.iter().map(|(s, _)| *s),
+            (manifest.bss_address, manifest.bss_size),
+            manifest.entry_point,
+        ).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        header.write(&mut output)?;
+
+        segments.sort_by_key(|(s, _)| s.offset);
+        let mut position = DOL_HEADER_LEN as u64;
+        for (s, file) in segments.iter().filter(|(s, _)| s.size != 0) {
+            io::copy(&mut io::repeat(0).take(s.offset - position), &mut output)?;
+            // The size is checked again, in case the file changed since
+            let copied = io::copy(&mut File::open(dir.join(file))?.take(s.size as u64), &mut output)?;
+            if copied != s.size as u64 {
+                return Err(invalid_data(format!("{} changed while it was being read", dir.join(file).display())));
+            }
+            position = s.offset + s.size as u64;
+        }
+        output.flush()?;
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{sections::dol::segment::SegmentType, test_util::TestDir};
+
+    // A DOL with two text segments and a data segment, each filled with a
+    // different byte
+    fn dol() -> Vec<u8> {
+        let segment = |seg_type, seg_num, offset, size| Segment {
+            offset,
+            size,
+            loading_address: 0x8000_0000 + offset,
+            seg_type,
+            seg_num,
+        };
+        let segments = [
+            segment(SegmentType::Text, 0, 0x100, 0x40),
+            segment(SegmentType::Text, 1, 0x140, 0x24),
+            segment(SegmentType::Data, 0, 0x180, 0x30),
+        ];
+        let header = DOLHeader::from_segments(segments, (0x8010_0000, 0x200), 0x8000_0100).unwrap();
+        let mut dol = Vec::new();
+        header.write(&mut dol).unwrap();
+        for (s, fill) in header.iter_segments().zip(1..) {
+            dol.resize(s.offset as usize, 0);
+            dol.resize(s.offset as usize + s.size, fill);
+        }
+        dol
+    }
+
+    fn repack(dir: &Path) -> Vec<u8> {
+        let mut repacked = Vec::new();
+        DOLHeader::pack(dir, &mut repacked).unwrap();
+        repacked
+    }
+
+    #[test]
+    fn unpacking_and_repacking() {
+        let dol = dol();
+        let dir = TestDir::new();
+        let header = DOLHeader::new(Cursor::new(&dol), 0).unwrap();
+        let (manifest, difference) = header.unpack_checked(Cursor::new(&dol), dir.path()).unwrap();
+        assert_eq!(difference, None);
+        let files: Vec<&str> = manifest.segments.iter().map(|s| s.file.as_str()).collect();
+        assert_eq!(files, ["text0.bin", "text1.bin", "data0.bin"]);
+        assert_eq!(fs::read(dir.join("text1.bin")).unwrap(), [2; 0x24]);
+        assert_eq!(fs::read_to_string(dir.join(BSS_NAME)).unwrap(), "address 0x80100000\nsize 0x200\n");
+        let script = fs::read_to_string(dir.join(LINKER_SCRIPT_NAME)).unwrap();
+        assert!(script.contains("    .data0 0x80000180 : { \"data0.bin\" }\n"), "{}", script);
+        assert!(script.contains("    .bss 0x80100000 (NOLOAD) : { . += 0x200; }\n"), "{}", script);
+        assert!(repack(dir.path()) == dol);
+    }
+
+    #[test]
+    fn segments_without_offsets_are_laid_out_again() {
+        let dol = dol();
+        let dir = TestDir::new();
+        DOLHeader::new(Cursor::new(&dol), 0).unwrap().unpack(Cursor::new(&dol), dir.path()).unwrap();
+        let path = dir.join(SEGMENTS_MANIFEST_NAME);
+        let mut manifest: SegmentsManifest = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        for s in &mut manifest.segments {
+            s.offset = None;
+        }
+        fs::write(&path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        // Bigger, so the ones after it move
+        fs::write(dir.join("text0.bin"), [1; 0x50]).unwrap();
+
+        let repacked = repack(dir.path());
+        let header = DOLHeader::new(Cursor::new(&repacked), 0).unwrap();
+        let offsets: Vec<u64> = header.iter_segments().map(|s| s.offset).collect();
+        assert_eq!(offsets, [0x100, 0x160, 0x1a0]);
+        assert_eq!(repacked.len(), 0x1d0);
+        assert!(repacked[0x160..0x184].iter().all(|&b| b == 2));
+    }
+
+    #[test]
+    fn dols_that_dont_repack_the_same() {
+        let mut dol = dol();
+        // Outside of every segment, so unpacking loses it
+        dol[0x170] = 0xff;
+        let dir = TestDir::new();
+        let header = DOLHeader::new(Cursor::new(&dol), 0).unwrap();
+        assert_eq!(header.unpack_checked(Cursor::new(&dol), dir.path()).unwrap().1, Some(0x170));
+
+        fs::write(dir.join(SEGMENTS_MANIFEST_NAME), b"{\"entry_point\": 0}").unwrap();
+        let e = DOLHeader::pack(dir.path(), Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("Invalid "), "{}", e);
+    }
+}
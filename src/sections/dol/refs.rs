@@ -0,0 +1,138 @@
+// For `analyze-refs`. Games often name the files they load with literal
+// paths in the DOL's data segments, like "/audio/bgm.dsp". A file whose path
+// never shows up there might be unused, but it could just as well be loaded
+// by a path built at runtime or from a REL, so this is only a guess.
+
+use std::{
+    collections::HashSet,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+use crate::sections::{
+    dol::{segment::SegmentType, DOLHeader},
+    fst::{entry::FileEntry, FST},
+};
+
+// Shorter strings are mostly noise, and no path worth matching is this
+// short anyway.
+pub const MIN_STRING_LEN: usize = 4;
+
+// The GameCube has 24MiB of RAM, so no real segment is bigger than this. It
+// bounds the read for a segment whose size is garbage.
+const MAX_SEGMENT_SIZE: u64 = 24 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct DolReferences<'a> {
+    pub referenced: Vec<&'a FileEntry>,
+    pub unreferenced: Vec<&'a FileEntry>,
+}
+
+// Makes paths comparable however the game wrote them: without the leading
+// slash, with forward slashes, and in lowercase, since the disc's file
+// system is case-insensitive.
+pub fn normalize_path(path: &str) -> String {
+    path.trim_start_matches(['/', '\\'])
+        .replace('\\', "/")
+        .to_lowercase()
+}
+
+// The NUL-terminated strings in `data` that are at least `MIN_STRING_LEN`
+// bytes of printable text. Printable means anything but control
+// characters, so names in UTF-8 count too.
+pub fn scan_strings(data: &[u8]) -> impl Iterator<Item = &str> {
+    data.split(|&b| b == 0)
+        // The last piece isn't terminated
+        .take(data.iter().filter(|&&b| b == 0).count())
+        .filter_map(|piece| {
+            let start = piece.iter().rposition(|&b| b < 0x20 || b == 0x7f).map_or(0, |i| i + 1);
+            let s = std::str::from_utf8(&piece[start..]).ok()?;
+            (s.len() >= MIN_STRING_LEN).then_some(s)
+        })
+}
+
+impl DOLHeader {
+    // The total size of the text segments, then of the data segments.
+    pub fn text_size(&self) -> usize {
+        self.iter_segments().filter(|s| s.seg_type == SegmentType::Text).map(|s| s.size).sum()
+    }
+
+    pub fn data_size(&self) -> usize {
+        self.iter_segments().filter(|s| s.seg_type == SegmentType::Data).map(|s| s.size).sum()
+    }
+
+    // Sorts the files in `fst` by whether their paths are in this DOL's data
+    // segments, read from `reader` (where the DOL is at `self.offset`).
+    pub fn find_references<'a>(&self, mut reader: impl Read + Seek, fst: &'a FST) -> io::Result<DolReferences<'a>> {
+        let mut strings = HashSet::new();
+        let mut data = Vec::new();
+        for s in self.iter_segments().filter(|s| s.seg_type == SegmentType::Data) {
+            data.clear();
+            reader.seek(SeekFrom::Start(s.offset))?;
+            reader.by_ref().take((s.size as u64).min(MAX_SEGMENT_SIZE)).read_to_end(&mut data)?;
+            strings.extend(scan_strings(&data).map(normalize_path));
+        }
+
+        let (referenced, unreferenced) = fst.entries().iter()
+            .filter_map(|e| e.as_file())
+            .partition(|f| strings.contains(&normalize_path(&f.info.normalized_path())));
+        Ok(DolReferences { referenced, unreferenced })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{sections::dol::DOL_HEADER_LEN, test_util::{build_image, open_image, synthetic_tree}};
+
+    const TEXT_SIZE: usize = 0x20;
+
+    // A DOL with one text segment and one data segment holding `data`
+    fn dol_with_data(data: &[u8]) -> Vec<u8> {
+        let data_offset = DOL_HEADER_LEN + TEXT_SIZE;
+        let mut dol = vec![0; data_offset + data.len()];
+        dol[0x00..0x04].copy_from_slice(&(DOL_HEADER_LEN as u32).to_be_bytes());
+        dol[0x48..0x4c].copy_from_slice(&0x8000_3100u32.to_be_bytes());
+        dol[0x90..0x94].copy_from_slice(&(TEXT_SIZE as u32).to_be_bytes());
+        dol[0x1c..0x20].copy_from_slice(&(data_offset as u32).to_be_bytes());
+        dol[0x64..0x68].copy_from_slice(&0x8000_4000u32.to_be_bytes());
+        dol[0xac..0xb0].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        dol[0xe0..0xe4].copy_from_slice(&0x8000_3100u32.to_be_bytes());
+        dol[data_offset..].copy_from_slice(data);
+        dol
+    }
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize_path("/data/levels/1.bin"), "data/levels/1.bin");
+        assert_eq!(normalize_path("\\Data\\Levels\\1.BIN"), "data/levels/1.bin");
+        assert_eq!(normalize_path("//readme.txt"), "readme.txt");
+        assert_eq!(normalize_path("Readme.txt"), "readme.txt");
+    }
+
+    #[test]
+    fn scanned_strings() {
+        let data = b"/audio/bgm.dsp\0abc\0\x01\x02/ok.bin\0caf\xc3\xa9.txt\0\xff\xfe\xfd\xfc\0unterminated";
+        let strings: Vec<&str> = scan_strings(data).collect();
+        assert_eq!(strings, ["/audio/bgm.dsp", "/ok.bin", "caf\u{e9}.txt"]);
+        assert_eq!(scan_strings(b"no terminator").count(), 0);
+        assert_eq!(scan_strings(b"").count(), 0);
+    }
+
+    #[test]
+    fn files_named_in_the_data_segments() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let data = b"/data/levels/1.bin\0README.TXT\0\\DATA\\big.bin\0";
+        let mut dol = dol_with_data(data);
+        // Only data segments count, not a path in a text segment
+        dol[DOL_HEADER_LEN..DOL_HEADER_LEN + 11].copy_from_slice(b"/empty.bin\0");
+        let header = DOLHeader::new(Cursor::new(&dol), 0).unwrap();
+        assert_eq!((header.text_size(), header.data_size()), (TEXT_SIZE, data.len()));
+
+        let refs = header.find_references(Cursor::new(&dol), &game.fst).unwrap();
+        let paths = |files: &[&FileEntry]| files.iter().map(|f| f.info.normalized_path()).collect::<Vec<_>>();
+        assert_eq!(paths(&refs.referenced), ["/readme.txt", "/data/levels/1.bin", "/data/big.bin"]);
+        assert_eq!(paths(&refs.unreferenced), ["/empty.bin", "/data/levels/2.bin"]);
+    }
+}
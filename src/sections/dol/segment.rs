@@ -1,8 +1,13 @@
 use std::{fmt, io::{self, Read, Seek, SeekFrom, Write}};
 
-use crate::{format_u64, format_usize, NumberStyle, parse_as_u64, sections::Section};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{parse_as_u64, sections::{Field, Section}};
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum SegmentType {
     Text, Data
 }
@@ -17,6 +22,7 @@ impl SegmentType {
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Segment {
     // NOTE: `offset` is not the offset stored on the ROM.
     // The ROM provides the offset relative to the start of the DOL header,
@@ -51,6 +57,14 @@ impl Segment {
         }
     }
 
+    // Which of the header's 18 entries in each table is this segment's
+    pub(crate) fn header_slot(&self) -> usize {
+        match self.seg_type {
+            SegmentType::Text => self.seg_num as usize,
+            SegmentType::Data => super::TEXT_SEG_COUNT + self.seg_num as usize,
+        }
+    }
+
     pub fn parse_segment_name(name: &str) -> Option<(SegmentType, u64)> {
         let (kind, suffix) =
             if let Some(suffix) = name.strip_prefix(".text") { (SegmentType::Text, suffix) }
@@ -83,11 +97,17 @@ impl fmt::Display for Segment {
 }
 
 impl Section for Segment {
-    fn print_info(&self, style: NumberStyle) {
-        println!("Segment name: {self}");
-        println!("Offset: {}", format_u64(self.offset, style));
-        println!("Size: {}", format_usize(self.size, style));
-        println!("Loading address: {}", format_u64(self.loading_address, style));
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::text("Segment name", self.to_string()),
+            Field::number("Offset", self.offset),
+            Field::number("Size", self.size as u64),
+            Field::number("Loading address", self.loading_address),
+        ]
+    }
+
+    fn name(&self) -> String {
+        self.to_string()
     }
 
     fn start(&self) -> u64 {
@@ -97,4 +117,9 @@ impl Section for Segment {
     fn size(&self) -> usize {
         self.size
     }
+
+    // Part of the DOL, like its header
+    fn is_system_data(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,156 @@
+// For `extract --dedup`, which links files with the same contents to the
+// first copy extracted instead of writing them again.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hash::{Crc32, CrcWriter},
+    io_util::{self, DedupMode, LinkKind},
+    sections::fst::entry::FileEntry,
+};
+
+const COMPARE_CHUNK_SIZE: usize = 0x10000;
+
+// A file that was extracted as a link to another one with the same contents.
+// Recorded in the manifest, so it's clear the files are meant to be
+// identical.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DuplicateFile {
+    pub path: String,
+    pub original: String,
+    pub link: LinkKind,
+}
+
+#[derive(Debug)]
+struct WrittenFile {
+    offset: u64,
+    crc: u32,
+    output: PathBuf,
+    path: String,
+}
+
+#[derive(Debug)]
+pub struct Dedup {
+    mode: DedupMode,
+    // By size, since only files of the same size can match
+    written: HashMap<usize, Vec<WrittenFile>>,
+    pub duplicates: Vec<DuplicateFile>,
+    // Only counting links, not the copies they fell back to
+    pub bytes_saved: u64,
+}
+
+impl Dedup {
+    pub fn new(mode: DedupMode) -> Dedup {
+        Dedup {
+            mode,
+            written: HashMap::new(),
+            duplicates: Vec::new(),
+            bytes_saved: 0,
+        }
+    }
+
+    // What to warn about once everything's extracted: the links that fell
+    // back to something else
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.mode == DedupMode::Reflink && self.duplicates.iter().any(|d| d.link == LinkKind::Hardlink) {
+            warnings.push("Reflinks aren't supported here, so duplicates are hard linked instead. Editing one of them edits all of them.".to_owned());
+        }
+        for d in self.duplicates.iter().filter(|d| d.link == LinkKind::Copy) {
+            warnings.push(format!("Couldn't link {} to {}, so it was copied", d.path, d.original));
+        }
+        warnings
+    }
+
+    // Writes `file` to `output`, or links it to an earlier file with the
+    // same contents. Files are compared by CRC-32 first, but only linked if
+    // all of their bytes match.
+    pub fn extract(&mut self, file: &FileEntry, output: &Path, mut iso: impl BufRead + Seek) -> io::Result<()> {
+        // There's nothing to save on empty files
+        if file.size == 0 {
+            return io_util::replace_file(output).map(drop);
+        }
+
+        let candidates = self.written.get(&file.size).map_or(&[][..], |c| &c[..]);
+        let mut crc = None;
+        let mut original = None;
+        for c in candidates {
+            if c.offset == file.file_offset {
+                original = Some(c);
+                break
+            }
+            let file_crc = match crc {
+                Some(crc) => crc,
+                None => *crc.insert(region_crc(&mut iso, file.file_offset, file.size)?),
+            };
+            if c.crc == file_crc && regions_match(&mut iso, c.offset, file.file_offset, file.size)? {
+                original = Some(c);
+                break
+            }
+        }
+
+        if let Some(original) = original {
+            let link = io_util::link_duplicate(&original.output, output, self.mode)?;
+            if link != LinkKind::Copy {
+                self.bytes_saved += file.size as u64;
+            }
+            self.duplicates.push(DuplicateFile {
+                path: file.info.normalized_path(),
+                original: original.path.clone(),
+                link,
+            });
+            return Ok(());
+        }
+
+        let mut out = CrcWriter::new(io_util::replace_file(output)?);
+        file.extract(&mut iso, &mut out)?;
+        out.flush()?;
+        self.written.entry(file.size).or_default().push(WrittenFile {
+            offset: file.file_offset,
+            crc: out.crc(),
+            output: output.to_owned(),
+            path: file.info.normalized_path(),
+        });
+        Ok(())
+    }
+}
+
+fn region_crc(mut iso: impl Read + Seek, offset: u64, size: usize) -> io::Result<u32> {
+    iso.seek(SeekFrom::Start(offset))?;
+    let mut crc = Crc32::new();
+    let mut buf = vec![0; COMPARE_CHUNK_SIZE];
+    let mut remaining = size;
+    while remaining > 0 {
+        let n = remaining.min(buf.len());
+        iso.read_exact(&mut buf[..n])?;
+        crc.update(&buf[..n]);
+        remaining -= n;
+    }
+    Ok(crc.finish())
+}
+
+fn regions_match(mut iso: impl Read + Seek, a: u64, b: u64, size: usize) -> io::Result<bool> {
+    let mut buf_a = vec![0; COMPARE_CHUNK_SIZE];
+    let mut buf_b = vec![0; COMPARE_CHUNK_SIZE];
+    let mut done = 0;
+    while done < size {
+        let n = (size - done).min(COMPARE_CHUNK_SIZE);
+        iso.seek(SeekFrom::Start(a + done as u64))?;
+        iso.read_exact(&mut buf_a[..n])?;
+        iso.seek(SeekFrom::Start(b + done as u64))?;
+        iso.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+        done += n;
+    }
+    Ok(true)
+}
@@ -1,24 +1,51 @@
 use std::{
-    fs::{create_dir_all, File},
-    io::{self, BufRead, Seek, SeekFrom, Write},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    ffi::OsStr,
+    fmt,
+    fs::{self, create_dir_all, File},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
     path::{self, Path, PathBuf},
+    sync::OnceLock,
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
 use eyre::WrapErr;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    format_u64,
-    format_usize,
-    sections::Section,
-    NumberStyle,
+    hash::Sha1Writer,
+    io_util::{self, DiskFull, FsHints},
+    profiling,
+    report::ProgressSink,
+    sections::{fst::{dedup::Dedup, handler::ExtractHandler, xattrs::OriginAttributes}, Field, Section},
 };
 
 pub const ENTRY_SIZE: usize = 12;
 
-// writes in big endian
+// Longer than any real file name, just so garbage can't make reading the
+// name use unbounded memory.
+pub const MAX_FILENAME_SIZE: u64 = 1024;
+
+// Whether anything `e` was caused by is running out of space.
+fn ran_out_of_space(e: &eyre::Report) -> bool {
+    e.chain().filter_map(|e| e.downcast_ref::<io::Error>()).any(io_util::is_disk_full)
+}
+
+// When extracting stopped with `DiskFull`, makes it say everything that's
+// left needs `bytes_left` bytes, rather than just the file it stopped at.
+pub fn set_bytes_needed(e: eyre::Report, bytes_left: u64) -> eyre::Report {
+    match e.downcast::<DiskFull>() {
+        Ok(full) => eyre::Report::new(DiskFull { bytes_needed: bytes_left.max(full.bytes_needed), ..full }),
+        Err(e) => e,
+    }
+}
+
+// writes in big endian, using however many bytes long `buf` is
 fn write_int_to_buffer(num: u64, buf: &mut [u8]) {
-    buf.copy_from_slice(&num.to_be_bytes())
+    let bytes = num.to_be_bytes();
+    buf.copy_from_slice(&bytes[bytes.len() - buf.len()..])
 }
 
 #[derive(Debug)]
@@ -35,6 +62,131 @@ pub struct EntryInfo {
     pub full_path: PathBuf,
 }
 
+impl EntryInfo {
+    // Whether `name` is this entry's name, ignoring the trailing separator
+    // directory names have.
+    pub fn name_matches(&self, name: impl AsRef<OsStr>) -> bool {
+        let name = name.as_ref();
+        name == &self.name[..]
+            || name == self.name.trim_end_matches(path::MAIN_SEPARATOR)
+    }
+
+    // `full_path` with forward slashes and without the trailing slash
+    // directory names have, like "/audio/us". This is the form paths are
+    // stored in outside of gcmod, like in manifests.
+    pub fn normalized_path(&self) -> String {
+        let mut path = String::new();
+        for c in self.full_path.iter().skip(1) {
+            path.push('/');
+            path.push_str(c.to_string_lossy().trim_end_matches(path::MAIN_SEPARATOR));
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+}
+
+// A file that couldn't be extracted, see `Entry::extract_with_name`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ExtractFailure {
+    pub path: String,
+    pub offset: u64,
+    pub error: String,
+}
+
+// A file that goes past the end of the image, so the end of it was
+// extracted as zeros that were never on it.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PaddedFile {
+    pub path: String,
+    // How many bytes of zeros are on the end
+    pub bytes: u64,
+}
+
+// An entry the way machine-readable listings have it, see `Entry::record`.
+// Directories have no size or offset.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct EntryRecord {
+    pub index: usize,
+    pub name: String,
+    // Like `EntryInfo::normalized_path`
+    pub path: String,
+    // "file" or "directory"
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub kind: &'static str,
+    pub size: Option<usize>,
+    pub offset: Option<u64>,
+}
+
+// What `Entry::extract_with_name` does besides writing each file where it
+// goes.
+#[derive(Debug, Default)]
+pub struct ExtractContext<'a> {
+    // If given, files that can't be extracted get recorded in it, rather
+    // than stopping the extraction.
+    pub failures: Option<&'a mut Vec<ExtractFailure>>,
+    // Files whose indices are in here aren't written at all.
+    pub skip: BTreeSet<usize>,
+    // Files whose indices are in here are written under that name instead
+    // of their own.
+    pub names: BTreeMap<usize, String>,
+    // If given, files with the same contents as one that was already
+    // written get linked to it.
+    pub dedup: Option<&'a mut Dedup>,
+    // If given, where each file came from gets set in its attributes.
+    pub origins: Option<&'a mut OriginAttributes>,
+    // Files that match one of these go to the first one that matches
+    // instead of being copied, see `ExtractHandler`.
+    pub handlers: &'a [ExtractHandler],
+    // Where the image's data really ends, like for trimmed or cut off
+    // images. Files that go past it get recorded in `padded`, or with
+    // `fail_on_truncated`, fail to extract instead.
+    pub data_end: Option<u64>,
+    pub padded: Vec<PaddedFile>,
+    pub fail_on_truncated: bool,
+    // If given, the SHA-1 of each file that was written gets put in it, by
+    // the file's index, for the manifest.
+    pub hashes: Option<BTreeMap<usize, [u8; 20]>>,
+    // If given, what to tell the OS as files are extracted.
+    pub hints: Option<ExtractHints<'a>>,
+    // If given, gets told about each file as it's extracted.
+    pub sink: Option<&'a dyn ProgressSink>,
+}
+
+// The hints `Entry::extract_with_name` gives, see `io_util::FsHints`.
+#[derive(Debug)]
+pub struct ExtractHints<'a> {
+    pub hints: &'static dyn FsHints,
+    // The ROM, if it's read from a file
+    pub source: Option<&'a File>,
+    // Where the file extracted after each one is, by index, if they're
+    // extracted in the order they're stored in
+    pub next: HashMap<usize, (u64, u64)>,
+}
+
+impl ExtractHints<'_> {
+    // Hints are best-effort, so nothing here fails.
+    fn before_copying(&self, index: usize) {
+        if let (Some(source), Some(&(offset, len))) = (self.source, self.next.get(&index)) {
+            let _ = self.hints.will_need(source, offset, len);
+        }
+    }
+
+    fn created(&self, file: &File, len: u64) {
+        let _ = self.hints.preallocate(file, len);
+    }
+}
+
+// What `Entry::extract_with_name` carries down through the directories.
+struct ExtractState<'a, 'b, C> {
+    context: &'b mut ExtractContext<'a>,
+    callback: C,
+}
+
 #[derive(Debug)]
 pub struct FileEntry {
     pub info: EntryInfo,
@@ -59,6 +211,21 @@ pub struct DirectoryEntry {
     // `next_index - info.index` because this field doesn't include the file_count
     // of it's subdirectories
     pub file_count: usize,
+    // What's directly in the directory by name, see `DirectoryEntry::child`.
+    pub children: ChildNames,
+}
+
+// A directory's contents by name, without the trailing separator directory
+// names have. It's built the first time `DirectoryEntry::child` is called,
+// and `FST::entries_mut` and `FST::entry_mut` clear it, since the contents
+// or their names could change through those.
+#[derive(Debug, Default)]
+pub struct ChildNames(OnceLock<HashMap<Box<str>, usize>>);
+
+impl ChildNames {
+    pub fn clear(&mut self) {
+        self.0.take();
+    }
 }
 
 #[derive(Debug)]
@@ -102,6 +269,7 @@ impl Entry {
                 next_index: f3 as usize,
                 // TODO: I don't like setting this to an incorrect, default value here...
                 file_count: 0,
+                children: ChildNames::default(),
             }),
             _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid byte in entry: {:#x}", entry[0]))),
         })
@@ -132,6 +300,22 @@ impl Entry {
         }
     }
 
+    pub fn record(&self) -> EntryRecord {
+        let info = self.info();
+        let (kind, size, offset) = match self {
+            Entry::File(f) => ("file", Some(f.size), Some(f.file_offset)),
+            Entry::Directory(_) => ("directory", None, None),
+        };
+        EntryRecord {
+            index: info.index,
+            name: info.name.trim_end_matches(path::MAIN_SEPARATOR).to_owned(),
+            path: info.normalized_path(),
+            kind,
+            size,
+            offset,
+        }
+    }
+
     pub fn info_mut(&mut self) -> &mut EntryInfo {
         match self {
             Entry::File(ref mut e) => &mut e.info,
@@ -140,14 +324,18 @@ impl Entry {
     }
 
     // move to Game?
+    // `callback` gets called after each file with the amount of files
+    // written and failed so far, and the file.
     pub fn extract_with_name(
         &self,
         filename: impl AsRef<Path>,
         fst: &[Entry],
         mut iso: impl BufRead + Seek,
-        mut callback: impl FnMut(usize),
+        context: &mut ExtractContext,
+        callback: impl FnMut(usize, usize, &FileEntry),
     ) -> eyre::Result<usize> {
-        self.extract_with_name_and_count(filename, fst, &mut iso, 0, &mut callback)
+        let mut state = ExtractState { context, callback };
+        self.extract_with_name_and_count(filename, fst, &mut iso, 0, &mut state)
     }
 
     fn extract_with_name_and_count(
@@ -156,7 +344,7 @@ impl Entry {
         fst: &[Entry],
         iso: &mut (impl BufRead + Seek),
         start_count: usize,
-        callback: &mut impl FnMut(usize),
+        state: &mut ExtractState<'_, '_, impl FnMut(usize, usize, &FileEntry)>,
     ) -> eyre::Result<usize> {
         let mut count = start_count;
 
@@ -164,23 +352,162 @@ impl Entry {
             Entry::Directory(ref d) => {
                 create_dir_all(filename.as_ref())
                     .wrap_err_with(|| format!("Failed to create output directory {:?})", filename.as_ref()))?;
-                for e in d.iter_contents(fst) {
+                let mut contents = d.iter_contents(fst);
+                for e in contents.by_ref() {
+                    let name = state.context.names.get(&e.info().index).unwrap_or(&e.info().name);
+                    // Its name couldn't be read, so there's nothing to call
+                    // it here, see `FST::unnamed_entries`
+                    if name.trim_end_matches(path::MAIN_SEPARATOR).is_empty() {
+                        let error = format!(
+                            "Entry {}, in directory {}, has no name, so it can only be extracted on its own, with idx:{}",
+                            e.info().index, d.info.index, e.info().index,
+                        );
+                        match state.context.failures.as_deref_mut() {
+                            Some(failures) => failures.push(ExtractFailure {
+                                path: d.info.normalized_path(),
+                                offset: e.as_file().map_or(0, |f| f.file_offset),
+                                error,
+                            }),
+                            None => return Err(eyre::eyre!("{}", error)),
+                        }
+                        continue;
+                    }
                     count += e.extract_with_name_and_count(
-                        filename.as_ref().join(&e.info().name),
+                        filename.as_ref().join(name),
                         fst,
                         iso,
                         count,
-                        callback,
+                        state,
                     )?;
                 }
+                for e in contents.errors() {
+                    match state.context.failures.as_deref_mut() {
+                        Some(failures) => failures.push(ExtractFailure {
+                            path: d.info.normalized_path(),
+                            // Directories aren't anywhere on the ROM
+                            offset: 0,
+                            error: e.to_string(),
+                        }),
+                        None => return Err(eyre::eyre!("{}", e))
+                            .wrap_err_with(|| format!("Failed to extract directory {}", d.info.normalized_path())),
+                    }
+                }
             },
+            Entry::File(ref f) if state.context.skip.contains(&f.info.index) => {},
             Entry::File(ref f) => {
-                let mut out = File::create(filename.as_ref())
-                    .wrap_err_with(|| format!("Failed to create output file {:?}", filename.as_ref()))?;
-                f.extract(iso, &mut out)
-                    .wrap_err_with(|| format!("Failed to copy file {:?}", f.info.full_path))?;
-                count += 1;
-                callback(count);
+                // How many files were done before this one
+                let done = (count + state.context.failures.as_ref().map_or(0, |f| f.len())) as u64;
+                if let Some(sink) = state.context.sink {
+                    sink.file_started(done, &f.info.normalized_path());
+                }
+                if let Some(hints) = &state.context.hints {
+                    hints.before_copying(f.info.index);
+                }
+                let padding = state.context.data_end.map_or(0, |end| f.bytes_past(end));
+                let handler = match state.context.handlers {
+                    [] => None,
+                    handlers => {
+                        let path = f.info.normalized_path();
+                        handlers.iter().find(|h| h.matches(&path))
+                    },
+                };
+                // Checked before anything is created for it, since handlers
+                // and the allocation hints would make a file of its full size
+                let fits = f.check_fits(&mut *iso);
+                let handled = match handler {
+                    _ if padding != 0 && state.context.fail_on_truncated => Err(eyre::eyre!(
+                        "{} goes {} bytes past the end of the image",
+                        f.info.normalized_path(), padding,
+                    )),
+                    _ if fits.is_err() => fits.map(|()| None)
+                        .wrap_err_with(|| format!("Failed to extract file {:?}", f.info.full_path)),
+                    Some(h) => h.run(f, filename.as_ref(), &mut *iso)
+                        .wrap_err_with(|| format!("Failed to handle file {:?}", f.info.full_path)),
+                    None => Ok(None),
+                };
+                // Plain copies get hashed as they're written, and anything
+                // else is read again for it
+                let hashing = state.context.hashes.is_some();
+                let mut hash = None;
+                // The files that were written for `f`
+                let result = match handled {
+                    Ok(Some(outputs)) => Ok(outputs),
+                    Err(e) => Err(e),
+                    Ok(None) => match state.context.dedup.as_deref_mut() {
+                        Some(dedup) => dedup.extract(f, filename.as_ref(), &mut *iso)
+                            .wrap_err_with(|| format!("Failed to extract file {:?}", f.info.full_path)),
+                        None => io_util::replace_file(filename.as_ref())
+                            .wrap_err_with(|| format!("Failed to create output file {:?}", filename.as_ref()))
+                            .and_then(|out| {
+                                if let Some(hints) = &state.context.hints {
+                                    hints.created(&out, f.size as u64);
+                                }
+                                let mut out = Sha1Writer::new(out);
+                                f.extract(&mut *iso, &mut out)
+                                    .wrap_err_with(|| format!("Failed to copy file {:?}", f.info.full_path))?;
+                                hash = hashing.then(|| out.finish().1);
+                                Ok(())
+                            }),
+                    }.map(|()| vec![filename.as_ref().to_owned()]),
+                };
+                // Running out of space stops everything, even with
+                // `failures`, since every file after it would fail too. What
+                // was written of this one goes, so it's never mistaken for
+                // the whole file.
+                let result = match result {
+                    Err(e) if ran_out_of_space(&e) => {
+                        let left_behind = match fs::remove_file(filename.as_ref()) {
+                            Err(e) if e.kind() != io::ErrorKind::NotFound => Some(e),
+                            _ => None,
+                        };
+                        return Err(eyre::Report::new(DiskFull {
+                            path: f.info.normalized_path(),
+                            output: filename.as_ref().to_owned(),
+                            bytes_needed: f.size as u64,
+                            available: io_util::available_space(io_util::parent_dir(filename.as_ref())),
+                            left_behind,
+                        }));
+                    },
+                    result => result,
+                };
+                let result = match (result, &mut state.context.hashes) {
+                    (Ok(outputs), Some(hashes)) => match hash {
+                        Some(hash) => Ok(hash),
+                        None => f.sha1(&mut *iso).wrap_err_with(|| format!("Failed to hash file {:?}", f.info.full_path)),
+                    }.map(|hash| {
+                        hashes.insert(f.info.index, hash);
+                        outputs
+                    }),
+                    (result, _) => result,
+                };
+                match (result, state.context.failures.as_deref_mut()) {
+                    // A handled file counts as one file however many outputs
+                    // it has, so the total stays the FST's file count
+                    (Ok(outputs), _) => {
+                        if let Some(origins) = state.context.origins.as_deref_mut() {
+                            for output in &outputs {
+                                origins.record(f, output);
+                            }
+                        }
+                        if padding != 0 {
+                            state.context.padded.push(PaddedFile { path: f.info.normalized_path(), bytes: padding });
+                        }
+                        if let Some(sink) = state.context.sink {
+                            sink.bytes_written(f.size as u64);
+                        }
+                        count += 1;
+                    },
+                    (Err(e), Some(failures)) => failures.push(ExtractFailure {
+                        path: f.info.normalized_path(),
+                        offset: f.file_offset,
+                        error: format!("{:#}", e),
+                    }),
+                    (Err(e), None) => return Err(e),
+                }
+                if let Some(sink) = state.context.sink {
+                    sink.file_finished(done, &f.info.normalized_path());
+                }
+                (state.callback)(count, state.context.failures.as_ref().map_or(0, |f| f.len()), f);
             },
         }
 
@@ -197,10 +524,19 @@ impl Entry {
         if info.index == 0 {
             info.name = path::MAIN_SEPARATOR.to_string();
         } else {
-            reader.seek(SeekFrom::Start(str_tbl_addr + info.filename_offset))?;
+            let name_addr = str_tbl_addr.checked_add(info.filename_offset)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "File name offset out of range"))?;
+            reader.seek(SeekFrom::Start(name_addr))?;
             let mut bytes = Vec::new();
-            reader.read_until(0, &mut bytes)?;
-            bytes.pop(); // Discard null terminator
+            reader.by_ref().take(MAX_FILENAME_SIZE + 1).read_until(0, &mut bytes)?;
+            if bytes.last() == Some(&0) {
+                bytes.pop(); // Discard null terminator
+            } else if bytes.len() as u64 > MAX_FILENAME_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("The name of entry {} is more than {} bytes long", info.index, MAX_FILENAME_SIZE),
+                ));
+            }
             info.name = String::from_utf8_lossy(&bytes).into_owned();
             if is_directory {
                 info.name.push(path::MAIN_SEPARATOR);
@@ -210,12 +546,24 @@ impl Entry {
     }
 
     pub fn format_long(&self) -> String {
+        self.format_long_named(&self.info().full_path.to_string_lossy())
+    }
+
+    // Like `format_long`, but with `name` in place of the path.
+    pub fn format_long_named(&self, name: &str) -> String {
         let (ftype, size) = match self {
             Entry::File(f) => ('-', f.size),
             Entry::Directory(d) => ('d', d.file_count),
         };
-        // 2^32 - 1 is 10 digits wide in decimal
-        format!("{} {:>10} {}", ftype, size, self.info().full_path.to_string_lossy())
+        // 2^24 - 1 (the most entries the string table has room for) is 8
+        // digits wide, and 2^32 - 1 is 10
+        format!(
+            "{} {:>8} {:>10} {}",
+            ftype,
+            self.info().index,
+            size,
+            name,
+        )
     }
 
     pub fn as_dir(&self) -> Option<&DirectoryEntry> {
@@ -261,16 +609,55 @@ impl Entry {
 
 impl FileEntry {
     // TODO: rename this
+    // Copies the file to `file`. Files that go past the end of `reader` fail
+    // before anything is written, so a garbage size in the FST can't turn
+    // into gigabytes of zeros. For trimmed images, the reader goes on to the
+    // disc's capacity with zeros, see `ZeroExtended`.
     pub fn extract<R, W>(&self, mut reader: R, mut file: W) -> io::Result<()>
     where
         R: BufRead + Seek,
         W: Write,
     {
+        self.check_fits(&mut reader)?;
+        let mut timer = profiling::start(profiling::copy_operation(self.size as u64));
         reader.seek(SeekFrom::Start(self.file_offset))?;
-        io::copy(
-            &mut reader.take(self.size as u64),
-            &mut file,
-        ).map(drop)
+        let copied = io::copy(&mut reader.take(self.size as u64), &mut file)?;
+        timer.add_bytes(copied);
+        if copied < self.size as u64 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+                "{} was cut off after {} of its {} bytes",
+                self.info.normalized_path(), copied, self.size,
+            )));
+        }
+        Ok(())
+    }
+
+    // Fails if the file goes past the end of `reader`, like when its size in
+    // the FST is garbage.
+    pub fn check_fits(&self, mut reader: impl Seek) -> io::Result<()> {
+        let end = self.file_offset + self.size as u64;
+        let len = reader.seek(SeekFrom::End(0))?;
+        if end > len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+                "{} ends at {:#x}, past the end of the disc at {:#x}",
+                self.info.normalized_path(), end, len,
+            )));
+        }
+        Ok(())
+    }
+
+    // The SHA-1 of what `extract` writes.
+    pub fn sha1(&self, reader: impl BufRead + Seek) -> io::Result<[u8; 20]> {
+        let mut out = Sha1Writer::new(io::sink());
+        self.extract(reader, &mut out)?;
+        Ok(out.finish().1)
+    }
+
+    // How many of the file's bytes are past `end`, where the image's data
+    // ends.
+    pub fn bytes_past(&self, end: u64) -> u64 {
+        let file_end = self.file_offset + self.size as u64;
+        file_end - end.clamp(self.file_offset, file_end)
     }
 }
 
@@ -278,12 +665,77 @@ impl DirectoryEntry {
     pub fn iter_contents<'a>(&'a self, fst: &'a [Entry]) -> DirectoryIter<'a> {
         DirectoryIter::new(self, fst)
     }
+
+    // Everything inside of the directory, all the way down, in FST order.
+    // Each directory's entries follow it, so that's also depth-first order.
+    pub fn iter_descendants<'a>(&self, fst: &'a [Entry]) -> std::slice::Iter<'a, Entry> {
+        let end = self.next_index.min(fst.len());
+        fst.get(self.info.index + 1..end).unwrap_or_default().iter()
+    }
+
+    // The entry directly inside of the directory called `name`, without going
+    // through its contents again after the first lookup. When two have the
+    // same name, the first one in FST order wins, like it does for
+    // `FST::entry_for_path`.
+    pub fn child<'a>(&self, name: &str, fst: &'a [Entry]) -> Option<&'a Entry> {
+        let names = self.children.0.get_or_init(|| {
+            let mut names = HashMap::new();
+            for e in self.iter_contents(fst) {
+                let info = e.info();
+                names.entry(info.name.trim_end_matches(path::MAIN_SEPARATOR).into()).or_insert(info.index);
+            }
+            names
+        });
+        names.get(name).and_then(|&i| fst.get(i))
+    }
+}
+
+// Something wrong with the next indices that `DirectoryIter` worked around.
+// The FST parser rejects all of these, but they can still come from the
+// mutation API or lenient parsing.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FstWalkError {
+    // The directory's next index is past the end of the FST.
+    OutOfRange { directory: usize, next_index: usize, len: usize },
+    // A subdirectory's next index isn't after it, so it's treated as empty.
+    NotAdvancing { directory: usize, next_index: usize },
+    // A subdirectory's next index is past the end of the directory it's in,
+    // so the directory ends with it.
+    Overflow { directory: usize, next_index: usize, parent: usize, parent_next_index: usize },
 }
 
+impl fmt::Display for FstWalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FstWalkError::OutOfRange { directory, next_index, len } => write!(
+                f,
+                "Directory {} has next index {}, but the file system table only has {} entries",
+                directory, next_index, len,
+            ),
+            FstWalkError::NotAdvancing { directory, next_index } => write!(
+                f,
+                "Directory {} has next index {}, which isn't after it, so it's treated as empty",
+                directory, next_index,
+            ),
+            FstWalkError::Overflow { directory, next_index, parent, parent_next_index } => write!(
+                f,
+                "Directory {} has next index {}, past the end of directory {} at {}",
+                directory, next_index, parent, parent_next_index,
+            ),
+        }
+    }
+}
+
+impl Error for FstWalkError {}
+
+// Goes through a directory's direct contents. It never panics or gets stuck
+// on bad next indices, it just stops or skips ahead, and `errors` has what
+// it ran into for callers that care.
 pub struct DirectoryIter<'a> {
     dir: &'a DirectoryEntry,
     fst: &'a [Entry],
     current_index: usize,
+    errors: Vec<FstWalkError>,
 }
 
 impl<'a> DirectoryIter<'a> {
@@ -292,33 +744,73 @@ impl<'a> DirectoryIter<'a> {
             dir,
             fst,
             current_index: dir.info.index + 1,
+            errors: Vec::new(),
         }
     }
+
+    // The problems found so far, so they're all here once it's done.
+    pub fn errors(&self) -> &[FstWalkError] {
+        &self.errors
+    }
 }
 
 impl<'a> Iterator for DirectoryIter<'a> {
     type Item = &'a Entry;
 
     fn next(&mut self) -> Option<&'a Entry> {
-        if self.current_index < self.dir.next_index {
-            let res = &self.fst[self.current_index];
-            let step = match res {
-                Entry::File(_) => 1,
-                Entry::Directory(ref d) => d.next_index - self.current_index,
-            };
-            self.current_index += step;
-            Some(res)
-        } else {
-            None
+        if self.current_index >= self.dir.next_index {
+            return None;
         }
+        let res = match self.fst.get(self.current_index) {
+            Some(e) => e,
+            None => {
+                self.errors.push(FstWalkError::OutOfRange {
+                    directory: self.dir.info.index,
+                    next_index: self.dir.next_index,
+                    len: self.fst.len(),
+                });
+                self.current_index = self.dir.next_index;
+                return None;
+            },
+        };
+        // Always move forward, even if a subdirectory ends before it starts,
+        // so this can't loop forever.
+        self.current_index = match res {
+            Entry::File(_) => self.current_index + 1,
+            Entry::Directory(ref d) if d.next_index <= self.current_index => {
+                self.errors.push(FstWalkError::NotAdvancing {
+                    directory: self.current_index,
+                    next_index: d.next_index,
+                });
+                self.current_index + 1
+            },
+            Entry::Directory(ref d) if d.next_index > self.dir.next_index => {
+                self.errors.push(FstWalkError::Overflow {
+                    directory: self.current_index,
+                    next_index: d.next_index,
+                    parent: self.dir.info.index,
+                    parent_next_index: self.dir.next_index,
+                });
+                self.dir.next_index
+            },
+            Entry::Directory(ref d) => d.next_index,
+        };
+        Some(res)
     }
 }
 
 impl Section for FileEntry {
-    fn print_info(&self, style: NumberStyle) {
-        println!("Path: {}", self.info.full_path.to_string_lossy());
-        println!("Offset: {}", format_u64(self.file_offset, style));
-        println!("Size: {}", format_usize(self.size, style));
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::text("Path", self.info.full_path.to_string_lossy()),
+            Field::text("Index", self.info.index.to_string()),
+            Field::number("Offset", self.file_offset),
+            Field::number("Size", self.size as u64),
+        ]
+    }
+
+    fn name(&self) -> String {
+        self.info.full_path.to_string_lossy().into_owned()
     }
 
     fn start(&self) -> u64 {
@@ -329,3 +821,47 @@ impl Section for FileEntry {
         self.size
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(file_offset: u64, size: usize) -> FileEntry {
+        let info = EntryInfo {
+            index: 1,
+            name: "a.bin".to_owned(),
+            filename_offset: 0,
+            directory_index: Some(0),
+            full_path: PathBuf::from("/a.bin"),
+        };
+        FileEntry { info, file_offset, size }
+    }
+
+    #[test]
+    fn files_past_the_end_arent_padded() {
+        let image = crate::synthetic::contents(0x2000);
+        let mut out = Vec::new();
+        file(0x1000, 0x1000).extract(io::Cursor::new(&image), &mut out).unwrap();
+        assert!(out == image[0x1000..]);
+
+        // Not even the part that's there gets written
+        let mut out = Vec::new();
+        let e = file(0x1000, 0x1001).extract(io::Cursor::new(&image), &mut out).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(e.to_string(), "/a.bin ends at 0x2001, past the end of the disc at 0x2000");
+        assert!(out.is_empty());
+        assert!(file(0x1000, usize::MAX >> 1).sha1(io::Cursor::new(&image)).is_err());
+    }
+
+    #[test]
+    fn bytes_past_the_end() {
+        let f = file(0x1000, 0x100);
+        assert_eq!(f.bytes_past(0x2000), 0);
+        assert_eq!(f.bytes_past(0x1100), 0);
+        assert_eq!(f.bytes_past(0x10ff), 1);
+        assert_eq!(f.bytes_past(0x1000), 0x100);
+        // All of it, even starting past the end
+        assert_eq!(f.bytes_past(0x800), 0x100);
+        assert_eq!(file(0x1000, 0).bytes_past(0), 0);
+    }
+}
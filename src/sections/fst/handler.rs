@@ -0,0 +1,220 @@
+// Hooks for library users that need to do something with certain files as
+// they're extracted, like unpacking a container, without reading the ROM
+// again afterwards. A handler gets each matching file's contents and makes
+// whatever outputs it wants next to where the file would have gone.
+
+use std::{
+    fmt,
+    fs::create_dir_all,
+    io::{self, BufRead, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    io_util,
+    known_files::glob_match,
+    magic,
+    profiling,
+    sections::fst::entry::{EntryInfo, FileEntry},
+};
+
+const YAZ0_HEADER_SIZE: usize = 16;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HandlerOutcome {
+    // The handler wrote everything there is to write for the file.
+    Handled,
+    // The file gets extracted like any other. Any outputs the handler
+    // already made are left alone.
+    Fallback,
+}
+
+// Makes an output file, given its path relative to the directory the file
+// being handled is in. It has to stay inside of that directory. Writes are
+// buffered, so flush the output to see any error writing the end of it.
+pub type OutputSink<'a> = dyn FnMut(&Path) -> io::Result<Box<dyn Write>> + 'a;
+
+pub type HandlerFn = dyn Fn(&EntryInfo, &mut dyn Read, &mut OutputSink<'_>) -> io::Result<HandlerOutcome>;
+
+pub struct ExtractHandler {
+    // Like the ones in `known_files`: with a slash, it's matched against the
+    // file's path on the ROM, and without one, against just its name.
+    pattern: String,
+    handler: Box<HandlerFn>,
+}
+
+impl fmt::Debug for ExtractHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtractHandler").field("pattern", &self.pattern).finish_non_exhaustive()
+    }
+}
+
+impl ExtractHandler {
+    pub fn new<H>(pattern: impl Into<String>, handler: H) -> ExtractHandler
+    where
+        H: Fn(&EntryInfo, &mut dyn Read, &mut OutputSink<'_>) -> io::Result<HandlerOutcome> + 'static,
+    {
+        ExtractHandler { pattern: pattern.into(), handler: Box::new(handler) }
+    }
+
+    // `path` should be normalized, see `EntryInfo::normalized_path`.
+    pub fn matches(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let text = if self.pattern.contains('/') { path } else { name };
+        glob_match(&self.pattern, text)
+    }
+
+    // Runs the handler on `file`, which would be extracted to `filename`.
+    // Returns the outputs it made if it handled the file, or `None` if it
+    // should be extracted like usual.
+    pub(crate) fn run(
+        &self,
+        file: &FileEntry,
+        filename: &Path,
+        mut iso: impl BufRead + Seek,
+    ) -> io::Result<Option<Vec<PathBuf>>> {
+        let mut timer = profiling::start("extract_handler");
+        timer.add_bytes(file.size as u64);
+        let dir = filename.parent().unwrap_or(Path::new(""));
+        let mut outputs = Vec::new();
+        let mut sink = |path: &Path| -> io::Result<Box<dyn Write>> {
+            if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The handler for {} made an output outside of its directory, {:?}", file.info.normalized_path(), path),
+                ));
+            }
+            let output = dir.join(path);
+            if let Some(parent) = output.parent() {
+                create_dir_all(parent)?;
+            }
+            let writer = BufWriter::new(io_util::replace_file(&output)?);
+            outputs.push(output);
+            Ok(Box::new(writer))
+        };
+
+        iso.seek(SeekFrom::Start(file.file_offset))?;
+        let mut contents = (&mut iso).take(file.size as u64);
+        match (self.handler)(&file.info, &mut contents, &mut sink)? {
+            HandlerOutcome::Handled => Ok(Some(outputs)),
+            HandlerOutcome::Fallback => Ok(None),
+        }
+    }
+}
+
+// A handler that extracts Yaz0 compressed files decompressed instead, for
+// patterns like "*.szs". The extension becomes whatever `magic::sniff`
+// recognizes in the decompressed data, like "a.szs" to "a.arc", or goes
+// away if it doesn't recognize anything. Files that aren't really Yaz0 are
+// extracted as they are. Rebuilding a tree extracted this way puts the
+// decompressed files on the ROM, under their new names.
+pub fn decompress_yaz0(
+    info: &EntryInfo,
+    contents: &mut dyn Read,
+    sink: &mut OutputSink<'_>,
+) -> io::Result<HandlerOutcome> {
+    let mut compressed = Vec::new();
+    contents.read_to_end(&mut compressed)?;
+    if compressed.len() < YAZ0_HEADER_SIZE || &compressed[..4] != magic::YAZ0_MAGIC {
+        return Ok(HandlerOutcome::Fallback);
+    }
+    let data = yaz0_decompress(&compressed).map_err(|e| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} isn't valid Yaz0 ({})", info.normalized_path(), e),
+    ))?;
+
+    let name = Path::new(&info.name);
+    let name = match magic::sniff(&data[..data.len().min(magic::SNIFF_SIZE)]) {
+        Some(kind) => name.with_extension(kind.extension()),
+        None => name.with_extension(""),
+    };
+    let mut output = sink(&name)?;
+    output.write_all(&data)?;
+    output.flush()?;
+    Ok(HandlerOutcome::Handled)
+}
+
+// Each group of 8 chunks starts with a byte whose bits say which are
+// literal bytes (1) and which are copies of earlier output (0). A copy is
+// 2 bytes, a distance back of up to 0x1000 and a length of 3 to 17, or 3
+// bytes for lengths of 18 to 0x111.
+fn yaz0_decompress(compressed: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let size = u32::from_be_bytes(compressed[4..8].try_into().unwrap()) as usize;
+    // The size is from the file, so it isn't trusted up front. No chunk
+    // goes from less than 2 bytes to more than 0x111.
+    let mut data = Vec::with_capacity(size.min(compressed.len() * 0x111 / 2));
+    let mut input = compressed[YAZ0_HEADER_SIZE..].iter().copied();
+    let mut next = || input.next().ok_or("it ends early");
+    while data.len() < size {
+        let code = next()?;
+        for bit in (0..8).rev() {
+            if data.len() >= size {
+                break;
+            }
+            if code & (1 << bit) != 0 {
+                data.push(next()?);
+                continue;
+            }
+            let (b1, b2) = (next()? as usize, next()? as usize);
+            let distance = ((b1 & 0xf) << 8 | b2) + 1;
+            let length = match b1 >> 4 {
+                0 => next()? as usize + 0x12,
+                n => n + 2,
+            };
+            if distance > data.len() {
+                return Err("a copy starts before the beginning");
+            }
+            // Copies can overlap what they're copying, so this goes a byte
+            // at a time
+            let start = data.len() - distance;
+            for i in 0..length.min(size - data.len()) {
+                data.push(data[start + i]);
+            }
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaz0(size: u32, chunks: &[u8]) -> Vec<u8> {
+        let mut compressed = magic::YAZ0_MAGIC.to_vec();
+        compressed.extend_from_slice(&size.to_be_bytes());
+        compressed.resize(YAZ0_HEADER_SIZE, 0);
+        compressed.extend_from_slice(chunks);
+        compressed
+    }
+
+    #[test]
+    fn patterns_match_names_or_paths() {
+        let handler = |pattern| ExtractHandler::new(pattern, |_: &EntryInfo, _: &mut dyn Read, _: &mut OutputSink<'_>| {
+            Ok(HandlerOutcome::Handled)
+        });
+        assert!(handler("*.szs").matches("/a/b/c.szs"));
+        assert!(!handler("*.szs").matches("/a/b/c.arc"));
+        assert!(handler("/a/*/c.szs").matches("/a/b/c.szs"));
+        assert!(!handler("/a/*.szs").matches("/b/c.szs"));
+    }
+
+    #[test]
+    fn decompressing_literals_and_copies() {
+        // 3 literals, then 9 bytes copied from 3 back, overlapping itself
+        assert_eq!(yaz0_decompress(&yaz0(12, &[0xe0, b'a', b'b', b'c', 0x70, 0x02])).unwrap(), b"abcabcabcabc");
+        // A copy with a third byte for its length, 0x12 more than it says
+        assert_eq!(yaz0_decompress(&yaz0(20, &[0xc0, b'a', b'b', 0x00, 0x01, 0x00])).unwrap(), b"ab".repeat(10));
+        // Stopping at the size, even partway through a copy
+        assert_eq!(yaz0_decompress(&yaz0(5, &[0xe0, b'a', b'b', b'c', 0x70, 0x02])).unwrap(), b"abcab");
+        assert_eq!(yaz0_decompress(&yaz0(0, &[])).unwrap(), b"");
+    }
+
+    #[test]
+    fn invalid_yaz0() {
+        assert_eq!(yaz0_decompress(&yaz0(12, &[0xe0, b'a', b'b'])), Err("it ends early"));
+        assert_eq!(yaz0_decompress(&yaz0(12, &[0xe0, b'a', b'b', b'c', 0x70])), Err("it ends early"));
+        assert_eq!(yaz0_decompress(&yaz0(12, &[0x00, 0x10, 0x00])), Err("a copy starts before the beginning"));
+        // A huge size, that the data doesn't have
+        assert_eq!(yaz0_decompress(&yaz0(u32::MAX, &[0xff, 1, 2, 3, 4, 5, 6, 7, 8])), Err("it ends early"));
+    }
+}
@@ -1,25 +1,129 @@
 use std::{
     cmp::max,
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt,
     io::{self, BufRead, Read, Seek, SeekFrom, Write},
-    path::{Path, PathBuf},
+    path::{self, Component, Path, PathBuf},
+    sync::OnceLock,
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::{
-    format_u64,
-    format_usize,
-    NumberStyle,
-    sections::Section,
+    align,
+    layout_ref::{reading, Block},
+    parse_as_u64,
+    sections::{Field, Section},
 };
 
+pub mod dedup;
 pub mod entry;
-use entry::{DirectoryEntry, Entry, EntryInfo, ENTRY_SIZE};
+pub mod handler;
+pub mod probe;
+pub mod recover;
+pub mod strict;
+pub mod suggest;
+pub mod summary;
+pub mod xattrs;
+use entry::{ChildNames, DirectoryEntry, Entry, EntryInfo, ExtractContext, FileEntry, ENTRY_SIZE};
+use recover::FstRepair;
 
 pub const FST_OFFSET_OFFSET: u64 = 0x0424;
 pub const FST_SIZE_OFFSET: u64 = 0x0428;
 
+// The entry count comes from the ROM, so it isn't trusted for allocating
+// space up front. Real games have a few thousand entries at most.
+const MAX_PREALLOCATED_ENTRIES: usize = 0x10000;
+
+// Real FSTs are well under a MiB, so anything over this is most likely a
+// corrupt size, see `ParseOptions::max_buffered_fst_size`.
+pub const DEFAULT_MAX_BUFFERED_FST_SIZE: usize = 64 * 1024 * 1024;
+
+pub const INDEX_SELECTOR_PREFIX: &str = "idx:";
+// After a directory's selector, like "/audio#fst", to extract just the part of
+// the FST for that directory, see `FST::subtree`.
+pub const SUBTREE_SELECTOR_SUFFIX: &str = "#fst";
+
+// What entries whose parents go in a circle have their paths under, since
+// they aren't under the root
+pub const CYCLE_DIR_NAME: &str = "<cycle>";
+
+#[derive(Debug)]
+pub enum FstError {
+    NotFound(String),
+    NotADirectory(String),
+}
+
+impl fmt::Display for FstError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FstError::NotFound(selector) => write!(f, "{} does not exist", selector),
+            FstError::NotADirectory(selector) => write!(f, "{} isn't a directory", selector),
+        }
+    }
+}
+
+impl Error for FstError {}
+
+// How a ROM gets read, for `Game::open_with` and `FST::open_with`. There
+// will be more of these, so only the setters can be counted on.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ParseOptions {
+    // FSTs up to this size are read into memory at once instead of seeking
+    // around the ROM for every name. Bigger ones are read entry by entry, so
+    // a garbage size in the header can't make gcmod allocate gigabytes.
+    pub max_buffered_fst_size: usize,
+    // Fail to open games whose apploader says it's bigger than the space
+    // before the DOL and FST, instead of only counting what fits. Then
+    // `Apploader::oversized` says it's too big, for warning about it.
+    pub strict_apploader: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            max_buffered_fst_size: DEFAULT_MAX_BUFFERED_FST_SIZE,
+            strict_apploader: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn max_buffered_fst_size(mut self, size: usize) -> ParseOptions {
+        self.max_buffered_fst_size = size;
+        self
+    }
+
+    pub fn strict_apploader(mut self, strict: bool) -> ParseOptions {
+        self.strict_apploader = strict;
+        self
+    }
+}
+
+// Whether an FST was read into memory first, and if so, how big the buffer
+// was.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "mode", rename_all = "lowercase"))]
+pub enum FstRead {
+    Streamed,
+    Buffered { bytes: usize },
+}
+
+// Past this, files lining up on a bigger power of two is more likely to be
+// chance than how the image was laid out.
+pub const MAX_DETECTED_ALIGNMENT: u64 = 1024 * 1024;
+
+// How much of the disc a file of `size` bytes takes up when every file
+// starts on a multiple of `alignment`.
+pub fn aligned_file_size(size: usize, alignment: u64) -> u64 {
+    align(size as u64, alignment)
+}
+
 #[derive(Debug)]
 pub struct FST {
     /*
@@ -28,9 +132,96 @@ pub struct FST {
      */
     pub offset: u64,
     pub file_count: usize,
-    pub total_file_system_size: usize,
-    pub entries: Vec<Entry>,
+    pub total_file_size: usize,
+    // Behind `entries` and `entries_mut`, so the path index can't go stale.
+    entries: Vec<Entry>,
     pub size: usize,
+    // Built the first time a path is looked up, see `PathIndex`.
+    index: OnceLock<PathIndex>,
+    // What had to be fixed to read it, see `FST::recover`. Empty for any
+    // FST that isn't malformed.
+    pub repairs: Vec<FstRepair>,
+    pub read: FstRead,
+}
+
+// How far `FST::resolve` got with a path.
+#[derive(Debug)]
+pub enum Resolution<'a> {
+    Found(&'a Entry),
+    // The directory at `parent` (like "/audio") has nothing called
+    // `missing`. `parent` is None for a bare name, which could be anywhere.
+    Missing { parent: Option<String>, missing: String },
+    // The path goes on past `file`, or ends with a slash after it.
+    NotADirectory { file: &'a Entry },
+    // Paths on the ROM can't go up with "..", or have anything but plain
+    // names in them.
+    Invalid,
+}
+
+impl Resolution<'_> {
+    // Why the path doesn't lead to an entry, like "/audio has nothing called
+    // us". None for `Found`.
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            Resolution::Found(_) => None,
+            Resolution::Missing { parent: Some(parent), missing } =>
+                Some(format!("{} has nothing called {}", parent, missing)),
+            Resolution::Missing { parent: None, missing } => Some(format!("nothing on the ROM is called {}", missing)),
+            Resolution::NotADirectory { file } =>
+                Some(format!("{} is a file, not a directory", file.info().normalized_path())),
+            Resolution::Invalid => Some("paths on the ROM can only have plain names in them".to_owned()),
+        }
+    }
+}
+
+// For looking up entries without going through the whole FST each time.
+// It's thrown away whenever `entries` can be changed, see `FST::entries_mut`,
+// along with each directory's `ChildNames`.
+#[derive(Debug)]
+struct PathIndex {
+    // By normalized path, see `EntryInfo::normalized_path`.
+    paths: HashMap<Box<str>, usize>,
+    // By name, without the trailing separator directory names have.
+    // For both, the first entry in FST order wins when there are duplicates,
+    // since that's the one a depth-first search would find.
+    names: HashMap<Box<str>, usize>,
+}
+
+impl PathIndex {
+    fn new(entries: &[Entry]) -> PathIndex {
+        let mut paths = HashMap::with_capacity(entries.len());
+        let mut names = HashMap::with_capacity(entries.len());
+        for e in entries {
+            let info = e.info();
+            paths.entry(info.normalized_path().into()).or_insert(info.index);
+            // The root doesn't have a name of its own
+            if info.index != 0 {
+                let name = info.name.trim_end_matches(path::MAIN_SEPARATOR);
+                names.entry(name.into()).or_insert(info.index);
+            }
+        }
+        PathIndex { paths, names }
+    }
+}
+
+// An absolute path normalized the same way the paths in `PathIndex` are, or
+// None if it has anything but plain names in it.
+fn normalized_path(path: &Path) -> Option<String> {
+    let mut normalized = String::new();
+    for c in path.components() {
+        match c {
+            Component::RootDir => {},
+            Component::Normal(name) => {
+                normalized.push('/');
+                normalized.push_str(name.to_str()?);
+            },
+            _ => return None,
+        }
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
 }
 
 impl FST {
@@ -40,17 +231,19 @@ impl FST {
 
         let mut entry_buffer: [u8; ENTRY_SIZE] = [0; ENTRY_SIZE];
         iso.take(ENTRY_SIZE as u64).read_exact(&mut entry_buffer)?;
-        let root = Entry::new(&entry_buffer, 0, None)
-            .expect("Couldn't read root fst entry.");
+        let root = Entry::new(&entry_buffer, 0, None)?;
         let entry_count = root.as_dir()
-            .expect("Root fst wasn't a directory.")
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The root of the file system table isn't a directory",
+            ))?
             .next_index;
 
-        let mut entries = Vec::with_capacity(entry_count);
+        let mut entries = Vec::with_capacity(entry_count.min(MAX_PREALLOCATED_ENTRIES));
         entries.push(root);
 
         let mut file_count = 0;
-        let mut total_file_system_size = 0;
+        let mut total_file_size = 0;
 
         // (parent_index, index of next file not in the parent dir, # of files in this parent)
         let mut parents = vec![(0, entry_count, 0)];
@@ -72,9 +265,18 @@ impl FST {
             match &e {
                 Entry::File(f) => {
                     file_count += 1;
-                    total_file_system_size += f.size;
+                    total_file_size += f.size;
                 },
                 Entry::Directory(d) => {
+                    // Directories have to end after they start and before
+                    // their parent does, or iterating over them never ends.
+                    let parent_end = parents.last().map_or(entry_count, |p| p.1);
+                    if d.next_index <= index || d.next_index > parent_end {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Directory entry {} has an invalid end index {}", index, d.next_index),
+                        ));
+                    }
                     parents.push((index, d.next_index, 0));
                 },
             }
@@ -97,21 +299,204 @@ impl FST {
         let mut fst = FST {
             offset,
             file_count,
-            total_file_system_size,
+            total_file_size,
             entries,
             size,
+            index: OnceLock::new(),
+            repairs: Vec::new(),
+            read: FstRead::Streamed,
         };
 
-        // Note: I'm not using `for e in &mut fst.entries`
-        // because of borrow checking...
-        for i in 0..fst.entries.len() {
-            let path = fst.get_full_path(fst.entries[i].info());
-            fst.entries[i].info_mut().full_path = path;
+        fst.fill_full_paths();
+        Ok(fst)
+    }
+
+    // A file system with nothing in it but the root directory.
+    pub fn empty() -> FST {
+        let root = Entry::Directory(DirectoryEntry {
+            info: EntryInfo {
+                index: 0,
+                name: String::new(),
+                filename_offset: 0,
+                directory_index: None,
+                full_path: "/".into(),
+            },
+            parent_index: 0,
+            next_index: 1,
+            file_count: 0,
+            children: ChildNames::default(),
+        });
+        FST {
+            offset: 0,
+            file_count: 0,
+            total_file_size: 0,
+            entries: vec![root],
+            // Plus the root's empty name
+            size: ENTRY_SIZE + 1,
+            index: OnceLock::new(),
+            repairs: Vec::new(),
+            read: FstRead::Streamed,
         }
+    }
+
+    // For entries that already have their full paths set.
+    pub fn with_entries(
+        offset: u64,
+        entries: Vec<Entry>,
+        file_count: usize,
+        total_file_size: usize,
+        size: usize,
+    ) -> FST {
+        FST {
+            offset,
+            file_count,
+            total_file_size,
+            entries,
+            size,
+            index: OnceLock::new(),
+            repairs: Vec::new(),
+            read: FstRead::Streamed,
+        }
+    }
 
+    // What the files take up with each one padded to a multiple of
+    // `alignment`, the way the rebuilder lays them out, so it's how much
+    // room rebuilding them with that alignment needs. `total_file_size` is
+    // the same without the padding.
+    pub fn total_aligned_size(&self, alignment: u64) -> u64 {
+        self.entries.iter()
+            .filter_map(Entry::as_file)
+            .map(|f| aligned_file_size(f.size, alignment))
+            .sum()
+    }
+
+    // The biggest power of two, up to `MAX_DETECTED_ALIGNMENT`, that every
+    // file with anything in it starts on a multiple of, counting from
+    // `base`, where the image starts. None without any files like that.
+    pub fn detected_alignment(&self, base: u64) -> Option<u64> {
+        let offsets = self.entries.iter()
+            .filter_map(Entry::as_file)
+            .filter(|f| f.size != 0)
+            .map(|f| f.file_offset.wrapping_sub(base))
+            .reduce(|a, b| a | b)?;
+        Some((1 << offsets.trailing_zeros().min(MAX_DETECTED_ALIGNMENT.trailing_zeros())) as u64)
+    }
+
+    // A standalone FST for the directory `selector` names (see
+    // `entry_for_selector`), with it as the root. Everything is renumbered
+    // and the string table is packed again. Files keep their offsets on the
+    // ROM, unless `rebase_offsets` is set, in which case they're moved so the
+    // first one starts at 0.
+    pub fn subtree(&self, selector: &str, rebase_offsets: bool) -> Result<FST, FstError> {
+        let dir = self.entry_for_selector(selector)
+            .ok_or_else(|| FstError::NotFound(selector.to_owned()))?
+            .as_dir()
+            .ok_or_else(|| FstError::NotADirectory(selector.to_owned()))?;
+        let base = dir.info.index;
+        let descendants = dir.iter_descendants(&self.entries);
+        let first_offset = descendants.clone()
+            .filter_map(|e| e.as_file())
+            .map(|f| f.file_offset)
+            .min()
+            .unwrap_or(0);
+        let offset_base = if rebase_offsets { first_offset } else { 0 };
+
+        let mut entries = Vec::with_capacity(descendants.len() + 1);
+        entries.push(Entry::Directory(DirectoryEntry {
+            info: EntryInfo {
+                index: 0,
+                name: path::MAIN_SEPARATOR.to_string(),
+                filename_offset: 0,
+                directory_index: None,
+                full_path: PathBuf::new(),
+            },
+            parent_index: 0,
+            next_index: dir.next_index.min(self.entries.len()).saturating_sub(base),
+            file_count: dir.file_count,
+            children: ChildNames::default(),
+        }));
+
+        let mut filename_offset = 0;
+        let mut file_count = 0;
+        let mut total_file_size = 0;
+        for e in descendants {
+            let old = e.info();
+            let info = EntryInfo {
+                index: old.index - base,
+                name: old.name.clone(),
+                filename_offset,
+                directory_index: old.directory_index.map(|i| i.saturating_sub(base)),
+                full_path: PathBuf::new(),
+            };
+            // Written without the trailing separator, plus 1 for the null byte
+            filename_offset += old.name.trim_end_matches(path::MAIN_SEPARATOR).len() as u64 + 1;
+            entries.push(match e {
+                Entry::File(f) => {
+                    file_count += 1;
+                    total_file_size += f.size;
+                    Entry::File(FileEntry {
+                        info,
+                        file_offset: f.file_offset - offset_base,
+                        size: f.size,
+                    })
+                },
+                Entry::Directory(d) => Entry::Directory(DirectoryEntry {
+                    info,
+                    parent_index: d.parent_index.saturating_sub(base),
+                    next_index: d.next_index.saturating_sub(base),
+                    file_count: d.file_count,
+                    children: ChildNames::default(),
+                }),
+            });
+        }
+
+        // The root's empty name is all there is to an empty table
+        let size = entries.len() * ENTRY_SIZE + max(filename_offset, 1) as usize;
+        let mut fst = FST::with_entries(0, entries, file_count, total_file_size, size);
+        fst.fill_full_paths();
         Ok(fst)
     }
 
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    // The path index is rebuilt on the next lookup after this, so entries
+    // can be added, removed, and renamed through it. Renamed entries still
+    // need `fill_full_paths` for their paths to change.
+    pub fn entries_mut(&mut self) -> &mut Vec<Entry> {
+        self.invalidate_index();
+        &mut self.entries
+    }
+
+    pub fn entry(&self, index: usize) -> Option<&Entry> {
+        self.entries.get(index)
+    }
+
+    // The entry could be renamed through this, so the path index has to be
+    // rebuilt afterwards.
+    pub fn entry_mut(&mut self, index: usize) -> Option<&mut Entry> {
+        self.invalidate_index();
+        self.entries.get_mut(index)
+    }
+
+    // Builds the path index now instead of on the first lookup, for callers
+    // that want to pay for it up front.
+    pub fn build_index(&self) {
+        self.path_index();
+    }
+
+    fn invalidate_index(&mut self) {
+        self.index.take();
+        for d in self.entries.iter_mut().filter_map(Entry::as_dir_mut) {
+            d.children.clear();
+        }
+    }
+
+    fn path_index(&self) -> &PathIndex {
+        self.index.get_or_init(|| PathIndex::new(&self.entries))
+    }
+
     pub fn root(&self) -> &DirectoryEntry {
         self.entries[0].as_dir().unwrap()
     }
@@ -120,18 +505,23 @@ impl FST {
         &mut self,
         path: impl AsRef<Path>,
         iso: impl BufRead + Seek,
-        callback: impl FnMut(usize),
+        context: &mut ExtractContext,
+        callback: impl FnMut(usize, usize, &FileEntry),
     ) -> eyre::Result<usize> {
-        self.entries[0].extract_with_name(path, &self.entries, iso, callback)
+        self.entries[0].extract_with_name(path, &self.entries, iso, context, callback)
     }
 
+    // `base` is where the image starts in `iso`, and `fst_offset` is
+    // absolute, like `FST::offset`. The size is the one in the header.
     pub fn extract(
         mut iso: impl Read + Seek,
         mut file: impl Write,
+        base: u64,
         fst_offset: u64,
     ) -> io::Result<()> {
-        iso.seek(SeekFrom::Start(FST_SIZE_OFFSET))?;
-        let size = iso.read_u32::<BigEndian>()? as usize;
+        debug_assert!(fst_offset >= base, "FST offset {:#x} is before the image at {:#x}", fst_offset, base);
+        iso.seek(SeekFrom::Start(base + FST_SIZE_OFFSET))?;
+        let size = iso.read_u32::<BigEndian>().map_err(reading(Block::Header, "fst_size"))? as usize;
 
         iso.seek(SeekFrom::Start(fst_offset))?;
         io::copy(
@@ -144,7 +534,11 @@ impl FST {
         let mut sorted_names = BTreeMap::new();
         for e in &self.entries {
             e.write(&mut writer)?;
-            sorted_names.insert(e.info().filename_offset, &e.info().name);
+            // Directory names read from a ROM have a separator on the end,
+            // which isn't part of the name on the ROM.
+            let name = e.info().name.as_str();
+            let name = if e.is_dir() { name.trim_end_matches(path::MAIN_SEPARATOR) } else { name };
+            sorted_names.insert(e.info().filename_offset, name);
         }
         let null_byte = [0];
         for name in sorted_names.values() {
@@ -156,58 +550,220 @@ impl FST {
 
     pub fn entry_for_path(&self, path: impl AsRef<Path>) -> Option<&Entry> {
         let path = path.as_ref();
+        let index = self.path_index();
         if path.is_relative() {
             // Just treat the entire `path` like a single filename in this case
-            self.entry_with_name(path, self.root())
+            let name = path.to_str()?.trim_end_matches(path::MAIN_SEPARATOR);
+            index.names.get(name).and_then(|&i| self.entry(i))
         } else {
-            // For each component in `path` (skipping the initial "/"),
-            // try to find the corresponding file with that name
-            path.iter().skip(1).try_fold(&self.entries[0], |entry, name| {
-                entry.as_dir().and_then(|dir| {
-                    dir.iter_contents(&self.entries).find(|e| &e.info().name[..] == name)
-                })
-            })
+            let normalized = normalized_path(path)?;
+            index.paths.get(normalized.as_str()).and_then(|&i| self.entry(i))
         }
     }
 
-    fn entry_with_name<'a>(&'a self, name: impl AsRef<Path>, dir: &'a DirectoryEntry) -> Option<&'a Entry> {
-        let name = name.as_ref();
-        dir.iter_contents(&self.entries).find_map(|e| {
-            if name.as_os_str() == &e.info().name[..] {
-                Some(e)
-            } else {
-                e.as_dir().and_then(|subdir| self.entry_with_name(name, subdir))
+    // Like `entry_for_path`, but says where it stopped when there's nothing
+    // at `path`. Unlike `entry_for_path`, a file with a slash after it isn't
+    // found.
+    pub fn resolve<'a>(&'a self, path: &str) -> Resolution<'a> {
+        let index = self.path_index();
+        let dir_only = path.ends_with('/');
+        let found = |e: &'a Entry| -> Resolution<'a> {
+            if dir_only && !e.is_dir() { Resolution::NotADirectory { file: e } } else { Resolution::Found(e) }
+        };
+        let path = Path::new(path);
+        if path.is_relative() {
+            let Some(name) = path.to_str().map(|n| n.trim_end_matches(path::MAIN_SEPARATOR)) else {
+                return Resolution::Invalid;
+            };
+            return match index.names.get(name).and_then(|&i| self.entry(i)) {
+                Some(e) => found(e),
+                None => Resolution::Missing { parent: None, missing: name.to_owned() },
+            };
+        }
+
+        // The whole path first, since two directories with the same name
+        // can each have different things in them, and only the first one's
+        // are in its parent's `ChildNames`
+        let Some(normalized) = normalized_path(path) else {
+            return Resolution::Invalid;
+        };
+        if let Some(e) = index.paths.get(normalized.as_str()).and_then(|&i| self.entry(i)) {
+            return found(e);
+        }
+
+        // Then one component at a time, to say where it stopped
+        let mut current = &self.entries[0];
+        let mut parent = String::from("/");
+        for name in normalized.split('/').filter(|n| !n.is_empty()) {
+            let Some(dir) = current.as_dir() else {
+                return Resolution::NotADirectory { file: current };
+            };
+            current = match dir.child(name, &self.entries) {
+                Some(e) => e,
+                None => return Resolution::Missing { parent: Some(parent), missing: name.to_owned() },
+            };
+            if parent.len() > 1 {
+                parent.push('/');
             }
-        })
+            parent.push_str(name);
+        }
+        found(current)
+    }
+
+    // Either "idx:" followed by an entry's index, like "idx:12", or a path for
+    // `entry_for_path`.
+    pub fn entry_for_selector(&self, selector: &str) -> Option<&Entry> {
+        match selector.strip_prefix(INDEX_SELECTOR_PREFIX) {
+            Some(index) => parse_as_u64(index).ok()
+                .and_then(|i| usize::try_from(i).ok())
+                .and_then(|i| self.entry(i)),
+            None => self.entry_for_path(selector),
+        }
+    }
+
+    // The entries other than the root without names, like when the string
+    // table is past the end of a truncated image, which reads as zeros.
+    // They can only be found by index.
+    pub fn unnamed_entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().skip(1).filter(|e| e.info().name.trim_end_matches(path::MAIN_SEPARATOR).is_empty())
     }
 
     pub fn get_parent_for_entry(&self, entry: &EntryInfo) -> Option<&Entry> {
-        entry.directory_index.map(|i| &self.entries[i])
+        entry.directory_index.and_then(|i| self.entry(i))
+    }
+
+    // Sets every entry's `full_path` from their names and parent indices,
+    // with a `FstRepair::ParentCycle` for each circle of parents.
+    pub fn fill_full_paths(&mut self) {
+        let cycles = self.parent_cycles();
+        let in_cycle: HashSet<usize> = cycles.iter().flatten().copied().collect();
+        // Note: I'm not using `for e in &mut self.entries`
+        // because of borrow checking...
+        for i in 0..self.entries.len() {
+            let path = self.get_full_path(self.entries[i].info(), &in_cycle);
+            self.entries[i].info_mut().full_path = path;
+        }
+        self.repairs.extend(cycles.into_iter().map(|indices| FstRepair::ParentCycle { indices }));
+        self.invalidate_index();
     }
 
-    fn get_full_path(&self, entry: &EntryInfo) -> PathBuf {
+    // The directories whose parent indices go in a circle, which FSTs that
+    // were put together wrong can have, sorted for each circle. Each entry
+    // is only followed once, however long the chains are.
+    fn parent_cycles(&self) -> Vec<Vec<usize>> {
+        // 0 for not seen yet, 1 for on the chain being followed, 2 for done
+        let mut state = vec![0u8; self.entries.len()];
+        let mut cycles = Vec::new();
+        for start in 0..self.entries.len() {
+            let mut chain = Vec::new();
+            let mut next = Some(start);
+            while let Some(i) = next.filter(|&i| state[i] == 0) {
+                state[i] = 1;
+                chain.push(i);
+                next = self.get_parent_for_entry(self.entries[i].info()).map(|p| p.info().index);
+            }
+            if let Some(i) = next.filter(|&i| state[i] == 1) {
+                let mut cycle = chain.split_off(chain.iter().position(|&c| c == i).unwrap());
+                for &c in &cycle {
+                    state[c] = 2;
+                }
+                cycle.sort_unstable();
+                cycles.push(cycle);
+            }
+            for i in chain {
+                state[i] = 2;
+            }
+        }
+        cycles
+    }
+
+    // Entries whose parents never get to the root, because they go in a
+    // circle, get a path under `CYCLE_DIR_NAME` instead, with the names up to
+    // the circle and around it once.
+    fn get_full_path(&self, entry: &EntryInfo, in_cycle: &HashSet<usize>) -> PathBuf {
         let mut parent = entry;
-        let mut names = vec![&entry.name];
+        let mut names = vec![entry.name.as_str()];
+        // Where the walk stops, once it's in a circle
+        let mut cycle_start = in_cycle.contains(&entry.index).then_some(entry.index);
 
         while let Some(p) = self.get_parent_for_entry(parent) {
+            let index = p.info().index;
+            if cycle_start == Some(index) {
+                break;
+            }
+            if cycle_start.is_none() && in_cycle.contains(&index) {
+                cycle_start = Some(index);
+            }
             parent = p.info();
             names.push(&parent.name);
         }
 
+        if cycle_start.is_some() {
+            names.push(CYCLE_DIR_NAME);
+            names.push(path::MAIN_SEPARATOR_STR);
+        }
         names.iter().rev().collect()
     }
 }
 
+// What `info -t fst` shows, for `--json`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FstInfo {
+    pub offset: u64,
+    pub entries: usize,
+    pub files: usize,
+    pub total_file_size: usize,
+    // See `FST::detected_alignment`
+    pub alignment: Option<u64>,
+    // With each file padded to `alignment`
+    pub total_aligned_size: Option<u64>,
+    pub size: usize,
+    pub read: FstRead,
+}
+
+impl FST {
+    pub fn info(&self) -> FstInfo {
+        let alignment = self.detected_alignment(0);
+        FstInfo {
+            offset: self.offset,
+            entries: self.entries.len(),
+            files: self.file_count,
+            total_file_size: self.total_file_size,
+            alignment,
+            total_aligned_size: alignment.map(|a| self.total_aligned_size(a)),
+            size: self.size,
+            read: self.read,
+        }
+    }
+}
+
 impl Section for FST {
-    fn print_info(&self, style: NumberStyle) {
-        println!("Offset: {}", format_u64(self.offset, style));
-        println!("Total entries: {}", format_usize(self.entries.len(), style));
-        println!("Total files: {}", format_usize(self.file_count, style));
-        println!(
-            "Total space used by files: {} bytes",
-            format_usize(self.total_file_system_size, style),
-        );
-        println!("Size: {} bytes", format_usize(self.size, style));
+    fn fields(&self) -> Vec<Field> {
+        let info = self.info();
+        let mut fields = vec![
+            Field::number("Offset", info.offset),
+            Field::number("Total entries", info.entries as u64),
+            Field::number("Total files", info.files as u64),
+            Field::bytes("Total file size", info.total_file_size as u64),
+        ];
+        if let (Some(alignment), Some(total)) = (info.alignment, info.total_aligned_size) {
+            fields.push(Field::bytes("Alignment the files are at", alignment));
+            fields.push(Field::bytes("Total with padding to that alignment", total));
+        }
+        fields.push(Field::bytes("Size", info.size as u64));
+        match info.read {
+            FstRead::Streamed => fields.push(Field::text("Read", "entry by entry")),
+            FstRead::Buffered { bytes } => {
+                fields.push(Field::text("Read", "all at once"));
+                fields.push(Field::bytes("Read buffer", bytes as u64));
+            },
+        }
+        fields
+    }
+
+    fn name(&self) -> String {
+        "Game.toc".to_owned()
     }
 
     fn start(&self) -> u64 {
@@ -217,4 +773,477 @@ impl Section for FST {
     fn size(&self) -> usize {
         self.size
     }
+
+    fn is_system_data(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+    use crate::{
+        sections::fst::entry::{FstWalkError, MAX_FILENAME_SIZE},
+        test_util::{build_image, open_image, synthetic_tree, TestDir},
+    };
+
+    fn entry(flags: u8, name_offset: u32, a: u32, b: u32) -> Vec<u8> {
+        let mut bytes = vec![flags];
+        bytes.extend_from_slice(&name_offset.to_be_bytes()[1..]);
+        bytes.extend_from_slice(&a.to_be_bytes());
+        bytes.extend_from_slice(&b.to_be_bytes());
+        bytes
+    }
+
+    fn parse(entries: &[Vec<u8>], names: &[u8]) -> io::Result<FST> {
+        FST::new(Cursor::new([&entries.concat()[..], names].concat()), 0)
+    }
+
+    fn invalid_data(result: io::Result<FST>) -> String {
+        let e = result.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e);
+        e.to_string()
+    }
+
+    #[test]
+    fn totals_with_and_without_padding() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let fst = &game.fst;
+        assert_eq!(fst.total_file_size, 64 + 4096 + 1000 + 1_060_921);
+        // Only big.bin's size isn't a multiple of 4, and the empty file
+        // takes up nothing
+        assert_eq!(fst.total_aligned_size(4), fst.total_file_size as u64 + 3);
+        assert_eq!(fst.detected_alignment(0), Some(0x8000));
+        assert_eq!(fst.total_aligned_size(0x8000), 3 * 0x8000 + 33 * 0x8000);
+        assert_eq!(fst.detected_alignment(0x4000), Some(0x4000));
+        let info = fst.info();
+        assert_eq!((info.alignment, info.total_aligned_size), (Some(0x8000), Some(36 * 0x8000)));
+    }
+
+    #[test]
+    fn a_small_fst_parses() {
+        let fst = small();
+        assert_eq!(fst.file_count, 1);
+        assert!(fst.entry_for_path("/dir/a.bin").is_some());
+    }
+
+    // Found by fuzzing: the root's entry count used to be what was allocated
+    #[test]
+    fn huge_entry_counts_dont_allocate() {
+        let e = parse(&[entry(1, 0, 0, u32::MAX)], b"").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    // Found by fuzzing: a name with no NUL read to the end of the image
+    #[test]
+    fn names_without_an_end() {
+        let name = vec![b'a'; MAX_FILENAME_SIZE as usize + 1];
+        let text = invalid_data(parse(&[entry(1, 0, 0, 2), entry(0, 0, 0, 0)], &name));
+        assert!(text.contains("more than 1024 bytes"), "{}", text);
+
+        // A name that runs into the end of the image is cut off there
+        let fst = parse(&[entry(1, 0, 0, 2), entry(0, 0, 0, 0)], b"abc").unwrap();
+        assert_eq!(fst.entries()[1].info().name, "abc");
+    }
+
+    // Found by fuzzing: the name's address could overflow
+    #[test]
+    fn name_offsets_past_the_end_of_memory() {
+        let mut e = Entry::new(&entry(0, 0xff_ffff, 0, 0), 1, Some(0)).unwrap();
+        let e = e.read_filename(Cursor::new(Vec::new()), u64::MAX - 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    // Found by fuzzing: a directory that ended before it started never
+    // stopped being iterated over
+    #[test]
+    fn directories_have_to_end_inside_their_parent() {
+        let text = invalid_data(parse(&[entry(1, 0, 0, 3), entry(1, 0, 0, 1), entry(0, 0, 0, 0)], b"a\0"));
+        assert!(text.contains("Directory entry 1 has an invalid end index 1"), "{}", text);
+        let text = invalid_data(parse(&[entry(1, 0, 0, 3), entry(1, 0, 0, 4), entry(0, 0, 0, 0)], b"a\0"));
+        assert!(text.contains("invalid end index 4"), "{}", text);
+    }
+
+    fn small() -> FST {
+        parse(&[entry(1, 0, 0, 3), entry(1, 0, 0, 3), entry(0, 4, 0x8000, 5)], b"dir\0a.bin\0").unwrap()
+    }
+
+    #[test]
+    fn entries_by_index() {
+        let mut fst = small();
+        assert_eq!(fst.entry(2).unwrap().info().normalized_path(), "/dir/a.bin");
+        assert!(fst.entry(3).is_none() && fst.entry(usize::MAX).is_none());
+        assert!(fst.entry_mut(3).is_none());
+        for selector in ["idx:2", "idx:0x2", "/dir/a.bin"] {
+            assert_eq!(fst.entry_for_selector(selector).unwrap().info().index, 2, "{}", selector);
+        }
+        for selector in ["idx:3", "idx:", "idx:two", "idx:99999999999999999999"] {
+            assert!(fst.entry_for_selector(selector).is_none(), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn parents_out_of_range() {
+        let fst = small();
+        let mut info = EntryInfo {
+            index: 2,
+            name: "a.bin".to_owned(),
+            filename_offset: 4,
+            directory_index: Some(100),
+            full_path: "/dir/a.bin".into(),
+        };
+        assert!(fst.get_parent_for_entry(&info).is_none());
+        info.directory_index = Some(1);
+        assert_eq!(fst.get_parent_for_entry(&info).unwrap().info().index, 1);
+    }
+
+    fn info(index: usize, name: &str, directory_index: Option<usize>) -> EntryInfo {
+        EntryInfo { index, name: name.to_owned(), filename_offset: 0, directory_index, full_path: PathBuf::new() }
+    }
+
+    fn dir(index: usize, name: &str, parent: usize, next_index: usize) -> Entry {
+        let directory_index = (index != 0).then_some(parent);
+        Entry::Directory(DirectoryEntry { info: info(index, name, directory_index), parent_index: parent, next_index, file_count: 0, children: ChildNames::default() })
+    }
+
+    fn file(index: usize, name: &str, parent: usize) -> Entry {
+        Entry::File(FileEntry { info: info(index, name, Some(parent)), file_offset: 0x8000, size: 0 })
+    }
+
+    // FST::new takes parents from where entries are, so these can only be put
+    // together from entries
+    #[test]
+    fn parents_that_go_in_a_circle() {
+        let entries = vec![
+            dir(0, "/", 0, 8),
+            // a and b are each other's parent, and c is its own
+            dir(1, "a/", 2, 4),
+            dir(2, "b/", 1, 4),
+            file(3, "f", 2),
+            dir(4, "c/", 4, 6),
+            file(5, "g", 4),
+            dir(6, "d/", 0, 8),
+            file(7, "h", 6),
+        ];
+        let mut fst = FST::with_entries(0, entries, 3, 0, 8 * ENTRY_SIZE);
+        fst.fill_full_paths();
+
+        let paths: Vec<String> = fst.entries().iter().map(|e| e.info().normalized_path()).collect();
+        assert_eq!(paths, ["/", "/<cycle>/b/a", "/<cycle>/a/b", "/<cycle>/a/b/f", "/<cycle>/c", "/<cycle>/c/g", "/d", "/d/h"]);
+        let cycles: Vec<&[usize]> = fst.repairs.iter().map(|r| match r {
+            FstRepair::ParentCycle { indices } => &indices[..],
+            r => panic!("{}", r),
+        }).collect();
+        assert_eq!(cycles, [&[1, 2][..], &[4]]);
+        assert_eq!(
+            fst.repairs[0].to_string(),
+            "Directories 1, 2 have parent indices that go in a circle, so they have paths under <cycle>",
+        );
+        assert!(fst.entry_for_path("/d/h").is_some());
+    }
+
+    fn resolved(fst: &FST, path: &str) -> Result<usize, String> {
+        match fst.resolve(path) {
+            Resolution::Found(e) => Ok(e.info().index),
+            r => Err(r.problem().unwrap()),
+        }
+    }
+
+    #[test]
+    fn resolving_paths() {
+        let fst = open_image(&build_image(synthetic_tree().path())).fst;
+        let index = |path| fst.entry_for_path(path).unwrap().info().index;
+        assert_eq!(resolved(&fst, "/"), Ok(0));
+        assert_eq!(resolved(&fst, "/readme.txt"), Ok(index("/readme.txt")));
+        assert_eq!(resolved(&fst, "/data/levels/"), Ok(index("/data/levels")));
+        assert_eq!(resolved(&fst, "/data//levels/./1.bin"), Ok(index("/data/levels/1.bin")));
+        // Bare names are looked for anywhere
+        assert_eq!(resolved(&fst, "big.bin"), Ok(index("/data/big.bin")));
+        assert_eq!(resolved(&fst, "big.bin/"), Err("/data/big.bin is a file, not a directory".to_owned()));
+        assert_eq!(resolved(&fst, "huge.bin"), Err("nothing on the ROM is called huge.bin".to_owned()));
+    }
+
+    #[test]
+    fn where_resolving_stops() {
+        let fst = open_image(&build_image(synthetic_tree().path())).fst;
+        // A file with a slash after it, or more after it
+        assert_eq!(resolved(&fst, "/readme.txt/"), Err("/readme.txt is a file, not a directory".to_owned()));
+        assert_eq!(resolved(&fst, "/data/big.bin/more/parts"), Err("/data/big.bin is a file, not a directory".to_owned()));
+        // The part that doesn't exist, and where
+        assert_eq!(resolved(&fst, "/audio/us/bgm.dsp"), Err("/ has nothing called audio".to_owned()));
+        assert_eq!(resolved(&fst, "/data/levls/1.bin"), Err("/data has nothing called levls".to_owned()));
+        assert_eq!(resolved(&fst, "/data/levels/3.bin"), Err("/data/levels has nothing called 3.bin".to_owned()));
+        assert_eq!(resolved(&fst, "/empty/x"), Err("/empty has nothing called x".to_owned()));
+        for path in ["/data/../readme.txt", "/.."] {
+            assert!(matches!(fst.resolve(path), Resolution::Invalid), "{}", path);
+        }
+    }
+
+    // The parser rejects these, but entries can be changed afterwards
+    #[test]
+    fn walking_broken_directories() {
+        let mut fst = small();
+        let indices = |fst: &FST, dir: usize| {
+            let mut iter = fst.entry(dir).unwrap().as_dir().unwrap().iter_contents(fst.entries());
+            let indices: Vec<usize> = iter.by_ref().map(|e| e.info().index).collect();
+            (indices, iter.errors().to_vec())
+        };
+        assert_eq!(indices(&fst, 0), (vec![1], vec![]));
+
+        fst.entry_mut(0).unwrap().as_dir_mut().unwrap().next_index = 10;
+        let (found, errors) = indices(&fst, 0);
+        assert_eq!(found, [1]);
+        assert_eq!(errors, [FstWalkError::OutOfRange { directory: 0, next_index: 10, len: 3 }]);
+
+        fst.entry_mut(0).unwrap().as_dir_mut().unwrap().next_index = 3;
+        fst.entry_mut(1).unwrap().as_dir_mut().unwrap().next_index = 1;
+        let (found, errors) = indices(&fst, 0);
+        assert_eq!(found, [1, 2]);
+        assert_eq!(errors, [FstWalkError::NotAdvancing { directory: 1, next_index: 1 }]);
+
+        fst.entry_mut(1).unwrap().as_dir_mut().unwrap().next_index = 5;
+        let (found, errors) = indices(&fst, 0);
+        assert_eq!(found, [1]);
+        assert_eq!(errors, [FstWalkError::Overflow { directory: 1, next_index: 5, parent: 0, parent_next_index: 3 }]);
+    }
+
+    #[test]
+    fn walking_backward_and_huge_next_indices() {
+        let mut fst = small();
+        let walk = |fst: &FST| {
+            let mut iter = fst.entry(0).unwrap().as_dir().unwrap().iter_contents(fst.entries());
+            let indices: Vec<usize> = iter.by_ref().map(|e| e.info().index).collect();
+            (indices, iter.errors().to_vec())
+        };
+        // Pointing back at its parent
+        fst.entry_mut(1).unwrap().as_dir_mut().unwrap().next_index = 0;
+        assert_eq!(walk(&fst), (vec![1, 2], vec![FstWalkError::NotAdvancing { directory: 1, next_index: 0 }]));
+
+        fst.entry_mut(1).unwrap().as_dir_mut().unwrap().next_index = usize::MAX;
+        assert_eq!(walk(&fst), (vec![1], vec![
+            FstWalkError::Overflow { directory: 1, next_index: usize::MAX, parent: 0, parent_next_index: 3 },
+        ]));
+        fst.entry_mut(1).unwrap().as_dir_mut().unwrap().next_index = 3;
+        fst.entry_mut(0).unwrap().as_dir_mut().unwrap().next_index = usize::MAX;
+        assert_eq!(walk(&fst), (vec![1], vec![
+            FstWalkError::OutOfRange { directory: 0, next_index: usize::MAX, len: 3 },
+        ]));
+    }
+
+    #[test]
+    fn extracting_broken_directories() {
+        let tree = TestDir::new();
+        let mut fst = small();
+        fst.entry_mut(1).unwrap().as_dir_mut().unwrap().next_index = 1;
+        let mut iso = vec![0; 0x8000];
+        iso.extend_from_slice(b"hello");
+        let root = fst.entry(0).unwrap();
+        let extract = |to: &str, context: &mut ExtractContext| {
+            root.extract_with_name(tree.join(to), fst.entries(), Cursor::new(&iso), context, |_, _, _| {})
+        };
+
+        let e = extract("strict", &mut ExtractContext::default()).unwrap_err();
+        assert!(format!("{:#}", e).contains("Directory 1 has next index 1"), "{:#}", e);
+
+        let mut failures = Vec::new();
+        let mut context = ExtractContext { failures: Some(&mut failures), ..ExtractContext::default() };
+        extract("keep-going", &mut context).unwrap();
+        assert_eq!(failures.len(), 1, "{:?}", failures);
+        // The directory it's in is the one whose walk went wrong
+        assert_eq!(failures[0].path, "/");
+        // Its file is still found, after it
+        assert_eq!(fs::read(tree.join("keep-going/a.bin")).unwrap(), b"hello");
+    }
+
+    // Every entry is found by its path, the way the index was built, and by
+    // its name in its parent
+    fn assert_index_matches(fst: &FST) {
+        for e in fst.entries() {
+            let path = e.info().normalized_path();
+            assert_eq!(fst.entry_for_path(&path).map(|f| f.info().index), Some(e.info().index), "{}", path);
+            if let Some(parent) = fst.get_parent_for_entry(e.info()).and_then(Entry::as_dir) {
+                let name = e.info().name.trim_end_matches(path::MAIN_SEPARATOR);
+                assert_eq!(parent.child(name, fst.entries()).map(|f| f.info().index), Some(e.info().index), "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn children_by_name() {
+        let fst = small();
+        let root = fst.root();
+        assert_eq!(root.child("dir", fst.entries()).unwrap().info().index, 1);
+        // Only what's directly inside
+        assert!(root.child("a.bin", fst.entries()).is_none());
+        let dir = fst.entry(1).unwrap().as_dir().unwrap();
+        assert_eq!(dir.child("a.bin", fst.entries()).unwrap().info().index, 2);
+        assert!(dir.child("dir", fst.entries()).is_none());
+    }
+
+    #[test]
+    fn the_first_of_two_children_with_the_same_name_wins() {
+        let entries = vec![dir(0, "/", 0, 5), dir(1, "d/", 0, 3), file(2, "a", 1), dir(3, "d/", 0, 5), file(4, "b", 3)];
+        let mut fst = FST::with_entries(0, entries, 2, 0, 5 * ENTRY_SIZE);
+        fst.fill_full_paths();
+        assert_eq!(fst.root().child("d", fst.entries()).unwrap().info().index, 1);
+        // The whole path still finds what's in the second one
+        assert_eq!(resolved(&fst, "/d/b"), Ok(4));
+        assert_eq!(resolved(&fst, "/d/c"), Err("/d has nothing called c".to_owned()));
+    }
+
+    #[test]
+    fn the_index_follows_renames() {
+        let mut fst = small();
+        fst.build_index();
+        fst.entry_mut(2).unwrap().info_mut().name = "b.bin".to_owned();
+        fst.fill_full_paths();
+        assert_eq!(fst.entry_for_path("/dir/b.bin").unwrap().info().index, 2);
+        assert_eq!(fst.entry_for_path("b.bin").unwrap().info().index, 2);
+        assert!(fst.entry_for_path("/dir/a.bin").is_none() && fst.entry_for_path("a.bin").is_none());
+        assert_index_matches(&fst);
+    }
+
+    #[test]
+    fn the_index_follows_added_and_removed_entries() {
+        let mut fst = small();
+        fst.build_index();
+        let mut added = Entry::new(&entry(0, 10, 0x9000, 3), 3, Some(0)).unwrap();
+        added.info_mut().name = "c.bin".to_owned();
+        fst.entries_mut().push(added);
+        fst.entries_mut()[0].as_dir_mut().unwrap().next_index = 4;
+        fst.fill_full_paths();
+        assert_eq!(fst.entry_for_path("/c.bin").unwrap().info().index, 3);
+        assert_eq!(fst.entry_for_selector("idx:3").unwrap().info().normalized_path(), "/c.bin");
+        assert_index_matches(&fst);
+
+        fst.entries_mut().truncate(2);
+        fst.entries_mut()[0].as_dir_mut().unwrap().next_index = 2;
+        fst.entries_mut()[1].as_dir_mut().unwrap().next_index = 2;
+        assert!(fst.entry_for_path("/c.bin").is_none());
+        assert!(fst.entry_for_path("/dir/a.bin").is_none() && fst.entry_for_path("a.bin").is_none());
+        assert_eq!(fst.entry_for_path("/dir").unwrap().info().index, 1);
+        assert_index_matches(&fst);
+    }
+
+    // Paths under `dir`, relative to it, with their sizes for files
+    fn relative_paths(fst: &FST, dir: &str) -> Vec<(String, Option<usize>)> {
+        let prefix = dir.trim_end_matches('/');
+        fst.entries().iter()
+            .skip(1)
+            .filter_map(|e| {
+                let path = e.info().normalized_path();
+                let relative = path.strip_prefix(prefix).filter(|r| r.starts_with('/'))?.to_owned();
+                Some((relative, e.as_file().map(|f| f.size)))
+            })
+            .collect()
+    }
+
+    fn written(fst: &FST) -> FST {
+        let mut bytes = Vec::new();
+        fst.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), fst.size);
+        FST::new(Cursor::new(bytes), 0).unwrap()
+    }
+
+    #[test]
+    fn subtrees_read_back() {
+        let tree = synthetic_tree();
+        fs::create_dir(tree.join("data/levels/none")).unwrap();
+        let game = open_image(&build_image(tree.path()));
+        for dir in ["/data", "/data/levels", "/"] {
+            let sub = written(&game.fst.subtree(dir, false).unwrap());
+            let expected = if dir == "/" { relative_paths(&game.fst, "") } else { relative_paths(&game.fst, dir) };
+            assert_eq!(relative_paths(&sub, ""), expected, "{}", dir);
+            assert!(sub.repairs.is_empty(), "{}: {:?}", dir, sub.repairs);
+            assert_eq!(sub.root().next_index, sub.entries().len(), "{}", dir);
+            assert_eq!(sub.file_count, expected.iter().filter(|(_, size)| size.is_some()).count(), "{}", dir);
+            // The same files, where they were on the ROM
+            for f in sub.entries().iter().filter_map(|e| e.as_file()) {
+                let path = format!("{}{}", dir.trim_end_matches('/'), f.info.normalized_path());
+                assert_eq!(f.file_offset, game.fst.entry_for_path(&path).unwrap().as_file().unwrap().file_offset, "{}", path);
+            }
+        }
+        let levels = written(&game.fst.subtree("/data/levels", false).unwrap());
+        assert!(levels.entry_for_path("/none").unwrap().is_dir());
+    }
+
+    #[test]
+    fn rebased_subtrees_start_at_0() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        let sub = written(&game.fst.subtree("/data", true).unwrap());
+        let offsets: Vec<u64> = sub.entries().iter().filter_map(|e| e.as_file()).map(|f| f.file_offset).collect();
+        assert_eq!(offsets.iter().min(), Some(&0));
+        let original = |path| game.fst.entry_for_path(path).unwrap().as_file().unwrap().file_offset;
+        let moved_by = original("/data/big.bin") - sub.entry_for_path("/big.bin").unwrap().as_file().unwrap().file_offset;
+        assert_eq!(original("/data/levels/1.bin") - moved_by, sub.entry_for_path("/levels/1.bin").unwrap().as_file().unwrap().file_offset);
+    }
+
+    #[test]
+    fn subtrees_of_files_or_nothing() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        assert!(matches!(game.fst.subtree("/readme.txt", false), Err(FstError::NotADirectory(_))));
+        assert!(matches!(game.fst.subtree("/nothing", false), Err(FstError::NotFound(_))));
+        let empty = written(&game.fst.subtree("/empty", false).unwrap());
+        assert_eq!((empty.entries().len(), empty.file_count), (1, 0));
+    }
+
+    // 200 directories of 99 files each, plus the root
+    fn wide_fst() -> FST {
+        let mut entries = vec![dir(0, "/", 0, 20_001)];
+        for d in 0..200 {
+            let index = entries.len();
+            entries.push(dir(index, &format!("dir{}/", d), 0, index + 100));
+            for f in 1..100 {
+                entries.push(file(index + f, &format!("file{}.bin", f), index));
+            }
+        }
+        let mut fst = FST::with_entries(0, entries, 200 * 99, 0, 20_001 * ENTRY_SIZE);
+        fst.fill_full_paths();
+        fst
+    }
+
+    // Not a real benchmark, just how long lookups take on an FST that's
+    // bigger than any real game's, with the index and by going through
+    // every entry. Run it with
+    // `cargo test --release -- --ignored --nocapture lookups_in_20k_entries`.
+    #[test]
+    #[ignore]
+    fn lookups_in_20k_entries() {
+        use std::time::Instant;
+
+        let fst = wide_fst();
+        assert_eq!(fst.entries().len(), 20_001);
+        let paths: Vec<String> = fst.entries().iter().step_by(20).map(|e| e.info().normalized_path()).collect();
+
+        let start = Instant::now();
+        fst.build_index();
+        let built = start.elapsed();
+
+        let start = Instant::now();
+        for path in &paths {
+            assert!(fst.entry_for_path(path).is_some());
+        }
+        let indexed = start.elapsed();
+
+        // Missing the last name, so it goes component by component
+        let start = Instant::now();
+        for path in &paths {
+            assert!(!matches!(fst.resolve(&format!("{}x", path)), Resolution::Found(_)));
+        }
+        let by_component = start.elapsed();
+
+        let start = Instant::now();
+        for path in &paths {
+            assert!(fst.entries().iter().any(|e| e.info().normalized_path() == *path));
+        }
+        let scanned = start.elapsed();
+
+        let per = |d: std::time::Duration| d / paths.len() as u32;
+        println!("Building the index: {:?}", built);
+        println!("{} lookups: {:?} each with the index, {:?} each by component, {:?} each scanning",
+            paths.len(), per(indexed), per(by_component), per(scanned));
+        assert!(indexed < scanned && by_component < scanned);
+    }
 }
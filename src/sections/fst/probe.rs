@@ -0,0 +1,255 @@
+// Whether an FST has an entry at a path, without reading the whole thing,
+// for checking lots of ROMs for one file, like a launcher looking for a
+// mod's marker file. Only the entries in the directories along the path
+// are read, skipping over the ones in their subdirectories, and only their
+// names are read from the string table.
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use super::{entry::{ENTRY_SIZE, MAX_FILENAME_SIZE}, FST};
+use crate::profiling;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProbeHit {
+    File { index: usize, offset: u64, size: u64 },
+    Directory { index: usize },
+}
+
+impl ProbeHit {
+    pub fn index(&self) -> usize {
+        match *self {
+            ProbeHit::File { index, .. } | ProbeHit::Directory { index } => index,
+        }
+    }
+}
+
+// The parts of an entry record `probe_path` needs.
+struct Record {
+    is_dir: bool,
+    filename_offset: u64,
+    // A file's offset, or a directory's parent
+    offset: u64,
+    // A file's size, or a directory's end index
+    size: u64,
+}
+
+impl FST {
+    // Finds `path`, like "/audio/us/bgm.dsp", the way `FST::entry_for_path`
+    // does for absolute paths, including taking the first when two entries
+    // have the same path. A leading slash is optional. Like `FST::summary`,
+    // this doesn't repair malformed FSTs, so it fails with `InvalidData` on
+    // ones `FST::open` could still read.
+    pub fn probe_path(
+        mut iso: impl BufRead + Seek,
+        offset: u64,
+        fst_size: usize,
+        path: &str,
+    ) -> io::Result<Option<ProbeHit>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let _timer = profiling::start("fst_probe");
+        let mut components = Vec::new();
+        // Like `Path::components`, which `entry_for_path` goes by
+        for c in path.split('/').filter(|&c| !c.is_empty() && c != ".") {
+            if c == ".." {
+                return Ok(None);
+            }
+            components.push(c);
+        }
+
+        let root = read_record(&mut iso, offset, 0)?;
+        if !root.is_dir {
+            return Err(invalid("The root of the file system table isn't a directory".to_owned()));
+        }
+        let entry_count = root.size as usize;
+        if entry_count == 0 || entry_count.saturating_mul(ENTRY_SIZE) > fst_size {
+            return Err(invalid(format!(
+                "The file system table has {} entries, which don't fit in {} bytes",
+                entry_count, fst_size,
+            )));
+        }
+        let string_table = offset + (entry_count * ENTRY_SIZE) as u64;
+        let mut probe = Probe { iso, offset, string_table };
+        probe.find(0, entry_count, &components)
+    }
+}
+
+struct Probe<R> {
+    iso: R,
+    offset: u64,
+    string_table: u64,
+}
+
+impl<R: BufRead + Seek> Probe<R> {
+    // Looks for `components` under the directory at `dir`, which ends at
+    // `end`. Every child with the right name is tried in order, since two
+    // directories with the same name can each have different things in them.
+    fn find(&mut self, dir: usize, end: usize, components: &[&str]) -> io::Result<Option<ProbeHit>> {
+        let Some((name, rest)) = components.split_first() else {
+            return Ok(Some(ProbeHit::Directory { index: dir }));
+        };
+        let mut index = dir + 1;
+        while index < end {
+            let record = read_record(&mut self.iso, self.offset, index)?;
+            let next = if record.is_dir {
+                let next = record.size as usize;
+                if next <= index || next > end {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Directory entry {} has an invalid end index {}", index, next),
+                    ));
+                }
+                next
+            } else {
+                index + 1
+            };
+            if self.name_is(record.filename_offset, index, name)? {
+                let hit = match (record.is_dir, rest.is_empty()) {
+                    (true, _) => self.find(index, next, rest)?,
+                    (false, true) => Some(ProbeHit::File { index, offset: record.offset, size: record.size }),
+                    (false, false) => None,
+                };
+                if hit.is_some() {
+                    return Ok(hit);
+                }
+            }
+            index = next;
+        }
+        Ok(None)
+    }
+
+    // Compares names the way `Entry::read_filename` reads them.
+    fn name_is(&mut self, filename_offset: u64, index: usize, name: &str) -> io::Result<bool> {
+        let address = self.string_table.checked_add(filename_offset)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "File name offset out of range"))?;
+        self.iso.seek(SeekFrom::Start(address))?;
+        let mut bytes = Vec::new();
+        self.iso.by_ref().take(MAX_FILENAME_SIZE + 1).read_until(0, &mut bytes)?;
+        if bytes.last() == Some(&0) {
+            bytes.pop();
+        } else if bytes.len() as u64 > MAX_FILENAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The name of entry {} is more than {} bytes long", index, MAX_FILENAME_SIZE),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&bytes) == name)
+    }
+}
+
+fn read_record(mut iso: impl Read + Seek, offset: u64, index: usize) -> io::Result<Record> {
+    let mut entry = [0; ENTRY_SIZE];
+    iso.seek(SeekFrom::Start(offset + (index * ENTRY_SIZE) as u64))?;
+    iso.read_exact(&mut entry)?;
+    let is_dir = match entry[0] {
+        0 => false,
+        1 => true,
+        b => return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid byte in entry {}: {:#x}", index, b),
+        )),
+    };
+    Ok(Record {
+        is_dir,
+        filename_offset: (&entry[1..4]).read_u24::<BigEndian>()? as u64,
+        offset: (&entry[4..8]).read_u32::<BigEndian>()? as u64,
+        size: (&entry[8..12]).read_u32::<BigEndian>()? as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{sections::fst::entry::Entry, test_util::{build_image, open_image, synthetic_tree}};
+
+    fn entry(flags: u8, name_offset: u32, a: u32, b: u32) -> Vec<u8> {
+        let mut bytes = vec![flags];
+        bytes.extend_from_slice(&name_offset.to_be_bytes()[1..]);
+        bytes.extend_from_slice(&a.to_be_bytes());
+        bytes.extend_from_slice(&b.to_be_bytes());
+        bytes
+    }
+
+    // What the full parse finds at `path`, as a hit
+    fn parsed_hit(fst: &FST, path: &str) -> Option<ProbeHit> {
+        let path = if path.starts_with('/') { path.to_owned() } else { format!("/{}", path) };
+        fst.entry_for_path(path).map(|e| match e {
+            Entry::File(f) => ProbeHit::File { index: f.info.index, offset: f.file_offset, size: f.size as u64 },
+            Entry::Directory(d) => ProbeHit::Directory { index: d.info.index },
+        })
+    }
+
+    fn assert_same_as_parsed(fst_bytes: &[u8], paths: &[String]) {
+        let fst = FST::new(Cursor::new(fst_bytes), 0).unwrap();
+        for path in paths {
+            let probed = FST::probe_path(Cursor::new(fst_bytes), 0, fst_bytes.len(), path).unwrap();
+            assert_eq!(probed, parsed_hit(&fst, path), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn probes_match_a_full_parse() {
+        let image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        let fst_bytes = &image[game.fst.offset as usize..][..game.header.fst_size];
+        let mut paths: Vec<String> = game.fst.entries().iter().map(|e| e.info().normalized_path()).collect();
+        for path in paths.clone() {
+            paths.push(path[1..].to_owned());
+            paths.push(format!("{}/", path));
+            paths.push(path.to_uppercase());
+            paths.push(format!("{}x", path));
+            paths.push(path[..path.len() - 1].to_owned());
+            paths.push(format!("{}/./", path));
+        }
+        paths.extend([
+            "/readme.txt/inside", "/data/levels/3.bin", "/data/levels/../big.bin", "/data//levels/1.bin",
+            "/data/./levels/2.bin", "/empty/anything", "/levels/1.bin", "1.bin", "",
+        ].map(str::to_owned));
+        let hits = paths.iter().filter(|p| parsed_hit(&game.fst, p).is_some()).count();
+        assert!(hits > game.fst.entries().len() && hits < paths.len(), "{} of {}", hits, paths.len());
+        assert_same_as_parsed(fst_bytes, &paths);
+    }
+
+    // Each directory called b has different files in it, and both are
+    // looked in
+    #[test]
+    fn directories_with_the_same_name() {
+        let names = b"b\0x\0y\0";
+        let fst = [
+            entry(1, 0, 0, 7),
+            entry(1, 0, 0, 3),
+            entry(0, 2, 0x8000, 1),
+            entry(1, 0, 0, 5),
+            entry(0, 4, 0x9000, 2),
+            entry(0, 2, 0xa000, 3),
+            entry(0, 4, 0xb000, 4),
+        ].concat();
+        let fst = [&fst[..], names].concat();
+        let probe = |path| FST::probe_path(Cursor::new(&fst), 0, fst.len(), path).unwrap();
+        assert_eq!(probe("/b/x"), Some(ProbeHit::File { index: 2, offset: 0x8000, size: 1 }));
+        assert_eq!(probe("/b/y"), Some(ProbeHit::File { index: 4, offset: 0x9000, size: 2 }));
+        assert_eq!(probe("/b"), Some(ProbeHit::Directory { index: 1 }));
+        assert_eq!(probe("/y"), Some(ProbeHit::File { index: 6, offset: 0xb000, size: 4 }));
+        assert_eq!(probe("/x"), Some(ProbeHit::File { index: 5, offset: 0xa000, size: 3 }));
+        assert_eq!(probe("/b/z"), None);
+        assert_same_as_parsed(&fst, &["/b/x", "/b/y", "/b", "/x", "/y", "/b/z"].map(str::to_owned));
+    }
+
+    #[test]
+    fn malformed_fsts() {
+        let invalid = |fst: Vec<u8>, path: &str| {
+            let e = FST::probe_path(Cursor::new(&fst), 0, fst.len(), path).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e);
+            e.to_string()
+        };
+        assert!(invalid(entry(0, 0, 0, 1), "/a").contains("isn't a directory"));
+        assert!(invalid(entry(1, 0, 0, 2), "/a").contains("2 entries, which don't fit in 12 bytes"));
+        let fst = [entry(1, 0, 0, 2), entry(2, 0, 0, 0), b"a\0".to_vec()].concat();
+        assert!(invalid(fst, "/a").contains("Invalid byte in entry 1"));
+        let fst = [entry(1, 0, 0, 3), entry(1, 0, 0, 9), entry(0, 2, 0, 0), b"a\0b\0".to_vec()].concat();
+        assert!(invalid(fst, "/b").contains("Directory entry 1 has an invalid end index 9"));
+    }
+}
@@ -0,0 +1,478 @@
+// Some homebrew ISO builders write FSTs that aren't nested the way `FST::new`
+// expects: directories after some of their contents, directories that end
+// past their parent, or a root whose next index leaves out entries at the
+// end. `FST::open` falls back to `FST::recover` for those, which works out
+// the tree from the parent indices and the directories' ranges instead, and
+// lays it out again in the usual order. What it had to fix is kept in
+// `FST::repairs`, so `verify` can still call the ROM malformed.
+
+use std::{
+    cmp::max,
+    fmt,
+    io::{self, BufRead, Cursor, ErrorKind, Read, Seek, SeekFrom},
+    sync::OnceLock,
+};
+
+use crate::profiling;
+
+use super::{entry::{Entry, ENTRY_SIZE}, FstRead, ParseOptions, FST};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FstRepair {
+    // The root's next index, which is the number of entries, left some out.
+    RootUndercount { claimed: usize, actual: usize },
+    // A directory's next index isn't after it, or is past the end of the
+    // FST, so nothing is taken to be in it by position.
+    InvalidDirectoryEnd { index: usize, next_index: usize },
+    // A directory's parent index isn't the directory it's in by position.
+    // The parent index wins.
+    Reparented { index: usize, contained_by: usize, parent_index: usize },
+    // A directory's parent index isn't a directory, or goes in a circle, so
+    // it's put in the directory it's in by position.
+    InvalidParent { index: usize, parent_index: usize },
+    // Entries that had to move so every directory's contents follow it.
+    // Their indices changed.
+    Renumbered { count: usize },
+    // Directories whose parent indices go in a circle, so they and what's
+    // in them got paths under `CYCLE_DIR_NAME`. Only FSTs put together by
+    // hand can have them, since reading one takes parents from positions.
+    ParentCycle { indices: Vec<usize> },
+}
+
+impl fmt::Display for FstRepair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FstRepair::RootUndercount { claimed, actual } => write!(
+                f,
+                "The root says there are {} entries, but there are {}",
+                claimed, actual,
+            ),
+            FstRepair::InvalidDirectoryEnd { index, next_index } => write!(
+                f,
+                "Directory {} has an invalid next index {}",
+                index, next_index,
+            ),
+            FstRepair::Reparented { index, contained_by, parent_index } => write!(
+                f,
+                "Directory {} is inside directory {}, but its parent index is {}",
+                index, contained_by, parent_index,
+            ),
+            FstRepair::InvalidParent { index, parent_index } => write!(
+                f,
+                "Directory {} has an invalid parent index {}",
+                index, parent_index,
+            ),
+            FstRepair::Renumbered { count } => write!(
+                f,
+                "{} entries come before the directory they're in, so their indices changed",
+                count,
+            ),
+            FstRepair::ParentCycle { indices } => write!(
+                f,
+                "Directories {} have parent indices that go in a circle, so they have paths under {}",
+                indices.iter().map(usize::to_string).collect::<Vec<_>>().join(", "),
+                super::CYCLE_DIR_NAME,
+            ),
+        }
+    }
+}
+
+impl FST {
+    // Like `new`, but FSTs that `new` can't read or that leave out entries
+    // get recovered. `size` is the FST's size from the header, which bounds
+    // how many entries there could be.
+    pub fn open(iso: impl BufRead + Seek, offset: u64, size: usize) -> io::Result<FST> {
+        FST::open_with(iso, offset, size, &ParseOptions::default())
+    }
+
+    pub fn open_with(mut iso: impl BufRead + Seek, offset: u64, size: usize, options: &ParseOptions) -> io::Result<FST> {
+        let mut timer = profiling::start("fst_parse");
+        timer.add_bytes(size as u64);
+        if size <= options.max_buffered_fst_size {
+            iso.seek(SeekFrom::Start(offset))?;
+            let mut buf = Vec::with_capacity(size);
+            (&mut iso).take(size as u64).read_to_end(&mut buf)?;
+            // Anything that reaches the end of the buffer without the NUL
+            // after the last name might have been cut off by it, like when
+            // the size in the header is too small, so that's read again
+            // from the ROM.
+            if let Ok(mut fst) = FST::open_streamed(Cursor::new(&buf), 0, size) {
+                if fst.size < buf.len() || buf.last() == Some(&0) {
+                    fst.offset = offset;
+                    fst.read = FstRead::Buffered { bytes: buf.len() };
+                    return Ok(fst);
+                }
+            }
+        }
+        FST::open_streamed(iso, offset, size)
+    }
+
+    fn open_streamed(mut iso: impl BufRead + Seek, offset: u64, size: usize) -> io::Result<FST> {
+        let (fst, error) = match FST::new(&mut iso, offset) {
+            Ok(fst) => {
+                let count = fst.entries().len();
+                if !entry_follows(&mut iso, offset, count, size)? && !fst.has_parent_after_child() {
+                    return Ok(fst);
+                }
+                (Some(fst), None)
+            },
+            Err(e) if e.kind() == ErrorKind::InvalidData => (None, Some(e)),
+            Err(e) => return Err(e),
+        };
+        match (FST::recover(&mut iso, offset, size), fst, error) {
+            (Ok(recovered), _, _) => Ok(recovered),
+            // Recovering doesn't know any more than `new` did
+            (Err(_), Some(fst), _) => Ok(fst),
+            (Err(_), None, Some(e)) | (Err(e), None, None) => Err(e),
+        }
+    }
+
+    // A directory whose parent index is a directory after it was written
+    // by a builder that doesn't nest entries, since a directory's parent
+    // always comes first. Plenty of FSTs have parent indices that are just
+    // wrong, like all 0, and those are fine to read by position.
+    fn has_parent_after_child(&self) -> bool {
+        self.entries.iter()
+            .filter_map(Entry::as_dir)
+            .any(|d| d.parent_index > d.info.index && self.entries.get(d.parent_index).is_some_and(Entry::is_dir))
+    }
+
+    // Reads every entry first, then finds each one's parent: the parent
+    // index for directories, if it makes sense, and otherwise the innermost
+    // directory whose range it's in.
+    pub fn recover(mut iso: impl BufRead + Seek, offset: u64, size: usize) -> io::Result<FST> {
+        let mut iso = &mut iso;
+        iso.seek(SeekFrom::Start(offset))?;
+        let mut buf = [0; ENTRY_SIZE];
+        iso.read_exact(&mut buf)?;
+        let root = Entry::new(&buf, 0, None)?;
+        let claimed = root.as_dir()
+            .ok_or_else(|| io::Error::new(
+                ErrorKind::InvalidData,
+                "The root of the file system table isn't a directory",
+            ))?
+            .next_index;
+        let limit = max(size / ENTRY_SIZE, claimed);
+
+        let mut repairs = Vec::new();
+        let mut entries = vec![root];
+        // Directories that end past the root's count show it's too small
+        let mut count = claimed;
+        while entries.len() < limit {
+            let index = entries.len();
+            match iso.read_exact(&mut buf) {
+                Ok(()) => {},
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && index >= count => break,
+                Err(e) => return Err(e),
+            }
+            if index >= count && !looks_like_entry(&buf, index, size) {
+                break;
+            }
+            let e = Entry::new(&buf, index, None)?;
+            if let Entry::Directory(d) = &e {
+                count = max(count, d.next_index.min(limit));
+            }
+            entries.push(e);
+        }
+        let n = entries.len();
+        if n > claimed {
+            repairs.push(FstRepair::RootUndercount { claimed, actual: n });
+        }
+
+        let str_tbl_addr = offset + (n * ENTRY_SIZE) as u64;
+        let mut end = str_tbl_addr;
+        for e in &mut entries {
+            e.read_filename(&mut iso, str_tbl_addr)?;
+            end = max(end, iso.stream_position()?);
+        }
+
+        // (start, end) of each directory with a usable range
+        let mut ranges = Vec::new();
+        for e in &entries[1..] {
+            if let Entry::Directory(d) = e {
+                let index = d.info.index;
+                if d.next_index <= index || d.next_index > n {
+                    repairs.push(FstRepair::InvalidDirectoryEnd { index, next_index: d.next_index });
+                } else {
+                    ranges.push((index, d.next_index));
+                }
+            }
+        }
+        let contained_by = |i: usize| ranges.iter()
+            .filter(|&&(start, end)| start < i && i < end)
+            .map(|&(start, _)| start)
+            .max()
+            .unwrap_or(0);
+
+        let mut parents = vec![0; n];
+        for (i, e) in entries.iter().enumerate().skip(1) {
+            parents[i] = match e {
+                Entry::File(_) => contained_by(i),
+                Entry::Directory(d) => {
+                    let p = d.parent_index;
+                    if p != i && entries.get(p).is_some_and(Entry::is_dir) {
+                        if p != contained_by(i) {
+                            repairs.push(FstRepair::Reparented { index: i, contained_by: contained_by(i), parent_index: p });
+                        }
+                        p
+                    } else {
+                        repairs.push(FstRepair::InvalidParent { index: i, parent_index: p });
+                        contained_by(i)
+                    }
+                },
+            };
+        }
+        // Parent indices can go in circles, which would leave a group of
+        // directories that isn't anywhere in the tree
+        for i in 1..n {
+            let mut p = parents[i];
+            let mut steps = 0;
+            while p != 0 && steps < n {
+                p = parents[p];
+                steps += 1;
+            }
+            if p != 0 {
+                let parent_index = entries[i].as_dir().map_or(parents[i], |d| d.parent_index);
+                repairs.push(FstRepair::InvalidParent { index: i, parent_index });
+                parents[i] = 0;
+            }
+        }
+
+        let mut children = vec![Vec::new(); n];
+        for i in 1..n {
+            children[parents[i]].push(i);
+        }
+        // Depth-first, with each directory's contents in their original order
+        let mut order = Vec::with_capacity(n);
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            order.push(i);
+            stack.extend(children[i].iter().rev());
+        }
+        let mut new_index = vec![0; n];
+        for (new, &old) in order.iter().enumerate() {
+            new_index[old] = new;
+        }
+        let moved = order.iter().enumerate().filter(|&(new, &old)| new != old).count();
+        if moved > 0 {
+            repairs.push(FstRepair::Renumbered { count: moved });
+        }
+
+        // How many entries each directory has in it, all the way down
+        let mut descendants = vec![0; n];
+        for &i in order.iter().rev().filter(|&&i| i != 0) {
+            descendants[parents[i]] += descendants[i] + 1;
+        }
+
+        let mut old_entries: Vec<Option<Entry>> = entries.into_iter().map(Some).collect();
+        let mut file_count = 0;
+        let mut total_file_size = 0;
+        let mut entries = Vec::with_capacity(n);
+        for &old in &order {
+            let mut e = old_entries[old].take().unwrap();
+            let index = new_index[old];
+            {
+                let info = e.info_mut();
+                info.index = index;
+                info.directory_index = (old != 0).then(|| new_index[parents[old]]);
+            }
+            match &mut e {
+                Entry::File(f) => {
+                    file_count += 1;
+                    total_file_size += f.size;
+                },
+                Entry::Directory(d) => {
+                    d.parent_index = new_index[parents[old]];
+                    d.next_index = index + descendants[old] + 1;
+                    d.file_count = children[old].len();
+                },
+            }
+            entries.push(e);
+        }
+
+        let mut fst = FST {
+            offset,
+            file_count,
+            total_file_size,
+            entries,
+            size: (end - offset) as usize,
+            index: OnceLock::new(),
+            repairs,
+            read: FstRead::Streamed,
+        };
+        fst.fill_full_paths();
+        Ok(fst)
+    }
+}
+
+// Whether there's an entry right after the first `count`, where the string
+// table should start. Names start with a printable character, or with the
+// NUL of an empty name and then a printable one, so as an entry, they
+// don't have a type of 0 or 1 and a name offset inside the string table.
+pub(super) fn entry_follows(mut iso: impl Read + Seek, offset: u64, count: usize, size: usize) -> io::Result<bool> {
+    if (count + 1) * ENTRY_SIZE > size {
+        return Ok(false);
+    }
+    iso.seek(SeekFrom::Start(offset + (count * ENTRY_SIZE) as u64))?;
+    let mut buf = [0; ENTRY_SIZE];
+    match iso.read_exact(&mut buf) {
+        Ok(()) => Ok(looks_like_entry(&buf, count, size)),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn looks_like_entry(buf: &[u8; ENTRY_SIZE], index: usize, size: usize) -> bool {
+    let name_offset = u32::from_be_bytes([0, buf[1], buf[2], buf[3]]) as usize;
+    let table_size = size.saturating_sub((index + 1) * ENTRY_SIZE);
+    buf[0] <= 1 && buf.iter().any(|&b| b != 0) && name_offset < table_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_image, synthetic_tree};
+
+    const SYNTHETIC_FST: (u64, usize) = (0x2800, 0xa7);
+
+    fn entry(flags: u8, name_offset: u32, a: u32, b: u32) -> Vec<u8> {
+        let mut bytes = vec![flags];
+        bytes.extend_from_slice(&name_offset.to_be_bytes()[1..]);
+        bytes.extend_from_slice(&a.to_be_bytes());
+        bytes.extend_from_slice(&b.to_be_bytes());
+        bytes
+    }
+
+    fn open(entries: &[Vec<u8>], names: &[u8]) -> FST {
+        let bytes = [&entries.concat()[..], names].concat();
+        FST::open(Cursor::new(&bytes), 0, bytes.len()).unwrap()
+    }
+
+    fn paths(fst: &FST) -> Vec<String> {
+        fst.entries().iter().skip(1).map(|e| e.info().normalized_path()).collect()
+    }
+
+    // Written out again, it's an FST that reads the usual way
+    fn reads_back(fst: &FST) {
+        let mut bytes = Vec::new();
+        fst.write(&mut bytes).unwrap();
+        let again = FST::new(Cursor::new(&bytes), 0).unwrap();
+        assert_eq!(paths(&again), paths(fst));
+        assert!(!entry_follows(Cursor::new(&bytes), 0, again.entries().len(), bytes.len()).unwrap());
+        assert!(!again.has_parent_after_child());
+    }
+
+    #[test]
+    fn directories_after_their_contents() {
+        // /top/sub/x.bin, with sub before top
+        let fst = open(
+            &[
+                entry(1, 0, 0, 5),
+                entry(1, 0, 3, 3),
+                entry(0, 4, 0x100, 1),
+                entry(1, 10, 0, 5),
+                entry(0, 14, 0x200, 2),
+            ],
+            b"sub\0x.bin\0top\0y.bin\0",
+        );
+        assert_eq!(paths(&fst), ["/top", "/top/sub", "/top/sub/x.bin", "/top/y.bin"]);
+        assert_eq!(fst.repairs, [
+            FstRepair::Reparented { index: 1, contained_by: 0, parent_index: 3 },
+            FstRepair::Renumbered { count: 3 },
+        ]);
+        let top = fst.entries()[1].as_dir().unwrap();
+        let sub = fst.entries()[2].as_dir().unwrap();
+        assert_eq!((top.parent_index, top.next_index, top.file_count), (0, 5, 2));
+        assert_eq!((sub.parent_index, sub.next_index, sub.file_count), (1, 4, 1));
+        assert_eq!((fst.file_count, fst.total_file_size), (2, 3));
+        reads_back(&fst);
+    }
+
+    #[test]
+    fn roots_that_leave_out_entries() {
+        let fst = open(&[entry(1, 0, 0, 2), entry(0, 0, 0x100, 1), entry(0, 6, 0x200, 2)], b"a.bin\0b.bin\0");
+        assert_eq!(paths(&fst), ["/a.bin", "/b.bin"]);
+        assert_eq!(fst.repairs, [FstRepair::RootUndercount { claimed: 2, actual: 3 }]);
+        assert_eq!(fst.entries()[0].as_dir().unwrap().next_index, 3);
+        reads_back(&fst);
+    }
+
+    #[test]
+    fn directories_with_no_range() {
+        let fst = open(&[entry(1, 0, 0, 3), entry(1, 0, 0, 0), entry(0, 2, 0x100, 1)], b"d\0f.bin\0");
+        assert_eq!(paths(&fst), ["/d", "/f.bin"]);
+        assert_eq!(fst.repairs, [FstRepair::InvalidDirectoryEnd { index: 1, next_index: 0 }]);
+        reads_back(&fst);
+    }
+
+    #[test]
+    fn parent_indices_that_go_in_a_circle() {
+        // a and b are each other's parent, and each is the other's range
+        let fst = open(
+            &[entry(1, 0, 0, 4), entry(1, 0, 2, 3), entry(1, 2, 1, 4), entry(0, 4, 0x100, 1)],
+            b"a\0b\0f.bin\0",
+        );
+        assert_eq!(fst.entries().len(), 4);
+        assert!(fst.repairs.iter().any(|r| matches!(r, FstRepair::InvalidParent { .. })), "{:?}", fst.repairs);
+        reads_back(&fst);
+    }
+
+    // What every retail FST looks like needs nothing recovered
+    #[test]
+    fn nested_fsts_arent_recovered() {
+        let fst = open(&[entry(1, 0, 0, 3), entry(1, 0, 0, 3), entry(0, 2, 0x100, 1)], b"d\0f.bin\0");
+        assert_eq!(paths(&fst), ["/d", "/d/f.bin"]);
+        assert!(fst.repairs.is_empty(), "{:?}", fst.repairs);
+    }
+
+    fn read_with_cap(image: &[u8], size: usize, cap: usize) -> FST {
+        let options = ParseOptions::default().max_buffered_fst_size(cap);
+        FST::open_with(Cursor::new(image), SYNTHETIC_FST.0, size, &options).unwrap()
+    }
+
+    #[test]
+    fn buffering_up_to_the_cap() {
+        let image = build_image(synthetic_tree().path());
+        let (_, size) = SYNTHETIC_FST;
+        let buffered = read_with_cap(&image, size, size);
+        assert_eq!(buffered.read, FstRead::Buffered { bytes: size });
+        for cap in [size - 1, 0] {
+            let streamed = read_with_cap(&image, size, cap);
+            assert_eq!(streamed.read, FstRead::Streamed);
+            assert_eq!(format!("{:?}", streamed.entries()), format!("{:?}", buffered.entries()));
+            assert_eq!((streamed.offset, streamed.size, streamed.file_count), (buffered.offset, buffered.size, buffered.file_count));
+        }
+        let default = FST::open(Cursor::new(&image), SYNTHETIC_FST.0, size).unwrap();
+        assert_eq!(default.read, FstRead::Buffered { bytes: size });
+
+        // A garbage size isn't allocated
+        let huge = FST::open(Cursor::new(&image), SYNTHETIC_FST.0, 3 << 30).unwrap();
+        assert_eq!(huge.read, FstRead::Streamed);
+        assert_eq!(paths(&huge), paths(&buffered));
+    }
+
+    // A header whose FST size cuts off the last name is read from the ROM
+    // instead, past the end of the buffer
+    #[test]
+    fn sizes_too_small_for_the_fst() {
+        let image = build_image(synthetic_tree().path());
+        let (_, size) = SYNTHETIC_FST;
+        let fst = read_with_cap(&image, size - 2, size);
+        assert_eq!(fst.read, FstRead::Streamed);
+        assert_eq!(paths(&fst), paths(&read_with_cap(&image, size, size)));
+    }
+
+    #[test]
+    fn recovered_either_way() {
+        let mut image = build_image(synthetic_tree().path());
+        let (offset, size) = SYNTHETIC_FST;
+        let root_next = offset as usize + 8;
+        image[root_next..root_next + 4].copy_from_slice(&8u32.to_be_bytes());
+        let buffered = read_with_cap(&image, size, size);
+        let streamed = read_with_cap(&image, size, 0);
+        assert_eq!(buffered.read, FstRead::Buffered { bytes: size });
+        assert_eq!(buffered.repairs, [FstRepair::RootUndercount { claimed: 8, actual: 9 }]);
+        assert_eq!(streamed.repairs, buffered.repairs);
+        assert_eq!(format!("{:?}", streamed.entries()), format!("{:?}", buffered.entries()));
+    }
+}
@@ -0,0 +1,309 @@
+// For `verify --strict-fst`. `FST::new` reads anything it can make sense of,
+// but retail FSTs follow stricter rules, which these check.
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+use super::{entry::{Entry, ENTRY_SIZE}, ParseOptions, FST};
+
+// Characters not allowed in names on retail discs, besides anything that
+// isn't printable ASCII.
+const RESERVED_NAME_CHARS: &[u8] = b"/\\:*?\"<>|";
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum StrictViolation {
+    // A directory's parent_index isn't a directory that contains it.
+    ParentMismatch { index: usize, recorded: usize, actual: usize },
+    // A directory's parent_index is a directory that contains it, but not
+    // the closest one, so the next_index of the directory in between
+    // swallowed entries that say they're outside it.
+    NextIndexSkip { index: usize, skipped: usize },
+    // Names are in the string table in the same order as their entries.
+    FilenameOrder { index: usize, offset: u64, previous: u64 },
+    // Every byte of the string table is part of some entry's name, other
+    // than zeros padding the FST to a multiple of 4 bytes. `offset` is from
+    // the start of the string table.
+    UnreferencedBytes { offset: u64, len: u64 },
+    // Names are printable ASCII, without any of `RESERVED_NAME_CHARS`.
+    NameCharset { index: usize, name: String },
+    // Files start on a multiple of 4 bytes.
+    UnalignedFile { index: usize, offset: u64 },
+}
+
+impl fmt::Display for StrictViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrictViolation::ParentMismatch { index, recorded, actual } => write!(
+                f,
+                "Directory {} has parent index {}, but it's in directory {} (a directory's parent index must be the directory it's in)",
+                index, recorded, actual,
+            ),
+            StrictViolation::NextIndexSkip { index, skipped } => write!(
+                f,
+                "Directory {} contains entry {}, which says it's outside it (a directory's next index must end it right after its own entries)",
+                index, skipped,
+            ),
+            StrictViolation::FilenameOrder { index, offset, previous } => write!(
+                f,
+                "Entry {}'s name is at {:#x}, before the previous entry's at {:#x} (names must be in entry order)",
+                index, offset, previous,
+            ),
+            StrictViolation::UnreferencedBytes { offset, len } => write!(
+                f,
+                "{} bytes at {:#x} in the string table aren't part of any name (the string table must only have names in it)",
+                len, offset,
+            ),
+            StrictViolation::NameCharset { index, name } => write!(
+                f,
+                "Entry {}'s name {:?} has characters retail discs don't use (names must be printable ASCII, without any of {})",
+                index, name, String::from_utf8_lossy(RESERVED_NAME_CHARS),
+            ),
+            StrictViolation::UnalignedFile { index, offset } => write!(
+                f,
+                "File {} is at {:#x} (files must start on a multiple of 4 bytes)",
+                index, offset,
+            ),
+        }
+    }
+}
+
+impl FST {
+    // The `size` bytes of the FST as they are on the ROM, for
+    // `strict_validate`. Like `open_with`, this only reads up to
+    // `options.max_buffered_fst_size` into memory, so a garbage size in the
+    // header fails instead of allocating gigabytes.
+    pub fn read_raw(&self, mut iso: impl Read + Seek, size: usize, options: &ParseOptions) -> io::Result<Vec<u8>> {
+        if size > options.max_buffered_fst_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "The FST is {:#x} bytes, more than the {:#x} that can be read into memory at once",
+                size, options.max_buffered_fst_size,
+            )));
+        }
+        let mut bytes = vec![0; size];
+        iso.seek(SeekFrom::Start(self.offset))?;
+        iso.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    // Checks the rules retail FSTs follow. `fst_bytes` is the FST as it is on
+    // the ROM, since the parsed entries don't keep the string table.
+    pub fn strict_validate(&self, fst_bytes: &[u8]) -> Vec<StrictViolation> {
+        let mut violations = Vec::new();
+        let table_start = (self.entries.len() * ENTRY_SIZE).min(fst_bytes.len());
+        let table = &fst_bytes[table_start..];
+
+        let is_ancestor = |ancestor: usize, mut index: usize| {
+            while let Some(parent) = self.entries[index].info().directory_index {
+                if parent == ancestor {
+                    return true;
+                }
+                index = parent;
+            }
+            false
+        };
+
+        // Which bytes of the string table are part of a name
+        let mut referenced = vec![false; table.len()];
+        let mut previous_offset = None;
+        for e in &self.entries {
+            let info = e.info();
+            let start = info.filename_offset as usize;
+            let name = table.get(start..)
+                .map(|t| &t[..t.iter().position(|&b| b == 0).map_or(t.len(), |n| n + 1)])
+                .unwrap_or(&[]);
+            for r in &mut referenced[start.min(table.len())..start.min(table.len()) + name.len()] {
+                *r = true;
+            }
+
+            // The root's name is meaningless
+            if info.index == 0 {
+                continue;
+            }
+
+            if let Some(previous) = previous_offset {
+                if info.filename_offset <= previous {
+                    violations.push(StrictViolation::FilenameOrder {
+                        index: info.index,
+                        offset: info.filename_offset,
+                        previous,
+                    });
+                }
+            }
+            previous_offset = Some(info.filename_offset);
+
+            let name = name.strip_suffix(&[0]).unwrap_or(name);
+            if name.iter().any(|b| !(0x20..0x7f).contains(b) || RESERVED_NAME_CHARS.contains(b)) {
+                violations.push(StrictViolation::NameCharset {
+                    index: info.index,
+                    name: info.name.clone(),
+                });
+            }
+
+            match e {
+                Entry::Directory(d) => {
+                    let actual = info.directory_index.unwrap_or(0);
+                    if d.parent_index == actual {
+                        continue;
+                    }
+                    let recorded_is_dir = self.entries.get(d.parent_index).is_some_and(Entry::is_dir);
+                    if recorded_is_dir && is_ancestor(d.parent_index, info.index) {
+                        // The closest directory it's in that it doesn't
+                        // think it's in
+                        let mut swallowed_by = actual;
+                        while self.entries[swallowed_by].info().directory_index != Some(d.parent_index) {
+                            swallowed_by = self.entries[swallowed_by].info().directory_index.unwrap();
+                        }
+                        violations.push(StrictViolation::NextIndexSkip { index: swallowed_by, skipped: info.index });
+                    } else {
+                        violations.push(StrictViolation::ParentMismatch {
+                            index: info.index,
+                            recorded: d.parent_index,
+                            actual,
+                        });
+                    }
+                },
+                Entry::File(f) => {
+                    if !f.file_offset.is_multiple_of(4) {
+                        violations.push(StrictViolation::UnalignedFile { index: info.index, offset: f.file_offset });
+                    }
+                },
+            }
+        }
+
+        // Up to 3 zeros at the end that bring the FST to a multiple of 4
+        // bytes are padding
+        let mut end = table.len();
+        let padding = table.iter().zip(&referenced).rev()
+            .take_while(|&(&b, &r)| b == 0 && !r)
+            .count();
+        if padding < 4 && fst_bytes.len().is_multiple_of(4) {
+            end -= padding;
+        }
+        let mut i = 0;
+        while i < end {
+            if referenced[i] {
+                i += 1;
+                continue;
+            }
+            let run = referenced[i..end].iter().position(|&r| r).unwrap_or(end - i);
+            violations.push(StrictViolation::UnreferencedBytes { offset: i as u64, len: run as u64 });
+            i += run;
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn entry(flags: u8, name_offset: u32, a: u32, b: u32) -> Vec<u8> {
+        let mut bytes = vec![flags];
+        bytes.extend_from_slice(&name_offset.to_be_bytes()[1..]);
+        bytes.extend_from_slice(&a.to_be_bytes());
+        bytes.extend_from_slice(&b.to_be_bytes());
+        bytes
+    }
+
+    // /a/b/f.bin, /a/b/g.bin and /h.bin, which follow every rule. Changing
+    // one thing breaks just one.
+    fn entries() -> Vec<Vec<u8>> {
+        vec![
+            entry(1, 0, 0, 6),
+            entry(1, 0, 0, 5),
+            entry(1, 2, 1, 5),
+            entry(0, 4, 0x8000, 4),
+            entry(0, 10, 0x8004, 4),
+            entry(0, 16, 0x8008, 4),
+        ]
+    }
+
+    // With the 2 zeros that pad it to a multiple of 4
+    const NAMES: &[u8] = b"a\0b\0f.bin\0g.bin\0h.bin\0\0\0";
+
+    fn violations(entries: &[Vec<u8>], names: &[u8]) -> Vec<StrictViolation> {
+        let bytes = [&entries.concat()[..], names].concat();
+        let fst = FST::new(Cursor::new(&bytes), 0).unwrap();
+        fst.strict_validate(&bytes)
+    }
+
+    #[test]
+    fn retail_fsts_pass() {
+        assert_eq!(violations(&entries(), NAMES), []);
+        // Without the padding
+        assert_eq!(violations(&entries(), &NAMES[..NAMES.len() - 2]), []);
+    }
+
+    #[test]
+    fn parents_that_dont_contain_the_directory() {
+        let mut entries = entries();
+        entries[2] = entry(1, 2, 3, 5);
+        assert_eq!(violations(&entries, NAMES), [StrictViolation::ParentMismatch { index: 2, recorded: 3, actual: 1 }]);
+    }
+
+    #[test]
+    fn next_indices_that_swallow_entries() {
+        let mut entries = entries();
+        // /b says it's in the root, but /a's next_index takes it in
+        entries[2] = entry(1, 2, 0, 5);
+        assert_eq!(violations(&entries, NAMES), [StrictViolation::NextIndexSkip { index: 1, skipped: 2 }]);
+    }
+
+    #[test]
+    fn names_out_of_order() {
+        let mut entries = entries();
+        entries[4] = entry(0, 16, 0x8004, 4);
+        entries[5] = entry(0, 10, 0x8008, 4);
+        assert_eq!(violations(&entries, NAMES), [StrictViolation::FilenameOrder { index: 5, offset: 10, previous: 16 }]);
+    }
+
+    #[test]
+    fn bytes_no_name_uses() {
+        let names = [&NAMES[..NAMES.len() - 2], b"junk"].concat();
+        assert_eq!(violations(&entries(), &names), [StrictViolation::UnreferencedBytes { offset: 22, len: 4 }]);
+        // More zeros than padding needs
+        let names = [&NAMES[..NAMES.len() - 2], &[0; 6]].concat();
+        assert_eq!(violations(&entries(), &names), [StrictViolation::UnreferencedBytes { offset: 22, len: 6 }]);
+    }
+
+    #[test]
+    fn names_outside_the_charset() {
+        for (bad, name) in [(b'*', "f*bin"), (b'\\', "f\\bin")] {
+            let mut names = NAMES.to_vec();
+            names[5] = bad;
+            assert_eq!(violations(&entries(), &names), [StrictViolation::NameCharset { index: 3, name: name.to_owned() }]);
+        }
+        let mut names = NAMES.to_vec();
+        names[4] = 0x7f;
+        assert!(matches!(&violations(&entries(), &names)[..], [StrictViolation::NameCharset { index: 3, .. }]));
+    }
+
+    #[test]
+    fn unaligned_files() {
+        let mut entries = entries();
+        entries[4] = entry(0, 10, 0x8006, 4);
+        assert_eq!(violations(&entries, NAMES), [StrictViolation::UnalignedFile { index: 4, offset: 0x8006 }]);
+    }
+
+    #[test]
+    fn raw_reads_stop_at_the_cap() {
+        let bytes = [&entries().concat()[..], NAMES].concat();
+        let fst = FST::new(Cursor::new(&bytes), 0).unwrap();
+        let options = ParseOptions::default().max_buffered_fst_size(bytes.len());
+        assert_eq!(fst.read_raw(Cursor::new(&bytes), bytes.len(), &options).unwrap(), bytes);
+        let e = fst.read_raw(Cursor::new(&bytes), bytes.len() + 1, &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains(&format!("{:#x}", bytes.len())), "{}", e);
+    }
+
+    #[test]
+    fn violations_say_which_rule() {
+        let text = StrictViolation::UnalignedFile { index: 4, offset: 0x8006 }.to_string();
+        assert!(text.contains("0x8006") && text.contains("multiple of 4"), "{}", text);
+    }
+}
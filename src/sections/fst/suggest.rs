@@ -0,0 +1,106 @@
+// Guesses at what a path that isn't in the FST was meant to be, for "Did you
+// mean" messages. Entries whose path ends with it come first, so a bare
+// file name finds the file in whichever directory it's in, then ones whose
+// names are a few typos away from its last part.
+
+use super::{entry::Entry, FST};
+
+// How many entries `suggest` returns at most
+pub const MAX_SUGGESTIONS: usize = 5;
+
+impl FST {
+    // Closest first, ignoring case. Empty when nothing's close.
+    pub fn suggest(&self, query: &str) -> Vec<&Entry> {
+        let query = query.trim_matches('/').to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let base = query.rsplit('/').next().unwrap_or(&query);
+        // Names this far off are more likely another file than a typo
+        let max_distance = (base.chars().count() / 3).max(1);
+
+        let mut matches: Vec<(usize, String, &Entry)> = self.entries.iter()
+            .skip(1)
+            .filter_map(|e| {
+                let path = e.info().normalized_path().to_lowercase();
+                if path == format!("/{}", query) || path.ends_with(&format!("/{}", query)) {
+                    return Some((0, path, e));
+                }
+                let name = path.rsplit('/').next().unwrap_or_default();
+                let distance = edit_distance(name, base);
+                (distance <= max_distance).then_some((distance, path, e))
+            })
+            .collect();
+        matches.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        matches.into_iter().take(MAX_SUGGESTIONS).map(|(_, _, e)| e).collect()
+    }
+}
+
+// The Levenshtein distance, by character.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_image, open_image, synthetic_tree};
+
+    fn suggestions(fst: &FST, query: &str) -> Vec<String> {
+        fst.suggest(query).iter().map(|e| e.info().normalized_path()).collect()
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("big.bin", "bgi.bin"), 2);
+        // By character, not byte
+        assert_eq!(edit_distance("é.bin", "e.bin"), 1);
+    }
+
+    #[test]
+    fn near_misses() {
+        let fst = open_image(&build_image(synthetic_tree().path())).fst;
+        // Paths ending with it come first, in whichever directory
+        assert_eq!(suggestions(&fst, "1.bin")[0], "/data/levels/1.bin");
+        assert_eq!(suggestions(&fst, "levels/2.bin")[0], "/data/levels/2.bin");
+        assert_eq!(suggestions(&fst, "/DATA/Big.bin"), ["/data/big.bin"]);
+        // Then typos in the last part, closest first
+        assert_eq!(suggestions(&fst, "/data/bgi.bin"), ["/data/big.bin"]);
+        assert_eq!(suggestions(&fst, "/redme.txt"), ["/readme.txt"]);
+        let bins = suggestions(&fst, "/data/levels/3.bin");
+        assert_eq!(bins, ["/data/levels/1.bin", "/data/levels/2.bin"]);
+        assert_eq!(suggestions(&fst, "/empty.bni")[0], "/empty.bin");
+    }
+
+    #[test]
+    fn nothing_close() {
+        let fst = open_image(&build_image(synthetic_tree().path())).fst;
+        for query in ["", "/", "/opening.bnr", "/data/completely-different"] {
+            assert!(suggestions(&fst, query).is_empty(), "{:?}: {:?}", query, suggestions(&fst, query));
+        }
+    }
+
+    #[test]
+    fn at_most_five() {
+        let tree = synthetic_tree();
+        for i in 0..10 {
+            std::fs::write(tree.join(format!("data/{}.bin", i)), [i]).unwrap();
+        }
+        let fst = open_image(&build_image(tree.path())).fst;
+        assert_eq!(suggestions(&fst, "/x.bin").len(), MAX_SUGGESTIONS);
+    }
+}
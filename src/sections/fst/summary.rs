@@ -0,0 +1,173 @@
+// The counts and sizes from an FST without reading any names, for scanning
+// lots of ROMs, like `catalog` does. Only the entry records are read, which
+// is a fraction of the FST, and nothing is allocated per entry.
+
+use std::io::{self, BufRead, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use super::{entry::ENTRY_SIZE, recover::entry_follows, FST};
+use crate::profiling;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FstSummary {
+    // Including the root
+    pub entry_count: usize,
+    pub file_count: usize,
+    // Same as `FST::total_file_size`
+    pub total_file_size: u64,
+    // Where the file that ends last ends, or 0 without any files
+    pub max_offset: u64,
+    // Where the first file with anything in it starts, or 0 without any.
+    // Empty files' offsets don't mean anything.
+    pub min_offset: u64,
+}
+
+impl FST {
+    // Reads the entries the root says there are. Unlike `FST::open`, this
+    // doesn't repair malformed FSTs, so a summary that fails with
+    // `InvalidData` might still be readable the slow way.
+    pub fn summary(mut iso: impl BufRead + Seek, offset: u64, fst_size: usize) -> io::Result<FstSummary> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut timer = profiling::start("fst_summary");
+        iso.seek(SeekFrom::Start(offset))?;
+
+        let mut entry = [0; ENTRY_SIZE];
+        iso.read_exact(&mut entry)?;
+        if entry[0] != 1 {
+            return Err(invalid("The root of the file system table isn't a directory".to_owned()));
+        }
+        let entry_count = (&entry[8..12]).read_u32::<BigEndian>()? as usize;
+        // The entries come before the string table, so they have to fit
+        if entry_count == 0 || entry_count.saturating_mul(ENTRY_SIZE) > fst_size {
+            return Err(invalid(format!(
+                "The file system table has {} entries, which don't fit in {} bytes",
+                entry_count, fst_size,
+            )));
+        }
+        timer.add_bytes((entry_count * ENTRY_SIZE) as u64);
+
+        let mut summary = FstSummary { entry_count, ..FstSummary::default() };
+        // Where each directory the current entry is in ends, checked like
+        // `FST::new` does
+        let mut dir_ends = vec![entry_count];
+        for index in 1..entry_count {
+            while dir_ends.last() == Some(&index) {
+                dir_ends.pop();
+            }
+            iso.read_exact(&mut entry)?;
+            match entry[0] {
+                0 => {
+                    let file_offset = (&entry[4..8]).read_u32::<BigEndian>()? as u64;
+                    let size = (&entry[8..12]).read_u32::<BigEndian>()? as u64;
+                    summary.file_count += 1;
+                    summary.total_file_size += size;
+                    summary.max_offset = summary.max_offset.max(file_offset + size);
+                    if size != 0 && (summary.min_offset == 0 || file_offset < summary.min_offset) {
+                        summary.min_offset = file_offset;
+                    }
+                },
+                1 => {
+                    let next_index = (&entry[8..12]).read_u32::<BigEndian>()? as usize;
+                    if next_index <= index || next_index > *dir_ends.last().unwrap_or(&entry_count) {
+                        return Err(invalid(format!("Directory entry {} has an invalid end index {}", index, next_index)));
+                    }
+                    dir_ends.push(next_index);
+                },
+                b => return Err(invalid(format!("Invalid byte in entry {}: {:#x}", index, b))),
+            }
+        }
+        // Roots that undercount are the most common reason for a repair,
+        // and would make the counts wrong
+        if entry_follows(&mut iso, offset, entry_count, fst_size)? {
+            return Err(invalid("The file system table has more entries than its root says".to_owned()));
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{build_image, synthetic_tree};
+
+    const SYNTHETIC_FST: (u64, usize) = (0x2800, 0xa7);
+
+    fn entry(flags: u8, name_offset: usize, a: usize, b: usize) -> Vec<u8> {
+        let mut bytes = vec![flags];
+        bytes.extend_from_slice(&(name_offset as u32).to_be_bytes()[1..]);
+        bytes.extend_from_slice(&(a as u32).to_be_bytes());
+        bytes.extend_from_slice(&(b as u32).to_be_bytes());
+        bytes
+    }
+
+    // `dirs` directories of `files` files each, of every size from 0 up
+    fn big_fst(dirs: usize, files: usize) -> Vec<u8> {
+        let count = 1 + dirs * (files + 1);
+        let mut entries = entry(1, 0, 0, count);
+        let mut names = Vec::new();
+        let mut offset = 0x10_0000;
+        for d in 0..dirs {
+            let index = 1 + d * (files + 1);
+            entries.extend(entry(1, names.len(), 0, index + files + 1));
+            names.extend(format!("dir{}\0", d).bytes());
+            for f in 0..files {
+                let size = (d * files + f) % 5000;
+                entries.extend(entry(0, names.len(), offset, size));
+                names.extend(format!("file{}.bin\0", f).bytes());
+                offset += size.next_multiple_of(4);
+            }
+        }
+        [entries, names].concat()
+    }
+
+    fn assert_same_as_parsed(bytes: &[u8], offset: u64, size: usize) {
+        let summary = FST::summary(Cursor::new(bytes), offset, size).unwrap();
+        let fst = FST::open(Cursor::new(bytes), offset, size).unwrap();
+        let files: Vec<_> = fst.entries().iter().filter_map(|e| e.as_file()).collect();
+        assert_eq!(summary.entry_count, fst.entries().len());
+        assert_eq!(summary.file_count, fst.file_count);
+        assert_eq!(summary.total_file_size, fst.total_file_size as u64);
+        assert_eq!(summary.max_offset, files.iter().map(|f| f.file_offset + f.size as u64).max().unwrap_or(0));
+        let min = files.iter().filter(|f| f.size != 0).map(|f| f.file_offset).min().unwrap_or(0);
+        assert_eq!(summary.min_offset, min);
+    }
+
+    #[test]
+    fn summaries_match_a_full_parse() {
+        let image = build_image(synthetic_tree().path());
+        let (offset, size) = SYNTHETIC_FST;
+        assert_same_as_parsed(&image, offset, size);
+        let summary = FST::summary(Cursor::new(&image), offset, size).unwrap();
+        assert_eq!((summary.entry_count, summary.file_count), (9, 5));
+        assert_eq!((summary.min_offset, summary.max_offset), (0x8000, 0x12_3039));
+
+        let big = big_fst(100, 200);
+        assert_same_as_parsed(&big, 0, big.len());
+        assert_eq!(FST::summary(Cursor::new(&big), 0, big.len()).unwrap().entry_count, 20101);
+    }
+
+    fn summary_error(bytes: &[u8]) -> String {
+        let e = FST::summary(Cursor::new(bytes), 0, bytes.len()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e);
+        e.to_string()
+    }
+
+    #[test]
+    fn fsts_a_full_parse_would_repair_or_reject() {
+        let names = b"d\0f.bin\0";
+        let fst = |entries: &[Vec<u8>]| [&entries.concat()[..], names].concat();
+        assert!(summary_error(&fst(&[entry(0, 0, 0, 3)])).contains("isn't a directory"));
+        assert!(summary_error(&fst(&[entry(1, 0, 0, 0)])).contains("don't fit"));
+        assert!(summary_error(&fst(&[entry(1, 0, 0, 1000)])).contains("don't fit"));
+        let bad_end = fst(&[entry(1, 0, 0, 3), entry(1, 0, 0, 1), entry(0, 2, 0x100, 1)]);
+        assert!(summary_error(&bad_end).contains("Directory entry 1 has an invalid end index 1"));
+        let past_parent = fst(&[entry(1, 0, 0, 3), entry(1, 0, 0, 4), entry(0, 2, 0x100, 1)]);
+        assert!(summary_error(&past_parent).contains("invalid end index 4"));
+        let undercounted = fst(&[entry(1, 0, 0, 2), entry(1, 0, 0, 2), entry(0, 2, 0x100, 1)]);
+        assert!(summary_error(&undercounted).contains("more entries than its root says"));
+        assert!(FST::open(Cursor::new(&undercounted), 0, undercounted.len()).is_ok());
+    }
+}
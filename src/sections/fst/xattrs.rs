@@ -0,0 +1,161 @@
+// For `extract --xattrs`, which records where on the ROM each file came from
+// in the file's extended attributes, so that's known without the manifest.
+
+use std::{io, path::Path};
+
+use crate::{
+    io_util::{file_attributes, FileAttributes},
+    sections::fst::entry::FileEntry,
+};
+
+pub const OFFSET_ATTRIBUTE: &str = "user.gcmod.offset";
+pub const SIZE_ATTRIBUTE: &str = "user.gcmod.size";
+pub const INDEX_ATTRIBUTE: &str = "user.gcmod.index";
+pub const GAME_ID_ATTRIBUTE: &str = "user.gcmod.gameid";
+
+pub const ATTRIBUTES: [&str; 4] = [OFFSET_ATTRIBUTE, SIZE_ATTRIBUTE, INDEX_ATTRIBUTE, GAME_ID_ATTRIBUTE];
+
+#[derive(Debug)]
+pub struct OriginAttributes {
+    attributes: &'static dyn FileAttributes,
+    game_id: String,
+    // The first failure, after which no more are set, so there's just one
+    // warning
+    failure: Option<String>,
+}
+
+impl OriginAttributes {
+    // `None` if this platform doesn't have attributes at all.
+    pub fn new(game_id: String) -> Option<OriginAttributes> {
+        file_attributes().map(|attributes| OriginAttributes { attributes, game_id, failure: None })
+    }
+
+    // Sets the attributes for `file` on `path`, where it was extracted to.
+    // Attributes are nice to have, so if they can't be set, the extraction
+    // goes on without them, and `failure` says why for the warning.
+    pub fn record(&mut self, file: &FileEntry, path: &Path) {
+        if self.failure.is_some() {
+            return;
+        }
+        let values = [
+            file.file_offset.to_string(),
+            file.size.to_string(),
+            file.info.index.to_string(),
+            self.game_id.clone(),
+        ];
+        for (name, value) in ATTRIBUTES.iter().zip(values) {
+            if let Err(e) = self.attributes.set(path, name, value.as_bytes()) {
+                self.failure = Some(format!(
+                    "Couldn't set extended attributes on {} ({}), so they weren't set on any more files",
+                    path.display(), e,
+                ));
+                return;
+            }
+        }
+    }
+
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+}
+
+// The gcmod attributes on `path` that it has, as (name, value).
+pub fn read_origin_attributes(path: impl AsRef<Path>) -> io::Result<Vec<(&'static str, String)>> {
+    let attributes = file_attributes().ok_or_else(|| io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Extended attributes aren't supported on this platform",
+    ))?;
+    let mut found = Vec::new();
+    for name in ATTRIBUTES {
+        if let Some(value) = attributes.get(path.as_ref(), name)? {
+            found.push((name, String::from_utf8_lossy(&value).into_owned()));
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::{
+        test_util::{build_image, open_image, synthetic_tree, TestDir},
+        Game,
+    };
+
+    fn readme(game: &Game) -> &FileEntry {
+        game.fst.entry_for_path("/readme.txt").unwrap().as_file().unwrap()
+    }
+
+    fn game() -> Game {
+        open_image(&build_image(synthetic_tree().path()))
+    }
+
+    fn extracted(dir: &TestDir) -> PathBuf {
+        let path = dir.path().join("readme.txt");
+        fs::write(&path, "readme").unwrap();
+        path
+    }
+
+    #[test]
+    fn attributes_read_back() {
+        let dir = TestDir::new();
+        let path = extracted(&dir);
+        let Some(attributes) = file_attributes() else { return };
+        // Like tmpfs on older kernels
+        if attributes.set(&path, "user.gcmod.probe", b"").is_err() {
+            eprintln!("Skipping, {} doesn't support extended attributes", dir.path().display());
+            return;
+        }
+        assert_eq!(read_origin_attributes(&path).unwrap(), []);
+
+        let game = game();
+        let file = readme(&game);
+        let mut origin = OriginAttributes::new("GSLF01".to_owned()).unwrap();
+        origin.record(file, &path);
+        assert_eq!(origin.failure(), None);
+        assert_eq!(read_origin_attributes(&path).unwrap(), [
+            (OFFSET_ATTRIBUTE, file.file_offset.to_string()),
+            (SIZE_ATTRIBUTE, file.size.to_string()),
+            (INDEX_ATTRIBUTE, "1".to_owned()),
+            (GAME_ID_ATTRIBUTE, "GSLF01".to_owned()),
+        ]);
+    }
+
+    #[derive(Debug)]
+    struct Unsupported(AtomicUsize);
+
+    impl FileAttributes for Unsupported {
+        fn set(&self, _path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Err(io::Error::new(io::ErrorKind::Unsupported, "not here"))
+        }
+
+        fn get(&self, _path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn file_systems_without_attributes_are_tried_once() {
+        static UNSUPPORTED: Unsupported = Unsupported(AtomicUsize::new(0));
+        let dir = TestDir::new();
+        let path = extracted(&dir);
+        let game = game();
+        let file = readme(&game);
+        let mut origin = OriginAttributes { attributes: &UNSUPPORTED, game_id: "GSLF01".to_owned(), failure: None };
+        for _ in 0..3 {
+            origin.record(file, &path);
+        }
+        let failure = origin.failure().unwrap();
+        assert!(failure.starts_with(&format!("Couldn't set extended attributes on {} (not here)", path.display())), "{}", failure);
+        assert_eq!(UNSUPPORTED.0.load(Ordering::Relaxed), 1);
+        // The file is fine without them
+        assert_eq!(fs::read_to_string(&path).unwrap(), "readme");
+    }
+}
@@ -1,15 +1,21 @@
 // This chapter of yagcd was invaluable to working on this file:
 // http://hitmen.c02.at/files/yagcd/yagcd/chap13.html
 
-use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Cursor, Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    format_u64,
-    format_usize,
-    sections::Section,
-    NumberStyle,
+    rom_handle::RomWriteHandle,
+    sections::{apploader::{Apploader, APPLOADER_OFFSET}, Field, Section},
+    MIN_ALIGNMENT,
 };
 
 pub const GAME_HEADER_SIZE: usize = 0x2440;
@@ -23,12 +29,18 @@ pub const TITLE_OFFSET: u64 = 0x20;
 
 pub const MAGIC_WORD: u32 = 0xc2339f3d;
 
+pub const AUDIO_STREAMING_OFFSET: u64 = 0x08;
+pub const STREAM_BUFFER_SIZE_OFFSET: u64 = 0x09;
+// Streaming with a buffer size of 0 hangs real hardware.
+pub const STREAM_BUFFER_SIZE_RANGE: std::ops::RangeInclusive<u8> = 1..=15;
+
 pub const GAME_CODE_SIZE: usize = 4;
 pub const MAKER_CODE_SIZE: usize = 2;
 pub const DISK_ID_SIZE: usize = 1;
+pub const DISK_ID_OFFSET: u64 = 0x06;
 pub const VERSION_SIZE: usize = 1;
 pub const AUDIO_STREAMING_SIZE: usize = 1;
-pub const STREAM_BUFFER_SIZE_SIZE: usize = 2;
+pub const STREAM_BUFFER_SIZE_SIZE: usize = 1;
 pub const UNUSED_REGION_1_SIZE: usize = 0x12;
 pub const MAGIC_WORD_SIZE: usize = 4;
 pub const GAME_NAME_SIZE: usize = 0x03e0;
@@ -44,7 +56,9 @@ pub const USER_LENGTH_SIZE: usize = 4;
 pub const UNKNOWN_REGION_SIZE: usize = 4;
 pub const UNUSED_REGION_3_SIZE: usize = 4;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Header {
     pub game_code: String,
     pub maker_code: String,
@@ -65,6 +79,132 @@ pub struct Header {
     // yagcd separates this from the rest of the header,
     // calling it "Disk header information". Idk why...
     pub information: HeaderInformation,
+    // The whole block as it was read, for `raw` and `write_patched`. Empty
+    // for headers that weren't read from a ROM.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    raw: Vec<u8>,
+    // The fields changed through setters since this was read
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dirty: DirtyFields,
+}
+
+// The fields of the header block, for tracking which ones changed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HeaderField {
+    GameCode,
+    MakerCode,
+    DiskId,
+    Version,
+    AudioStreaming,
+    StreamBufferSize,
+    Title,
+    DebugMonitorOffset,
+    DebugMonitorLoadAddr,
+    DolOffset,
+    FstOffset,
+    FstSize,
+    MaxFstSize,
+    UserPosition,
+    UserLength,
+    Unknown,
+    // bi2's known fields
+    DebugMonitorSize,
+    SimulatedMemorySize,
+    ArgumentOffset,
+    DebugFlag,
+    TrackLocation,
+    TrackSize,
+    CountryCode,
+    TotalDiscs,
+    LongFileName,
+    PadSpec,
+    DolLimit,
+}
+
+impl HeaderField {
+    pub const ALL: [HeaderField; 27] = [
+        HeaderField::GameCode,
+        HeaderField::MakerCode,
+        HeaderField::DiskId,
+        HeaderField::Version,
+        HeaderField::AudioStreaming,
+        HeaderField::StreamBufferSize,
+        HeaderField::Title,
+        HeaderField::DebugMonitorOffset,
+        HeaderField::DebugMonitorLoadAddr,
+        HeaderField::DolOffset,
+        HeaderField::FstOffset,
+        HeaderField::FstSize,
+        HeaderField::MaxFstSize,
+        HeaderField::UserPosition,
+        HeaderField::UserLength,
+        HeaderField::Unknown,
+        HeaderField::DebugMonitorSize,
+        HeaderField::SimulatedMemorySize,
+        HeaderField::ArgumentOffset,
+        HeaderField::DebugFlag,
+        HeaderField::TrackLocation,
+        HeaderField::TrackSize,
+        HeaderField::CountryCode,
+        HeaderField::TotalDiscs,
+        HeaderField::LongFileName,
+        HeaderField::PadSpec,
+        HeaderField::DolLimit,
+    ];
+
+    // Where the field is in the header block
+    pub fn range(self) -> Range<usize> {
+        let (start, size) = match self {
+            HeaderField::GameCode => (0x00, GAME_CODE_SIZE),
+            HeaderField::MakerCode => (0x04, MAKER_CODE_SIZE),
+            HeaderField::DiskId => (DISK_ID_OFFSET as usize, DISK_ID_SIZE),
+            HeaderField::Version => (0x07, VERSION_SIZE),
+            HeaderField::AudioStreaming => (AUDIO_STREAMING_OFFSET as usize, AUDIO_STREAMING_SIZE),
+            HeaderField::StreamBufferSize => (STREAM_BUFFER_SIZE_OFFSET as usize, STREAM_BUFFER_SIZE_SIZE),
+            HeaderField::Title => (TITLE_OFFSET as usize, GAME_NAME_SIZE),
+            HeaderField::DebugMonitorOffset => (0x400, DEBUG_MONITOR_OFFSET_SIZE),
+            HeaderField::DebugMonitorLoadAddr => (0x404, DEBUG_MONITOR_LOAD_ADDR_SIZE),
+            HeaderField::DolOffset => (0x420, DOL_OFFSET_SIZE),
+            HeaderField::FstOffset => (0x424, FST_OFFSET_SIZE),
+            HeaderField::FstSize => (0x428, FST_SIZE_SIZE),
+            HeaderField::MaxFstSize => (0x42c, FST_MAX_SIZE_SIZE),
+            HeaderField::UserPosition => (0x430, USER_POSITION_SIZE),
+            HeaderField::UserLength => (0x434, USER_LENGTH_SIZE),
+            HeaderField::Unknown => (0x438, UNKNOWN_REGION_SIZE),
+            // bi2's fields are all words, one after another
+            bi2_field => {
+                let index = HeaderField::ALL.iter().position(|&f| f == bi2_field).unwrap()
+                    - HeaderField::ALL.iter().position(|&f| f == HeaderField::DebugMonitorSize).unwrap();
+                (BI2_OFFSET as usize + index * 4, 4)
+            },
+        };
+        start..start + size
+    }
+
+    fn bit(self) -> u32 {
+        1 << HeaderField::ALL.iter().position(|&f| f == self).unwrap()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DirtyFields(u32);
+
+impl DirtyFields {
+    pub fn insert(&mut self, field: HeaderField) {
+        self.0 |= field.bit();
+    }
+
+    pub fn contains(&self, field: HeaderField) -> bool {
+        self.0 & field.bit() != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = HeaderField> + '_ {
+        HeaderField::ALL.into_iter().filter(|&f| self.contains(f))
+    }
 }
 
 // DISK HEADER INFORMATION DATA
@@ -75,9 +215,109 @@ pub const DEBUG_FLAG_SIZE: usize = 4;
 pub const TRACK_LOCATION_SIZE: usize = 4;
 pub const TRACK_SIZE_SIZE: usize = 4;
 pub const COUNTRY_CODE_SIZE: usize = 4;
-pub const INFO_UNKNOWN_SIZE: usize = 4;
+pub const TOTAL_DISCS_SIZE: usize = 4;
+pub const LONG_FILE_NAME_SIZE: usize = 4;
+pub const PAD_SPEC_SIZE: usize = 4;
+pub const DOL_LIMIT_SIZE: usize = 4;
+
+// bi2.bin, the whole "Disk header information" block. Only the start of it is
+// understood, so the rest is kept as-is.
+pub const BI2_OFFSET: u64 = 0x440;
+pub const BI2_SIZE: usize = 0x2000;
+pub const BI2_KNOWN_SIZE: usize = 0x2c;
+pub const TOTAL_DISCS_OFFSET: u64 = BI2_OFFSET + 0x1c;
+
+// The values the IPL expects in `country_code` for each region.
+pub const COUNTRY_CODE_JAPAN: u32 = 0;
+pub const COUNTRY_CODE_USA: u32 = 1;
+pub const COUNTRY_CODE_PAL: u32 = 2;
+pub const COUNTRY_CODE_KOREA: u32 = 4;
+
+// What retail games use, 24MiB.
+pub const DEFAULT_SIMULATED_MEMORY_SIZE: u32 = 0x0180_0000;
 
 #[derive(Debug)]
+pub enum HeaderError {
+    StreamBufferSizeRequired,
+    InvalidStreamBufferSize(u8),
+    InvalidGameCode(String),
+    InvalidMakerCode(String),
+    TitleTooLong(usize),
+    InvalidDiscNumber(u8),
+    DiscNumberPastTotal { disc: u32, total: u32 },
+    MisalignedOffset { section: &'static str, offset: u64 },
+    MissingOffset { section: &'static str },
+    OffsetInHeader { section: &'static str, offset: u64 },
+    OffsetInApploader { section: &'static str, offset: u64, apploader_end: u64 },
+    SharedOffset(u64),
+    OffsetPastEnd { section: &'static str, offset: u64, len: u64 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::StreamBufferSizeRequired =>
+                write!(f, "Audio streaming needs a stream buffer size"),
+            HeaderError::InvalidStreamBufferSize(n) => write!(
+                f,
+                "Invalid stream buffer size {}, it must be from {} to {} when audio streaming is on",
+                n, STREAM_BUFFER_SIZE_RANGE.start(), STREAM_BUFFER_SIZE_RANGE.end(),
+            ),
+            HeaderError::InvalidGameCode(code) => write!(
+                f,
+                "Invalid game code {:?}, it must be {} ASCII letters or digits",
+                code, GAME_CODE_SIZE,
+            ),
+            HeaderError::InvalidMakerCode(code) => write!(
+                f,
+                "Invalid maker code {:?}, it must be {} ASCII letters or digits",
+                code, MAKER_CODE_SIZE,
+            ),
+            HeaderError::TitleTooLong(len) => write!(
+                f,
+                "The title is {} bytes long, but it can be at most {} bytes",
+                len, GAME_NAME_SIZE - 1,
+            ),
+            HeaderError::InvalidDiscNumber(n) =>
+                write!(f, "Invalid disc number {}, discs are numbered from 1", n),
+            HeaderError::DiscNumberPastTotal { disc, total } => write!(
+                f,
+                "This is disc {}, but bi2 says there are only {} discs",
+                disc, total,
+            ),
+            HeaderError::MisalignedOffset { section, offset } => write!(
+                f,
+                "The {} is at {:#x}, which isn't a multiple of {}",
+                section, offset, MIN_ALIGNMENT,
+            ),
+            HeaderError::MissingOffset { section } =>
+                write!(f, "The header's {} offset is 0", section),
+            HeaderError::OffsetInHeader { section, offset } => write!(
+                f,
+                "The header says the {} is at {:#x}, inside the header, which ends at {:#x}",
+                section, offset, GAME_HEADER_SIZE,
+            ),
+            HeaderError::OffsetInApploader { section, offset, apploader_end } => write!(
+                f,
+                "The header says the {} is at {:#x}, inside the apploader, which ends at {:#x}",
+                section, offset, apploader_end,
+            ),
+            HeaderError::SharedOffset(offset) =>
+                write!(f, "The header says the DOL and the FST are both at {:#x}", offset),
+            HeaderError::OffsetPastEnd { section, offset, len } => write!(
+                f,
+                "The header says the {} is at {:#x}, past the end of the image at {:#x}",
+                section, offset, len,
+            ),
+        }
+    }
+}
+
+impl Error for HeaderError {}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct HeaderInformation {
     pub debug_monitor_size: u32,
     pub simulated_memory_size: u32,
@@ -86,7 +326,15 @@ pub struct HeaderInformation {
     pub track_location: u32,
     pub track_size: u32,
     pub country_code: u32,
-    pub unknown: u32,
+    // 0 on most single disc games.
+    pub total_discs: u32,
+    pub long_file_name: u32,
+    pub pad_spec: u32,
+    pub dol_limit: u32,
+    // The whole block as it was read. `write` puts the fields above over this,
+    // so the parts nobody understands yet survive a round trip.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub raw: Vec<u8>,
 }
 
 impl HeaderInformation {
@@ -95,19 +343,34 @@ impl HeaderInformation {
         R: Read + Seek,
     {
         file.seek(SeekFrom::Start(offset))?;
+        let mut raw = vec![0; BI2_SIZE];
+        file.read_exact(&mut raw)?;
+
+        let mut fields = &raw[..];
         Ok(HeaderInformation {
-            debug_monitor_size: file.read_u32::<BigEndian>()?,
-            simulated_memory_size: file.read_u32::<BigEndian>()?,
-            argument_offset: file.read_u32::<BigEndian>()?,
-            debug_flag: file.read_u32::<BigEndian>()?,
-            track_location: file.read_u32::<BigEndian>()?,
-            track_size: file.read_u32::<BigEndian>()?,
-            country_code: file.read_u32::<BigEndian>()?,
-            unknown: file.read_u32::<BigEndian>()?,
+            debug_monitor_size: fields.read_u32::<BigEndian>()?,
+            simulated_memory_size: fields.read_u32::<BigEndian>()?,
+            argument_offset: fields.read_u32::<BigEndian>()?,
+            debug_flag: fields.read_u32::<BigEndian>()?,
+            track_location: fields.read_u32::<BigEndian>()?,
+            track_size: fields.read_u32::<BigEndian>()?,
+            country_code: fields.read_u32::<BigEndian>()?,
+            total_discs: fields.read_u32::<BigEndian>()?,
+            long_file_name: fields.read_u32::<BigEndian>()?,
+            pad_spec: fields.read_u32::<BigEndian>()?,
+            dol_limit: fields.read_u32::<BigEndian>()?,
+            raw,
         })
     }
 
-    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+    pub fn write(&self, writer: impl Write) -> io::Result<()> {
+        self.write_over(&self.raw, writer)
+    }
+
+    // Writes the whole block, using `base` for everything after the known
+    // fields. An empty `base` (like from a header that was deserialized) is
+    // treated as all zeroes.
+    pub fn write_over(&self, base: &[u8], mut writer: impl Write) -> io::Result<()> {
         writer.write_u32::<BigEndian>(self.debug_monitor_size)?;
         writer.write_u32::<BigEndian>(self.simulated_memory_size)?;
         writer.write_u32::<BigEndian>(self.argument_offset)?;
@@ -115,17 +378,156 @@ impl HeaderInformation {
         writer.write_u32::<BigEndian>(self.track_location)?;
         writer.write_u32::<BigEndian>(self.track_size)?;
         writer.write_u32::<BigEndian>(self.country_code)?;
-        writer.write_u32::<BigEndian>(self.unknown)?;
-        Ok(())
+        writer.write_u32::<BigEndian>(self.total_discs)?;
+        writer.write_u32::<BigEndian>(self.long_file_name)?;
+        writer.write_u32::<BigEndian>(self.pad_spec)?;
+        writer.write_u32::<BigEndian>(self.dol_limit)?;
+
+        match base.get(BI2_KNOWN_SIZE..BI2_SIZE) {
+            Some(rest) => writer.write_all(rest),
+            None => writer.write_all(&[0; BI2_SIZE - BI2_KNOWN_SIZE]),
+        }
+    }
+}
+
+impl HeaderInformation {
+    // What a retail game would have, apart from `country_code`.
+    pub fn default_with(country_code: u32) -> HeaderInformation {
+        HeaderInformation {
+            debug_monitor_size: 0,
+            simulated_memory_size: DEFAULT_SIMULATED_MEMORY_SIZE,
+            argument_offset: 0,
+            debug_flag: 0,
+            track_location: 0,
+            track_size: 0,
+            country_code,
+            total_discs: 0,
+            long_file_name: 0,
+            pad_spec: 0,
+            dol_limit: 0,
+            raw: Vec::new(),
+        }
+    }
+}
+
+// The last character of a game code is its region. Unknown regions are
+// treated as USA.
+pub fn country_code_for_region(region: char) -> u32 {
+    match region.to_ascii_uppercase() {
+        'J' => COUNTRY_CODE_JAPAN,
+        'K' | 'Q' | 'T' => COUNTRY_CODE_KOREA,
+        'P' | 'D' | 'F' | 'H' | 'I' | 'S' | 'U' | 'X' | 'Y' | 'Z' => COUNTRY_CODE_PAL,
+        _ => COUNTRY_CODE_USA,
+    }
+}
+
+pub fn region_name(country_code: u32) -> &'static str {
+    match country_code {
+        COUNTRY_CODE_JAPAN => "NTSC-J",
+        COUNTRY_CODE_USA => "NTSC-U",
+        COUNTRY_CODE_PAL => "PAL",
+        COUNTRY_CODE_KOREA => "NTSC-K",
+        _ => "Unknown",
     }
 }
 
+// The publisher a maker code is for. These are Nintendo's licensee codes, and
+// this is only the big ones, so plenty of real codes aren't here.
+pub fn maker_name(maker_code: &str) -> Option<&'static str> {
+    let name = match maker_code {
+        "01" => "Nintendo",
+        "08" => "Capcom",
+        "41" => "Ubisoft",
+        "4F" => "Eidos",
+        "51" => "Acclaim",
+        "52" => "Activision",
+        "5D" => "Midway",
+        "5G" => "Majesco",
+        "64" => "LucasArts",
+        "69" => "Electronic Arts",
+        "70" => "Atari",
+        "78" => "THQ",
+        "8P" => "Sega",
+        "A4" => "Konami",
+        "AF" => "Namco",
+        _ => return None,
+    };
+    Some(name)
+}
+
 impl Header {
+    // A header for a new game. The offsets and sizes are all 0, since
+    // rebuilding fills them in.
+    pub fn default_with(game_code: &str, maker_code: &str, title: &str) -> Result<Header, HeaderError> {
+        let region = game_code.chars().last().unwrap_or('E');
+        let header = Header {
+            game_code: game_code.to_ascii_uppercase(),
+            maker_code: maker_code.to_ascii_uppercase(),
+            disk_id: 0,
+            version: 0,
+            audio_streaming: 0,
+            stream_buffer_size: 0,
+            title: title.to_owned(),
+            debug_monitor_offset: 0,
+            debug_monitor_load_addr: 0,
+            dol_offset: 0,
+            fst_offset: 0,
+            fst_size: 0,
+            max_fst_size: 0,
+            user_position: 0,
+            user_length: 0,
+            unknown: 0,
+            information: HeaderInformation::default_with(country_code_for_region(region)),
+            raw: Vec::new(),
+            dirty: DirtyFields::default(),
+        };
+        header.validate()?;
+        Ok(header)
+    }
+
+    // Checks that the header can be written, and that it won't hang the
+    // console because of its streaming settings.
+    pub fn validate(&self) -> Result<(), HeaderError> {
+        let is_code = |code: &str, size| code.len() == size && code.bytes().all(|b| b.is_ascii_alphanumeric());
+        if !is_code(&self.game_code, GAME_CODE_SIZE) {
+            return Err(HeaderError::InvalidGameCode(self.game_code.clone()));
+        }
+        if !is_code(&self.maker_code, MAKER_CODE_SIZE) {
+            return Err(HeaderError::InvalidMakerCode(self.maker_code.clone()));
+        }
+        // Leave room for the NUL terminator
+        if self.title.len() >= GAME_NAME_SIZE {
+            return Err(HeaderError::TitleTooLong(self.title.len()));
+        }
+        match self.audio_streaming_problem() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
     pub fn new<R>(mut file: R, offset: u64) -> io::Result<Header>
     where
         R: BufRead + Seek,
     {
         file.seek(SeekFrom::Start(offset))?;
+        let mut raw = vec![0; GAME_HEADER_SIZE];
+        file.read_exact(&mut raw)?;
+        Header::parse_from_bytes(&raw)
+    }
+
+    // Parses the header block at the start of `bytes`, which has to be at
+    // least `GAME_HEADER_SIZE` long.
+    pub fn parse_from_bytes(bytes: &[u8]) -> io::Result<Header> {
+        let raw = bytes.get(..GAME_HEADER_SIZE).ok_or_else(|| io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("The header is {:#x} bytes long, but it should be {:#x}", bytes.len(), GAME_HEADER_SIZE),
+        ))?;
+        let mut header = Header::parse(Cursor::new(raw))?;
+        header.raw = raw.to_vec();
+        Ok(header)
+    }
+
+    fn parse(mut file: impl BufRead + Seek) -> io::Result<Header> {
         let mut game_code = String::with_capacity(GAME_CODE_SIZE);
         file.by_ref().take(GAME_CODE_SIZE as u64)
             .read_to_string(&mut game_code)?;
@@ -180,6 +582,7 @@ impl Header {
         let user_length = file.read_u32::<BigEndian>()?;
         let unknown = file.read_u32::<BigEndian>()?;
 
+        file.seek(SeekFrom::Current(UNUSED_REGION_3_SIZE as i64))?;
         let pos = file.stream_position()?;
         let information = HeaderInformation::new(file, pos)?;
 
@@ -201,22 +604,245 @@ impl Header {
             user_length,
             unknown,
             information,
+            raw: Vec::new(),
+            dirty: DirtyFields::default(),
+        })
+    }
+
+    // The header block exactly as it was read, or nothing if it wasn't read
+    // from a ROM.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    // How much of the disc the header takes up, including bi2 and everything
+    // after the fields gcmod knows about.
+    pub fn size_on_disc(&self) -> usize {
+        GAME_HEADER_SIZE
+    }
+
+    // The fields changed through setters, or marked with `mark_dirty`.
+    pub fn dirty_fields(&self) -> DirtyFields {
+        self.dirty
+    }
+
+    // For fields changed without a setter, so `write_patched` writes them.
+    pub fn mark_dirty(&mut self, field: HeaderField) {
+        self.dirty.insert(field);
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<(), HeaderError> {
+        // Leave room for the NUL terminator
+        if title.len() >= GAME_NAME_SIZE {
+            return Err(HeaderError::TitleTooLong(title.len()));
+        }
+        self.title = title.to_owned();
+        self.dirty.insert(HeaderField::Title);
+        Ok(())
+    }
+
+    // Where the DOL and FST are, like after rebuilding.
+    pub fn set_layout(&mut self, dol_offset: u64, fst_offset: u64, fst_size: usize, max_fst_size: usize) {
+        self.dol_offset = dol_offset;
+        self.fst_offset = fst_offset;
+        self.fst_size = fst_size;
+        self.max_fst_size = max_fst_size;
+        for field in [HeaderField::DolOffset, HeaderField::FstOffset, HeaderField::FstSize, HeaderField::MaxFstSize] {
+            self.dirty.insert(field);
+        }
+    }
+
+    pub fn set_user_area(&mut self, position: u32, length: u32) {
+        self.user_position = position;
+        self.user_length = length;
+        self.dirty.insert(HeaderField::UserPosition);
+        self.dirty.insert(HeaderField::UserLength);
+    }
+
+    // Turning streaming on takes a buffer size, or keeps the current one if
+    // it's valid. Turning it off always zeroes the buffer size.
+    pub fn set_audio_streaming(&mut self, enabled: bool, buffer_size: Option<u8>) -> Result<(), HeaderError> {
+        if enabled {
+            let size = buffer_size
+                .or(Some(self.stream_buffer_size).filter(|&s| s != 0))
+                .ok_or(HeaderError::StreamBufferSizeRequired)?;
+            if !STREAM_BUFFER_SIZE_RANGE.contains(&size) {
+                return Err(HeaderError::InvalidStreamBufferSize(size));
+            }
+            self.audio_streaming = 1;
+            self.stream_buffer_size = size;
+        } else {
+            self.audio_streaming = 0;
+            self.stream_buffer_size = 0;
+        }
+        self.dirty.insert(HeaderField::AudioStreaming);
+        self.dirty.insert(HeaderField::StreamBufferSize);
+        Ok(())
+    }
+
+    // The problem with the current streaming settings, if there is one.
+    pub fn audio_streaming_problem(&self) -> Option<HeaderError> {
+        if self.audio_streaming == 0 {
+            None
+        } else if self.stream_buffer_size == 0 {
+            Some(HeaderError::StreamBufferSizeRequired)
+        } else if !STREAM_BUFFER_SIZE_RANGE.contains(&self.stream_buffer_size) {
+            Some(HeaderError::InvalidStreamBufferSize(self.stream_buffer_size))
+        } else {
+            None
+        }
+    }
+
+    // The IPL and apploader copy the DOL and FST in 4 byte words, so their
+    // offsets have to line up with them.
+    pub fn alignment_problem(&self) -> Option<HeaderError> {
+        [("DOL", self.dol_offset), ("FST", self.fst_offset)].into_iter()
+            .find(|&(_, offset)| !offset.is_multiple_of(MIN_ALIGNMENT))
+            .map(|(section, offset)| HeaderError::MisalignedOffset { section, offset })
+    }
+
+    // The first problem with where the header says the DOL and FST are,
+    // which would have them read from the header, the apploader, each
+    // other, or nowhere, turning whatever's there into nonsense. `len` is
+    // the image's length from the header. The apploader's end is only
+    // trusted if it's inside the image.
+    pub fn offset_problem(&self, apploader: Option<&Apploader>, len: u64) -> Option<HeaderError> {
+        [("DOL", self.dol_offset), ("FST", self.fst_offset)].into_iter()
+            .find_map(|(section, offset)| section_offset_problem(section, offset, apploader, len))
+            .or_else(|| (self.dol_offset == self.fst_offset).then_some(HeaderError::SharedOffset(self.dol_offset)))
+    }
+
+    // The disc number starts at 1, though it's stored starting at 0. Without
+    // a `total`, bi2's total is raised to the disc number if it's lower, so
+    // the two always agree afterwards.
+    pub fn set_disc(&mut self, disc: u8, total: Option<u32>) -> Result<(), HeaderError> {
+        if disc == 0 {
+            return Err(HeaderError::InvalidDiscNumber(disc));
+        }
+        let total = match total {
+            Some(total) if total < disc as u32 =>
+                return Err(HeaderError::DiscNumberPastTotal { disc: disc as u32, total }),
+            Some(total) => total,
+            None => self.information.total_discs.max(disc as u32),
+        };
+        self.disk_id = disc - 1;
+        self.information.total_discs = total;
+        self.dirty.insert(HeaderField::DiskId);
+        self.dirty.insert(HeaderField::TotalDiscs);
+        Ok(())
+    }
+
+    // The area a development image reserves for the debug monitor, which
+    // retail games don't have.
+    pub fn debug_monitor(&self) -> Option<DebugMonitor> {
+        (self.debug_monitor_offset != 0 && self.information.debug_monitor_size != 0).then_some(DebugMonitor {
+            offset: self.debug_monitor_offset as u64,
+            size: self.information.debug_monitor_size as usize,
+            load_addr: self.debug_monitor_load_addr,
         })
     }
 
-    pub fn extract<R, W>(mut iso: R, mut output: W) -> io::Result<()>
+    // For rebuilds that put files where the debug monitor was.
+    pub fn clear_debug_monitor(&mut self) {
+        self.debug_monitor_offset = 0;
+        self.debug_monitor_load_addr = 0;
+        self.information.debug_monitor_size = 0;
+        for field in [HeaderField::DebugMonitorOffset, HeaderField::DebugMonitorLoadAddr, HeaderField::DebugMonitorSize] {
+            self.dirty.insert(field);
+        }
+    }
+
+    // The problem with the disc number, if bi2 has a total it's past.
+    pub fn disc_problem(&self) -> Option<HeaderError> {
+        let (disc, total) = (self.disk_id as u32 + 1, self.information.total_discs);
+        (total != 0 && disc > total).then_some(HeaderError::DiscNumberPastTotal { disc, total })
+    }
+
+    // Overwrites just the streaming fields of the header starting at `offset`
+    // in `iso`.
+    pub fn patch_audio_streaming(&self, iso: &mut RomWriteHandle, offset: u64) -> io::Result<()> {
+        self.patch_audio_streaming_in(iso, offset)
+    }
+
+    fn patch_audio_streaming_in(&self, mut file: impl Write + Seek, offset: u64) -> io::Result<()> {
+        file.seek(SeekFrom::Start(offset + AUDIO_STREAMING_OFFSET))?;
+        file.write_u8(self.audio_streaming)?;
+        file.seek(SeekFrom::Start(offset + STREAM_BUFFER_SIZE_OFFSET))?;
+        file.write_u8(self.stream_buffer_size)?;
+        file.flush()
+    }
+
+    // Writes the bytes of this header that differ from `original`, the
+    // header currently at `offset` in `iso`. Unlike `write`, this leaves any
+    // data gcmod doesn't know about alone.
+    pub fn write_changes(&self, original: &Header, iso: &mut RomWriteHandle, offset: u64) -> io::Result<()> {
+        self.write_changes_in(original, iso, offset)
+    }
+
+    fn write_changes_in(&self, original: &Header, mut file: impl Write + Seek, offset: u64) -> io::Result<()> {
+        // A deserialized header doesn't have bi2's raw block
+        let info_base = if self.information.raw.is_empty() {
+            &original.information.raw
+        } else {
+            &self.information.raw
+        };
+        let (mut old, mut new) = (Vec::new(), Vec::new());
+        original.write(&mut old)?;
+        self.write_over(info_base, &mut new)?;
+
+        let mut i = 0;
+        while i < new.len() {
+            if old[i] == new[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < new.len() && old[i] != new[i] {
+                i += 1;
+            }
+            file.seek(SeekFrom::Start(offset + start as u64))?;
+            file.write_all(&new[start..i])?;
+        }
+        file.flush()
+    }
+
+    // `base` is where the image starts in `iso`.
+    pub fn extract<R, W>(mut iso: R, mut output: W, base: u64) -> io::Result<()>
     where
         R: Read + Seek,
         W: Write,
     {
-        iso.seek(SeekFrom::Start(0))?;
+        iso.seek(SeekFrom::Start(base))?;
         io::copy(
             &mut iso.take(GAME_HEADER_SIZE as u64),
             &mut output,
         ).map(drop)
     }
 
-    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+    pub fn write(&self, writer: impl Write) -> io::Result<()> {
+        self.write_over(&self.information.raw, writer)
+    }
+
+    // Writes the block as it was read, with only the dirty fields changed,
+    // so everything else is the same down to the byte. Headers that weren't
+    // read from a ROM are written whole, like with `write`.
+    pub fn write_patched(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut new = Vec::with_capacity(GAME_HEADER_SIZE);
+        self.write(&mut new)?;
+        if self.raw.is_empty() {
+            return writer.write_all(&new);
+        }
+        let mut patched = self.raw.clone();
+        for field in self.dirty.iter() {
+            let range = field.range();
+            patched[range.clone()].copy_from_slice(&new[range]);
+        }
+        writer.write_all(&patched)
+    }
+
+    // `info_base` is what's under bi2's known fields, see
+    // `HeaderInformation::write_over`.
+    fn write_over(&self, info_base: &[u8], mut writer: impl Write) -> io::Result<()> {
         let mut buf = Vec::new();
 
         writer.write_all(self.game_code.as_bytes())?;
@@ -254,25 +880,63 @@ impl Header {
         buf.resize(UNUSED_REGION_3_SIZE, 0);
         writer.write_all(&buf[..])?;
 
-        self.information.write(&mut writer)?;
+        self.information.write_over(info_base, &mut writer)
+    }
+}
 
-        // There's just a bunch of left over space here, it may sometimes
-        // contain some information, I don't know...
-        // Email me if you know what this is about.
-        buf.resize(0x1fe0, 0);
-        writer.write_all(&buf[..])?;
+// What `info -t header` shows, for `--json`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HeaderInfo {
+    pub game_id: String,
+    pub title: String,
+    pub dol_offset: u64,
+    pub fst_offset: u64,
+    pub fst_size: usize,
+    pub audio_streaming: bool,
+    pub stream_buffer_size: u8,
+    // From 1
+    pub disc: u32,
+    // None when bi2 says 0, which older images do
+    pub total_discs: Option<u32>,
+}
 
-        Ok(())
+impl Header {
+    pub fn info(&self) -> HeaderInfo {
+        HeaderInfo {
+            game_id: format!("{}{}", self.game_code, self.maker_code),
+            title: self.title.clone(),
+            dol_offset: self.dol_offset,
+            fst_offset: self.fst_offset,
+            fst_size: self.fst_size,
+            audio_streaming: self.audio_streaming != 0,
+            stream_buffer_size: self.stream_buffer_size,
+            disc: self.disk_id as u32 + 1,
+            total_discs: Some(self.information.total_discs).filter(|&t| t != 0),
+        }
     }
 }
 
 impl Section for Header {
-    fn print_info(&self, style: NumberStyle) {
-        println!("Game ID: {}{}", self.game_code, self.maker_code);
-        println!("Title: {}", self.title);
-        println!("DOL offset: {}", format_u64(self.dol_offset, style));
-        println!("FST offset: {}", format_u64(self.fst_offset, style));
-        println!("FST size: {} bytes", format_usize(self.fst_size, style));
+    fn fields(&self) -> Vec<Field> {
+        let info = self.info();
+        vec![
+            Field::text("Game ID", info.game_id),
+            Field::text("Title", info.title),
+            Field::number("DOL offset", info.dol_offset),
+            Field::number("FST offset", info.fst_offset),
+            Field::bytes("FST size", info.fst_size as u64),
+            Field::text("Audio streaming", if info.audio_streaming { "on" } else { "off" }),
+            Field::text("Stream buffer size", info.stream_buffer_size.to_string()),
+            Field::text("Disc", match info.total_discs {
+                None => info.disc.to_string(),
+                Some(total) => format!("{} of {}", info.disc, total),
+            }),
+        ]
+    }
+
+    fn name(&self) -> String {
+        "ISO.hdr".to_owned()
     }
 
     fn start(&self) -> u64 {
@@ -282,4 +946,408 @@ impl Section for Header {
     fn size(&self) -> usize {
         GAME_HEADER_SIZE
     }
+
+    fn is_system_data(&self) -> bool {
+        true
+    }
+}
+
+// From `Header::debug_monitor`. `offset` is from the start of the image,
+// until `Game` makes it absolute like the other sections'.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DebugMonitor {
+    pub offset: u64,
+    pub size: usize,
+    pub load_addr: u32,
+}
+
+impl DebugMonitor {
+    // (start, end), with `end` exclusive
+    pub fn range(&self) -> (u64, u64) {
+        (self.offset, self.offset + self.size as u64)
+    }
+}
+
+impl Section for DebugMonitor {
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::number("Offset", self.offset),
+            Field::bytes("Size (reserved for the debug monitor)", self.size as u64),
+            Field::number("Load address", self.load_addr as u64),
+        ]
+    }
+
+    fn name(&self) -> String {
+        "Debug monitor".to_owned()
+    }
+
+    fn start(&self) -> u64 {
+        self.offset
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    // Nothing else should go in it either
+    fn is_system_data(&self) -> bool {
+        true
+    }
+}
+
+// `Header::offset_problem`'s checks of one section's offset, other than
+// whether the other one is at the same place.
+pub fn section_offset_problem(
+    section: &'static str,
+    offset: u64,
+    apploader: Option<&Apploader>,
+    len: u64,
+) -> Option<HeaderError> {
+    let apploader_end = apploader
+        .map(|a| APPLOADER_OFFSET + a.total_size() as u64)
+        .filter(|&end| end <= len);
+    if offset == 0 {
+        Some(HeaderError::MissingOffset { section })
+    } else if offset < GAME_HEADER_SIZE as u64 {
+        Some(HeaderError::OffsetInHeader { section, offset })
+    } else if let Some(apploader_end) = apploader_end.filter(|&end| offset < end) {
+        Some(HeaderError::OffsetInApploader { section, offset, apploader_end })
+    } else if offset >= len {
+        Some(HeaderError::OffsetPastEnd { section, offset, len })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn header() -> Header {
+        Header::default_with("GSLF", "01", "test").unwrap()
+    }
+
+    fn streaming(header: &Header) -> (u8, u8) {
+        (header.audio_streaming, header.stream_buffer_size)
+    }
+
+    #[test]
+    fn turning_streaming_on() {
+        let mut h = header();
+        h.set_audio_streaming(true, Some(10)).unwrap();
+        assert_eq!(streaming(&h), (1, 10));
+        // Keeping the size it has
+        h.set_audio_streaming(true, None).unwrap();
+        assert_eq!(streaming(&h), (1, 10));
+        h.set_audio_streaming(true, Some(1)).unwrap();
+        assert_eq!(streaming(&h), (1, 1));
+        h.set_audio_streaming(true, Some(15)).unwrap();
+        assert_eq!(streaming(&h), (1, 15));
+        assert!(h.audio_streaming_problem().is_none());
+        assert!(h.validate().is_ok());
+    }
+
+    #[test]
+    fn streaming_needs_a_valid_buffer_size() {
+        let mut h = header();
+        assert!(matches!(h.set_audio_streaming(true, None), Err(HeaderError::StreamBufferSizeRequired)));
+        assert!(matches!(h.set_audio_streaming(true, Some(0)), Err(HeaderError::InvalidStreamBufferSize(0))));
+        assert!(matches!(h.set_audio_streaming(true, Some(16)), Err(HeaderError::InvalidStreamBufferSize(16))));
+        // Failing changes nothing
+        assert_eq!(streaming(&h), (0, 0));
+        assert!(h.dirty.is_empty());
+    }
+
+    #[test]
+    fn turning_streaming_off_zeroes_the_buffer_size() {
+        let mut h = header();
+        h.set_audio_streaming(true, Some(5)).unwrap();
+        h.set_audio_streaming(false, None).unwrap();
+        assert_eq!(streaming(&h), (0, 0));
+        h.set_audio_streaming(false, Some(5)).unwrap();
+        assert_eq!(streaming(&h), (0, 0));
+    }
+
+    #[test]
+    fn streaming_problems() {
+        let mut h = header();
+        for (values, problem) in [((0, 0), None), ((0, 99), None), ((1, 3), None), ((1, 0), Some("needs")), ((1, 16), Some("16"))] {
+            (h.audio_streaming, h.stream_buffer_size) = values;
+            let found = h.audio_streaming_problem().map(|e| e.to_string());
+            match problem {
+                None => assert!(found.is_none(), "{:?}: {:?}", values, found),
+                Some(text) => assert!(found.as_ref().is_some_and(|e| e.contains(text)), "{:?}: {:?}", values, found),
+            }
+            assert_eq!(h.validate().is_ok(), problem.is_none());
+        }
+    }
+
+    #[test]
+    fn patching_streaming_only_writes_its_bytes() {
+        let mut original = Vec::new();
+        header().write(&mut original).unwrap();
+        let mut h = header();
+        h.set_audio_streaming(true, Some(7)).unwrap();
+
+        let mut patched = Cursor::new(original.clone());
+        h.patch_audio_streaming_in(&mut patched, 0).unwrap();
+        let patched = patched.into_inner();
+        let changed: Vec<usize> = (0..original.len()).filter(|&i| original[i] != patched[i]).collect();
+        assert_eq!(changed, [AUDIO_STREAMING_OFFSET as usize, STREAM_BUFFER_SIZE_OFFSET as usize]);
+        assert_eq!((patched[8], patched[9]), (1, 7));
+
+        let read = Header::new(Cursor::new(&patched), 0).unwrap();
+        assert_eq!(streaming(&read), (1, 7));
+    }
+
+    #[test]
+    fn a_new_header_reads_back() {
+        let h = Header::default_with("ghbe", "0a", "My Homebrew").unwrap();
+        let mut bytes = Vec::new();
+        h.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), GAME_HEADER_SIZE);
+        assert_eq!(bytes[0x1c..0x20], MAGIC_WORD.to_be_bytes());
+
+        let read = Header::new(Cursor::new(&bytes), 0).unwrap();
+        assert_eq!(read.game_code, "GHBE");
+        assert_eq!(read.maker_code, "0A");
+        assert_eq!(read.title, "My Homebrew");
+        assert_eq!((read.dol_offset, read.fst_offset, read.fst_size), (0, 0, 0));
+        assert_eq!(read.information.country_code, COUNTRY_CODE_USA);
+        assert_eq!(read.information.simulated_memory_size, DEFAULT_SIMULATED_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn new_headers_get_their_region() {
+        for (code, country) in [("GHBJ", COUNTRY_CODE_JAPAN), ("GHBP", COUNTRY_CODE_PAL), ("GHBK", COUNTRY_CODE_KOREA), ("GHBE", COUNTRY_CODE_USA)] {
+            assert_eq!(Header::default_with(code, "01", "x").unwrap().information.country_code, country, "{}", code);
+        }
+    }
+
+    #[test]
+    fn new_headers_need_valid_fields() {
+        assert!(matches!(Header::default_with("GHB", "01", "x"), Err(HeaderError::InvalidGameCode(_))));
+        assert!(matches!(Header::default_with("GHB!", "01", "x"), Err(HeaderError::InvalidGameCode(_))));
+        assert!(matches!(Header::default_with("GHBE", "001", "x"), Err(HeaderError::InvalidMakerCode(_))));
+        let title = "x".repeat(GAME_NAME_SIZE);
+        assert!(matches!(Header::default_with("GHBE", "01", &title), Err(HeaderError::TitleTooLong(_))));
+        Header::default_with("GHBE", "01", &title[1..]).unwrap();
+    }
+
+    #[test]
+    fn short_or_garbage_headers_fail() {
+        let e = Header::parse_from_bytes(&[0; 0x100]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        // No magic word
+        assert!(Header::new(Cursor::new(vec![0xff; GAME_HEADER_SIZE]), 0).is_err());
+    }
+
+    fn discs(header: &Header) -> (u8, u32) {
+        (header.disk_id, header.information.total_discs)
+    }
+
+    #[test]
+    fn setting_the_disc() {
+        let mut h = header();
+        h.set_disc(2, None).unwrap();
+        // Raised so it isn't less than the disc
+        assert_eq!(discs(&h), (1, 2));
+        h.set_disc(1, None).unwrap();
+        assert_eq!(discs(&h), (0, 2));
+        h.set_disc(3, Some(4)).unwrap();
+        assert_eq!(discs(&h), (2, 4));
+        assert!(h.disc_problem().is_none());
+
+        assert!(matches!(h.set_disc(0, None), Err(HeaderError::InvalidDiscNumber(0))));
+        assert!(matches!(h.set_disc(3, Some(2)), Err(HeaderError::DiscNumberPastTotal { disc: 3, total: 2 })));
+        assert_eq!(discs(&h), (2, 4));
+    }
+
+    #[test]
+    fn disc_problems() {
+        let mut h = header();
+        for (values, problem) in [((0, 0), false), ((3, 0), false), ((1, 2), false), ((2, 2), true), ((0, 1), false)] {
+            (h.disk_id, h.information.total_discs) = values;
+            assert_eq!(h.disc_problem().is_some(), problem, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn setting_the_disc_only_writes_its_bytes() {
+        let mut original = Vec::new();
+        header().write(&mut original).unwrap();
+        // Something in bi2 gcmod doesn't know about
+        original[BI2_OFFSET as usize + 0x100] = 0xab;
+        let read = Header::new(Cursor::new(&original), 0).unwrap();
+        let mut h = read.clone();
+        h.set_disc(2, None).unwrap();
+
+        let mut patched = Cursor::new(original.clone());
+        h.write_changes_in(&read, &mut patched, 0).unwrap();
+        let patched = patched.into_inner();
+        let changed: Vec<usize> = (0..original.len()).filter(|&i| original[i] != patched[i]).collect();
+        let total = TOTAL_DISCS_OFFSET as usize + TOTAL_DISCS_SIZE - 1;
+        assert_eq!(changed, [DISK_ID_OFFSET as usize, total]);
+        assert_eq!(patched[total - 3..=total], 2u32.to_be_bytes());
+
+        let read = Header::new(Cursor::new(&patched), 0).unwrap();
+        assert_eq!(discs(&read), (1, 2));
+        assert_eq!(read.information.raw[0x100], 0xab);
+    }
+
+    // A header whose every byte outside the known fields is junk, which
+    // `write` would zero
+    fn junk_header() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        header().write(&mut bytes).unwrap();
+        let magic = 0x1c..0x20;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            if !magic.contains(&i) && !HeaderField::ALL.iter().any(|f| f.range().contains(&i)) {
+                *byte = (i % 251) as u8 | 0x80;
+            }
+        }
+        bytes
+    }
+
+    fn change(h: &mut Header, field: HeaderField) {
+        let info = &mut h.information;
+        match field {
+            HeaderField::GameCode => h.game_code = "GZZJ".to_owned(),
+            HeaderField::MakerCode => h.maker_code = "99".to_owned(),
+            HeaderField::DiskId => h.disk_id = 3,
+            HeaderField::Version => h.version = 2,
+            HeaderField::AudioStreaming => h.audio_streaming = 1,
+            HeaderField::StreamBufferSize => h.stream_buffer_size = 10,
+            HeaderField::Title => h.title = "Changed".to_owned(),
+            HeaderField::DebugMonitorOffset => h.debug_monitor_offset = 0x1234_5678,
+            HeaderField::DebugMonitorLoadAddr => h.debug_monitor_load_addr = 0x8070_0000,
+            HeaderField::DolOffset => h.dol_offset = 0x1e800,
+            HeaderField::FstOffset => h.fst_offset = 0x2_0000,
+            HeaderField::FstSize => h.fst_size = 0x1_1111,
+            HeaderField::MaxFstSize => h.max_fst_size = 0x2_2222,
+            HeaderField::UserPosition => h.user_position = 0x4_0000,
+            HeaderField::UserLength => h.user_length = 0x100_0000,
+            HeaderField::Unknown => h.unknown = 0xdead_beef,
+            HeaderField::DebugMonitorSize => info.debug_monitor_size = 0x2000,
+            HeaderField::SimulatedMemorySize => info.simulated_memory_size = 0x0300_0000,
+            HeaderField::ArgumentOffset => info.argument_offset = 0x333,
+            HeaderField::DebugFlag => info.debug_flag = 1,
+            HeaderField::TrackLocation => info.track_location = 0x444,
+            HeaderField::TrackSize => info.track_size = 0x555,
+            HeaderField::CountryCode => info.country_code = COUNTRY_CODE_KOREA,
+            HeaderField::TotalDiscs => info.total_discs = 4,
+            HeaderField::LongFileName => info.long_file_name = 1,
+            HeaderField::PadSpec => info.pad_spec = 0x666,
+            HeaderField::DolLimit => info.dol_limit = 0x777,
+        }
+        h.mark_dirty(field);
+    }
+
+    #[test]
+    fn field_ranges() {
+        let mut covered = vec![false; GAME_HEADER_SIZE];
+        for field in HeaderField::ALL {
+            let range = field.range();
+            assert!(!range.is_empty() && range.end <= GAME_HEADER_SIZE, "{:?}: {:x?}", field, range);
+            for i in range {
+                assert!(!covered[i], "{:?} overlaps another field at {:#x}", field, i);
+                covered[i] = true;
+            }
+        }
+        assert_eq!(HeaderField::TotalDiscs.range(), TOTAL_DISCS_OFFSET as usize..TOTAL_DISCS_OFFSET as usize + 4);
+        assert_eq!(HeaderField::DolLimit.range().end, BI2_OFFSET as usize + BI2_KNOWN_SIZE);
+        assert_eq!(HeaderField::Title.range(), 0x20..0x400);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn the_raw_block() {
+        let bytes = junk_header();
+        let mut longer = bytes.clone();
+        longer.extend([0xaa; 0x100]);
+        let h = Header::parse_from_bytes(&longer).unwrap();
+        assert!(h.raw() == bytes.as_slice());
+        assert_eq!(h.size_on_disc(), GAME_HEADER_SIZE);
+        assert!(h.dirty_fields().is_empty());
+
+        let read = Header::new(Cursor::new(&longer), 0).unwrap();
+        assert!(read.raw() == h.raw());
+        assert_eq!(serde_json::to_value(&read).unwrap(), serde_json::to_value(&h).unwrap());
+        // Made rather than read
+        assert!(header().raw().is_empty());
+    }
+
+    #[test]
+    fn patching_nothing_writes_what_was_read() {
+        let bytes = junk_header();
+        let h = Header::parse_from_bytes(&bytes).unwrap();
+        let mut written = Vec::new();
+        h.write(&mut written).unwrap();
+        assert!(written != bytes, "the junk should be lost without patching");
+
+        let mut patched = Vec::new();
+        h.write_patched(&mut patched).unwrap();
+        assert!(patched == bytes);
+    }
+
+    #[test]
+    fn patching_only_writes_each_dirty_field() {
+        let bytes = junk_header();
+        let read = Header::parse_from_bytes(&bytes).unwrap();
+        for field in HeaderField::ALL {
+            let mut h = read.clone();
+            change(&mut h, field);
+            assert!(h.dirty_fields().iter().eq([field]), "{:?}", field);
+
+            let mut patched = Vec::new();
+            h.write_patched(&mut patched).unwrap();
+            assert_eq!(patched.len(), GAME_HEADER_SIZE);
+            let changed: Vec<usize> = (0..GAME_HEADER_SIZE).filter(|&i| patched[i] != bytes[i]).collect();
+            assert!(!changed.is_empty(), "{:?} didn't change", field);
+            assert!(changed.iter().all(|i| field.range().contains(i)), "{:?}: {:x?}", field, changed);
+
+            // And it reads back as the new value
+            let back = Header::parse_from_bytes(&patched).unwrap();
+            let mut new = Vec::new();
+            h.write(&mut new).unwrap();
+            assert!(new[field.range()] == patched[field.range()], "{:?}", field);
+            let mut rewritten = Vec::new();
+            back.write(&mut rewritten).unwrap();
+            assert!(rewritten == new, "{:?}", field);
+        }
+    }
+
+    #[test]
+    fn setters_mark_their_fields() {
+        let mut h = Header::parse_from_bytes(&junk_header()).unwrap();
+        h.set_title("Set").unwrap();
+        h.set_user_area(0x1_0000, 0x2_0000);
+        h.set_layout(0x3000, 0x2800, 0xa7, 0xa7);
+        let dirty: Vec<HeaderField> = h.dirty_fields().iter().collect();
+        assert_eq!(dirty, [
+            HeaderField::Title, HeaderField::DolOffset, HeaderField::FstOffset, HeaderField::FstSize,
+            HeaderField::MaxFstSize, HeaderField::UserPosition, HeaderField::UserLength,
+        ]);
+        // Failing doesn't
+        assert!(h.set_audio_streaming(true, Some(0)).is_err());
+        assert!(!h.dirty_fields().contains(HeaderField::AudioStreaming));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn headers_that_werent_read_are_written_whole() {
+        let bytes = junk_header();
+        let read = Header::parse_from_bytes(&bytes).unwrap();
+        let mut deserialized: Header = serde_json::from_value(serde_json::to_value(&read).unwrap()).unwrap();
+        assert!(deserialized.raw().is_empty());
+        deserialized.set_title("Set").unwrap();
+
+        let (mut whole, mut patched) = (Vec::new(), Vec::new());
+        deserialized.write(&mut whole).unwrap();
+        deserialized.write_patched(&mut patched).unwrap();
+        assert!(patched == whole);
+    }
 }
@@ -1,7 +1,10 @@
 pub mod apploader;
+pub mod banner;
 pub mod dol;
 pub mod fst;
 pub mod header;
+pub mod rel;
+pub mod thp;
 
 mod section;
-pub use section::Section;
+pub use section::{field, print_fields, write_fields, Field, FieldValue, Section};
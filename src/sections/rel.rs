@@ -0,0 +1,490 @@
+// RELs are the dynamically linked modules some games load at runtime, kind of
+// like DLLs. Only the header and the tables it points to are parsed here.
+// http://wiki.tockdom.com/wiki/REL_(File_Format)
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{format_u64, format_usize, NumberStyle};
+
+pub const REL_HEADER_V1_SIZE: usize = 0x40;
+pub const REL_HEADER_V2_SIZE: usize = 0x48;
+pub const REL_HEADER_V3_SIZE: usize = 0x4C;
+
+pub const REL_SECTION_INFO_SIZE: usize = 8;
+pub const REL_IMPORT_SIZE: usize = 8;
+
+// Real modules have around 20 sections, this is just to reject garbage.
+pub const REL_MAX_SECTIONS: u32 = 0x100;
+
+#[derive(Copy, Clone, Debug)]
+pub struct RelSection {
+    pub offset: u64,
+    pub size: usize,
+    pub executable: bool,
+}
+
+impl RelSection {
+    // BSS sections have a size, but no data in the file.
+    pub fn is_bss(&self) -> bool {
+        self.offset == 0 && self.size != 0
+    }
+
+    pub fn kind(&self) -> &'static str {
+        if self.is_bss() {
+            "bss"
+        } else if self.executable {
+            "executable"
+        } else {
+            "data"
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RelImport {
+    pub module_id: u32,
+    pub offset: u64,
+}
+
+// A section index and an offset within that section.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RelSymbol {
+    pub section: u8,
+    pub offset: u64,
+}
+
+#[derive(Debug)]
+pub struct RelHeader {
+    pub module_id: u32,
+    pub version: u32,
+    pub sections: Vec<RelSection>,
+    pub name_offset: u64,
+    pub name_size: usize,
+    pub bss_size: usize,
+    pub relocation_offset: u64,
+    pub imports: Vec<RelImport>,
+    pub bss_section: u8,
+    pub prolog: RelSymbol,
+    pub epilog: RelSymbol,
+    pub unresolved: RelSymbol,
+    // Version 2 and up
+    pub align: Option<u32>,
+    pub bss_align: Option<u32>,
+    // Version 3 and up
+    pub fix_size: Option<u32>,
+}
+
+// What `info -t rel` shows, for `--json`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RelInfo {
+    pub module_id: u32,
+    pub version: u32,
+    pub header_size: usize,
+    pub bss_size: usize,
+    pub prolog: RelSymbol,
+    pub epilog: RelSymbol,
+    pub unresolved: RelSymbol,
+    pub align: Option<u32>,
+    pub bss_align: Option<u32>,
+    pub fix_size: Option<u32>,
+    pub sections: Vec<RelSectionInfo>,
+    pub imports: Vec<RelImport>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RelSectionInfo {
+    pub offset: u64,
+    pub size: usize,
+    // See `RelSection::kind`
+    pub kind: &'static str,
+}
+
+fn header_size(version: u32) -> usize {
+    match version {
+        1 => REL_HEADER_V1_SIZE,
+        2 => REL_HEADER_V2_SIZE,
+        _ => REL_HEADER_V3_SIZE,
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl RelHeader {
+    // A REL that goes to the end of `file`, like a standalone one.
+    pub fn new<R>(mut file: R, offset: u64) -> io::Result<RelHeader>
+    where
+        R: Read + Seek,
+    {
+        let size = file.seek(SeekFrom::End(0))?.saturating_sub(offset);
+        RelHeader::with_size(file, offset, size)
+    }
+
+    // A REL that's `size` bytes, like a file on a ROM. Nothing it points to
+    // can be past that.
+    pub fn with_size<R>(mut file: R, offset: u64, size: u64) -> io::Result<RelHeader>
+    where
+        R: Read + Seek,
+    {
+        if size < REL_HEADER_V1_SIZE as u64 {
+            return Err(invalid(format!("The REL is {} bytes, too short for its header", size)));
+        }
+        // Whether `len` bytes at `at` are in the REL
+        let fits = |at: u64, len: u64| at.checked_add(len).is_some_and(|end| end <= size);
+
+        file.seek(SeekFrom::Start(offset))?;
+        let module_id = file.read_u32::<BigEndian>()?;
+        // The next and previous module links are only used at runtime.
+        file.seek(SeekFrom::Current(8))?;
+        let section_count = file.read_u32::<BigEndian>()?;
+        let section_info_offset = file.read_u32::<BigEndian>()? as u64;
+        let name_offset = file.read_u32::<BigEndian>()? as u64;
+        let name_size = file.read_u32::<BigEndian>()? as usize;
+        let version = file.read_u32::<BigEndian>()?;
+        let bss_size = file.read_u32::<BigEndian>()? as usize;
+        let relocation_offset = file.read_u32::<BigEndian>()? as u64;
+        let import_offset = file.read_u32::<BigEndian>()? as u64;
+        let import_size = file.read_u32::<BigEndian>()? as usize;
+
+        if !(1..=3).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown REL version {}", version),
+            ));
+        }
+        if section_count > REL_MAX_SECTIONS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Too many REL sections ({})", section_count),
+            ));
+        }
+        if !fits(0, header_size(version) as u64) {
+            return Err(invalid(format!("The REL is {} bytes, too short for a version {} header", size, version)));
+        }
+        if !fits(section_info_offset, section_count as u64 * REL_SECTION_INFO_SIZE as u64) {
+            return Err(invalid(format!(
+                "The REL's section table at {:#x} goes past its end at {:#x}", section_info_offset, size,
+            )));
+        }
+        if !fits(import_offset, import_size as u64) {
+            return Err(invalid(format!(
+                "The REL's import table at {:#x} goes past its end at {:#x}", import_offset, size,
+            )));
+        }
+
+        let prolog_section = file.read_u8()?;
+        let epilog_section = file.read_u8()?;
+        let unresolved_section = file.read_u8()?;
+        let bss_section = file.read_u8()?;
+        let prolog = RelSymbol {
+            section: prolog_section,
+            offset: file.read_u32::<BigEndian>()? as u64,
+        };
+        let epilog = RelSymbol {
+            section: epilog_section,
+            offset: file.read_u32::<BigEndian>()? as u64,
+        };
+        let unresolved = RelSymbol {
+            section: unresolved_section,
+            offset: file.read_u32::<BigEndian>()? as u64,
+        };
+
+        let (align, bss_align) = if version >= 2 {
+            (Some(file.read_u32::<BigEndian>()?), Some(file.read_u32::<BigEndian>()?))
+        } else {
+            (None, None)
+        };
+        let fix_size = if version >= 3 {
+            Some(file.read_u32::<BigEndian>()?)
+        } else {
+            None
+        };
+
+        file.seek(SeekFrom::Start(offset + section_info_offset))?;
+        let mut sections = Vec::with_capacity(section_count as usize);
+        for _ in 0..section_count {
+            // The lowest bit of the offset is the executable flag.
+            let raw_offset = file.read_u32::<BigEndian>()?;
+            let section = RelSection {
+                offset: (raw_offset & !1) as u64,
+                size: file.read_u32::<BigEndian>()? as usize,
+                executable: raw_offset & 1 != 0,
+            };
+            // Sections with no offset aren't in the file
+            if section.offset != 0 && !fits(section.offset, section.size as u64) {
+                return Err(invalid(format!(
+                    "REL section {} at {:#x} goes past the REL's end at {:#x}", sections.len(), section.offset, size,
+                )));
+            }
+            sections.push(section);
+        }
+
+        file.seek(SeekFrom::Start(offset + import_offset))?;
+        let import_count = import_size / REL_IMPORT_SIZE;
+        let mut imports = Vec::with_capacity(import_count);
+        for _ in 0..import_count {
+            let import = RelImport {
+                module_id: file.read_u32::<BigEndian>()?,
+                offset: file.read_u32::<BigEndian>()? as u64,
+            };
+            // Where its relocations are
+            if import.offset >= size {
+                return Err(invalid(format!(
+                    "The REL's relocations for module {} at {:#x} are past its end at {:#x}",
+                    import.module_id, import.offset, size,
+                )));
+            }
+            imports.push(import);
+        }
+
+        Ok(RelHeader {
+            module_id,
+            version,
+            sections,
+            name_offset,
+            name_size,
+            bss_size,
+            relocation_offset,
+            imports,
+            bss_section,
+            prolog,
+            epilog,
+            unresolved,
+            align,
+            bss_align,
+            fix_size,
+        })
+    }
+
+    pub fn header_size(&self) -> usize {
+        header_size(self.version)
+    }
+
+    pub fn info(&self) -> RelInfo {
+        RelInfo {
+            module_id: self.module_id,
+            version: self.version,
+            header_size: self.header_size(),
+            bss_size: self.bss_size,
+            prolog: self.prolog,
+            epilog: self.epilog,
+            unresolved: self.unresolved,
+            align: self.align,
+            bss_align: self.bss_align,
+            fix_size: self.fix_size,
+            sections: self.sections.iter()
+                .map(|s| RelSectionInfo { offset: s.offset, size: s.size, kind: s.kind() })
+                .collect(),
+            imports: self.imports.clone(),
+        }
+    }
+
+    pub fn write_info(&self, mut out: impl Write, style: NumberStyle) -> io::Result<()> {
+        let info = self.info();
+        let symbol = |s: &RelSymbol| format!("section {}, offset {}", s.section, format_u64(s.offset, style));
+
+        writeln!(out, "Module ID: {}", format_u64(info.module_id as u64, style))?;
+        writeln!(out, "Version: {}", info.version)?;
+        writeln!(out, "Header size: {} bytes", format_usize(info.header_size, style))?;
+        writeln!(out, "BSS size: {} bytes", format_usize(info.bss_size, style))?;
+        writeln!(out, "Prolog: {}", symbol(&info.prolog))?;
+        writeln!(out, "Epilog: {}", symbol(&info.epilog))?;
+        writeln!(out, "Unresolved: {}", symbol(&info.unresolved))?;
+        if let (Some(align), Some(bss_align)) = (info.align, info.bss_align) {
+            writeln!(out, "Alignment: {}", format_u64(align as u64, style))?;
+            writeln!(out, "BSS alignment: {}", format_u64(bss_align as u64, style))?;
+        }
+        if let Some(fix_size) = info.fix_size {
+            writeln!(out, "Fix size: {}", format_u64(fix_size as u64, style))?;
+        }
+
+        writeln!(out, "Sections:")?;
+        for (i, s) in info.sections.iter().enumerate() {
+            writeln!(
+                out,
+                "    {}: offset {}, size {}, {}",
+                i,
+                format_u64(s.offset, style),
+                format_usize(s.size, style),
+                s.kind,
+            )?;
+        }
+
+        writeln!(out, "Imports:")?;
+        for import in &info.imports {
+            writeln!(
+                out,
+                "    module {} at {}",
+                format_u64(import.module_id as u64, style),
+                format_u64(import.offset, style),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use byteorder::ByteOrder;
+
+    use super::*;
+
+    const SIZE: usize = 0xd0;
+
+    // A REL of `version` with a null section, 0x20 bytes of code at 0x80,
+    // 0x10 bytes of data at 0xa0, and 0x100 bytes of BSS, importing from
+    // modules 0 and 1 with relocations at 0xc0 and 0xc8.
+    fn rel(version: u32) -> Vec<u8> {
+        let mut bytes = vec![0; SIZE];
+        let mut put = |at: usize, value: u32| BigEndian::write_u32(&mut bytes[at..], value);
+        put(0x00, 0x2a);
+        put(0x0c, 4);
+        put(0x10, 0x50);
+        put(0x14, 0x1234);
+        put(0x18, 0x10);
+        put(0x1c, version);
+        put(0x20, 0x100);
+        put(0x24, 0xc0);
+        put(0x28, 0xb0);
+        put(0x2c, 0x10);
+        put(0x30, 0x0102_0003);
+        put(0x34, 0x4);
+        put(0x38, 0x8);
+        put(0x3c, 0xc);
+        if version >= 2 {
+            put(0x40, 0x20);
+            put(0x44, 0x8);
+        }
+        if version >= 3 {
+            put(0x48, 0xb0);
+        }
+        // Sections
+        put(0x58, 0x81);
+        put(0x5c, 0x20);
+        put(0x60, 0xa0);
+        put(0x64, 0x10);
+        put(0x6c, 0x100);
+        // Imports
+        put(0xb4, 0xc0);
+        put(0xb8, 1);
+        put(0xbc, 0xc8);
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> io::Result<RelHeader> {
+        RelHeader::new(Cursor::new(bytes), 0)
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        let e = parse(bytes).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e);
+        e.to_string()
+    }
+
+    #[test]
+    fn fields() {
+        for version in 1..=3 {
+            let rel = parse(&rel(version)).unwrap();
+            assert_eq!(rel.module_id, 0x2a);
+            assert_eq!(rel.version, version);
+            assert_eq!((rel.name_offset, rel.name_size), (0x1234, 0x10));
+            assert_eq!(rel.bss_size, 0x100);
+            assert_eq!(rel.relocation_offset, 0xc0);
+            assert_eq!(rel.bss_section, 3);
+            assert_eq!((rel.prolog.section, rel.prolog.offset), (1, 4));
+            assert_eq!((rel.epilog.section, rel.epilog.offset), (2, 8));
+            assert_eq!((rel.unresolved.section, rel.unresolved.offset), (0, 0xc));
+
+            let sections: Vec<_> = rel.sections.iter().map(|s| (s.offset, s.size, s.kind())).collect();
+            assert_eq!(sections, [(0, 0, "data"), (0x80, 0x20, "executable"), (0xa0, 0x10, "data"), (0, 0x100, "bss")]);
+            let imports: Vec<_> = rel.imports.iter().map(|i| (i.module_id, i.offset)).collect();
+            assert_eq!(imports, [(0, 0xc0), (1, 0xc8)]);
+        }
+    }
+
+    #[test]
+    fn versions() {
+        let v1 = parse(&rel(1)).unwrap();
+        assert_eq!(v1.header_size(), REL_HEADER_V1_SIZE);
+        assert_eq!((v1.align, v1.bss_align, v1.fix_size), (None, None, None));
+        let v2 = parse(&rel(2)).unwrap();
+        assert_eq!(v2.header_size(), REL_HEADER_V2_SIZE);
+        assert_eq!((v2.align, v2.bss_align, v2.fix_size), (Some(0x20), Some(8), None));
+        let v3 = parse(&rel(3)).unwrap();
+        assert_eq!(v3.header_size(), REL_HEADER_V3_SIZE);
+        assert_eq!((v3.align, v3.bss_align, v3.fix_size), (Some(0x20), Some(8), Some(0xb0)));
+
+        let mut v4 = rel(3);
+        BigEndian::write_u32(&mut v4[0x1c..], 4);
+        assert!(error(&v4).contains("Unknown REL version 4"));
+    }
+
+    #[test]
+    fn info() {
+        let mut out = Vec::new();
+        parse(&rel(3)).unwrap().write_info(&mut out, NumberStyle::Hexadecimal).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("Module ID: 0x2a\nVersion: 3\nHeader size: 0x4c bytes\n"), "{}", text);
+        assert!(text.contains("Prolog: section 1, offset 0x4\n"), "{}", text);
+        assert!(text.contains("Fix size: 0xb0\n"), "{}", text);
+        assert!(text.contains("Sections:\n    0: offset 0x0, size 0x0, data\n    1: offset 0x80, size 0x20, executable\n"), "{}", text);
+        assert!(text.ends_with("Imports:\n    module 0x0 at 0xc0\n    module 0x1 at 0xc8\n"), "{}", text);
+    }
+
+    #[test]
+    fn offset_in_a_bigger_file() {
+        let mut bytes = vec![0xff; 0x10];
+        bytes.extend(rel(2));
+        let rel = RelHeader::new(Cursor::new(&bytes), 0x10).unwrap();
+        assert_eq!(rel.module_id, 0x2a);
+        assert_eq!(rel.imports.len(), 2);
+    }
+
+    #[test]
+    fn too_short_for_the_header() {
+        assert!(error(&rel(1)[..0x3f]).contains("too short for its header"));
+        let mut v3 = rel(3);
+        v3.truncate(0x48);
+        assert!(error(&v3).contains("too short for a version 3 header"));
+    }
+
+    #[test]
+    fn tables_past_the_end() {
+        let mut bytes = rel(3);
+        BigEndian::write_u32(&mut bytes[0x10..], 0xc0);
+        assert!(error(&bytes).contains("section table"));
+
+        let mut bytes = rel(3);
+        BigEndian::write_u32(&mut bytes[0x2c..], 0xffff_fff8);
+        assert!(error(&bytes).contains("import table"));
+
+        let mut bytes = rel(3);
+        BigEndian::write_u32(&mut bytes[0x5c..], 0x100);
+        assert!(error(&bytes).contains("section 1"));
+
+        let mut bytes = rel(3);
+        BigEndian::write_u32(&mut bytes[0xbc..], SIZE as u32);
+        assert!(error(&bytes).contains("module 1"));
+    }
+
+    #[test]
+    fn bounded_by_the_entry_not_the_rom() {
+        // Like a REL on a ROM, with other files after it
+        let mut bytes = rel(3);
+        bytes.resize(0x1000, 0);
+        assert!(RelHeader::with_size(Cursor::new(&bytes), 0, SIZE as u64).is_ok());
+        let e = RelHeader::with_size(Cursor::new(&bytes), 0, 0xb8).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,13 +1,87 @@
-use std::cmp::Ordering;
-use crate::NumberStyle;
+use std::{cmp::Ordering, io::{self, Write}};
+use crate::{format_u64, NumberStyle};
+
+// One line of what `print_info` shows, see `Section::fields`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub label: &'static str,
+    pub value: FieldValue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    // Shown in the number style
+    Number(u64),
+    // Shown in the number style, with "bytes" after it
+    Bytes(u64),
+    Text(String),
+    // Each one's fields are shown under the label, after a blank line, like
+    // the DOL's segments
+    Sections(Vec<Vec<Field>>),
+}
+
+impl Field {
+    pub fn number(label: &'static str, n: u64) -> Field {
+        Field { label, value: FieldValue::Number(n) }
+    }
+
+    pub fn bytes(label: &'static str, n: u64) -> Field {
+        Field { label, value: FieldValue::Bytes(n) }
+    }
+
+    pub fn text(label: &'static str, s: impl Into<String>) -> Field {
+        Field { label, value: FieldValue::Text(s.into()) }
+    }
+}
+
+// The value of the field labeled `label`, if there is one.
+pub fn field<'a>(fields: &'a [Field], label: &str) -> Option<&'a FieldValue> {
+    fields.iter().find(|f| f.label == label).map(|f| &f.value)
+}
+
+pub fn print_fields(fields: &[Field], style: NumberStyle) {
+    write_fields(&mut io::stdout().lock(), fields, style).expect("failed printing to stdout");
+}
+
+pub fn write_fields(out: &mut dyn Write, fields: &[Field], style: NumberStyle) -> io::Result<()> {
+    for f in fields {
+        match &f.value {
+            FieldValue::Number(n) => writeln!(out, "{}: {}", f.label, format_u64(*n, style))?,
+            FieldValue::Bytes(n) => writeln!(out, "{}: {} bytes", f.label, format_u64(*n, style))?,
+            FieldValue::Text(s) => writeln!(out, "{}: {}", f.label, s)?,
+            FieldValue::Sections(sections) => {
+                writeln!(out, "{}:", f.label)?;
+                for s in sections {
+                    writeln!(out)?;
+                    write_fields(out, s, style)?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
 
 pub trait Section {
-    fn print_info(&self, style: NumberStyle);
+    // What `print_info` shows, for library users that want it some other
+    // way. Sections with an `info` method build these from it.
+    fn fields(&self) -> Vec<Field>;
+
+    fn print_info(&self, style: NumberStyle) {
+        print_fields(&self.fields(), style);
+    }
+
+    fn name(&self) -> String;
 
     fn start(&self) -> u64;
 
     fn size(&self) -> usize;
 
+    // The header, apploader, DOL and FST, which game files should never be
+    // stored inside of.
+    fn is_system_data(&self) -> bool {
+        false
+    }
+
     fn end(&self) -> u64 {
         self.start() + self.size() as u64 - 1
     }
@@ -22,3 +96,58 @@ pub trait Section {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sections::fst::entry::Entry,
+        synthetic,
+        test_util::{build_image, open_image, synthetic_tree},
+    };
+
+    fn text(s: &str) -> Option<FieldValue> {
+        Some(FieldValue::Text(s.to_owned()))
+    }
+
+    fn value(section: &dyn Section, label: &str) -> Option<FieldValue> {
+        field(&section.fields(), label).cloned()
+    }
+
+    #[test]
+    fn the_synthetic_games_fields() {
+        let game = open_image(&build_image(synthetic_tree().path()));
+        assert_eq!(value(&game.header, "Game ID"), text(&format!("{}{}", synthetic::GAME_CODE, synthetic::MAKER_CODE)));
+        assert_eq!(value(&game.header, "Title"), text(synthetic::TITLE));
+        assert_eq!(value(&game.header, "DOL offset"), Some(FieldValue::Number(0x3000)));
+        assert_eq!(value(&game.apploader, "Date"), text(&game.apploader.date));
+        // Only when the apploader's too big
+        assert_eq!(value(&game.apploader, "Size on the disc (all there's room for before the DOL and FST)"), None);
+        assert_eq!(value(&game.dol, "Entry point"), Some(FieldValue::Number(0x8000_3100)));
+        let segments: Vec<_> = game.dol.iter_segments().map(Section::fields).collect();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(field(&segments[0], "Segment name"), text(".text0").as_ref());
+        assert_eq!(value(&game.dol, "Segments"), Some(FieldValue::Sections(segments)));
+        assert_eq!(value(&game.fst, "Total files"), Some(FieldValue::Number(synthetic::FILES.len() as u64)));
+        let file = game.fst.entry_for_path("/data/levels/2.bin").and_then(Entry::as_file).unwrap();
+        assert_eq!(value(file, "Size"), Some(FieldValue::Number(4096)));
+        assert_eq!(value(file, "Nothing"), None);
+    }
+
+    #[test]
+    fn writing_fields() {
+        let fields = [
+            Field::number("Offset", 0x20),
+            Field::bytes("Size", 10),
+            Field::text("Name", "a"),
+            Field { label: "Parts", value: FieldValue::Sections(vec![vec![Field::number("Part", 1)]]) },
+        ];
+        let written = |style| {
+            let mut out = Vec::new();
+            write_fields(&mut out, &fields, style).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(written(NumberStyle::Decimal), "Offset: 32\nSize: 10 bytes\nName: a\nParts:\n\nPart: 1\n");
+        assert_eq!(written(NumberStyle::Hexadecimal), "Offset: 0x20\nSize: 0xa bytes\nName: a\nParts:\n\nPart: 0x1\n");
+    }
+}
@@ -0,0 +1,291 @@
+// THP is the movie format most games use for their FMVs, motion JPEG frames
+// with optional interleaved audio. Only the header and the component info are
+// parsed here, which is enough to describe a movie without decoding it.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::{format_u64, format_usize, NumberStyle};
+
+pub const THP_MAGIC: &[u8; 4] = b"THP\0";
+pub const THP_HEADER_SIZE: usize = 0x30;
+
+pub const THP_VERSION_1_0: u32 = 0x0001_0000;
+pub const THP_VERSION_1_1: u32 = 0x0001_1000;
+
+// The component types are a fixed array, unused slots are 0xff.
+pub const THP_MAX_COMPONENTS: usize = 16;
+pub const THP_COMPONENT_VIDEO: u8 = 0;
+pub const THP_COMPONENT_AUDIO: u8 = 1;
+pub const THP_COMPONENT_NONE: u8 = 0xff;
+
+// The header and component info are always at the start of the file, this is
+// more than enough to read them.
+pub const THP_PROBE_SIZE: usize = 0x1000;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ThpVideo {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ThpAudio {
+    pub channels: u32,
+    pub frequency: u32,
+    pub samples: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ThpComponent {
+    Video(ThpVideo),
+    Audio(ThpAudio),
+}
+
+#[derive(Debug)]
+pub struct ThpHeader {
+    pub version: u32,
+    pub max_buffer_size: usize,
+    pub max_audio_samples: u32,
+    pub fps: f32,
+    pub frame_count: u32,
+    pub first_frame_size: usize,
+    pub data_size: usize,
+    pub component_data_offset: u64,
+    pub offsets_data_offset: u64,
+    pub first_frame_offset: u64,
+    pub last_frame_offset: u64,
+    pub components: Vec<ThpComponent>,
+}
+
+impl ThpHeader {
+    pub fn new<R>(mut file: R, offset: u64) -> io::Result<ThpHeader>
+    where
+        R: Read + Seek,
+    {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != THP_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a THP file"));
+        }
+
+        let version = file.read_u32::<BigEndian>()?;
+        if version != THP_VERSION_1_0 && version != THP_VERSION_1_1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown THP version {:#x}", version),
+            ));
+        }
+        let max_buffer_size = file.read_u32::<BigEndian>()? as usize;
+        let max_audio_samples = file.read_u32::<BigEndian>()?;
+        let fps = file.read_f32::<BigEndian>()?;
+        let frame_count = file.read_u32::<BigEndian>()?;
+        let first_frame_size = file.read_u32::<BigEndian>()? as usize;
+        let data_size = file.read_u32::<BigEndian>()? as usize;
+        let component_data_offset = file.read_u32::<BigEndian>()? as u64;
+        let offsets_data_offset = file.read_u32::<BigEndian>()? as u64;
+        let first_frame_offset = file.read_u32::<BigEndian>()? as u64;
+        let last_frame_offset = file.read_u32::<BigEndian>()? as u64;
+
+        if !fps.is_finite() || fps <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid THP frame rate {}", fps),
+            ));
+        }
+
+        file.seek(SeekFrom::Start(offset + component_data_offset))?;
+        let component_count = file.read_u32::<BigEndian>()? as usize;
+        if component_count > THP_MAX_COMPONENTS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Too many THP components ({})", component_count),
+            ));
+        }
+        let mut types = [0; THP_MAX_COMPONENTS];
+        file.read_exact(&mut types)?;
+
+        // The info for each component follows the types, in the same order.
+        // Version 1.1 added a field to each of them.
+        let mut components = Vec::with_capacity(component_count);
+        for &kind in &types[..component_count] {
+            match kind {
+                THP_COMPONENT_VIDEO => {
+                    let width = file.read_u32::<BigEndian>()?;
+                    let height = file.read_u32::<BigEndian>()?;
+                    if version == THP_VERSION_1_1 {
+                        file.read_u32::<BigEndian>()?;
+                    }
+                    components.push(ThpComponent::Video(ThpVideo { width, height }));
+                },
+                THP_COMPONENT_AUDIO => {
+                    let channels = file.read_u32::<BigEndian>()?;
+                    let frequency = file.read_u32::<BigEndian>()?;
+                    let samples = file.read_u32::<BigEndian>()?;
+                    if version == THP_VERSION_1_1 {
+                        file.read_u32::<BigEndian>()?;
+                    }
+                    components.push(ThpComponent::Audio(ThpAudio { channels, frequency, samples }));
+                },
+                THP_COMPONENT_NONE => {},
+                _ => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown THP component type {}", kind),
+                )),
+            }
+        }
+
+        Ok(ThpHeader {
+            version,
+            max_buffer_size,
+            max_audio_samples,
+            fps,
+            frame_count,
+            first_frame_size,
+            data_size,
+            component_data_offset,
+            offsets_data_offset,
+            first_frame_offset,
+            last_frame_offset,
+            components,
+        })
+    }
+
+    // In seconds
+    pub fn duration(&self) -> f64 {
+        self.frame_count as f64 / self.fps as f64
+    }
+
+    pub fn video(&self) -> Option<ThpVideo> {
+        self.components.iter().find_map(|c| match c {
+            ThpComponent::Video(v) => Some(*v),
+            _ => None,
+        })
+    }
+
+    pub fn audio(&self) -> Option<ThpAudio> {
+        self.components.iter().find_map(|c| match c {
+            ThpComponent::Audio(a) => Some(*a),
+            _ => None,
+        })
+    }
+
+    // Like "640x480, 29.97 fps, 12.5s, audio", for listings.
+    pub fn summary(&self) -> String {
+        let mut summary = match self.video() {
+            Some(v) => format!("{}x{}, ", v.width, v.height),
+            None => String::new(),
+        };
+        summary += &format!("{:.2} fps, {:.1}s", self.fps, self.duration());
+        if self.audio().is_some() {
+            summary += ", audio";
+        }
+        summary
+    }
+
+    pub fn write_info(&self, mut out: impl Write, style: NumberStyle) -> io::Result<()> {
+        writeln!(out, "THP version: {}.{}", self.version >> 16, (self.version >> 12) & 0xf)?;
+        writeln!(out, "Frames: {}", self.frame_count)?;
+        writeln!(out, "Frame rate: {:.2} fps", self.fps)?;
+        writeln!(out, "Duration: {:.2}s", self.duration())?;
+        match self.video() {
+            Some(v) => writeln!(out, "Resolution: {}x{}", v.width, v.height)?,
+            None => writeln!(out, "Resolution: none")?,
+        }
+        match self.audio() {
+            Some(a) => writeln!(out, "Audio: {} channels, {} Hz", a.channels, a.frequency)?,
+            None => writeln!(out, "Audio: none")?,
+        }
+        writeln!(out, "Max buffer size: {} bytes", format_usize(self.max_buffer_size, style))?;
+        writeln!(out, "First frame offset: {}", format_u64(self.first_frame_offset, style))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // A THP with a 640x480 video component and, with `audio`, a stereo
+    // 32 kHz one, 300 frames at 29.97 fps
+    pub fn thp(version: u32, audio: bool) -> Vec<u8> {
+        let mut bytes = THP_MAGIC.to_vec();
+        let words: [u32; 11] = [version, 0x2_0000, 1600, 29.97f32.to_bits(), 300, 0x1000, 0x10_0000, 0x30, 0, 0x100, 0xf_f000];
+        for w in words {
+            bytes.extend_from_slice(&w.to_be_bytes());
+        }
+        bytes.extend_from_slice(&(if audio { 2u32 } else { 1 }).to_be_bytes());
+        let mut types = [THP_COMPONENT_NONE; THP_MAX_COMPONENTS];
+        types[0] = THP_COMPONENT_VIDEO;
+        if audio {
+            types[1] = THP_COMPONENT_AUDIO;
+        }
+        bytes.extend_from_slice(&types);
+        let extra: &[u32] = if version == THP_VERSION_1_1 { &[0] } else { &[] };
+        for w in [&[640, 480][..], extra].concat() {
+            bytes.extend_from_slice(&w.to_be_bytes());
+        }
+        if audio {
+            for w in [&[2, 32000, 1600][..], extra].concat() {
+                bytes.extend_from_slice(&w.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> io::Result<ThpHeader> {
+        ThpHeader::new(Cursor::new(bytes), 0)
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        let e = parse(bytes).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e);
+        e.to_string()
+    }
+
+    #[test]
+    fn both_versions_parse() {
+        for version in [THP_VERSION_1_0, THP_VERSION_1_1] {
+            let thp = parse(&thp(version, true)).unwrap();
+            assert_eq!((thp.version, thp.frame_count, thp.first_frame_offset), (version, 300, 0x100));
+            let video = thp.video().unwrap();
+            assert_eq!((video.width, video.height), (640, 480));
+            let audio = thp.audio().unwrap();
+            assert_eq!((audio.channels, audio.frequency, audio.samples), (2, 32000, 1600));
+            assert!((thp.duration() - 10.01).abs() < 0.01, "{}", thp.duration());
+            assert_eq!(thp.summary(), "640x480, 29.97 fps, 10.0s, audio");
+        }
+    }
+
+    #[test]
+    fn without_audio() {
+        let thp = parse(&thp(THP_VERSION_1_0, false)).unwrap();
+        assert!(thp.audio().is_none());
+        assert_eq!(thp.summary(), "640x480, 29.97 fps, 10.0s");
+        let mut info = Vec::new();
+        thp.write_info(&mut info, NumberStyle::Decimal).unwrap();
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("THP version: 1.0\n") && info.contains("Audio: none\n"), "{}", info);
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let good = thp(THP_VERSION_1_1, true);
+        let with = |offset: usize, value: u32| {
+            let mut bytes = good.clone();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+            bytes
+        };
+        assert_eq!(error(&with(0, 0x54485001)), "Not a THP file");
+        assert!(error(&with(4, 0x0002_0000)).contains("Unknown THP version"));
+        assert!(error(&with(0x10, 0f32.to_bits())).contains("Invalid THP frame rate"));
+        assert!(error(&with(0x10, f32::NAN.to_bits())).contains("Invalid THP frame rate"));
+        assert!(error(&with(0x30, 17)).contains("Too many THP components"));
+        assert!(error(&with(0x34, 0x0200_0000)).contains("Unknown THP component type 2"));
+        assert_eq!(parse(&good[..good.len() - 1]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
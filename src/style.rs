@@ -0,0 +1,240 @@
+// Colors for the listings printed to a terminal. Nothing here changes what
+// gets printed, just how it looks.
+
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
+use crate::sections::fst::entry::Entry;
+
+// Files at least this big get highlighted in listings, 16MiB.
+pub const DEFAULT_LARGE_FILE_SIZE: u64 = 0x100_0000;
+
+const RESET: &str = "\x1b[0m";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(s: &str) -> Option<ColorChoice> {
+        match s {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // `auto` only colors terminals, and never when NO_COLOR is set to
+    // anything (see https://no-color.org).
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && io::stdout().is_terminal(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Directory,
+    File,
+    LargeFile,
+    // The header, apploader, DOL and FST, and files stored inside them
+    System,
+    // The files, as a whole
+    UserArea,
+    Gap,
+    Warning,
+}
+
+impl Kind {
+    fn default_code(self) -> &'static str {
+        match self {
+            Kind::Directory => "01;34",
+            Kind::File => "0",
+            Kind::LargeFile => "01;33",
+            Kind::System => "01;35",
+            Kind::UserArea => "32",
+            Kind::Gap => "02",
+            Kind::Warning => "01;31",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Colors {
+    enabled: bool,
+    large_file_size: u64,
+    directory: Option<String>,
+    file: Option<String>,
+    // Lowercase, without the "*."
+    extensions: Vec<(String, String)>,
+}
+
+impl Colors {
+    // Prints everything as-is.
+    pub fn plain() -> Colors {
+        Colors {
+            enabled: false,
+            large_file_size: DEFAULT_LARGE_FILE_SIZE,
+            directory: None,
+            file: None,
+            extensions: Vec::new(),
+        }
+    }
+
+    // Uses the colors from LS_COLORS where it has them.
+    pub fn new(choice: ColorChoice, large_file_size: u64) -> Colors {
+        let mut colors = Colors {
+            enabled: choice.enabled(),
+            large_file_size,
+            ..Colors::plain()
+        };
+        if let Some(ls_colors) = env::var_os("LS_COLORS") {
+            colors.read_ls_colors(&ls_colors.to_string_lossy());
+        }
+        colors
+    }
+
+    // Only `di`, `fi` and `*.ext` are used, the rest is ignored. So are
+    // codes that aren't plain SGR parameters, so nothing but colors can end
+    // up in the output.
+    fn read_ls_colors(&mut self, ls_colors: &str) {
+        for (key, code) in ls_colors.split(':').filter_map(|e| e.split_once('=')) {
+            if code.is_empty() || !code.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                continue
+            }
+            match key {
+                "di" => self.directory = Some(code.to_owned()),
+                "fi" => self.file = Some(code.to_owned()),
+                _ => if let Some(ext) = key.strip_prefix("*.") {
+                    self.extensions.push((ext.to_lowercase(), code.to_owned()));
+                },
+            }
+        }
+    }
+
+    pub fn paint(&self, kind: Kind, text: &str) -> String {
+        self.paint_code(kind.default_code(), text)
+    }
+
+    fn paint_code(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}{}", code, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    // `system` is whether the file is stored inside of the system data. That
+    // and being large take priority over the user's colors, since they're
+    // worth noticing.
+    pub fn paint_entry(&self, entry: &Entry, system: bool, text: &str) -> String {
+        let file = match entry {
+            Entry::Directory(_) => {
+                let code = self.directory.as_deref().unwrap_or(Kind::Directory.default_code());
+                return self.paint_code(code, text);
+            },
+            Entry::File(f) => f,
+        };
+        if system {
+            return self.paint(Kind::System, text);
+        }
+        if file.size as u64 >= self.large_file_size {
+            return self.paint(Kind::LargeFile, text);
+        }
+
+        let name = file.info.name.to_lowercase();
+        let by_extension = self.extensions.iter()
+            .find(|(ext, _)| name.ends_with(&format!(".{}", ext)))
+            .map(|(_, code)| code.as_str());
+        let code = by_extension
+            .or(self.file.as_deref())
+            .unwrap_or(Kind::File.default_code());
+        self.paint_code(code, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::sections::fst::entry::{ChildNames, DirectoryEntry, EntryInfo, FileEntry};
+
+    fn info(name: &str) -> EntryInfo {
+        EntryInfo {
+            index: 1,
+            name: name.to_owned(),
+            filename_offset: 0,
+            directory_index: Some(0),
+            full_path: PathBuf::from("/").join(name),
+        }
+    }
+
+    fn file(name: &str, size: usize) -> Entry {
+        Entry::File(FileEntry { info: info(name), file_offset: 0x8000, size })
+    }
+
+    fn directory() -> Entry {
+        Entry::Directory(DirectoryEntry { info: info("dir"), parent_index: 0, next_index: 2, file_count: 0, children: ChildNames::default() })
+    }
+
+    fn colors(enabled: bool, ls_colors: &str) -> Colors {
+        let mut colors = Colors { enabled, large_file_size: 100, ..Colors::plain() };
+        colors.read_ls_colors(ls_colors);
+        colors
+    }
+
+    #[test]
+    fn never_is_plain() {
+        let c = colors(false, "di=34:fi=33:*.txt=36");
+        assert_eq!(c.paint(Kind::Warning, "text"), "text");
+        assert_eq!(c.paint_entry(&file("a.txt", 1), false, "a.txt"), "a.txt");
+        assert_eq!(c.paint_entry(&file("a.bin", 1000), true, "a.bin"), "a.bin");
+    }
+
+    #[test]
+    fn always_uses_ls_colors() {
+        let c = colors(true, "di=34:fi=33:*.TXT=36:ln=01;36:no-equals:*.bin=");
+        let directory = directory();
+        assert_eq!(c.paint_entry(&directory, false, "dir/"), "\x1b[34mdir/\x1b[0m");
+        assert_eq!(c.paint_entry(&file("A.txt", 1), false, "A.txt"), "\x1b[36mA.txt\x1b[0m");
+        assert_eq!(c.paint_entry(&file("a.bin", 1), false, "a.bin"), "\x1b[33ma.bin\x1b[0m");
+        // Before the user's colors
+        assert_eq!(c.paint_entry(&file("a.txt", 100), false, "a.txt"), "\x1b[01;33ma.txt\x1b[0m");
+        assert_eq!(c.paint_entry(&file("a.txt", 100), true, "a.txt"), "\x1b[01;35ma.txt\x1b[0m");
+    }
+
+    #[test]
+    fn always_without_ls_colors() {
+        let c = colors(true, "");
+        let directory = directory();
+        assert_eq!(c.paint_entry(&directory, false, "dir/"), "\x1b[01;34mdir/\x1b[0m");
+        assert_eq!(c.paint_entry(&file("a.txt", 1), false, "a.txt"), "\x1b[0ma.txt\x1b[0m");
+        assert_eq!(c.paint(Kind::Gap, "gap"), "\x1b[02mgap\x1b[0m");
+    }
+
+    // Anything but SGR parameters could move the cursor, or worse
+    #[test]
+    fn only_colors_are_taken_from_ls_colors() {
+        let c = colors(true, "di=34\x1b[2J:fi=5;4m:*.txt=1;");
+        assert_eq!((c.directory, c.file), (None, None));
+        assert_eq!(c.extensions, [("txt".to_owned(), "1;".to_owned())]);
+    }
+
+    #[test]
+    fn color_choices() {
+        assert_eq!(ColorChoice::parse("always"), Some(ColorChoice::Always));
+        assert_eq!(ColorChoice::parse("Always"), None);
+        assert!(ColorChoice::Always.enabled() && !ColorChoice::Never.enabled());
+    }
+}
@@ -0,0 +1,96 @@
+// A small made-up game for `self-test`, written as a tree the way a homebrew
+// project would be, so it can be rebuilt into a ROM. It has a bit of
+// everything the file system can have: nested directories, an empty file
+// and an empty directory, and a file bigger than a write chunk.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::{
+    paths::*,
+    report::Reporter,
+    sections::{apploader::Apploader, fst::FST, header::Header},
+    RebuildOptions,
+    ROMRebuilder,
+    WRITE_CHUNK_SIZE,
+};
+
+pub const GAME_CODE: &str = "GSLF";
+pub const MAKER_CODE: &str = "01";
+pub const TITLE: &str = "gcmod self-test";
+
+// Big enough for everything with the default alignment, and small enough to
+// write quickly.
+pub const CAPACITY: u64 = 4 * 1024 * 1024;
+
+const DOL_HEADER_SIZE: usize = 0x100;
+const DOL_LOAD_ADDR: u32 = 0x8000_3100;
+
+// (path, size). Each file's bytes are from `contents`.
+pub const FILES: &[(&str, usize)] = &[
+    ("readme.txt", 64),
+    ("empty.bin", 0),
+    ("data/levels/1.bin", 1000),
+    ("data/levels/2.bin", 4096),
+    ("data/big.bin", WRITE_CHUNK_SIZE + 12345),
+];
+
+pub const EMPTY_DIRS: &[&str] = &["empty"];
+
+// Writes the tree into `dir`, which has to be empty or not exist yet.
+pub fn write_tree(dir: impl AsRef<Path>) -> io::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir.join(HEADER_PATH).parent().unwrap())?;
+
+    let header = Header::default_with(GAME_CODE, MAKER_CODE, TITLE)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    header.write(File::create(dir.join(HEADER_PATH))?)?;
+    Apploader::write_placeholder(File::create(dir.join(APPLOADER_PATH))?)?;
+    write_dol(File::create(dir.join(DOL_PATH))?)?;
+    // Rebuilding replaces this
+    FST::empty().write(File::create(dir.join(FST_PATH))?)?;
+
+    for (path, size) in FILES {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, contents(*size))?;
+    }
+    for path in EMPTY_DIRS {
+        fs::create_dir_all(dir.join(path))?;
+    }
+    Ok(())
+}
+
+// Writes the tree into `tree`, like `write_tree`, and rebuilds it into a
+// ROM of `CAPACITY` bytes at `image`.
+pub fn write_image(tree: impl AsRef<Path>, image: impl AsRef<Path>) -> io::Result<()> {
+    write_tree(&tree)?;
+    let options = RebuildOptions { capacity: CAPACITY, ..RebuildOptions::default() };
+    ROMRebuilder::rebuild(tree, &options, File::create(image)?, &Reporter::quiet())
+}
+
+// Something other than zeros, so a file copied to the wrong place or cut
+// short shows up.
+pub fn contents(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+// A DOL with one text segment that's just a branch to itself.
+fn write_dol(mut file: impl Write) -> io::Result<()> {
+    let code: [u32; 8] = [0x4800_0000, 0, 0, 0, 0, 0, 0, 0];
+    let mut header = [0; DOL_HEADER_SIZE];
+    (&mut header[0x00..]).write_u32::<BigEndian>(DOL_HEADER_SIZE as u32)?;
+    (&mut header[0x48..]).write_u32::<BigEndian>(DOL_LOAD_ADDR)?;
+    (&mut header[0x90..]).write_u32::<BigEndian>((code.len() * 4) as u32)?;
+    (&mut header[0xe0..]).write_u32::<BigEndian>(DOL_LOAD_ADDR)?;
+    file.write_all(&header)?;
+    for word in code {
+        file.write_u32::<BigEndian>(word)?;
+    }
+    Ok(())
+}
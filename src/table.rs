@@ -0,0 +1,143 @@
+// The file system as a table, one row per entry, for loading into
+// spreadsheets. Fields are quoted like RFC 4180 says, since names can have
+// commas, quotes, and even newlines in them.
+
+use std::io::{self, Write};
+
+use crate::sections::fst::entry::{DirectoryEntry, Entry};
+
+pub const COLUMNS: &[&str] = &[
+    "index",
+    "path",
+    "directory",
+    "offset",
+    "offset_hex",
+    "size",
+    "parent_index",
+    "extension",
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TableFormat {
+    Csv,
+    Tsv,
+}
+
+impl TableFormat {
+    pub fn parse(s: &str) -> Option<TableFormat> {
+        match s {
+            "csv" => Some(TableFormat::Csv),
+            "tsv" => Some(TableFormat::Tsv),
+            _ => None,
+        }
+    }
+
+    fn delimiter(self) -> char {
+        match self {
+            TableFormat::Csv => ',',
+            TableFormat::Tsv => '\t',
+        }
+    }
+}
+
+// Directories leave the offset, size, and extension empty, and so does the
+// root for its parent.
+pub fn entry_row(entry: &Entry) -> Vec<String> {
+    let info = entry.info();
+    let (offset, offset_hex, size, extension) = match entry {
+        Entry::File(f) => (
+            f.file_offset.to_string(),
+            format!("{:#x}", f.file_offset),
+            f.size.to_string(),
+            info.name.rsplit_once('.').map_or("", |(_, ext)| ext).to_owned(),
+        ),
+        Entry::Directory(_) => Default::default(),
+    };
+    vec![
+        info.index.to_string(),
+        info.normalized_path(),
+        entry.is_dir().to_string(),
+        offset,
+        offset_hex,
+        size,
+        info.directory_index.map_or_else(String::new, |i| i.to_string()),
+        extension,
+    ]
+}
+
+// Quotes `field` if it has the delimiter, a quote, or a line break in it,
+// doubling any quotes.
+pub fn quote_field(field: &str, format: TableFormat) -> String {
+    let needs_quotes = field.contains(|c| c == format.delimiter() || c == '"' || c == '\n' || c == '\r');
+    if needs_quotes {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn write_row<S: AsRef<str>>(mut writer: impl Write, row: &[S], format: TableFormat) -> io::Result<()> {
+    let delimiter = format.delimiter().to_string();
+    let fields: Vec<_> = row.iter().map(|f| quote_field(f.as_ref(), format)).collect();
+    // CRLF, like RFC 4180 has it
+    write!(writer, "{}\r\n", fields.join(&delimiter))
+}
+
+// Writes a row for everything inside of `dir`, all the way down.
+pub fn write_table(
+    mut writer: impl Write,
+    dir: &DirectoryEntry,
+    entries: &[Entry],
+    format: TableFormat,
+    header: bool,
+) -> io::Result<()> {
+    if header {
+        write_row(&mut writer, COLUMNS, format)?;
+    }
+    for entry in dir.iter_descendants(entries) {
+        write_row(&mut writer, &entry_row(entry), format)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::sections::fst::entry::{EntryInfo, FileEntry};
+
+    #[test]
+    fn quoting() {
+        let csv = TableFormat::Csv;
+        assert_eq!(quote_field("plain name.bin", csv), "plain name.bin");
+        assert_eq!(quote_field("a,b", csv), "\"a,b\"");
+        assert_eq!(quote_field("say \"hi\"", csv), "\"say \"\"hi\"\"\"");
+        assert_eq!(quote_field("two\nlines", csv), "\"two\nlines\"");
+        assert_eq!(quote_field("cr\r", csv), "\"cr\r\"");
+        assert_eq!(quote_field("", csv), "");
+        // Each format only quotes its own delimiter
+        assert_eq!(quote_field("a,b", TableFormat::Tsv), "a,b");
+        assert_eq!(quote_field("a\tb", TableFormat::Tsv), "\"a\tb\"");
+        assert_eq!(quote_field("a\tb", csv), "a\tb");
+    }
+
+    #[test]
+    fn rows() {
+        let file = Entry::File(FileEntry {
+            info: EntryInfo {
+                index: 3,
+                name: "a,b.tar.gz".to_owned(),
+                filename_offset: 0,
+                directory_index: Some(1),
+                full_path: PathBuf::from("/dir/a,b.tar.gz"),
+            },
+            file_offset: 0x8000,
+            size: 12,
+        });
+        assert_eq!(entry_row(&file), ["3", "/dir/a,b.tar.gz", "false", "32768", "0x8000", "12", "1", "gz"]);
+        let mut out = Vec::new();
+        write_row(&mut out, &entry_row(&file), TableFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "3,\"/dir/a,b.tar.gz\",false,32768,0x8000,12,1,gz\r\n");
+    }
+}
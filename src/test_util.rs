@@ -0,0 +1,111 @@
+// For the unit tests. The ones in tests/ have their own copy in
+// tests/common, since they can't see anything that's only built for tests.
+
+use std::{
+    cell::RefCell,
+    env,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{report::{ProgressSink, Reporter}, synthetic, Game, RebuildOptions, ROMRebuilder};
+
+// A directory that's deleted with everything in it when it's dropped.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new() -> TestDir {
+        TestDir::new_in(&env::temp_dir())
+    }
+
+    pub fn new_in(base: &Path) -> TestDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = base.join(format!("gcmod-unit-test-{}-{}", process::id(), n));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// The synthetic game's tree, in a `TestDir`, to change before building it
+pub fn synthetic_tree() -> TestDir {
+    let dir = TestDir::new();
+    synthetic::write_tree(dir.path()).unwrap();
+    dir
+}
+
+// `tree` rebuilt into an image in memory, of `synthetic::CAPACITY` bytes
+pub fn build_image(tree: &Path) -> Vec<u8> {
+    let options = RebuildOptions { capacity: synthetic::CAPACITY, ..RebuildOptions::default() };
+    let mut image = Vec::new();
+    ROMRebuilder::rebuild(tree, &options, &mut image, &Reporter::quiet()).unwrap();
+    image
+}
+
+pub fn open_image(image: &[u8]) -> Game {
+    Game::open(Cursor::new(image), 0).unwrap()
+}
+
+// Stands in for a library user's progress display. It's shared, since the
+// reporter keeps the one it's given.
+#[derive(Clone, Default)]
+pub struct RecordingSink(pub Rc<RefCell<SinkRecord>>);
+
+#[derive(Debug, Default)]
+pub struct SinkRecord {
+    pub totals: Option<(u64, Option<u64>)>,
+    pub started: Vec<(u64, String)>,
+    pub finished: Vec<(u64, String)>,
+    pub bytes: u64,
+}
+
+impl ProgressSink for RecordingSink {
+    fn totals(&self, files: u64, bytes: Option<u64>) {
+        self.0.borrow_mut().totals = Some((files, bytes));
+    }
+
+    fn file_started(&self, index: u64, path: &str) {
+        self.0.borrow_mut().started.push((index, path.to_owned()));
+    }
+
+    fn bytes_written(&self, bytes: u64) {
+        self.0.borrow_mut().bytes += bytes;
+    }
+
+    fn file_finished(&self, index: u64, path: &str) {
+        self.0.borrow_mut().finished.push((index, path.to_owned()));
+    }
+}
+
+impl RecordingSink {
+    // Every one of `files` started and finished in order, numbered from 0,
+    // with `bytes` written all together
+    pub fn check(&self, files: usize, bytes: u64) {
+        let record = self.0.borrow();
+        assert_eq!(record.started, record.finished);
+        assert_eq!(record.started.len(), files);
+        let indices: Vec<u64> = record.started.iter().map(|&(i, _)| i).collect();
+        assert_eq!(indices, (0..files as u64).collect::<Vec<_>>());
+        assert_eq!(record.bytes, bytes);
+        assert_eq!(record.totals.map(|(n, _)| n), Some(files as u64));
+    }
+}
@@ -0,0 +1,213 @@
+// Dates and times as seconds since the Unix epoch, in UTC, and the made-up
+// modification times for `ls --long --fake-mtime`. The FST has no times at
+// all, so every entry gets the same one.
+
+use std::{error::Error, fmt};
+
+use crate::Game;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FakeMtime {
+    // Midnight UTC on the apploader's date, see `Apploader::build_date`
+    Apploader,
+    Epoch,
+    At(i64),
+}
+
+#[derive(Debug)]
+pub enum TimestampError {
+    // Not "apploader", "epoch", or an RFC 3339 time
+    Invalid(String),
+    // The apploader's date, like a placeholder's 0000/00/00
+    NoApploaderDate(String),
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimestampError::Invalid(s) => write!(
+                f,
+                "{:?} isn't \"apploader\", \"epoch\", or a time like 2002-07-19T12:00:00Z",
+                s,
+            ),
+            TimestampError::NoApploaderDate(date) =>
+                write!(f, "The apploader's date {:?} isn't a real date, so there's no time to use", date),
+        }
+    }
+}
+
+impl Error for TimestampError {}
+
+impl FakeMtime {
+    pub fn parse(s: &str) -> Result<FakeMtime, TimestampError> {
+        match s {
+            "apploader" => Ok(FakeMtime::Apploader),
+            "epoch" => Ok(FakeMtime::Epoch),
+            _ => parse_rfc3339(s).map(FakeMtime::At).ok_or_else(|| TimestampError::Invalid(s.to_owned())),
+        }
+    }
+
+    // In seconds since the Unix epoch.
+    pub fn for_game(self, game: &Game) -> Result<i64, TimestampError> {
+        match self {
+            FakeMtime::Apploader => game.apploader.build_date()
+                .and_then(|d| parse_rfc3339(&format!("{}T00:00:00Z", d)))
+                .ok_or_else(|| TimestampError::NoApploaderDate(game.apploader.date.clone())),
+            FakeMtime::Epoch => Ok(0),
+            FakeMtime::At(t) => Ok(t),
+        }
+    }
+}
+
+// Like "2024-05-01T12:00:00Z"
+pub fn utc_date(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+    let secs = timestamp.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs / 3600, secs / 60 % 60, secs % 60,
+    )
+}
+
+// Like "2024-05-01 12:00", the way `ls -l --time-style=long-iso` has it
+pub fn utc_minute(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+    let secs = timestamp.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, secs / 3600, secs / 60 % 60)
+}
+
+// Like "2024-05-01T12:00:00Z" or "2024-05-01T14:00:00.5+02:00". Fractions
+// of a second are dropped.
+pub fn parse_rfc3339(s: &str) -> Option<i64> {
+    let number = |s: &str, len: usize| {
+        (s.len() == len && s.bytes().all(|b| b.is_ascii_digit())).then(|| s.parse::<i64>().ok()).flatten()
+    };
+    let (date, time) = s.split_once(['T', 't', ' '])?;
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 4)?;
+    let month = number(parts.next()?, 2)?;
+    let day = number(parts.next()?, 2)?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let at = time.rfind(['+', '-'])?;
+        let (time, zone) = time.split_at(at);
+        let (hours, minutes) = zone[1..].split_once(':')?;
+        let (hours, minutes) = (number(hours, 2)?, number(minutes, 2)?);
+        if hours > 23 || minutes > 59 {
+            return None;
+        }
+        let offset = hours * 3600 + minutes * 60;
+        (time, if zone.starts_with('-') { -offset } else { offset })
+    };
+    let time = time.split_once('.').map_or(Some(time), |(t, frac)| {
+        (!frac.is_empty() && frac.bytes().all(|b| b.is_ascii_digit())).then_some(t)
+    })?;
+    let mut parts = time.split(':');
+    let hour = number(parts.next()?, 2)?;
+    let minute = number(parts.next()?, 2)?;
+    // 60 for leap seconds
+    let second = number(parts.next()?, 2)?;
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Howard Hinnant's days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// The same, backwards
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sections::apploader::APPLOADER_OFFSET, test_util::{build_image, open_image, synthetic_tree}};
+
+    #[test]
+    fn parsing_times() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339("2002-07-19T00:00:00Z"), Some(1027036800));
+        assert_eq!(parse_rfc3339("2002-07-19t00:00:00z"), Some(1027036800));
+        assert_eq!(parse_rfc3339("2002-07-19 02:00:00.25+02:00"), Some(1027036800));
+        assert_eq!(parse_rfc3339("2002-07-18T23:30:00-00:30"), Some(1027036800));
+        assert_eq!(parse_rfc3339("2024-02-29T12:00:00Z"), Some(1709208000));
+        assert_eq!(parse_rfc3339("1969-12-31T23:00:00Z"), Some(-3600));
+        assert_eq!(parse_rfc3339("2016-12-31T23:59:60Z"), Some(1483228800));
+    }
+
+    #[test]
+    fn times_that_dont_parse() {
+        for s in [
+            "", "2002-07-19", "2002-07-19T00:00Z", "2002-7-19T00:00:00Z", "2002-07-19T00:00:00",
+            "2023-02-29T00:00:00Z", "2002-13-01T00:00:00Z", "2002-00-10T00:00:00Z", "2002-04-31T00:00:00Z",
+            "2002-07-19T24:00:00Z", "2002-07-19T00:60:00Z", "2002-07-19T00:00:61Z", "2002-07-19T00:00:00.Z",
+            "2002-07-19T00:00:00+24:00", "2002-07-19T00:00:00+0200", "+002-07-19T00:00:00Z",
+        ] {
+            assert_eq!(parse_rfc3339(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn formatting_times() {
+        assert_eq!(utc_date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_date(1709208000 + 59), "2024-02-29T12:00:59Z");
+        assert_eq!(utc_date(-3600), "1969-12-31T23:00:00Z");
+        assert_eq!(utc_minute(1027036800 + 3661), "2002-07-19 01:01");
+        // Every day of a leap year and the years around it
+        for day in -400..800 {
+            let t = 951868800 + day * 86400 + 45296;
+            assert_eq!(parse_rfc3339(&utc_date(t)), Some(t), "{}", utc_date(t));
+        }
+    }
+
+    #[test]
+    fn fake_mtimes() {
+        assert_eq!(FakeMtime::parse("apploader").unwrap(), FakeMtime::Apploader);
+        assert_eq!(FakeMtime::parse("epoch").unwrap(), FakeMtime::Epoch);
+        assert_eq!(FakeMtime::parse("2002-07-19T00:00:00Z").unwrap(), FakeMtime::At(1027036800));
+        assert!(matches!(FakeMtime::parse("Epoch"), Err(TimestampError::Invalid(_))));
+
+        let mut image = build_image(synthetic_tree().path());
+        let game = open_image(&image);
+        assert_eq!(FakeMtime::Epoch.for_game(&game).unwrap(), 0);
+        assert_eq!(FakeMtime::At(5).for_game(&game).unwrap(), 5);
+        assert!(matches!(FakeMtime::Apploader.for_game(&game), Err(TimestampError::NoApploaderDate(_))));
+        let offset = APPLOADER_OFFSET as usize;
+        image[offset..offset + 10].copy_from_slice(b"2002/07/19");
+        assert_eq!(FakeMtime::Apploader.for_game(&open_image(&image)).unwrap(), 1027036800);
+    }
+}
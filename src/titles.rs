@@ -0,0 +1,476 @@
+// Every title a game has: the header's, and the short and long ones in each
+// of the banner's language blocks, which don't always agree. Japanese games
+// often have a romanized header title and the real one only in the banner,
+// in Shift-JIS.
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Seek, Write},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sections::{
+        banner::{Banner, BannerVersion, BANNER_PATH},
+        fst::{probe::ProbeHit, FST},
+        header::{Header, COUNTRY_CODE_JAPAN, COUNTRY_CODE_KOREA},
+    },
+    Game,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Dutch,
+    Japanese,
+    Korean,
+}
+
+// The languages of a BNR2 banner's blocks, in order, like `banner::LANGUAGES`.
+pub const BNR2_LANGUAGES: [Language; 6] = [
+    Language::English,
+    Language::German,
+    Language::French,
+    Language::Spanish,
+    Language::Italian,
+    Language::Dutch,
+];
+
+impl Language {
+    // A code like "ja", or a name like "japanese".
+    pub fn parse(s: &str) -> Option<Language> {
+        let s = s.to_ascii_lowercase();
+        [
+            ("en", Language::English),
+            ("de", Language::German),
+            ("fr", Language::French),
+            ("es", Language::Spanish),
+            ("it", Language::Italian),
+            ("nl", Language::Dutch),
+            ("ja", Language::Japanese),
+            ("ko", Language::Korean),
+        ]
+            .into_iter()
+            .find(|&(code, l)| s == code || s == l.name().to_ascii_lowercase())
+            .map(|(_, l)| l)
+    }
+
+    // What a game from a region is in, and what a BNR1 banner's one block is.
+    pub fn for_region(country_code: u32) -> Language {
+        match country_code {
+            COUNTRY_CODE_JAPAN => Language::Japanese,
+            COUNTRY_CODE_KOREA => Language::Korean,
+            _ => Language::English,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "German",
+            Language::French => "French",
+            Language::Spanish => "Spanish",
+            Language::Italian => "Italian",
+            Language::Dutch => "Dutch",
+            Language::Japanese => "Japanese",
+            Language::Korean => "Korean",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum TextEncoding {
+    Ascii,
+    Utf8,
+    Latin1,
+    ShiftJis,
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TextEncoding::Ascii => "ASCII",
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Latin1 => "Latin-1",
+            TextEncoding::ShiftJis => "Shift-JIS",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Title {
+    pub text: String,
+    pub encoding: TextEncoding,
+    // Some characters couldn't be decoded, and are U+FFFD instead. Only kana
+    // and the common symbols are decoded from Shift-JIS, not kanji.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub lossy: bool,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BannerTitles {
+    pub language: Language,
+    pub short_title: Title,
+    pub long_title: Title,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Titles {
+    pub header: Title,
+    // The region's language, which `best` goes by without a preference
+    pub default_language: Language,
+    // None if there's no banner, or it couldn't be read
+    pub banner_version: Option<BannerVersion>,
+    pub banner: Vec<BannerTitles>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub banner_error: Option<String>,
+}
+
+impl Titles {
+    // `banner` is what `Game::read_banner` found, if anything.
+    pub fn new(header: &Header, banner: Option<&io::Result<Banner>>) -> Titles {
+        let country_code = header.information.country_code;
+        let header_title = Title {
+            text: header.title.clone(),
+            encoding: if header.title.is_ascii() { TextEncoding::Ascii } else { TextEncoding::Utf8 },
+            lossy: false,
+        };
+        let mut titles = Titles {
+            header: header_title,
+            default_language: Language::for_region(country_code),
+            banner_version: None,
+            banner: Vec::new(),
+            banner_error: None,
+        };
+        match banner {
+            Some(Ok(banner)) => {
+                titles.banner_version = Some(banner.version);
+                titles.banner = banner.infos.iter().enumerate()
+                    .map(|(i, info)| BannerTitles {
+                        language: match banner.version {
+                            BannerVersion::BNR1 => Language::for_region(country_code),
+                            BannerVersion::BNR2 => BNR2_LANGUAGES[i],
+                        },
+                        short_title: banner_title(&info.short_title, country_code),
+                        long_title: banner_title(&info.long_title, country_code),
+                    })
+                    .collect();
+            },
+            Some(Err(e)) => titles.banner_error = Some(e.to_string()),
+            None => {},
+        }
+        titles
+    }
+
+    // The title to show for `language`, or the region's language without
+    // one. That's the long title of the banner block in the language, or its
+    // short one, or failing that, the same from the region's language's
+    // block, and then the first block. Titles that are blank or couldn't be
+    // fully decoded are skipped, and the header's is the last resort.
+    pub fn best(&self, language: Option<Language>) -> &Title {
+        [language, Some(self.default_language)].into_iter()
+            .flatten()
+            .filter_map(|l| self.banner.iter().find(|b| b.language == l))
+            .chain(self.banner.first())
+            .flat_map(|b| [&b.long_title, &b.short_title])
+            .find(|t| !t.text.trim().is_empty() && !t.lossy)
+            .unwrap_or(&self.header)
+    }
+
+    // Every title's text, without repeats, for searching.
+    pub fn texts(&self) -> Vec<&str> {
+        let mut texts = vec![self.header.text.as_str()];
+        for t in self.banner.iter().flat_map(|b| [&b.short_title, &b.long_title]) {
+            if !t.text.is_empty() && !texts.contains(&t.text.as_str()) {
+                texts.push(&t.text);
+            }
+        }
+        texts
+    }
+
+    pub fn write_info(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "Titles:")?;
+        writeln!(out, "  Header: {} ({})", self.header.text, self.header.encoding)?;
+        match (&self.banner_version, &self.banner_error) {
+            (_, Some(e)) => writeln!(out, "  Banner: couldn't be read ({})", e)?,
+            (None, None) => writeln!(out, "  Banner: none")?,
+            (Some(_), None) => {
+                for b in &self.banner {
+                    for (kind, t) in [("short", &b.short_title), ("long", &b.long_title)] {
+                        if t.text.is_empty() {
+                            writeln!(out, "  {} {}: (blank)", b.language.name(), kind)?;
+                            continue;
+                        }
+                        writeln!(
+                            out,
+                            "  {} {}: {} ({}{})",
+                            b.language.name(), kind, t.text, t.encoding,
+                            if t.lossy { ", not fully decoded" } else { "" },
+                        )?;
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Game {
+    // Reads the banner, if there is one, which nothing else does until it's
+    // asked for.
+    pub fn titles(&self, iso: impl Read + Seek) -> Titles {
+        let banner = self.read_banner(iso).map(|(_, b)| b);
+        Titles::new(&self.header, banner.as_ref())
+    }
+}
+
+// The same without parsing the whole FST, for the image at `offset` in
+// `iso`, for the catalog.
+pub fn read_titles(mut iso: impl BufRead + Seek, offset: u64) -> io::Result<Titles> {
+    let header = Header::new(&mut iso, offset)?;
+    let fst_offset = offset + header.fst_offset;
+    let banner = match FST::probe_path(&mut iso, fst_offset, header.fst_size, BANNER_PATH)? {
+        Some(ProbeHit::File { offset: file_offset, .. }) => Some(Banner::new(&mut iso, offset + file_offset)),
+        _ => None,
+    };
+    Ok(Titles::new(&header, banner.as_ref()))
+}
+
+// Banner text is read as Latin-1, one character per byte, so the bytes are
+// still there to decode it again. Japanese games' banners are Shift-JIS.
+fn banner_title(text: &str, country_code: u32) -> Title {
+    let bytes: Vec<u8> = text.chars().map(|c| c as u32 as u8).collect();
+    if bytes.is_ascii() {
+        return Title { text: text.to_owned(), encoding: TextEncoding::Ascii, lossy: false };
+    }
+    if country_code == COUNTRY_CODE_JAPAN {
+        let (text, lossy) = decode_shift_jis(&bytes);
+        Title { text, encoding: TextEncoding::ShiftJis, lossy }
+    } else {
+        Title { text: text.to_owned(), encoding: TextEncoding::Latin1, lossy: false }
+    }
+}
+
+// Decodes ASCII, half-width katakana, and the full-width letters, digits,
+// kana, and punctuation titles use most. Anything else, like kanji, would
+// need the whole JIS X 0208 table, and becomes U+FFFD.
+fn decode_shift_jis(bytes: &[u8]) -> (String, bool) {
+    let mut text = String::with_capacity(bytes.len());
+    let mut lossy = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let c = match b {
+            0x00..=0x7f => Some(b as u32),
+            0xa1..=0xdf => Some(0xff61 + (b - 0xa1) as u32),
+            0x81..=0x9f | 0xe0..=0xfc if i + 1 < bytes.len() => {
+                i += 1;
+                double_byte(((b as u32) << 8) | bytes[i] as u32)
+            },
+            _ => None,
+        };
+        match c.and_then(char::from_u32) {
+            Some(c) => text.push(c),
+            None => {
+                text.push(char::REPLACEMENT_CHARACTER);
+                lossy = true;
+            },
+        }
+        i += 1;
+    }
+    (text, lossy)
+}
+
+fn double_byte(code: u32) -> Option<u32> {
+    Some(match code {
+        0x824f..=0x8258 => 0xff10 + (code - 0x824f),
+        0x8260..=0x8279 => 0xff21 + (code - 0x8260),
+        0x8281..=0x829a => 0xff41 + (code - 0x8281),
+        0x829f..=0x82f1 => 0x3041 + (code - 0x829f),
+        0x8340..=0x837e => 0x30a1 + (code - 0x8340),
+        // 0x837f isn't used
+        0x8380..=0x8396 => 0x30a1 + (code - 0x8341),
+        _ => {
+            const SYMBOLS: [(u32, u32); 20] = [
+                (0x8140, 0x3000), (0x8141, 0x3001), (0x8142, 0x3002), (0x8143, 0xff0c),
+                (0x8144, 0xff0e), (0x8145, 0x30fb), (0x8146, 0xff1a), (0x8148, 0xff1f),
+                (0x8149, 0xff01), (0x815b, 0x30fc), (0x815e, 0xff0f), (0x8160, 0x301c),
+                (0x8169, 0xff08), (0x816a, 0xff09), (0x8175, 0x300c), (0x8176, 0x300d),
+                (0x817b, 0xff0b), (0x817c, 0xff0d), (0x8195, 0xff06), (0x819a, 0x2605),
+            ];
+            return SYMBOLS.iter().find(|&&(sjis, _)| sjis == code).map(|&(_, c)| c);
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+    use crate::{
+        sections::{
+            banner::{BANNER_INFO_OFFSET, BANNER_INFO_SIZE, LONG_TITLE_SIZE, SHORT_MAKER_SIZE, SHORT_TITLE_SIZE},
+            header::{COUNTRY_CODE_PAL, COUNTRY_CODE_USA},
+        },
+        test_util::{build_image, open_image, synthetic_tree},
+    };
+
+    const COUNTRY_CODE: usize = 0x458;
+
+    // A banner with a block for each short and long title, as raw bytes
+    fn banner(magic: &[u8; 4], blocks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut banner = magic.to_vec();
+        banner.resize(BANNER_INFO_OFFSET as usize, 0xaa);
+        for (short, long) in blocks {
+            let mut block = vec![0; BANNER_INFO_SIZE];
+            block[..short.len()].copy_from_slice(short);
+            let long_offset = SHORT_TITLE_SIZE + SHORT_MAKER_SIZE;
+            block[long_offset..long_offset + long.len()].copy_from_slice(long);
+            block[long_offset + LONG_TITLE_SIZE..][..8].copy_from_slice(b"Nintendo");
+            banner.extend(block);
+        }
+        banner
+    }
+
+    // The synthetic game with `banner` as its opening.bnr, from `country_code`
+    fn image(banner: Option<Vec<u8>>, country_code: u32) -> Vec<u8> {
+        let tree = synthetic_tree();
+        if let Some(banner) = banner {
+            fs::write(tree.path().join("opening.bnr"), banner).unwrap();
+        }
+        let mut image = build_image(tree.path());
+        image[COUNTRY_CODE..COUNTRY_CODE + 4].copy_from_slice(&country_code.to_be_bytes());
+        image
+    }
+
+    fn titles_of(image: &[u8]) -> Titles {
+        let titles = open_image(image).titles(Cursor::new(image));
+        // The catalog's way finds the same ones
+        #[cfg(feature = "serde")]
+        {
+            let probed = read_titles(Cursor::new(image), 0).unwrap();
+            assert_eq!(serde_json::to_value(&probed).unwrap(), serde_json::to_value(&titles).unwrap());
+        }
+        titles
+    }
+
+    fn listing(titles: &Titles) -> String {
+        let mut out = Vec::new();
+        titles.write_info(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    // ゲーム, in Shift-JIS
+    const GAME: &[u8] = &[0x83, 0x51, 0x81, 0x5b, 0x83, 0x80];
+    // 遊, a kanji
+    const KANJI: &[u8] = &[0x97, 0x56];
+
+    #[test]
+    fn languages() {
+        assert_eq!(Language::parse("ja"), Some(Language::Japanese));
+        assert_eq!(Language::parse("German"), Some(Language::German));
+        assert_eq!(Language::parse("NL"), Some(Language::Dutch));
+        assert_eq!(Language::parse("jp"), None);
+        assert_eq!(Language::for_region(COUNTRY_CODE_JAPAN), Language::Japanese);
+        assert_eq!(Language::for_region(COUNTRY_CODE_KOREA), Language::Korean);
+        assert_eq!(Language::for_region(COUNTRY_CODE_PAL), Language::English);
+    }
+
+    #[test]
+    fn shift_jis() {
+        assert_eq!(decode_shift_jis(GAME), ("ゲーム".to_owned(), false));
+        assert_eq!(decode_shift_jis(b"Zelda \x82\xa0\xb1"), ("Zelda あｱ".to_owned(), false));
+        assert_eq!(decode_shift_jis(b"\x82\x60\x82\x81\x82\x4f\x81\x49"), ("Ａａ０！".to_owned(), false));
+        assert_eq!(decode_shift_jis(KANJI), ("\u{fffd}".to_owned(), true));
+        // Cut off partway through a character
+        assert_eq!(decode_shift_jis(b"a\x83"), ("a\u{fffd}".to_owned(), true));
+    }
+
+    #[test]
+    fn japanese_banners() {
+        let titles = titles_of(&image(Some(banner(b"BNR1", &[(b"Game", GAME)])), COUNTRY_CODE_JAPAN));
+        assert_eq!(titles.default_language, Language::Japanese);
+        assert_eq!(titles.banner_version, Some(BannerVersion::BNR1));
+        let block = &titles.banner[0];
+        assert_eq!(block.language, Language::Japanese);
+        assert_eq!((block.short_title.text.as_str(), block.short_title.encoding), ("Game", TextEncoding::Ascii));
+        assert_eq!((block.long_title.text.as_str(), block.long_title.encoding), ("ゲーム", TextEncoding::ShiftJis));
+        assert_eq!(titles.best(None).text, "ゲーム");
+        // There's nothing in English, so it's the same
+        assert_eq!(titles.best(Some(Language::English)).text, "ゲーム");
+        assert!(listing(&titles).contains("  Japanese long: ゲーム (Shift-JIS)\n"), "{}", listing(&titles));
+    }
+
+    #[test]
+    fn titles_that_cant_be_decoded_are_passed_over() {
+        let titles = titles_of(&image(Some(banner(b"BNR1", &[(b"", KANJI)])), COUNTRY_CODE_JAPAN));
+        assert!(titles.banner[0].long_title.lossy);
+        // The short title is blank, so it's the header's
+        assert_eq!(titles.best(None).text, "gcmod self-test");
+        assert!(listing(&titles).contains("  Japanese short: (blank)\n  Japanese long: \u{fffd} (Shift-JIS, not fully decoded)\n"), "{}", listing(&titles));
+        assert_eq!(titles.texts(), ["gcmod self-test", "\u{fffd}"]);
+    }
+
+    // The same bytes outside of Japan are Latin-1
+    #[test]
+    fn other_regions_banners() {
+        let titles = titles_of(&image(Some(banner(b"BNR1", &[(b"Pok\xe9mon", b"Pok\xe9mon")])), COUNTRY_CODE_USA));
+        assert_eq!(titles.banner[0].language, Language::English);
+        assert_eq!(titles.banner[0].long_title.encoding, TextEncoding::Latin1);
+        assert_eq!(titles.best(Some(Language::Japanese)).text, "Pokémon");
+        assert_eq!(titles.texts(), ["gcmod self-test", "Pokémon"]);
+    }
+
+    #[test]
+    fn pal_banners() {
+        let blocks: [(&[u8], &[u8]); 6] = [
+            (b"Game", b"The Game"),
+            (b"Spiel", b"Das Spiel"),
+            (b"Jeu", b"Le Jeu"),
+            (b"", b""),
+            (b"Gioco", b""),
+            (b"Spel", b"Het Spel"),
+        ];
+        let titles = titles_of(&image(Some(banner(b"BNR2", &blocks)), COUNTRY_CODE_PAL));
+        assert_eq!(titles.banner_version, Some(BannerVersion::BNR2));
+        let languages: Vec<Language> = titles.banner.iter().map(|b| b.language).collect();
+        assert_eq!(languages, BNR2_LANGUAGES);
+        assert_eq!(titles.best(None).text, "The Game");
+        assert_eq!(titles.best(Some(Language::German)).text, "Das Spiel");
+        // Blank titles fall back to the short one, then to the region's
+        assert_eq!(titles.best(Some(Language::Italian)).text, "Gioco");
+        assert_eq!(titles.best(Some(Language::Spanish)).text, "The Game");
+        assert!(listing(&titles).contains("  Spanish short: (blank)\n  Spanish long: (blank)\n"), "{}", listing(&titles));
+    }
+
+    #[test]
+    fn without_a_banner() {
+        let titles = titles_of(&image(None, COUNTRY_CODE_JAPAN));
+        assert_eq!(titles.banner_version, None);
+        assert!(titles.banner.is_empty() && titles.banner_error.is_none());
+        assert_eq!(titles.best(Some(Language::Japanese)).text, "gcmod self-test");
+        assert_eq!(listing(&titles), "Titles:\n  Header: gcmod self-test (ASCII)\n  Banner: none\n");
+    }
+
+    #[test]
+    fn banners_that_cant_be_read() {
+        let titles = titles_of(&image(Some(banner(b"BNR3", &[(b"Game", b"Game")])), COUNTRY_CODE_PAL));
+        assert_eq!(titles.banner_error.as_deref(), Some("Invalid banner magic"));
+        assert_eq!(titles.best(None).text, "gcmod self-test");
+        assert!(listing(&titles).contains("  Banner: couldn't be read (Invalid banner magic)\n"), "{}", listing(&titles));
+    }
+}
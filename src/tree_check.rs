@@ -0,0 +1,293 @@
+// Checks for trees that mix parts of different games, like an ISO.hdr from
+// one game with the files of another. Those still rebuild fine, which makes
+// them confusing, so `rebuild` runs these first. Each one is a guess, so
+// only a mismatch with the manifest stops the rebuild.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+#[cfg(feature = "serde")]
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::{
+    nkit,
+    paths::SystemPaths,
+    sections::{banner::{Banner, BANNER_PATH}, header::Header},
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TreeCheck {
+    // The banner's titles against the header's
+    BannerTitle,
+    // Start.dol's size against the room the header's layout had for it
+    DolLayout,
+    // The game ID recorded at extraction against the header's
+    ManifestGameId,
+    // A header with NKit's stamp, see `nkit`
+    Nkit,
+}
+
+#[derive(Debug)]
+pub struct TreeWarning {
+    pub check: TreeCheck,
+    pub message: String,
+}
+
+impl TreeWarning {
+    // Whether the rebuild should stop unless mixing was asked for.
+    pub fn is_error(&self) -> bool {
+        self.check == TreeCheck::ManifestGameId
+    }
+}
+
+impl fmt::Display for TreeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// Runs every check on the tree at `root`. A check that can't be run, like
+// the banner one in a tree without a banner, is skipped.
+pub fn check_tree(root: impl AsRef<Path>) -> io::Result<Vec<TreeWarning>> {
+    let root = root.as_ref();
+    let system = SystemPaths::for_tree(root);
+    let header = Header::new(BufReader::new(File::open(root.join(system.header()))?), 0)?;
+    Ok([
+        check_banner(root, &header),
+        check_dol(root, &system, &header),
+        check_manifest(root, &header),
+        check_nkit(&header),
+    ].into_iter().flatten().collect())
+}
+
+fn check_banner(root: &Path, header: &Header) -> Option<TreeWarning> {
+    let file = File::open(root.join(BANNER_PATH.trim_start_matches('/'))).ok()?;
+    let banner = Banner::new(BufReader::new(file), 0).ok()?;
+    let titles: Vec<&str> = banner.infos.iter()
+        .flat_map(|i| [&i.short_title[..], &i.long_title[..]])
+        .filter(|t| !t.trim().is_empty())
+        .collect();
+    if header.title.trim().is_empty() || titles.is_empty() {
+        return None;
+    }
+    if titles.iter().any(|t| titles_match(&header.title, t)) {
+        return None;
+    }
+    Some(TreeWarning {
+        check: TreeCheck::BannerTitle,
+        message: format!(
+            "The header's title is {:?}, but opening.bnr's is {:?}. They might be from different games.",
+            header.title, titles[0],
+        ),
+    })
+}
+
+// Banner titles are usually longer or punctuated differently, so they only
+// have to share a word.
+fn titles_match(a: &str, b: &str) -> bool {
+    fn words(s: &str) -> HashSet<String> {
+        s.split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| w.chars().count() >= 3 && !w.eq_ignore_ascii_case("the"))
+            .map(str::to_lowercase)
+            .collect()
+    }
+    let (a, b) = (words(a), words(b));
+    // Nothing to compare, like a title in Japanese without any spaces
+    if a.is_empty() || b.is_empty() {
+        return true;
+    }
+    !a.is_disjoint(&b)
+}
+
+// There's nothing in the header about the DOL but its offset. The next
+// thing after it in the header's layout, either the FST or the first file,
+// bounds how big the DOL it was written for could have been.
+fn check_dol(root: &Path, system: &SystemPaths, header: &Header) -> Option<TreeWarning> {
+    let size = root.join(system.dol()).metadata().ok()?.len();
+    let next = [header.fst_offset, header.user_position as u64].into_iter()
+        .filter(|&o| o > header.dol_offset)
+        .min()?;
+    let room = next - header.dol_offset;
+    // Modded DOLs grow, and there's alignment slack after the DOL, so only
+    // a lot bigger or a lot smaller counts
+    let slack = 0x10000;
+    let message = if size > room * 2 + slack {
+        format!(
+            "Start.dol is {} bytes, but the header's layout only had room for {} bytes. The header might be from a different game.",
+            size, room,
+        )
+    } else if size * 4 < room && room - size > slack {
+        format!(
+            "Start.dol is {} bytes, but the header's layout had room for {} bytes. The header might be from a different game.",
+            size, room,
+        )
+    } else {
+        return None;
+    };
+    Some(TreeWarning { check: TreeCheck::DolLayout, message })
+}
+
+fn check_nkit(header: &Header) -> Option<TreeWarning> {
+    let stamp = nkit::find_stamp(header.raw())?;
+    Some(TreeWarning {
+        check: TreeCheck::Nkit,
+        message: format!("The tree's ISO.hdr says: {} Pass --acknowledge-nkit to not be warned.", stamp.notice()),
+    })
+}
+
+#[cfg(feature = "serde")]
+fn check_manifest(root: &Path, header: &Header) -> Option<TreeWarning> {
+    let manifest = Manifest::read(root.join(MANIFEST_NAME)).ok()?;
+    let game_id = format!("{}{}", header.game_code, header.maker_code);
+    (manifest.game_id != game_id).then(|| TreeWarning {
+        check: TreeCheck::ManifestGameId,
+        message: format!(
+            "The tree was extracted from {}, but its ISO.hdr is for {}.",
+            manifest.game_id, game_id,
+        ),
+    })
+}
+
+// The manifest can't be read without serde.
+#[cfg(not(feature = "serde"))]
+fn check_manifest(_root: &Path, _header: &Header) -> Option<TreeWarning> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+
+    use super::*;
+    use crate::{
+        paths::{DOL_PATH, HEADER_PATH},
+        rom_handle::RomWriteHandle,
+        sections::banner::{BannerInfo, BannerVersion, BANNER_INFO_OFFSET, BANNER_INFO_SIZE},
+        test_util::synthetic_tree,
+    };
+    #[cfg(feature = "serde")]
+    use crate::test_util::{build_image, open_image};
+
+    fn checks(root: &Path) -> Vec<TreeCheck> {
+        check_tree(root).unwrap().into_iter().map(|w| w.check).collect()
+    }
+
+    fn edit_header(root: &Path, edit: impl FnOnce(&mut Header)) {
+        let path = root.join(HEADER_PATH);
+        let mut header = Header::new(BufReader::new(File::open(&path).unwrap()), 0).unwrap();
+        edit(&mut header);
+        let mut bytes = Vec::new();
+        header.write_patched(&mut bytes).unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+
+    // Where the synthetic image has everything, like in a tree extracted
+    // from it. A tree that was never built has all zeros.
+    fn extracted_layout(h: &mut Header) {
+        h.set_layout(0x3000, 0x2800, 0xa7, 0xa7);
+        h.set_user_area(0x8000, 0x12_0000);
+    }
+
+    fn write_banner(root: &Path, title: &str) {
+        let mut bytes = vec![0; BANNER_INFO_OFFSET as usize + BANNER_INFO_SIZE];
+        bytes[..4].copy_from_slice(b"BNR1");
+        let info = BannerInfo {
+            short_title: title.to_owned(),
+            short_maker: "Maker".to_owned(),
+            long_title: title.to_owned(),
+            long_maker: String::new(),
+            description: String::new(),
+        };
+        let path = root.join(BANNER_PATH.trim_start_matches('/'));
+        fs::write(&path, bytes).unwrap();
+        let mut file = RomWriteHandle::open_for_modification(&path, &OpenOptions::new()).unwrap();
+        Banner { version: BannerVersion::BNR1, infos: vec![info], offset: 0 }.write_text(&mut file, 0).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_clean_tree_has_no_warnings() {
+        let tree = synthetic_tree();
+        write_banner(tree.path(), "GCMOD Self-Test Deluxe");
+        let game = open_image(&build_image(tree.path()));
+        Manifest::new(&game).write(tree.path().join(MANIFEST_NAME)).unwrap();
+        assert_eq!(checks(tree.path()), []);
+    }
+
+    #[test]
+    fn banners_from_another_game() {
+        let tree = synthetic_tree();
+        write_banner(tree.path(), "Super Mario Sunshine");
+        let warnings = check_tree(tree.path()).unwrap();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(warnings[0].check, TreeCheck::BannerTitle);
+        assert!(warnings[0].message.contains("Super Mario Sunshine"), "{}", warnings[0]);
+        assert!(!warnings[0].is_error());
+    }
+
+    #[test]
+    fn titles_share_a_word() {
+        assert!(titles_match("Super Mario Sunshine", "SUPER MARIO SUNSHINE"));
+        assert!(titles_match("The Legend of Zelda: The Wind Waker", "Zelda - Wind Waker"));
+        assert!(!titles_match("Super Mario Sunshine", "Metroid Prime"));
+        // "The" and short words don't count
+        assert!(!titles_match("The Sims", "The Hobbit"));
+        // Nothing to compare
+        assert!(titles_match("ゼルダの伝説", "Zelda"));
+        assert!(titles_match("ゼルダの伝説", "ゼルダ"));
+    }
+
+    #[test]
+    fn dols_the_header_didnt_have_room_for() {
+        let tree = synthetic_tree();
+        edit_header(tree.path(), extracted_layout);
+        assert_eq!(checks(tree.path()), []);
+        fs::write(tree.path().join(DOL_PATH), vec![0; 0x40000]).unwrap();
+        assert_eq!(checks(tree.path()), [TreeCheck::DolLayout]);
+    }
+
+    #[test]
+    fn dols_much_smaller_than_the_room_they_had() {
+        let tree = synthetic_tree();
+        edit_header(tree.path(), |h| {
+            extracted_layout(h);
+            h.set_user_area(0x10_0000, 0x10_0000);
+        });
+        assert_eq!(checks(tree.path()), [TreeCheck::DolLayout]);
+        // A little more is just slack
+        edit_header(tree.path(), |h| h.set_user_area(0x1_0000, 0x10_0000));
+        assert_eq!(checks(tree.path()), []);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn headers_extracted_from_another_game() {
+        let tree = synthetic_tree();
+        let game = open_image(&build_image(tree.path()));
+        Manifest::new(&game).write(tree.path().join(MANIFEST_NAME)).unwrap();
+        edit_header(tree.path(), |h| {
+            h.game_code = "GMSE".to_owned();
+            h.mark_dirty(crate::sections::header::HeaderField::GameCode);
+        });
+        let warnings = check_tree(tree.path()).unwrap();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].is_error());
+        assert!(warnings[0].message.contains("GMSE01"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn nkit_headers() {
+        let tree = synthetic_tree();
+        let path = tree.path().join(HEADER_PATH);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[nkit::NKIT_STAMP_OFFSET..nkit::NKIT_STAMP_OFFSET + 8].copy_from_slice(b"NKITv01\0");
+        fs::write(&path, bytes).unwrap();
+        let warnings = check_tree(tree.path()).unwrap();
+        assert_eq!(warnings.iter().map(|w| w.check).collect::<Vec<_>>(), [TreeCheck::Nkit]);
+        assert!(warnings[0].message.contains("(v01)"), "{}", warnings[0]);
+    }
+}
@@ -0,0 +1,220 @@
+// Where gcmod puts what it only needs while a command runs, like a
+// decompressed ROM that didn't fit in memory, the self-test's images, and
+// crash reports. That's `--work-dir`, or `WORK_DIR_ENV`, or the system's
+// temporary directory, for systems where that's small or can't be used.
+// Everything gcmod makes in it starts with `PREFIX`, so `clean` only ever
+// removes gcmod's own.
+
+use std::{
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+use crate::report;
+
+pub const WORK_DIR_ENV: &str = "GCMOD_WORK_DIR";
+pub const PREFIX: &str = "gcmod-";
+
+// From `--work-dir`, see `set_work_dir`
+static WORK_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Sets the work directory for the rest of the process, over `WORK_DIR_ENV`,
+// creating it if it has to. Only the first call does anything, since
+// something may already be in the old one.
+pub fn set_work_dir(path: impl Into<PathBuf>) -> io::Result<()> {
+    let path = path.into();
+    fs::create_dir_all(&path)?;
+    let _ = WORK_DIR.set(path);
+    Ok(())
+}
+
+pub fn work_dir() -> PathBuf {
+    if let Some(dir) = WORK_DIR.get() {
+        return dir.clone();
+    }
+    match env::var_os(WORK_DIR_ENV) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::temp_dir(),
+    }
+}
+
+// The work directory, created if it isn't there yet.
+pub fn created_work_dir() -> io::Result<PathBuf> {
+    let dir = work_dir();
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// A file in the work directory that's deleted once it's closed, and that
+// never has a name on systems that allow that.
+pub fn temp_file() -> io::Result<File> {
+    tempfile::tempfile_in(created_work_dir()?)
+}
+
+// Where a file that replaces `path` once it's all written goes until then.
+// It's next to `path` instead of in the work directory, so it's on the same
+// file system and can be renamed into place.
+pub fn sibling_temp(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    Some(crate::io_util::parent_dir(path).join(format!(".{}.gcmod-tmp", name.to_string_lossy())))
+}
+
+// A directory of its own in the work directory for one operation. `finish`
+// deletes it with everything in it. If it's dropped without that, like when
+// the operation failed, it's left for looking into, with a warning saying
+// where it is.
+#[derive(Debug)]
+pub struct Workspace {
+    path: PathBuf,
+    operation: String,
+    finished: bool,
+}
+
+impl Workspace {
+    // Named for `operation`, like "gcmod-self-test-1234-0"
+    pub fn new(operation: &str) -> io::Result<Workspace> {
+        Workspace::new_in(&created_work_dir()?, operation)
+    }
+
+    // Like `new`, in `base` instead of the work directory
+    pub fn new_in(base: &Path, operation: &str) -> io::Result<Workspace> {
+        let pid = process::id();
+        for attempt in 0.. {
+            let path = base.join(format!("{}{}-{}-{}", PREFIX, operation, pid, attempt));
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(Workspace { path, operation: operation.to_owned(), finished: false }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // For when the operation worked
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finished = true;
+        fs::remove_dir_all(&self.path)
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if !self.finished {
+            report::warn(format!(
+                "{} didn't finish, so what it was working on was left in {}. `gcmod clean-workdir` removes it.",
+                self.operation, self.path.display(),
+            ));
+        }
+    }
+}
+
+// Something `clean` removed, or would have.
+#[derive(Debug)]
+pub struct Removed {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+// Removes everything gcmod left in `dir` that was last changed at least
+// `older_than` ago, or with `dry_run`, only lists it.
+pub fn clean(dir: &Path, older_than: Duration, dry_run: bool) -> io::Result<Vec<Removed>> {
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with(PREFIX) {
+            continue
+        }
+        let metadata = entry.metadata()?;
+        let age = metadata.modified().ok().and_then(|m| now.duration_since(m).ok()).unwrap_or_default();
+        if age < older_than {
+            continue
+        }
+        let path = entry.path();
+        let bytes = match metadata.is_dir() {
+            true => dir_size(&path)?,
+            false => metadata.len(),
+        };
+        if !dry_run {
+            match metadata.is_dir() {
+                true => fs::remove_dir_all(&path)?,
+                false => fs::remove_file(&path)?,
+            }
+        }
+        removed.push(Removed { path, bytes });
+    }
+    removed.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(removed)
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total += match metadata.is_dir() {
+            true => dir_size(&entry.path())?,
+            false => metadata.len(),
+        };
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn finished_and_unfinished_workspaces() {
+        let base = TestDir::new();
+        let (done, failed) = (Workspace::new_in(base.path(), "done").unwrap(), Workspace::new_in(base.path(), "failed").unwrap());
+        let (done_path, failed_path) = (done.path().to_owned(), failed.path().to_owned());
+        assert!(done_path.file_name().unwrap().to_string_lossy().starts_with("gcmod-done-"));
+        fs::write(done_path.join("artifact"), "done").unwrap();
+        fs::write(failed_path.join("artifact"), "failed").unwrap();
+        done.finish().unwrap();
+        // Like an operation that failed
+        drop(failed);
+        assert!(!done_path.exists());
+        assert!(failed_path.join("artifact").is_file());
+
+        // Another one for the same operation gets a name of its own
+        let again = Workspace::new_in(base.path(), "failed").unwrap();
+        assert_ne!(again.path(), failed_path);
+        again.finish().unwrap();
+    }
+
+    #[test]
+    fn cleaning_only_removes_gcmods_own() {
+        let base = TestDir::new();
+        let failed = Workspace::new_in(base.path(), "failed").unwrap();
+        let failed_path = failed.path().to_owned();
+        fs::write(failed_path.join("artifact"), "failed").unwrap();
+        drop(failed);
+        fs::write(base.join("gcmod-crash.txt"), "crash").unwrap();
+        fs::write(base.join("not-gcmod"), "").unwrap();
+
+        // Nothing's old enough yet
+        assert!(clean(base.path(), Duration::from_secs(60 * 60), false).unwrap().is_empty());
+
+        let listed = clean(base.path(), Duration::ZERO, true).unwrap();
+        let listed: Vec<_> = listed.iter().map(|r| (r.path.clone(), r.bytes)).collect();
+        assert_eq!(listed, [(base.join("gcmod-crash.txt"), 5), (failed_path.clone(), 6)]);
+        assert!(failed_path.exists());
+
+        let removed: Vec<_> = clean(base.path(), Duration::ZERO, false).unwrap().into_iter().map(|r| r.path).collect();
+        assert_eq!(removed, [base.join("gcmod-crash.txt"), failed_path.clone()]);
+        assert!(!failed_path.exists() && !base.join("gcmod-crash.txt").exists());
+        assert!(base.join("not-gcmod").exists());
+    }
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{stderr, stdout, success, Fixture};
+
+fn analyzers(fixture: &Fixture, args: &[&str]) -> String {
+    let mut info = vec!["info", "image.iso", "--analyzers"];
+    info.extend(args);
+    stdout(&success(fixture.run(&info)))
+}
+
+#[test]
+fn listing_the_analyzers() {
+    let fixture = Fixture::new();
+    let list = analyzers(&fixture, &["list"]);
+    let names: Vec<&str> = list.lines().map(|l| l.split_whitespace().next().unwrap()).collect();
+    assert_eq!(names, ["region", "fingerprint", "scrub", "assets"]);
+    let json: serde_json::Value = serde_json::from_str(&analyzers(&fixture, &["list", "--format", "json"])).unwrap();
+    assert_eq!(json[2]["name"], "scrub");
+    assert!(json[2]["description"].as_str().unwrap().contains("zeroed"), "{}", json);
+}
+
+#[test]
+fn reports_in_text_and_json() {
+    let fixture = Fixture::new();
+    let text = analyzers(&fixture, &["scrub, assets"]);
+    assert_eq!(text.matches("\n[").count(), 1, "{}", text);
+    assert!(text.starts_with("[scrub]\nStatus:"), "{}", text);
+    assert!(text.ends_with("\n\n[assets]\nAssets:  none known\n"), "{}", text);
+
+    let json: serde_json::Value = serde_json::from_str(&analyzers(&fixture, &["region,fingerprint", "--format", "json"])).unwrap();
+    let reports = json.as_array().unwrap();
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0]["analyzer"], "region");
+    assert_eq!(reports[1]["analyzer"], "fingerprint");
+    assert_eq!(reports[1]["sections"][0]["fields"][0]["key"], "Quick-id");
+    assert_eq!(reports[1]["warnings"], serde_json::json!([]));
+}
+
+#[test]
+fn analyzers_that_arent_registered() {
+    let fixture = Fixture::new();
+    for names in ["region,debug-flag", ","] {
+        let output = fixture.run(&["info", "image.iso", "--analyzers", names]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("pass --analyzers list to see them"), "{}", stderr(&output));
+        assert!(stdout(&output).is_empty(), "{}", stdout(&output));
+    }
+}
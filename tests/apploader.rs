@@ -0,0 +1,32 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const CODE_SIZE: usize = 0x2454;
+
+// An apploader that says it's far bigger than the room before the FST
+fn oversized(fixture: &Fixture) {
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[CODE_SIZE..CODE_SIZE + 4].copy_from_slice(&0x7fff_ffffu32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+}
+
+#[test]
+fn oversized_apploaders() {
+    let fixture = Fixture::new();
+    oversized(&fixture);
+    let output = success(fixture.run(&["info", "image.iso", "-t", "layout"]));
+    let text = stderr(&output);
+    assert!(
+        text.contains("The apploader's header says it's 0x80000000 bytes, but there are only 0x3c0 bytes before the DOL and FST, so only those are counted as the apploader."),
+        "{}", text,
+    );
+    assert!(stdout(&output).contains("0x00002440-0x00002800: Apploader"), "{}", stdout(&output));
+
+    let output = fixture.run(&["verify", "image.iso"]);
+    assert!(!output.status.success());
+    let text = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(text.contains("The apploader's header says it's 0x80000000 bytes"), "{}", text);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const APPLOADER: &str = "tree/&&systemdata/Apploader.ldr";
+const CODE_SIZE: usize = 0x14;
+const TRAILER_SIZE: usize = 0x18;
+
+// Only the header changes, rebuilding doesn't need the rest
+fn apploader_sizes(fixture: &Fixture, code: u32, trailer: u32) {
+    let mut bytes = fs::read(fixture.path(APPLOADER)).unwrap();
+    bytes[CODE_SIZE..CODE_SIZE + 4].copy_from_slice(&code.to_be_bytes());
+    bytes[TRAILER_SIZE..TRAILER_SIZE + 4].copy_from_slice(&trailer.to_be_bytes());
+    fs::write(fixture.path(APPLOADER), bytes).unwrap();
+}
+
+fn rebuild(fixture: &Fixture, args: &[&str]) -> std::process::Output {
+    let mut rebuild = vec!["rebuild", "tree", "rebuilt.iso"];
+    rebuild.extend(args);
+    fixture.run(&rebuild)
+}
+
+#[test]
+fn apploaders_at_the_limit_rebuild() {
+    let fixture = Fixture::new();
+    apploader_sizes(&fixture, 0xf_0000, 0x1_0000);
+    let output = success(rebuild(&fixture, &[]));
+    assert!(stderr(&output).contains("close to the most the IPL can boot"), "{}", stderr(&output));
+}
+
+#[test]
+fn apploaders_under_the_warning_rebuild_quietly() {
+    let fixture = Fixture::new();
+    apploader_sizes(&fixture, 0xc_0000, 0);
+    let output = success(rebuild(&fixture, &[]));
+    assert!(!stderr(&output).contains("IPL"), "{}", stderr(&output));
+}
+
+#[test]
+fn apploaders_over_the_limit_need_ignore_boot_limits() {
+    let fixture = Fixture::new();
+    apploader_sizes(&fixture, 0xf_0000, 0x1_0001);
+    let output = rebuild(&fixture, &[]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("it can be at most 0x100000 bytes"), "{}", stderr(&output));
+    assert!(!fixture.path("rebuilt.iso").exists());
+
+    let output = success(rebuild(&fixture, &["--ignore-boot-limits"]));
+    assert!(stderr(&output).contains("it can be at most 0x100000 bytes"), "{}", stderr(&output));
+}
+
+#[test]
+fn boot_trace_stops_at_an_apploader_over_the_limit() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    let code_size = 0x2440 + CODE_SIZE;
+    image[code_size..code_size + 4].copy_from_slice(&0x10_0020u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+    let output = fixture.run(&["boot-trace", "image.iso"]);
+    assert!(!output.status.success());
+    let text = stdout(&output);
+    assert!(text.contains("it can be at most 0x100000 bytes"), "{}", text);
+}
+
+#[test]
+fn boot_trace_stops_at_a_misaligned_dol() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[0x420..0x424].copy_from_slice(&0x3002u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+    let output = fixture.run(&["boot-trace", "image.iso"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("The DOL is at 0x3002, which isn't a multiple of 4"), "{}", stdout(&output));
+}
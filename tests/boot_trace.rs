@@ -0,0 +1,34 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const DOL_ENTRY_POINT: usize = 0x3000 + 0xe0;
+
+#[test]
+fn tracing_a_clean_image() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(fixture.run(&["boot-trace", "image.iso"])));
+    assert!(text.starts_with("1. Read the header at 0x0\n"), "{}", text);
+    assert!(text.ends_with("\nNothing found that would stop it from booting.\n"), "{}", text);
+
+    let doc: serde_json::Value = serde_json::from_str(&stdout(&success(fixture.run(&["boot-trace", "image.iso", "--format", "json"])))).unwrap();
+    assert_eq!(doc["steps"].as_array().unwrap().len(), 6);
+    assert_eq!(doc["stopped"], false);
+}
+
+#[test]
+fn tracing_a_broken_image() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[DOL_ENTRY_POINT..DOL_ENTRY_POINT + 4].copy_from_slice(&0x8000_4000u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+
+    let output = fixture.run(&["boot-trace", "image.iso"]);
+    assert!(!output.status.success());
+    let text = stdout(&output);
+    assert!(text.contains("6. Jump to the DOL's entry point at 0x80004000\n     Error: The entry point isn't in any of the DOL's segments\n"), "{}", text);
+    assert!(text.ends_with("\nBooting would fail at step 6.\n"), "{}", text);
+    assert!(stderr(&output).contains("The ROM wouldn't boot"), "{}", stderr(&output));
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+fn fixture_with_case_collisions() -> Fixture {
+    let fixture = Fixture::new();
+    for dir in ["Data", "Straße", "STRASSE"] {
+        fs::create_dir(fixture.path("tree").join(dir)).unwrap();
+        fs::write(fixture.path("tree").join(dir).join("x.bin"), dir).unwrap();
+    }
+    fs::write(fixture.path("tree/README.TXT"), "Upper case").unwrap();
+    fs::remove_file(fixture.image()).unwrap();
+    success(fixture.run(&["rebuild", "tree", "image.iso"]));
+    fixture
+}
+
+// Without --preserve-indices, entries can come back in another order
+fn sorted_listing(fixture: &Fixture, image: &str) -> Vec<String> {
+    let mut lines: Vec<String> = stdout(&success(fixture.run(&["ls", "-R", image]))).lines().map(str::to_owned).collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn case_collisions_round_trip() {
+    let fixture = fixture_with_case_collisions();
+    let output = success(fixture.run(&["extract", "image.iso", "out", "--case-insensitive"]));
+    let text = stderr(&output);
+    assert!(text.contains("/README.TXT only differs by case from readme.txt, which is the same name on out, so it's extracted as README~1.TXT"), "{}", text);
+    assert!(text.contains("/STRASSE only differs by case from Straße"), "{}", text);
+    assert!(fixture.path("out/Data~1/x.bin").exists() || fixture.path("out/data~1/x.bin").exists(), "{}", text);
+    assert_eq!(fs::read_to_string(fixture.path("out/README~1.TXT")).unwrap(), "Upper case");
+    let manifest = fs::read_to_string(fixture.path("out/.gcmod")).unwrap();
+    assert!(manifest.contains("README~1.TXT"), "{}", manifest);
+
+    // The original names go back on the ROM
+    success(fixture.run(&["rebuild", "out", "renamed.iso"]));
+    assert_eq!(sorted_listing(&fixture, "renamed.iso"), sorted_listing(&fixture, "image.iso"));
+    // And when the order is kept, the same ROM
+    for args in [&["--preserve-indices"][..], &["--no-rebuild-fst"]] {
+        let mut rebuild = vec!["rebuild", "out", "rebuilt.iso"];
+        rebuild.extend(args);
+        success(fixture.run(&rebuild));
+        assert!(fs::read(fixture.path("rebuilt.iso")).unwrap() == fs::read(fixture.image()).unwrap(), "{:?}", args);
+        fs::remove_file(fixture.path("rebuilt.iso")).unwrap();
+    }
+}
+
+// Case-sensitive file systems keep every name, unless told otherwise
+#[test]
+fn case_sensitive_destinations() {
+    let fixture = fixture_with_case_collisions();
+    let output = success(fixture.run(&["extract", "image.iso", "out"]));
+    if gcmod::io_util::is_case_sensitive(fixture.path("out")).unwrap() {
+        assert!(!stderr(&output).contains("only differs by case"), "{}", stderr(&output));
+        assert_eq!(fs::read_to_string(fixture.path("out/README.TXT")).unwrap(), "Upper case");
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use std::fs;
+
+use common::{gcmod, stdout, success, TestDir};
+
+#[test]
+fn cleaning_the_work_dir() {
+    let dir = TestDir::new();
+    let work = dir.path().join("work");
+    fs::create_dir_all(work.join("gcmod-rebuild-1-0")).unwrap();
+    fs::write(work.join("gcmod-rebuild-1-0/partial.iso"), [0; 100]).unwrap();
+    fs::write(work.join("mine.txt"), "").unwrap();
+    let clean = |args: &[&str]| {
+        stdout(&success(gcmod(dir.path()).arg("--work-dir").arg(&work).arg("clean-workdir").args(args).output().unwrap()))
+    };
+
+    // Too new for the default
+    let text = clean(&[]);
+    assert!(text.starts_with("Removed 0 of gcmod's files and directories"), "{}", text);
+
+    let text = clean(&["--older-than", "0", "--dry-run"]);
+    assert!(text.contains("gcmod-rebuild-1-0 (100 bytes)\nWould remove 1 of gcmod's"), "{}", text);
+    assert!(work.join("gcmod-rebuild-1-0").exists());
+
+    let text = clean(&["--older-than", "0"]);
+    assert!(text.contains("\nRemoved 1 of gcmod's"), "{}", text);
+    assert!(!work.join("gcmod-rebuild-1-0").exists());
+    assert!(work.join("mine.txt").exists());
+
+    let output = gcmod(dir.path()).args(["clean-workdir", "--older-than", "a day"]).output().unwrap();
+    assert!(!output.status.success());
+}
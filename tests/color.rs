@@ -0,0 +1,58 @@
+mod common;
+
+use common::{gcmod, stdout, success, Fixture};
+
+const LS_COLORS: &str = "di=34:*.txt=36:ln=01;36";
+
+fn run(fixture: &Fixture, color: &str, args: &[&str]) -> String {
+    let output = gcmod(fixture.dir.path())
+        .env_remove("NO_COLOR")
+        .env("LS_COLORS", LS_COLORS)
+        .args(["--color", color])
+        .args(args)
+        .output()
+        .unwrap();
+    stdout(&success(output))
+}
+
+#[test]
+fn listings_with_color() {
+    let fixture = Fixture::new();
+    assert_eq!(
+        run(&fixture, "always", &["ls", "image.iso"]),
+        "\x1b[36m/readme.txt\x1b[0m\n\x1b[34m/empty/\x1b[0m\n\x1b[0m/empty.bin\x1b[0m\n\x1b[34m/data/\x1b[0m\n",
+    );
+    assert_eq!(
+        run(&fixture, "always", &["ls", "-l", "--large", "4096", "image.iso", "/data/levels"]),
+        "-        6       4096 \x1b[01;33m/data/levels/2.bin\x1b[0m\n-        7       1000 \x1b[0m/data/levels/1.bin\x1b[0m\n",
+    );
+}
+
+#[test]
+fn listings_without_color() {
+    let fixture = Fixture::new();
+    assert_eq!(run(&fixture, "never", &["ls", "image.iso"]), "/readme.txt\n/empty/\n/empty.bin\n/data/\n");
+    assert_eq!(
+        run(&fixture, "never", &["ls", "-l", "--large", "4096", "image.iso", "/data/levels"]),
+        "-        6       4096 /data/levels/2.bin\n-        7       1000 /data/levels/1.bin\n",
+    );
+    // Not a terminal
+    assert_eq!(run(&fixture, "auto", &["ls", "image.iso"]), run(&fixture, "never", &["ls", "image.iso"]));
+}
+
+#[test]
+fn the_layout_with_and_without_color() {
+    let fixture = Fixture::new();
+    let layout = ["info", "image.iso", "--type", "layout"];
+    let plain = run(&fixture, "never", &layout);
+    assert!(!plain.contains('\x1b'), "{:?}", plain);
+
+    let colored = run(&fixture, "always", &layout);
+    let lines: Vec<&str> = colored.lines().collect();
+    assert_eq!(lines[0], "0x00000000-0x00002440: \x1b[01;35mISO.hdr\x1b[0m");
+    assert!(lines.contains(&"0x00008000-0x00128000: \x1b[32mUser area\x1b[0m"), "{:?}", colored);
+    assert!(lines.last().unwrap().starts_with("\x1b[02mUnused: "), "{:?}", colored);
+    // The same text, once the colors are taken out
+    let stripped = colored.replace("\x1b[0m", "").replace("\x1b[01;35m", "").replace("\x1b[32m", "").replace("\x1b[02m", "");
+    assert_eq!(stripped, plain);
+}
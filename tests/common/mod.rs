@@ -0,0 +1,98 @@
+// Shared by the tests that run the gcmod binary. Each test file only uses
+// some of it.
+#![allow(dead_code)]
+
+use std::{
+    env,
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Like the unit tests' `TestDir`: deleted with everything in it when it's
+// dropped.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new() -> TestDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("gcmod-test-{}-{}", process::id(), n));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// A directory with the synthetic game's tree in "tree" and its image in
+// "image.iso", to run commands on.
+pub struct Fixture {
+    pub dir: TestDir,
+}
+
+impl Fixture {
+    pub fn new() -> Fixture {
+        let dir = TestDir::new();
+        gcmod::synthetic::write_image(dir.path().join("tree"), dir.path().join("image.iso")).unwrap();
+        Fixture { dir }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn image(&self) -> PathBuf {
+        self.path("image.iso")
+    }
+
+    // gcmod, run in the fixture's directory with `args`
+    pub fn run(&self, args: &[&str]) -> Output {
+        gcmod(self.dir.path()).args(args).output().unwrap()
+    }
+
+    // The names in the fixture's directory
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(self.dir.path()).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+// The gcmod binary, run in `dir` with the user's config and work directory
+// kept out of it.
+pub fn gcmod(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gcmod"));
+    command.current_dir(dir)
+        .env_remove("GCMOD_CONFIG")
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("GCMOD_WORK_DIR", dir)
+        .env("NO_COLOR", "1");
+    command
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// Panics with what gcmod printed if it failed
+pub fn success(output: Output) -> Output {
+    assert!(output.status.success(), "gcmod failed:\n{}{}", stdout(&output), stderr(&output));
+    output
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::fs;
+
+use serde_json::Value;
+
+use common::{stdout, success, Fixture};
+
+// The same tree, with its files stored in the reverse order
+fn fixture_with_reversed_copy() -> Fixture {
+    let fixture = Fixture::new();
+    fs::write(fixture.path("order.json"), r#"["/data/big.bin", "/data/levels/1.bin", "/data/levels/2.bin", "/readme.txt"]"#).unwrap();
+    success(fixture.run(&["rebuild", "tree", "reversed.iso", "--file-order", "order.json"]));
+    fixture
+}
+
+fn compare(fixture: &Fixture, args: &[&str]) -> String {
+    let mut compare = vec!["compare-layout", "image.iso"];
+    compare.extend(args);
+    stdout(&success(fixture.run(&compare)))
+}
+
+#[test]
+fn the_table_of_offsets() {
+    let fixture = fixture_with_reversed_copy();
+    assert_eq!(
+        compare(&fixture, &["reversed.iso"]),
+        "Offset in A  Offset in B      Delta  Order      Path\n\
+        0x00008000   0x00120000   +0x118000  reordered  /readme.txt\n\
+        0x00010000   0x00118000   +0x108000  reordered  /data/levels/2.bin\n\
+        0x00010000   0x00128000   +0x118000  reordered  /empty.bin\n\
+        0x00018000   0x00110000    +0xf8000  reordered  /data/levels/1.bin\n\
+        0x00020000   0x00008000    -0x18000  reordered  /data/big.bin\n\
+        \n\
+        5 files in both: 5 moved, 5 reordered, mean |delta| 897843 bytes, Kendall tau -0.7379\n",
+    );
+    let same = compare(&fixture, &["image.iso"]);
+    assert!(same.ends_with("\n5 files in both: 0 moved, 0 reordered, mean |delta| 0 bytes, Kendall tau 1.0000\n"), "{}", same);
+}
+
+#[test]
+fn the_comparison_as_json() {
+    let fixture = fixture_with_reversed_copy();
+    let doc: Value = serde_json::from_str(&compare(&fixture, &["reversed.iso", "--format", "json"])).unwrap();
+    let big = doc["files"].as_array().unwrap().iter().find(|f| f["path"] == "/data/big.bin").unwrap();
+    assert_eq!((big["offset_a"].as_u64(), big["offset_b"].as_u64(), big["delta"].as_i64()), (Some(0x20000), Some(0x8000), Some(-0x18000)));
+    assert_eq!(big["size_a"], 1060921);
+    assert_eq!(big["order_changed"], true);
+    assert_eq!(doc["stats"]["common_files"], 5);
+    assert_eq!(doc["stats"]["moved"], 5);
+    assert!((doc["stats"]["kendall_tau"].as_f64().unwrap() + 0.7379).abs() < 1e-4, "{}", doc["stats"]);
+    assert_eq!(doc["only_in_a"], Value::Array(Vec::new()));
+}
+
+#[test]
+fn files_only_one_has() {
+    let fixture = Fixture::new();
+    fs::rename(fixture.path("tree/data/levels/1.bin"), fixture.path("tree/data/levels/one.bin")).unwrap();
+    success(fixture.run(&["rebuild", "tree", "renamed.iso"]));
+    let text = compare(&fixture, &["renamed.iso"]);
+    assert!(text.contains("\nOnly in A (1):\n  /data/levels/1.bin\n\nOnly in B (1):\n  /data/levels/one.bin\n"), "{}", text);
+    assert!(text.contains("\n4 files in both: "), "{}", text);
+}
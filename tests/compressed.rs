@@ -0,0 +1,50 @@
+#![cfg(feature = "stream-compress")]
+
+mod common;
+
+use std::{fs, io::Write};
+
+use common::{stderr, stdout, success, Fixture};
+
+fn fixture_with_compressed_copies() -> Fixture {
+    let fixture = Fixture::new();
+    let image = fs::read(fixture.image()).unwrap();
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    gzip.write_all(&image).unwrap();
+    fs::write(fixture.path("image.iso.gz"), gzip.finish().unwrap()).unwrap();
+    fs::write(fixture.path("image.iso.zst"), zstd::encode_all(&image[..], 1).unwrap()).unwrap();
+    fixture
+}
+
+#[test]
+fn compressed_roms_open_like_the_image() {
+    let fixture = fixture_with_compressed_copies();
+    let plain = stdout(&success(fixture.run(&["hash", "image.iso"])));
+    for (name, compression) in [("image.iso.gz", "gzip"), ("image.iso.zst", "zstd")] {
+        let info = stdout(&success(fixture.run(&["info", name])));
+        assert!(info.contains(&format!("Compressed: {}, decompressed to read it\n", compression)), "{}", info);
+        assert!(info.contains("gcmod self-test"), "{}", info);
+        assert_eq!(stdout(&success(fixture.run(&["hash", name]))).replace(name, "image.iso"), plain);
+    }
+    assert!(!stdout(&success(fixture.run(&["info", "image.iso"]))).contains("Compressed:"));
+}
+
+#[test]
+fn compressed_roms_cant_be_changed() {
+    let fixture = fixture_with_compressed_copies();
+    let before = fs::read(fixture.path("image.iso.gz")).unwrap();
+    let output = fixture.run(&["grow-fst", "image.iso.gz", "--headroom", "32K"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("gzip compressed, so it can't be changed in place"), "{}", stderr(&output));
+    assert!(fs::read(fixture.path("image.iso.gz")).unwrap() == before);
+}
+
+#[test]
+fn cut_off_streams() {
+    let fixture = fixture_with_compressed_copies();
+    let compressed = fs::read(fixture.path("image.iso.zst")).unwrap();
+    fs::write(fixture.path("image.iso.zst"), &compressed[..compressed.len() / 2]).unwrap();
+    let output = fixture.run(&["info", "image.iso.zst"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Couldn't decompress the zstd ROM"), "{}", stderr(&output));
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::fs;
+
+use common::{gcmod, stderr, stdout, success, Fixture};
+
+const SHORT: &str = "/readme.txt\n/empty/\n/empty.bin\n/data/\n";
+
+fn write_user_config(fixture: &Fixture, text: &str) {
+    fs::create_dir_all(fixture.path("config/gcmod")).unwrap();
+    fs::write(fixture.path("config/gcmod/config.toml"), text).unwrap();
+}
+
+fn ls(fixture: &Fixture, args: &[&str]) -> String {
+    let mut ls = vec!["ls"];
+    ls.extend(args);
+    ls.push("image.iso");
+    stdout(&success(fixture.run(&ls)))
+}
+
+#[test]
+fn the_local_file_beats_the_users() {
+    let fixture = Fixture::new();
+    assert_eq!(ls(&fixture, &[]), SHORT);
+    write_user_config(&fixture, "[ls]\nlong = true\n");
+    let long = ls(&fixture, &[]);
+    assert!(long.starts_with("-        1         64 /readme.txt\n"), "{}", long);
+
+    fs::write(fixture.path(".gcmod.toml"), "[ls]\nlong = false\n").unwrap();
+    assert_eq!(ls(&fixture, &[]), SHORT);
+    // The command line beats both
+    assert_eq!(ls(&fixture, &["-l"]), long);
+}
+
+// Options that take a value can come before the command without it being
+// mistaken for the command
+#[test]
+fn global_options_before_the_command() {
+    let fixture = Fixture::new();
+    write_user_config(&fixture, "[ls]\nlong = true\n");
+    let long = ls(&fixture, &[]);
+    for globals in [&["--work-dir", "wd"][..], &["--work-dir=wd"], &["--color", "never", "-q"]] {
+        let mut args = globals.to_vec();
+        args.extend(["ls", "image.iso"]);
+        assert_eq!(stdout(&success(fixture.run(&args))), long, "{:?}", globals);
+    }
+}
+
+#[test]
+fn gcmod_config_replaces_the_users() {
+    let fixture = Fixture::new();
+    write_user_config(&fixture, "[ls]\nlong = true\n");
+    fs::write(fixture.path("other.toml"), "color = \"always\"\n").unwrap();
+    let output = gcmod(fixture.dir.path())
+        .env("GCMOD_CONFIG", "other.toml")
+        .env_remove("NO_COLOR")
+        .args(["ls", "image.iso"])
+        .output()
+        .unwrap();
+    let text = stdout(&success(output));
+    assert!(text.contains('\x1b') && !text.contains("64 "), "{:?}", text);
+
+    // Even when it doesn't exist
+    let output = gcmod(fixture.dir.path()).env("GCMOD_CONFIG", "missing.toml").args(["ls", "image.iso"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("missing.toml"), "{}", stderr(&output));
+}
+
+#[test]
+fn showing_the_config() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(fixture.run(&["config", "show"])));
+    assert!(text.starts_with("No config files."), "{}", text);
+
+    write_user_config(&fixture, "quiet = true\n[rebuild]\nalignment = 4\njobs = 8\n");
+    fs::write(fixture.path(".gcmod.toml"), "[rebuild]\njobs = 2\n").unwrap();
+    let text = stdout(&success(fixture.run(&["config", "show"])));
+    let user = fixture.path("config/gcmod/config.toml");
+    let local = fixture.path(".gcmod.toml");
+    assert!(text.contains(&format!("#   user: {}\n#   local: {}\n", user.display(), local.display())), "{}", text);
+    assert!(text.contains(&format!("quiet = true  # {}\n", user.display())), "{}", text);
+    assert!(text.contains(&format!("\n[rebuild]\nfile-alignment = 4  # {}\njobs = 2            # {}\n", user.display(), local.display())), "{}", text);
+}
+
+#[test]
+fn unknown_keys_name_the_file() {
+    let fixture = Fixture::new();
+    fs::write(fixture.path(".gcmod.toml"), "[rebuild]\noutput = \"x.iso\"\n").unwrap();
+    let output = fixture.run(&["ls", "image.iso"]);
+    assert!(!output.status.success());
+    let text = stderr(&output);
+    assert!(text.contains(".gcmod.toml") && text.contains("unknown field `output`"), "{}", text);
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use std::fs;
+
+use common::{gcmod, stderr, Fixture};
+
+#[test]
+fn crash_reports() {
+    let fixture = Fixture::new();
+    let home = fixture.dir.path().to_str().unwrap();
+    let output = gcmod(fixture.dir.path())
+        .env("GCMOD_INDUCE_PANIC", "1")
+        .env("HOME", home)
+        .args(["ls", "-l", &format!("{}/image.iso", home)])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(70));
+    let text = stderr(&output);
+    assert!(text.starts_with("gcmod crashed: Panicking because GCMOD_INDUCE_PANIC is set\n"), "{}", text);
+    let path = text.split("A crash report was written to ").nth(1)
+        .and_then(|t| t.strip_suffix(". Please attach it when filing an issue.\n"))
+        .unwrap_or_else(|| panic!("{}", text));
+
+    let report = fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines[..2], ["gcmod crash report", ""]);
+    let fields: Vec<&str> = lines[2..].iter().map(|l| l.split(':').next().unwrap()).collect();
+    assert_eq!(fields, ["Version", "Platform", "Date", "Command", "Arguments", "Thread", "Location", "Payload"]);
+    assert!(report.contains("\nCommand:    ls\n"), "{}", report);
+    // With the home directory taken out
+    assert!(report.contains("\nArguments:  gcmod ls -l ~/image.iso\n"), "{}", report);
+    assert!(report.contains("\nLocation:   src/main.rs:"), "{}", report);
+    // The message can have anything in it, so it's only printed
+    assert!(report.contains("\nPayload:    String\n"), "{}", report);
+    assert!(!report.contains("Panicking") && !report.contains(home), "{}", report);
+}
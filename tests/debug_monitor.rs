@@ -0,0 +1,53 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const DEBUG_MONITOR_OFFSET: usize = 0x400;
+const DEBUG_MONITOR_SIZE: usize = 0x440;
+
+// Like a development image's header
+fn reserve(fixture: &Fixture, offset: u32, size: u32) {
+    let path = fixture.path("tree/&&systemdata/ISO.hdr");
+    let mut header = fs::read(&path).unwrap();
+    header[DEBUG_MONITOR_OFFSET..DEBUG_MONITOR_OFFSET + 4].copy_from_slice(&offset.to_be_bytes());
+    header[DEBUG_MONITOR_SIZE..DEBUG_MONITOR_SIZE + 4].copy_from_slice(&size.to_be_bytes());
+    fs::write(path, header).unwrap();
+}
+
+fn layout(fixture: &Fixture, image: &str) -> String {
+    stdout(&success(fixture.run(&["info", image, "--type", "layout"])))
+}
+
+fn readme(fixture: &Fixture, image: &str) -> String {
+    stdout(&success(fixture.run(&["info", image, "--path", "/readme.txt", "--hex"])))
+}
+
+#[test]
+fn rebuilding_around_the_debug_monitor() {
+    let fixture = Fixture::new();
+    reserve(&fixture, 0x2800, 0x10000);
+    let output = success(fixture.run(&["rebuild", "tree", "dev.iso", "--verify-after"]));
+    assert!(stdout(&output).contains("No problems found."), "{}", stdout(&output));
+    let text = layout(&fixture, "dev.iso");
+    assert!(text.contains("0x00002800-0x00012800: Debug monitor\n"), "{}", text);
+    assert!(readme(&fixture, "dev.iso").contains("Offset: 0x18000\n"), "{}", readme(&fixture, "dev.iso"));
+    assert!(!layout(&fixture, "image.iso").contains("Debug monitor"));
+
+    let output = success(fixture.run(&["rebuild", "tree", "retail.iso", "--ignore-debug-monitor", "--verify-after"]));
+    assert!(stderr(&output).contains("The debug monitor is taken out of the header"), "{}", stderr(&output));
+    assert!(!layout(&fixture, "retail.iso").contains("Debug monitor"));
+    assert_eq!(readme(&fixture, "retail.iso"), readme(&fixture, "image.iso"));
+}
+
+#[test]
+fn a_debug_monitor_that_leaves_no_room() {
+    let fixture = Fixture::new();
+    reserve(&fixture, 0x8000, 0x5700_0000);
+    let output = fixture.run(&["rebuild", "tree", "dev.iso"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Pass --ignore-debug-monitor to put files there instead."), "{}", stderr(&output));
+    assert!(!fixture.path("dev.iso").exists());
+    success(fixture.run(&["rebuild", "tree", "dev.iso", "--ignore-debug-monitor"]));
+}
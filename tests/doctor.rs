@@ -0,0 +1,27 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+#[test]
+fn only_failures_fail() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["doctor", "image.iso", "tree"]));
+    let text = stdout(&output);
+    assert!(text.contains("PASS  input        image.iso is a ROM of gcmod self-test"), "{}", text);
+    assert!(text.contains("PASS  input        tree is an extracted ROM"), "{}", text);
+    assert!(text.ends_with("No checks failed.\n"), "{}", text);
+
+    // Not empty, which only warns
+    fs::create_dir(fixture.path("out")).unwrap();
+    fs::write(fixture.path("out/file"), b"").unwrap();
+    let output = success(fixture.run(&["doctor", "--for", "extract", "image.iso", "out"]));
+    assert!(stdout(&output).contains("WARN  destination"), "{}", stdout(&output));
+
+    fs::write(fixture.path("garbage.iso"), b"not a ROM").unwrap();
+    let output = fixture.run(&["doctor", "image.iso", "garbage.iso"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("FAIL  input        garbage.iso isn't a valid ROM"), "{}", stdout(&output));
+    assert!(stderr(&output).contains("1 check failed"), "{}", stderr(&output));
+}
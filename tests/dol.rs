@@ -0,0 +1,48 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, success, Fixture};
+
+#[test]
+fn repacking_an_unpacked_dol() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    success(fixture.run(&["unpack-dol", "out/&&systemdata/Start.dol", "segments"]));
+    success(fixture.run(&["repack-dol", "segments", "repacked.dol"]));
+    assert!(fs::read(fixture.path("repacked.dol")).unwrap() == fs::read(fixture.path("out/&&systemdata/Start.dol")).unwrap());
+
+    // The same, from the ROM itself
+    success(fixture.run(&["unpack-dol", "image.iso", "from-rom"]));
+    assert_eq!(
+        fs::read_to_string(fixture.path("from-rom/segments.json")).unwrap(),
+        fs::read_to_string(fixture.path("segments/segments.json")).unwrap(),
+    );
+    let output = fixture.run(&["unpack-dol", "image.iso", "from-rom"]);
+    assert!(!output.status.success(), "{}", stderr(&output));
+    success(fixture.run(&["unpack-dol", "image.iso", "from-rom", "--force"]));
+}
+
+#[test]
+fn splitting_while_extracting() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["extract", "image.iso", "out", "--dol-split"]));
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+    let split = fixture.path("out/&&systemdata/Start.dol.d");
+    for name in ["text0.bin", "segments.json", "bss.txt", "layout.ld"] {
+        assert!(split.join(name).is_file(), "{} is missing", name);
+    }
+    success(fixture.run(&["repack-dol", "out/&&systemdata/Start.dol.d", "repacked.dol"]));
+    let dol = fs::read(fixture.path("out/&&systemdata/Start.dol")).unwrap();
+    assert!(fs::read(fixture.path("repacked.dol")).unwrap() == dol);
+
+    // Only the DOL, with its segments next to it
+    success(fixture.run(&["extract", "image.iso", "Start.dol", "-s", "&&systemdata/Start.dol", "--dol-split"]));
+    assert!(fs::read(fixture.path("Start.dol")).unwrap() == dol);
+    success(fixture.run(&["repack-dol", "Start.dol.d", "alone.dol"]));
+    assert!(fs::read(fixture.path("alone.dol")).unwrap() == dol);
+
+    let output = fixture.run(&["extract", "image.iso", "hdr", "-s", "&&systemdata/ISO.hdr", "--dol-split"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("--dol-split only works when extracting everything"), "{}", stderr(&output));
+}
@@ -0,0 +1,74 @@
+// The commands are run with sh, so these only run on Unix
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const DOL_ENTRY_POINT: usize = 0x3000 + 0xe0;
+const ROOT_NEXT_INDEX: usize = 0x2808;
+
+fn break_image(fixture: &Fixture, at: usize, value: u32) {
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[at..at + 4].copy_from_slice(&value.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+}
+
+#[test]
+fn running_the_command_with_the_rom() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["exec", "image.iso", "--", "sh", "-c", "echo \"[$1]\" \"$2\"", "sh", "{}", "rom={}"]));
+    assert_eq!(stdout(&output), "[image.iso] rom=image.iso\n");
+    // At the end, without a {}
+    let output = success(fixture.run(&["exec", "image.iso", "--require", "verify,boot-check", "--", "sh", "-c", "echo \"$1\"", "sh"]));
+    assert_eq!(stdout(&output), "image.iso\n");
+    // The command's exit code is gcmod's
+    let output = fixture.run(&["exec", "image.iso", "--", "sh", "-c", "exit 7"]);
+    assert_eq!(output.status.code(), Some(7));
+}
+
+#[test]
+fn failed_checks_dont_run_the_command() {
+    let fixture = Fixture::new();
+    let command = ["--", "sh", "-c", "touch ran"];
+    break_image(&fixture, DOL_ENTRY_POINT, 0x8000_4000);
+    // The entry point isn't something `verify` checks
+    success(fixture.run(&[&["exec", "image.iso", "--require", "verify"][..], &["--", "true"]].concat()));
+    let output = fixture.run(&[&["exec", "image.iso", "--require", "verify,boot-check"][..], &command].concat());
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stdout(&output).contains("Error: The entry point isn't in any of the DOL's segments"), "{}", stdout(&output));
+    assert!(stderr(&output).contains("boot-check failed, so the command wasn't run."), "{}", stderr(&output));
+    assert!(!fixture.path("ran").exists());
+
+    break_image(&fixture, ROOT_NEXT_INDEX, 8);
+    let output = fixture.run(&[&["exec", "image.iso"][..], &command].concat());
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stdout(&output).starts_with("Error: "), "{}", stdout(&output));
+    assert!(stderr(&output).contains("verify failed, so the command wasn't run."), "{}", stderr(&output));
+    assert!(!fixture.path("ran").exists());
+
+    let output = fixture.run(&[&["exec", "image.iso", "--require", "verify,crc"][..], &command].concat());
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unknown check \"crc\""), "{}", stderr(&output));
+    assert!(!fixture.path("ran").exists());
+}
+
+// Swapping the ROM out once the checks are done: only with --pass-fd does
+// the command still get the one that was checked
+#[cfg(target_os = "linux")]
+#[test]
+fn passing_the_checked_file() {
+    let fixture = Fixture::new();
+    fs::copy(fixture.image(), fixture.path("checked.iso")).unwrap();
+    fs::write(fixture.path("other.iso"), b"not the ROM").unwrap();
+    let swap = "cp other.iso new.iso && mv new.iso image.iso && cmp -s \"$1\" checked.iso && echo \"same $1\"";
+    let output = success(fixture.run(&["exec", "image.iso", "--pass-fd", "--", "sh", "-c", swap, "sh", "{}"]));
+    assert!(stdout(&output).starts_with("same /proc/self/fd/"), "{}", stdout(&output));
+
+    fs::copy(fixture.path("checked.iso"), fixture.image()).unwrap();
+    let output = fixture.run(&["exec", "image.iso", "--", "sh", "-c", swap, "sh", "{}"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).is_empty(), "{}", stdout(&output));
+}
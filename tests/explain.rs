@@ -0,0 +1,30 @@
+mod common;
+
+use common::{gcmod, stderr, stdout, success, TestDir};
+
+// Doesn't need an image
+fn explain(offset: &str) -> String {
+    let dir = TestDir::new();
+    stdout(&success(gcmod(dir.path()).args(["explain", offset]).output().unwrap()))
+}
+
+#[test]
+fn header_and_dol_offsets() {
+    assert_eq!(
+        explain("0x420"),
+        "As an offset in the image:\n  header+0x420: dol_offset, byte 0 of 4 (Header::dol_offset)\n      Where the DOL, Start.dol, is.\n",
+    );
+    let both = explain("0x1c");
+    let (image, dol) = both.split_once("As an offset in the DOL:\n").unwrap();
+    assert!(image.contains("header+0x1c: magic_word, byte 0 of 4"), "{}", both);
+    assert!(dol.contains("dol+0x1c: data_offsets[0], byte 0 of 4 (Segment::offset)"), "{}", both);
+}
+
+#[test]
+fn offsets_nothing_fixed_is_at() {
+    assert!(explain("0x5000").starts_with("Nothing fixed is at 0x5000"));
+    let dir = TestDir::new();
+    let output = gcmod(dir.path()).args(["explain", "nowhere"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid offset"), "{}", stderr(&output));
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+#[test]
+fn inis_are_named_for_the_game() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["export-ini", "image.iso"]));
+    assert_eq!(stdout(&output), "Wrote GSLF01.ini.\n");
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+    assert!(fs::read_to_string(fixture.path("GSLF01.ini")).unwrap().starts_with("# GSLF01 - gcmod self-test\n"));
+
+    // Into a directory, under the same name
+    fs::create_dir(fixture.path("settings")).unwrap();
+    success(fixture.run(&["export-ini", "image.iso", "--out", "settings"]));
+    assert!(fixture.path("settings/GSLF01.ini").exists());
+
+    let output = success(fixture.run(&["export-ini", "image.iso", "--out", "other.ini"]));
+    assert!(stderr(&output).contains("Dolphin won't load other.ini for GSLF01, which it looks for as GSLF01.ini."), "{}", stderr(&output));
+}
+
+#[test]
+fn replacing_an_ini() {
+    let fixture = Fixture::new();
+    fs::write(fixture.path("GSLF01.ini"), "# GALE01 - Super Smash Bros. Melee\n").unwrap();
+    let output = fixture.run(&["export-ini", "image.iso"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("already exists, pass --force"), "{}", stderr(&output));
+
+    let output = success(fixture.run(&["export-ini", "image.iso", "--force"]));
+    assert!(stderr(&output).contains("GSLF01.ini is for GALE01, not GSLF01, and is being replaced."), "{}", stderr(&output));
+    // No warning when it was already for this game
+    let output = success(fixture.run(&["export-ini", "image.iso", "--force"]));
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+}
+
+#[test]
+fn embedding_gecko_codes() {
+    let fixture = Fixture::new();
+    let codes = "$Infinite Health [Someone]\n*A note\n04123456 60000000\n";
+    fs::write(fixture.path("codes.txt"), codes).unwrap();
+    let output = success(fixture.run(&["export-ini", "image.iso", "--gecko", "codes.txt"]));
+    assert!(stdout(&output).starts_with("Adding 1 Gecko codes.\n"), "{}", stdout(&output));
+    let ini = fs::read_to_string(fixture.path("GSLF01.ini")).unwrap();
+    assert!(ini.ends_with(&format!("[Gecko]\n{}", codes)), "{}", ini);
+
+    fs::write(fixture.path("codes.txt"), "$Infinite Health\n0412345 60000000\n").unwrap();
+    let output = fixture.run(&["export-ini", "image.iso", "--gecko", "codes.txt", "--out", "bad.ini"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Line 2 isn't two hexadecimal words"), "{}", stderr(&output));
+    assert!(!fixture.path("bad.ini").exists());
+}
+
+#[test]
+fn wiki_infoboxes() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(fixture.run(&["export-ini", "image.iso", "--wiki"])));
+    assert!(text.starts_with("{{Infobox VG\n|title = gcmod self-test\n"), "{}", text);
+    assert!(!fixture.path("GSLF01.ini").exists());
+}
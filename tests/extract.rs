@@ -0,0 +1,292 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+#[test]
+fn extracts_the_synthetic_game() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    for &(path, size) in gcmod::synthetic::FILES {
+        assert!(fs::read(fixture.path("out").join(path)).unwrap() == gcmod::synthetic::contents(size), "{}", path);
+    }
+}
+
+// The hints only change how the files are written
+#[test]
+fn extracting_without_fs_hints() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "hinted"]));
+    success(fixture.run(&["extract", "image.iso", "plain", "--no-fs-hints"]));
+    for &(path, _) in gcmod::synthetic::FILES {
+        let hinted = fs::read(fixture.path("hinted").join(path)).unwrap();
+        assert!(hinted == fs::read(fixture.path("plain").join(path)).unwrap(), "{}", path);
+    }
+}
+
+#[test]
+fn destination_problems_are_caught_first() {
+    let fixture = Fixture::new();
+    fs::write(fixture.path("file"), "").unwrap();
+    let output = fixture.run(&["extract", "image.iso", "file"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("already exists and is a file"), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(fixture.path("file")).unwrap(), "");
+
+    let output = fixture.run(&["extract", "image.iso", "missing/out"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("doesn't exist"), "{}", stderr(&output));
+    assert!(!fixture.path("missing").exists());
+}
+
+#[test]
+fn existing_empty_directories_are_extracted_into() {
+    let fixture = Fixture::new();
+    fs::create_dir(fixture.path("out")).unwrap();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    assert!(fs::read(fixture.path("out/data/levels/1.bin")).unwrap() == gcmod::synthetic::contents(1000));
+}
+
+#[test]
+fn non_empty_directories_need_force() {
+    let fixture = Fixture::new();
+    fs::create_dir(fixture.path("out")).unwrap();
+    fs::write(fixture.path("out/notes.txt"), "mine").unwrap();
+    fs::write(fixture.path("out/readme.txt"), "in the way").unwrap();
+
+    let output = fixture.run(&["extract", "image.iso", "out"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("already exists and isn't empty, pass --force"), "{}", stderr(&output));
+    assert_eq!(fs::read_dir(fixture.path("out")).unwrap().count(), 2);
+
+    success(fixture.run(&["extract", "image.iso", "out", "--force"]));
+    assert_eq!(fs::read_to_string(fixture.path("out/notes.txt")).unwrap(), "mine");
+    assert!(fs::read(fixture.path("out/readme.txt")).unwrap() == gcmod::synthetic::contents(64));
+}
+
+#[cfg(unix)]
+#[test]
+fn read_only_destinations_are_caught_first() {
+    use std::os::unix::fs::PermissionsExt;
+    let fixture = Fixture::new();
+    let read_only = fixture.path("read-only");
+    fs::create_dir(&read_only).unwrap();
+    fs::set_permissions(&read_only, fs::Permissions::from_mode(0o555)).unwrap();
+    if fs::File::create(read_only.join("probe")).is_ok() {
+        eprintln!("skipped, read-only directories can be written to here");
+        return;
+    }
+
+    let output = fixture.run(&["extract", "image.iso", "read-only/out"]);
+    fs::set_permissions(&read_only, fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("is not writable"), "{}", stderr(&output));
+    assert_eq!(fs::read_dir(&read_only).unwrap().count(), 0);
+}
+
+// Where `path` starts in the fixture's image
+fn file_offset(fixture: &Fixture, path: &str) -> u64 {
+    let iso = std::io::BufReader::new(fs::File::open(fixture.image()).unwrap());
+    let game = gcmod::Game::open(iso, 0).unwrap();
+    game.fst.entries().iter()
+        .filter_map(|e| e.as_file())
+        .find(|f| f.info.normalized_path() == path)
+        .unwrap()
+        .file_offset
+}
+
+#[test]
+fn keep_going_lists_what_failed() {
+    let fixture = Fixture::new();
+    // Cut off partway through the biggest file
+    let big = file_offset(&fixture, "/data/big.bin");
+    let mut bytes = fs::read(fixture.image()).unwrap();
+    bytes.truncate(big as usize + 100);
+    fs::write(fixture.image(), bytes).unwrap();
+
+    let output = fixture.run(&["extract", "image.iso", "out", "--keep-going", "--fail-on-truncated"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("failed to extract, see"), "{}", stderr(&output));
+    let errors = fs::read_to_string(fixture.path("out/extraction-errors.json")).unwrap();
+    assert!(errors.contains("\"/data/big.bin\""), "{}", errors);
+    assert!(fs::read(fixture.path("out/readme.txt")).unwrap() == gcmod::synthetic::contents(64));
+}
+
+#[test]
+fn directories_as_standalone_fsts() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "data.toc", "-s", "/data#fst"]));
+    let fst = gcmod::sections::fst::FST::new(std::io::Cursor::new(fs::read(fixture.path("data.toc")).unwrap()), 0).unwrap();
+    let mut paths: Vec<String> = fst.entries().iter().map(|e| e.info().normalized_path()).collect();
+    paths.sort();
+    assert_eq!(paths, ["/", "/big.bin", "/levels", "/levels/1.bin", "/levels/2.bin"]);
+
+    let output = fixture.run(&["extract", "image.iso", "readme.toc", "-s", "/readme.txt#fst"]);
+    assert!(!output.status.success());
+    assert!(!fixture.path("readme.toc").exists());
+}
+
+// Two copies of the same 5000 bytes, and one the same size that isn't
+fn fixture_with_duplicates() -> Fixture {
+    let fixture = Fixture::new();
+    let contents = vec![7; 5000];
+    fs::write(fixture.path("tree/copy-a.bin"), &contents).unwrap();
+    fs::write(fixture.path("tree/data/copy-b.bin"), &contents).unwrap();
+    fs::write(fixture.path("tree/different.bin"), vec![8; 5000]).unwrap();
+    success(fixture.run(&["rebuild", "tree", "dupes.iso"]));
+    fixture
+}
+
+#[cfg(unix)]
+#[test]
+fn duplicates_are_linked() {
+    use std::os::unix::fs::MetadataExt;
+    let fixture = fixture_with_duplicates();
+    let output = success(fixture.run(&["extract", "dupes.iso", "out", "--dedup", "hardlink"]));
+    assert!(stdout(&output).contains("Linked 1 duplicate files, saving 5000 bytes."), "{}", stdout(&output));
+    let inode = |path: &str| fs::metadata(fixture.path(path)).unwrap().ino();
+    assert_eq!(inode("out/copy-a.bin"), inode("out/data/copy-b.bin"));
+    assert_ne!(inode("out/copy-a.bin"), inode("out/different.bin"));
+
+    let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(fixture.path("out/.gcmod")).unwrap()).unwrap();
+    assert_eq!(manifest["duplicates"], serde_json::json!([
+        { "path": "/data/copy-b.bin", "original": "/copy-a.bin", "link": "hardlink" },
+    ]));
+    success(fixture.run(&["rebuild", "out", "rebuilt.iso"]));
+    success(fixture.run(&["diff", "dupes.iso", "rebuilt.iso"]));
+}
+
+#[cfg(unix)]
+#[test]
+fn without_dedup_duplicates_are_separate() {
+    use std::os::unix::fs::MetadataExt;
+    let fixture = fixture_with_duplicates();
+    success(fixture.run(&["extract", "dupes.iso", "out"]));
+    let inode = |path: &str| fs::metadata(fixture.path(path)).unwrap().ino();
+    assert_ne!(inode("out/copy-a.bin"), inode("out/data/copy-b.bin"));
+    let manifest = fs::read_to_string(fixture.path("out/.gcmod")).unwrap();
+    assert!(!manifest.contains("\"duplicates\""), "{}", manifest);
+}
+
+#[test]
+fn extended_attributes_record_where_files_came_from() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["extract", "image.iso", "out", "--xattrs"]));
+    if stderr(&output).contains("Couldn't set extended attributes") {
+        eprintln!("Skipping, the file system doesn't support extended attributes");
+        return;
+    }
+    let shown = stdout(&success(fixture.run(&["xattr", "out/data/levels/2.bin"])));
+    assert_eq!(shown, "Offset: 65536 (0x10000)\nSize: 4096\nIndex: 6\nGame ID: GSLF01\n");
+
+    // Without --xattrs, there aren't any
+    success(fixture.run(&["extract", "image.iso", "plain"]));
+    let output = fixture.run(&["xattr", "plain/readme.txt"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("doesn't have any gcmod attributes"), "{}", stderr(&output));
+}
+
+// The fixture, rebuilt with files that have no extension
+fn fixture_with_extensionless_files() -> Fixture {
+    let fixture = Fixture::new();
+    let mut yaz0 = b"Yaz0".to_vec();
+    yaz0.resize(0x40, 0);
+    let mut rarc = b"RARC".to_vec();
+    rarc.resize(0x40, 0x11);
+    for (path, contents) in [
+        ("tree/data/model", yaz0),
+        ("tree/data/pack", rarc),
+        ("tree/data/notes", b"Some notes\n".to_vec()),
+        ("tree/data/notes.txt", b"Other notes\n".to_vec()),
+        ("tree/data/blob", vec![0xff; 0x40]),
+    ] {
+        fs::write(fixture.path(path), contents).unwrap();
+    }
+    fs::remove_file(fixture.image()).unwrap();
+    success(fixture.run(&["rebuild", "tree", "image.iso"]));
+    fixture
+}
+
+#[test]
+fn inferring_extensions() {
+    let fixture = fixture_with_extensionless_files();
+    let output = success(fixture.run(&["extract", "image.iso", "out", "--infer-extensions"]));
+    assert!(
+        stderr(&output).contains("/data/notes looks like text, but it can't be renamed to notes.txt since notes.txt is already there"),
+        "{}", stderr(&output),
+    );
+    let mut names: Vec<String> = fs::read_dir(fixture.path("out/data")).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["big.bin", "blob", "levels", "model.szs", "notes", "notes.txt", "pack.arc"]);
+    assert_eq!(fs::read(fixture.path("out/data/notes")).unwrap(), b"Some notes\n");
+
+    let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(fixture.path("out/.gcmod")).unwrap()).unwrap();
+    assert_eq!(
+        manifest["renamed"],
+        serde_json::json!([
+            { "path": "/data/model", "extracted_as": "model.szs" },
+            { "path": "/data/pack", "extracted_as": "pack.arc" },
+        ]),
+    );
+
+    // With the names it had before
+    success(fixture.run(&["rebuild", "out", "rebuilt.iso", "--preserve-indices"]));
+    let listing = |image| stdout(&success(fixture.run(&["ls", "-l", "-R", image])));
+    assert_eq!(listing("rebuilt.iso"), listing("image.iso"));
+    assert!(fs::read(fixture.path("rebuilt.iso")).unwrap() == fs::read(fixture.image()).unwrap());
+
+    fs::write(fixture.path("out/data/model"), b"new").unwrap();
+    let output = fixture.run(&["rebuild", "out", "both.iso"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("data/model and data/model.szs would both be data/model on the ROM"), "{}", stderr(&output));
+}
+
+#[test]
+fn reporting_inferred_extensions() {
+    let fixture = fixture_with_extensionless_files();
+    let output = success(fixture.run(&["extract", "image.iso", "out", "--infer-extensions=report"]));
+    assert_eq!(
+        stdout(&output),
+        "/data/model -> model.szs (Yaz0 compressed data)\n\
+        /data/notes (text): not renamed, notes.txt is already there\n\
+        /data/pack -> pack.arc (RARC archive)\n\
+        2 of 10 files would be renamed.\n",
+    );
+    assert!(!fixture.path("out").exists());
+}
+
+fn section(fixture: &Fixture, name: &str, output: &str) -> String {
+    let output = fixture.run(&["extract", "image.iso", output, "--section", name]);
+    assert!(!output.status.success(), "{}", name);
+    stderr(&output)
+}
+
+#[test]
+fn sections_that_arent_there() {
+    let fixture = Fixture::new();
+    let text = section(&fixture, "/data/bgi.bin", "out.bin");
+    assert!(text.contains("There's no section or file called /data/bgi.bin. Did you mean:\n  /data/big.bin"), "{}", text);
+    let text = section(&fixture, "/data/levls/1.bin", "out.bin");
+    assert!(text.contains("/data has nothing called levls"), "{}", text);
+    let text = section(&fixture, "/nothing/like/it", "out.bin");
+    assert!(text.contains("There's no section or file called /nothing/like/it: / has nothing called nothing.\n"), "{}", text);
+    assert!(!text.contains("Did you mean"), "{}", text);
+
+    let text = section(&fixture, ".text9", "out.bin");
+    assert!(text.contains("The DOL doesn't have a .text9 segment. The DOL's segments are:\n  .text0"), "{}", text);
+    assert!(!fixture.path("out.bin").exists());
+}
+
+#[test]
+fn sections_that_cant_be_written() {
+    let fixture = Fixture::new();
+    let text = section(&fixture, "/readme.txt", "missing/out.txt");
+    assert!(text.contains("Failed to extract to missing/out.txt"), "{}", text);
+    // With why
+    assert!(text.contains("No such file or directory"), "{}", text);
+    assert!(!text.contains("Did you mean"), "{}", text);
+    success(fixture.run(&["extract", "image.iso", "readme.txt", "--section", "/readme.txt"]));
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const APPLOADER_DATE: usize = 0x2440;
+
+fn ls(fixture: &Fixture, mtime: &str) -> std::process::Output {
+    fixture.run(&["ls", "-l", "--fake-mtime", mtime, "image.iso", "/data/levels"])
+}
+
+#[test]
+fn every_entry_gets_the_same_time() {
+    let fixture = Fixture::new();
+    assert_eq!(
+        stdout(&success(ls(&fixture, "epoch"))),
+        "-        6       4096 1970-01-01 00:00 /data/levels/2.bin\n-        7       1000 1970-01-01 00:00 /data/levels/1.bin\n",
+    );
+    let text = stdout(&success(ls(&fixture, "2002-07-19T14:30:00+02:00")));
+    assert!(text.lines().all(|l| l.contains(" 2002-07-19 12:30 /data/levels/")), "{}", text);
+
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[APPLOADER_DATE..APPLOADER_DATE + 10].copy_from_slice(b"2002/07/19");
+    fs::write(fixture.image(), image).unwrap();
+    let text = stdout(&success(ls(&fixture, "apploader")));
+    assert!(text.lines().all(|l| l.contains(" 2002-07-19 00:00 /data/levels/")), "{}", text);
+}
+
+#[test]
+fn times_there_arent() {
+    let fixture = Fixture::new();
+    // The synthetic apploader's date is a placeholder
+    let output = ls(&fixture, "apploader");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("isn't a real date"), "{}", stderr(&output));
+    let output = ls(&fixture, "yesterday");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("\"yesterday\" isn't \"apploader\", \"epoch\", or a time"), "{}", stderr(&output));
+    // Only for the long format
+    assert!(!fixture.run(&["ls", "--fake-mtime", "epoch", "image.iso"]).status.success());
+}
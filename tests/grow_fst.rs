@@ -0,0 +1,78 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const ENTRY_SIZE: usize = 12;
+const EMPTY_BIN: usize = 3;
+
+fn be32(image: &[u8], at: usize) -> usize {
+    u32::from_be_bytes(image[at..at + 4].try_into().unwrap()) as usize
+}
+
+// Adds `count` empty files to the root of the image's FST in place, and
+// returns how big the FST is now
+fn add_files(image: &mut [u8], count: usize) -> usize {
+    let (fst_offset, fst_size) = (be32(image, 0x424), be32(image, 0x428));
+    let fst = &image[fst_offset..fst_offset + fst_size];
+    let entries = be32(fst, 8);
+    let (mut table, mut names) = (fst[..entries * ENTRY_SIZE].to_vec(), fst[entries * ENTRY_SIZE..].to_vec());
+    table[8..12].copy_from_slice(&((entries + count) as u32).to_be_bytes());
+    for i in 0..count {
+        let mut entry = table[EMPTY_BIN * ENTRY_SIZE..][..ENTRY_SIZE].to_vec();
+        entry[1..4].copy_from_slice(&(names.len() as u32).to_be_bytes()[1..]);
+        table.extend(entry);
+        names.extend(format!("extra{:04}.bin\0", i).as_bytes());
+    }
+    let fst = [table, names].concat();
+    image[fst_offset..fst_offset + fst.len()].copy_from_slice(&fst);
+    image[0x428..0x42c].copy_from_slice(&(fst.len() as u32).to_be_bytes());
+    fst.len()
+}
+
+#[test]
+fn growing_the_fst() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "before"]));
+    let output = success(fixture.run(&["grow-fst", "image.iso", "--headroom", "64K"]));
+    let text = stdout(&output);
+    assert!(text.contains("Moving /readme.txt (64 bytes) from 0x00008000 to "), "{}", text);
+    assert!(text.contains("The file system table can now take up "), "{}", text);
+    let verify = stdout(&success(fixture.run(&["verify", "image.iso"])));
+    assert!(verify.contains("No problems found."), "{}", verify);
+
+    // The FST can now take more files than it had room for before
+    let mut image = fs::read(fixture.image()).unwrap();
+    let old_max_fst_size = 0xa7;
+    let fst_size = add_files(&mut image, 1000);
+    assert!(fst_size > old_max_fst_size && fst_size <= be32(&image, 0x42c), "{:#x}", fst_size);
+    fs::write(fixture.image(), &image).unwrap();
+    let verify = stdout(&success(fixture.run(&["verify", "image.iso"])));
+    assert!(verify.contains("No problems found."), "{}", verify);
+
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    for &(path, size) in gcmod::synthetic::FILES {
+        assert!(fs::read(fixture.path("out").join(path)).unwrap() == gcmod::synthetic::contents(size), "{}", path);
+    }
+    for i in [0, 999] {
+        assert!(fs::read(fixture.path("out").join(format!("extra{:04}.bin", i))).unwrap().is_empty());
+    }
+    let dol = "&&systemdata/Start.dol";
+    assert!(fs::read(fixture.path("out").join(dol)).unwrap() == fs::read(fixture.path("before").join(dol)).unwrap());
+    // There's room already
+    let text = stdout(&success(fixture.run(&["grow-fst", "image.iso", "--headroom", "32K"])));
+    assert!(text.starts_with("The file system table already has room for "), "{}", text);
+}
+
+#[test]
+fn room_that_cant_be_made_changes_nothing() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    for (headroom, error) in [("128K", "to move /data/big.bin"), ("16M", "past the end of the image"), ("lots", "Invalid headroom")] {
+        let output = fixture.run(&["grow-fst", "image.iso", "--headroom", headroom]);
+        assert!(!output.status.success(), "{}", headroom);
+        assert!(stderr(&output).contains(error), "{}: {}", headroom, stderr(&output));
+        assert!(fs::read(fixture.image()).unwrap() == before);
+    }
+}
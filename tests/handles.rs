@@ -0,0 +1,40 @@
+mod common;
+
+use std::fs::{self, OpenOptions};
+
+use gcmod::rom_handle::RomWriteHandle;
+
+use common::{stderr, success, Fixture};
+
+#[cfg(unix)]
+#[test]
+fn read_commands_work_on_read_only_roms() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    fs::set_permissions(fixture.image(), fs::Permissions::from_mode(0o444)).unwrap();
+    success(fixture.run(&["info", "image.iso"]));
+    success(fixture.run(&["ls", "-R", "image.iso"]));
+    success(fixture.run(&["verify", "image.iso"]));
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    assert!(fs::read(fixture.image()).unwrap() == before);
+    assert_eq!(fs::metadata(fixture.image()).unwrap().permissions().mode() & 0o777, 0o444);
+}
+
+#[cfg(unix)]
+#[test]
+fn a_second_writer_is_turned_away() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    let lock = RomWriteHandle::open_for_modification(fixture.image(), &OpenOptions::new()).unwrap();
+    for args in [&["rebuild", "tree", "--update", "image.iso"][..], &["set", "image.iso", "--disc", "2"]] {
+        let output = fixture.run(args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains("already modifying this ROM"), "{:?}: {}", args, stderr(&output));
+    }
+    assert!(fs::read(fixture.image()).unwrap() == before);
+
+    drop(lock);
+    success(fixture.run(&["set", "image.iso", "--disc", "2"]));
+}
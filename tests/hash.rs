@@ -0,0 +1,44 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const README_OFFSET: usize = 0x8000;
+
+#[test]
+fn quick_ids_say_what_they_sampled() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(fixture.run(&["hash", "--quick", "image.iso"])));
+    assert!(text.starts_with("Quick-id: q1-"), "{}", text);
+    for name in ["header", "fst", "dol", "files start", "files end"] {
+        assert!(text.contains(&format!("  {:<12} 0x", name)), "{}", text);
+    }
+    assert!(text.contains("not a cryptographic hash"), "{}", text);
+
+    let full = stdout(&success(fixture.run(&["hash", "image.iso"])));
+    assert!(!full.contains("q1-"), "{}", full);
+}
+
+#[test]
+fn catalogs_match_by_quick_id_or_full_hash() {
+    let fixture = Fixture::new();
+    fs::create_dir(fixture.path("roms")).unwrap();
+    let image = fs::read(fixture.image()).unwrap();
+    fs::write(fixture.path("roms/same.iso"), &image).unwrap();
+    let mut changed = image;
+    changed[README_OFFSET] ^= 1;
+    fs::write(fixture.path("roms/changed.iso"), &changed).unwrap();
+    success(fixture.run(&["catalog", "roms"]));
+
+    let matches = stdout(&success(fixture.run(&["catalog", "roms", "--match", "image.iso"])));
+    assert!(matches.contains("same.iso") && !matches.contains("changed.iso"), "{}", matches);
+
+    // Nothing was hashed in full yet
+    let output = success(fixture.run(&["catalog", "roms", "--match", "image.iso", "--full"]));
+    assert_eq!(stdout(&output), "No ROMs in the index match.\n");
+    assert!(stderr(&output).contains("2 of the ROMs in the index weren't hashed in full"), "{}", stderr(&output));
+    success(fixture.run(&["catalog", "roms", "--full"]));
+    let matches = stdout(&success(fixture.run(&["catalog", "roms", "--match", "image.iso", "--full"])));
+    assert!(matches.contains("same.iso") && !matches.contains("changed.iso"), "{}", matches);
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+fn extract_and_rebuild(fixture: &Fixture, args: &[&str]) -> std::process::Output {
+    let mut rebuild = vec!["rebuild", "out", "rebuilt.iso", "--capacity", "4194304"];
+    rebuild.extend(args);
+    fixture.run(&rebuild)
+}
+
+#[test]
+fn preserved_indices_dont_change() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    success(extract_and_rebuild(&fixture, &["--preserve-indices"]));
+    let diff = success(fixture.run(&["diff", "--indices", "image.iso", "rebuilt.iso"]));
+    assert_eq!(stdout(&diff), "");
+}
+
+#[test]
+fn added_files_are_conflicts() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    fs::write(fixture.path("out/aaa.bin"), "new").unwrap();
+
+    let output = extract_and_rebuild(&fixture, &["--preserve-indices"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("aaa.bin was added"), "{}", stderr(&output));
+    assert!(!fixture.path("rebuilt.iso").exists());
+
+    // Without it, the new file moves the ones after it
+    success(extract_and_rebuild(&fixture, &[]));
+    let diff = stdout(&success(fixture.run(&["diff", "--indices", "image.iso", "rebuilt.iso"])));
+    assert!(diff.lines().any(|l| l == "+ /aaa.bin"), "{}", diff);
+    assert!(diff.lines().any(|l| l.starts_with("  /data: ")), "{}", diff);
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+fn info(fixture: &Fixture, args: &[&str]) -> String {
+    let mut info = vec!["info", "image.iso"];
+    info.extend(args);
+    stdout(&success(fixture.run(&info)))
+}
+
+// `ls -l` shows each entry's index, and it's the one `info` and idx: find
+#[test]
+fn listed_indices_find_the_same_entry() {
+    let fixture = Fixture::new();
+    let listing = stdout(&success(fixture.run(&["ls", "-l", "-R", "image.iso"])));
+    let mut checked = 0;
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (index, path) = (fields[1], fields[fields.len() - 1]);
+        let by_path = info(&fixture, &["--path", path]);
+        assert!(by_path.contains(&format!("\nIndex: {}\n", index)), "{}: {}", line, by_path);
+        assert_eq!(info(&fixture, &["--index", index]), by_path);
+        checked += 1;
+    }
+    assert!(checked >= 8, "{}", listing);
+
+    let by_index = stdout(&success(fixture.run(&["ls", "image.iso", "idx:5"])));
+    assert_eq!(by_index, stdout(&success(fixture.run(&["ls", "image.iso", "/data/levels"]))));
+}
+
+#[test]
+fn indices_that_dont_exist() {
+    let fixture = Fixture::new();
+    for args in [&["info", "image.iso", "--index", "500"][..], &["ls", "image.iso", "idx:500"]] {
+        let output = fixture.run(args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains("idx:500"), "{}", stderr(&output));
+    }
+}
+
+const APPLOADER_DATE: usize = 0x2440;
+const COUNTRY_CODE: usize = 0x458;
+
+#[test]
+fn the_provenance_line() {
+    let fixture = Fixture::new();
+    let text = info(&fixture, &[]);
+    assert!(text.contains("Built: unknown · Region: PAL · Disc 1 of unknown · Publisher: Nintendo (01)\n"), "{}", text);
+
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[APPLOADER_DATE..APPLOADER_DATE + 10].copy_from_slice(b"2002/07/19");
+    image[COUNTRY_CODE..COUNTRY_CODE + 4].copy_from_slice(&7u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+    let text = info(&fixture, &[]);
+    assert!(text.contains("Built: 2002-07-19 (apploader SDK date) · Region: unknown · Disc 1 of unknown"), "{}", text);
+    let meta: serde_json::Value = serde_json::from_str(&stdout(&success(fixture.run(&["dump-meta", "image.iso"])))).unwrap();
+    assert_eq!(meta["provenance"]["build_date"], "2002-07-19");
+    assert!(meta["provenance"]["region"].is_null());
+}
+
+#[test]
+fn fst_totals_with_and_without_padding() {
+    let fixture = Fixture::new();
+    let text = info(&fixture, &["--type", "fst"]);
+    assert!(text.contains("Total file size: 1066081"), "{}", text);
+    assert!(text.contains("Alignment the files are at: 32768"), "{}", text);
+    assert!(text.contains("Total with padding to that alignment: 1179648"), "{}", text);
+}
+
+fn json(fixture: &Fixture, args: &[&str]) -> serde_json::Value {
+    let mut with_json = args.to_vec();
+    with_json.push("--json");
+    let text = info(fixture, &with_json);
+    // Numbers are always plain integers
+    with_json.push("--hex");
+    assert_eq!(info(fixture, &with_json), text, "{:?}", args);
+    serde_json::from_str(&text).unwrap()
+}
+
+#[test]
+fn json_for_every_type() {
+    let fixture = Fixture::new();
+    let summary = json(&fixture, &[]);
+    assert_eq!(summary["title"], "gcmod self-test");
+    assert_eq!(summary["game_id"], "GSLF01");
+    assert_eq!(summary["capacity"], 0x5705_8000u64);
+
+    let header = json(&fixture, &["-t", "header"]);
+    assert_eq!(header["game_id"], "GSLF01");
+    assert_eq!(header["dol_offset"], 0x3000);
+    assert_eq!(header["fst_offset"], 0x2800);
+    assert_eq!(header["disc"], 1);
+
+    let dol = json(&fixture, &["-t", "dol"]);
+    assert_eq!(dol["offset"], 0x3000);
+    assert_eq!(dol["entry_point"], 0x8000_3100u32);
+    assert_eq!(dol["text_size"], 32);
+    assert_eq!(dol["segments"].as_array().unwrap().len(), 1);
+
+    let fst = json(&fixture, &["-t", "fst"]);
+    assert_eq!(fst["offset"], 0x2800);
+    assert_eq!(fst["files"], 5);
+    assert_eq!(fst["total_file_size"], 1066081);
+    assert_eq!(fst["alignment"], 0x8000);
+    assert_eq!(fst["total_aligned_size"], 1179648);
+
+    let apploader = json(&fixture, &["-t", "apploader"]);
+    assert_eq!(apploader["offset"], 0x2440);
+    assert!(apploader["size_on_disc"].as_u64().unwrap() <= apploader["size"].as_u64().unwrap(), "{}", apploader);
+
+    let layout = json(&fixture, &["-t", "layout"]);
+    let system: Vec<_> = layout["system"].as_array().unwrap().iter().map(|r| r["start"].as_u64().unwrap()).collect();
+    assert!(system.contains(&0x2800) && system.contains(&0x3000), "{}", layout);
+}
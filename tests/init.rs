@@ -0,0 +1,59 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const DOL: &str = "tree/&&systemdata/Start.dol";
+const APPLOADER: &str = "tree/&&systemdata/Apploader.ldr";
+
+#[test]
+fn a_new_project_rebuilds() {
+    let fixture = Fixture::new();
+    success(fixture.run(&[
+        "init", "new", "--game-code", "GHBE", "--maker-code", "01", "--title", "My Homebrew",
+        "--dol", DOL, "--apploader", APPLOADER,
+    ]));
+    for name in ["ISO.hdr", "Start.dol", "Apploader.ldr", "Game.toc"] {
+        assert!(fixture.path("new/&&systemdata").join(name).is_file(), "{}", name);
+    }
+    assert!(fs::read(fixture.path("new/&&systemdata/Start.dol")).unwrap() == fs::read(fixture.path(DOL)).unwrap());
+
+    fs::write(fixture.path("new/hello.txt"), "hello").unwrap();
+    success(fixture.run(&["rebuild", "new", "new.iso"]));
+    success(fixture.run(&["verify", "new.iso"]));
+    let info = stdout(&success(fixture.run(&["info", "new.iso"])));
+    assert!(info.contains("GHBE") && info.contains("My Homebrew"), "{}", info);
+}
+
+#[test]
+fn without_an_apploader_it_warns() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&[
+        "init", "new", "--game-code", "GHBP", "--maker-code", "01", "--title", "x", "--dol", DOL,
+    ]));
+    assert!(stderr(&output).contains("No apploader given"), "{}", stderr(&output));
+    assert!(fixture.path("new/&&systemdata/Apploader.ldr").is_file());
+}
+
+#[test]
+fn bad_input_creates_nothing() {
+    let fixture = Fixture::new();
+    let init = |code: &str, dol: &str| fixture.run(&[
+        "init", "new", "--game-code", code, "--maker-code", "01", "--title", "x", "--dol", dol,
+    ]);
+    assert!(!init("GHB", DOL).status.success());
+    // Not a DOL
+    assert!(!init("GHBE", "tree/readme.txt").status.success());
+    assert!(!fixture.path("new").exists());
+}
+
+#[test]
+fn only_into_an_empty_directory() {
+    let fixture = Fixture::new();
+    let output = fixture.run(&[
+        "init", "tree", "--game-code", "GHBE", "--maker-code", "01", "--title", "x", "--dol", DOL,
+    ]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("isn't an empty directory"), "{}", stderr(&output));
+}
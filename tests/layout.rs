@@ -0,0 +1,68 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const USER_POSITION: usize = 0x430;
+
+#[test]
+fn the_layout_shows_the_user_area() {
+    let fixture = Fixture::new();
+    let layout = stdout(&success(fixture.run(&["info", "image.iso", "--type", "layout"])));
+    assert!(layout.contains(": User area"), "{}", layout);
+    assert!(!layout.contains("outside of the user area"), "{}", layout);
+}
+
+#[test]
+fn verify_warns_about_files_outside_the_user_area() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    // Only the first 32 KiB past where it starts
+    image[USER_POSITION + 4..USER_POSITION + 8].copy_from_slice(&0x8000u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+
+    let output = fixture.run(&["verify", "image.iso"]);
+    let text = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(text.contains("/data/big.bin is outside of the user area"), "{}", text);
+
+    let layout = stdout(&success(fixture.run(&["info", "image.iso", "--type", "layout"])));
+    assert!(layout.contains("/data/big.bin is outside of the user area"), "{}", layout);
+}
+
+const ROM_SIZE: u64 = 0x5705_8000;
+const DUAL_LAYER_DISC_SIZE: u64 = 0x1_FD7F_8000;
+
+#[test]
+fn offsets_past_retail_size() {
+    let fixture = Fixture::new();
+    let offset = format!("{:#x}", ROM_SIZE + 0x1000);
+    let output = fixture.run(&["info", "image.iso", "--offset", &offset]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid offset"), "{}", stderr(&output));
+
+    // Doesn't take any space, on file systems with sparse files
+    fs::File::options().write(true).open(fixture.image()).unwrap().set_len(DUAL_LAYER_DISC_SIZE).unwrap();
+    let text = stdout(&success(fixture.run(&["info", "image.iso", "--offset", &offset])));
+    assert!(text.contains("free space at the end of the image, after /data/big.bin"), "{}", text);
+    success(fixture.run(&["verify", "image.iso"]));
+}
+
+fn describe(fixture: &Fixture, offset: &str) -> String {
+    stdout(&success(fixture.run(&["info", "image.iso", "--offset", offset, "--hex"])))
+}
+
+#[test]
+fn offsets_between_sections() {
+    let fixture = Fixture::new();
+    assert_eq!(
+        describe(&fixture, "0x2500"),
+        "Offset 0x2500 is in 0x3a0 bytes of padding between Apploader.ldr (ends 0x245f) and Game.toc (starts 0x2800)\n\
+        Distance from the end of Apploader.ldr: 0xa1\n\
+        Distance to the start of Game.toc: 0x300\n",
+    );
+    let slack = describe(&fixture, "0x8050");
+    assert!(slack.starts_with("Offset 0x8050 is in 0x7fc0 bytes of alignment slack after /readme.txt (ends 0x803f), before /data/levels/2.bin (starts 0x10000) on a 0x8000 byte boundary\n"), "{}", slack);
+    let end = describe(&fixture, "0x130000");
+    assert!(end.contains("free space at the end of the image, after /data/big.bin"), "{}", end);
+}
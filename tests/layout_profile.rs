@@ -0,0 +1,51 @@
+mod common;
+
+use std::fs;
+
+use serde_json::Value;
+
+use common::{stderr, stdout, success, Fixture};
+
+const LOG: &str = "\
+    01:02:345 Core/HW/DVD/FileMonitor.cpp:76 I[FileMon]: /data/big.bin\n\
+    01:02:346 Core\\HW\\DVD\\FileMonitor.cpp:76 I[FileMon]: README.TXT\n\
+    01:02:400 Core/HW/DVD/DVDInterface.cpp:398 I[DVDINTERFACE]: Read 0x20 bytes\n\
+    01:02:401 I[FileMon]: /data/big.bin\n\
+    01:02:402 I[FileMon]: gone.bin\n";
+
+// From a log to a rebuilt image with the files read first at the start
+#[test]
+fn importing_a_log_and_rebuilding_with_it() {
+    let fixture = Fixture::new();
+    fs::write(fixture.path("filemon.log"), LOG).unwrap();
+    let output = success(fixture.run(&["layout-profile", "import", "filemon.log", "--out", "order.json", "--rom", "image.iso"]));
+    assert_eq!(stdout(&output), "Wrote the order of 2 files, read 3 times, to order.json\n");
+    let text = stderr(&output);
+    assert!(text.contains("1 of the 4 reads in the log are of files that aren't on the ROM, so they're left out:\n  line 5: /gone.bin"), "{}", text);
+    let profile: Value = serde_json::from_str(&fs::read_to_string(fixture.path("order.json")).unwrap()).unwrap();
+    assert_eq!(profile["files"][0]["path"], "/data/big.bin");
+    assert_eq!(profile["files"][0]["accesses"], 2);
+    // The ROM's case
+    assert_eq!(profile["files"][1]["path"], "/readme.txt");
+
+    success(fixture.run(&["rebuild", "tree", "ordered.iso", "--file-order", "order.json"]));
+    let compared = stdout(&success(fixture.run(&["compare-layout", "image.iso", "ordered.iso", "--format", "json"])));
+    let compared: Value = serde_json::from_str(&compared).unwrap();
+    let mut files: Vec<(u64, &str)> = compared["files"].as_array().unwrap().iter()
+        .filter(|f| f["size_b"] != 0)
+        .map(|f| (f["offset_b"].as_u64().unwrap(), f["path"].as_str().unwrap()))
+        .collect();
+    files.sort();
+    let paths: Vec<&str> = files.iter().map(|&(_, path)| path).collect();
+    assert_eq!(paths, ["/data/big.bin", "/readme.txt", "/data/levels/2.bin", "/data/levels/1.bin"]);
+}
+
+#[test]
+fn logs_without_the_file_monitor() {
+    let fixture = Fixture::new();
+    fs::write(fixture.path("other.log"), "01:02:400 I[DVDINTERFACE]: Read 0x20 bytes\n").unwrap();
+    let output = fixture.run(&["layout-profile", "import", "other.log", "--out", "order.json"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("other.log doesn't have any lines from Dolphin's file monitor (FileMon)"), "{}", stderr(&output));
+    assert!(!fixture.path("order.json").exists());
+}
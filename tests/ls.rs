@@ -0,0 +1,69 @@
+mod common;
+
+use common::{stderr, stdout, success, Fixture};
+
+fn ls(fixture: &Fixture, args: &[&str]) -> String {
+    let mut ls = vec!["ls", "image.iso"];
+    ls.extend(args);
+    stdout(&success(fixture.run(&ls)))
+}
+
+fn ls_error(fixture: &Fixture, path: &str) -> String {
+    let output = fixture.run(&["ls", "image.iso", path]);
+    assert!(!output.status.success(), "{}", path);
+    stderr(&output)
+}
+
+#[test]
+fn files_are_listed_on_their_own() {
+    let fixture = Fixture::new();
+    assert_eq!(ls(&fixture, &["/readme.txt"]), "/readme.txt\n");
+    assert_eq!(ls(&fixture, &["-l", "/readme.txt"]), "-        1         64 /readme.txt\n");
+    assert_eq!(ls(&fixture, &["/"]), ls(&fixture, &[]));
+    assert_eq!(ls(&fixture, &["-d", "/data/levels"]), "/data/levels/\n");
+    assert_eq!(ls(&fixture, &["/data/levels"]), "/data/levels/2.bin\n/data/levels/1.bin\n");
+}
+
+#[test]
+fn paths_that_dont_lead_anywhere() {
+    let fixture = Fixture::new();
+    let text = ls_error(&fixture, "/readme.txt/");
+    assert!(text.contains("/readme.txt/ doesn't exist: /readme.txt is a file, not a directory (64 bytes at 0x8000)"), "{}", text);
+    let text = ls_error(&fixture, "/data/big.bin/inside");
+    assert!(text.contains("/data/big.bin is a file, not a directory"), "{}", text);
+    let text = ls_error(&fixture, "/data/levls/1.bin");
+    assert!(text.contains("/data/levls/1.bin doesn't exist: /data has nothing called levls"), "{}", text);
+    let text = ls_error(&fixture, "/audio");
+    assert!(text.contains("/audio doesn't exist: / has nothing called audio"), "{}", text);
+    let text = ls_error(&fixture, "huge.bin");
+    assert!(text.contains("Nothing on the ROM is called huge.bin"), "{}", text);
+    let text = ls_error(&fixture, "idx:500");
+    assert!(text.contains("There's no entry idx:500"), "{}", text);
+}
+
+fn records(fixture: &Fixture, args: &[&str]) -> Vec<serde_json::Value> {
+    let mut with_json = vec!["--json"];
+    with_json.extend(args);
+    ls(fixture, &with_json).lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+#[test]
+fn json_records() {
+    let fixture = Fixture::new();
+    let levels = records(&fixture, &["/data/levels"]);
+    assert_eq!(levels, [
+        serde_json::json!({ "index": 6, "name": "2.bin", "path": "/data/levels/2.bin", "type": "file", "size": 4096, "offset": 0x10000 }),
+        serde_json::json!({ "index": 7, "name": "1.bin", "path": "/data/levels/1.bin", "type": "file", "size": 1000, "offset": 0x18000 }),
+    ]);
+    let dir = records(&fixture, &["-d", "/data/levels"]);
+    assert_eq!(dir, [serde_json::json!({ "index": 5, "name": "levels", "path": "/data/levels", "type": "directory", "size": null, "offset": null })]);
+    assert_eq!(records(&fixture, &["/readme.txt"])[0]["offset"], 0x8000);
+
+    // One line for everything, the same ones the text listing has
+    let all = records(&fixture, &["-R"]);
+    let paths: Vec<&str> = all.iter().map(|r| r["path"].as_str().unwrap()).collect();
+    assert_eq!(paths.len(), ls(&fixture, &["-R"]).lines().count());
+    assert!(paths.contains(&"/data/levels/1.bin") && paths.contains(&"/empty"), "{:?}", paths);
+    let top = records(&fixture, &[]);
+    assert!(top.len() < all.len() && top.iter().all(|r| r["path"].as_str().unwrap().matches('/').count() == 1), "{:?}", top);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+// The synthetic DOL's one segment, 0x20 bytes starting with a branch
+const DOL_OFFSET: usize = 0x3000;
+const BSS_ADDRESS: usize = DOL_OFFSET + 0xd8;
+
+fn read(fixture: &Fixture, addr: &str, n: &str) -> String {
+    stdout(&success(fixture.run(&["info", "image.iso", "--mem-addr", addr, "--read", n])))
+}
+
+#[test]
+fn reading_at_an_address() {
+    let fixture = Fixture::new();
+    let text = read(&fixture, "0x80003100", "4");
+    assert!(text.contains("Offset from start of segment: 0\n"), "{}", text);
+    assert!(text.contains("\n80003100: 48 00 00 00 "), "{}", text);
+    assert!(text.contains("u32: 1207959552 (0x48000000)\ni32: 1207959552\nf32: "), "{}", text);
+
+    // Not on a row's boundary
+    let text = read(&fixture, "0x80003101", "8");
+    assert!(text.contains("\n80003100:    00 00 00 00 00 00 00  00 "), "{}", text);
+    assert!(text.contains("u64: 0 (0x0000000000000000)\ni64: 0\nf64: 0.0"), "{}", text);
+
+    // Or not a number
+    let text = read(&fixture, "0x80003100", "3");
+    assert!(!text.contains("u32"), "{}", text);
+}
+
+#[test]
+fn reads_stop_at_the_end_of_the_segment() {
+    let fixture = Fixture::new();
+    let text = read(&fixture, "0x8000311c", "4");
+    assert!(text.contains("u32: 0 (0x00000000)"), "{}", text);
+    let text = read(&fixture, "0x8000311c", "8");
+    assert!(text.contains("The segment ends after 4 bytes."), "{}", text);
+    assert!(!text.contains("u64"), "{}", text);
+}
+
+#[test]
+fn addresses_in_the_bss_or_nowhere() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[BSS_ADDRESS..BSS_ADDRESS + 4].copy_from_slice(&0x8000_4000u32.to_be_bytes());
+    image[BSS_ADDRESS + 4..BSS_ADDRESS + 8].copy_from_slice(&0x100u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+
+    let text = read(&fixture, "0x80004010", "4");
+    assert_eq!(text, "0x80004010 is zero-initialized at runtime (BSS), no file bytes\n");
+    let output = fixture.run(&["info", "image.iso", "--mem-addr", "0x80004100"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No DOL segment will be loaded at this address"), "{}", stderr(&output));
+}
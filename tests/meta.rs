@@ -0,0 +1,121 @@
+mod common;
+
+use std::{env, fs, path::Path};
+
+use serde_json::Value;
+
+use common::{stderr, stdout, success, Fixture};
+
+const GOLDEN: &str = "tests/golden/synthetic-meta.json";
+
+// What changes from one build of gcmod to the next, taken out so the golden
+// file doesn't have to
+fn without_build_info(mut doc: Value) -> Value {
+    for field in ["gcmod_version", "gcmod_git_hash", "gcmod_features"] {
+        doc[field] = Value::Null;
+    }
+    doc
+}
+
+fn dump(fixture: &Fixture) -> Value {
+    serde_json::from_str(&stdout(&success(fixture.run(&["dump-meta", "image.iso"])))).unwrap()
+}
+
+// GCMOD_BLESS=1 writes the golden file, after a change to the schema
+#[test]
+fn dump_meta_matches_the_golden_file() {
+    let doc = without_build_info(dump(&Fixture::new()));
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN);
+    if env::var_os("GCMOD_BLESS").is_some() {
+        fs::write(&golden, serde_json::to_string_pretty(&doc).unwrap() + "\n").unwrap();
+    }
+    let expected: Value = serde_json::from_str(&fs::read_to_string(&golden).unwrap()).unwrap();
+    assert!(doc == expected, "dump-meta changed, see {}:\n{}", GOLDEN, serde_json::to_string_pretty(&doc).unwrap());
+}
+
+#[test]
+fn layout_ends_are_exclusive() {
+    let doc = dump(&Fixture::new());
+    let regions = doc["layout"].as_array().unwrap();
+    let header = regions.iter().find(|r| r["name"] == "ISO.hdr").unwrap();
+    assert_eq!((header["start"].as_u64(), header["end"].as_u64()), (Some(0), Some(0x2440)));
+
+    // Nothing overlaps, and the gaps are exactly what's between the rest
+    let mut spans: Vec<(u64, u64)> = regions.iter()
+        .chain(doc["gaps"].as_array().unwrap())
+        .map(|r| (r["start"].as_u64().unwrap(), r["end"].as_u64().unwrap()))
+        .filter(|(start, end)| start != end)
+        .collect();
+    spans.sort();
+    for pair in spans.windows(2) {
+        assert!(pair[0].1 <= pair[1].0, "{:?}", pair);
+    }
+}
+
+const HEADER_SIZE: usize = 0x2440;
+const TITLE: usize = 0x20;
+const COUNTRY_CODE: usize = 0x458;
+
+fn apply(fixture: &Fixture, doc: &Value) -> std::process::Output {
+    fs::write(fixture.path("meta.json"), serde_json::to_string(doc).unwrap()).unwrap();
+    fixture.run(&["apply-meta", "image.iso", "meta.json"])
+}
+
+#[test]
+fn applying_only_changes_what_was_edited() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    let mut doc = dump(&fixture);
+    doc["header"]["title"] = "Edited".into();
+    doc["header"]["information"]["country_code"] = 1.into();
+    success(apply(&fixture, &doc));
+
+    // Along with what's worked out from them
+    doc["provenance"]["region"] = "NTSC-U".into();
+    doc["titles"]["header"]["text"] = "Edited".into();
+    assert!(without_build_info(dump(&fixture)) == without_build_info(doc), "more than the title and region changed");
+    let after = fs::read(fixture.image()).unwrap();
+    assert!(after[HEADER_SIZE..] == before[HEADER_SIZE..], "something past the header changed");
+    let changed: Vec<usize> = (0..HEADER_SIZE).filter(|&i| after[i] != before[i]).collect();
+    assert!(
+        changed.iter().all(|&i| (TITLE..TITLE + 0x3e0).contains(&i) || (COUNTRY_CODE..COUNTRY_CODE + 4).contains(&i)),
+        "{:x?}", changed,
+    );
+    assert_eq!(&after[TITLE..TITLE + 7], b"Edited\0");
+    assert_eq!(after[COUNTRY_CODE..COUNTRY_CODE + 4], 1u32.to_be_bytes());
+}
+
+#[test]
+fn the_file_system_is_only_informational() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    let mut doc = dump(&fixture);
+    doc["fst"]["file_count"] = 100.into();
+    doc["header"]["fst_offset"] = 0x10000.into();
+    let output = success(apply(&fixture, &doc));
+    assert!(stdout(&output).contains("Ignoring changes to header offsets and sizes"), "{}", stdout(&output));
+    assert!(fs::read(fixture.image()).unwrap() == before);
+}
+
+#[test]
+fn documents_it_cant_read_change_nothing() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    let doc = dump(&fixture);
+
+    let mut newer = doc.clone();
+    newer["schema_version"] = 1000.into();
+    newer["header"]["title"] = "Edited".into();
+    let mut unknown_field = doc.clone();
+    unknown_field["colour"] = "blue".into();
+    unknown_field["header"]["title"] = "Edited".into();
+    let mut invalid = doc;
+    invalid["header"]["game_code"] = "TOO LONG".into();
+
+    for (doc, error) in [(newer, "unsupported schema version 1000"), (unknown_field, "colour"), (invalid, "game code")] {
+        let output = apply(&fixture, &doc);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains(error), "{}", stderr(&output));
+        assert!(fs::read(fixture.image()).unwrap() == before);
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const NKIT_STAMP: usize = 0x200;
+
+fn nkit_fixture() -> Fixture {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[NKIT_STAMP..NKIT_STAMP + 7].copy_from_slice(b"NKITv01");
+    fs::write(fixture.image(), image).unwrap();
+    fixture
+}
+
+#[test]
+fn info_says_its_an_nkit_image() {
+    let fixture = nkit_fixture();
+    let text = stdout(&success(fixture.run(&["info", "image.iso"])));
+    assert!(text.contains("Notice: This is an NKit image (v01)."), "{}", text);
+    let text = stdout(&success(Fixture::new().run(&["info", "image.iso"])));
+    assert!(!text.contains("NKit"), "{}", text);
+}
+
+#[test]
+fn extracting_and_rebuilding_warn_unless_acknowledged() {
+    let fixture = nkit_fixture();
+    let output = success(fixture.run(&["extract", "image.iso", "out"]));
+    assert!(stderr(&output).contains("This is an NKit image (v01). NKit moved its files"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Pass --acknowledge-nkit to not be warned."), "{}", stderr(&output));
+    let output = success(fixture.run(&["extract", "image.iso", "quiet", "--acknowledge-nkit"]));
+    assert!(!stderr(&output).contains("NKit"), "{}", stderr(&output));
+
+    // The tree has the stamp in its ISO.hdr
+    let output = success(fixture.run(&["rebuild", "out", "rebuilt.iso"]));
+    assert!(stderr(&output).contains("The tree's ISO.hdr says: This is an NKit image (v01)."), "{}", stderr(&output));
+    let output = success(fixture.run(&["rebuild", "out", "quiet.iso", "--acknowledge-nkit"]));
+    assert!(!stderr(&output).contains("NKit"), "{}", stderr(&output));
+}
+
+#[test]
+fn acknowledging_in_the_config() {
+    let fixture = nkit_fixture();
+    fs::write(fixture.path(".gcmod.toml"), "[extract]\nacknowledge-nkit = true\n[rebuild]\nacknowledge-nkit = true\n").unwrap();
+    let output = success(fixture.run(&["extract", "image.iso", "out"]));
+    assert!(!stderr(&output).contains("NKit"), "{}", stderr(&output));
+    let output = success(fixture.run(&["rebuild", "out", "rebuilt.iso"]));
+    assert!(!stderr(&output).contains("NKit"), "{}", stderr(&output));
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{stderr, stdout, success, Fixture};
+
+// What each command prints when it isn't run in a terminal, which is what
+// other tools see
+fn output(fixture: &Fixture, args: &[&str]) -> String {
+    let output = success(fixture.run(args));
+    assert_eq!(stderr(&output), "", "{:?}", args);
+    let text = stdout(&output);
+    assert!(!text.contains('\r'), "{:?}: {:?}", args, text);
+    text
+}
+
+#[test]
+fn extract_and_rebuild() {
+    let fixture = Fixture::new();
+    assert_eq!(
+        output(&fixture, &["extract", "image.iso", "out"]),
+        "Extracting system data...\n\
+        Extracting file system...\n\
+        5/9 files written.\n\
+        6/9 files written.\n\
+        7/9 files written.\n\
+        8/9 files written.\n\
+        9/9 files written.\n",
+    );
+    let added: String = (1..=9).map(|i| format!("{}/9 files added.\n", i)).collect();
+    assert_eq!(
+        output(&fixture, &["rebuild", "out", "rebuilt.iso"]),
+        added + " 0% of space filled (1191993/1459978240 bytes).\n",
+    );
+}
+
+#[test]
+fn no_progress_and_quiet() {
+    let fixture = Fixture::new();
+    assert_eq!(
+        output(&fixture, &["extract", "image.iso", "out", "--no-progress"]),
+        "Extracting system data...\nExtracting file system...\n",
+    );
+    assert_eq!(output(&fixture, &["extract", "image.iso", "quiet", "--quiet"]), "");
+}
+
+#[test]
+fn ls_and_info() {
+    let fixture = Fixture::new();
+    assert_eq!(output(&fixture, &["ls", "image.iso"]), "/readme.txt\n/empty/\n/empty.bin\n/data/\n");
+    assert_eq!(
+        output(&fixture, &["info", "image.iso"]),
+        "Title: gcmod self-test\n\
+        GameID: GSLF01\n\
+        Version: 0\n\
+        Built: unknown · Region: PAL · Disc 1 of unknown · Publisher: Nintendo (01)\n\
+        Trimmed image: 4 MiB of 1392 MiB\n\
+        \n\
+        ROM Layout:\n\
+        0x00000000-0x00002440: ISO.hdr\n\
+        0x00002440-0x00002460: Apploader.ldr\n\
+        0x00002800-0x000028a7: Game.toc\n\
+        0x00003000-0x00003120: Start.dol\n\
+        0x00008000-0x00128000: User area\n\
+        Unused: 1458902392 bytes in 7 gaps\n",
+    );
+}
@@ -0,0 +1,18 @@
+mod common;
+
+use common::{stdout, success, Fixture};
+
+#[test]
+fn probing_for_paths() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(fixture.run(&["probe", "image.iso", "/readme.txt"])));
+    assert_eq!(text, "Found /readme.txt: file 1 at 0x00008000, 64 bytes\n");
+    let text = stdout(&success(fixture.run(&["probe", "image.iso", "/data/levels"])));
+    assert_eq!(text, "Found /data/levels: directory 5\n");
+
+    for path in ["/data/levels/3.bin", "/readme.txt/inside", "/README.TXT"] {
+        let output = fixture.run(&["probe", "image.iso", path]);
+        assert_eq!(output.status.code(), Some(3), "{}", path);
+        assert_eq!(stdout(&output), format!("Not found: {}\n", path));
+    }
+}
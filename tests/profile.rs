@@ -0,0 +1,44 @@
+mod common;
+
+use std::{fs::File, io::BufReader};
+
+use gcmod::{profiling, report::Reporter, ExtractOptions, Game};
+
+use common::{stderr, success, Fixture};
+
+// The only test here that turns profiling on, since it's for the whole process
+#[test]
+fn extracting_records_each_operation() {
+    let fixture = Fixture::new();
+    let collector = profiling::collect().unwrap();
+    assert!(profiling::enabled());
+    assert!(profiling::collect().is_none());
+
+    let mut iso = BufReader::new(File::open(fixture.image()).unwrap());
+    let mut game = Game::open(&mut iso, 0).unwrap();
+    let failures = game.extract(&mut iso, fixture.path("out"), &ExtractOptions::default(), &Reporter::quiet()).unwrap();
+    assert!(failures.is_empty());
+
+    let totals = collector.totals();
+    let names: Vec<&str> = totals.keys().copied().collect();
+    for name in ["fst_parse", "copy <64KiB", "copy 1MiB-16MiB"] {
+        assert!(names.contains(&name), "{:?}", names);
+    }
+    // readme.txt, 2.bin and 1.bin, and empty.bin
+    assert_eq!(totals["copy <64KiB"].calls, 4);
+    assert_eq!(totals["copy <64KiB"].bytes, 64 + 4096 + 1000);
+    assert_eq!(totals["copy 1MiB-16MiB"].bytes, 1060921);
+}
+
+#[test]
+fn profile_tables() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["--profile", "rebuild", "tree", "rebuilt.iso"]));
+    let table = stderr(&output);
+    assert!(table.contains("\nOperation "), "{}", table);
+    for name in ["layout_plan", "zero_fill", "header_write", "copy <64KiB"] {
+        assert!(table.lines().any(|l| l.starts_with(name)), "{}: {}", name, table);
+    }
+    let output = fixture.run(&["--profile", "--progress-json", "ls", "image.iso"]);
+    assert!(!output.status.success());
+}
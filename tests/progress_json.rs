@@ -0,0 +1,178 @@
+mod common;
+
+use std::{fs, process::Output};
+
+use serde_json::{Map, Value};
+
+use common::{stderr, success, Fixture};
+
+const PHASES: &[&str] = &["spool", "system_data", "extract", "check", "hash", "write", "update", "verify", "scan"];
+
+fn field<'a>(event: &'a Map<String, Value>, name: &str) -> &'a Value {
+    event.get(name).unwrap_or_else(|| panic!("no {} in {:?}", name, event))
+}
+
+fn check_fields(event: &Map<String, Value>, required: &[&str], optional: &[&str]) {
+    for name in required {
+        field(event, name);
+    }
+    for name in event.keys() {
+        assert!(name == "event" || required.contains(&name.as_str()) || optional.contains(&name.as_str()), "{} in {:?}", name, event);
+    }
+}
+
+fn check_count(event: &Map<String, Value>, name: &str) -> u64 {
+    field(event, name).as_u64().unwrap_or_else(|| panic!("{} isn't a count in {:?}", name, event))
+}
+
+// Every line of what `output` sent to stderr checked against the schema in
+// the README, along with what the command's progress said. Gives back the
+// events, in order.
+fn check_events(output: &Output) -> Vec<Map<String, Value>> {
+    let events: Vec<Map<String, Value>> = stderr(output).lines()
+        .map(|line| match serde_json::from_str(line) {
+            Ok(Value::Object(event)) => event,
+            _ => panic!("not a JSON object: {:?}", line),
+        })
+        .collect();
+    assert!(events.len() >= 2, "{:?}", events);
+
+    let mut phase = None;
+    let mut last = (0, 0, 0);
+    let mut warnings = 0;
+    for (i, event) in events.iter().enumerate() {
+        let kind = field(event, "event").as_str().unwrap();
+        assert_eq!(kind == "start", i == 0, "{:?}", events);
+        assert_eq!(kind == "done", i == events.len() - 1, "{:?}", events);
+        match kind {
+            "start" => {
+                check_fields(event, &["schema_version", "command", "gcmod_version"], &[]);
+                assert_eq!(check_count(event, "schema_version"), 1);
+                assert!(field(event, "command").is_string() && field(event, "gcmod_version").is_string());
+            },
+            "phase" => {
+                check_fields(event, &["phase"], &[]);
+                let name = field(event, "phase").as_str().unwrap();
+                assert!(PHASES.contains(&name), "{:?}", event);
+                phase = Some(name);
+            },
+            "progress" => {
+                check_fields(event, &["phase", "current", "total"], &["bytes", "total_bytes", "path"]);
+                assert_eq!(field(event, "phase").as_str(), phase, "progress outside of its phase");
+                let (current, total) = (check_count(event, "current"), check_count(event, "total"));
+                assert!(current <= total, "{:?}", event);
+                // The done event keeps the last bytes that were known
+                let bytes = event.get("bytes").map_or(last.2, |_| check_count(event, "bytes"));
+                if event.contains_key("total_bytes") {
+                    assert!(bytes <= check_count(event, "total_bytes"), "{:?}", event);
+                }
+                if let Some(path) = event.get("path") {
+                    assert!(path.as_str().unwrap().starts_with('/'), "{:?}", event);
+                }
+                last = (current, total, bytes);
+            },
+            "warning" => {
+                check_fields(event, &["message"], &[]);
+                assert!(field(event, "message").is_string());
+                warnings += 1;
+            },
+            "done" => {
+                check_fields(event, &["ok", "stats"], &["error"]);
+                let ok = field(event, "ok").as_bool().unwrap();
+                assert_eq!(ok, output.status.success(), "{:?}", event);
+                assert_eq!(event.contains_key("error"), !ok, "{:?}", event);
+                let stats = field(event, "stats").as_object().unwrap();
+                check_fields(stats, &["elapsed_ms", "warnings", "current", "total", "bytes"], &[]);
+                check_count(stats, "elapsed_ms");
+                assert_eq!(check_count(stats, "warnings"), warnings);
+                let counts = (check_count(stats, "current"), check_count(stats, "total"), check_count(stats, "bytes"));
+                assert_eq!(counts, last, "{:?}", event);
+            },
+            _ => panic!("unknown event {:?}", event),
+        }
+    }
+    events
+}
+
+fn run(fixture: &Fixture, args: &[&str]) -> (Output, Vec<Map<String, Value>>) {
+    let mut with_json = vec!["--progress-json"];
+    with_json.extend(args);
+    let output = fixture.run(&with_json);
+    let events = check_events(&output);
+    (output, events)
+}
+
+fn phases(events: &[Map<String, Value>]) -> Vec<&str> {
+    events.iter().filter(|e| e["event"] == "phase").map(|e| e["phase"].as_str().unwrap()).collect()
+}
+
+fn last_progress(events: &[Map<String, Value>]) -> &Map<String, Value> {
+    events.iter().rev().find(|e| e["event"] == "progress").unwrap()
+}
+
+#[test]
+fn extracting() {
+    let fixture = Fixture::new();
+    let (output, events) = run(&fixture, &["extract", "image.iso", "out"]);
+    success(output);
+    assert_eq!(events[0]["command"], "extract");
+    assert_eq!(phases(&events), ["system_data", "extract"]);
+    let last = last_progress(&events);
+    assert_eq!((&last["current"], &last["total"]), (&Value::from(9), &Value::from(9)));
+    assert_eq!(last["total_bytes"], 1066081);
+
+    // The error ends up in the done event too
+    let (output, events) = run(&fixture, &["extract", "image.iso", "out"]);
+    assert!(!output.status.success());
+    assert!(events.last().unwrap()["error"].as_str().unwrap().contains("out"), "{:?}", events);
+}
+
+#[test]
+fn rebuilding() {
+    let fixture = Fixture::new();
+    for (name, jobs) in [("one.iso", "1"), ("three.iso", "3")] {
+        let (output, events) = run(&fixture, &["rebuild", "tree", name, "--jobs", jobs, "--verify-after=full"]);
+        success(output);
+        let phases = phases(&events);
+        assert!(phases.contains(&"write") && phases.ends_with(&["verify"]), "{:?}", phases);
+    }
+    assert!(fs::read(fixture.path("one.iso")).unwrap() == fs::read(fixture.path("three.iso")).unwrap());
+}
+
+#[test]
+fn warnings_are_events() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    let header = fixture.path("out/&&systemdata/ISO.hdr");
+    let mut bytes = fs::read(&header).unwrap();
+    bytes[..4].copy_from_slice(b"GMSE");
+    fs::write(&header, bytes).unwrap();
+
+    let (output, events) = run(&fixture, &["rebuild", "out", "mixed.iso"]);
+    assert!(!output.status.success());
+    assert!(events.last().unwrap()["error"].as_str().unwrap().contains("--allow-mixed"), "{:?}", events);
+
+    let (output, events) = run(&fixture, &["rebuild", "out", "mixed.iso", "--allow-mixed"]);
+    success(output);
+    let warning = events.iter().find(|e| e["event"] == "warning").unwrap();
+    assert!(warning["message"].as_str().unwrap().contains("GMSE01"), "{:?}", warning);
+}
+
+#[test]
+fn other_commands() {
+    let fixture = Fixture::new();
+    for args in [&["verify", "image.iso"][..], &["ls", "image.iso"], &["scrub", "image.iso", "scrubbed.iso"]] {
+        let (output, events) = run(&fixture, args);
+        success(output);
+        assert_eq!(events[0]["command"], args[0]);
+    }
+    let (output, events) = run(&fixture, &["info", "image.iso", "--index", "500"]);
+    assert!(!output.status.success());
+    assert!(events.last().unwrap()["error"].as_str().unwrap().contains("idx:500"), "{:?}", events);
+
+    // Mistakes on the command line are events too
+    let (output, events) = run(&fixture, &["scrub", "image.iso"]);
+    assert!(!output.status.success());
+    assert_eq!(events[0]["command"], "scrub");
+    assert!(events[1]["error"].as_str().unwrap().contains("<output>"), "{:?}", events);
+}
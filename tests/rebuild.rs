@@ -0,0 +1,155 @@
+mod common;
+
+use std::{fs, process::Output};
+
+use common::{stderr, stdout, success, Fixture};
+
+const README_OFFSET: usize = 0x8000;
+
+fn text(output: &Output) -> String {
+    format!("{}{}", stdout(output), stderr(output))
+}
+
+fn rebuild(fixture: &Fixture, args: &[&str]) -> Output {
+    let mut rebuild = vec!["rebuild", "tree"];
+    rebuild.extend(args);
+    fixture.run(&rebuild)
+}
+
+#[test]
+fn verifying_a_clean_rebuild() {
+    let fixture = Fixture::new();
+    for (name, mode) in [("plan.iso", "--verify-after"), ("full.iso", "--verify-after=full")] {
+        let output = success(rebuild(&fixture, &[name, mode]));
+        assert!(stdout(&output).contains("No problems found."), "{}: {}", mode, text(&output));
+    }
+    let output = rebuild(&fixture, &["other.iso", "--verify-after=some"]);
+    assert!(!output.status.success());
+    assert!(!fixture.path("other.iso").exists());
+}
+
+#[test]
+fn full_verification_compares_contents() {
+    let fixture = Fixture::new();
+    success(rebuild(&fixture, &["rebuilt.iso"]));
+    let mut image = fs::read(fixture.path("rebuilt.iso")).unwrap();
+    let readme = fs::read(fixture.path("tree/readme.txt")).unwrap();
+    assert!(image[README_OFFSET..README_OFFSET + readme.len()] == readme[..]);
+    image[README_OFFSET + 10] ^= 0xff;
+    fs::write(fixture.path("rebuilt.iso"), &image).unwrap();
+
+    // Nothing in the tree changed, so updating leaves the corrupt file
+    // alone. The plan still matches.
+    success(rebuild(&fixture, &["--update", "rebuilt.iso", "--verify-after"]));
+    let output = rebuild(&fixture, &["--update", "rebuilt.iso", "--verify-after=full"]);
+    assert!(!output.status.success());
+    let text = text(&output);
+    assert!(text.contains("Contents") && text.contains("/readme.txt"), "{}", text);
+    assert!(text.contains("it was left at rebuilt.iso"), "{}", text);
+    // Left there to look at
+    assert!(fs::read(fixture.path("rebuilt.iso")).unwrap() == image);
+}
+
+#[test]
+fn jobs_dont_change_the_rom() {
+    let fixture = Fixture::new();
+    success(rebuild(&fixture, &["one.iso", "--jobs", "1"]));
+    success(rebuild(&fixture, &["four.iso", "--jobs", "4"]));
+    assert!(fs::read(fixture.path("one.iso")).unwrap() == fs::read(fixture.path("four.iso")).unwrap());
+    let output = rebuild(&fixture, &["zero.iso", "--jobs", "0"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn trees_extracted_from_another_game_need_allow_mixed() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    let header = fixture.path("out/&&systemdata/ISO.hdr");
+    let mut bytes = fs::read(&header).unwrap();
+    bytes[..4].copy_from_slice(b"GMSE");
+    fs::write(&header, bytes).unwrap();
+
+    let output = fixture.run(&["rebuild", "out", "mixed.iso"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Pass --allow-mixed"), "{}", stderr(&output));
+    assert!(!fixture.path("mixed.iso").exists());
+
+    let output = success(fixture.run(&["rebuild", "out", "mixed.iso", "--allow-mixed"]));
+    assert!(stderr(&output).contains("extracted from GSLF01, but its ISO.hdr is for GMSE01"), "{}", stderr(&output));
+}
+
+#[test]
+fn building_from_a_plan() {
+    let fixture = Fixture::new();
+    success(rebuild(&fixture, &["direct.iso"]));
+    success(rebuild(&fixture, &["--plan-out", "plan.json", "--plan-hashes"]));
+    // Only the plan, without a ROM
+    assert_eq!(fixture.names(), ["direct.iso", "image.iso", "plan.json", "tree"]);
+    success(rebuild(&fixture, &["--plan-in", "plan.json", "planned.iso"]));
+    assert!(fs::read(fixture.path("direct.iso")).unwrap() == fs::read(fixture.path("planned.iso")).unwrap());
+
+    fs::write(fixture.path("tree/data/levels/1.bin"), [0; 10]).unwrap();
+    let output = rebuild(&fixture, &["--plan-in", "plan.json", "changed.iso"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("data/levels/1.bin is 10 bytes, but was planned as 1000"), "{}", stderr(&output));
+    assert!(!fixture.path("changed.iso").exists());
+}
+
+#[test]
+fn the_existing_fst_has_to_match_the_tree() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    fs::remove_file(fixture.path("out/readme.txt")).unwrap();
+    fs::write(fixture.path("out/new.bin"), b"new").unwrap();
+    let output = fixture.run(&["rebuild", "out", "kept.iso", "--no-rebuild-fst", "--prune-missing"]);
+    assert!(!output.status.success());
+    let text = stderr(&output);
+    assert!(text.contains("Missing from the tree (1):\n            /readme.txt\n"), "{}", text);
+    assert!(text.contains("Not in the FST (1):\n            /new.bin"), "{}", text);
+
+    fs::remove_file(fixture.path("out/new.bin")).unwrap();
+    let output = success(fixture.run(&["rebuild", "out", "kept.iso", "--no-rebuild-fst", "--prune-missing", "--verify-after"]));
+    assert!(stderr(&output).contains("/readme.txt isn't in the tree, so it's left empty"), "{}", stderr(&output));
+    let listing = stdout(&success(fixture.run(&["ls", "-l", "kept.iso"])));
+    assert!(listing.contains("          0 /readme.txt"), "{}", listing);
+}
+
+#[test]
+fn syncing_the_output() {
+    let fixture = Fixture::new();
+    success(rebuild(&fixture, &["none.iso"]));
+    for mode in ["data", "full"] {
+        let name = format!("{}.iso", mode);
+        success(rebuild(&fixture, &[&name, "--sync", mode]));
+        assert!(fs::read(fixture.path(&name)).unwrap() == fs::read(fixture.path("none.iso")).unwrap());
+    }
+    assert!(!rebuild(&fixture, &["other.iso", "--sync", "some"]).status.success());
+    assert!(fixture.names().iter().all(|n| !n.contains("tmp") && n != "other.iso"), "{:?}", fixture.names());
+}
+
+#[test]
+fn sparse_gaps_dont_change_the_rom() {
+    let fixture = Fixture::new();
+    success(rebuild(&fixture, &["sparse.iso"]));
+    success(rebuild(&fixture, &["written.iso", "--no-sparse-gaps"]));
+    success(rebuild(&fixture, &["jobs.iso", "--jobs", "4"]));
+    let sparse = fs::read(fixture.path("sparse.iso")).unwrap();
+    assert!(sparse == fs::read(fixture.path("written.iso")).unwrap());
+    assert!(sparse == fs::read(fixture.path("jobs.iso")).unwrap());
+}
+
+#[test]
+fn alignments_have_to_be_multiples_of_4() {
+    let fixture = Fixture::new();
+    for (flag, value) in [("--file-alignment", "6"), ("--file-alignment", "0"), ("--system-alignment", "2050")] {
+        let output = rebuild(&fixture, &["bad.iso", flag, value]);
+        assert!(!output.status.success());
+        let text = stderr(&output);
+        assert!(text.contains(&format!("Invalid alignment {} from {}", value, flag)), "{}", text);
+        assert!(!fixture.path("bad.iso").exists());
+    }
+
+    let output = success(rebuild(&fixture, &["odd.iso", "-a", "12", "--verify-after"]));
+    assert!(stderr(&output).contains("The file alignment 12 isn't a power of two"), "{}", stderr(&output));
+    assert!(stdout(&output).contains("No problems found."), "{}", text(&output));
+}
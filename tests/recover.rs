@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const ROOT_NEXT_INDEX: usize = 0x2808;
+
+// The synthetic image with a root that leaves out /data/big.bin, the last
+// entry, so it has to be recovered to read
+fn undercounted(fixture: &Fixture) -> Vec<u8> {
+    let mut image = fs::read(fixture.image()).unwrap();
+    assert_eq!(image[ROOT_NEXT_INDEX..ROOT_NEXT_INDEX + 4], 9u32.to_be_bytes());
+    image[ROOT_NEXT_INDEX..ROOT_NEXT_INDEX + 4].copy_from_slice(&8u32.to_be_bytes());
+    fs::write(fixture.image(), &image).unwrap();
+    image
+}
+
+#[test]
+fn recovered_fsts_are_malformed() {
+    let fixture = Fixture::new();
+    undercounted(&fixture);
+    let output = success(fixture.run(&["ls", "image.iso", "/data"]));
+    assert!(stdout(&output).contains("/data/big.bin"), "{}", stdout(&output));
+    assert!(stderr(&output).contains("The file system table is malformed"), "{}", stderr(&output));
+
+    let output = fixture.run(&["verify", "image.iso"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("The root says there are 8 entries, but there are 9"), "{}", stdout(&output));
+
+    // Rebuilt, it's nested the usual way
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    success(fixture.run(&["rebuild", "out", "rebuilt.iso"]));
+    let output = success(fixture.run(&["verify", "rebuilt.iso", "--strict-fst"]));
+    assert!(!stderr(&output).contains("malformed"), "{}", stderr(&output));
+}
+
+#[test]
+fn recovered_fsts_cant_be_changed_in_place() {
+    let fixture = Fixture::new();
+    let image = undercounted(&fixture);
+    fs::write(fixture.path("new.bin"), b"new").unwrap();
+    fs::write(fixture.path("mapping.txt"), "/data/levels/1.bin = new.bin\n").unwrap();
+    for args in [&["grow-fst", "image.iso", "--headroom", "64K"][..], &["replace-batch", "image.iso", "mapping.txt"]] {
+        let output = fixture.run(args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains("can't be changed in place"), "{}", stderr(&output));
+        assert!(fs::read(fixture.image()).unwrap() == image, "{:?}", args);
+    }
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+// The fixture extracted to "out", and a new revision of its image in
+// "rev2.iso" with a file changed, one added, and one removed
+fn two_revisions() -> Fixture {
+    let fixture = Fixture::new();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    fs::write(fixture.path("tree/data/levels/1.bin"), b"changed").unwrap();
+    fs::write(fixture.path("tree/new.bin"), b"new").unwrap();
+    fs::remove_file(fixture.path("tree/readme.txt")).unwrap();
+    success(fixture.run(&["rebuild", "tree", "rev2.iso"]));
+    fixture
+}
+
+fn manifest_paths(fixture: &Fixture) -> Vec<String> {
+    let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(fixture.path("out/.gcmod")).unwrap()).unwrap();
+    manifest["entries"].as_array().unwrap().iter().map(|e| e["path"].as_str().unwrap().to_owned()).collect()
+}
+
+#[test]
+fn refreshing_to_a_new_revision() {
+    let fixture = two_revisions();
+    // The image didn't change it, so it's left as it is
+    fs::write(fixture.path("out/data/levels/2.bin"), b"edited").unwrap();
+
+    let output = success(fixture.run(&["extract", "--refresh", "rev2.iso", "out"]));
+    let text = stdout(&output);
+    assert!(text.contains("1 added, 1 changed, 1 removed, 3 unchanged.\n"), "{}", text);
+    assert!(text.contains("The removed files were moved to out/.gcmod-trash."), "{}", text);
+
+    assert_eq!(fs::read(fixture.path("out/data/levels/1.bin")).unwrap(), b"changed");
+    assert_eq!(fs::read(fixture.path("out/new.bin")).unwrap(), b"new");
+    assert_eq!(fs::read(fixture.path("out/data/levels/2.bin")).unwrap(), b"edited");
+    assert!(!fixture.path("out/readme.txt").exists());
+    assert!(fs::read(fixture.path("out/.gcmod-trash/readme.txt")).unwrap() == fs::read(fixture.image()).unwrap()[0x8000..0x8040]);
+    let paths = manifest_paths(&fixture);
+    assert!(paths.contains(&"/new.bin".to_owned()) && !paths.contains(&"/readme.txt".to_owned()), "{:?}", paths);
+
+    // Nothing's different the second time
+    let output = success(fixture.run(&["extract", "--refresh", "rev2.iso", "out"]));
+    assert!(stdout(&output).contains("0 added, 0 changed, 0 removed, 5 unchanged.\n"), "{}", stdout(&output));
+}
+
+#[test]
+fn purging_removed_files() {
+    let fixture = two_revisions();
+    success(fixture.run(&["extract", "--refresh", "rev2.iso", "out", "--purge"]));
+    assert!(!fixture.path("out/readme.txt").exists());
+    assert!(!fixture.path("out/.gcmod-trash").exists());
+
+    // Back to the first revision
+    let output = success(fixture.run(&["extract", "--refresh", "image.iso", "out", "--purge"]));
+    assert!(stdout(&output).contains("1 added, 1 changed, 1 removed, 3 unchanged.\n"), "{}", stdout(&output));
+    assert!(!fixture.path("out/new.bin").exists());
+    success(fixture.run(&["rebuild", "out", "again.iso"]));
+    let listing = |image| stdout(&success(fixture.run(&["ls", "-l", "-R", image])));
+    assert_eq!(listing("again.iso"), listing("image.iso"));
+}
+
+#[test]
+fn refreshing_needs_a_manifest_for_the_same_game() {
+    let fixture = two_revisions();
+    fs::remove_file(fixture.path("out/.gcmod")).unwrap();
+    let output = fixture.run(&["extract", "--refresh", "rev2.iso", "out"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Couldn't read the manifest in out, which refreshing needs"), "{}", stderr(&output));
+
+    success(fixture.run(&["extract", "image.iso", "other"]));
+    let header = fixture.path("tree/&&systemdata/ISO.hdr");
+    let mut bytes = fs::read(&header).unwrap();
+    bytes[..4].copy_from_slice(b"GMSE");
+    fs::write(&header, bytes).unwrap();
+    success(fixture.run(&["rebuild", "tree", "other.iso"]));
+    let output = fixture.run(&["extract", "--refresh", "other.iso", "other"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("other was extracted from GSLF01, not GMSE01"), "{}", stderr(&output));
+}
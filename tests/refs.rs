@@ -0,0 +1,47 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const DOL_OFFSET: usize = 0x3000;
+const DOL_SIZE: usize = 0x120;
+const DATA: &[u8] = b"/data/levels/1.bin\0Readme.txt\0";
+
+#[test]
+fn the_synthetic_dol_names_nothing() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(fixture.run(&["analyze-refs", "image.iso"])));
+    assert!(text.starts_with("Text: 32 bytes\nData: 0 bytes\n"), "{}", text);
+    assert!(text.contains("\nReferenced: 0 files, 0 bytes\n"), "{}", text);
+    assert!(text.contains("\nUnreferenced: 5 files, 1066081 bytes\n"), "{}", text);
+}
+
+// The game's DOL, with a data segment after its text segment
+fn write_dol(fixture: &Fixture) {
+    let image = fs::read(fixture.image()).unwrap();
+    let mut dol = image[DOL_OFFSET..DOL_OFFSET + DOL_SIZE].to_vec();
+    dol[0x1c..0x20].copy_from_slice(&(DOL_SIZE as u32).to_be_bytes());
+    dol[0x64..0x68].copy_from_slice(&0x8000_4000u32.to_be_bytes());
+    dol[0xac..0xb0].copy_from_slice(&(DATA.len() as u32).to_be_bytes());
+    dol.extend(DATA);
+    fs::write(fixture.path("Start.dol"), dol).unwrap();
+}
+
+#[test]
+fn an_extracted_dol_and_fst() {
+    let fixture = Fixture::new();
+    write_dol(&fixture);
+    let output = success(fixture.run(&["analyze-refs", "--dol", "Start.dol", "--fst", "tree/&&systemdata/Game.toc"]));
+    let text = stdout(&output);
+    assert!(text.contains(&format!("Data: {} bytes\n", DATA.len())), "{}", text);
+    assert!(
+        text.contains("\nReferenced: 2 files, 1064 bytes\n          64 /readme.txt\n        1000 /data/levels/1.bin\n"),
+        "{}", text,
+    );
+    assert!(text.contains("\nUnreferenced: 3 files, 1065017 bytes\n"), "{}", text);
+
+    // Both or neither
+    let output = fixture.run(&["analyze-refs", "--dol", "Start.dol"]);
+    assert!(!output.status.success(), "{}", stderr(&output));
+}
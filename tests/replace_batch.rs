@@ -0,0 +1,45 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+#[test]
+fn replacing_files_in_place_and_moving_them() {
+    let fixture = Fixture::new();
+    fs::create_dir(fixture.path("mod")).unwrap();
+    fs::write(fixture.path("mod/same.bin"), [0xa5; 1000]).unwrap();
+    fs::write(fixture.path("mod/grown.bin"), [0x5a; 10000]).unwrap();
+    fs::write(fixture.path("mod/mod.txt"), "# Levels\n/data/levels/1.bin = same.bin\ndata/levels/2.bin = grown.bin\n").unwrap();
+    let output = success(fixture.run(&["replace-batch", "image.iso", "mod/mod.txt"]));
+    let text = stdout(&output);
+    assert!(text.contains("Replacing /data/levels/1.bin (1000 bytes, was 1000) at 0x00018000\n"), "{}", text);
+    assert!(text.contains("Moving /data/levels/2.bin (10000 bytes, was 4096) from 0x00010000 to "), "{}", text);
+    assert!(text.ends_with("Replaced 2 files.\n"), "{}", text);
+
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    assert_eq!(fs::read(fixture.path("out/data/levels/1.bin")).unwrap(), [0xa5; 1000]);
+    assert_eq!(fs::read(fixture.path("out/data/levels/2.bin")).unwrap(), [0x5a; 10000]);
+    assert!(fs::read(fixture.path("out/readme.txt")).unwrap() == fs::read(fixture.path("tree/readme.txt")).unwrap());
+    success(fixture.run(&["verify", "image.iso"]));
+}
+
+#[test]
+fn batches_that_cant_be_done_write_nothing() {
+    let fixture = Fixture::new();
+    let original = fs::read(fixture.image()).unwrap();
+    fs::write(fixture.path("new.bin"), b"new").unwrap();
+    for (mapping, error) in [
+        ("/readme.txt = new.bin\n/missing.bin = new.bin\n", "There's nothing at /missing.bin on the ROM"),
+        ("/readme.txt = new.bin\n/data = new.bin\n", "/data is a directory, not a file"),
+        ("/readme.txt = new.bin\nidx:1 = new.bin\n", "/readme.txt is replaced more than once"),
+        ("/readme.txt new.bin\n", "Line 1 isn't like"),
+        ("/readme.txt = gone.bin\n", "Couldn't read"),
+    ] {
+        fs::write(fixture.path("mod.txt"), mapping).unwrap();
+        let output = fixture.run(&["replace-batch", "image.iso", "mod.txt"]);
+        assert!(!output.status.success(), "{}", mapping);
+        assert!(stderr(&output).contains(error), "{}: {}", mapping, stderr(&output));
+        assert!(fs::read(fixture.image()).unwrap() == original, "{}", mapping);
+    }
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use std::{env, fs, path::Path};
+
+use serde_json::Value;
+
+use common::{gcmod, stdout, success, Fixture};
+
+const TEXT_GOLDEN: &str = "tests/golden/synthetic-report.txt";
+const JSON_GOLDEN: &str = "tests/golden/synthetic-report.json";
+
+// 2001-09-09, so the date is the same everywhere
+const SOURCE_DATE_EPOCH: &str = "1000000000";
+// The SHA-1 covers the whole ROM, so a retail sized one would be slow
+const CAPACITY: &str = "0x400000";
+
+fn report(fixture: &Fixture, name: &str, args: &[&str]) -> String {
+    success(gcmod(fixture.dir.path())
+        .env("SOURCE_DATE_EPOCH", SOURCE_DATE_EPOCH)
+        .args(["rebuild", "tree", &format!("{}.iso", name), "--report", name, "--capacity", CAPACITY])
+        .args(args)
+        .output()
+        .unwrap());
+    fs::read_to_string(fixture.path(name)).unwrap()
+}
+
+fn sha1_of(path: &Path) -> String {
+    gcmod::hash::to_hex(&gcmod::hash::sha1_of(fs::File::open(path).unwrap()).unwrap())
+}
+
+// GCMOD_BLESS=1 writes the golden file, after a change to the report
+fn check_golden(golden: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(golden);
+    if env::var_os("GCMOD_BLESS").is_some() {
+        fs::write(&path, actual).unwrap();
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    assert!(actual == expected, "the report changed, see {}:\n{}", golden, actual);
+}
+
+#[test]
+fn text_reports_match_the_golden_file() {
+    let fixture = Fixture::new();
+    let text = report(&fixture, "report.txt", &[]);
+    let (built_by, rest) = text.split_once('\n').unwrap();
+    assert!(built_by.starts_with("Built by:          gcmod "), "{}", built_by);
+    check_golden(TEXT_GOLDEN, rest);
+
+    let sha1 = sha1_of(&fixture.path("report.txt.iso"));
+    assert!(rest.contains(&format!("SHA-1:             {}\n", sha1)), "{}", rest);
+}
+
+#[test]
+fn json_reports_match_the_golden_file() {
+    let fixture = Fixture::new();
+    let mut doc: Value = serde_json::from_str(&report(&fixture, "report.json", &["--report-format", "json"])).unwrap();
+    assert_eq!(doc["sha1"].as_str().unwrap(), sha1_of(&fixture.path("report.json.iso")));
+    for field in ["gcmod_version", "git_hash"] {
+        doc[field] = Value::Null;
+    }
+    check_golden(JSON_GOLDEN, &(serde_json::to_string_pretty(&doc).unwrap() + "\n"));
+
+    // The file table is everything on the ROM, in order
+    let files = doc["files"].as_array().unwrap();
+    assert_eq!(files.len() as u64, doc["file_count"].as_u64().unwrap());
+    let offsets: Vec<u64> = files.iter().map(|f| f["offset"].as_u64().unwrap()).collect();
+    assert!(offsets.windows(2).all(|w| w[0] <= w[1]), "{:?}", offsets);
+    let listing = stdout(&success(fixture.run(&["ls", "-R", "report.json.iso"])));
+    let regular = listing.lines().filter(|l| !l.ends_with('/')).count();
+    assert_eq!(files.len(), regular, "{}", listing);
+}
+
+#[test]
+fn reports_need_a_new_rom() {
+    let fixture = Fixture::new();
+    let output = fixture.run(&["rebuild", "tree", "--update", "image.iso", "--report", "report.txt"]);
+    assert!(!output.status.success());
+    assert!(!fixture.path("report.txt").exists());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+// The fixture's image, with the header written over
+fn trashed() -> Fixture {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[..0x440].fill(0xff);
+    fs::write(fixture.image(), image).unwrap();
+    assert!(!fixture.run(&["info", "image.iso"]).status.success());
+    fixture
+}
+
+#[test]
+fn rescue_finds_the_system_data() {
+    let fixture = trashed();
+    let text = stdout(&success(fixture.run(&["rescue", "image.iso"])));
+    for found in [
+        "Apploader at 0x00002440 (0x40 bytes), high confidence",
+        "DOL at 0x00003000 (0x120 bytes), high confidence",
+        "FST at 0x00002800 (0xa7 bytes), high confidence: 9 entries, 5 of them files",
+    ] {
+        assert!(text.contains(found), "{}", text);
+    }
+}
+
+#[test]
+fn rescue_extracts_what_it_found() {
+    let fixture = trashed();
+    let output = success(fixture.run(&["rescue", "image.iso", "--extract", "out"]));
+    assert!(stdout(&output).contains("Using the DOL at 0x3000 and the FST at 0x2800."), "{}", stdout(&output));
+    for &(path, size) in gcmod::synthetic::FILES {
+        assert!(fs::read(fixture.path("out").join(path)).unwrap() == gcmod::synthetic::contents(size), "{}", path);
+    }
+}
+
+#[test]
+fn rescue_needs_a_dol_to_extract() {
+    let fixture = trashed();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[0x3000..0x3120].fill(0);
+    fs::write(fixture.image(), image).unwrap();
+    let output = fixture.run(&["rescue", "image.iso", "--extract", "out"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("No DOL found."), "{}", stdout(&output));
+    assert!(stderr(&output).contains("Can't extract without a DOL"), "{}", stderr(&output));
+}
+
+const FST_OFFSET: usize = 0x424;
+
+#[test]
+fn rescue_says_whats_wrong_with_the_headers_offsets() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[FST_OFFSET..FST_OFFSET + 4].fill(0);
+    fs::write(fixture.image(), image).unwrap();
+    let output = fixture.run(&["info", "image.iso"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("The header's FST offset is 0"), "{}", stderr(&output));
+
+    let output = success(fixture.run(&["rescue", "image.iso"]));
+    assert!(stderr(&output).contains("The header's FST offset is 0, so it can't be used to read the ROM."), "{}", stderr(&output));
+    assert!(stdout(&output).contains("FST at 0x00002800 (0xa7 bytes), high confidence"), "{}", stdout(&output));
+}
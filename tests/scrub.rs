@@ -0,0 +1,99 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+// The synthetic image would count as trimmed, and be written out at full
+// size, so these give its own capacity. Scrub and unscrub themselves are
+// tested in src/scrub.rs.
+
+const CAPACITY: &str = "4194304";
+
+fn crc_of(output: &std::process::Output) -> String {
+    let out = stdout(output);
+    let line = out.lines().find_map(|l| l.strip_prefix("CRC-32: ")).unwrap_or_else(|| panic!("No CRC in {:?}", out));
+    line.trim().to_owned()
+}
+
+#[test]
+fn expect_crc_checks_before_writing() {
+    let fixture = Fixture::new();
+    let image = fixture.image();
+    let image = image.to_str().unwrap();
+    let before = fixture.names();
+
+    let crc = crc_of(&success(fixture.run(&["scrub", image, "first.iso", "--capacity", CAPACITY])));
+    assert_eq!(fs::metadata(fixture.path("first.iso")).unwrap().len(), 4 << 20);
+    fs::remove_file(fixture.path("first.iso")).unwrap();
+
+    let wrong = fixture.run(&["scrub", image, "out.iso", "--capacity", CAPACITY, "--expect-crc", "00000000"]);
+    assert!(!wrong.status.success());
+    // Not even a temporary file
+    assert_eq!(fixture.names(), before);
+
+    success(fixture.run(&["scrub", image, "out.iso", "--capacity", CAPACITY, "--expect-crc", &crc]));
+    assert_eq!(fixture.names().len(), before.len() + 1);
+    assert!(fixture.path("out.iso").is_file());
+}
+
+#[test]
+fn existing_output_is_left_alone() {
+    let fixture = Fixture::new();
+    fs::write(fixture.path("out.iso"), "mine").unwrap();
+    let output = fixture.run(&["scrub", fixture.image().to_str().unwrap(), "out.iso", "--capacity", CAPACITY]);
+    assert!(!output.status.success());
+    assert_eq!(fs::read(fixture.path("out.iso")).unwrap(), b"mine");
+}
+
+#[test]
+fn the_contents_have_to_fit_in_the_capacity() {
+    let fixture = Fixture::new();
+    for verb in ["scrub", "unscrub"] {
+        let output = fixture.run(&[verb, "image.iso", "out.iso", "--capacity", "1048576"]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("don't fit in a disc of 0x100000 bytes"), "{}", stderr(&output));
+        assert!(!fixture.path("out.iso").exists());
+    }
+}
+
+const FST_OFFSET: usize = 0x2800;
+const ENTRY_SIZE: usize = 12;
+const LEVELS_INDEX: usize = 5;
+
+#[test]
+fn repaired_fsts_need_force() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    // /data/levels ends before it starts
+    let next_index = FST_OFFSET + LEVELS_INDEX * ENTRY_SIZE + 8;
+    image[next_index..next_index + 4].copy_from_slice(&2u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+
+    for verb in ["scrub", "unscrub"] {
+        let output = fixture.run(&[verb, "image.iso", "out.iso", "--capacity", CAPACITY]);
+        assert!(!output.status.success());
+        let text = stderr(&output);
+        assert!(text.contains("which gcmod worked around to read it"), "{}", text);
+        assert!(text.contains("Pass --force to continue anyway."), "{}", text);
+        assert!(!fixture.path("out.iso").exists());
+    }
+    success(fixture.run(&["scrub", "image.iso", "out.iso", "--capacity", CAPACITY, "--force"]));
+}
+
+const ENTRY_POINT: usize = 0x3000 + 0xe0;
+
+#[test]
+fn roms_that_wouldnt_boot_need_force() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[ENTRY_POINT..ENTRY_POINT + 4].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+
+    let output = fixture.run(&["scrub", "image.iso", "out.iso", "--capacity", CAPACITY]);
+    assert!(!output.status.success());
+    let text = stderr(&output);
+    assert!(text.contains("The entry point isn't in any of the DOL's segments"), "{}", text);
+    assert!(!fixture.path("out.iso").exists());
+    success(fixture.run(&["scrub", "image.iso", "out.iso", "--capacity", CAPACITY, "--force"]));
+}
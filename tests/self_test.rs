@@ -0,0 +1,62 @@
+mod common;
+
+use std::{fs, process::Output};
+
+use common::{stderr, stdout, success, Fixture};
+
+// Each stage's result and name
+fn stages(output: &Output) -> Vec<(String, String)> {
+    stdout(output).lines()
+        .filter_map(|line| line.split_once("  "))
+        .filter(|(result, _)| ["PASS", "FAIL"].contains(result))
+        .map(|(result, rest)| (result.to_owned(), rest.split("  ").next().unwrap().trim().to_owned()))
+        .collect()
+}
+
+fn all_passed(stages: &[(String, String)]) -> bool {
+    stages.iter().all(|(result, _)| result == "PASS")
+}
+
+#[test]
+fn a_round_trip() {
+    let fixture = Fixture::new();
+    let before = fixture.names();
+    let output = success(fixture.run(&["self-test"]));
+    let text = stdout(&output);
+    let stages = stages(&output);
+    assert!(all_passed(&stages), "{}", text);
+    let names: Vec<&str> = stages.iter().map(|(_, name)| name.as_str()).collect();
+    assert_eq!(names, ["setup", "build", "extract", "rebuild", "verify", "cleanup"], "{}", text);
+    assert!(!stderr(&output).contains("Warning"), "{}", stderr(&output));
+    assert!(text.ends_with("All stages passed.\n"), "{}", text);
+    // The workspace is gone
+    assert_eq!(fixture.names(), before);
+}
+
+#[test]
+fn checking_a_rom() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["self-test", "--with", "image.iso"]));
+    let passed = stages(&output);
+    assert!(passed.len() >= 4 && all_passed(&passed), "{}", stdout(&output));
+    assert_eq!(passed[0].1, "parse");
+
+    let output = fixture.run(&["self-test", "--with", "missing.iso"]);
+    assert!(!output.status.success());
+    assert_eq!(stages(&output), [("FAIL".to_owned(), "parse".to_owned())]);
+}
+
+const ROOT_NEXT_INDEX: usize = 0x2808;
+
+#[test]
+fn roms_with_problems_fail() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[ROOT_NEXT_INDEX..ROOT_NEXT_INDEX + 4].copy_from_slice(&8u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+    let output = fixture.run(&["self-test", "--with", "image.iso"]);
+    assert!(!output.status.success());
+    let stages = stages(&output);
+    assert_eq!(stages[0], ("PASS".to_owned(), "parse".to_owned()));
+    assert_eq!(stages.last().unwrap().0, "FAIL", "{:?}", stages);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const STREAMING: usize = 0x08;
+
+fn streaming(path: &std::path::Path) -> (u8, u8) {
+    let bytes = fs::read(path).unwrap();
+    (bytes[STREAMING], bytes[STREAMING + 1])
+}
+
+#[test]
+fn sets_streaming_on_a_rom() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["set", "image.iso", "--audio-streaming", "on", "--stream-buffer", "10"]));
+    assert_eq!(streaming(&fixture.image()), (1, 10));
+    success(fixture.run(&["verify", "image.iso"]));
+
+    success(fixture.run(&["set", "image.iso", "--audio-streaming", "off"]));
+    assert_eq!(streaming(&fixture.image()), (0, 0));
+}
+
+#[test]
+fn sets_streaming_in_a_tree() {
+    let fixture = Fixture::new();
+    let header = fixture.path("tree/&&systemdata/ISO.hdr");
+    success(fixture.run(&["set", "tree", "--audio-streaming", "on", "--stream-buffer", "3"]));
+    assert_eq!(streaming(&header), (1, 3));
+}
+
+#[test]
+fn invalid_streaming_changes_nothing() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    for args in [&["--audio-streaming", "on"][..], &["--audio-streaming", "on", "--stream-buffer", "16"]] {
+        let output = fixture.run(&[&["set", "image.iso"][..], args].concat());
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains("stream buffer size"), "{}", stderr(&output));
+    }
+    assert!(fs::read(fixture.image()).unwrap() == before);
+}
+
+#[test]
+fn verify_catches_streaming_without_a_buffer() {
+    let fixture = Fixture::new();
+    let mut bytes = fs::read(fixture.image()).unwrap();
+    bytes[STREAMING] = 1;
+    fs::write(fixture.image(), bytes).unwrap();
+    let output = fixture.run(&["verify", "image.iso"]);
+    assert!(!output.status.success());
+    let text = stdout(&output) + &stderr(&output);
+    assert!(text.contains("Audio streaming needs a stream buffer size"), "{}", text);
+}
+
+const DISK_ID: usize = 0x06;
+const TOTAL_DISCS: usize = 0x45c;
+
+fn changed(before: &[u8], after: &[u8]) -> Vec<usize> {
+    assert_eq!(before.len(), after.len());
+    (0..before.len()).filter(|&i| before[i] != after[i]).collect()
+}
+
+#[test]
+fn setting_the_disc_changes_only_its_bytes() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    let output = stdout(&success(fixture.run(&["set", "image.iso", "--disc", "2", "--total-discs", "3"])));
+    assert!(output.contains("Disc: 2 of 3"), "{}", output);
+    let after = fs::read(fixture.image()).unwrap();
+    assert_eq!(changed(&before, &after), [DISK_ID, TOTAL_DISCS + 3]);
+    assert_eq!((after[DISK_ID], &after[TOTAL_DISCS..TOTAL_DISCS + 4]), (1, &3u32.to_be_bytes()[..]));
+
+    // Back to how it was, apart from the total
+    success(fixture.run(&["set", "image.iso", "--disc", "1"]));
+    let again = fs::read(fixture.image()).unwrap();
+    assert_eq!(changed(&before, &again), [TOTAL_DISCS + 3]);
+    success(fixture.run(&["verify", "image.iso"]));
+}
+
+#[test]
+fn invalid_discs_change_nothing() {
+    let fixture = Fixture::new();
+    let before = fs::read(fixture.image()).unwrap();
+    for (args, error) in [
+        (&["--disc", "0"][..], "discs are numbered from 1"),
+        (&["--disc", "3", "--total-discs", "2"], "only 2 discs"),
+        (&["--disc", "256"], "Invalid disc number"),
+    ] {
+        let output = fixture.run(&[&["set", "image.iso"][..], args].concat());
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains(error), "{}", stderr(&output));
+    }
+    assert!(fs::read(fixture.image()).unwrap() == before);
+}
+
+#[test]
+fn verify_catches_a_disc_past_the_total() {
+    let fixture = Fixture::new();
+    let mut bytes = fs::read(fixture.image()).unwrap();
+    bytes[DISK_ID] = 2;
+    bytes[TOTAL_DISCS..TOTAL_DISCS + 4].copy_from_slice(&2u32.to_be_bytes());
+    fs::write(fixture.image(), bytes).unwrap();
+    let output = fixture.run(&["verify", "image.iso"]);
+    assert!(stdout(&output).contains("This is disc 3, but bi2 says there are only 2 discs"), "{}", stdout(&output));
+}
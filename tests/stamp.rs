@@ -0,0 +1,36 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const README_OFFSET: usize = 0x8000;
+const STAMP_OFFSET: usize = 0x1fe0;
+const STAMP_END: usize = STAMP_OFFSET + 0x50;
+
+#[test]
+fn stamping_a_rebuild() {
+    let fixture = Fixture::new();
+    success(fixture.run(&["rebuild", "tree", "plain.iso"]));
+    let text = stdout(&success(fixture.run(&["info", "plain.iso", "--stamp"])));
+    assert!(text.contains("The ROM has no build stamp."), "{}", text);
+    let output = fixture.run(&["verify", "plain.iso", "--check-stamp"]);
+    assert!(!output.status.success());
+
+    success(fixture.run(&["rebuild", "tree", "stamped.iso", "--stamp"]));
+    let text = stdout(&success(fixture.run(&["info", "stamped.iso", "--stamp"])));
+    assert!(text.contains("Stamp version: 1\n") && text.contains("Tree hash: "), "{}", text);
+    let text = stdout(&success(fixture.run(&["verify", "stamped.iso", "--check-stamp"])));
+    assert!(text.contains("The build stamp matches the ROM's files."), "{}", text);
+
+    // Only the stamp is different
+    let (plain, mut stamped) = (fs::read(fixture.path("plain.iso")).unwrap(), fs::read(fixture.path("stamped.iso")).unwrap());
+    assert!(plain[..STAMP_OFFSET] == stamped[..STAMP_OFFSET] && plain[STAMP_END..] == stamped[STAMP_END..]);
+
+    stamped[README_OFFSET] ^= 0xff;
+    fs::write(fixture.path("stamped.iso"), stamped).unwrap();
+    let output = fixture.run(&["verify", "stamped.iso", "--check-stamp"]);
+    assert!(!output.status.success());
+    let text = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(text.contains("The ROM's files have changed since it was built"), "{}", text);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+// The fixture, with an image of a ROM that has its own /&&systemdata, in
+// "weird.iso"
+fn fixture_with_its_own_system_data() -> Fixture {
+    let fixture = Fixture::new();
+    fs::rename(fixture.path("tree/&&systemdata"), fixture.path("tree/&&systemdata.gcmod")).unwrap();
+    fs::create_dir(fixture.path("tree/&&systemdata")).unwrap();
+    fs::write(fixture.path("tree/&&systemdata/readme.txt"), b"a real file").unwrap();
+    let manifest = r#"{"gcmod_version": "0", "game_id": "GSLF01", "entries": [], "system_dir": "&&systemdata.gcmod"}"#;
+    fs::write(fixture.path("tree/.gcmod"), manifest).unwrap();
+    success(fixture.run(&["rebuild", "tree", "weird.iso"]));
+    fixture
+}
+
+#[test]
+fn roms_with_their_own_system_data_directory() {
+    let fixture = fixture_with_its_own_system_data();
+    let listing = stdout(&success(fixture.run(&["ls", "-R", "weird.iso"])));
+    assert!(listing.contains("/&&systemdata/readme.txt\n"), "{}", listing);
+    let output = fixture.run(&["verify", "weird.iso"]);
+    let text = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(text.contains("The ROM has its own /&&systemdata, so extracting puts the system data in &&systemdata.gcmod instead"), "{}", text);
+    let output = fixture.run(&["verify", "image.iso"]);
+    assert!(!stdout(&output).contains("its own /&&systemdata"), "{}", stdout(&output));
+
+    let output = success(fixture.run(&["extract", "weird.iso", "out"]));
+    assert!(stderr(&output).contains("&&systemdata.gcmod"), "{}", stderr(&output));
+    assert_eq!(fs::read(fixture.path("out/&&systemdata/readme.txt")).unwrap(), b"a real file");
+    assert!(fixture.path("out/&&systemdata.gcmod/ISO.hdr").is_file());
+    assert!(!fixture.path("out/&&systemdata/ISO.hdr").exists());
+    let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(fixture.path("out/.gcmod")).unwrap()).unwrap();
+    assert_eq!(manifest["system_dir"], "&&systemdata.gcmod");
+
+    // Its own &&systemdata goes back on the ROM
+    success(fixture.run(&["rebuild", "out", "rebuilt.iso"]));
+    assert!(fs::read(fixture.path("rebuilt.iso")).unwrap() == fs::read(fixture.path("weird.iso")).unwrap());
+    success(fixture.run(&["rebuild", "out", "kept.iso", "--no-rebuild-fst"]));
+    assert!(fs::read(fixture.path("kept.iso")).unwrap() == fs::read(fixture.path("weird.iso")).unwrap());
+}
+
+#[test]
+fn refreshing_between_the_two_layouts() {
+    let fixture = fixture_with_its_own_system_data();
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    success(fixture.run(&["extract", "--refresh", "weird.iso", "out"]));
+    assert!(fixture.path("out/&&systemdata.gcmod/ISO.hdr").is_file());
+    assert!(fixture.path("out/&&systemdata/readme.txt").is_file());
+    assert!(!fixture.path("out/&&systemdata/ISO.hdr").exists());
+
+    success(fixture.run(&["extract", "--refresh", "image.iso", "out"]));
+    assert!(fixture.path("out/&&systemdata/ISO.hdr").is_file());
+    assert!(!fixture.path("out/&&systemdata.gcmod").exists());
+    success(fixture.run(&["rebuild", "out", "rebuilt.iso"]));
+    // The fixture's image is smaller than a disc, so it's only the start
+    let (rebuilt, image) = (fs::read(fixture.path("rebuilt.iso")).unwrap(), fs::read(fixture.image()).unwrap());
+    assert!(rebuilt[..image.len()] == image[..]);
+}
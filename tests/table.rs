@@ -0,0 +1,70 @@
+mod common;
+
+use std::fs;
+
+use common::{stdout, success, Fixture};
+
+// Names the FST can have that need quoting, in names.iso
+const NAMES: &[&str] = &["a,b.bin", "say \"hi\".txt", "two\nlines.bin", "tab\there"];
+
+// Reads RFC 4180 records back, to check they come out as they went in
+fn parse(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let (mut rows, mut row, mut field) = (Vec::new(), Vec::new(), String::new());
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {
+                chars.next();
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            c => field.push(c),
+        }
+    }
+    assert!(field.is_empty() && row.is_empty(), "{:?}", text);
+    rows
+}
+
+fn fixture_with_names() -> Fixture {
+    let fixture = Fixture::new();
+    for (i, name) in NAMES.iter().enumerate() {
+        fs::write(fixture.path("tree/data").join(name), vec![b'x'; i + 1]).unwrap();
+    }
+    success(fixture.run(&["rebuild", "tree", "names.iso"]));
+    fixture
+}
+
+#[test]
+fn names_that_need_quoting_round_trip() {
+    let fixture = fixture_with_names();
+    for (format, delimiter) in [("csv", ','), ("tsv", '\t')] {
+        let text = stdout(&success(fixture.run(&["ls", "names.iso", "/data", "--format", format])));
+        let rows = parse(&text, delimiter);
+        assert_eq!(rows[0], ["index", "path", "directory", "offset", "offset_hex", "size", "parent_index", "extension"]);
+        assert!(rows.iter().all(|r| r.len() == 8), "{:?}", rows);
+        for (i, name) in NAMES.iter().enumerate() {
+            let row = rows.iter().find(|r| r[1] == format!("/data/{}", name)).unwrap_or_else(|| panic!("{:?}: {:?}", name, rows));
+            assert_eq!((row[2].as_str(), row[5].as_str()), ("false", (i + 1).to_string().as_str()), "{:?}", row);
+        }
+    }
+}
+
+#[test]
+fn without_a_header_into_a_file() {
+    let fixture = fixture_with_names();
+    let with_header = stdout(&success(fixture.run(&["ls", "names.iso", "--format", "csv"])));
+    let output = success(fixture.run(&["ls", "names.iso", "--format", "csv", "--no-header", "--out", "table.csv"]));
+    assert_eq!(stdout(&output), "");
+    let without = fs::read_to_string(fixture.path("table.csv")).unwrap();
+    assert_eq!(with_header.split_once("\r\n").unwrap().1, without);
+    // Everything but the root
+    let listed = stdout(&success(fixture.run(&["ls", "-R", "names.iso"])));
+    assert_eq!(parse(&without, ',').len(), listed.lines().count() - NAMES.iter().filter(|n| n.contains('\n')).count());
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const INFO_OFFSET: usize = 0x1820;
+const INFO_SIZE: usize = 0x140;
+const LONG_TITLE: usize = 0x40;
+
+// A PAL game, with a BNR2 banner in every language but Spanish
+fn fixture_with_banner() -> Fixture {
+    let fixture = Fixture::new();
+    let mut banner = b"BNR2".to_vec();
+    banner.resize(INFO_OFFSET, 0);
+    for (short, long) in [("Game", "The Game"), ("Spiel", "Das Spiel"), ("Jeu", "Le Jeu"), ("", ""), ("Gioco", "Il Gioco"), ("Spel", "Het Spel")] {
+        let mut block = vec![0; INFO_SIZE];
+        block[..short.len()].copy_from_slice(short.as_bytes());
+        block[LONG_TITLE..LONG_TITLE + long.len()].copy_from_slice(long.as_bytes());
+        banner.extend(block);
+    }
+    fs::write(fixture.path("tree/opening.bnr"), banner).unwrap();
+    fs::remove_file(fixture.image()).unwrap();
+    success(fixture.run(&["rebuild", "tree", "image.iso"]));
+    fixture
+}
+
+fn info(fixture: &Fixture, args: &[&str]) -> String {
+    let mut info = vec!["info", "image.iso"];
+    info.extend(args);
+    stdout(&success(fixture.run(&info)))
+}
+
+#[test]
+fn titles_by_language() {
+    let fixture = fixture_with_banner();
+    assert!(info(&fixture, &[]).contains("Title: The Game\n"), "{}", info(&fixture, &[]));
+    assert!(info(&fixture, &["--lang", "de"]).contains("Title: Das Spiel\n"));
+    assert!(info(&fixture, &["--lang", "italian"]).contains("Title: Il Gioco\n"));
+    // Nothing in Spanish, or Japanese
+    assert!(info(&fixture, &["--lang", "es"]).contains("Title: The Game\n"));
+    assert!(info(&fixture, &["--lang", "ja"]).contains("Title: The Game\n"));
+    let output = fixture.run(&["info", "image.iso", "--lang", "klingon"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unknown language \"klingon\""), "{}", stderr(&output));
+
+    let verbose = info(&fixture, &["-v"]);
+    assert!(verbose.contains("Titles:\n  Header: gcmod self-test (ASCII)\n  English short: Game (ASCII)\n  English long: The Game (ASCII)\n"), "{}", verbose);
+    assert!(verbose.contains("  Spanish short: (blank)\n"), "{}", verbose);
+    assert!(!info(&fixture, &[]).contains("Titles:"));
+}
+
+#[test]
+fn every_title_is_recorded() {
+    let fixture = fixture_with_banner();
+    let meta: serde_json::Value = serde_json::from_str(&stdout(&success(fixture.run(&["dump-meta", "image.iso"])))).unwrap();
+    assert_eq!(meta["titles"]["banner_version"], "BNR2");
+    assert_eq!(meta["titles"]["default_language"], "english");
+    let banner = meta["titles"]["banner"].as_array().unwrap();
+    assert_eq!(banner.len(), 6);
+    assert_eq!(banner[5]["language"], "dutch");
+    assert_eq!(banner[5]["long_title"]["text"], "Het Spel");
+
+    fs::create_dir(fixture.path("roms")).unwrap();
+    fs::copy(fixture.image(), fixture.path("roms/game.iso")).unwrap();
+    success(fixture.run(&["catalog", "roms"]));
+    let found = stdout(&success(fixture.run(&["catalog", "roms", "--find", "spiel"])));
+    assert!(found.contains("game.iso"), "{}", found);
+    let found = stdout(&success(fixture.run(&["catalog", "roms", "--find", "Zelda"])));
+    assert!(!found.contains("game.iso"), "{}", found);
+}
+
+#[test]
+fn without_a_banner() {
+    let fixture = Fixture::new();
+    assert!(info(&fixture, &["--lang", "fr"]).contains("Title: gcmod self-test\n"));
+    let verbose = info(&fixture, &["-v"]);
+    assert!(verbose.contains("Titles:\n  Header: gcmod self-test (ASCII)\n  Banner: none\n"), "{}", verbose);
+}
+
+#[test]
+fn banner_info() {
+    let fixture = fixture_with_banner();
+    let text = info(&fixture, &["-t", "banner"]);
+    assert!(text.contains("Version: BNR2\nLanguages:\n\nLanguage: English\nTitle: The Game\nShort title: Game\n"), "{}", text);
+    assert!(text.contains("Language: German\nTitle: Das Spiel\n"), "{}", text);
+
+    let json: serde_json::Value = serde_json::from_str(&info(&fixture, &["-t", "banner", "--json"])).unwrap();
+    assert_eq!(json["version"], "BNR2");
+    assert_eq!(json["infos"][1]["long_title"], "Das Spiel");
+    assert_eq!(json["infos"].as_array().unwrap().len(), 6);
+
+    // A standalone one, at the start of the file
+    let text = stdout(&success(fixture.run(&["info", "tree/opening.bnr", "-t", "banner"])));
+    assert!(text.starts_with("Offset: 0\nVersion: BNR2\n"), "{}", text);
+}
+
+#[test]
+fn banner_info_without_a_banner() {
+    let fixture = Fixture::new();
+    let output = fixture.run(&["info", "image.iso", "-t", "banner"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("The ROM has no banner"), "{}", stderr(&output));
+}
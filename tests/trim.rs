@@ -0,0 +1,133 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const ROM_SIZE: u64 = 0x5705_8000;
+// Where /data/big.bin, the last file, ends
+const LAST_FILE_END: u64 = 0x12_3039;
+
+fn trimmed() -> Fixture {
+    let fixture = Fixture::new();
+    fs::File::options().write(true).open(fixture.image()).unwrap().set_len(LAST_FILE_END).unwrap();
+    fixture
+}
+
+#[test]
+fn trimmed_images_are_read_like_any_other() {
+    let fixture = trimmed();
+    let info = stdout(&success(fixture.run(&["info", "image.iso"])));
+    assert!(info.contains("Trimmed image: 1 MiB of 1392 MiB"), "{}", info);
+
+    let output = success(fixture.run(&["verify", "image.iso"]));
+    let text = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(!text.to_lowercase().contains("corrupt"), "{}", text);
+
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    assert!(fs::read(fixture.path("out/data/big.bin")).unwrap() == fs::read(fixture.path("tree/data/big.bin")).unwrap());
+    // The missing tail reads as zeros
+    let offset = format!("{:#x}", ROM_SIZE - 0x10);
+    let text = stdout(&success(fixture.run(&["info", "image.iso", "--offset", &offset])));
+    assert!(text.contains("free space at the end of the image"), "{}", text);
+}
+
+#[test]
+fn updating_can_extend_the_image() {
+    let fixture = trimmed();
+    let output = fixture.run(&["rebuild", "tree", "--extend"]);
+    assert!(!output.status.success(), "--extend is only for --update");
+
+    success(fixture.run(&["rebuild", "tree", "--update", "image.iso", "--extend"]));
+    assert_eq!(fs::metadata(fixture.image()).unwrap().len(), ROM_SIZE);
+    let info = stdout(&success(fixture.run(&["info", "image.iso"])));
+    assert!(!info.contains("Trimmed image"), "{}", info);
+    success(fixture.run(&["verify", "image.iso"]));
+}
+
+#[test]
+fn images_cut_off_inside_a_file_arent_trimmed() {
+    let fixture = Fixture::new();
+    fs::File::options().write(true).open(fixture.image()).unwrap().set_len(LAST_FILE_END - 1).unwrap();
+    let info = stdout(&success(fixture.run(&["info", "image.iso"])));
+    assert!(!info.contains("Trimmed image"), "{}", info);
+    assert!(info.contains("/data/big.bin ends past the end of the image"), "{}", info);
+}
+
+const BIG_FILE_SIZE: u64 = 1060921;
+
+// Cut 1000 bytes into /data/big.bin
+fn cut_off() -> Fixture {
+    let fixture = Fixture::new();
+    let cut = LAST_FILE_END - BIG_FILE_SIZE + 1000;
+    fs::File::options().write(true).open(fixture.image()).unwrap().set_len(cut).unwrap();
+    fixture
+}
+
+// Only trimmed images read as zeros past their end, since all that was cut
+// off them was padding. A file that was cut off can't be extracted.
+#[test]
+fn files_cut_off_fail() {
+    let fixture = cut_off();
+    let output = fixture.run(&["extract", "image.iso", "out"]);
+    assert!(!output.status.success());
+    let cut = LAST_FILE_END - BIG_FILE_SIZE + 1000;
+    assert!(
+        stderr(&output).contains(&format!("/data/big.bin ends at {:#x}, past the end of the disc at {:#x}", LAST_FILE_END, cut)),
+        "{}", stderr(&output),
+    );
+
+    let output = fixture.run(&["extract", "image.iso", "kept", "--keep-going"]);
+    assert!(!output.status.success());
+    let errors: serde_json::Value = serde_json::from_str(&fs::read_to_string(fixture.path("kept/extraction-errors.json")).unwrap()).unwrap();
+    assert_eq!(errors.as_array().unwrap().len(), 1, "{}", errors);
+    assert_eq!(errors[0]["path"], "/data/big.bin");
+    // Nothing else is near the end
+    assert!(fs::read(fixture.path("kept/data/levels/1.bin")).unwrap() == fs::read(fixture.path("tree/data/levels/1.bin")).unwrap());
+}
+
+#[test]
+fn failing_on_truncated_files() {
+    let fixture = cut_off();
+    let output = fixture.run(&["extract", "image.iso", "out", "--fail-on-truncated"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("/data/big.bin goes 1059921 bytes past the end of the image"), "{}", stderr(&output));
+    assert!(!fixture.path("out/data/big.bin").exists());
+
+    let output = fixture.run(&["extract", "image.iso", "kept", "--fail-on-truncated", "--keep-going"]);
+    assert!(!output.status.success());
+    let errors: serde_json::Value = serde_json::from_str(&fs::read_to_string(fixture.path("kept/extraction-errors.json")).unwrap()).unwrap();
+    assert_eq!(errors.as_array().unwrap().len(), 1, "{}", errors);
+    assert_eq!(errors[0]["path"], "/data/big.bin");
+    assert!(!fixture.path("kept/data/big.bin").exists());
+    assert!(fixture.path("kept/data/levels/1.bin").exists());
+}
+
+const FST_OFFSET: usize = 0x2800;
+const ENTRY_SIZE: usize = 12;
+
+// Files that go past the end of the disc fail on their own, instead of
+// being padded out to whatever size the FST says
+#[test]
+fn files_past_the_capacity_fail() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    let name = FST_OFFSET + 9 * ENTRY_SIZE;
+    assert_eq!(&image[name..name + 11], b"readme.txt\0");
+    // /readme.txt is 2 GiB
+    let size = FST_OFFSET + ENTRY_SIZE + 8;
+    image[size..size + 4].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+
+    let output = fixture.run(&["extract", "image.iso", "out"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("past the end of the disc at 0x400000"), "{}", stderr(&output));
+
+    let output = fixture.run(&["extract", "image.iso", "kept", "--keep-going"]);
+    assert!(!output.status.success());
+    let errors: serde_json::Value = serde_json::from_str(&fs::read_to_string(fixture.path("kept/extraction-errors.json")).unwrap()).unwrap();
+    assert_eq!(errors.as_array().unwrap().len(), 1, "{}", errors);
+    assert_eq!(errors[0]["path"], "/readme.txt");
+    assert!(!fixture.path("kept/readme.txt").exists());
+    assert!(fs::read(fixture.path("kept/data/big.bin")).unwrap() == fs::read(fixture.path("tree/data/big.bin")).unwrap());
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::fs;
+
+use common::{stderr, stdout, success, Fixture};
+
+const FST_OFFSET: usize = 0x2800;
+const FST_SIZE: usize = 0xa7;
+// Where "levels" starts in the string table, after the 9 entries and
+// "readme.txt", "empty", "empty.bin" and "data"
+const LEVELS_NAME: usize = FST_OFFSET + 9 * 12 + 32;
+
+// The string table from "levels" on reads as zeros, like it was cut off
+fn fixture_with_unnamed_entries() -> Fixture {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    assert_eq!(&image[LEVELS_NAME..LEVELS_NAME + 7], b"levels\0");
+    image[LEVELS_NAME..FST_OFFSET + FST_SIZE].fill(0);
+    fs::write(fixture.image(), image).unwrap();
+    fixture
+}
+
+fn ls(fixture: &Fixture, args: &[&str]) -> String {
+    let mut ls = vec!["ls", "image.iso"];
+    ls.extend(args);
+    stdout(&success(fixture.run(&ls)))
+}
+
+#[test]
+fn placeholders_in_listings() {
+    let fixture = fixture_with_unnamed_entries();
+    assert_eq!(
+        ls(&fixture, &["-R"]),
+        "/readme.txt\n/empty/\n/empty.bin\n/data/\n/data/<unnamed entry #5>/\n\
+        /data/<unnamed entry #5>/<unnamed entry #6>\n/data/<unnamed entry #5>/<unnamed entry #7>\n/data/<unnamed entry #8>\n",
+    );
+    assert_eq!(
+        ls(&fixture, &["-l", "idx:5"]),
+        "-        6       4096 /data/<unnamed entry #5>/<unnamed entry #6>\n-        7       1000 /data/<unnamed entry #5>/<unnamed entry #7>\n",
+    );
+    assert_eq!(ls(&fixture, &["-d", "idx:5"]), "/data/<unnamed entry #5>/\n");
+
+    let output = success(fixture.run(&["ls", "image.iso"]));
+    let text = stderr(&output);
+    assert!(text.contains("4 of the 8 entries in the file system table have no name"), "{}", text);
+    assert!(text.contains("like idx:N") && text.contains("gcmod rescue"), "{}", text);
+}
+
+#[test]
+fn listings_of_named_tables_dont_warn() {
+    let fixture = Fixture::new();
+    let output = success(fixture.run(&["ls", "-R", "image.iso"]));
+    assert!(!stdout(&output).contains("<unnamed"), "{}", stdout(&output));
+    assert!(!stderr(&output).contains("have no name"), "{}", stderr(&output));
+}
+
+// By index, with the placeholder nowhere in what's written
+#[test]
+fn extracting_unnamed_entries() {
+    let fixture = fixture_with_unnamed_entries();
+    success(fixture.run(&["extract", "image.iso", "2.bin", "--section", "idx:6"]));
+    assert!(fs::read(fixture.path("2.bin")).unwrap() == gcmod::synthetic::contents(4096));
+    // Its files have nothing to be called in it
+    let output = fixture.run(&["extract", "image.iso", "levels", "--section", "idx:5"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Entry 6, in directory 5, has no name, so it can only be extracted on its own, with idx:6"), "{}", stderr(&output));
+
+    success(fixture.run(&["extract", "image.iso", "Game.toc", "--section", "&&systemdata/Game.toc"]));
+    let image = fs::read(fixture.image()).unwrap();
+    assert!(fs::read(fixture.path("Game.toc")).unwrap() == image[FST_OFFSET..FST_OFFSET + FST_SIZE]);
+    for name in fixture.names() {
+        assert!(!name.contains("unnamed"), "{:?}", fixture.names());
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use std::fs;
+
+use common::{stdout, success, Fixture};
+
+const FST_OFFSET: usize = 0x2800;
+// Where /readme.txt's offset is in its FST entry, the second one
+const README_OFFSET: usize = FST_OFFSET + 12 + 4;
+
+#[test]
+fn rebuilt_fsts_follow_the_retail_rules() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(fixture.run(&["verify", "image.iso", "--strict-fst"])));
+    assert!(text.contains("No problems found."), "{}", text);
+}
+
+#[test]
+fn strict_fst_problems_fail_verify() {
+    let fixture = Fixture::new();
+    let mut image = fs::read(fixture.image()).unwrap();
+    image[README_OFFSET..README_OFFSET + 4].copy_from_slice(&0x8002u32.to_be_bytes());
+    fs::write(fixture.image(), image).unwrap();
+
+    // gcmod reads it fine
+    let lenient = fixture.run(&["verify", "image.iso"]);
+    assert!(!stdout(&lenient).contains("Strict FST"), "{}", stdout(&lenient));
+
+    let output = fixture.run(&["verify", "image.iso", "--strict-fst"]);
+    assert!(!output.status.success());
+    let text = stdout(&output);
+    assert!(text.contains("Error: File 1 is at 0x8002 (files must start on a multiple of 4 bytes)"), "{}", text);
+}
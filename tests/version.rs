@@ -0,0 +1,58 @@
+mod common;
+
+use std::fs;
+
+use serde_json::Value;
+
+use common::{gcmod, stderr, stdout, success, Fixture};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[test]
+fn verbose_version() {
+    let fixture = Fixture::new();
+    let text = stdout(&success(gcmod(fixture.dir.path()).args(["--version", "--verbose"]).output().unwrap()));
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], format!("gcmod {}", VERSION));
+    for (line, field) in lines[1..].iter().zip(["Commit: ", "Features: ", "Image formats: "]) {
+        let value = line.strip_prefix(field).unwrap_or_else(|| panic!("{}", text));
+        assert!(!value.is_empty(), "{}", text);
+    }
+    assert_eq!(lines.len(), 4, "{}", text);
+}
+
+fn check_build_info(doc: &Value) {
+    assert_eq!(doc["gcmod_version"], VERSION);
+    assert!(doc["gcmod_git_hash"].as_str().is_some_and(|h| !h.is_empty()), "{}", doc["gcmod_git_hash"]);
+    assert!(doc["gcmod_features"].is_array(), "{}", doc["gcmod_features"]);
+}
+
+#[test]
+fn documents_record_the_build() {
+    let fixture = Fixture::new();
+    let meta: Value = serde_json::from_str(&stdout(&success(fixture.run(&["dump-meta", "image.iso"])))).unwrap();
+    check_build_info(&meta);
+
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    let manifest: Value = serde_json::from_str(&fs::read_to_string(fixture.path("out/.gcmod")).unwrap()).unwrap();
+    check_build_info(&manifest);
+}
+
+#[test]
+fn other_major_versions_are_warned_about() {
+    let fixture = Fixture::new();
+    let mut meta: Value = serde_json::from_str(&stdout(&success(fixture.run(&["dump-meta", "image.iso"])))).unwrap();
+    let major: u32 = VERSION.split('.').next().unwrap().parse().unwrap();
+    meta["gcmod_version"] = format!("{}.0.0", major + 1).into();
+    fs::write(fixture.path("meta.json"), meta.to_string()).unwrap();
+    let output = success(fixture.run(&["apply-meta", "image.iso", "meta.json"]));
+    assert!(stderr(&output).contains("The metadata document was written by gcmod"), "{}", stderr(&output));
+
+    success(fixture.run(&["extract", "image.iso", "out"]));
+    let path = fixture.path("out/.gcmod");
+    let mut manifest: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    manifest["gcmod_version"] = format!("{}.0.0", major + 1).into();
+    fs::write(&path, manifest.to_string()).unwrap();
+    let output = success(fixture.run(&["rebuild", "out", "rebuilt.iso", "--preserve-indices"]));
+    assert!(stderr(&output).contains("The manifest was written by gcmod"), "{}", stderr(&output));
+}